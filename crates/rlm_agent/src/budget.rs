@@ -0,0 +1,243 @@
+//! Token and cost limits on agent runs
+//!
+//! A [`Budget`] caps the tokens, or the dollars, one run may spend. Every
+//! backend call of the run counts: the rounds, the RLM iterations and
+//! `llm_query` sub-calls inside them, plans, and summaries of tool output.
+//! Once the budget is used up the next call fails and the run stops with
+//! [`AgentError::BudgetExceeded`](crate::AgentError::BudgetExceeded),
+//! carrying what it did so far.
+
+use rlm_core::{ChatBackend, ChatParams, Message, RlmError, ToolDefinition, ToolUse, Usage};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Dollars per million tokens
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Pricing {
+    pub input: f64,
+    pub output: f64,
+    /// Input tokens served from the provider's prompt cache
+    pub cached_input: f64,
+}
+
+impl Pricing {
+    /// Prices with cached input charged as uncached
+    pub fn new(input: f64, output: f64) -> Self {
+        Self {
+            input,
+            output,
+            cached_input: input,
+        }
+    }
+
+    pub fn with_cached_input(mut self, cached_input: f64) -> Self {
+        self.cached_input = cached_input;
+        self
+    }
+
+    /// List prices of well-known models, matched by name prefix
+    pub fn for_model(model: &str) -> Option<Self> {
+        // Longer prefixes first, so "gpt-4o-mini" isn't priced as "gpt-4o"
+        const PRICES: &[(&str, f64, f64, f64)] = &[
+            ("claude-opus-4", 15.0, 75.0, 1.5),
+            ("claude-sonnet-4", 3.0, 15.0, 0.3),
+            ("claude-3-7-sonnet", 3.0, 15.0, 0.3),
+            ("claude-3-5-sonnet", 3.0, 15.0, 0.3),
+            ("claude-3-5-haiku", 0.8, 4.0, 0.08),
+            ("gpt-4o-mini", 0.15, 0.6, 0.075),
+            ("gpt-4o", 2.5, 10.0, 1.25),
+            ("gpt-4.1-mini", 0.4, 1.6, 0.1),
+            ("gpt-4.1", 2.0, 8.0, 0.5),
+        ];
+        PRICES
+            .iter()
+            .find(|(prefix, ..)| model.starts_with(prefix))
+            .map(|&(_, input, output, cached)| Self::new(input, output).with_cached_input(cached))
+    }
+
+    /// Dollars `usage` costs
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        let cached = usage.cached_input_tokens.min(usage.input_tokens);
+        let uncached = usage.input_tokens - cached;
+        (uncached as f64 * self.input
+            + cached as f64 * self.cached_input
+            + usage.output_tokens as f64 * self.output)
+            / 1_000_000.0
+    }
+}
+
+/// What one agent run may spend (None = unlimited)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Budget {
+    pub max_tokens: Option<u64>,
+    /// Dollars, at [`Self::pricing`]
+    pub max_cost: Option<f64>,
+    /// Prices for `max_cost` (None = [`Pricing::for_model`])
+    pub pricing: Option<Pricing>,
+}
+
+impl Budget {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: u64) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    pub fn with_max_cost(mut self, max_cost: f64) -> Self {
+        self.max_cost = Some(max_cost);
+        self
+    }
+
+    pub fn with_pricing(mut self, pricing: Pricing) -> Self {
+        self.pricing = Some(pricing);
+        self
+    }
+
+    pub fn is_limited(&self) -> bool {
+        self.max_tokens.is_some() || self.max_cost.is_some()
+    }
+}
+
+/// The limit a run reached
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetLimit {
+    Tokens(u64),
+    Cost(f64),
+}
+
+impl fmt::Display for BudgetLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BudgetLimit::Tokens(n) => write!(f, "token budget of {}", n),
+            BudgetLimit::Cost(dollars) => write!(f, "cost budget of ${:.2}", dollars),
+        }
+    }
+}
+
+/// Spending of the run in progress against its budget
+pub(crate) struct BudgetMeter {
+    max_tokens: Option<u64>,
+    max_cost: Option<(f64, Pricing)>,
+    spent: Mutex<Usage>,
+}
+
+impl BudgetMeter {
+    pub(crate) fn new(budget: Budget, model: &str) -> rlm_core::Result<Self> {
+        let max_cost = match budget.max_cost {
+            Some(max) => {
+                let pricing = budget
+                    .pricing
+                    .or_else(|| Pricing::for_model(model))
+                    .ok_or_else(|| {
+                        RlmError::Config(format!(
+                            "No prices known for model '{}'; set Budget::pricing to limit cost",
+                            model
+                        ))
+                    })?;
+                Some((max, pricing))
+            }
+            None => None,
+        };
+        Ok(Self {
+            max_tokens: budget.max_tokens,
+            max_cost,
+            spent: Mutex::new(Usage::default()),
+        })
+    }
+
+    /// Start counting a new run
+    pub(crate) fn reset(&self) {
+        *self.spent.lock().unwrap() = Usage::default();
+    }
+
+    pub(crate) fn spent(&self) -> Usage {
+        self.spent.lock().unwrap().clone()
+    }
+
+    fn add(&self, usage: &Usage) {
+        self.spent.lock().unwrap().add(usage);
+    }
+
+    /// The limit the run has used up, if any
+    pub(crate) fn exceeded(&self) -> Option<BudgetLimit> {
+        let spent = self.spent.lock().unwrap();
+        if let Some(max) = self.max_tokens.filter(|&max| spent.total_tokens >= max) {
+            return Some(BudgetLimit::Tokens(max));
+        }
+        self.max_cost
+            .filter(|(max, pricing)| pricing.cost(&spent) >= *max)
+            .map(|(max, _)| BudgetLimit::Cost(max))
+    }
+
+    fn check(&self) -> rlm_core::Result<()> {
+        match self.exceeded() {
+            Some(limit) => Err(RlmError::BudgetExceeded(format!(
+                "the run used up its {}",
+                limit
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Backend decorator refusing calls once the run's budget is used up
+pub(crate) struct BudgetedBackend {
+    inner: Arc<dyn ChatBackend>,
+    meter: Arc<BudgetMeter>,
+}
+
+impl BudgetedBackend {
+    pub(crate) fn new(inner: Arc<dyn ChatBackend>, meter: Arc<BudgetMeter>) -> Self {
+        Self { inner, meter }
+    }
+}
+
+impl ChatBackend for BudgetedBackend {
+    fn chat(&self, messages: &[Message], params: &ChatParams) -> rlm_core::Result<(String, Usage)> {
+        self.meter.check()?;
+        let (text, usage) = self.inner.chat(messages, params)?;
+        self.meter.add(&usage);
+        Ok((text, usage))
+    }
+
+    fn supports_tools(&self) -> bool {
+        self.inner.supports_tools()
+    }
+
+    fn chat_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+        params: &ChatParams,
+    ) -> rlm_core::Result<(String, Vec<ToolUse>, Usage)> {
+        self.meter.check()?;
+        let (text, calls, usage) = self.inner.chat_with_tools(messages, tools, params)?;
+        self.meter.add(&usage);
+        Ok((text, calls, usage))
+    }
+
+    fn embed(&self, texts: &[String], model: &str) -> rlm_core::Result<Vec<Vec<f32>>> {
+        self.inner.embed(texts, model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pricing() {
+        let sonnet = Pricing::for_model("claude-sonnet-4-20250514").unwrap();
+        let usage = Usage::new(1_000_000, 100_000).with_cached_input_tokens(500_000);
+        // 0.5M uncached at $3, 0.5M cached at $0.30, 0.1M output at $15
+        assert!((sonnet.cost(&usage) - 3.15).abs() < 1e-9);
+        assert_eq!(Pricing::for_model("gpt-4o-mini").unwrap().input, 0.15);
+        assert!(Pricing::for_model("llama3").is_none());
+        assert!(BudgetMeter::new(Budget::new().with_max_cost(1.0), "llama3").is_err());
+    }
+}
@@ -0,0 +1,202 @@
+//! Tools the caller runs
+//!
+//! A [`ClientTool`] is offered to the model like any other tool, but the
+//! agent doesn't run its calls: [`Agent::run_resumed`](crate::Agent::run_resumed)
+//! stops at them and hands them back in
+//! [`AgentRun::pending_calls`](crate::AgentRun::pending_calls). The caller
+//! runs them and resumes the task with the calls and their results as
+//! [`ClientTurn`]s. This is how an OpenAI-style API serves `tools`: the
+//! client executes its own functions between requests.
+
+use crate::{schema, Tool, ToolCall, ToolError, ToolResult};
+use serde_json::{json, Value};
+
+/// Category client tools are listed under in the docs
+pub const CLIENT_CATEGORY: &str = "client";
+
+/// A tool known by its name, description, and parameters, run by the caller
+pub struct ClientTool {
+    name: String,
+    description: String,
+    usage: String,
+    parameters: Value,
+}
+
+impl ClientTool {
+    /// Tool taking arguments described by the JSON schema `parameters`
+    /// (None = no arguments)
+    pub fn new(
+        name: impl Into<String>,
+        description: impl Into<String>,
+        parameters: Option<Value>,
+    ) -> Self {
+        let name = name.into();
+        Self {
+            usage: format!("<tool:{}>{{...}}</tool>", name),
+            name,
+            description: description.into(),
+            parameters: parameters.unwrap_or_else(|| json!({ "type": "object", "properties": {} })),
+        }
+    }
+}
+
+impl Tool for ClientTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn usage(&self) -> &str {
+        &self.usage
+    }
+
+    fn category(&self) -> &str {
+        CLIENT_CATEGORY
+    }
+
+    fn parameters_schema(&self) -> Value {
+        self.parameters.clone()
+    }
+
+    /// Only reached in a round where another call had bad arguments, which
+    /// keeps the round from being handed back
+    fn execute(&self, _args: &str) -> ToolResult {
+        ToolResult::err(ToolError::Failed(format!(
+            "{} was not called, as another call of the same response was invalid; call it again",
+            self.name
+        )))
+    }
+}
+
+/// What happened in a task after it was handed back to the caller
+#[derive(Debug, Clone)]
+pub enum ClientTurn {
+    /// What the model said, with the client tool calls it made
+    Assistant { text: String, calls: Vec<ToolCall> },
+    /// What a client tool call returned
+    ToolResult { name: String, output: String },
+}
+
+/// The turns as the agent's conversation history, in the form its own
+/// rounds record them
+pub(crate) fn history(turns: &[ClientTurn]) -> Vec<(String, String)> {
+    let mut history: Vec<(String, String)> = Vec::new();
+    for turn in turns {
+        match turn {
+            ClientTurn::Assistant { text, calls } => {
+                let mut entry = text.clone();
+                for call in calls {
+                    entry.push_str(&format!("\n[called {}: {}]", call.name, call.args));
+                }
+                history.push(("Assistant".to_string(), entry));
+            }
+            ClientTurn::ToolResult { name, output } => {
+                let result = format!("[{}] Result:\n{}\n\n", name, output);
+                // The results of one round go together
+                match history.last_mut() {
+                    Some((role, results)) if role == "Tool Results" => results.push_str(&result),
+                    _ => history.push(("Tool Results".to_string(), result)),
+                }
+            }
+        }
+    }
+    history
+}
+
+/// The calls to hand back for a round, with their arguments checked and
+/// normalized; None when the round calls no client tool or one of its
+/// client calls has bad arguments
+pub(crate) fn pending_calls(
+    calls: &[ToolCall],
+    tools: &crate::ToolRegistry,
+) -> Option<Vec<ToolCall>> {
+    let mut pending = Vec::new();
+    for call in calls {
+        let Some(tool) = tools.get(&call.name) else {
+            continue;
+        };
+        if tool.category() != CLIENT_CATEGORY {
+            continue;
+        }
+        let args = schema::prepare_args(&tool.parameters_schema(), &call.args).ok()?;
+        pending.push(ToolCall {
+            name: call.name.clone(),
+            arguments: serde_json::from_str::<Value>(&args)
+                .ok()
+                .filter(Value::is_object),
+            args,
+        });
+    }
+    Some(pending).filter(|pending| !pending.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, args: &str) -> ToolCall {
+        ToolCall {
+            name: name.to_string(),
+            args: args.to_string(),
+            arguments: None,
+        }
+    }
+
+    #[test]
+    fn test_history_and_pending_calls() {
+        let turns = [
+            ClientTurn::Assistant {
+                text: "Checking.".to_string(),
+                calls: vec![call("weather", "{\"city\":\"Paris\"}")],
+            },
+            ClientTurn::ToolResult {
+                name: "weather".to_string(),
+                output: "sunny".to_string(),
+            },
+            ClientTurn::ToolResult {
+                name: "weather".to_string(),
+                output: "warm".to_string(),
+            },
+        ];
+        let history = history(&turns);
+        assert_eq!(history.len(), 2);
+        assert_eq!(
+            history[0].1,
+            "Checking.\n[called weather: {\"city\":\"Paris\"}]"
+        );
+        assert_eq!(
+            history[1].1,
+            "[weather] Result:\nsunny\n\n[weather] Result:\nwarm\n\n"
+        );
+
+        let mut tools = crate::ToolRegistry::new();
+        let parameters = json!({
+            "type": "object",
+            "properties": { "city": { "type": "string" } },
+            "required": ["city"]
+        });
+        tools.register(ClientTool::new(
+            "weather",
+            "Weather in a city",
+            Some(parameters),
+        ));
+        tools.register(crate::tools::EchoTool);
+
+        let pending = pending_calls(
+            &[
+                call("echo", "hi"),
+                call("weather", " {\"city\": \"Paris\"} "),
+            ],
+            &tools,
+        )
+        .unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].args, "{\"city\":\"Paris\"}");
+        assert_eq!(pending[0].arguments.as_ref().unwrap()["city"], "Paris");
+        assert!(pending_calls(&[call("echo", "hi")], &tools).is_none());
+        assert!(pending_calls(&[call("weather", "{}")], &tools).is_none());
+    }
+}
@@ -0,0 +1,574 @@
+//! Embedding search over a directory of documents
+//!
+//! Some corpora are too large even for the REPL context strategy. A
+//! [`DocIndex`] splits the text files under a directory into chunks, embeds
+//! them with an [`Embedder`], and finds the chunks closest to a query. The
+//! index can be saved as JSON; refreshing it re-embeds only changed files.
+//! [`SearchDocsTool`] gives the model the top matches for its queries.
+
+use crate::{Tool, ToolError, ToolResult};
+use rlm_core::write_atomic;
+use rlm_core::ChatBackend;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Files larger than this are left out of the index
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+
+/// Directories never indexed, besides hidden ones
+const SKIPPED_DIRS: &[&str] = &["target", "node_modules"];
+
+/// Turns text into vectors; closer vectors mean closer meaning
+pub trait Embedder: Send + Sync {
+    /// Names the embedding space; a saved index from another one is rebuilt
+    fn id(&self) -> String;
+
+    fn embed(&self, texts: &[String]) -> rlm_core::Result<Vec<Vec<f32>>>;
+}
+
+/// Embeddings from a backend's embeddings API
+///
+/// With the OpenAI backend pointed at a local server (Ollama, llama.cpp),
+/// this runs a local embedding model.
+pub struct BackendEmbedder {
+    backend: Arc<dyn ChatBackend>,
+    model: String,
+    batch_size: usize,
+}
+
+impl BackendEmbedder {
+    pub fn new(backend: Arc<dyn ChatBackend>, model: impl Into<String>) -> Self {
+        Self {
+            backend,
+            model: model.into(),
+            batch_size: 64,
+        }
+    }
+
+    /// Texts sent per request
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+}
+
+impl Embedder for BackendEmbedder {
+    fn id(&self) -> String {
+        format!("backend:{}", self.model)
+    }
+
+    fn embed(&self, texts: &[String]) -> rlm_core::Result<Vec<Vec<f32>>> {
+        let mut vectors = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(self.batch_size) {
+            vectors.extend(self.backend.embed(batch, &self.model)?);
+        }
+        Ok(vectors)
+    }
+}
+
+/// Embeddings by hashing words into a fixed number of dimensions
+///
+/// Needs no model or network and matches on shared words only, not on
+/// meaning. A fallback for when no embedding model is configured.
+pub struct HashEmbedder {
+    dims: usize,
+}
+
+impl HashEmbedder {
+    pub fn new(dims: usize) -> Self {
+        Self { dims: dims.max(1) }
+    }
+}
+
+impl Default for HashEmbedder {
+    fn default() -> Self {
+        Self::new(512)
+    }
+}
+
+/// FNV-1a, stable across builds unlike the std hasher
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+impl Embedder for HashEmbedder {
+    fn id(&self) -> String {
+        format!("hash:{}", self.dims)
+    }
+
+    fn embed(&self, texts: &[String]) -> rlm_core::Result<Vec<Vec<f32>>> {
+        Ok(texts
+            .iter()
+            .map(|text| {
+                let mut vector = vec![0.0; self.dims];
+                for word in text
+                    .split(|c: char| !c.is_alphanumeric())
+                    .filter(|word| word.chars().count() >= 2)
+                {
+                    let hash = fnv1a(word.to_lowercase().as_bytes());
+                    let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+                    vector[(hash % self.dims as u64) as usize] += sign;
+                }
+                vector
+            })
+            .collect())
+    }
+}
+
+/// A piece of an indexed file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Chunk {
+    /// Path relative to the indexed directory
+    pub path: String,
+    /// First and last line, 1-based
+    pub start_line: usize,
+    pub end_line: usize,
+    pub text: String,
+    pub vector: Vec<f32>,
+}
+
+/// Size and modification time a file was indexed at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct FileStamp {
+    len: u64,
+    modified_ms: u64,
+}
+
+/// Embedded chunks of the files under a directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocIndex {
+    embedder: String,
+    chunk_chars: usize,
+    files: BTreeMap<String, FileStamp>,
+    chunks: Vec<Chunk>,
+}
+
+/// Text files under `root` with their stamps, by relative path
+fn walk(root: &Path) -> std::io::Result<BTreeMap<String, (PathBuf, FileStamp)>> {
+    let mut files = BTreeMap::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            let meta = entry.metadata()?;
+            if meta.is_dir() {
+                if !SKIPPED_DIRS.contains(&name.as_str()) {
+                    dirs.push(path);
+                }
+                continue;
+            }
+            if !meta.is_file() || meta.len() > MAX_FILE_BYTES {
+                continue;
+            }
+            let modified_ms = meta
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0);
+            let relative = path
+                .strip_prefix(root)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .replace('\\', "/");
+            let stamp = FileStamp {
+                len: meta.len(),
+                modified_ms,
+            };
+            files.insert(relative, (path, stamp));
+        }
+    }
+    Ok(files)
+}
+
+/// Split `text` into runs of whole lines of about `max_chars` characters
+fn chunk_lines(text: &str, max_chars: usize) -> Vec<(usize, usize, String)> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut start = 1;
+    for (i, line) in text.lines().enumerate() {
+        let number = i + 1;
+        if !current.is_empty() && current.chars().count() + line.chars().count() > max_chars {
+            if !current.trim().is_empty() {
+                chunks.push((start, number - 1, std::mem::take(&mut current)));
+            }
+            current.clear();
+            start = number;
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+    if !current.trim().is_empty() {
+        let end = text.lines().count();
+        chunks.push((start, end, current));
+    }
+    chunks
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+impl DocIndex {
+    /// Empty index for `embedder`, splitting files into chunks of about
+    /// `chunk_chars` characters
+    pub fn new(embedder: &dyn Embedder, chunk_chars: usize) -> Self {
+        Self {
+            embedder: embedder.id(),
+            chunk_chars: chunk_chars.max(1),
+            files: BTreeMap::new(),
+            chunks: Vec::new(),
+        }
+    }
+
+    /// Index saved at `path` if it exists and was built the same way
+    pub fn load(
+        path: &Path,
+        embedder: &dyn Embedder,
+        chunk_chars: usize,
+    ) -> std::io::Result<Option<Self>> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let index: Self = serde_json::from_str(&text)?;
+        Ok((index.embedder == embedder.id() && index.chunk_chars == chunk_chars).then_some(index))
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        write_atomic(path, &serde_json::to_string(self)?)
+    }
+
+    /// Bring the index up to date with the files under `root`
+    ///
+    /// Unchanged files keep their chunks; new and changed ones are embedded
+    /// and removed ones dropped. Returns how many files were embedded.
+    pub fn refresh(&mut self, root: &Path, embedder: &dyn Embedder) -> Result<usize, ToolError> {
+        let files = walk(root)
+            .map_err(|e| ToolError::from_io(format!("Failed to list '{}'", root.display()), &e))?;
+
+        let mut kept: Vec<Chunk> = Vec::new();
+        let mut pending: Vec<Chunk> = Vec::new();
+        let mut stamps = BTreeMap::new();
+        let mut embedded = 0;
+        let mut old: BTreeMap<&str, Vec<&Chunk>> = BTreeMap::new();
+        for chunk in &self.chunks {
+            old.entry(chunk.path.as_str()).or_default().push(chunk);
+        }
+
+        for (relative, (path, stamp)) in &files {
+            if self.files.get(relative) == Some(stamp) {
+                kept.extend(old.remove(relative.as_str()).into_iter().flatten().cloned());
+                stamps.insert(relative.clone(), *stamp);
+                continue;
+            }
+            let bytes = std::fs::read(path).map_err(|e| {
+                ToolError::from_io(format!("Failed to read '{}'", path.display()), &e)
+            })?;
+            // Binary files aren't worth embedding
+            let Ok(text) = String::from_utf8(bytes) else {
+                continue;
+            };
+            for (start_line, end_line, text) in chunk_lines(&text, self.chunk_chars) {
+                pending.push(Chunk {
+                    path: relative.clone(),
+                    start_line,
+                    end_line,
+                    text,
+                    vector: Vec::new(),
+                });
+            }
+            stamps.insert(relative.clone(), *stamp);
+            embedded += 1;
+        }
+
+        // The path helps queries that name a file or topic
+        let texts: Vec<String> = pending
+            .iter()
+            .map(|c| format!("{}\n{}", c.path, c.text))
+            .collect();
+        let vectors = embedder
+            .embed(&texts)
+            .map_err(|e| ToolError::Failed(format!("Failed to embed documents: {}", e)))?;
+        if vectors.len() != pending.len() {
+            return Err(ToolError::Failed(format!(
+                "Embedder returned {} vectors for {} chunks",
+                vectors.len(),
+                pending.len()
+            )));
+        }
+        for (chunk, vector) in pending.iter_mut().zip(vectors) {
+            chunk.vector = vector;
+        }
+
+        kept.extend(pending);
+        kept.sort_by(|a, b| a.path.cmp(&b.path).then(a.start_line.cmp(&b.start_line)));
+        self.chunks = kept;
+        self.files = stamps;
+        self.embedder = embedder.id();
+        Ok(embedded)
+    }
+
+    /// Number of chunks
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Up to `k` chunks closest to `query`, best first, with their scores
+    pub fn search(
+        &self,
+        query: &str,
+        embedder: &dyn Embedder,
+        k: usize,
+    ) -> Result<Vec<(f32, &Chunk)>, ToolError> {
+        let vector = embedder
+            .embed(&[query.to_string()])
+            .map_err(|e| ToolError::Failed(format!("Failed to embed the query: {}", e)))?
+            .into_iter()
+            .next()
+            .unwrap_or_default();
+        let mut scored: Vec<(f32, &Chunk)> = self
+            .chunks
+            .iter()
+            .map(|chunk| (cosine(&vector, &chunk.vector), chunk))
+            .filter(|(score, _)| *score > 0.0)
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored.truncate(k);
+        Ok(scored)
+    }
+}
+
+/// search_docs tool: finds the chunks of a document directory that best
+/// match a query
+///
+/// The directory is indexed on the first call, and brought up to date on
+/// the first call of each later process when the index is saved.
+pub struct SearchDocsTool {
+    root: PathBuf,
+    embedder: Arc<dyn Embedder>,
+    index_path: Option<PathBuf>,
+    chunk_chars: usize,
+    top_k: usize,
+    index: Mutex<Option<DocIndex>>,
+}
+
+impl SearchDocsTool {
+    pub fn new(root: impl Into<PathBuf>, embedder: Arc<dyn Embedder>) -> Self {
+        Self {
+            root: root.into(),
+            embedder,
+            index_path: None,
+            chunk_chars: 1500,
+            top_k: 5,
+            index: Mutex::new(None),
+        }
+    }
+
+    /// Save the index in this JSON file
+    pub fn with_index_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.index_path = Some(path.into());
+        self
+    }
+
+    /// Characters per chunk, about
+    pub fn with_chunk_chars(mut self, chunk_chars: usize) -> Self {
+        self.chunk_chars = chunk_chars.max(1);
+        self
+    }
+
+    /// Chunks returned per query
+    pub fn with_top_k(mut self, top_k: usize) -> Self {
+        self.top_k = top_k.max(1);
+        self
+    }
+
+    /// Load or build the index, refreshed against the directory
+    fn open_index(&self) -> Result<DocIndex, ToolError> {
+        let saved = match self.index_path {
+            Some(ref path) => DocIndex::load(path, self.embedder.as_ref(), self.chunk_chars)
+                .map_err(|e| {
+                    ToolError::from_io(format!("Failed to load '{}'", path.display()), &e)
+                })?,
+            None => None,
+        };
+        let mut index =
+            saved.unwrap_or_else(|| DocIndex::new(self.embedder.as_ref(), self.chunk_chars));
+        let embedded = index.refresh(&self.root, self.embedder.as_ref())?;
+        if let Some(ref path) = self.index_path {
+            if embedded > 0 || !path.exists() {
+                index.save(path).map_err(|e| {
+                    ToolError::from_io(format!("Failed to save '{}'", path.display()), &e)
+                })?;
+            }
+        }
+        Ok(index)
+    }
+}
+
+impl Tool for SearchDocsTool {
+    fn name(&self) -> &str {
+        "search_docs"
+    }
+
+    fn description(&self) -> &str {
+        "Search the document collection; returns the passages that best match the query"
+    }
+
+    fn usage(&self) -> &str {
+        "<tool:search_docs>how are API keys rotated</tool>"
+    }
+
+    fn category(&self) -> &str {
+        "files"
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        // The first call embeds the whole directory
+        Some(Duration::from_secs(600))
+    }
+
+    fn execute(&self, args: &str) -> ToolResult {
+        let query = args.trim();
+        if query.is_empty() {
+            return ToolResult::err(ToolError::InvalidArgs("Empty query".to_string()));
+        }
+
+        let mut index = self.index.lock().unwrap();
+        if index.is_none() {
+            match self.open_index() {
+                Ok(opened) => *index = Some(opened),
+                Err(e) => return ToolResult::err(e),
+            }
+        }
+        let Some(index) = index.as_ref() else {
+            return ToolResult::err(ToolError::Failed("Index unavailable".to_string()));
+        };
+        if index.is_empty() {
+            return ToolResult::ok(format!("No documents under {}", self.root.display()));
+        }
+
+        let hits = match index.search(query, self.embedder.as_ref(), self.top_k) {
+            Ok(hits) => hits,
+            Err(e) => return ToolResult::err(e),
+        };
+        if hits.is_empty() {
+            return ToolResult::ok("No matching passages");
+        }
+        let passages: Vec<String> = hits
+            .iter()
+            .map(|(score, chunk)| {
+                format!(
+                    "{}:{}-{} (score {:.2})\n{}",
+                    chunk.path, chunk.start_line, chunk.end_line, score, chunk.text
+                )
+            })
+            .collect();
+        ToolResult::ok(passages.join("\n\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Hash embeddings, counting the texts embedded
+    struct Counting(HashEmbedder, AtomicUsize);
+
+    impl Embedder for Counting {
+        fn id(&self) -> String {
+            self.0.id()
+        }
+
+        fn embed(&self, texts: &[String]) -> rlm_core::Result<Vec<Vec<f32>>> {
+            self.1.fetch_add(texts.len(), Ordering::SeqCst);
+            self.0.embed(texts)
+        }
+    }
+
+    #[test]
+    fn test_chunk_lines() {
+        let chunks = chunk_lines("one\ntwo\nthree\n\nfour", 8);
+        assert_eq!(
+            chunks,
+            [
+                (1, 2, "one\ntwo".to_string()),
+                (3, 4, "three\n".to_string()),
+                (5, 5, "four".to_string()),
+            ]
+        );
+        assert!(chunk_lines("\n\n", 10).is_empty());
+    }
+
+    #[test]
+    fn test_search_docs_tool() {
+        let dir = std::env::temp_dir().join(format!("rlm_agent_docs_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("ops")).unwrap();
+        std::fs::create_dir_all(dir.join("target")).unwrap();
+        std::fs::write(
+            dir.join("ops/keys.md"),
+            "# Keys\nAPI keys are rotated every 90 days by the vault job.",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("deploy.md"),
+            "Deploys go through the staging cluster first.",
+        )
+        .unwrap();
+        std::fs::write(dir.join("target/keys.md"), "API keys rotated build output").unwrap();
+        let index_path = dir.join(".index.json");
+
+        let embedder = Arc::new(Counting(HashEmbedder::default(), AtomicUsize::new(0)));
+        let tool = SearchDocsTool::new(&dir, embedder.clone())
+            .with_index_path(&index_path)
+            .with_top_k(1);
+        let result = tool.execute("how often are API keys rotated");
+        assert!(result.success);
+        assert!(result.output.starts_with("ops/keys.md:1-2 (score"));
+        assert!(result.output.contains("every 90 days"));
+        assert!(tool.execute("staging").output.starts_with("deploy.md:1-1"));
+        assert_eq!(tool.execute(" ").error.unwrap().kind(), "invalid_args");
+        // Two files, plus the two queries
+        assert_eq!(embedder.1.load(Ordering::SeqCst), 4);
+
+        // A new tool reuses the saved index, embedding only the changed file
+        std::fs::write(dir.join("deploy.md"), "Deploys need a sign-off.").unwrap();
+        let embedder = Arc::new(Counting(HashEmbedder::default(), AtomicUsize::new(0)));
+        let tool = SearchDocsTool::new(&dir, embedder.clone())
+            .with_index_path(&index_path)
+            .with_top_k(1);
+        assert!(tool
+            .execute("sign-off")
+            .output
+            .contains("Deploys need a sign-off."));
+        assert_eq!(embedder.1.load(Ordering::SeqCst), 2);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
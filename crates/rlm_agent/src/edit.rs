@@ -0,0 +1,402 @@
+//! Editing files in place with diffs or search/replace
+//!
+//! The [`EditFileTool`] changes part of a file instead of rewriting all of
+//! it: either a unified diff, whose hunks are matched by their context
+//! lines (so line numbers that are a little off still apply), or a search
+//! string that must occur exactly once, with its replacement. The file is
+//! replaced atomically after a backup of the original, and the tool returns
+//! the changed lines with some context around them, numbered, so the model
+//! can check the result without reading the file again.
+
+use crate::{Tool, ToolError, ToolResult, Workspace};
+use serde::Deserialize;
+use serde_json::json;
+use std::ops::Range;
+use std::path::Path;
+
+/// Unchanged lines shown around each change
+const CONTEXT_LINES: usize = 3;
+
+/// Lines of a hunk quoted when it doesn't match the file
+const QUOTED_HUNK_LINES: usize = 5;
+
+/// One `@@` section of a unified diff
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Hunk {
+    /// 1-based line the hunk says it starts at, when its header has one
+    old_start: Option<usize>,
+    /// Lines with their ' ', '-', or '+' marker
+    lines: Vec<(char, String)>,
+}
+
+impl Hunk {
+    /// Lines the hunk expects in the file
+    fn old_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter(|(marker, _)| *marker != '+')
+            .map(|(_, line)| line.as_str())
+            .collect()
+    }
+
+    /// Lines the hunk leaves in their place
+    fn new_lines(&self) -> Vec<String> {
+        self.lines
+            .iter()
+            .filter(|(marker, _)| *marker != '-')
+            .map(|(_, line)| line.clone())
+            .collect()
+    }
+}
+
+/// Hunks of a unified diff; file headers before the first `@@` are skipped
+fn parse_diff(diff: &str) -> Result<Vec<Hunk>, ToolError> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    for (number, line) in diff.lines().enumerate() {
+        if let Some(header) = line.strip_prefix("@@") {
+            // "@@ -12,5 +12,6 @@"; models sometimes leave out the numbers
+            let old_start = header
+                .trim_start()
+                .strip_prefix('-')
+                .and_then(|rest| rest.split([',', ' ']).next())
+                .and_then(|start| start.parse().ok());
+            hunks.push(Hunk {
+                old_start,
+                lines: Vec::new(),
+            });
+            continue;
+        }
+        let Some(hunk) = hunks.last_mut() else {
+            continue;
+        };
+        let mut chars = line.chars();
+        match chars.next() {
+            Some(marker @ (' ' | '-' | '+')) => hunk.lines.push((marker, chars.collect())),
+            // A blank context line whose space was trimmed away
+            None => hunk.lines.push((' ', String::new())),
+            Some('\\') => {}
+            Some(_) => {
+                return Err(ToolError::InvalidArgs(format!(
+                    "line {} of the diff doesn't start with ' ', '-', '+', or '@@': {}",
+                    number + 1,
+                    line
+                )))
+            }
+        }
+    }
+    hunks.retain(|hunk| !hunk.lines.is_empty());
+    if hunks.is_empty() {
+        return Err(ToolError::InvalidArgs(
+            "the diff has no hunks; each starts with an @@ line".to_string(),
+        ));
+    }
+    Ok(hunks)
+}
+
+/// Whether `old` is at `pos` in `lines`, comparing `compare` of each line
+fn matches_at(lines: &[String], old: &[&str], pos: usize, compare: fn(&str) -> &str) -> bool {
+    pos + old.len() <= lines.len()
+        && old
+            .iter()
+            .zip(&lines[pos..])
+            .all(|(a, b)| compare(a) == compare(b))
+}
+
+/// Where hunk lines `old` are in `lines`, at or after `from`: exactly if
+/// possible, else ignoring trailing whitespace; the match nearest
+/// `expected` wins
+fn find_hunk(lines: &[String], old: &[&str], from: usize, expected: usize) -> Option<usize> {
+    let comparisons: [fn(&str) -> &str; 2] = [|line| line, str::trim_end];
+    comparisons.into_iter().find_map(|compare| {
+        (from..=lines.len().saturating_sub(old.len()))
+            .filter(|&pos| matches_at(lines, old, pos, compare))
+            .min_by_key(|&pos| pos.abs_diff(expected))
+    })
+}
+
+/// Apply `hunks` in order, returning the line ranges they changed
+fn apply_diff(lines: &mut Vec<String>, hunks: &[Hunk]) -> Result<Vec<Range<usize>>, ToolError> {
+    let mut changed = Vec::new();
+    // Lines added minus lines removed so far, to shift later line numbers
+    let mut offset = 0isize;
+    let mut from = 0;
+    for (index, hunk) in hunks.iter().enumerate() {
+        let old = hunk.old_lines();
+        let expected = hunk
+            .old_start
+            .map(|start| (start.saturating_sub(1) as isize + offset).max(from as isize) as usize)
+            .unwrap_or(from)
+            .min(lines.len());
+        let pos = if old.is_empty() {
+            Some(expected)
+        } else {
+            find_hunk(lines, &old, from, expected)
+        };
+        let Some(pos) = pos else {
+            let quoted: Vec<&str> = old.iter().take(QUOTED_HUNK_LINES).copied().collect();
+            return Err(ToolError::InvalidArgs(format!(
+                "hunk {} doesn't match the file; its lines starting\n{}\nweren't found. \
+                 Read the file again and make the context lines match it exactly.",
+                index + 1,
+                quoted.join("\n")
+            )));
+        };
+        let new = hunk.new_lines();
+        offset += new.len() as isize - old.len() as isize;
+        from = pos + new.len();
+        changed.push(pos..from);
+        lines.splice(pos..pos + old.len(), new);
+    }
+    Ok(changed)
+}
+
+/// Replace the one occurrence of `search`, returning the lines it changed
+fn apply_replace(
+    text: &mut String,
+    search: &str,
+    replace: &str,
+) -> Result<Range<usize>, ToolError> {
+    if search.is_empty() {
+        return Err(ToolError::InvalidArgs("search is empty".to_string()));
+    }
+    let mut found = text.match_indices(search).map(|(at, _)| at);
+    let (Some(at), None) = (found.next(), found.next()) else {
+        let count = text.matches(search).count();
+        return Err(ToolError::InvalidArgs(if count == 0 {
+            "search text not found; read the file again and copy it exactly".to_string()
+        } else {
+            format!(
+                "search text found {} times; include more lines around it so it is unique",
+                count
+            )
+        }));
+    };
+    text.replace_range(at..at + search.len(), replace);
+    let start = text[..at].matches('\n').count();
+    let end = start + replace.matches('\n').count() + 1;
+    Ok(start..end)
+}
+
+/// Numbered lines of `text` around each of the `changed` ranges
+fn context(text: &str, changed: &[Range<usize>]) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut out = String::new();
+    for range in changed {
+        let start = range.start.saturating_sub(CONTEXT_LINES);
+        let end = (range.end + CONTEXT_LINES).min(lines.len());
+        out.push_str(&format!("@@ lines {}-{} @@\n", start + 1, end));
+        for (number, line) in lines.iter().enumerate().take(end).skip(start) {
+            out.push_str(&format!("{:>5}  {}\n", number + 1, line));
+        }
+    }
+    out
+}
+
+/// Write `content` to `path` through a temporary file, so the file is
+/// never left half written, keeping its permissions
+fn replace_file(path: &Path, content: &str) -> std::io::Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = path.with_file_name(format!(".{}.rlm_tmp", name));
+    std::fs::write(&tmp, content)?;
+    let renamed = std::fs::metadata(path)
+        .and_then(|meta| std::fs::set_permissions(&tmp, meta.permissions()))
+        .and_then(|_| std::fs::rename(&tmp, path));
+    if renamed.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    renamed
+}
+
+#[derive(Deserialize)]
+struct EditArgs {
+    path: String,
+    #[serde(default)]
+    diff: Option<String>,
+    #[serde(default)]
+    search: Option<String>,
+    #[serde(default)]
+    replace: Option<String>,
+}
+
+/// Edit file tool: applies a unified diff or a search/replace to a file
+/// in the workspace
+pub struct EditFileTool {
+    pub workspace: Workspace,
+    /// Keep the original next to the file as `<name>.bak`
+    pub backup: bool,
+}
+
+impl EditFileTool {
+    pub fn new(workspace: Workspace) -> Self {
+        Self {
+            workspace,
+            backup: true,
+        }
+    }
+
+    pub fn with_backup(mut self, backup: bool) -> Self {
+        self.backup = backup;
+        self
+    }
+
+    fn edit(&self, args: EditArgs) -> Result<String, ToolError> {
+        let path = args.path.trim();
+        let resolved = self.workspace.resolve_for_write(path)?;
+        let original = self.workspace.read(path)?;
+        // Edit with \n line endings, putting \r\n back afterwards
+        let crlf = original.contains("\r\n");
+        let mut text = original.replace("\r\n", "\n");
+
+        let changed = match (args.diff, args.search, args.replace) {
+            (Some(diff), None, None) => {
+                let hunks = parse_diff(&diff.replace("\r\n", "\n"))?;
+                let trailing_newline = text.ends_with('\n') || text.is_empty();
+                let mut lines: Vec<String> = text.lines().map(String::from).collect();
+                let changed = apply_diff(&mut lines, &hunks)?;
+                text = lines.join("\n");
+                if trailing_newline && !text.is_empty() {
+                    text.push('\n');
+                }
+                changed
+            }
+            (None, Some(search), Some(replace)) => {
+                let search = search.replace("\r\n", "\n");
+                vec![apply_replace(
+                    &mut text,
+                    &search,
+                    &replace.replace("\r\n", "\n"),
+                )?]
+            }
+            _ => {
+                return Err(ToolError::InvalidArgs(
+                    "give either diff, or search and replace".to_string(),
+                ))
+            }
+        };
+        if text == original.replace("\r\n", "\n") {
+            return Err(ToolError::InvalidArgs(
+                "the edit leaves the file unchanged".to_string(),
+            ));
+        }
+        let shown = context(&text, &changed);
+        if crlf {
+            text = text.replace('\n', "\r\n");
+        }
+        self.workspace.check_size(path, text.len() as u64)?;
+
+        let io_error = |e| ToolError::from_io(format_args!("Failed to edit '{}'", path), &e);
+        let mut summary = format!("Edited {} ({} change(s))", path, changed.len());
+        if self.backup {
+            let name = resolved.file_name().unwrap_or_default().to_string_lossy();
+            let backup = resolved.with_file_name(format!("{}.bak", name));
+            std::fs::write(&backup, &original).map_err(io_error)?;
+            summary.push_str(&format!("; the original is in {}.bak", path));
+        }
+        replace_file(&resolved, &text).map_err(io_error)?;
+        Ok(format!("{}\n{}", summary, shown))
+    }
+}
+
+impl Tool for EditFileTool {
+    fn name(&self) -> &str {
+        "edit_file"
+    }
+
+    fn description(&self) -> &str {
+        "Change part of a file with a unified diff, or by replacing text that occurs once in it"
+    }
+
+    fn usage(&self) -> &str {
+        r#"<tool:edit_file>{"path": "src/main.rs", "search": "let x = 1;", "replace": "let x = 2;"}</tool> or {"path": "src/main.rs", "diff": "@@ -1,2 +1,2 @@\n fn main() {\n-    let x = 1;\n+    let x = 2;"}"#
+    }
+
+    fn category(&self) -> &str {
+        "files"
+    }
+
+    fn parameters_schema(&self) -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": { "type": "string" },
+                "diff": { "type": "string", "description": "Unified diff of the file" },
+                "search": { "type": "string", "description": "Text occurring once in the file" },
+                "replace": { "type": "string", "description": "Text to put in its place" }
+            },
+            "required": ["path"],
+            "additionalProperties": false
+        })
+    }
+
+    fn execute(&self, args: &str) -> ToolResult {
+        let args: EditArgs = match serde_json::from_str(args) {
+            Ok(args) => args,
+            Err(e) => return ToolResult::err(ToolError::InvalidArgs(e.to_string())),
+        };
+        match self.edit(args) {
+            Ok(output) => ToolResult::ok(output),
+            Err(e) => ToolResult::err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_apply_diff() {
+        let mut file = lines("a\nb\nc\nd\ne\nf\ng");
+        // Line numbers off by one; the context still finds the hunks
+        let hunks = parse_diff(
+            "--- a/x\n+++ b/x\n@@ -3,3 +3,3 @@\n b\n-c\n+C\n d\n@@ -7,2 +7,3 @@\n f\n+f2\n g\n",
+        )
+        .unwrap();
+        assert_eq!(apply_diff(&mut file, &hunks).unwrap(), [1..4, 5..8]);
+        assert_eq!(file, lines("a\nb\nC\nd\ne\nf\nf2\ng"));
+
+        let hunks = parse_diff("@@ @@\n x\n-y\n").unwrap();
+        let err = apply_diff(&mut file, &hunks).unwrap_err();
+        assert!(err.message().contains("hunk 1 doesn't match"));
+        assert!(parse_diff("just text").is_err());
+    }
+
+    #[test]
+    fn test_edit_file_tool() {
+        let root = std::env::temp_dir().join(format!("rlm_agent_edit_{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a.rs"), "fn main() {\r\n    let x = 1;\r\n}\r\n").unwrap();
+        let tool = EditFileTool::new(Workspace::new(&root));
+
+        let result = tool.execute(r#"{"path": "a.rs", "search": "x = 1", "replace": "x = 2"}"#);
+        assert!(
+            result.output.contains("    2      let x = 2;"),
+            "{}",
+            result.output
+        );
+        assert_eq!(
+            std::fs::read_to_string(root.join("a.rs")).unwrap(),
+            "fn main() {\r\n    let x = 2;\r\n}\r\n"
+        );
+        assert!(std::fs::read_to_string(root.join("a.rs.bak"))
+            .unwrap()
+            .contains("x = 1"));
+
+        let result = tool.execute(r#"{"path": "a.rs", "search": "x = 1", "replace": "x = 3"}"#);
+        assert!(result.error.unwrap().message().contains("not found"));
+        let result = tool.execute(
+            r#"{"path": "a.rs", "diff": "@@ -2 +2 @@\n-    let x = 2;\n+    let x = 3;"}"#,
+        );
+        assert!(result.success, "{:?}", result.error);
+        assert!(std::fs::read_to_string(root.join("a.rs"))
+            .unwrap()
+            .contains("x = 3;\r\n"));
+        let result = tool.execute(r#"{"path": "../a.rs", "search": "a", "replace": "b"}"#);
+        assert_eq!(result.error.unwrap().kind(), "permission_denied");
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
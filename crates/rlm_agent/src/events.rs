@@ -0,0 +1,48 @@
+//! Live progress of an agent run
+//!
+//! [`Agent::run_with_events`](crate::Agent::run_with_events) sends an
+//! [`AgentEvent`] over a channel at each step, so a CLI or web UI can show
+//! the run as it happens instead of waiting for it to return.
+
+use crate::{Subtask, ToolCall, ToolCallRecord};
+use serde::{Deserialize, Serialize};
+use std::sync::mpsc::Sender;
+
+/// A step of an agent run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AgentEvent {
+    /// A planned run is starting a subtask (0-based `index`)
+    SubtaskStarted { index: usize, description: String },
+    /// A planned run finished a subtask, done or failed
+    SubtaskFinished { index: usize, subtask: Subtask },
+    /// A tool round is about to ask the model
+    RoundStarted { round: u32 },
+    /// The model's response for a round
+    ModelResponse { round: u32, text: String },
+    /// The model's tool calls were malformed; `message` asks it to resend them
+    Correction { round: u32, message: String },
+    /// The model called a tool; it runs if its permissions allow it
+    ToolCallStarted {
+        round: u32,
+        #[serde(flatten)]
+        call: ToolCall,
+    },
+    /// A tool call returned, with the result as the model sees it
+    ToolCallFinished(ToolCallRecord),
+    /// The run's final answer
+    Answer { answer: String },
+}
+
+/// Where a run sends its events, if anywhere
+#[derive(Clone, Copy)]
+pub(crate) struct Events<'a>(pub(crate) Option<&'a Sender<AgentEvent>>);
+
+impl Events<'_> {
+    /// Send `event`; a receiver that went away doesn't stop the run
+    pub(crate) fn emit(&self, event: impl FnOnce() -> AgentEvent) {
+        if let Some(sender) = self.0 {
+            let _ = sender.send(event());
+        }
+    }
+}
@@ -0,0 +1,430 @@
+//! Tools run as external processes over JSON-RPC
+//!
+//! An [`ExternalTool`] wraps any executable that answers JSON-RPC 2.0
+//! requests on stdio, so tools written in Python, Node, or anything else
+//! plug in without Rust. Each request is one line on the process's stdin;
+//! the process writes the response as one line on stdout (other lines, like
+//! logs, are skipped). The process is started for each request and its
+//! stdin closed after it, so it may also serve requests in a loop.
+//!
+//! Two methods make up the contract:
+//!
+//! - `describe`, no params, asked once when the tool is loaded. Result:
+//!   `{"name": "...", "description": "...", "usage": "...", "parameters":
+//!   {JSON schema}, "category": "..."}`; the last three are optional,
+//!   without `parameters` the tool takes plain text, and without
+//!   `category` it is listed under "external".
+//! - `execute`, params `{"args": "..."}`, plus `"arguments": {...}` when
+//!   the arguments are a JSON object. Result: `{"output": "..."}`, with
+//!   `"cost"` in dollars for tools calling a paid API, or a string. An
+//!   error's `data.kind` may name a [`ToolError`] kind (`not_found`,
+//!   `invalid_args`, ...) for the model to see.
+//!
+//! A [`ToolManifest`] lists the executables to load.
+
+use crate::{schema, AsyncTool, ToolError, ToolResult};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+
+/// Longest a process may take to answer `describe`
+const DESCRIBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Characters of stderr quoted when a process gives no response
+const STDERR_CHARS: usize = 500;
+
+/// How to start one external tool
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExternalToolSpec {
+    /// Executable, looked up on PATH unless it contains a path separator
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Extra environment variables
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Working directory (None = the manifest's directory)
+    #[serde(default)]
+    pub cwd: Option<PathBuf>,
+    /// Seconds a call may run (None = the registry's default)
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+}
+
+impl ExternalToolSpec {
+    pub fn new(command: impl Into<String>) -> Self {
+        Self {
+            command: command.into(),
+            args: Vec::new(),
+            env: HashMap::new(),
+            cwd: None,
+            timeout_secs: None,
+        }
+    }
+
+    pub fn with_args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_env(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn with_cwd(mut self, cwd: impl Into<PathBuf>) -> Self {
+        self.cwd = Some(cwd.into());
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout_secs = Some(timeout.as_secs());
+        self
+    }
+
+    /// Paths relative to `dir` made relative to it rather than to the
+    /// current directory
+    fn relative_to(mut self, dir: &Path) -> Self {
+        if self.command.contains(['/', '\\']) && Path::new(&self.command).is_relative() {
+            self.command = dir.join(&self.command).to_string_lossy().into_owned();
+        }
+        self.cwd = Some(match self.cwd {
+            Some(cwd) => dir.join(cwd),
+            None => dir.to_path_buf(),
+        });
+        self
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new(&self.command);
+        command
+            .args(&self.args)
+            .envs(&self.env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if let Some(ref cwd) = self.cwd {
+            command.current_dir(cwd);
+        }
+        command
+    }
+}
+
+/// JSON file listing external tools:
+/// `{"tools": [{"command": "python3", "args": ["tools/jira.py"]}]}`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolManifest {
+    pub tools: Vec<ExternalToolSpec>,
+}
+
+impl ToolManifest {
+    /// Manifest at `path`; relative commands and directories in it are
+    /// taken from the manifest's directory
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let manifest: Self = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        Ok(Self {
+            tools: manifest
+                .tools
+                .into_iter()
+                .map(|spec| spec.relative_to(dir))
+                .collect(),
+        })
+    }
+
+    /// Start each listed tool and ask it to describe itself
+    pub fn load_tools(&self) -> Vec<std::io::Result<ExternalTool>> {
+        self.tools.iter().cloned().map(ExternalTool::new).collect()
+    }
+}
+
+/// What a tool says about itself in answer to `describe`
+#[derive(Debug, Clone, Deserialize)]
+struct Description {
+    name: String,
+    description: String,
+    #[serde(default)]
+    usage: Option<String>,
+    #[serde(default)]
+    parameters: Option<Value>,
+    #[serde(default)]
+    category: Option<String>,
+}
+
+/// A tool run by an external process (see the [module docs](self))
+pub struct ExternalTool {
+    spec: ExternalToolSpec,
+    name: String,
+    description: String,
+    usage: String,
+    parameters: Value,
+    category: String,
+}
+
+impl ExternalTool {
+    /// Tool started by `spec`, once it has described itself
+    pub fn new(spec: ExternalToolSpec) -> std::io::Result<Self> {
+        let result = describe(&spec).map_err(|e| {
+            std::io::Error::other(format!(
+                "'{}' failed to describe itself: {}",
+                spec.command, e
+            ))
+        })?;
+        let described: Description = serde_json::from_value(result)?;
+        let usage = described
+            .usage
+            .unwrap_or_else(|| format!("<tool:{}>arguments</tool>", described.name));
+        Ok(Self {
+            spec,
+            name: described.name,
+            description: described.description,
+            usage,
+            parameters: described.parameters.unwrap_or_else(schema::text_schema),
+            category: described.category.unwrap_or_else(|| "external".to_string()),
+        })
+    }
+}
+
+/// A JSON-RPC request line
+fn request(method: &str, params: Value) -> String {
+    let mut line =
+        json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }).to_string();
+    line.push('\n');
+    line
+}
+
+/// The result of the response on `line`, if it is one
+fn response(line: &str) -> Option<Result<Value, ToolError>> {
+    let mut message: Value = serde_json::from_str(line.trim()).ok()?;
+    if message.get("id")? != 1 {
+        return None;
+    }
+    if let Some(error) = message.get("error") {
+        let text = error["message"]
+            .as_str()
+            .unwrap_or("unknown error")
+            .to_string();
+        let kind = error["data"]["kind"].as_str().unwrap_or("failed");
+        let error = serde_json::from_value(json!({ "kind": kind, "message": text }))
+            .unwrap_or(ToolError::Failed(text));
+        return Some(Err(error));
+    }
+    Some(Ok(message.get_mut("result")?.take()))
+}
+
+/// Error for a process that exited without a response
+fn no_response(command: &str, stderr: &str) -> ToolError {
+    let stderr: String = stderr.trim().chars().take(STDERR_CHARS).collect();
+    ToolError::Failed(format!("'{}' gave no response: {}", command, stderr))
+}
+
+/// Ask the process of `spec` to describe itself
+fn describe(spec: &ExternalToolSpec) -> Result<Value, ToolError> {
+    let spawn_error =
+        |e| ToolError::from_io(format_args!("Failed to start '{}'", spec.command), &e);
+    let mut child = spec.command().spawn().map_err(spawn_error)?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A process that doesn't read stdin fails below instead
+        let _ = stdin.write_all(request("describe", Value::Null).as_bytes());
+    }
+
+    // Read on threads, so a stuck process can be killed at the deadline
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let stdout = child.stdout.take();
+    std::thread::spawn(move || {
+        let found = stdout.and_then(|stdout| {
+            BufReader::new(stdout)
+                .lines()
+                .map_while(Result::ok)
+                .find_map(|line| response(&line))
+        });
+        let _ = sender.send(found);
+    });
+    let mut stderr = child.stderr.take();
+    let stderr_reader = std::thread::spawn(move || {
+        let mut text = String::new();
+        if let Some(ref mut stderr) = stderr {
+            let _ = stderr.read_to_string(&mut text);
+        }
+        text
+    });
+
+    let deadline = Instant::now() + DESCRIBE_TIMEOUT;
+    let found = receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()));
+    let _ = child.kill();
+    let _ = child.wait();
+    match found {
+        Ok(Some(result)) => result,
+        Ok(None) => Err(no_response(
+            &spec.command,
+            &stderr_reader.join().unwrap_or_default(),
+        )),
+        Err(_) => Err(ToolError::Timeout(format!(
+            "'{}' did not answer within {}s",
+            spec.command,
+            DESCRIBE_TIMEOUT.as_secs()
+        ))),
+    }
+}
+
+#[async_trait]
+impl AsyncTool for ExternalTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn usage(&self) -> &str {
+        &self.usage
+    }
+
+    fn category(&self) -> &str {
+        &self.category
+    }
+
+    fn parameters_schema(&self) -> Value {
+        self.parameters.clone()
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.spec.timeout_secs.map(Duration::from_secs)
+    }
+
+    async fn execute(&self, args: &str) -> ToolResult {
+        let mut params = json!({ "args": args });
+        if let Ok(arguments @ Value::Object(_)) = serde_json::from_str(args) {
+            params["arguments"] = arguments;
+        }
+
+        // Killed when the call is dropped at its timeout
+        let mut command = tokio::process::Command::from(self.spec.command());
+        let mut child = match command.kill_on_drop(true).spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                return ToolResult::err(ToolError::from_io(
+                    format_args!("Failed to start '{}'", self.spec.command),
+                    &e,
+                ))
+            }
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = stdin.write_all(request("execute", params).as_bytes()).await;
+        }
+        // Drained alongside stdout, so a chatty process can't fill the pipe
+        let stderr = child.stderr.take().map(|mut pipe| {
+            tokio::spawn(async move {
+                let mut text = String::new();
+                let _ = pipe.read_to_string(&mut text).await;
+                text
+            })
+        });
+
+        let mut found = None;
+        if let Some(stdout) = child.stdout.take() {
+            let mut lines = tokio::io::BufReader::new(stdout).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                found = response(&line);
+                if found.is_some() {
+                    break;
+                }
+            }
+        }
+        let result = match found {
+            Some(result) => result,
+            None => {
+                let stderr = match stderr {
+                    Some(task) => task.await.unwrap_or_default(),
+                    None => String::new(),
+                };
+                Err(no_response(&self.spec.command, &stderr))
+            }
+        };
+        let _ = child.kill().await;
+
+        match result {
+            Ok(Value::String(output)) => ToolResult::ok(output),
+            Ok(result) => {
+                let mut tool_result = match result.get("output") {
+                    Some(Value::String(output)) => ToolResult::ok(output.clone()),
+                    _ => ToolResult::ok(result.to_string()),
+                };
+                tool_result.cost = result.get("cost").and_then(Value::as_f64);
+                tool_result
+            }
+            Err(e) => ToolResult::err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = r#"
+import json, sys
+for line in sys.stdin:
+    request = json.loads(line)
+    print("starting up")
+    if request["method"] == "describe":
+        result = {"name": "shout", "description": "Upper-case text",
+                  "parameters": {"type": "object",
+                                 "properties": {"text": {"type": "string"}},
+                                 "required": ["text"]}}
+    elif request["params"]["arguments"]["text"] == "missing":
+        print(json.dumps({"jsonrpc": "2.0", "id": request["id"], "error": {
+            "code": 1, "message": "no such text", "data": {"kind": "not_found"}}}))
+        continue
+    else:
+        result = {"output": request["params"]["arguments"]["text"].upper(),
+                  "cost": 0.25}
+    print(json.dumps({"jsonrpc": "2.0", "id": request["id"], "result": result}))
+"#;
+
+    #[test]
+    fn test_external_tool_from_manifest() {
+        let dir = std::env::temp_dir().join(format!("rlm_agent_external_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("shout.py"), SCRIPT).unwrap();
+        let manifest = dir.join("tools.json");
+        std::fs::write(
+            &manifest,
+            r#"{"tools": [{"command": "python3", "args": ["shout.py"]}, {"command": "./nope"}]}"#,
+        )
+        .unwrap();
+
+        let mut tools = ToolManifest::load(&manifest)
+            .unwrap()
+            .load_tools()
+            .into_iter();
+        let tool = tools.next().unwrap().unwrap();
+        assert_eq!(tool.name(), "shout");
+        assert_eq!(tool.usage(), "<tool:shout>arguments</tool>");
+        assert_eq!(tool.category(), "external");
+        assert!(tools.next().unwrap().is_err());
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let result = runtime.block_on(tool.execute(r#"{"text": "hi"}"#));
+        assert_eq!(result.output, "HI");
+        assert_eq!(result.cost, Some(0.25));
+        let result = runtime.block_on(tool.execute(r#"{"text": "missing"}"#));
+        assert_eq!(
+            result.error.unwrap(),
+            ToolError::NotFound("no such text".into())
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
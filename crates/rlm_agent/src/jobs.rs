@@ -0,0 +1,553 @@
+//! Batch runs of task files, now or on a schedule
+//!
+//! A [`JobRunner`] runs each task file of its sources (a directory's
+//! `.txt`, `.md`, and `.task` files, in name order, or single files) as a
+//! separate task, one after another or a few at a time. Every job writes
+//! two files named after its task file to the output directory:
+//! `<name>.json` with the run's report or error, and `<name>.transcript.md`
+//! to read what it did. Jobs that already have a result are skipped, so a
+//! directory works as a queue: drop in task files, and the next run picks
+//! up the new ones.
+//!
+//! A [`Schedule`] is a cron expression for running the jobs again and
+//! again, e.g. nightly.
+
+use crate::output::{self, OutputTruncation};
+use crate::{Agent, AgentError, AgentRunReport};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Extensions of the files in a source directory that are tasks
+const TASK_EXTENSIONS: &[&str] = &["txt", "md", "task"];
+
+/// Characters of a tool call's output put into a transcript
+const TRANSCRIPT_OUTPUT_CHARS: usize = 2_000;
+
+/// Years ahead a schedule is searched for its next time
+const SCHEDULE_YEARS: u64 = 5;
+
+/// One task file to run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Job {
+    /// File stem of the task file, which names its result files
+    pub name: String,
+    pub path: PathBuf,
+    pub task: String,
+}
+
+/// How a job went
+#[derive(Debug, Clone, Serialize)]
+pub struct JobOutcome {
+    pub name: String,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub duration_ms: u64,
+}
+
+/// What `<name>.json` holds
+#[derive(Serialize)]
+struct JobResult<'a> {
+    task: &'a str,
+    success: bool,
+    #[serde(flatten)]
+    report: &'a AgentRunReport,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Runs task files as agent tasks (see the [module docs](self))
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobRunner {
+    /// Directories of task files, or task files
+    pub sources: Vec<PathBuf>,
+    /// Where the result and transcript files go
+    pub output_dir: PathBuf,
+    /// Jobs run at once
+    pub parallelism: usize,
+    /// Run jobs that already have a result again
+    pub rerun: bool,
+}
+
+impl JobRunner {
+    pub fn new(output_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            sources: Vec::new(),
+            output_dir: output_dir.into(),
+            parallelism: 1,
+            rerun: false,
+        }
+    }
+
+    pub fn with_source(mut self, source: impl Into<PathBuf>) -> Self {
+        self.sources.push(source.into());
+        self
+    }
+
+    pub fn with_parallelism(mut self, parallelism: usize) -> Self {
+        self.parallelism = parallelism.max(1);
+        self
+    }
+
+    pub fn with_rerun(mut self, rerun: bool) -> Self {
+        self.rerun = rerun;
+        self
+    }
+
+    /// Jobs of the sources that are still to run
+    pub fn pending(&self) -> std::io::Result<Vec<Job>> {
+        let mut jobs = Vec::new();
+        for source in &self.sources {
+            if !source.is_dir() {
+                jobs.extend(self.job(source)?);
+                continue;
+            }
+            if same_dir(source, &self.output_dir) {
+                continue;
+            }
+            let mut paths: Vec<PathBuf> = std::fs::read_dir(source)?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.is_file() && is_task_file(path))
+                .collect();
+            paths.sort();
+            for path in paths {
+                jobs.extend(self.job(&path)?);
+            }
+        }
+        Ok(jobs)
+    }
+
+    /// Job of the task file at `path`, unless it is empty or done
+    fn job(&self, path: &Path) -> std::io::Result<Option<Job>> {
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if !self.rerun && self.result_path(&name).exists() {
+            return Ok(None);
+        }
+        let task = std::fs::read_to_string(path)?.trim().to_string();
+        Ok((!task.is_empty()).then(|| Job {
+            name,
+            path: path.to_path_buf(),
+            task,
+        }))
+    }
+
+    fn result_path(&self, name: &str) -> PathBuf {
+        self.output_dir.join(format!("{}.json", name))
+    }
+
+    /// Run the pending jobs, returning their outcomes in job order
+    ///
+    /// Each of the [`parallelism`](Self::parallelism) workers gets its
+    /// agent from `make_agent` and runs jobs on it until none are left.
+    /// `on_finished` sees each outcome as its job finishes. A failed job
+    /// doesn't stop the others; only failing to read the tasks or write
+    /// the results is an error.
+    pub fn run<F, G>(&self, make_agent: F, on_finished: G) -> std::io::Result<Vec<JobOutcome>>
+    where
+        F: Fn() -> rlm_core::Result<Agent> + Sync,
+        G: Fn(&JobOutcome) + Sync,
+    {
+        std::fs::create_dir_all(&self.output_dir)?;
+        let jobs = self.pending()?;
+        let next = AtomicUsize::new(0);
+        let outcomes = Mutex::new(Vec::new());
+        let workers = self.parallelism.clamp(1, jobs.len().max(1));
+
+        let worker = || -> std::io::Result<()> {
+            let mut agent = None;
+            loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                let Some(job) = jobs.get(index) else {
+                    return Ok(());
+                };
+                // Made on first use, so a worker without jobs makes none
+                let agent = agent.get_or_insert_with(&make_agent);
+                let started = Instant::now();
+                let outcome = match agent {
+                    Ok(agent) => {
+                        let result = agent.run_report(&job.task);
+                        self.write_result(job, &result)?;
+                        JobOutcome {
+                            name: job.name.clone(),
+                            success: result.is_ok(),
+                            error: result.err().map(|e| e.to_string()),
+                            duration_ms: started.elapsed().as_millis() as u64,
+                        }
+                    }
+                    Err(e) => JobOutcome {
+                        name: job.name.clone(),
+                        success: false,
+                        error: Some(format!("Failed to create agent: {}", e)),
+                        duration_ms: 0,
+                    },
+                };
+                on_finished(&outcome);
+                outcomes.lock().unwrap().push((index, outcome));
+            }
+        };
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers).map(|_| scope.spawn(worker)).collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("job worker panicked"))
+                .collect::<std::io::Result<Vec<()>>>()
+        })?;
+
+        let mut outcomes = outcomes.into_inner().unwrap();
+        outcomes.sort_by_key(|(index, _)| *index);
+        Ok(outcomes.into_iter().map(|(_, outcome)| outcome).collect())
+    }
+
+    /// Write the result and transcript files of `job`
+    fn write_result(
+        &self,
+        job: &Job,
+        result: &Result<AgentRunReport, AgentError>,
+    ) -> std::io::Result<()> {
+        let (report, error) = match result {
+            Ok(report) => (report, None),
+            Err(e) => (e.partial(), Some(e.to_string())),
+        };
+        let json = JobResult {
+            task: &job.task,
+            success: result.is_ok(),
+            report,
+            error: error.clone(),
+        };
+        std::fs::write(
+            self.output_dir.join(format!("{}.transcript.md", job.name)),
+            transcript(job, report, error.as_deref()),
+        )?;
+        // Written last, as it marks the job done
+        std::fs::write(
+            self.result_path(&job.name),
+            serde_json::to_string_pretty(&json)?,
+        )
+    }
+}
+
+fn is_task_file(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    !name.ends_with(".transcript.md")
+        && path
+            .extension()
+            .is_some_and(|ext| TASK_EXTENSIONS.iter().any(|task_ext| ext == *task_ext))
+}
+
+fn same_dir(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Readable account of a job's run
+fn transcript(job: &Job, report: &AgentRunReport, error: Option<&str>) -> String {
+    let run = &report.run;
+    let mut text = format!("# {}\n\n## Task\n\n{}\n", job.name, job.task);
+    for response in &run.responses {
+        text.push_str(&format!(
+            "\n## Round {}\n\n{}\n",
+            response.round,
+            response.response.trim()
+        ));
+        for record in run.tool_calls.iter().filter(|r| r.round == response.round) {
+            let outcome = match record.result.error {
+                Some(ref error) => format!("error: {}", error),
+                None => output::truncate(
+                    &record.result.output,
+                    TRANSCRIPT_OUTPUT_CHARS,
+                    OutputTruncation::Head,
+                ),
+            };
+            text.push_str(&format!(
+                "\n**{}**({})\n\n```text\n{}\n```\n",
+                record.call.name,
+                record.call.args,
+                outcome.trim_end()
+            ));
+        }
+    }
+    match error {
+        Some(error) => text.push_str(&format!("\n## Error\n\n{}\n", error)),
+        None => text.push_str(&format!("\n## Answer\n\n{}\n", run.answer)),
+    }
+    text.push_str(&format!(
+        "\n---\n{} rounds, {} tool calls, {} tokens, {:.1}s\n",
+        run.rounds,
+        run.tool_calls.len(),
+        run.usage.total_tokens,
+        run.duration_ms as f64 / 1000.0
+    ));
+    text
+}
+
+/// Cron schedule, in UTC: `minute hour day-of-month month day-of-week`
+///
+/// Fields take `*`, numbers, ranges (`1-5`), lists (`1,15`), and steps
+/// (`*/15`, `9-17/2`); Sunday is 0 or 7. As in cron, when both day fields
+/// are restricted a day matching either one fires. `@hourly`, `@daily`,
+/// `@weekly`, and `@monthly` stand for the usual expressions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Day-of-month started with `*` (`*`, `*/2`)
+    any_day: bool,
+    /// Day-of-week started with `*`
+    any_weekday: bool,
+}
+
+impl Schedule {
+    /// First time after `time` the schedule fires, to the minute (None if
+    /// it doesn't within a few years, like `0 0 31 2 *`)
+    pub fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        let secs = time.duration_since(UNIX_EPOCH).ok()?.as_secs();
+        let mut t = (secs / 60 + 1) * 60;
+        let end = t + SCHEDULE_YEARS * 366 * 86_400;
+        while t < end {
+            let days = t / 86_400;
+            let (_, month, day) = civil_from_days(days);
+            // 1970-01-01 was a Thursday
+            let weekday = (days + 4) % 7;
+            if !has(self.months, month) || !self.day_matches(day, weekday) {
+                t = (days + 1) * 86_400;
+                continue;
+            }
+            if !has(self.hours, t % 86_400 / 3_600) {
+                t = (t / 3_600 + 1) * 3_600;
+                continue;
+            }
+            if !has(self.minutes, t % 3_600 / 60) {
+                t += 60;
+                continue;
+            }
+            return Some(UNIX_EPOCH + Duration::from_secs(t));
+        }
+        None
+    }
+
+    fn day_matches(&self, day: u64, weekday: u64) -> bool {
+        let by_day = has(self.days, day);
+        let by_weekday = has(self.weekdays, weekday);
+        if self.any_day || self.any_weekday {
+            by_day && by_weekday
+        } else {
+            by_day || by_weekday
+        }
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(expr: &str) -> Result<Self, Self::Err> {
+        let expr = match expr.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            expr => expr,
+        };
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!(
+                "expected 5 fields (minute hour day month weekday), got {}",
+                fields.len()
+            ));
+        };
+        let mut weekdays = parse_field(weekday, 0, 7)?;
+        // Sunday is both 0 and 7
+        if has(weekdays, 7) {
+            weekdays |= 1;
+        }
+        Ok(Self {
+            minutes: parse_field(minute, 0, 59)?,
+            hours: parse_field(hour, 0, 23)?,
+            days: parse_field(day, 1, 31)?,
+            months: parse_field(month, 1, 12)?,
+            weekdays,
+            // Like cron, a day field starting with `*` narrows the other
+            // instead of adding to it
+            any_day: day.starts_with('*'),
+            any_weekday: weekday.starts_with('*'),
+        })
+    }
+}
+
+fn has(set: u64, value: u64) -> bool {
+    set & (1 << value) != 0
+}
+
+/// The values a cron field allows, as a bit set
+fn parse_field(field: &str, min: u64, max: u64) -> Result<u64, String> {
+    let number = |text: &str| -> Result<u64, String> {
+        match text.parse() {
+            Ok(n) if (min..=max).contains(&n) => Ok(n),
+            _ => Err(format!(
+                "'{}' in '{}' is not a number from {} to {}",
+                text, field, min, max
+            )),
+        }
+    };
+    let mut set = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u64>() {
+                Ok(step) if step > 0 => (range, Some(step)),
+                _ => return Err(format!("bad step '{}' in '{}'", step, field)),
+            },
+            None => (part, None),
+        };
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((first, last)) => (number(first)?, number(last)?),
+            // `5/10` runs from 5 to the end
+            None if step.is_some() => (number(range)?, max),
+            None => (number(range)?, number(range)?),
+        };
+        if first > last {
+            return Err(format!("range '{}' in '{}' is backwards", range, field));
+        }
+        for value in (first..=last).step_by(step.unwrap_or(1) as usize) {
+            set |= 1 << value;
+        }
+    }
+    Ok(set)
+}
+
+/// (year, month, day) of the day `days` after 1970-01-01
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    // Howard Hinnant's algorithm, for days on or after the epoch
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+/// `time` as an ISO 8601 UTC timestamp, e.g. `2026-10-17T02:00:00Z`
+pub fn format_utc(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (year, month, day) = civil_from_days(secs / 86_400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs % 86_400 / 3_600,
+        secs % 3_600 / 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AgentConfig, ToolRegistry};
+    use rlm_core::Backend;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_schedule() {
+        // Friday 2026-10-16 12:34:56 UTC
+        let now = at(1_792_154_096);
+        let next = |expr: &str, from| expr.parse::<Schedule>().unwrap().next_after(from);
+
+        assert_eq!(next("0 2 * * *", now), Some(at(1_792_202_400)));
+        assert_eq!(next("@daily", now), next("0 0 * * *", now));
+        assert_eq!(next("*/15 * * * *", now), Some(at(1_792_154_700)));
+        // Friday 17:50 to Monday 09:00
+        assert_eq!(
+            next("*/15 9-17 * * 1-5", at(1_792_173_000)),
+            Some(at(1_792_400_400))
+        );
+        // From 2027-02-28 to the next leap day
+        assert_eq!(
+            next("0 0 29 2 *", at(1_803_816_000)),
+            Some(at(1_835_395_200))
+        );
+        assert_eq!(next("0 0 31 2 *", now), None);
+        // Odd days that are Mondays, not odd days and Mondays
+        assert_eq!(next("0 0 */2 * 1", now), Some(at(1_792_368_000)));
+        assert_eq!(format_utc(at(1_792_202_400)), "2026-10-17T02:00:00Z");
+
+        assert!("60 * * * *".parse::<Schedule>().is_err());
+        assert!("* * *".parse::<Schedule>().is_err());
+        assert!("5-1 * * * *".parse::<Schedule>().is_err());
+        assert!("*/0 * * * *".parse::<Schedule>().is_err());
+    }
+
+    #[test]
+    fn test_job_runner() {
+        let dir = std::env::temp_dir().join(format!("rlm_agent_jobs_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let tasks = dir.join("tasks");
+        std::fs::create_dir_all(&tasks).unwrap();
+        std::fs::write(tasks.join("a.txt"), "Say hi").unwrap();
+        std::fs::write(tasks.join("b.md"), "Say bye").unwrap();
+        std::fs::write(tasks.join("empty.txt"), "  ").unwrap();
+        std::fs::write(tasks.join("notes.json"), "{}").unwrap();
+
+        let mock = rlm_core::MockBackend::new([
+            "<answer>done</answer><done>",
+            "<answer>done</answer><done>",
+        ]);
+        let make_agent = || {
+            let config = AgentConfig {
+                backend: Backend::Mock(mock.clone()),
+                direct: true,
+                ..Default::default()
+            };
+            Agent::new(config, ToolRegistry::new())
+        };
+        let runner = JobRunner::new(dir.join("results"))
+            .with_source(&tasks)
+            .with_parallelism(2);
+        let finished = AtomicUsize::new(0);
+        let outcomes = runner
+            .run(make_agent, |_| {
+                finished.fetch_add(1, Ordering::SeqCst);
+            })
+            .unwrap();
+
+        let names: Vec<&str> = outcomes.iter().map(|o| o.name.as_str()).collect();
+        assert_eq!(names, ["a", "b"]);
+        assert!(outcomes.iter().all(|o| o.success));
+        assert_eq!(finished.load(Ordering::SeqCst), 2);
+        let result: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(dir.join("results/a.json")).unwrap())
+                .unwrap();
+        assert_eq!(result["task"], "Say hi");
+        assert_eq!(result["answer"], "done");
+        let transcript = std::fs::read_to_string(dir.join("results/b.transcript.md")).unwrap();
+        assert!(transcript.contains("## Task\n\nSay bye\n"));
+        assert!(transcript.contains("## Answer\n\ndone\n"));
+
+        // Done jobs are skipped until asked for again
+        assert!(runner.run(make_agent, |_| {}).unwrap().is_empty());
+        assert_eq!(runner.with_rerun(true).pending().unwrap().len(), 2);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,25 +1,157 @@
 //! RLM Agent - Tool-use agent harness
 //!
-//! Uses RLM as an opaque reasoning engine. The agent harness:
+//! Uses RLM as an opaque reasoning engine (or, without the `rlm` feature,
+//! the chat backend directly). The agent harness:
 //! 1. Sends tasks to RLM
-//! 2. Parses tool calls from RLM output
-//! 3. Executes tools externally
-//! 4. Feeds results back to RLM
+//! 2. Parses tool calls from RLM output (or, on the direct engine, takes
+//!    them from the backend's native tool calling when it has it)
+//! 3. Executes tools externally, on a Tokio runtime owned by the agent,
+//!    once their [`permissions`] allow it
+//! 4. Feeds results back to RLM, cut down to their [`output`] limits
 //! 5. Repeats until task complete
-
+//!
+//! With planning on, the task is first split into subtasks that go through
+//! this loop one at a time (see [`plan`]). Tools the caller runs instead
+//! end the loop with their calls (see [`client`]).
+
+pub mod budget;
+pub mod client;
+pub mod docs;
+pub mod edit;
+pub mod events;
+pub mod external;
+pub mod jobs;
+pub mod memory;
+pub mod output;
+pub mod permissions;
+pub mod plan;
+pub mod profile;
+pub mod prompt;
+pub mod schema;
+pub mod session;
 pub mod tools;
-
-use rlm::{Backend, Rlm, RlmConfig};
+pub mod workspace;
+
+use async_trait::async_trait;
+pub use budget::{Budget, BudgetLimit, Pricing};
+use budget::{BudgetMeter, BudgetedBackend};
+pub use client::{ClientTool, ClientTurn};
+pub use docs::{BackendEmbedder, Embedder, HashEmbedder, SearchDocsTool};
+pub use edit::EditFileTool;
+pub use events::AgentEvent;
+use events::Events;
+pub use external::{ExternalTool, ExternalToolSpec, ToolManifest};
+pub use jobs::{Job, JobOutcome, JobRunner, Schedule};
+pub use memory::{MemoryStore, MemoryTool};
+pub use output::{OutputLimit, Overflow};
+pub use permissions::{Permission, PermissionPolicy, ToolApproval, ToolApprovalHook};
+pub use plan::{AgentRunReport, Subtask, SubtaskStatus};
+pub use profile::ToolProfile;
+pub use prompt::{AgentPrompt, PromptExample};
+pub use rlm_core::html;
+use rlm_core::parsing::{find_tags, TagMatch};
+use rlm_core::{
+    create_backend, Backend, ChatBackend, ChatParams, Message, RlmConfig, ToolDefinition, ToolUse,
+    Usage,
+};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::sync::Arc;
+pub use session::{AgentSession, SessionTurn};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::future::Future;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+pub use workspace::Workspace;
+
+/// Why a tool call failed
+///
+/// The kind is shown to the model so it can react (fix arguments, try
+/// another path) instead of guessing from free text. [`ToolError::Transient`]
+/// failures are retried once by the agent before being surfaced.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum ToolError {
+    /// Unknown tool, missing file or directory
+    NotFound(String),
+    /// Blocked by the OS or by tool policy
+    PermissionDenied(String),
+    /// The operation took too long
+    Timeout(String),
+    /// Malformed or unusable arguments
+    InvalidArgs(String),
+    /// Flaky failure that may succeed when retried
+    Transient(String),
+    /// Any other failure
+    Failed(String),
+}
+
+impl ToolError {
+    /// Short snake_case label for the error kind
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ToolError::NotFound(_) => "not_found",
+            ToolError::PermissionDenied(_) => "permission_denied",
+            ToolError::Timeout(_) => "timeout",
+            ToolError::InvalidArgs(_) => "invalid_args",
+            ToolError::Transient(_) => "transient",
+            ToolError::Failed(_) => "failed",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            ToolError::NotFound(m)
+            | ToolError::PermissionDenied(m)
+            | ToolError::Timeout(m)
+            | ToolError::InvalidArgs(m)
+            | ToolError::Transient(m)
+            | ToolError::Failed(m) => m,
+        }
+    }
+
+    pub fn is_transient(&self) -> bool {
+        matches!(self, ToolError::Transient(_))
+    }
+
+    /// Classify an I/O error, prefixing its message with `context`
+    pub fn from_io(context: impl fmt::Display, e: &std::io::Error) -> Self {
+        use std::io::ErrorKind;
+
+        let message = format!("{}: {}", context, e);
+        match e.kind() {
+            ErrorKind::NotFound => ToolError::NotFound(message),
+            ErrorKind::PermissionDenied => ToolError::PermissionDenied(message),
+            ErrorKind::TimedOut => ToolError::Timeout(message),
+            ErrorKind::InvalidInput | ErrorKind::InvalidData => ToolError::InvalidArgs(message),
+            ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::ConnectionRefused
+            | ErrorKind::BrokenPipe => ToolError::Transient(message),
+            _ => ToolError::Failed(message),
+        }
+    }
+}
+
+impl fmt::Display for ToolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}", self.kind(), self.message())
+    }
+}
+
+impl std::error::Error for ToolError {}
 
 /// Tool execution result
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ToolResult {
     pub success: bool,
     pub output: String,
-    pub error: Option<String>,
+    pub error: Option<ToolError>,
+    /// Dollars the call cost, for tools backed by a paid API
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
 }
 
 impl ToolResult {
@@ -28,26 +160,70 @@ impl ToolResult {
             success: true,
             output: output.into(),
             error: None,
+            cost: None,
         }
     }
 
-    pub fn err(error: impl Into<String>) -> Self {
+    pub fn err(error: ToolError) -> Self {
         Self {
             success: false,
             output: String::new(),
-            error: Some(error.into()),
+            error: Some(error),
+            cost: None,
         }
     }
+
+    pub fn with_cost(mut self, cost: f64) -> Self {
+        self.cost = Some(cost);
+        self
+    }
+
+    /// Whether the call failed with a [`ToolError::Transient`] error
+    pub fn is_transient(&self) -> bool {
+        self.error.as_ref().is_some_and(ToolError::is_transient)
+    }
 }
 
 /// Parsed tool call from model output
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ToolCall {
     pub name: String,
     pub args: String,
+    /// `args` parsed, when it is a JSON object
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<serde_json::Value>,
+}
+
+impl ToolCall {
+    /// A call made through native tool calling
+    ///
+    /// Plain-text tools are offered with a single `input` property (see
+    /// [`ToolRegistry::definitions`]), which is unwrapped back into `args`.
+    fn from_tool_use(call: ToolUse, tools: &ToolRegistry) -> Self {
+        let text_tool = tools
+            .get(&call.name)
+            .is_some_and(|tool| tool.parameters_schema() == schema::text_schema());
+        let input = call.arguments.get("input").and_then(|v| v.as_str());
+        let (args, arguments) = match (&call.arguments, input) {
+            (_, Some(input)) if text_tool => (input.to_string(), None),
+            (serde_json::Value::String(text), _) => (text.clone(), None),
+            (value, _) => (
+                value.to_string(),
+                Some(value.clone()).filter(|v| v.is_object()),
+            ),
+        };
+        Self {
+            name: call.name,
+            args,
+            arguments,
+        }
+    }
 }
 
 /// Tool definition
+///
+/// `execute` runs on a blocking thread, so it may do file or process I/O.
+/// Tools that wait on the network should implement [`AsyncTool`] instead.
 pub trait Tool: Send + Sync {
     /// Tool name
     fn name(&self) -> &str;
@@ -58,14 +234,169 @@ pub trait Tool: Send + Sync {
     /// Usage example
     fn usage(&self) -> &str;
 
+    /// Group the tool is listed under in the docs: "files", "code",
+    /// "web", or anything else
+    fn category(&self) -> &str {
+        "general"
+    }
+
+    /// JSON schema of the arguments; the default takes plain text
+    ///
+    /// Calls are checked against it before `execute` runs, which then gets
+    /// the arguments as JSON text (see [`schema::prepare_args`]).
+    fn parameters_schema(&self) -> serde_json::Value {
+        schema::text_schema()
+    }
+
+    /// Longest a call may run (None = the registry's default)
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Drop state kept for the current task; called as each run starts
+    fn reset(&self) {}
+
     /// Execute the tool
     fn execute(&self, args: &str) -> ToolResult;
 }
 
+/// Tool whose calls run as futures on the agent's Tokio runtime
+///
+/// A call past its timeout is cancelled by dropping its future, so an
+/// HTTP request in flight is aborted rather than left to finish.
+#[async_trait]
+pub trait AsyncTool: Send + Sync {
+    /// Tool name
+    fn name(&self) -> &str;
+
+    /// Tool description for the model
+    fn description(&self) -> &str;
+
+    /// Usage example
+    fn usage(&self) -> &str;
+
+    /// Group the tool is listed under in the docs: "files", "code",
+    /// "web", or anything else
+    fn category(&self) -> &str {
+        "general"
+    }
+
+    /// JSON schema of the arguments; the default takes plain text
+    ///
+    /// Calls are checked against it before `execute` runs, which then gets
+    /// the arguments as JSON text (see [`schema::prepare_args`]).
+    fn parameters_schema(&self) -> serde_json::Value {
+        schema::text_schema()
+    }
+
+    /// Longest a call may run (None = the registry's default)
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Drop state kept for the current task; called as each run starts
+    fn reset(&self) {}
+
+    /// Execute the tool
+    async fn execute(&self, args: &str) -> ToolResult;
+}
+
+/// A [`Tool`] run on Tokio's blocking thread pool
+///
+/// A timed-out call is abandoned: the thread runs to completion and its
+/// result is dropped.
+struct BlockingTool<T>(Arc<T>);
+
+#[async_trait]
+impl<T: Tool + 'static> AsyncTool for BlockingTool<T> {
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    fn description(&self) -> &str {
+        self.0.description()
+    }
+
+    fn usage(&self) -> &str {
+        self.0.usage()
+    }
+
+    fn category(&self) -> &str {
+        self.0.category()
+    }
+
+    fn parameters_schema(&self) -> serde_json::Value {
+        self.0.parameters_schema()
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.0.timeout()
+    }
+
+    fn reset(&self) {
+        self.0.reset()
+    }
+
+    async fn execute(&self, args: &str) -> ToolResult {
+        let tool = self.0.clone();
+        let args = args.to_string();
+        tokio::task::spawn_blocking(move || tool.execute(&args))
+            .await
+            .unwrap_or_else(|e| ToolResult::err(ToolError::Failed(format!("Tool panicked: {}", e))))
+    }
+}
+
+/// Extra attempts for a tool call failing with [`ToolError::Transient`]
+const TRANSIENT_RETRIES: usize = 1;
+
+/// Timeout for tools that don't set their own
+pub const DEFAULT_TOOL_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Run one call, cancelling it once its timeout passes
+async fn run_tool(
+    tool: &dyn AsyncTool,
+    args: &str,
+    default_timeout: Option<Duration>,
+) -> ToolResult {
+    let Some(limit) = tool.timeout().or(default_timeout) else {
+        return tool.execute(args).await;
+    };
+    tokio::time::timeout(limit, tool.execute(args))
+        .await
+        .unwrap_or_else(|_| {
+            ToolResult::err(ToolError::Timeout(format!(
+                "{} did not finish within {}s",
+                tool.name(),
+                limit.as_secs_f64()
+            )))
+        })
+}
+
 /// Registry of available tools
-#[derive(Default)]
 pub struct ToolRegistry {
-    tools: HashMap<String, Arc<dyn Tool>>,
+    tools: HashMap<String, Arc<dyn AsyncTool>>,
+    default_timeout: Option<Duration>,
+    permissions: PermissionPolicy,
+    output_limits: HashMap<String, OutputLimit>,
+    default_output_limit: Option<OutputLimit>,
+    /// Calls per task allowed of some tools
+    max_calls: HashMap<String, u32>,
+    /// Calls made this task of the tools in `max_calls`
+    call_counts: Mutex<HashMap<String, u32>>,
+}
+
+impl Default for ToolRegistry {
+    fn default() -> Self {
+        Self {
+            tools: HashMap::new(),
+            default_timeout: Some(DEFAULT_TOOL_TIMEOUT),
+            permissions: PermissionPolicy::default(),
+            output_limits: HashMap::new(),
+            default_output_limit: Some(output::DEFAULT_OUTPUT_LIMIT),
+            max_calls: HashMap::new(),
+            call_counts: Mutex::new(HashMap::new()),
+        }
+    }
 }
 
 impl ToolRegistry {
@@ -74,34 +405,213 @@ impl ToolRegistry {
     }
 
     pub fn register<T: Tool + 'static>(&mut self, tool: T) {
+        self.register_async(BlockingTool(Arc::new(tool)));
+    }
+
+    pub fn register_async<T: AsyncTool + 'static>(&mut self, tool: T) {
         self.tools.insert(tool.name().to_string(), Arc::new(tool));
     }
 
-    pub fn get(&self, name: &str) -> Option<Arc<dyn Tool>> {
+    pub fn get(&self, name: &str) -> Option<Arc<dyn AsyncTool>> {
         self.tools.get(name).cloned()
     }
 
+    /// Unregister tool `name`
+    pub fn remove(&mut self, name: &str) -> Option<Arc<dyn AsyncTool>> {
+        self.tools.remove(name)
+    }
+
+    /// Timeout for tools that don't set their own (None = unlimited)
+    pub fn set_default_timeout(&mut self, timeout: Option<Duration>) {
+        self.default_timeout = timeout;
+    }
+
+    /// Allow, deny, or ask about calls of tool `name`
+    pub fn set_permission(&mut self, name: impl Into<String>, permission: Permission) {
+        self.permissions.tools.insert(name.into(), permission);
+    }
+
+    /// Replace the whole permission policy
+    pub fn set_permissions(&mut self, permissions: PermissionPolicy) {
+        self.permissions = permissions;
+    }
+
+    pub fn permissions(&self) -> &PermissionPolicy {
+        &self.permissions
+    }
+
+    /// Cap the output of tool `name` the agent passes on to the model
+    pub fn set_output_limit(&mut self, name: impl Into<String>, limit: OutputLimit) {
+        self.output_limits.insert(name.into(), limit);
+    }
+
+    /// Output limit for tools without their own (None = unlimited)
+    pub fn set_default_output_limit(&mut self, limit: Option<OutputLimit>) {
+        self.default_output_limit = limit;
+    }
+
+    /// Output limit of tool `name`
+    pub fn output_limit(&self, name: &str) -> Option<OutputLimit> {
+        self.output_limits
+            .get(name)
+            .copied()
+            .or(self.default_output_limit)
+    }
+
+    /// Allow tool `name` at most `max` calls per task; later calls are
+    /// denied without running
+    pub fn set_max_calls(&mut self, name: impl Into<String>, max: u32) {
+        self.max_calls.insert(name.into(), max);
+    }
+
+    /// Calls per task allowed of tool `name` (None = unlimited)
+    pub fn max_calls(&self, name: &str) -> Option<u32> {
+        self.max_calls.get(name).copied()
+    }
+
     pub fn list(&self) -> Vec<&str> {
         self.tools.keys().map(|s| s.as_str()).collect()
     }
 
+    /// Reset every tool's per-task state and call counts
+    pub fn reset(&self) {
+        self.call_counts.lock().unwrap().clear();
+        for tool in self.tools.values() {
+            tool.reset();
+        }
+    }
+
+    /// Count a call of tool `name` against its limit, if it has one
+    fn count_call(&self, name: &str) -> Result<(), ToolError> {
+        let Some(max) = self.max_calls(name) else {
+            return Ok(());
+        };
+        let mut counts = self.call_counts.lock().unwrap();
+        let count = counts.entry(name.to_string()).or_default();
+        if *count >= max {
+            return Err(ToolError::PermissionDenied(format!(
+                "{} is limited to {} call(s) per task; work with what earlier calls returned",
+                name, max
+            )));
+        }
+        *count += 1;
+        Ok(())
+    }
+
+    /// Tool definitions for native tool calling, sorted by name
+    ///
+    /// Providers want an object schema, so plain-text tools take their
+    /// arguments as a string `input` property.
+    pub fn definitions(&self) -> Vec<ToolDefinition> {
+        let mut definitions: Vec<ToolDefinition> = self
+            .tools
+            .iter()
+            .map(|(name, tool)| {
+                let mut parameters = tool.parameters_schema();
+                if parameters == schema::text_schema() {
+                    parameters = serde_json::json!({
+                        "type": "object",
+                        "properties": {
+                            "input": { "type": "string", "description": tool.usage() }
+                        },
+                        "required": ["input"]
+                    });
+                }
+                ToolDefinition {
+                    name: name.clone(),
+                    description: tool.description().to_string(),
+                    parameters,
+                }
+            })
+            .collect();
+        definitions.sort_by(|a, b| a.name.cmp(&b.name));
+        definitions
+    }
+
     /// Generate tool documentation for system prompt
+    ///
+    /// Tools are grouped by category and sorted by name, so the prompt is
+    /// the same from run to run; tools taking JSON list their parameters.
     pub fn generate_docs(&self) -> String {
-        let mut docs = String::new();
-
+        let mut categories: BTreeMap<&str, BTreeMap<&str, &Arc<dyn AsyncTool>>> = BTreeMap::new();
         for (name, tool) in &self.tools {
-            docs.push_str(&format!("- {}: {}\n", name, tool.description()));
-            docs.push_str(&format!("  Usage: {}\n", tool.usage()));
+            categories
+                .entry(tool.category())
+                .or_default()
+                .insert(name, tool);
         }
 
+        let mut docs = String::new();
+        for (category, tools) in categories {
+            docs.push_str(&format!("[{}]\n", category));
+            for (name, tool) in tools {
+                docs.push_str(&format!("- {}: {}\n", name, tool.description()));
+                docs.push_str(&format!("  Usage: {}\n", tool.usage()));
+                let parameters = schema::describe(&tool.parameters_schema());
+                if !parameters.is_empty() {
+                    docs.push_str("  Parameters:\n");
+                    for parameter in parameters {
+                        docs.push_str(&format!("    {}\n", parameter));
+                    }
+                }
+            }
+        }
         docs
     }
 
-    /// Execute a tool by name
-    pub fn execute(&self, name: &str, args: &str) -> ToolResult {
-        match self.get(name) {
-            Some(tool) => tool.execute(args),
-            None => ToolResult::err(format!("Unknown tool: {}", name)),
+    /// Execute a tool by name, within its timeout
+    ///
+    /// Tools set to [`Permission::Ask`] are denied, as there is nobody to
+    /// ask. Must be awaited on a Tokio runtime.
+    pub async fn execute(&self, name: &str, args: &str) -> ToolResult {
+        let call = ToolCall {
+            name: name.to_string(),
+            args: args.to_string(),
+            arguments: None,
+        };
+        if let Err(e) = self.permissions.check(&call, None) {
+            return ToolResult::err(e);
+        }
+        self.call(name, args, false).await
+    }
+
+    /// A call of tool `name`, detached from the registry so it can be
+    /// spawned; transient failures are retried [`TRANSIENT_RETRIES`] times
+    ///
+    /// Permissions are the caller's to check.
+    fn call(
+        &self,
+        name: &str,
+        args: &str,
+        verbose: bool,
+    ) -> impl Future<Output = ToolResult> + Send + 'static {
+        let tool = self.get(name);
+        let counted = self.count_call(name);
+        let name = name.to_string();
+        let args = args.to_string();
+        let default_timeout = self.default_timeout;
+        async move {
+            let Some(tool) = tool else {
+                return ToolResult::err(ToolError::NotFound(format!("Unknown tool: {}", name)));
+            };
+            if let Err(e) = counted {
+                return ToolResult::err(e);
+            }
+            let args = match schema::prepare_args(&tool.parameters_schema(), &args) {
+                Ok(args) => args,
+                Err(e) => return ToolResult::err(e),
+            };
+            let mut result = run_tool(tool.as_ref(), &args, default_timeout).await;
+            for _ in 0..TRANSIENT_RETRIES {
+                if !result.is_transient() {
+                    break;
+                }
+                if verbose {
+                    println!("  Retrying {} after transient error", name);
+                }
+                result = run_tool(tool.as_ref(), &args, default_timeout).await;
+            }
+            result
         }
     }
 }
@@ -117,6 +627,32 @@ pub struct AgentConfig {
     pub max_tool_rounds: u32,
     pub temperature: f32,
     pub verbose: bool,
+    /// Call the backend directly instead of running the RLM REPL loop
+    pub direct: bool,
+    /// Show RLM execution progress on stdout
+    pub exec_log: bool,
+    /// Run the tool calls of a round concurrently instead of in order
+    pub parallel_tools: bool,
+    /// Offer tools through the backend's native tool calling when it has
+    /// it (direct engine only); `<tool:name>` tags still work as a fallback
+    pub native_tools: bool,
+    /// Decides calls of tools set to [`Permission::Ask`] (None = deny them)
+    pub approval_hook: Option<Arc<dyn ToolApprovalHook>>,
+    /// Notes whose most relevant entries go into each task's context
+    pub memory: Option<Arc<MemoryStore>>,
+    /// Plan subtasks first and run them one at a time
+    pub planning: bool,
+    /// Times a plan may be rewritten after a subtask fails
+    pub max_replans: u32,
+    /// Tokens and cost one run may spend
+    pub budget: Budget,
+    /// Times per task the model is told its tool calls were malformed and
+    /// asked to send them again; each takes a tool round
+    pub max_corrections: u32,
+    /// Preamble, rules, instructions, and examples of the prompt
+    pub prompt: AgentPrompt,
+    /// Tools to keep and their permissions (None = every registered tool)
+    pub tool_profile: Option<ToolProfile>,
 }
 
 impl Default for AgentConfig {
@@ -130,75 +666,253 @@ impl Default for AgentConfig {
             max_tool_rounds: 10,
             temperature: 0.7,
             verbose: false,
+            direct: !cfg!(feature = "rlm"),
+            exec_log: true,
+            parallel_tools: true,
+            native_tools: true,
+            approval_hook: None,
+            memory: None,
+            planning: false,
+            max_replans: 2,
+            budget: Budget::default(),
+            max_corrections: 2,
+            prompt: AgentPrompt::default(),
+            tool_profile: None,
         }
     }
 }
 
+/// Notes put into the context of a task
+const MEMORY_NOTES: usize = 5;
+
+/// Characters of a note put into the context
+const MEMORY_NOTE_CHARS: usize = 1_000;
+
 /// Parse tool calls from model output
-/// Format: <tool:name>args</tool>
+/// Format: <tool:name>args</tool>, args as text or a JSON object
 fn parse_tool_calls(text: &str) -> Vec<ToolCall> {
-    let mut calls = Vec::new();
-    let mut remaining = text;
+    find_tags(text, "tool")
+        .into_iter()
+        .filter_map(|tag| {
+            let args = tag.content.trim().to_string();
+            let arguments = serde_json::from_str::<serde_json::Value>(&args)
+                .ok()
+                .filter(|v| v.is_object());
+            Some(ToolCall {
+                name: tag.argument?,
+                args,
+                arguments,
+            })
+        })
+        .collect()
+}
 
-    while let Some(start) = remaining.find("<tool:") {
-        let after_prefix = &remaining[start + 6..];
+/// What is wrong with the tool calls of a response, if anything
+///
+/// Catches `<tool:` tags that are never closed or have no tool name (when
+/// `from_text`; native calls can't be malformed) and calls of tools that
+/// aren't registered.
+fn tool_call_problems(
+    text: &str,
+    calls: &[ToolCall],
+    tools: &ToolRegistry,
+    from_text: bool,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+    if from_text {
+        let tags = find_tags(text, "tool");
+        let opened = text.matches("<tool:").count() + text.matches("<tool>").count();
+        if opened > tags.len() {
+            problems.push("a <tool:name> tag is never closed with </tool>".to_string());
+        }
+        let unnamed = |tag: &TagMatch| tag.argument.as_deref().unwrap_or("").trim().is_empty();
+        if tags.iter().any(unnamed) {
+            problems.push("a tool tag has no tool name".to_string());
+        }
+    }
+    for call in calls {
+        if tools.get(&call.name).is_none() {
+            problems.push(format!("there is no tool named '{}'", call.name));
+        }
+    }
+    problems
+}
 
-        if let Some(name_end) = after_prefix.find('>') {
-            let name = &after_prefix[..name_end];
+/// Check if response signals completion
+fn is_complete(text: &str) -> bool {
+    text.contains("<done>") || text.contains("</done>")
+}
 
-            let content_start = name_end + 1;
-            let end_tag = format!("</tool>");
+/// Extract final answer from response
+fn extract_answer(text: &str) -> Option<String> {
+    find_tags(text, "answer")
+        .into_iter()
+        .next()
+        .map(|tag| tag.content.trim().to_string())
+}
 
-            if let Some(end) = after_prefix[content_start..].find(&end_tag) {
-                let args = &after_prefix[content_start..content_start + end];
+/// A tool call made during a run, with its result
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCallRecord {
+    pub round: u32,
+    #[serde(flatten)]
+    pub call: ToolCall,
+    pub result: ToolResult,
+    /// Time the tool ran, retries included (0 when it was denied)
+    #[serde(default)]
+    pub duration_ms: u64,
+}
 
-                calls.push(ToolCall {
-                    name: name.to_string(),
-                    args: args.trim().to_string(),
-                });
+/// Calls of one tool during a run
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ToolStats {
+    pub calls: u32,
+    pub failures: u32,
+    /// Time the calls ran in all
+    pub duration_ms: u64,
+    /// Dollars the calls cost, when the tool reports it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+}
 
-                remaining = &after_prefix[content_start + end + end_tag.len()..];
-                continue;
-            }
+impl ToolStats {
+    fn add(&mut self, record: &ToolCallRecord) {
+        self.calls += 1;
+        if !record.result.success {
+            self.failures += 1;
+        }
+        self.duration_ms += record.duration_ms;
+        if let Some(cost) = record.result.cost {
+            *self.cost.get_or_insert(0.0) += cost;
         }
-        break;
     }
+}
 
-    calls
+/// One round of a run: what the model said and what it cost
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoundRecord {
+    pub round: u32,
+    pub response: String,
+    pub usage: Usage,
+    /// Time the model took to respond
+    pub duration_ms: u64,
 }
 
-/// Check if response signals completion
-fn is_complete(text: &str) -> bool {
-    text.contains("<done>") || text.contains("</done>")
+/// Structured result of an agent run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentRun {
+    pub answer: String,
+    pub rounds: u32,
+    /// The model's response in each round, in order
+    #[serde(default)]
+    pub responses: Vec<RoundRecord>,
+    pub tool_calls: Vec<ToolCallRecord>,
+    /// Calls, failures, time, and cost of each tool used
+    #[serde(default)]
+    pub tool_stats: BTreeMap<String, ToolStats>,
+    pub usage: Usage,
+    /// Rounds whose tool calls were malformed and sent back to the model
+    #[serde(default)]
+    pub corrections: u32,
+    /// Wall time of the whole run
+    #[serde(default)]
+    pub duration_ms: u64,
+    /// Client tool calls the run stopped at, for the caller to run (see
+    /// [`Agent::run_resumed`]); `answer` is then what the model said
+    /// along with them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pending_calls: Vec<ToolCall>,
 }
 
-/// Extract final answer from response
-fn extract_answer(text: &str) -> Option<String> {
-    if let Some(start) = text.find("<answer>") {
-        let after = &text[start + 8..];
-        if let Some(end) = after.find("</answer>") {
-            return Some(after[..end].trim().to_string());
+/// Why an agent run failed
+///
+/// Either way the run's report up to the failure is kept, to see what
+/// went wrong.
+#[derive(Debug)]
+pub enum AgentError {
+    /// The run used up its [`Budget`]; `partial` holds what it did until
+    /// then, with the usage of every call it made
+    BudgetExceeded {
+        limit: BudgetLimit,
+        partial: Box<AgentRunReport>,
+    },
+    /// The engine or backend failed, or the run ran out of rounds
+    Failed {
+        error: rlm_core::RlmError,
+        partial: Box<AgentRunReport>,
+    },
+}
+
+impl AgentError {
+    /// What the run did before it stopped
+    pub fn partial(&self) -> &AgentRunReport {
+        match self {
+            AgentError::BudgetExceeded { partial, .. } | AgentError::Failed { partial, .. } => {
+                partial
+            }
         }
     }
-    None
+}
+
+impl fmt::Display for AgentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AgentError::BudgetExceeded { limit, partial } => write!(
+                f,
+                "Run stopped: it used up its {} ({} tokens in {} rounds)",
+                limit, partial.run.usage.total_tokens, partial.run.rounds
+            ),
+            AgentError::Failed { error, .. } => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for AgentError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AgentError::BudgetExceeded { .. } => None,
+            AgentError::Failed { error, .. } => Some(error),
+        }
+    }
+}
+
+/// Reasoning engine behind the agent
+enum Engine {
+    /// Full RLM REPL loop per round
+    #[cfg(feature = "rlm")]
+    Rlm(Box<rlm::Rlm>),
+    /// Single chat completion per round, no Python required
+    Direct {
+        backend: std::sync::Arc<dyn ChatBackend>,
+        params: ChatParams,
+    },
 }
 
 /// Tool-use Agent
 pub struct Agent {
     config: AgentConfig,
     tools: ToolRegistry,
-    rlm: Rlm,
+    engine: Engine,
+    /// Summarizes oversized tool output
+    backend: Arc<dyn ChatBackend>,
+    /// Spending of the current run, when it has a budget
+    meter: Option<Arc<BudgetMeter>>,
+    /// Runs tool calls
+    runtime: Runtime,
 }
 
 impl Agent {
     /// Create a new agent
-    pub fn new(config: AgentConfig, tools: ToolRegistry) -> rlm::Result<Self> {
+    pub fn new(config: AgentConfig, mut tools: ToolRegistry) -> rlm_core::Result<Self> {
+        if let Some(ref profile) = config.tool_profile {
+            profile.apply(&mut tools);
+        }
         let mut rlm_config = RlmConfig::new(&config.model)
             .with_backend(config.backend.clone())
             .with_max_iterations(config.max_iterations)
             .with_temperature(config.temperature)
             .with_verbose(config.verbose)
-            .with_exec_log(true);
+            .with_exec_log(config.exec_log);
 
         if let Some(ref url) = config.base_url {
             rlm_config = rlm_config.with_base_url(url);
@@ -207,38 +921,269 @@ impl Agent {
             rlm_config = rlm_config.with_api_key(key);
         }
 
-        let rlm = Rlm::new(rlm_config)?;
+        let mut backend = create_backend(&rlm_config)?;
+        // Every call of a run, RLM iterations included, goes through the meter
+        let meter = if config.budget.is_limited() {
+            let meter = Arc::new(BudgetMeter::new(config.budget, &config.model)?);
+            backend = Arc::new(BudgetedBackend::new(backend, meter.clone()));
+            rlm_config = rlm_config.with_backend(Backend::Custom(backend.clone()));
+            Some(meter)
+        } else {
+            None
+        };
+        let engine = Self::create_engine(&config, rlm_config, backend.clone())?;
+
+        Ok(Self {
+            config,
+            tools,
+            engine,
+            backend,
+            meter,
+            runtime: Runtime::new()?,
+        })
+    }
+
+    /// Tools the agent can call
+    pub fn tools(&self) -> &ToolRegistry {
+        &self.tools
+    }
+
+    /// Pick the RLM loop or a direct backend based on config and features
+    fn create_engine(
+        config: &AgentConfig,
+        rlm_config: RlmConfig,
+        backend: Arc<dyn ChatBackend>,
+    ) -> rlm_core::Result<Engine> {
+        #[cfg(feature = "rlm")]
+        if !config.direct {
+            return Ok(Engine::Rlm(Box::new(rlm::Rlm::new(rlm_config)?)));
+        }
+        #[cfg(not(feature = "rlm"))]
+        let _ = rlm_config;
+
+        Ok(Engine::Direct {
+            backend,
+            params: ChatParams::new(&config.model).with_temperature(config.temperature),
+        })
+    }
+
+    /// Whether tools go through the backend's native tool calling
+    fn native_tools(&self) -> bool {
+        match &self.engine {
+            #[cfg(feature = "rlm")]
+            Engine::Rlm(_) => false,
+            Engine::Direct { backend, .. } => self.config.native_tools && backend.supports_tools(),
+        }
+    }
+
+    /// Run one round of reasoning over the context, returning the response
+    /// and any native tool calls
+    fn complete(&self, context: &str) -> rlm_core::Result<(String, Vec<ToolCall>, Usage)> {
+        match &self.engine {
+            #[cfg(feature = "rlm")]
+            Engine::Rlm(rlm) => {
+                let result = rlm.completion_with_context(context, None)?;
+                Ok((result.response, Vec::new(), result.usage))
+            }
+            Engine::Direct { backend, params } => {
+                let messages = [Message::user(context)];
+                if !self.native_tools() {
+                    let (response, usage) = backend.chat(&messages, params)?;
+                    return Ok((response, Vec::new(), usage));
+                }
+                let (response, calls, usage) =
+                    backend.chat_with_tools(&messages, &self.tools.definitions(), params)?;
+                let calls = calls
+                    .into_iter()
+                    .map(|call| ToolCall::from_tool_use(call, &self.tools))
+                    .collect();
+                Ok((response, calls, usage))
+            }
+        }
+    }
+
+    /// Run a round's tool calls on the runtime, results in call order
+    ///
+    /// Permissions are checked for every call, asking the approval hook in
+    /// call order, before any of them runs. Each result comes with the time
+    /// its tool ran.
+    fn execute_tools(&self, calls: &[ToolCall]) -> Vec<(ToolResult, Duration)> {
+        let futures: Vec<_> = calls
+            .iter()
+            .map(|call| {
+                if self.config.verbose {
+                    println!("  Tool: {}({})", call.name, call.args);
+                }
+                let hook = self.config.approval_hook.as_deref();
+                self.tools.permissions.check(call, hook).inspect_err(|e| {
+                    if self.config.verbose {
+                        println!("  Denied: {}", e.message());
+                    }
+                })?;
+                let call = self.tools.call(&call.name, &call.args, self.config.verbose);
+                Ok(async move {
+                    let started = Instant::now();
+                    let result = call.await;
+                    (result, started.elapsed())
+                })
+            })
+            .collect();
+        type Handle = tokio::task::JoinHandle<(ToolResult, Duration)>;
+        let joined = |handle: Result<Handle, ToolError>| async move {
+            match handle {
+                Ok(handle) => handle.await.unwrap_or_else(|e| {
+                    let error = ToolError::Failed(format!("Tool panicked: {}", e));
+                    (ToolResult::err(error), Duration::ZERO)
+                }),
+                Err(denied) => (ToolResult::err(denied), Duration::ZERO),
+            }
+        };
+
+        self.runtime.block_on(async {
+            let mut results = Vec::with_capacity(futures.len());
+            let mut running = Vec::new();
+            for future in futures {
+                let handle = future.map(tokio::spawn);
+                if self.config.parallel_tools {
+                    running.push(handle);
+                } else {
+                    results.push(joined(handle).await);
+                }
+            }
+            for handle in running {
+                results.push(joined(handle).await);
+            }
+            results
+        })
+    }
+
+    /// Cut a tool's output down to its limit, returning what the model
+    /// sees and the usage of any summary
+    ///
+    /// A failed summary falls back to truncation.
+    fn limit_output(&self, task: &str, tool: &str, output: String) -> (String, Usage) {
+        let Some(limit) = self.tools.output_limit(tool) else {
+            return (output, Usage::default());
+        };
+        let max_input_chars = match limit.overflow {
+            Overflow::Truncate(mode) => {
+                return (
+                    output::truncate(&output, limit.max_chars, mode),
+                    Usage::default(),
+                )
+            }
+            Overflow::Summarize { max_input_chars } => max_input_chars,
+        };
+        let Some((head, rest)) = output::split_overflow(&output, limit.max_chars) else {
+            return (output, Usage::default());
+        };
+
+        let input = output::truncate(rest, max_input_chars, output::OutputTruncation::HeadAndTail);
+        let prompt = output::summary_prompt(task, tool, &input);
+        let params = ChatParams::new(&self.config.model);
+        match self.backend.chat(&[Message::user(prompt)], &params) {
+            Ok((summary, usage)) => (
+                format!(
+                    "{}\n[{} more chars, summarized:]\n{}",
+                    head,
+                    rest.chars().count(),
+                    summary.trim()
+                ),
+                usage,
+            ),
+            Err(e) => {
+                if self.config.verbose {
+                    println!("  Summarizing {} output failed: {}", tool, e);
+                }
+                let mode = output::OutputTruncation::HeadAndTail;
+                let truncated = output::truncate(&output, limit.max_chars, mode);
+                (truncated, Usage::default())
+            }
+        }
+    }
+
+    /// Notes relevant to the task, as a context section
+    fn memory_notes(&self, task: &str) -> String {
+        let notes = match self.config.memory {
+            Some(ref memory) => memory.relevant(task, MEMORY_NOTES),
+            None => return String::new(),
+        };
+        if notes.is_empty() {
+            return String::new();
+        }
+        let mut section = "\nNOTES FROM EARLIER TASKS:\n".to_string();
+        for note in notes {
+            let content = output::truncate(
+                &note.content,
+                MEMORY_NOTE_CHARS,
+                output::OutputTruncation::Head,
+            );
+            section.push_str(&format!("- {}: {}\n", note.name, content));
+        }
+        section
+    }
 
-        Ok(Self { config, tools, rlm })
+    /// How to call tools, and the rule saying so
+    fn call_format(&self) -> (&'static str, &'static str) {
+        if self.native_tools() {
+            (
+                "Call tools with function calling; their parameters describe the arguments.",
+                "Use tools by calling them",
+            )
+        } else {
+            (
+                "<tool:tool_name>arguments</tool>\n\
+                 Arguments are plain text, or a JSON object for tools whose usage shows one.",
+                "Use tools by outputting <tool:name>args</tool>",
+            )
+        }
     }
 
-    /// Build context with tool docs and conversation
-    fn build_context(&self, task: &str, history: &[(String, String)]) -> String {
+    /// Message telling the model what was wrong with its tool calls
+    fn correction(&self, problems: &[String]) -> String {
+        let mut names = self.tools.list();
+        names.sort_unstable();
+        format!(
+            "Your tool call was malformed: {}. No tools were run. Call tools in this \
+             format:\n{}\nAvailable tools: {}",
+            problems.join("; "),
+            self.call_format().0,
+            names.join(", ")
+        )
+    }
+
+    /// Build context with tool docs and conversation, after the `earlier`
+    /// tasks of the session
+    fn build_context(&self, task: &str, history: &[(String, String)], earlier: &str) -> String {
         let tool_docs = self.tools.generate_docs();
+        let (call_format, call_rule) = self.call_format();
+        let prompt = &self.config.prompt;
 
         let mut context = format!(
-            r#"You are an AI agent that completes tasks using tools.
+            r#"{preamble}
 
 AVAILABLE TOOLS:
 {tool_docs}
 
 TOOL CALL FORMAT:
-<tool:tool_name>arguments</tool>
+{call_format}
 
 COMPLETION FORMAT:
 When done, output: <answer>your final answer</answer><done>
 
 RULES:
-1. Use tools by outputting <tool:name>args</tool>
-2. Wait for tool results before continuing
-3. You can call multiple tools
-4. End with <answer>...</answer><done> when task is complete
-
+{rules}
 IMPORTANT: never simulate tool use.
-
+{extra}{notes}{earlier}
 TASK: {task}
 "#,
+            preamble = prompt.preamble,
             tool_docs = tool_docs,
+            call_format = call_format,
+            rules = prompt.rules_section(call_rule),
+            extra = prompt.extra_sections(),
+            notes = self.memory_notes(task),
+            earlier = earlier,
             task = task
         );
 
@@ -252,33 +1197,347 @@ TASK: {task}
     }
 
     /// Run the agent on a task
-    pub fn run(&self, task: &str) -> rlm::Result<String> {
-        let mut history: Vec<(String, String)> = Vec::new();
+    pub fn run(&self, task: &str) -> Result<String, AgentError> {
+        self.run_detailed(task).map(|run| run.answer)
+    }
+
+    /// Run the agent on a task, returning rounds, tool calls, and usage
+    pub fn run_detailed(&self, task: &str) -> Result<AgentRun, AgentError> {
+        self.run_report(task).map(|report| report.run)
+    }
+
+    /// Run the agent on a task, returning the run and, with planning on,
+    /// the plan and how each subtask went
+    pub fn run_report(&self, task: &str) -> Result<AgentRunReport, AgentError> {
+        self.run_inner(task, Events(None), "", None)
+    }
+
+    /// Run the agent on a task like [`Self::run_report`], sending progress
+    /// to `events` as it goes
+    ///
+    /// The sender is dropped when the run returns, ending the receiver's
+    /// iteration; run the agent on another thread than the one reading.
+    pub fn run_with_events(
+        &self,
+        task: &str,
+        events: mpsc::Sender<AgentEvent>,
+    ) -> Result<AgentRunReport, AgentError> {
+        self.run_inner(task, Events(Some(&events)), "", None)
+    }
+
+    /// Run the agent on a task as the next turn of `session`
+    ///
+    /// The task sees the session's recent tasks in its context, and its
+    /// outcome, failed or not, is added to the session.
+    pub fn run_in_session(
+        &self,
+        session: &mut AgentSession,
+        task: &str,
+    ) -> Result<AgentRunReport, AgentError> {
+        let result = self.run_inner(task, Events(None), &session.context_section(), None);
+        session.record(task, &result);
+        result
+    }
+
+    /// [`Self::run_in_session`], sending progress to `events`
+    pub fn run_in_session_with_events(
+        &self,
+        session: &mut AgentSession,
+        task: &str,
+        events: mpsc::Sender<AgentEvent>,
+    ) -> Result<AgentRunReport, AgentError> {
+        let result = self.run_inner(
+            task,
+            Events(Some(&events)),
+            &session.context_section(),
+            None,
+        );
+        session.record(task, &result);
+        result
+    }
+
+    /// Run the agent on a task whose [`ClientTool`]s the caller runs
+    ///
+    /// `earlier` is the conversation before the task, as a context section,
+    /// and `turns` what happened since the task was last handed back. When
+    /// the model calls client tools, the run stops with the calls in
+    /// [`AgentRun::pending_calls`]; run them and call again with the calls
+    /// and their results added to `turns`. Other calls made in the same
+    /// response are dropped, and the run is never planned.
+    pub fn run_resumed(
+        &self,
+        task: &str,
+        earlier: &str,
+        turns: &[ClientTurn],
+    ) -> Result<AgentRun, AgentError> {
+        self.run_inner(task, Events(None), earlier, Some(client::history(turns)))
+            .map(|report| report.run)
+    }
+
+    /// `resumed` holds the history of a run resumed after client tool
+    /// calls, None for a new run
+    fn run_inner(
+        &self,
+        task: &str,
+        events: Events,
+        earlier: &str,
+        resumed: Option<Vec<(String, String)>>,
+    ) -> Result<AgentRunReport, AgentError> {
+        self.tools.reset();
+        if let Some(ref meter) = self.meter {
+            meter.reset();
+        }
+        let started = Instant::now();
+        let mut report = AgentRunReport {
+            run: AgentRun {
+                answer: String::new(),
+                rounds: 0,
+                responses: Vec::new(),
+                tool_calls: Vec::new(),
+                tool_stats: BTreeMap::new(),
+                usage: Usage::default(),
+                corrections: 0,
+                duration_ms: 0,
+                pending_calls: Vec::new(),
+            },
+            subtasks: Vec::new(),
+            replans: 0,
+        };
+        let result = match resumed {
+            None if self.config.planning => self.run_planned(task, &mut report, events, earlier),
+            history => self
+                .run_rounds(
+                    task,
+                    &mut report.run,
+                    events,
+                    earlier,
+                    history.unwrap_or_default(),
+                )
+                .map(|_| ()),
+        };
+        report.run.duration_ms = started.elapsed().as_millis() as u64;
+
+        if let Err(error) = result {
+            let Some((meter, limit)) = self
+                .meter
+                .as_ref()
+                .and_then(|meter| Some((meter, meter.exceeded()?)))
+            else {
+                return Err(AgentError::Failed {
+                    error,
+                    partial: Box::new(report),
+                });
+            };
+            // Count what the failed call's round spent, too
+            report.run.usage = meter.spent();
+            return Err(AgentError::BudgetExceeded {
+                limit,
+                partial: Box::new(report),
+            });
+        }
+
+        events.emit(|| AgentEvent::Answer {
+            answer: report.run.answer.clone(),
+        });
+        Ok(report)
+    }
+
+    /// Ask the backend for subtasks
+    fn plan(&self, prompt: &str, run: &mut AgentRun) -> rlm_core::Result<Vec<Subtask>> {
+        let params = ChatParams::new(&self.config.model).with_temperature(self.config.temperature);
+        let (response, usage) = self.backend.chat(&[Message::user(prompt)], &params)?;
+        run.usage.add(&usage);
+        let subtasks = plan::parse_plan(&response);
+        if self.config.verbose {
+            println!("Plan:");
+            for subtask in &subtasks {
+                println!("  - {}", subtask.description);
+            }
+        }
+        Ok(subtasks)
+    }
+
+    /// Plan the task and run its subtasks in order, replanning the rest
+    /// when one fails; the plan and replans go into `report` as they happen
+    fn run_planned(
+        &self,
+        task: &str,
+        report: &mut AgentRunReport,
+        events: Events,
+        earlier: &str,
+    ) -> rlm_core::Result<()> {
+        let AgentRunReport {
+            run,
+            subtasks,
+            replans,
+        } = report;
+        let prompt = plan::plan_prompt(task, &self.tools.generate_docs(), earlier);
+        *subtasks = self.plan(&prompt, run)?;
+        if subtasks.is_empty() {
+            subtasks.push(Subtask::new(task));
+        }
+
+        let mut index = 0;
+        while index < subtasks.len() {
+            if self.config.verbose {
+                println!(
+                    "══ Subtask {}/{}: {} ══",
+                    index + 1,
+                    subtasks.len(),
+                    subtasks[index].description
+                );
+            }
+            events.emit(|| AgentEvent::SubtaskStarted {
+                index,
+                description: subtasks[index].description.clone(),
+            });
+            let rounds_before = run.rounds;
+            let subtask = plan::subtask_task(task, subtasks, index);
+            let outcome = self.run_rounds(&subtask, run, events, earlier, Vec::new());
+            subtasks[index].rounds = run.rounds - rounds_before;
+
+            let (reason, error) = match outcome {
+                Ok(response) => match find_tags(&response, "failed").into_iter().next() {
+                    Some(tag) => (tag.content.trim().to_string(), None),
+                    None => {
+                        subtasks[index].status = SubtaskStatus::Done;
+                        subtasks[index].result = Some(run.answer.clone());
+                        events.emit(|| AgentEvent::SubtaskFinished {
+                            index,
+                            subtask: subtasks[index].clone(),
+                        });
+                        index += 1;
+                        continue;
+                    }
+                },
+                Err(e @ rlm_core::RlmError::MaxIterationsReached(_)) => (e.to_string(), Some(e)),
+                Err(e) => return Err(e),
+            };
+            subtasks[index].status = SubtaskStatus::Failed;
+            subtasks[index].result = Some(reason.clone());
+            events.emit(|| AgentEvent::SubtaskFinished {
+                index,
+                subtask: subtasks[index].clone(),
+            });
+
+            let rest = if *replans < self.config.max_replans {
+                *replans += 1;
+                let prompt = plan::replan_prompt(task, &subtasks[..index], &subtasks[index]);
+                self.plan(&prompt, run)?
+            } else {
+                Vec::new()
+            };
+            if rest.is_empty() {
+                // No way forward: the failure is the outcome
+                if let Some(e) = error {
+                    return Err(e);
+                }
+                run.answer = format!(
+                    "Could not complete the task; '{}' failed: {}",
+                    subtasks[index].description, reason
+                );
+                return Ok(());
+            }
+            subtasks.truncate(index + 1);
+            subtasks.extend(rest);
+            index += 1;
+        }
+
+        // One step's answer is the answer; several are combined
+        let done: Vec<&Subtask> = subtasks
+            .iter()
+            .filter(|s| s.status == SubtaskStatus::Done)
+            .collect();
+        if done.len() > 1 {
+            let params = ChatParams::new(&self.config.model);
+            let prompt = plan::answer_prompt(task, subtasks);
+            let (answer, usage) = self.backend.chat(&[Message::user(prompt)], &params)?;
+            run.usage.add(&usage);
+            run.answer = answer.trim().to_string();
+        }
+        Ok(())
+    }
 
-        for round in 0..self.config.max_tool_rounds {
+    /// Run tool rounds on `task` after `history` until the model is done,
+    /// or calls client tools, adding to `run`; returns the final response
+    fn run_rounds(
+        &self,
+        task: &str,
+        run: &mut AgentRun,
+        events: Events,
+        earlier: &str,
+        mut history: Vec<(String, String)>,
+    ) -> rlm_core::Result<String> {
+        let mut corrections = 0;
+
+        for _ in 0..self.config.max_tool_rounds {
+            run.rounds += 1;
+            let round = run.rounds;
             if self.config.verbose {
-                println!("══ Agent Round {} ══", round + 1);
+                println!("══ Agent Round {} ══", round);
             }
+            events.emit(|| AgentEvent::RoundStarted { round });
 
             // Build context and call RLM
-            let context = self.build_context(task, &history);
-            let result = self.rlm.completion_with_context(&context, None)?;
-            let response = &result.response;
+            let context = self.build_context(task, &history, earlier);
+            let started = Instant::now();
+            let (response, native_calls, round_usage) = self.complete(&context)?;
+            run.usage.add(&round_usage);
+            run.responses.push(RoundRecord {
+                round,
+                response: response.clone(),
+                usage: round_usage,
+                duration_ms: started.elapsed().as_millis() as u64,
+            });
+            let response = &response;
 
             if self.config.verbose {
                 println!("Response: {}", response);
             }
+            events.emit(|| AgentEvent::ModelResponse {
+                round,
+                text: response.clone(),
+            });
 
             // Check for completion
             if is_complete(response) {
-                if let Some(answer) = extract_answer(response) {
-                    return Ok(answer);
-                }
+                run.answer = extract_answer(response).unwrap_or_else(|| response.clone());
                 return Ok(response.clone());
             }
 
-            // Parse and execute tool calls
-            let tool_calls = parse_tool_calls(response);
+            // Take native tool calls, falling back to tags in the text
+            let native = !native_calls.is_empty();
+            let tool_calls = if native {
+                native_calls
+            } else {
+                parse_tool_calls(response)
+            };
+
+            // Native calls aren't in the text; record them for later rounds
+            let mut turn = response.clone();
+            if native {
+                for call in &tool_calls {
+                    turn.push_str(&format!("\n[called {}: {}]", call.name, call.args));
+                }
+            }
+
+            // Send malformed calls back instead of running what's left of them
+            let problems = tool_call_problems(response, &tool_calls, &self.tools, !native);
+            if !problems.is_empty() && corrections < self.config.max_corrections {
+                corrections += 1;
+                run.corrections += 1;
+                if self.config.verbose {
+                    println!("Malformed tool calls: {}", problems.join("; "));
+                }
+                let correction = self.correction(&problems);
+                events.emit(|| AgentEvent::Correction {
+                    round,
+                    message: correction.clone(),
+                });
+                history.push(("Assistant".to_string(), turn));
+                history.push(("User".to_string(), correction));
+                continue;
+            }
 
             if tool_calls.is_empty() {
                 // No tools called, treat response as final
@@ -286,33 +1545,65 @@ TASK: {task}
                 continue;
             }
 
-            // Execute tools and collect results
-            let mut tool_output = String::new();
-            for call in &tool_calls {
-                if self.config.verbose {
-                    println!("  Tool: {}({})", call.name, call.args);
+            // Client tools run on the caller's side: hand their calls back
+            if let Some(pending) = client::pending_calls(&tool_calls, &self.tools) {
+                let tags = find_tags(response, "tool");
+                let mut text = response.clone();
+                for tag in tags.iter().rev() {
+                    text.replace_range(tag.span.clone(), "");
                 }
+                run.answer = text.trim().to_string();
+                run.pending_calls = pending;
+                return Ok(response.clone());
+            }
 
-                let result = self.tools.execute(&call.name, &call.args);
-
+            // Execute tools and collect results
+            for call in &tool_calls {
+                events.emit(|| AgentEvent::ToolCallStarted {
+                    round,
+                    call: call.clone(),
+                });
+            }
+            let results = self.execute_tools(&tool_calls);
+            let mut tool_output = String::new();
+            for (call, (mut result, duration)) in tool_calls.into_iter().zip(results) {
                 if result.success {
+                    let (output, summary_usage) =
+                        self.limit_output(task, &call.name, std::mem::take(&mut result.output));
+                    result.output = output;
+                    run.usage.add(&summary_usage);
+
                     tool_output
                         .push_str(&format!("[{}] Result:\n{}\n\n", call.name, result.output));
                 } else {
-                    tool_output.push_str(&format!(
-                        "[{}] Error: {}\n\n",
-                        call.name,
-                        result.error.unwrap_or_default()
-                    ));
+                    let error = result
+                        .error
+                        .as_ref()
+                        .map(ToString::to_string)
+                        .unwrap_or_default();
+                    tool_output.push_str(&format!("[{}] Error: {}\n\n", call.name, error));
                 }
+
+                let record = ToolCallRecord {
+                    round,
+                    call,
+                    result,
+                    duration_ms: duration.as_millis() as u64,
+                };
+                events.emit(|| AgentEvent::ToolCallFinished(record.clone()));
+                run.tool_stats
+                    .entry(record.call.name.clone())
+                    .or_default()
+                    .add(&record);
+                run.tool_calls.push(record);
             }
 
             // Add to history
-            history.push(("Assistant".to_string(), response.clone()));
+            history.push(("Assistant".to_string(), turn));
             history.push(("Tool Results".to_string(), tool_output));
         }
 
-        Err(rlm::RlmError::MaxIterationsReached(
+        Err(rlm_core::RlmError::MaxIterationsReached(
             self.config.max_tool_rounds,
         ))
     }
@@ -340,12 +1631,815 @@ mod tests {
         assert_eq!(calls.len(), 2);
     }
 
+    #[test]
+    fn test_generate_docs() {
+        let workspace = Workspace::new(std::env::temp_dir());
+        let docs = tools::default_tools_in(workspace.clone()).generate_docs();
+        // Same order whatever the hashing
+        assert_eq!(docs, tools::default_tools_in(workspace).generate_docs());
+
+        let headers: Vec<&str> = docs.lines().filter(|l| l.starts_with('[')).collect();
+        assert_eq!(headers, ["[code]", "[files]", "[general]", "[web]"]);
+        assert!(docs.contains("[files]\n- edit_file: "));
+        assert!(docs.contains("- write_file: Write content to a file\n  Usage: <tool:write_file>"));
+        assert!(docs.contains("  Parameters:\n    content (string, required)\n"));
+        // Plain-text tools have no parameters to list
+        let echo = docs.split("- echo: ").nth(1).unwrap();
+        assert!(!echo.lines().nth(2).unwrap().contains("Parameters"));
+    }
+
+    #[test]
+    fn test_json_tool_arguments() {
+        let path = std::env::temp_dir().join("rlm_agent_json_args.txt");
+        let call = format!(
+            "<tool:write_file>\n{}\n</tool>",
+            serde_json::json!({ "path": path, "content": "hi" })
+        );
+        let calls = parse_tool_calls(&call);
+        assert_eq!(calls[0].arguments.as_ref().unwrap()["content"], "hi");
+        assert!(parse_tool_calls("<tool:echo>hi</tool>")[0]
+            .arguments
+            .is_none());
+
+        let mock = rlm_core::MockBackend::new([
+            "<tool:write_file>notes.txt|||hi</tool>",
+            &call,
+            "<answer>written</answer><done>",
+        ]);
+        let config = AgentConfig {
+            backend: Backend::Mock(mock.clone()),
+            direct: true,
+            ..Default::default()
+        };
+        let tools = tools::default_tools_in(Workspace::new(std::env::temp_dir()));
+        let agent = Agent::new(config, tools).unwrap();
+
+        let run = agent.run_detailed("Write hi").unwrap();
+        assert_eq!(
+            run.tool_calls[0].result.error.as_ref().unwrap().kind(),
+            "invalid_args"
+        );
+        assert!(run.tool_calls[1].result.success);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hi");
+        let second_round = &mock.requests()[1][0].content;
+        assert!(second_round
+            .contains("[write_file] Error: [invalid_args] arguments must be JSON matching"));
+        let _ = std::fs::remove_file(path);
+    }
+
+    /// Backend answering with scripted native tool calls
+    struct NativeToolBackend {
+        rounds: std::sync::Mutex<Vec<(String, Vec<ToolUse>)>>,
+        offered: std::sync::Mutex<Vec<ToolDefinition>>,
+    }
+
+    impl ChatBackend for NativeToolBackend {
+        fn chat(&self, _: &[Message], _: &ChatParams) -> rlm_core::Result<(String, Usage)> {
+            unreachable!("tools are offered natively")
+        }
+
+        fn supports_tools(&self) -> bool {
+            true
+        }
+
+        fn chat_with_tools(
+            &self,
+            _: &[Message],
+            tools: &[ToolDefinition],
+            _: &ChatParams,
+        ) -> rlm_core::Result<(String, Vec<ToolUse>, Usage)> {
+            *self.offered.lock().unwrap() = tools.to_vec();
+            let (text, calls) = self.rounds.lock().unwrap().remove(0);
+            Ok((text, calls, Usage::default()))
+        }
+    }
+
+    #[test]
+    fn test_native_tool_calls() {
+        let path = std::env::temp_dir().join("rlm_agent_native_tools.txt");
+        let tool_use = |name: &str, arguments| ToolUse {
+            id: format!("call_{}", name),
+            name: name.to_string(),
+            arguments,
+        };
+        let backend = Arc::new(NativeToolBackend {
+            rounds: std::sync::Mutex::new(vec![
+                (
+                    "Working on it.".to_string(),
+                    vec![
+                        tool_use("echo", serde_json::json!({ "input": "hello" })),
+                        tool_use(
+                            "write_file",
+                            serde_json::json!({ "path": path, "content": "hi" }),
+                        ),
+                    ],
+                ),
+                // Tags still work when the model writes them out
+                ("<tool:echo>again</tool>".to_string(), Vec::new()),
+                ("<answer>done</answer><done>".to_string(), Vec::new()),
+            ]),
+            offered: std::sync::Mutex::new(Vec::new()),
+        });
+        let config = AgentConfig {
+            backend: Backend::Custom(backend.clone()),
+            direct: true,
+            ..Default::default()
+        };
+        let tools = tools::default_tools_in(Workspace::new(std::env::temp_dir()));
+        let agent = Agent::new(config, tools).unwrap();
+
+        let run = agent.run_detailed("Echo and write").unwrap();
+        assert_eq!(run.answer, "done");
+        assert_eq!(run.tool_calls[0].call.args, "hello");
+        assert_eq!(run.tool_calls[0].result.output, "hello");
+        assert!(run.tool_calls[1].result.success);
+        assert_eq!(run.tool_calls[2].call.args, "again");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hi");
+
+        let offered = backend.offered.lock().unwrap();
+        let echo = offered.iter().find(|t| t.name == "echo").unwrap();
+        assert_eq!(echo.parameters["required"][0], "input");
+        let write = offered.iter().find(|t| t.name == "write_file").unwrap();
+        assert_eq!(write.parameters["required"][1], "content");
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_permissions_and_approval() {
+        let mock = rlm_core::MockBackend::new([
+            "<tool:shell>echo hi</tool><tool:shell>rm -rf /tmp/x</tool><tool:calc>1 + 1</tool>",
+            "<answer>done</answer><done>",
+        ]);
+        let asked = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = asked.clone();
+        let config = AgentConfig {
+            backend: Backend::Mock(mock.clone()),
+            direct: true,
+            approval_hook: Some(Arc::new(move |call: &ToolCall| {
+                seen.lock().unwrap().push(call.args.clone());
+                if call.args.starts_with("rm") {
+                    ToolApproval::Deny("the user said no".to_string())
+                } else {
+                    ToolApproval::Approve
+                }
+            })),
+            ..Default::default()
+        };
+        let mut tools = tools::default_tools();
+        tools.set_permission("shell", Permission::Ask);
+        tools.set_permission("calc", Permission::Deny);
+        let agent = Agent::new(config, tools).unwrap();
+
+        let run = agent.run_detailed("Clean up").unwrap();
+        assert_eq!(*asked.lock().unwrap(), ["echo hi", "rm -rf /tmp/x"]);
+        assert_eq!(run.tool_calls[0].result.output, "hi\n");
+        for record in &run.tool_calls[1..] {
+            let error = record.result.error.as_ref().unwrap();
+            assert_eq!(error.kind(), "permission_denied");
+        }
+        let second_round = &mock.requests()[1][0].content;
+        assert!(second_round.contains(
+            "[shell] Error: [permission_denied] shell was not allowed to run: the user said no"
+        ));
+        assert!(second_round.contains("calc was not allowed to run: denied by policy"));
+
+        // Nobody to ask outside an agent
+        let runtime = Runtime::new().unwrap();
+        let mut tools = tools::default_tools();
+        tools.set_permission("echo", Permission::Ask);
+        let result = runtime.block_on(tools.execute("echo", "hi"));
+        assert_eq!(result.error.unwrap().kind(), "permission_denied");
+    }
+
+    #[test]
+    fn test_output_limits() {
+        let mock = rlm_core::MockBackend::new([
+            "<tool:echo>0123456789abcdefghij</tool><tool:calc>10 ** 30</tool>",
+            "The rest is the alphabet.",
+            "<answer>done</answer><done>",
+        ]);
+        let config = AgentConfig {
+            backend: Backend::Mock(mock.clone()),
+            direct: true,
+            ..Default::default()
+        };
+        let mut tools = tools::default_tools();
+        tools.set_default_output_limit(Some(OutputLimit::truncate(8)));
+        tools.set_output_limit("echo", OutputLimit::summarize(10));
+        let agent = Agent::new(config, tools).unwrap();
+
+        let run = agent.run_detailed("Echo").unwrap();
+        assert_eq!(
+            run.tool_calls[0].result.output,
+            "0123456789\n[10 more chars, summarized:]\nThe rest is the alphabet."
+        );
+        assert_eq!(
+            run.tool_calls[1].result.output,
+            "1000\n[truncated 23 chars]\n0000"
+        );
+        let requests = mock.requests();
+        assert!(requests[1][0]
+            .content
+            .contains("TASK: Echo\n\nOUTPUT:\nabcdefghij"));
+        assert!(requests[2][0]
+            .content
+            .contains("[echo] Result:\n0123456789\n[10 more chars, summarized:]"));
+    }
+
+    #[test]
+    fn test_memory_notes_in_context() {
+        let memory = Arc::new(MemoryStore::in_memory());
+        memory
+            .store("deploy", "Deploys go through the staging cluster")
+            .unwrap();
+        memory.store("lunch", "Pizza on Fridays").unwrap();
+        let mock = rlm_core::MockBackend::new(["<answer>ok</answer><done>"]);
+        let config = AgentConfig {
+            backend: Backend::Mock(mock.clone()),
+            direct: true,
+            memory: Some(memory),
+            ..Default::default()
+        };
+        let agent = Agent::new(config, tools::default_tools()).unwrap();
+
+        agent.run("Deploy the new release").unwrap();
+        let context = &mock.requests()[0][0].content;
+        assert!(context.contains(
+            "NOTES FROM EARLIER TASKS:\n- deploy: Deploys go through the staging cluster\n"
+        ));
+        assert!(!context.contains("Pizza"));
+    }
+
+    #[test]
+    fn test_planned_run_replans_after_failure() {
+        let mock = rlm_core::MockBackend::new([
+            "<subtask>Echo a</subtask>\n<subtask>Echo b</subtask>",
+            "<tool:echo>a</tool>",
+            "<answer>a</answer><done>",
+            "<failed>b is not allowed</failed><done>",
+            "<subtask>Echo c</subtask>",
+            "<answer>c</answer><done>",
+            "a and c",
+        ]);
+        let config = AgentConfig {
+            backend: Backend::Mock(mock.clone()),
+            direct: true,
+            planning: true,
+            ..Default::default()
+        };
+        let agent = Agent::new(config, tools::default_tools()).unwrap();
+
+        let report = agent.run_report("Echo some letters").unwrap();
+        assert_eq!(report.run.answer, "a and c");
+        assert_eq!(report.run.rounds, 4);
+        assert_eq!(report.run.tool_calls.len(), 1);
+        assert_eq!(report.replans, 1);
+        let steps: Vec<(&str, SubtaskStatus, u32)> = report
+            .subtasks
+            .iter()
+            .map(|s| (s.description.as_str(), s.status, s.rounds))
+            .collect();
+        assert_eq!(
+            steps,
+            [
+                ("Echo a", SubtaskStatus::Done, 2),
+                ("Echo b", SubtaskStatus::Failed, 1),
+                ("Echo c", SubtaskStatus::Done, 1),
+            ]
+        );
+        assert_eq!(
+            report.subtasks[1].result.as_deref(),
+            Some("b is not allowed")
+        );
+
+        // Later steps and the replan see what was done
+        let requests = mock.requests();
+        assert!(requests[3][0].content.contains("step 2 of 2"));
+        assert!(requests[3][0]
+            .content
+            .contains("DONE SO FAR:\n1. Echo a\n   Result: a"));
+        assert!(requests[4][0]
+            .content
+            .contains("FAILED: Echo b\nREASON: b is not allowed"));
+    }
+
+    #[test]
+    fn test_malformed_tool_calls_are_corrected() {
+        let mock = rlm_core::MockBackend::new([
+            "<tool:echo>hello",
+            "<tool:ecoh>hello</tool>",
+            "<tool:echo>hello</tool>",
+            "<answer>hello</answer><done>",
+        ]);
+        let config = AgentConfig {
+            backend: Backend::Mock(mock.clone()),
+            direct: true,
+            ..Default::default()
+        };
+        let agent = Agent::new(config, tools::default_tools()).unwrap();
+
+        let run = agent.run_detailed("Echo hello").unwrap();
+        assert_eq!(run.corrections, 2);
+        assert_eq!(run.tool_calls.len(), 1);
+        let requests = mock.requests();
+        assert!(requests[1][0]
+            .content
+            .contains("User: Your tool call was malformed: a <tool:name> tag is never closed"));
+        assert!(requests[2][0]
+            .content
+            .contains("malformed: there is no tool named 'ecoh'. No tools were run."));
+
+        // Past the limit, an unknown tool is an error result again
+        let mock = rlm_core::MockBackend::new([
+            "<tool:ecoh>hello</tool>",
+            "<answer>gave up</answer><done>",
+        ]);
+        let config = AgentConfig {
+            backend: Backend::Mock(mock),
+            direct: true,
+            max_corrections: 0,
+            ..Default::default()
+        };
+        let agent = Agent::new(config, tools::default_tools()).unwrap();
+        let run = agent.run_detailed("Echo hello").unwrap();
+        assert_eq!(run.corrections, 0);
+        assert_eq!(
+            run.tool_calls[0].result.error.as_ref().unwrap().kind(),
+            "not_found"
+        );
+    }
+
+    #[test]
+    fn test_custom_prompt_sections() {
+        let mock = rlm_core::MockBackend::new(["<answer>ok</answer><done>"]);
+        let prompt = AgentPrompt::new()
+            .with_preamble("You are a release engineer.")
+            .with_rule("Never push tags")
+            .with_instructions("Changelogs live in CHANGES.md.")
+            .with_example("Bump the version", "<tool:read_file>Cargo.toml</tool>");
+        let config = AgentConfig {
+            backend: Backend::Mock(mock.clone()),
+            direct: true,
+            prompt,
+            ..Default::default()
+        };
+        let agent = Agent::new(config, ToolRegistry::new()).unwrap();
+        agent.run("Cut a release").unwrap();
+
+        let context = &mock.requests()[0][0].content;
+        assert!(context.starts_with("You are a release engineer.\n"));
+        assert!(context.contains(
+            "3. You can call multiple tools\n4. Never push tags\n\
+             5. End with <answer>...</answer><done> when task is complete\n"
+        ));
+        assert!(context.contains("INSTRUCTIONS:\nChangelogs live in CHANGES.md.\n"));
+        assert!(context.contains(
+            "EXAMPLES:\nExample 1\nTask: Bump the version\n\
+             Assistant: <tool:read_file>Cargo.toml</tool>\n"
+        ));
+    }
+
+    #[test]
+    fn test_tool_call_limits_and_stats() {
+        let mock = rlm_core::MockBackend::new([
+            "<tool:echo>a</tool><tool:echo>b</tool><tool:calc>nope(</tool>",
+            "<answer>done</answer><done>",
+            "<tool:echo>c</tool>",
+            "<answer>done</answer><done>",
+        ]);
+        let config = AgentConfig {
+            backend: Backend::Mock(mock),
+            direct: true,
+            parallel_tools: false,
+            ..Default::default()
+        };
+        let mut tools = tools::default_tools();
+        tools.set_max_calls("echo", 1);
+        let agent = Agent::new(config, tools).unwrap();
+
+        let run = agent.run_detailed("Echo twice").unwrap();
+        let denied = run.tool_calls[1].result.error.as_ref().unwrap();
+        assert_eq!(denied.kind(), "permission_denied");
+        assert!(denied.message().contains("limited to 1 call(s) per task"));
+        assert_eq!(run.tool_stats["echo"].calls, 2);
+        assert_eq!(run.tool_stats["echo"].failures, 1);
+        assert_eq!(run.tool_stats["calc"].failures, 1);
+        assert_eq!(run.tool_stats["echo"].cost, None);
+
+        // The count starts over with the next task
+        let run = agent.run_detailed("Echo once").unwrap();
+        assert!(run.tool_calls[0].result.success);
+    }
+
     #[test]
     fn test_is_complete() {
         assert!(is_complete("Here's the answer <answer>42</answer><done>"));
         assert!(!is_complete("Still working..."));
     }
 
+    #[cfg(feature = "rlm")]
+    #[test]
+    fn test_agent_run_with_mock_backend() {
+        let mock = rlm_core::MockBackend::new(["FINAL(<answer>42</answer><done>)"]);
+        let config = AgentConfig {
+            backend: Backend::Mock(mock),
+            ..Default::default()
+        };
+        let agent = Agent::new(config, tools::default_tools()).unwrap();
+
+        assert_eq!(agent.run("What is 6 * 7?").unwrap(), "42");
+    }
+
+    #[test]
+    fn test_agent_direct_mode_with_tools() {
+        let mock = rlm_core::MockBackend::new([
+            "<tool:echo>hello</tool>",
+            "<answer>echoed hello</answer><done>",
+        ]);
+        let config = AgentConfig {
+            backend: Backend::Mock(mock.clone()),
+            direct: true,
+            ..Default::default()
+        };
+        let agent = Agent::new(config, tools::default_tools()).unwrap();
+
+        let run = agent.run_detailed("Echo hello").unwrap();
+        assert_eq!(run.answer, "echoed hello");
+        assert_eq!(run.rounds, 2);
+        assert_eq!(run.tool_calls.len(), 1);
+        assert_eq!(run.tool_calls[0].call.name, "echo");
+        assert!(run.tool_calls[0].result.success);
+        assert!(run.usage.total_tokens > 0);
+        let responses: Vec<(u32, &str)> = run
+            .responses
+            .iter()
+            .map(|r| (r.round, r.response.as_str()))
+            .collect();
+        assert_eq!(
+            responses,
+            [
+                (1, "<tool:echo>hello</tool>"),
+                (2, "<answer>echoed hello</answer><done>")
+            ]
+        );
+        assert!(run.responses.iter().all(|r| r.usage.total_tokens > 0));
+
+        let second_round = &mock.requests()[1][0].content;
+        assert!(second_round.contains("[echo] Result:\nhello"));
+    }
+
+    #[test]
+    fn test_client_tool_calls_handed_back() {
+        let mock = rlm_core::MockBackend::new([
+            "Let me check. <tool:weather>{\"city\": \"Paris\"}</tool>",
+            "<answer>Sunny in Paris</answer><done>",
+        ]);
+        let config = AgentConfig {
+            backend: Backend::Mock(mock.clone()),
+            direct: true,
+            ..Default::default()
+        };
+        let mut tools = ToolRegistry::new();
+        let parameters = serde_json::json!({
+            "type": "object",
+            "properties": { "city": { "type": "string" } }
+        });
+        tools.register(ClientTool::new(
+            "weather",
+            "Current weather",
+            Some(parameters),
+        ));
+        let agent = Agent::new(config, tools).unwrap();
+
+        let run = agent.run_resumed("Weather in Paris?", "", &[]).unwrap();
+        assert_eq!(run.answer, "Let me check.");
+        assert_eq!(run.pending_calls.len(), 1);
+        assert_eq!(run.pending_calls[0].args, "{\"city\":\"Paris\"}");
+        assert!(run.tool_calls.is_empty());
+
+        let turns = [
+            ClientTurn::Assistant {
+                text: run.answer,
+                calls: run.pending_calls,
+            },
+            ClientTurn::ToolResult {
+                name: "weather".to_string(),
+                output: "sunny, 24C".to_string(),
+            },
+        ];
+        let run = agent.run_resumed("Weather in Paris?", "", &turns).unwrap();
+        assert_eq!(run.answer, "Sunny in Paris");
+        assert!(run.pending_calls.is_empty());
+        let context = &mock.requests()[1][0].content;
+        assert!(context.contains("[client]\n- weather: Current weather"));
+        assert!(context.contains("[called weather: {\"city\":\"Paris\"}]"));
+        assert!(context.contains("[weather] Result:\nsunny, 24C"));
+    }
+
+    #[test]
+    fn test_failed_run_keeps_report() {
+        let mock = rlm_core::MockBackend::new(["<tool:echo>again</tool>"; 2]);
+        let config = AgentConfig {
+            backend: Backend::Mock(mock),
+            direct: true,
+            max_tool_rounds: 2,
+            ..Default::default()
+        };
+        let agent = Agent::new(config, tools::default_tools()).unwrap();
+
+        let err = agent.run_report("Echo forever").unwrap_err();
+        assert!(matches!(
+            err,
+            AgentError::Failed {
+                error: rlm_core::RlmError::MaxIterationsReached(2),
+                ..
+            }
+        ));
+        let run = &err.partial().run;
+        assert_eq!(run.responses.len(), 2);
+        assert_eq!(run.tool_calls.len(), 2);
+        assert!(run.tool_calls.iter().all(|r| r.result.success));
+    }
+
+    #[test]
+    fn test_session_follow_up_and_resume() {
+        let mock = rlm_core::MockBackend::new([
+            "<tool:echo>hello</tool>",
+            "<answer>said hello</answer><done>",
+            "<answer>it said hello</answer><done>",
+        ]);
+        let config = AgentConfig {
+            backend: Backend::Mock(mock.clone()),
+            direct: true,
+            ..Default::default()
+        };
+        let agent = Agent::new(config, tools::default_tools()).unwrap();
+
+        let mut session = AgentSession::new();
+        agent.run_in_session(&mut session, "Echo hello").unwrap();
+        assert!(!mock.requests()[0][0].content.contains("EARLIER TASKS"));
+
+        let path =
+            std::env::temp_dir().join(format!("rlm_agent_session_{}.json", std::process::id()));
+        session.save(&path).unwrap();
+        let mut resumed = AgentSession::load(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let report = agent
+            .run_in_session(&mut resumed, "What did the echo say?")
+            .unwrap();
+        assert_eq!(report.run.answer, "it said hello");
+        assert!(mock.requests()[2][0].content.contains(
+            "EARLIER TASKS IN THIS SESSION:\n1. Task: Echo hello\n   \
+             Tool echo(hello): hello\n   Answer: said hello\n"
+        ));
+        let tasks: Vec<&str> = resumed.turns.iter().map(|t| t.task.as_str()).collect();
+        assert_eq!(tasks, ["Echo hello", "What did the echo say?"]);
+    }
+
+    #[test]
+    fn test_budget_stops_run_with_partial_transcript() {
+        let mock = rlm_core::MockBackend::new([
+            "<tool:echo>one</tool>",
+            "<tool:echo>two</tool>",
+            "<answer>done</answer><done>",
+        ]);
+        let config = AgentConfig {
+            backend: Backend::Mock(mock.clone()),
+            direct: true,
+            budget: Budget::new().with_max_tokens(1),
+            ..Default::default()
+        };
+        let agent = Agent::new(config, tools::default_tools()).unwrap();
+
+        // The first call uses up the budget, so the second is refused
+        let err = agent.run_report("Echo twice").unwrap_err();
+        assert_eq!(mock.requests().len(), 1);
+        let AgentError::BudgetExceeded { limit, ref partial } = err else {
+            panic!("expected a budget error, got {}", err);
+        };
+        assert_eq!(limit, BudgetLimit::Tokens(1));
+        assert_eq!(partial.run.tool_calls.len(), 1);
+        assert_eq!(partial.run.tool_calls[0].result.output, "one");
+        assert!(partial.run.usage.total_tokens > 0);
+        assert!(err.to_string().contains("token budget of 1"));
+
+        // Each run gets the whole budget again
+        assert!(agent.run_report("Echo again").is_err());
+        assert_eq!(mock.requests().len(), 2);
+    }
+
+    #[test]
+    fn test_run_with_events() {
+        let mock = rlm_core::MockBackend::new([
+            "<tool:echo>hello</tool>",
+            "<answer>echoed hello</answer><done>",
+        ]);
+        let config = AgentConfig {
+            backend: Backend::Mock(mock),
+            direct: true,
+            ..Default::default()
+        };
+        let agent = Agent::new(config, tools::default_tools()).unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        agent.run_with_events("Echo hello", sender).unwrap();
+        let events: Vec<serde_json::Value> = receiver
+            .iter()
+            .map(|event| serde_json::to_value(event).unwrap())
+            .collect();
+        let kinds: Vec<&str> = events
+            .iter()
+            .map(|e| e["event"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                "round_started",
+                "model_response",
+                "tool_call_started",
+                "tool_call_finished",
+                "round_started",
+                "model_response",
+                "answer",
+            ]
+        );
+        assert_eq!(events[2]["name"], "echo");
+        assert_eq!(events[3]["result"]["output"], "hello");
+        assert_eq!(events[6]["answer"], "echoed hello");
+    }
+
+    #[test]
+    fn test_tool_error_from_io() {
+        let e = std::io::Error::from(std::io::ErrorKind::NotFound);
+        let err = ToolError::from_io("Failed to read 'x'", &e);
+        assert_eq!(err.kind(), "not_found");
+        assert!(err
+            .to_string()
+            .starts_with("[not_found] Failed to read 'x': "));
+
+        let e = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+        assert!(ToolError::from_io("fetch", &e).is_transient());
+
+        let result = Runtime::new()
+            .unwrap()
+            .block_on(ToolRegistry::new().execute("nope", ""));
+        assert_eq!(
+            result.error,
+            Some(ToolError::NotFound("Unknown tool: nope".to_string()))
+        );
+    }
+
+    /// Fails transiently on the first `failures` calls
+    struct FlakyTool {
+        failures: usize,
+        calls: std::sync::atomic::AtomicUsize,
+    }
+
+    impl Tool for FlakyTool {
+        fn name(&self) -> &str {
+            "flaky"
+        }
+
+        fn description(&self) -> &str {
+            "Fails transiently"
+        }
+
+        fn usage(&self) -> &str {
+            "<tool:flaky></tool>"
+        }
+
+        fn execute(&self, _args: &str) -> ToolResult {
+            let n = self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            if n < self.failures {
+                ToolResult::err(ToolError::Transient("connection reset".to_string()))
+            } else {
+                ToolResult::ok("fine")
+            }
+        }
+    }
+
+    fn run_flaky(failures: usize) -> (AgentRun, String) {
+        let mock =
+            rlm_core::MockBackend::new(["<tool:flaky></tool>", "<answer>done</answer><done>"]);
+        let config = AgentConfig {
+            backend: Backend::Mock(mock.clone()),
+            direct: true,
+            ..Default::default()
+        };
+        let mut tools = ToolRegistry::new();
+        tools.register(FlakyTool {
+            failures,
+            calls: Default::default(),
+        });
+        let agent = Agent::new(config, tools).unwrap();
+        let run = agent.run_detailed("go").unwrap();
+        let second_round = mock.requests()[1][0].content.clone();
+        (run, second_round)
+    }
+
+    #[test]
+    fn test_transient_tool_error_retried_once() {
+        let (run, second_round) = run_flaky(1);
+        assert!(run.tool_calls[0].result.success);
+        assert!(second_round.contains("[flaky] Result:\nfine"));
+
+        let (run, second_round) = run_flaky(2);
+        assert!(run.tool_calls[0].result.is_transient());
+        assert!(second_round.contains("[flaky] Error: [transient] connection reset"));
+    }
+
+    /// Sleeps for the number of milliseconds in its arguments
+    struct SleepTool;
+
+    #[async_trait]
+    impl AsyncTool for SleepTool {
+        fn name(&self) -> &str {
+            "sleep"
+        }
+
+        fn description(&self) -> &str {
+            "Sleeps"
+        }
+
+        fn usage(&self) -> &str {
+            "<tool:sleep>100</tool>"
+        }
+
+        fn timeout(&self) -> Option<Duration> {
+            Some(Duration::from_millis(500))
+        }
+
+        async fn execute(&self, args: &str) -> ToolResult {
+            let ms = args.trim().parse().unwrap_or(0);
+            tokio::time::sleep(Duration::from_millis(ms)).await;
+            ToolResult::ok(format!("slept {}", ms))
+        }
+    }
+
+    #[test]
+    fn test_async_tools_run_concurrently_with_timeouts() {
+        let mock = rlm_core::MockBackend::new([
+            "<tool:sleep>300</tool><tool:sleep>300</tool><tool:sleep>5000</tool>",
+            "<answer>done</answer><done>",
+        ]);
+        let config = AgentConfig {
+            backend: Backend::Mock(mock.clone()),
+            direct: true,
+            ..Default::default()
+        };
+        let mut tools = ToolRegistry::new();
+        tools.register_async(SleepTool);
+        let agent = Agent::new(config, tools).unwrap();
+
+        let started = std::time::Instant::now();
+        let run = agent.run_detailed("go").unwrap();
+        assert!(started.elapsed() < Duration::from_millis(1500));
+
+        let results: Vec<_> = run.tool_calls.iter().map(|r| &r.result).collect();
+        assert_eq!(results[0].output, "slept 300");
+        assert_eq!(results[1].output, "slept 300");
+        assert_eq!(results[2].error.as_ref().unwrap().kind(), "timeout");
+        let second_round = &mock.requests()[1][0].content;
+        assert!(second_round.contains("[sleep] Error: [timeout] sleep did not finish within 0.5s"));
+    }
+
+    /// Blocks its thread for the number of milliseconds in its arguments
+    struct BlockingSleepTool;
+
+    impl Tool for BlockingSleepTool {
+        fn name(&self) -> &str {
+            "block"
+        }
+
+        fn description(&self) -> &str {
+            "Blocks"
+        }
+
+        fn usage(&self) -> &str {
+            "<tool:block>100</tool>"
+        }
+
+        fn execute(&self, args: &str) -> ToolResult {
+            let ms = args.trim().parse().unwrap_or(0);
+            std::thread::sleep(Duration::from_millis(ms));
+            ToolResult::ok("woke up")
+        }
+    }
+
+    #[test]
+    fn test_blocking_tool_default_timeout() {
+        let mut tools = tools::default_tools();
+        tools.register(BlockingSleepTool);
+        tools.set_default_timeout(Some(Duration::from_millis(100)));
+        let runtime = Runtime::new().unwrap();
+
+        let result = runtime.block_on(tools.execute("block", "300"));
+        assert_eq!(result.error.unwrap().kind(), "timeout");
+        let result = runtime.block_on(tools.execute("block", "10"));
+        assert_eq!(result.output, "woke up");
+    }
+
     #[test]
     fn test_extract_answer() {
         let text = "Done! <answer>The result is 42</answer><done>";
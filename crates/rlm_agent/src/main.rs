@@ -1,12 +1,30 @@
 //! RLM Agent CLI - Tool-use agent demo
 
-use clap::Parser;
-use rlm::Backend;
-use rlm_agent::{tools, Agent, AgentConfig};
+use clap::{Parser, ValueEnum};
+use rlm_agent::{
+    jobs, tools, workspace, Agent, AgentConfig, AgentEvent, AgentPrompt, AgentRun, AgentRunReport,
+    AgentSession, AsyncTool, BackendEmbedder, Budget, Embedder, HashEmbedder, JobOutcome,
+    JobRunner, MemoryStore, MemoryTool, OutputLimit, Permission, Schedule, SearchDocsTool,
+    ToolApproval, ToolCall, ToolManifest, ToolProfile, ToolRegistry, Workspace,
+};
+use rlm_core::{Backend, RlmConfig, UserConfig, UserSettings};
 use rustyline::DefaultEditor;
+use serde::Serialize;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, SystemTime};
 
-#[derive(Debug, Clone, clap::ValueEnum)]
+/// Tools that need confirmation before running, unless --yes is given
+const CONFIRMED_TOOLS: &[&str] = &["shell", "write_file", "edit_file"];
+
+const DEFAULT_MODEL: &str = "claude-sonnet-4-20250514";
+const DEFAULT_TEMPERATURE: f32 = 0.7;
+const DEFAULT_MAX_ITERATIONS: u32 = 20;
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
 enum CliBackend {
+    #[value(alias = "openai")]
     OpenAI,
     Anthropic,
 }
@@ -15,13 +33,13 @@ enum CliBackend {
 #[command(name = "rlm_agent")]
 #[command(about = "Tool-use agent powered by RLM")]
 struct Args {
-    /// Model to use
-    #[arg(short, long, default_value = "claude-sonnet-4-20250514")]
-    model: String,
+    /// Model to use [default: claude-sonnet-4-20250514]
+    #[arg(short, long)]
+    model: Option<String>,
 
-    /// Backend: openai or anthropic
-    #[arg(short, long, value_enum, default_value = "anthropic")]
-    backend: CliBackend,
+    /// Backend: openai or anthropic [default: anthropic]
+    #[arg(short, long, value_enum)]
+    backend: Option<CliBackend>,
 
     /// Backend API URL (for OpenAI-compatible)
     #[arg(short = 'u', long)]
@@ -31,17 +49,17 @@ struct Args {
     #[arg(short = 'k', long)]
     backend_key: Option<String>,
 
-    /// Temperature for sampling
-    #[arg(short, long, default_value = "0.7")]
-    temperature: f32,
+    /// Temperature for sampling [default: 0.7]
+    #[arg(short, long)]
+    temperature: Option<f32>,
 
     /// Max tool execution rounds
     #[arg(long, default_value = "10")]
     max_rounds: u32,
 
-    /// Max RLM iterations per round
-    #[arg(long, default_value = "20")]
-    max_iterations: u32,
+    /// Max RLM iterations per round [default: 20]
+    #[arg(long)]
+    max_iterations: Option<u32>,
 
     /// Verbose output
     #[arg(short, long)]
@@ -51,61 +69,345 @@ struct Args {
     #[arg(short = 'T', long)]
     task: Option<String>,
 
+    /// Seconds a tool call may run before it is cancelled
+    #[arg(long, default_value = "120")]
+    tool_timeout: u64,
+
+    /// Run the tool calls of a round one after another
+    #[arg(long)]
+    sequential_tools: bool,
+
+    /// Characters of a tool result passed on to the model (0 = unlimited)
+    #[arg(long, default_value = "20000")]
+    max_tool_output: usize,
+
+    /// Summarize tool output over --max-tool-output instead of truncating it
+    #[arg(long)]
+    summarize_tool_output: bool,
+
+    /// Ask for <tool:name> tags instead of native tool calling
+    #[arg(long)]
+    text_tools: bool,
+
+    /// Times per task malformed tool calls are sent back to the model
+    #[arg(long, default_value = "2")]
+    max_corrections: u32,
+
+    /// Profile from ~/.config/rlm/config.toml, or a tool preset: read-only,
+    /// developer, or research (default: every tool)
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+
     /// Allow all shell commands (dangerous!)
     #[arg(long)]
     allow_all_shell: bool,
+
+    /// Leave out the sandboxed python tool
+    #[arg(long)]
+    no_python_tool: bool,
+
+    /// Keep notes across tasks in this JSON file (memory tool)
+    #[arg(long, value_name = "PATH")]
+    memory: Option<PathBuf>,
+
+    /// Save the session (tasks, answers, tool calls) to this JSON file
+    #[arg(long, value_name = "PATH", conflicts_with = "resume")]
+    session: Option<PathBuf>,
+
+    /// Continue the session saved in this JSON file, saving back to it
+    #[arg(long, value_name = "SESSION")]
+    resume: Option<PathBuf>,
+
+    /// Give the agent a read-only git tool for this repository
+    #[arg(long, value_name = "PATH")]
+    git_repo: Option<String>,
+
+    /// Index this directory for the search_docs tool
+    #[arg(long, value_name = "DIR")]
+    docs: Option<String>,
+
+    /// Backend embedding model for --docs (default: local word hashing)
+    #[arg(long, value_name = "MODEL")]
+    embedding_model: Option<String>,
+
+    /// Load external JSON-RPC tools listed in this JSON manifest
+    #[arg(long, value_name = "PATH")]
+    tool_manifest: Option<PathBuf>,
+
+    /// Directory the file tools are confined to
+    #[arg(long, value_name = "DIR", default_value = ".")]
+    workspace: PathBuf,
+
+    /// Don't let the file tools write
+    #[arg(long)]
+    read_only: bool,
+
+    /// Largest file the file tools read or write, in bytes (0 = unlimited)
+    #[arg(long, value_name = "BYTES", default_value_t = workspace::DEFAULT_MAX_FILE_BYTES)]
+    max_file_bytes: u64,
+
+    /// Run shell, write_file, and edit_file without asking for confirmation
+    #[arg(short = 'y', long)]
+    yes: bool,
+
+    /// Never run this tool (repeatable)
+    #[arg(long, value_name = "TOOL")]
+    deny_tool: Vec<String>,
+
+    /// Allow a tool at most N calls per task, as TOOL=N (repeatable)
+    #[arg(long, value_name = "TOOL=N", value_parser = parse_max_calls)]
+    max_tool_calls: Vec<(String, u32)>,
+
+    /// JSON file with the prompt's preamble, rules, instructions, and examples
+    #[arg(long, value_name = "PATH")]
+    prompt_file: Option<PathBuf>,
+
+    /// Plan subtasks first and run them one at a time
+    #[arg(long)]
+    plan: bool,
+
+    /// Times the plan may be rewritten after a subtask fails
+    #[arg(long, default_value = "2")]
+    max_replans: u32,
+
+    /// Stop a task once it has used this many tokens
+    #[arg(long, value_name = "TOKENS")]
+    max_total_tokens: Option<u64>,
+
+    /// Stop a task once it has cost this many dollars (known models only)
+    #[arg(long, value_name = "USD")]
+    max_cost: Option<f64>,
+
+    /// Skip the RLM REPL loop and call the backend directly
+    #[arg(long)]
+    direct: bool,
+
+    /// Scripted mode: run one task (from --task or stdin) and print the result as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Batch mode: run the task files in this directory, or this task file (repeatable)
+    #[arg(long, value_name = "PATH")]
+    jobs: Vec<PathBuf>,
+
+    /// Directory the results and transcripts of --jobs are written to
+    #[arg(long, value_name = "DIR", default_value = "results")]
+    job_output: PathBuf,
+
+    /// Jobs run at once
+    #[arg(long, value_name = "N", default_value = "1")]
+    parallel_jobs: usize,
+
+    /// Run jobs again even when they already have results
+    #[arg(long)]
+    rerun_jobs: bool,
+
+    /// Run --jobs on this cron schedule (UTC), e.g. "0 2 * * *" or @daily
+    #[arg(long, value_name = "CRON", requires = "jobs")]
+    schedule: Option<Schedule>,
+}
+
+/// Machine-readable result printed in --json mode
+#[derive(Serialize)]
+struct JsonOutput {
+    success: bool,
+    #[serde(flatten)]
+    run: Option<AgentRunReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Settings from the user config file and the tool preset to use
+///
+/// `--profile` names a profile in the config file or, if there is none by
+/// that name, a tool preset. Exits if neither fits or the file is broken.
+fn user_settings(profile: Option<&str>) -> (UserSettings, Option<ToolProfile>) {
+    let fail = |message: String| -> ! {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    };
+    let config = UserConfig::load_default()
+        .unwrap_or_else(|e| fail(format!("Failed to load config: {}", e)));
+    let (settings, preset) = match profile {
+        Some(name) if !config.profiles.contains_key(name) => match ToolProfile::named(name) {
+            Some(preset) => (config.defaults, Some(preset)),
+            None => fail(format!(
+                "Unknown profile '{}': not in the config file nor a tool preset ({})",
+                name,
+                rlm_agent::profile::PROFILE_NAMES.join(", ")
+            )),
+        },
+        profile => match config.settings(profile) {
+            Ok(settings) => (settings, None),
+            Err(e) => fail(e.to_string()),
+        },
+    };
+    let preset = match (preset, settings.tool_profile.as_deref()) {
+        (Some(preset), _) => Some(preset),
+        (None, Some(name)) => Some(parse_profile(name).unwrap_or_else(|e| fail(e))),
+        (None, None) => None,
+    };
+    (settings, preset)
 }
 
 fn main() {
     let args = Args::parse();
 
+    // Flags win over the config file, which wins over built-in defaults
+    let (settings, tool_profile) = user_settings(args.profile.as_deref());
+    let cli_backend = match (args.backend, settings.backend.as_deref()) {
+        (Some(backend), _) => backend,
+        (None, Some(name)) => CliBackend::from_str(name, true).unwrap_or_else(|e| {
+            eprintln!("Invalid backend '{}' in config: {}", name, e);
+            std::process::exit(1);
+        }),
+        (None, None) => CliBackend::Anthropic,
+    };
+    let model = args
+        .model
+        .clone()
+        .or(settings.model.clone())
+        .unwrap_or_else(|| DEFAULT_MODEL.to_string());
+
     // Build config
-    let backend = match args.backend {
+    let backend = match cli_backend {
         CliBackend::OpenAI => Backend::OpenAI,
         CliBackend::Anthropic => Backend::Anthropic,
     };
 
+    let mut session = match args.resume {
+        Some(ref path) => match AgentSession::load(path) {
+            Ok(session) => CliSession {
+                session,
+                path: Some(path.clone()),
+            },
+            Err(e) => {
+                eprintln!("Failed to resume session '{}': {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None => CliSession {
+            session: AgentSession::new(),
+            path: args.session.clone(),
+        },
+    };
+
+    let prompt = match args.prompt_file {
+        Some(ref path) => match AgentPrompt::load(path) {
+            Ok(prompt) => prompt,
+            Err(e) => {
+                eprintln!("Failed to load prompt '{}': {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None => AgentPrompt::default(),
+    };
+
+    // A resumed session keeps its memory unless another is given
+    if args.memory.is_some() {
+        session.session.memory = args.memory.clone();
+    }
+    let memory = match session.session.memory {
+        Some(ref path) => match MemoryStore::open(path) {
+            Ok(store) => Some(Arc::new(store)),
+            Err(e) => {
+                eprintln!("Failed to open memory '{}': {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
     let mut config = AgentConfig {
-        model: args.model.clone(),
+        model: model.clone(),
         backend,
-        base_url: args.backend_url.clone(),
-        api_key: args.backend_key.clone(),
-        max_iterations: args.max_iterations,
+        base_url: args.backend_url.clone().or(settings.base_url.clone()),
+        api_key: args.backend_key.clone().or_else(|| settings.api_key()),
+        max_iterations: args
+            .max_iterations
+            .or(settings.max_iterations)
+            .unwrap_or(DEFAULT_MAX_ITERATIONS),
         max_tool_rounds: args.max_rounds,
-        temperature: args.temperature,
-        verbose: args.verbose,
+        temperature: args
+            .temperature
+            .or(settings.temperature)
+            .unwrap_or(DEFAULT_TEMPERATURE),
+        // Keep stdout clean for the JSON result and the job list
+        verbose: args.verbose && !args.json && args.jobs.is_empty(),
+        direct: args.direct,
+        exec_log: !args.json && args.jobs.is_empty(),
+        parallel_tools: !args.sequential_tools,
+        native_tools: !args.text_tools,
+        // Nobody can answer a prompt in --json or --jobs mode
+        approval_hook: (!args.yes && !args.json && args.jobs.is_empty())
+            .then(|| Arc::new(confirm_tool_call) as _),
+        memory,
+        planning: args.plan,
+        max_replans: args.max_replans,
+        budget: Budget {
+            max_tokens: args.max_total_tokens,
+            max_cost: args.max_cost,
+            pricing: None,
+        },
+        max_corrections: args.max_corrections,
+        prompt,
+        tool_profile,
     };
 
     // Default URL for OpenAI backend
-    if matches!(args.backend, CliBackend::OpenAI) && config.base_url.is_none() {
+    if matches!(cli_backend, CliBackend::OpenAI) && config.base_url.is_none() {
         config.base_url = Some("http://localhost:11434/v1".to_string());
     }
 
-    // Build tool registry
-    let mut tools = tools::default_tools();
-
-    // Replace shell tool if allow_all requested
-    if args.allow_all_shell {
-        tools.register(tools::ShellTool::allow_all());
+    if !args.jobs.is_empty() {
+        std::process::exit(run_jobs(&args, config));
     }
 
+    let tools = build_tools(&args, &mut config);
+
     // Create agent
     let agent = match Agent::new(config, tools) {
         Ok(a) => a,
         Err(e) => {
-            eprintln!("Failed to create agent: {}", e);
+            if args.json {
+                print_json(&JsonOutput {
+                    success: false,
+                    run: None,
+                    error: Some(format!("Failed to create agent: {}", e)),
+                });
+            } else {
+                eprintln!("Failed to create agent: {}", e);
+            }
             std::process::exit(1);
         }
     };
 
+    // Scripted mode - no banner, no readline
+    if args.json {
+        let task = match args.task {
+            Some(task) => task,
+            None => {
+                let mut task = String::new();
+                if let Err(e) = std::io::stdin().read_to_string(&mut task) {
+                    eprintln!("Failed to read task from stdin: {}", e);
+                    std::process::exit(1);
+                }
+                task
+            }
+        };
+        std::process::exit(run_task_json(&agent, &mut session, task.trim()));
+    }
+
     println!("RLM Agent - Tool-use demo");
-    println!("Model: {}", args.model);
-    println!("Backend: {:?}", args.backend);
+    println!("Model: {}", model);
+    println!("Backend: {:?}", cli_backend);
     println!();
 
     // Single task mode
     if let Some(task) = args.task {
-        run_task(&agent, &task);
+        if !run_task(&agent, &mut session, &task, args.verbose) {
+            std::process::exit(1);
+        }
         return;
     }
 
@@ -118,8 +420,16 @@ fn main() {
         }
     };
 
-    println!("Available tools: echo, read_file, write_file, list_dir, shell, calc");
-    println!("Type 'exit' or Ctrl+D to quit.");
+    let mut agent_tools = agent.tools().list();
+    agent_tools.sort_unstable();
+    println!("Available tools: {}", agent_tools.join(", "));
+    if !session.session.turns.is_empty() {
+        println!(
+            "Resumed session with {} earlier tasks.",
+            session.session.turns.len()
+        );
+    }
+    println!("Type 'clear' to forget earlier tasks, 'exit' or Ctrl+D to quit.");
     println!();
 
     loop {
@@ -134,9 +444,15 @@ fn main() {
                 if line == "exit" || line == "quit" {
                     break;
                 }
+                if line == "clear" {
+                    session.session.clear();
+                    session.save();
+                    println!("Earlier tasks forgotten.");
+                    continue;
+                }
 
                 let _ = rl.add_history_entry(line);
-                run_task(&agent, line);
+                run_task(&agent, &mut session, line, args.verbose);
                 println!();
             }
             Err(rustyline::error::ReadlineError::Interrupted) => {
@@ -155,20 +471,357 @@ fn main() {
     }
 }
 
-fn run_task(agent: &Agent, task: &str) {
+/// Tool registry for the agent, setting the tool profile of `config`
+fn build_tools(args: &Args, config: &mut AgentConfig) -> ToolRegistry {
+    let mut workspace = Workspace::new(&args.workspace)
+        .with_max_file_bytes(Some(args.max_file_bytes).filter(|&max| max > 0));
+    if args.read_only {
+        workspace = workspace.read_only();
+    }
+    let mut tools = tools::default_tools_in(workspace);
+    tools.set_default_timeout(Some(Duration::from_secs(args.tool_timeout)));
+    tools.set_default_output_limit(match args.max_tool_output {
+        0 => None,
+        max if args.summarize_tool_output => Some(OutputLimit::summarize(max)),
+        max => Some(OutputLimit::truncate(max)),
+    });
+
+    // Replace shell tool if allow_all requested
+    if args.allow_all_shell {
+        tools.register(tools::ShellTool::allow_all());
+    }
+
+    #[cfg(feature = "rlm")]
+    if !args.no_python_tool {
+        match python_tool(config) {
+            Ok(tool) => tools.register(tool),
+            Err(e) => eprintln!("Python tool unavailable: {}", e),
+        }
+    }
+
+    if let Some(ref memory) = config.memory {
+        tools.register(MemoryTool::new(memory.clone()));
+    }
+
+    if let Some(ref repo) = args.git_repo {
+        tools.register(tools::GitTool::new(repo));
+    }
+
+    if let Some(ref dir) = args.docs {
+        let embedder: Arc<dyn Embedder> = match args.embedding_model {
+            Some(ref model) => match rlm_core::create_backend(&backend_config(config)) {
+                Ok(backend) => Arc::new(BackendEmbedder::new(backend, model)),
+                Err(e) => {
+                    eprintln!("Failed to create embedding backend: {}", e);
+                    std::process::exit(1);
+                }
+            },
+            None => Arc::new(HashEmbedder::default()),
+        };
+        let index = std::path::Path::new(dir).join(".rlm_docs_index.json");
+        tools.register(SearchDocsTool::new(dir, embedder).with_index_path(index));
+    }
+
+    let mut profile = config.tool_profile.take();
+    if let Some(ref path) = args.tool_manifest {
+        match ToolManifest::load(path) {
+            Ok(manifest) => {
+                for tool in manifest.load_tools() {
+                    match tool {
+                        Ok(tool) => {
+                            // Tools asked for by name are kept whatever the profile
+                            if let Some(ref mut profile) = profile {
+                                profile
+                                    .tools
+                                    .insert(tool.name().to_string(), Permission::Allow);
+                            }
+                            tools.register_async(tool)
+                        }
+                        Err(e) => eprintln!("External tool unavailable: {}", e),
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("Failed to load tool manifest '{}': {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if config.approval_hook.is_some() {
+        for name in CONFIRMED_TOOLS {
+            tools.set_permission(*name, Permission::Ask);
+        }
+    }
+    for name in &args.deny_tool {
+        tools.set_permission(name.as_str(), Permission::Deny);
+    }
+    for (name, max) in &args.max_tool_calls {
+        tools.set_max_calls(name.as_str(), *max);
+    }
+    if config.approval_hook.is_none() {
+        // Nobody to ask, so what the profile would ask about just runs
+        for permission in profile.iter_mut().flat_map(|p| p.tools.values_mut()) {
+            if *permission == Permission::Ask {
+                *permission = Permission::Allow;
+            }
+        }
+    }
+    config.tool_profile = profile;
+    tools
+}
+
+/// Core config for the agent's backend, for tools that call it themselves
+fn backend_config(config: &AgentConfig) -> RlmConfig {
+    let mut rlm_config = RlmConfig::new(&config.model).with_backend(config.backend.clone());
+    if let Some(ref url) = config.base_url {
+        rlm_config = rlm_config.with_base_url(url);
+    }
+    if let Some(ref key) = config.api_key {
+        rlm_config = rlm_config.with_api_key(key);
+    }
+    rlm_config
+}
+
+/// Python tool on the agent's backend, under the restricted sandbox
+#[cfg(feature = "rlm")]
+fn python_tool(config: &AgentConfig) -> rlm_core::Result<tools::PythonTool> {
+    tools::PythonTool::new(
+        backend_config(config).with_sandbox(rlm_core::SandboxPolicy::restricted()),
+    )
+}
+
+/// Ask on the terminal whether a tool call may run
+fn confirm_tool_call(call: &ToolCall) -> ToolApproval {
+    print!("Allow {}({})? [y/N] ", call.name, call.args);
+    let _ = std::io::stdout().flush();
+
+    let mut reply = String::new();
+    if std::io::stdin().read_line(&mut reply).is_err() {
+        return ToolApproval::Deny("could not read the user's answer".to_string());
+    }
+    match reply.trim().to_lowercase().as_str() {
+        "y" | "yes" => ToolApproval::Approve,
+        _ => ToolApproval::Deny("the user declined".to_string()),
+    }
+}
+
+/// Print steps and tool calls as the run makes them
+fn print_progress(events: mpsc::Receiver<AgentEvent>) {
+    for event in events {
+        match event {
+            AgentEvent::SubtaskStarted { index, description } => {
+                println!("Step {}: {}", index + 1, description)
+            }
+            AgentEvent::ToolCallStarted { call, .. } => {
+                let args: String = call.args.chars().take(80).collect();
+                println!("  → {}({})", call.name, args.replace('\n', " "));
+            }
+            AgentEvent::Correction { .. } => println!("  ↺ malformed tool call, asked again"),
+            AgentEvent::ToolCallFinished(record) => {
+                if let Some(error) = record.result.error {
+                    println!("  ✗ {}: {}", record.call.name, error.message());
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Tasks of this run of the CLI, and where they are saved
+struct CliSession {
+    session: AgentSession,
+    path: Option<PathBuf>,
+}
+
+impl CliSession {
+    fn save(&self) {
+        if let Some(ref path) = self.path {
+            if let Err(e) = self.session.save(path) {
+                eprintln!("Failed to save session '{}': {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Run a task with human-readable output, returning whether it succeeded
+///
+/// Progress is shown from the run's events unless verbose output already
+/// covers it.
+fn run_task(agent: &Agent, session: &mut CliSession, task: &str, verbose: bool) -> bool {
     println!("─── Running task ───");
     println!();
 
-    match agent.run(task) {
-        Ok(result) => {
+    let (sender, receiver) = mpsc::channel();
+    let result = std::thread::scope(|scope| {
+        if verbose {
+            drop(receiver);
+        } else {
+            scope.spawn(move || print_progress(receiver));
+        }
+        agent.run_in_session_with_events(&mut session.session, task, sender)
+    });
+    session.save();
+
+    match result {
+        Ok(report) => {
+            if !report.subtasks.is_empty() {
+                println!();
+                println!("─── Plan ───");
+                for subtask in &report.subtasks {
+                    println!("[{}] {}", subtask.status.as_str(), subtask.description);
+                }
+            }
             println!();
             println!("─── Result ───");
-            println!("{}", result);
+            println!("{}", report.run.answer);
+            print_stats(&report.run);
+            true
         }
         Err(e) => {
             println!();
             println!("─── Error ───");
             println!("{}", e);
+            print_stats(&e.partial().run);
+            false
         }
     }
 }
+
+/// One-line summary of what a run took
+fn print_stats(run: &AgentRun) {
+    let failed = run.tool_calls.iter().filter(|r| !r.result.success).count();
+    println!();
+    println!(
+        "{} rounds, {} tool calls ({} failed), {} tokens, {:.1}s",
+        run.rounds,
+        run.tool_calls.len(),
+        failed,
+        run.usage.total_tokens,
+        run.duration_ms as f64 / 1000.0
+    );
+    for (name, stats) in &run.tool_stats {
+        let cost = stats
+            .cost
+            .map(|cost| format!(", ${:.4}", cost))
+            .unwrap_or_default();
+        println!(
+            "  {}: {} calls ({} failed), {:.1}s{}",
+            name,
+            stats.calls,
+            stats.failures,
+            stats.duration_ms as f64 / 1000.0,
+            cost
+        );
+    }
+}
+
+/// Parse a --profile value
+fn parse_profile(name: &str) -> Result<ToolProfile, String> {
+    ToolProfile::named(name).ok_or_else(|| {
+        format!(
+            "unknown profile '{}' (expected one of: {})",
+            name,
+            rlm_agent::profile::PROFILE_NAMES.join(", ")
+        )
+    })
+}
+
+/// Parse a --max-tool-calls value
+fn parse_max_calls(value: &str) -> Result<(String, u32), String> {
+    let (name, max) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected TOOL=N, got '{}'", value))?;
+    let max = max
+        .parse()
+        .map_err(|e| format!("bad call count '{}': {}", max, e))?;
+    Ok((name.to_string(), max))
+}
+
+/// Run a task and print the structured result as JSON, returning the exit code
+fn run_task_json(agent: &Agent, session: &mut CliSession, task: &str) -> i32 {
+    let result = agent.run_in_session(&mut session.session, task);
+    session.save();
+    let (output, code) = match result {
+        Ok(run) => (
+            JsonOutput {
+                success: true,
+                run: Some(run),
+                error: None,
+            },
+            0,
+        ),
+        Err(e) => (
+            JsonOutput {
+                success: false,
+                // A failed run still shows what it did
+                run: Some(e.partial().clone()),
+                error: Some(e.to_string()),
+            },
+            1,
+        ),
+    };
+    print_json(&output);
+    code
+}
+
+/// Run the --jobs task files, once or on the --schedule, returning the exit code
+fn run_jobs(args: &Args, config: AgentConfig) -> i32 {
+    let mut runner = JobRunner::new(&args.job_output)
+        .with_parallelism(args.parallel_jobs)
+        .with_rerun(args.rerun_jobs);
+    for source in &args.jobs {
+        runner = runner.with_source(source);
+    }
+    let make_agent = || {
+        let mut config = config.clone();
+        let tools = build_tools(args, &mut config);
+        Agent::new(config, tools)
+    };
+    let print_outcome = |outcome: &JobOutcome| match outcome.error {
+        Some(ref error) => println!("✗ {}: {}", outcome.name, error),
+        None => println!(
+            "✓ {} ({:.1}s)",
+            outcome.name,
+            outcome.duration_ms as f64 / 1000.0
+        ),
+    };
+    let run = || match runner.run(make_agent, print_outcome) {
+        Ok(outcomes) => {
+            let failed = outcomes.iter().filter(|o| !o.success).count();
+            println!(
+                "{} jobs, {} failed; results in {}",
+                outcomes.len(),
+                failed,
+                args.job_output.display()
+            );
+            failed == 0
+        }
+        Err(e) => {
+            eprintln!("Failed to run jobs: {}", e);
+            false
+        }
+    };
+
+    let Some(ref schedule) = args.schedule else {
+        return if run() { 0 } else { 1 };
+    };
+    loop {
+        let now = SystemTime::now();
+        let Some(next) = schedule.next_after(now) else {
+            eprintln!("The schedule never fires");
+            return 1;
+        };
+        println!("Next run at {}", jobs::format_utc(next));
+        std::thread::sleep(next.duration_since(now).unwrap_or_default());
+        // A failed run doesn't stop the next one
+        run();
+    }
+}
+
+fn print_json(output: &JsonOutput) {
+    match serde_json::to_string_pretty(output) {
+        Ok(json) => println!("{}", json),
+        Err(e) => eprintln!("Failed to serialize result: {}", e),
+    }
+}
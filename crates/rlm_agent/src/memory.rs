@@ -0,0 +1,304 @@
+//! Notes the agent keeps across tasks
+//!
+//! A [`MemoryStore`] holds named notes, optionally persisted to a JSON
+//! file. The [`MemoryTool`] lets the model store, retrieve, update, and
+//! delete them; an agent given the store (`AgentConfig::memory`) also puts
+//! the notes most relevant to each task into its context.
+
+use crate::{Tool, ToolError, ToolResult};
+use rlm_core::write_atomic;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A named note
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Note {
+    pub name: String,
+    pub content: String,
+    /// Unix seconds of the last change
+    pub updated: u64,
+}
+
+/// Named notes, saved to a file after every change when it has one
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    notes: Mutex<BTreeMap<String, Note>>,
+    path: Option<PathBuf>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Lowercase words of three or more letters or digits
+fn words(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() >= 3)
+        .map(str::to_lowercase)
+        .collect()
+}
+
+impl MemoryStore {
+    /// Notes kept for this process only
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// Notes saved in the JSON file at `path`, loaded if it exists
+    pub fn open(path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let path = path.into();
+        let notes = match std::fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            notes: Mutex::new(notes),
+            path: Some(path),
+        })
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Note> {
+        self.notes.lock().unwrap().get(name).cloned()
+    }
+
+    /// All notes, by name
+    pub fn list(&self) -> Vec<Note> {
+        self.notes.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Create or replace a note
+    pub fn store(&self, name: &str, content: &str) -> std::io::Result<()> {
+        self.change(|notes| {
+            notes.insert(
+                name.to_string(),
+                Note {
+                    name: name.to_string(),
+                    content: content.to_string(),
+                    updated: now(),
+                },
+            );
+            true
+        })
+        .map(|_| ())
+    }
+
+    /// Append to an existing note; false if there is none
+    pub fn append(&self, name: &str, content: &str) -> std::io::Result<bool> {
+        self.change(|notes| match notes.get_mut(name) {
+            Some(note) => {
+                if !note.content.is_empty() {
+                    note.content.push('\n');
+                }
+                note.content.push_str(content);
+                note.updated = now();
+                true
+            }
+            None => false,
+        })
+    }
+
+    /// Delete a note; false if there is none
+    pub fn delete(&self, name: &str) -> std::io::Result<bool> {
+        self.change(|notes| notes.remove(name).is_some())
+    }
+
+    /// Up to `limit` notes sharing words with `text`, most overlap first,
+    /// then most recently updated
+    pub fn relevant(&self, text: &str, limit: usize) -> Vec<Note> {
+        let wanted = words(text);
+        let notes = self.notes.lock().unwrap();
+        let mut scored: Vec<(usize, &Note)> = notes
+            .values()
+            .map(|note| {
+                let mut have = words(&note.name);
+                have.extend(words(&note.content));
+                (have.intersection(&wanted).count(), note)
+            })
+            .filter(|(score, _)| *score > 0)
+            .collect();
+        scored.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.updated.cmp(&a.1.updated)));
+        scored
+            .into_iter()
+            .take(limit)
+            .map(|(_, note)| note.clone())
+            .collect()
+    }
+
+    /// Apply `edit` and save if it reports a change
+    ///
+    /// The edit goes to a copy that only replaces the notes once saved, so a
+    /// failed write leaves memory matching the file.
+    fn change(
+        &self,
+        edit: impl FnOnce(&mut BTreeMap<String, Note>) -> bool,
+    ) -> std::io::Result<bool> {
+        let mut notes = self.notes.lock().unwrap();
+        let mut edited = notes.clone();
+        if !edit(&mut edited) {
+            return Ok(false);
+        }
+        if let Some(ref path) = self.path {
+            write_atomic(path, &serde_json::to_string_pretty(&edited)?)?;
+        }
+        *notes = edited;
+        Ok(true)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum MemoryArgs {
+    Store { name: String, content: String },
+    Update { name: String, content: String },
+    Retrieve { name: String },
+    Delete { name: String },
+    List,
+}
+
+/// Memory tool: store and look up named notes that outlive the task
+pub struct MemoryTool {
+    store: Arc<MemoryStore>,
+}
+
+impl MemoryTool {
+    pub fn new(store: Arc<MemoryStore>) -> Self {
+        Self { store }
+    }
+}
+
+impl Tool for MemoryTool {
+    fn name(&self) -> &str {
+        "memory"
+    }
+
+    fn description(&self) -> &str {
+        "Keep notes for later tasks: store, update (append), retrieve, delete, or list them"
+    }
+
+    fn usage(&self) -> &str {
+        r#"<tool:memory>{"action": "store", "name": "build", "content": "Run make check before committing"}</tool>"#
+    }
+
+    fn parameters_schema(&self) -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "action": { "enum": ["store", "update", "retrieve", "delete", "list"] },
+                "name": { "type": "string" },
+                "content": { "type": "string" }
+            },
+            "required": ["action"],
+            "additionalProperties": false
+        })
+    }
+
+    fn execute(&self, args: &str) -> ToolResult {
+        let args: MemoryArgs = match serde_json::from_str(args) {
+            Ok(args) => args,
+            Err(e) => return ToolResult::err(ToolError::InvalidArgs(e.to_string())),
+        };
+        let saved = |result: std::io::Result<bool>, name: &str, done: &str| match result {
+            Ok(true) => ToolResult::ok(format!("{} note '{}'", done, name)),
+            Ok(false) => ToolResult::err(ToolError::NotFound(format!("No note '{}'", name))),
+            Err(e) => ToolResult::err(ToolError::from_io("Failed to save notes", &e)),
+        };
+
+        match args {
+            MemoryArgs::Store { name, content } => saved(
+                self.store.store(&name, &content).map(|_| true),
+                &name,
+                "Stored",
+            ),
+            MemoryArgs::Update { name, content } => {
+                saved(self.store.append(&name, &content), &name, "Updated")
+            }
+            MemoryArgs::Delete { name } => saved(self.store.delete(&name), &name, "Deleted"),
+            MemoryArgs::Retrieve { name } => match self.store.get(&name) {
+                Some(note) => ToolResult::ok(note.content),
+                None => ToolResult::err(ToolError::NotFound(format!("No note '{}'", name))),
+            },
+            MemoryArgs::List => {
+                let names: Vec<String> = self.store.list().into_iter().map(|n| n.name).collect();
+                ToolResult::ok(if names.is_empty() {
+                    "No notes".to_string()
+                } else {
+                    names.join("\n")
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_tool_persists() {
+        let path =
+            std::env::temp_dir().join(format!("rlm_agent_memory_{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let tool = MemoryTool::new(Arc::new(MemoryStore::open(&path).unwrap()));
+
+        let run = |args: serde_json::Value| tool.execute(&args.to_string());
+        assert!(run(json!({ "action": "store", "name": "build", "content": "use make" })).success);
+        assert!(
+            run(json!({ "action": "update", "name": "build", "content": "then test" })).success
+        );
+        let missing = run(json!({ "action": "update", "name": "deploy", "content": "x" }));
+        assert_eq!(missing.error.unwrap().kind(), "not_found");
+        assert_eq!(run(json!({ "action": "list" })).output, "build");
+
+        // A new store on the same file sees the notes
+        let store = MemoryStore::open(&path).unwrap();
+        assert_eq!(store.get("build").unwrap().content, "use make\nthen test");
+        assert!(store.delete("build").unwrap());
+        assert!(MemoryStore::open(&path).unwrap().list().is_empty());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_failed_save_keeps_notes() {
+        let path = std::env::temp_dir()
+            .join(format!("rlm_agent_memory_missing_{}", std::process::id()))
+            .join("notes.json");
+        let store = MemoryStore::open(&path).unwrap();
+        assert!(store.store("build", "use make").is_err());
+        assert!(store.get("build").is_none());
+        assert!(store.list().is_empty());
+    }
+
+    #[test]
+    fn test_relevant_notes() {
+        let store = MemoryStore::in_memory();
+        store
+            .store("build", "Run make check before committing")
+            .unwrap();
+        store
+            .store("deploy", "Deploys go through the staging cluster")
+            .unwrap();
+        store
+            .store("style", "Commit messages are imperative")
+            .unwrap();
+
+        let names = |notes: Vec<Note>| notes.into_iter().map(|n| n.name).collect::<Vec<_>>();
+        assert_eq!(
+            names(store.relevant("Check the build and make a commit", 1)),
+            ["build"]
+        );
+        assert_eq!(names(store.relevant("deploy to staging", 1)), ["deploy"]);
+        assert!(store.relevant("unrelated", 5).is_empty());
+    }
+}
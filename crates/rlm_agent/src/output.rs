@@ -0,0 +1,110 @@
+//! Limits on how much tool output reaches the conversation
+//!
+//! A `read_file` of a large log would otherwise fill the history and push
+//! the task out of the context window. Output over an [`OutputLimit`] is
+//! cut down before the agent appends it, either by dropping the overflow or
+//! by having the backend summarize it.
+
+pub use rlm_core::OutputTruncation;
+
+/// What happens to the part of an output over the limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Drop it, keeping the head (and tail) the mode names
+    Truncate(OutputTruncation),
+    /// Keep the head and replace the rest with a summary by the backend,
+    /// which sees at most `max_input_chars` of it
+    Summarize { max_input_chars: usize },
+}
+
+/// Cap on the characters of one tool result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputLimit {
+    pub max_chars: usize,
+    pub overflow: Overflow,
+}
+
+impl OutputLimit {
+    /// Keep the start and end of long output
+    pub fn truncate(max_chars: usize) -> Self {
+        Self {
+            max_chars,
+            overflow: Overflow::Truncate(OutputTruncation::HeadAndTail),
+        }
+    }
+
+    /// Summarize what doesn't fit, reading up to 100k characters of it
+    pub fn summarize(max_chars: usize) -> Self {
+        Self {
+            max_chars,
+            overflow: Overflow::Summarize {
+                max_input_chars: 100_000,
+            },
+        }
+    }
+}
+
+/// Limit for tools without their own
+pub const DEFAULT_OUTPUT_LIMIT: OutputLimit = OutputLimit {
+    max_chars: 20_000,
+    overflow: Overflow::Truncate(OutputTruncation::HeadAndTail),
+};
+
+/// Cap `text` at `max` characters, marking how much was dropped
+pub fn truncate(text: &str, max: usize, mode: OutputTruncation) -> String {
+    let total = text.chars().count();
+    if total <= max {
+        return text.to_string();
+    }
+    let dropped = total - max;
+    match mode {
+        OutputTruncation::Head => {
+            let head: String = text.chars().take(max).collect();
+            format!("{}\n[truncated {} chars]", head, dropped)
+        }
+        OutputTruncation::HeadAndTail => {
+            let head_len = max / 2;
+            let head: String = text.chars().take(head_len).collect();
+            let tail: String = text.chars().skip(total - (max - head_len)).collect();
+            format!("{}\n[truncated {} chars]\n{}", head, dropped, tail)
+        }
+    }
+}
+
+/// Split `text` after `max` characters
+pub(crate) fn split_overflow(text: &str, max: usize) -> Option<(&str, &str)> {
+    let (at, _) = text.char_indices().nth(max)?;
+    Some(text.split_at(at))
+}
+
+/// Prompt asking for a summary of the overflow of a tool's output
+pub(crate) fn summary_prompt(task: &str, tool: &str, overflow: &str) -> String {
+    format!(
+        "An agent working on the task below called the tool `{}`. Its output was too long \
+         to show in full; this is the part that didn't fit. Summarize it in a few \
+         paragraphs, keeping the details that matter for the task (names, numbers, \
+         errors, paths).\n\nTASK: {}\n\nOUTPUT:\n{}",
+        tool, task, overflow
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate() {
+        let text = "abcdefghij";
+        assert_eq!(truncate(text, 20, OutputTruncation::Head), text);
+        assert_eq!(
+            truncate(text, 4, OutputTruncation::Head),
+            "abcd\n[truncated 6 chars]"
+        );
+        assert_eq!(
+            truncate(text, 4, OutputTruncation::HeadAndTail),
+            "ab\n[truncated 6 chars]\nij"
+        );
+        assert_eq!(split_overflow("äöüß", 1), Some(("ä", "öüß")));
+        assert_eq!(split_overflow("äöüß", 4), None);
+    }
+}
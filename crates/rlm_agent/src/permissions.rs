@@ -0,0 +1,153 @@
+//! Per-tool permission policies and interactive approval
+//!
+//! Each tool is allowed, denied, or needs approval ([`Permission::Ask`]).
+//! Asked calls go to the agent's [`ToolApprovalHook`]; without one they are
+//! denied. A denied call never runs and reaches the model as a
+//! [`ToolError::PermissionDenied`] result, so it can try something else.
+
+use crate::{ToolCall, ToolError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+/// What happens when the model calls a tool
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    /// Run it
+    #[default]
+    Allow,
+    /// Refuse it
+    Deny,
+    /// Run it once the approval hook agrees
+    Ask,
+}
+
+/// Decision on one asked tool call
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ToolApproval {
+    /// Run the call
+    Approve,
+    /// Don't run it; the message goes back to the model as the error
+    Deny(String),
+}
+
+/// Reviews tool calls whose tool is set to [`Permission::Ask`]
+pub trait ToolApprovalHook: Send + Sync {
+    fn review(&self, call: &ToolCall) -> ToolApproval;
+}
+
+impl fmt::Debug for dyn ToolApprovalHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ToolApprovalHook")
+    }
+}
+
+impl<F> ToolApprovalHook for F
+where
+    F: Fn(&ToolCall) -> ToolApproval + Send + Sync,
+{
+    fn review(&self, call: &ToolCall) -> ToolApproval {
+        self(call)
+    }
+}
+
+/// Permissions of the tools in a registry
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PermissionPolicy {
+    /// For tools without their own entry
+    pub default: Permission,
+    pub tools: HashMap<String, Permission>,
+}
+
+impl PermissionPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_default(mut self, permission: Permission) -> Self {
+        self.default = permission;
+        self
+    }
+
+    pub fn with_tool(mut self, name: impl Into<String>, permission: Permission) -> Self {
+        self.tools.insert(name.into(), permission);
+        self
+    }
+
+    /// Permission of tool `name`
+    pub fn get(&self, name: &str) -> Permission {
+        self.tools.get(name).copied().unwrap_or(self.default)
+    }
+
+    /// Whether `call` may run, asking `hook` when its tool needs approval
+    pub fn check(
+        &self,
+        call: &ToolCall,
+        hook: Option<&dyn ToolApprovalHook>,
+    ) -> Result<(), ToolError> {
+        let denied = |reason: &str| {
+            Err(ToolError::PermissionDenied(format!(
+                "{} was not allowed to run: {}",
+                call.name, reason
+            )))
+        };
+        match self.get(&call.name) {
+            Permission::Allow => Ok(()),
+            Permission::Deny => denied("denied by policy"),
+            Permission::Ask => match hook.map(|hook| hook.review(call)) {
+                Some(ToolApproval::Approve) => Ok(()),
+                Some(ToolApproval::Deny(reason)) => denied(&reason),
+                None => denied("it needs approval and no approval hook is set"),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, args: &str) -> ToolCall {
+        ToolCall {
+            name: name.to_string(),
+            args: args.to_string(),
+            arguments: None,
+        }
+    }
+
+    #[test]
+    fn test_policy_check() {
+        let policy = PermissionPolicy::new()
+            .with_tool("shell", Permission::Ask)
+            .with_tool("write_file", Permission::Deny);
+        let hook = |call: &ToolCall| {
+            if call.args.starts_with("rm") {
+                ToolApproval::Deny("user declined".to_string())
+            } else {
+                ToolApproval::Approve
+            }
+        };
+
+        assert!(policy.check(&call("echo", "hi"), None).is_ok());
+        assert!(policy.check(&call("shell", "ls"), Some(&hook)).is_ok());
+        let err = policy
+            .check(&call("shell", "rm -rf x"), Some(&hook))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "[permission_denied] shell was not allowed to run: user declined"
+        );
+        let err = policy.check(&call("shell", "ls"), None).unwrap_err();
+        assert!(err
+            .message()
+            .ends_with("it needs approval and no approval hook is set"));
+        let err = policy
+            .check(&call("write_file", "{}"), Some(&hook))
+            .unwrap_err();
+        assert!(err.message().ends_with("denied by policy"));
+
+        let closed = PermissionPolicy::new().with_default(Permission::Deny);
+        assert!(closed.check(&call("echo", "hi"), None).is_err());
+    }
+}
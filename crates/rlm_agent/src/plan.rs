@@ -0,0 +1,177 @@
+//! Planning: splitting a task into subtasks run one at a time
+//!
+//! With `AgentConfig::planning` set, the agent first asks the model for a
+//! plan, then runs each [`Subtask`] as a task of its own, handing it the
+//! results of the earlier ones. A subtask that fails gets the rest of the
+//! plan rewritten around it, up to `AgentConfig::max_replans` times. The
+//! plan and each subtask's status come back in the [`AgentRunReport`].
+
+use crate::AgentRun;
+use rlm_core::parsing::find_tags;
+use serde::{Deserialize, Serialize};
+
+/// Most subtasks taken from one plan
+pub const MAX_SUBTASKS: usize = 10;
+
+/// Where a subtask stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubtaskStatus {
+    Pending,
+    Done,
+    Failed,
+}
+
+impl SubtaskStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubtaskStatus::Pending => "pending",
+            SubtaskStatus::Done => "done",
+            SubtaskStatus::Failed => "failed",
+        }
+    }
+}
+
+/// One step of a plan
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Subtask {
+    pub description: String,
+    pub status: SubtaskStatus,
+    /// Answer when done, reason when failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<String>,
+    /// Tool rounds spent on it
+    pub rounds: u32,
+}
+
+impl Subtask {
+    pub fn new(description: impl Into<String>) -> Self {
+        Self {
+            description: description.into(),
+            status: SubtaskStatus::Pending,
+            result: None,
+            rounds: 0,
+        }
+    }
+}
+
+/// Structured result of an agent run, with its plan when it had one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentRunReport {
+    #[serde(flatten)]
+    pub run: AgentRun,
+    /// Subtasks in the order they were run or planned (empty without
+    /// planning); failed ones stay in the list ahead of their replacements
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subtasks: Vec<Subtask>,
+    /// Times the rest of the plan was rewritten after a failure
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub replans: u32,
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+/// Subtasks in `<subtask>` tags of a plan response
+pub(crate) fn parse_plan(text: &str) -> Vec<Subtask> {
+    find_tags(text, "subtask")
+        .into_iter()
+        .map(|tag| tag.content.trim().to_string())
+        .filter(|description| !description.is_empty())
+        .take(MAX_SUBTASKS)
+        .map(Subtask::new)
+        .collect()
+}
+
+const PLAN_FORMAT: &str = "Reply with the subtasks only, one per tag:\n\
+                           <subtask>first subtask</subtask>\n\
+                           <subtask>second subtask</subtask>";
+
+/// Prompt asking for a plan for `task`, after the `earlier` tasks of its
+/// session
+pub(crate) fn plan_prompt(task: &str, tool_docs: &str, earlier: &str) -> String {
+    format!(
+        "Break the task below into at most {} subtasks that an agent with these tools \
+         can do one after another. Make each one concrete, with a result that can be \
+         checked. A simple task needs only one.\n\nAVAILABLE TOOLS:\n{}\n{}\nTASK: {}\n\n{}",
+        MAX_SUBTASKS, tool_docs, earlier, task, PLAN_FORMAT
+    )
+}
+
+/// Prompt asking for new subtasks for the rest of `task` after `failed`
+pub(crate) fn replan_prompt(task: &str, subtasks: &[Subtask], failed: &Subtask) -> String {
+    format!(
+        "An agent working through a plan for the task below could not finish a \
+         subtask. Write new subtasks for the rest of the task, building on what was \
+         done and avoiding what failed.\n\nTASK: {}\n\n{}FAILED: {}\nREASON: {}\n\n{}",
+        task,
+        done_section(subtasks),
+        failed.description,
+        failed.result.as_deref().unwrap_or("unknown"),
+        PLAN_FORMAT
+    )
+}
+
+/// Task given to the agent for subtask `index` of `subtasks`
+pub(crate) fn subtask_task(task: &str, subtasks: &[Subtask], index: usize) -> String {
+    format!(
+        "{}\n\nThis is step {} of {} of a plan for the overall task: {}\n{}\
+         Do only this step; its answer is passed on to the next steps. If it can't \
+         be done, output <failed>why</failed><done> instead of an answer.",
+        subtasks[index].description,
+        index + 1,
+        subtasks.len(),
+        task,
+        done_section(subtasks)
+    )
+}
+
+/// Prompt asking for the final answer from the subtasks' results
+pub(crate) fn answer_prompt(task: &str, subtasks: &[Subtask]) -> String {
+    format!(
+        "An agent did the task below in steps. Write the final answer to the task \
+         from the results of the steps; reply with the answer only.\n\nTASK: {}\n\n{}",
+        task,
+        done_section(subtasks)
+    )
+}
+
+/// Finished subtasks and their results, as a prompt section
+fn done_section(subtasks: &[Subtask]) -> String {
+    let done: Vec<String> = subtasks
+        .iter()
+        .filter(|s| s.status == SubtaskStatus::Done)
+        .enumerate()
+        .map(|(i, s)| {
+            format!(
+                "{}. {}\n   Result: {}",
+                i + 1,
+                s.description,
+                s.result.as_deref().unwrap_or("")
+            )
+        })
+        .collect();
+    if done.is_empty() {
+        String::new()
+    } else {
+        format!("DONE SO FAR:\n{}\n\n", done.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_plan() {
+        let plan = parse_plan(
+            "Plan:\n<subtask>Find the config</subtask>\n<subtask> </subtask>\n\
+             <subtask>Change the port</subtask>",
+        );
+        let descriptions: Vec<&str> = plan.iter().map(|s| s.description.as_str()).collect();
+        assert_eq!(descriptions, ["Find the config", "Change the port"]);
+        assert!(plan.iter().all(|s| s.status == SubtaskStatus::Pending));
+        assert!(parse_plan("1. Find the config").is_empty());
+    }
+}
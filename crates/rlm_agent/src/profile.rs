@@ -0,0 +1,177 @@
+//! Named presets of the tools an agent gets
+//!
+//! A [`ToolProfile`] lists the tools to keep, each with its [`Permission`].
+//! Applied to a registry, it removes every other tool and sets the listed
+//! permissions, so one switch picks between an agent that can only look
+//! around (`read-only`), one that can change code (`developer`), and one
+//! that reads the web (`research`). A profile never loosens a permission
+//! the registry already has: a denied tool stays denied.
+
+use crate::tools::ShellTool;
+use crate::{Permission, ToolRegistry};
+use std::collections::HashMap;
+
+/// Names of the built-in profiles
+pub const PROFILE_NAMES: &[&str] = &["read-only", "developer", "research"];
+
+/// Tools an agent keeps and what it may do with them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolProfile {
+    pub name: String,
+    /// Tools kept, with their permissions; the others are removed
+    pub tools: HashMap<String, Permission>,
+    /// Let the shell tool run any command instead of read-only ones
+    pub unrestricted_shell: bool,
+}
+
+impl ToolProfile {
+    /// Profile without tools
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            tools: HashMap::new(),
+            unrestricted_shell: false,
+        }
+    }
+
+    /// Reads files, docs, and git history; changes nothing
+    pub fn read_only() -> Self {
+        Self::new("read-only").with_tools(
+            &[
+                "echo",
+                "calc",
+                "read_file",
+                "list_dir",
+                "shell",
+                "git",
+                "search_docs",
+            ],
+            Permission::Allow,
+        )
+    }
+
+    /// Edits files and runs any command, asking before it changes anything
+    pub fn developer() -> Self {
+        let mut profile = Self::new("developer")
+            .with_tools(
+                &[
+                    "echo",
+                    "calc",
+                    "read_file",
+                    "list_dir",
+                    "git",
+                    "search_docs",
+                    "memory",
+                    "python",
+                ],
+                Permission::Allow,
+            )
+            .with_tools(&["write_file", "edit_file", "shell"], Permission::Ask);
+        profile.unrestricted_shell = true;
+        profile
+    }
+
+    /// Fetches pages and works through what it finds; writes only notes
+    pub fn research() -> Self {
+        Self::new("research").with_tools(
+            &[
+                "echo",
+                "calc",
+                "read_file",
+                "list_dir",
+                "fetch_page",
+                "search_docs",
+                "memory",
+                "python",
+            ],
+            Permission::Allow,
+        )
+    }
+
+    /// Built-in profile called `name`, one of [`PROFILE_NAMES`]
+    pub fn named(name: &str) -> Option<Self> {
+        match name {
+            "read-only" => Some(Self::read_only()),
+            "developer" => Some(Self::developer()),
+            "research" => Some(Self::research()),
+            _ => None,
+        }
+    }
+
+    /// Keep tool `name` with `permission`
+    pub fn with_tool(mut self, name: impl Into<String>, permission: Permission) -> Self {
+        self.tools.insert(name.into(), permission);
+        self
+    }
+
+    fn with_tools(mut self, names: &[&str], permission: Permission) -> Self {
+        for name in names {
+            self.tools.insert(name.to_string(), permission);
+        }
+        self
+    }
+
+    /// Remove the tools the profile doesn't keep from `registry` and set
+    /// the permissions of the rest
+    pub fn apply(&self, registry: &mut ToolRegistry) {
+        let names: Vec<String> = registry.list().into_iter().map(String::from).collect();
+        for name in names {
+            let Some(&permission) = self.tools.get(&name) else {
+                registry.remove(&name);
+                continue;
+            };
+            let current = registry.permissions().get(&name);
+            registry.set_permission(name, strictest(current, permission));
+        }
+        if self.unrestricted_shell && registry.get("shell").is_some() {
+            registry.register(ShellTool::allow_all());
+        }
+    }
+}
+
+/// The more restrictive of two permissions
+fn strictest(a: Permission, b: Permission) -> Permission {
+    match (a, b) {
+        (Permission::Deny, _) | (_, Permission::Deny) => Permission::Deny,
+        (Permission::Ask, _) | (_, Permission::Ask) => Permission::Ask,
+        _ => Permission::Allow,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{tools, Workspace};
+
+    #[test]
+    fn test_profiles() {
+        let registry = || tools::default_tools_in(Workspace::new(std::env::temp_dir()));
+
+        let mut read_only = registry();
+        read_only.set_permission("shell", Permission::Ask);
+        ToolProfile::named("read-only")
+            .unwrap()
+            .apply(&mut read_only);
+        let mut names = read_only.list();
+        names.sort_unstable();
+        assert_eq!(names, ["calc", "echo", "list_dir", "read_file", "shell"]);
+        // Kept as strict as it was
+        assert_eq!(read_only.permissions().get("shell"), Permission::Ask);
+        assert_eq!(read_only.permissions().get("read_file"), Permission::Allow);
+
+        let mut developer = registry();
+        developer.set_permission("edit_file", Permission::Deny);
+        ToolProfile::developer()
+            .with_tool("lint", Permission::Allow)
+            .apply(&mut developer);
+        assert!(developer.get("fetch_page").is_none());
+        assert_eq!(developer.permissions().get("write_file"), Permission::Ask);
+        assert_eq!(developer.permissions().get("edit_file"), Permission::Deny);
+
+        let mut research = registry();
+        ToolProfile::research().apply(&mut research);
+        assert!(research.get("fetch_page").is_some());
+        assert!(research.get("shell").is_none());
+        assert!(ToolProfile::named("admin").is_none());
+    }
+}
@@ -0,0 +1,128 @@
+//! Configurable sections of the agent's prompt
+//!
+//! An [`AgentPrompt`] sets the preamble, the rules, extra instructions, and
+//! worked examples the agent's context is built from. The tool list and
+//! the tool call and answer formats stay fixed, as the agent parses them
+//! out of the model's responses. Prompts load from JSON files, so a
+//! deployment can adapt the agent to its domain without code.
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Opening line of the default prompt
+pub const DEFAULT_PREAMBLE: &str = "You are an AI agent that completes tasks using tools.";
+
+/// Sections of the agent's prompt
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AgentPrompt {
+    /// Who the agent is and what it is for
+    pub preamble: String,
+    /// Numbered rules, between the one on calling tools and the one on
+    /// finishing, which are always there
+    pub rules: Vec<String>,
+    /// Free-form guidance, shown under INSTRUCTIONS
+    pub instructions: Option<String>,
+    /// Worked examples, shown under EXAMPLES
+    pub examples: Vec<PromptExample>,
+}
+
+/// A task and how the agent should go about it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PromptExample {
+    pub task: String,
+    /// The agent's side of the example: tool calls, results, and answer
+    pub response: String,
+}
+
+impl Default for AgentPrompt {
+    fn default() -> Self {
+        Self {
+            preamble: DEFAULT_PREAMBLE.to_string(),
+            rules: vec![
+                "Wait for tool results before continuing".to_string(),
+                "You can call multiple tools".to_string(),
+            ],
+            instructions: None,
+            examples: Vec::new(),
+        }
+    }
+}
+
+impl AgentPrompt {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Prompt saved at `path`; sections it leaves out keep their defaults
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    pub fn with_preamble(mut self, preamble: impl Into<String>) -> Self {
+        self.preamble = preamble.into();
+        self
+    }
+
+    /// Replace the rules
+    pub fn with_rules<I, S>(mut self, rules: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.rules = rules.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_rule(mut self, rule: impl Into<String>) -> Self {
+        self.rules.push(rule.into());
+        self
+    }
+
+    pub fn with_instructions(mut self, instructions: impl Into<String>) -> Self {
+        self.instructions = Some(instructions.into());
+        self
+    }
+
+    pub fn with_example(mut self, task: impl Into<String>, response: impl Into<String>) -> Self {
+        self.examples.push(PromptExample {
+            task: task.into(),
+            response: response.into(),
+        });
+        self
+    }
+
+    /// The RULES list, with `call_rule` first and the finishing rule last
+    pub(crate) fn rules_section(&self, call_rule: &str) -> String {
+        let rules = std::iter::once(call_rule)
+            .chain(self.rules.iter().map(String::as_str))
+            .chain(std::iter::once(
+                "End with <answer>...</answer><done> when task is complete",
+            ));
+        rules
+            .enumerate()
+            .map(|(i, rule)| format!("{}. {}\n", i + 1, rule))
+            .collect()
+    }
+
+    /// Instructions and examples, as sections after the rules
+    pub(crate) fn extra_sections(&self) -> String {
+        let mut sections = String::new();
+        if let Some(ref instructions) = self.instructions {
+            sections.push_str(&format!("\nINSTRUCTIONS:\n{}\n", instructions.trim()));
+        }
+        if !self.examples.is_empty() {
+            sections.push_str("\nEXAMPLES:\n");
+            for (i, example) in self.examples.iter().enumerate() {
+                sections.push_str(&format!(
+                    "Example {}\nTask: {}\nAssistant: {}\n",
+                    i + 1,
+                    example.task,
+                    example.response.trim()
+                ));
+            }
+        }
+        sections
+    }
+}
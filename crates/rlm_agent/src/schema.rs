@@ -0,0 +1,168 @@
+//! JSON-schema checks for tool arguments
+//!
+//! Arguments are validated with [`rlm_core::schema_errors`], the validator
+//! `response_format` answers go through, so every JSON Schema keyword
+//! counts.
+
+use crate::ToolError;
+use serde_json::{json, Value};
+
+/// Schema of a tool taking its arguments as plain text
+pub fn text_schema() -> Value {
+    json!({ "type": "string" })
+}
+
+/// Most problems reported for one call
+const MAX_ERRORS: usize = 10;
+
+/// Problems with `value` under `schema`, empty when it validates
+pub fn validate(schema: &Value, value: &Value) -> Vec<String> {
+    match rlm_core::schema_errors(schema, value, MAX_ERRORS) {
+        Ok(errors) => errors,
+        Err(e) => vec![format!("the tool's schema is invalid: {}", e)],
+    }
+}
+
+/// The arguments `execute` receives for a call body
+///
+/// Tools with the [`text_schema`] get the body as written (or the string
+/// a JSON string literal holds). Other tools get JSON text that validates
+/// against their schema; anything else is [`ToolError::InvalidArgs`]
+/// naming what is wrong and the schema expected.
+pub fn prepare_args(schema: &Value, body: &str) -> Result<String, ToolError> {
+    let parsed = serde_json::from_str::<Value>(body);
+    if *schema == text_schema() {
+        return Ok(match parsed {
+            Ok(Value::String(text)) => text,
+            _ => body.to_string(),
+        });
+    }
+
+    let value = parsed.map_err(|e| {
+        ToolError::InvalidArgs(format!(
+            "arguments must be JSON matching {} ({})",
+            schema, e
+        ))
+    })?;
+    let errors = validate(schema, &value);
+    if !errors.is_empty() {
+        return Err(ToolError::InvalidArgs(format!(
+            "arguments don't match the schema: {}; expected JSON matching {}",
+            errors.join("; "),
+            schema
+        )));
+    }
+    Ok(value.to_string())
+}
+
+/// The properties of an object schema, one line each, for the tool docs:
+/// `name (type, required): description`
+pub fn describe(schema: &Value) -> Vec<String> {
+    let Some(properties) = schema.get("properties").and_then(Value::as_object) else {
+        return Vec::new();
+    };
+    let required: Vec<&str> = schema
+        .get("required")
+        .and_then(Value::as_array)
+        .map(|names| names.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+    properties
+        .iter()
+        .map(|(name, property)| {
+            let mut line = format!("{} ({}", name, type_label(property));
+            if required.contains(&name.as_str()) {
+                line.push_str(", required");
+            }
+            line.push(')');
+            if let Some(description) = property.get("description").and_then(Value::as_str) {
+                line.push_str(": ");
+                line.push_str(description);
+            }
+            line
+        })
+        .collect()
+}
+
+/// The values `schema` takes, in words
+fn type_label(schema: &Value) -> String {
+    if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+        let values: Vec<String> = values.iter().map(Value::to_string).collect();
+        return format!("one of {}", values.join(", "));
+    }
+    let label = match schema.get("type") {
+        Some(Value::String(t)) => t.clone(),
+        Some(Value::Array(ts)) => {
+            let types: Vec<&str> = ts.iter().filter_map(Value::as_str).collect();
+            types.join(" or ")
+        }
+        _ => "any".to_string(),
+    };
+    match schema.get("items") {
+        Some(items) if label == "array" => format!("array of {}", type_label(items)),
+        _ => label,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "path": { "type": "string" },
+                "mode": { "enum": ["append", "overwrite"] },
+                "lines": { "type": "array", "items": { "type": "integer" } }
+            },
+            "required": ["path"],
+            "additionalProperties": false
+        });
+        assert!(validate(&schema, &json!({ "path": "a.txt", "lines": [1, 2] })).is_empty());
+        assert_eq!(
+            validate(
+                &schema,
+                &json!({ "mode": "delete", "lines": [1, "2"], "extra": true })
+            ),
+            [
+                "/lines/1: \"2\" is not of type \"integer\"",
+                "/mode: \"delete\" is not one of [\"append\",\"overwrite\"]",
+                "Additional properties are not allowed ('extra' was unexpected)",
+                "\"path\" is a required property",
+            ]
+        );
+        assert_eq!(
+            validate(&schema, &json!("a.txt")),
+            ["\"a.txt\" is not of type \"object\""]
+        );
+        // Keywords beyond the common ones count too
+        let schema = json!({ "type": "string", "minLength": 3 });
+        assert_eq!(validate(&schema, &json!("ab")).len(), 1);
+        assert_eq!(
+            validate(&json!({ "type": 5 }), &json!(1))[0],
+            "the tool's schema is invalid: 5 is not valid under any of the schemas listed in the 'anyOf' keyword"
+        );
+    }
+
+    #[test]
+    fn test_prepare_args() {
+        assert_eq!(prepare_args(&text_schema(), "a.txt").unwrap(), "a.txt");
+        assert_eq!(prepare_args(&text_schema(), "\"a b\"").unwrap(), "a b");
+        assert_eq!(
+            prepare_args(&text_schema(), "{\"x\": 1}").unwrap(),
+            "{\"x\": 1}"
+        );
+
+        let schema = json!({ "type": "object", "required": ["path"] });
+        assert_eq!(
+            prepare_args(&schema, "{ \"path\": \"a\" }").unwrap(),
+            "{\"path\":\"a\"}"
+        );
+        let err = prepare_args(&schema, "a.txt|||hello").unwrap_err();
+        assert_eq!(err.kind(), "invalid_args");
+        assert!(err.message().starts_with("arguments must be JSON matching"));
+        let err = prepare_args(&schema, "{}").unwrap_err();
+        assert!(err.message().contains("\"path\" is a required property"));
+    }
+}
@@ -0,0 +1,128 @@
+//! Multi-turn sessions that can be saved and resumed
+//!
+//! An [`AgentSession`] records each task run through
+//! [`Agent::run_in_session`](crate::Agent::run_in_session): the task, its
+//! answer or error, and its tool calls. Later tasks in the session get the
+//! most recent of them in their context, so a follow-up can build on what
+//! an earlier task did. Sessions save to and load from JSON files.
+
+use crate::output::{self, OutputTruncation};
+use crate::{AgentError, AgentRunReport, ToolCallRecord};
+use rlm_core::write_atomic;
+use rlm_core::Usage;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Earlier tasks put into the context of the next one
+const SESSION_TURNS: usize = 5;
+
+/// Characters of an earlier answer put into the context
+const ANSWER_CHARS: usize = 1_000;
+
+/// Tool calls of an earlier task put into the context
+const TURN_TOOL_CALLS: usize = 10;
+
+/// Characters of an earlier tool call's output put into the context
+const TOOL_OUTPUT_CHARS: usize = 200;
+
+/// One task of a session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionTurn {
+    pub task: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub answer: Option<String>,
+    /// Why the run failed, when it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub tool_calls: Vec<ToolCallRecord>,
+    #[serde(default)]
+    pub usage: Usage,
+}
+
+/// Tasks run so far in a conversation with the agent
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AgentSession {
+    pub turns: Vec<SessionTurn>,
+    /// Memory file used with the session, reopened when it is resumed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<PathBuf>,
+}
+
+impl AgentSession {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Session saved at `path`
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        write_atomic(path.as_ref(), &serde_json::to_string_pretty(self)?)
+    }
+
+    /// Forget the tasks so far
+    pub fn clear(&mut self) {
+        self.turns.clear();
+    }
+
+    /// Add the outcome of a run of `task`
+    pub fn record(&mut self, task: &str, result: &Result<AgentRunReport, AgentError>) {
+        let turn = match result {
+            Ok(report) => SessionTurn {
+                task: task.to_string(),
+                answer: Some(report.run.answer.clone()),
+                error: None,
+                tool_calls: report.run.tool_calls.clone(),
+                usage: report.run.usage.clone(),
+            },
+            // A failed run keeps what it did
+            Err(e) => SessionTurn {
+                task: task.to_string(),
+                answer: None,
+                error: Some(e.to_string()),
+                tool_calls: e.partial().run.tool_calls.clone(),
+                usage: e.partial().run.usage.clone(),
+            },
+        };
+        self.turns.push(turn);
+    }
+
+    /// The most recent tasks, as a context section
+    pub(crate) fn context_section(&self) -> String {
+        if self.turns.is_empty() {
+            return String::new();
+        }
+        let skipped = self.turns.len().saturating_sub(SESSION_TURNS);
+        let mut section = "\nEARLIER TASKS IN THIS SESSION:\n".to_string();
+        for (i, turn) in self.turns.iter().enumerate().skip(skipped) {
+            section.push_str(&format!("{}. Task: {}\n", i + 1, turn.task));
+            for record in turn.tool_calls.iter().take(TURN_TOOL_CALLS) {
+                let outcome = match record.result.error {
+                    Some(ref error) => format!("error: {}", error.message()),
+                    None => output::truncate(
+                        &record.result.output,
+                        TOOL_OUTPUT_CHARS,
+                        OutputTruncation::Head,
+                    ),
+                };
+                section.push_str(&format!(
+                    "   Tool {}({}): {}\n",
+                    record.call.name, record.call.args, outcome
+                ));
+            }
+            match (&turn.answer, &turn.error) {
+                (Some(answer), _) => section.push_str(&format!(
+                    "   Answer: {}\n",
+                    output::truncate(answer, ANSWER_CHARS, OutputTruncation::Head)
+                )),
+                (None, Some(error)) => section.push_str(&format!("   Failed: {}\n", error)),
+                (None, None) => {}
+            }
+        }
+        section
+    }
+}
@@ -1,7 +1,14 @@
 //! Built-in tools for the agent
 
-use crate::{Tool, ToolResult};
+use crate::{html, AsyncTool, EditFileTool, OutputLimit, Tool, ToolError, ToolResult, Workspace};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::json;
+use std::path::PathBuf;
 use std::process::Command;
+#[cfg(feature = "rlm")]
+use std::sync::mpsc;
+use std::time::Duration;
 
 /// Echo tool - for testing
 pub struct EchoTool;
@@ -24,8 +31,16 @@ impl Tool for EchoTool {
     }
 }
 
-/// Read file tool
-pub struct ReadFileTool;
+/// Read file tool, confined to its workspace
+pub struct ReadFileTool {
+    pub workspace: Workspace,
+}
+
+impl ReadFileTool {
+    pub fn new(workspace: Workspace) -> Self {
+        Self { workspace }
+    }
+}
 
 impl Tool for ReadFileTool {
     fn name(&self) -> &str {
@@ -40,17 +55,34 @@ impl Tool for ReadFileTool {
         "<tool:read_file>path/to/file.txt</tool>"
     }
 
+    fn category(&self) -> &str {
+        "files"
+    }
+
     fn execute(&self, args: &str) -> ToolResult {
-        let path = args.trim();
-        match std::fs::read_to_string(path) {
+        match self.workspace.read(args.trim()) {
             Ok(content) => ToolResult::ok(content),
-            Err(e) => ToolResult::err(format!("Failed to read '{}': {}", path, e)),
+            Err(e) => ToolResult::err(e),
         }
     }
 }
 
-/// Write file tool
-pub struct WriteFileTool;
+/// Write file tool, confined to its workspace
+pub struct WriteFileTool {
+    pub workspace: Workspace,
+}
+
+impl WriteFileTool {
+    pub fn new(workspace: Workspace) -> Self {
+        Self { workspace }
+    }
+}
+
+#[derive(Deserialize)]
+struct WriteFileArgs {
+    path: String,
+    content: String,
+}
 
 impl Tool for WriteFileTool {
     fn name(&self) -> &str {
@@ -58,31 +90,54 @@ impl Tool for WriteFileTool {
     }
 
     fn description(&self) -> &str {
-        "Write content to a file. Format: path|||content"
+        "Write content to a file"
     }
 
     fn usage(&self) -> &str {
-        "<tool:write_file>path/to/file.txt|||file content here</tool>"
+        r#"<tool:write_file>{"path": "path/to/file.txt", "content": "file content here"}</tool>"#
     }
 
-    fn execute(&self, args: &str) -> ToolResult {
-        let parts: Vec<&str> = args.splitn(2, "|||").collect();
-        if parts.len() != 2 {
-            return ToolResult::err("Invalid format. Use: path|||content");
-        }
+    fn category(&self) -> &str {
+        "files"
+    }
 
-        let path = parts[0].trim();
-        let content = parts[1];
+    fn parameters_schema(&self) -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": { "type": "string" },
+                "content": { "type": "string" }
+            },
+            "required": ["path", "content"],
+            "additionalProperties": false
+        })
+    }
 
-        match std::fs::write(path, content) {
+    fn execute(&self, args: &str) -> ToolResult {
+        let args: WriteFileArgs = match serde_json::from_str(args) {
+            Ok(args) => args,
+            Err(e) => return ToolResult::err(ToolError::InvalidArgs(e.to_string())),
+        };
+        let path = args.path.trim();
+        let content = args.content;
+
+        match self.workspace.write(path, &content) {
             Ok(()) => ToolResult::ok(format!("Written {} bytes to {}", content.len(), path)),
-            Err(e) => ToolResult::err(format!("Failed to write '{}': {}", path, e)),
+            Err(e) => ToolResult::err(e),
         }
     }
 }
 
-/// List directory tool
-pub struct ListDirTool;
+/// List directory tool, confined to its workspace
+pub struct ListDirTool {
+    pub workspace: Workspace,
+}
+
+impl ListDirTool {
+    pub fn new(workspace: Workspace) -> Self {
+        Self { workspace }
+    }
+}
 
 impl Tool for ListDirTool {
     fn name(&self) -> &str {
@@ -97,11 +152,19 @@ impl Tool for ListDirTool {
         "<tool:list_dir>path/to/directory</tool>"
     }
 
+    fn category(&self) -> &str {
+        "files"
+    }
+
     fn execute(&self, args: &str) -> ToolResult {
         let path = args.trim();
         let path = if path.is_empty() { "." } else { path };
+        let dir = match self.workspace.resolve(path) {
+            Ok(dir) => dir,
+            Err(e) => return ToolResult::err(e),
+        };
 
-        match std::fs::read_dir(path) {
+        match std::fs::read_dir(dir) {
             Ok(entries) => {
                 let mut files: Vec<String> = entries
                     .filter_map(|e| e.ok())
@@ -117,7 +180,10 @@ impl Tool for ListDirTool {
                 files.sort();
                 ToolResult::ok(files.join("\n"))
             }
-            Err(e) => ToolResult::err(format!("Failed to list '{}': {}", path, e)),
+            Err(e) => ToolResult::err(ToolError::from_io(
+                format_args!("Failed to list '{}'", path),
+                &e,
+            )),
         }
     }
 }
@@ -171,6 +237,10 @@ impl Tool for ShellTool {
         "<tool:shell>ls -la</tool>"
     }
 
+    fn category(&self) -> &str {
+        "code"
+    }
+
     fn execute(&self, args: &str) -> ToolResult {
         let cmd = args.trim();
 
@@ -178,10 +248,10 @@ impl Tool for ShellTool {
         if !self.allowed_commands.is_empty() {
             let first_word = cmd.split_whitespace().next().unwrap_or("");
             if !self.allowed_commands.iter().any(|c| c == first_word) {
-                return ToolResult::err(format!(
+                return ToolResult::err(ToolError::PermissionDenied(format!(
                     "Command '{}' not allowed. Allowed: {:?}",
                     first_word, self.allowed_commands
-                ));
+                )));
             }
         }
 
@@ -193,10 +263,13 @@ impl Tool for ShellTool {
                 if output.status.success() {
                     ToolResult::ok(stdout.to_string())
                 } else {
-                    ToolResult::err(format!("Exit {}: {}", output.status, stderr))
+                    ToolResult::err(ToolError::Failed(format!(
+                        "Exit {}: {}",
+                        output.status, stderr
+                    )))
                 }
             }
-            Err(e) => ToolResult::err(format!("Failed to run command: {}", e)),
+            Err(e) => ToolResult::err(ToolError::from_io("Failed to run command", &e)),
         }
     }
 }
@@ -230,22 +303,513 @@ impl Tool for CalcTool {
                 if output.status.success() {
                     ToolResult::ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
                 } else {
-                    ToolResult::err(String::from_utf8_lossy(&output.stderr).to_string())
+                    ToolResult::err(ToolError::InvalidArgs(
+                        String::from_utf8_lossy(&output.stderr).to_string(),
+                    ))
+                }
+            }
+            Err(e) => ToolResult::err(ToolError::from_io("Calc error", &e)),
+        }
+    }
+}
+
+/// Git tool: read-only subcommands run in one repository
+pub struct GitTool {
+    pub repo: PathBuf,
+    pub allowed_subcommands: Vec<String>,
+}
+
+/// Options that write files, run programs, or leave the repository
+const GIT_BLOCKED_OPTIONS: &[&str] = &[
+    "--output",
+    "--ext-diff",
+    "--no-index",
+    "--exec",
+    "--git-dir",
+    "--work-tree",
+    "--upload-pack",
+    "--receive-pack",
+    "--open-files-in-pager",
+];
+
+impl GitTool {
+    pub fn new(repo: impl Into<PathBuf>) -> Self {
+        Self {
+            repo: repo.into(),
+            allowed_subcommands: ["status", "log", "diff", "show", "blame"]
+                .map(String::from)
+                .to_vec(),
+        }
+    }
+}
+
+/// Split arguments on whitespace, keeping quoted runs together
+fn split_args(args: &str) -> Result<Vec<String>, ToolError> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote = None;
+    for c in args.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
                 }
             }
-            Err(e) => ToolResult::err(format!("Calc error: {}", e)),
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if quote.is_some() {
+        return Err(ToolError::InvalidArgs("unclosed quote".to_string()));
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+impl Tool for GitTool {
+    fn name(&self) -> &str {
+        "git"
+    }
+
+    fn description(&self) -> &str {
+        "Inspect the repository: status, log, diff, show, or blame"
+    }
+
+    fn usage(&self) -> &str {
+        "<tool:git>log --oneline -n 10 -- src/main.rs</tool>"
+    }
+
+    fn category(&self) -> &str {
+        "code"
+    }
+
+    fn execute(&self, args: &str) -> ToolResult {
+        let words = match split_args(args) {
+            Ok(words) => words,
+            Err(e) => return ToolResult::err(e),
+        };
+        let Some(subcommand) = words.first() else {
+            return ToolResult::err(ToolError::InvalidArgs(format!(
+                "expected a subcommand, one of {}",
+                self.allowed_subcommands.join(", ")
+            )));
+        };
+        if !self.allowed_subcommands.contains(subcommand) {
+            return ToolResult::err(ToolError::PermissionDenied(format!(
+                "git {} not allowed. Allowed: {}",
+                subcommand,
+                self.allowed_subcommands.join(", ")
+            )));
+        }
+        let blocked = words[1..].iter().find(|word| {
+            GIT_BLOCKED_OPTIONS
+                .iter()
+                .any(|option| word.split('=').next() == Some(*option))
+        });
+        if let Some(option) = blocked {
+            return ToolResult::err(ToolError::PermissionDenied(format!(
+                "git option '{}' not allowed",
+                option
+            )));
+        }
+
+        let mut command = Command::new("git");
+        command
+            .arg("--no-pager")
+            .arg("-C")
+            .arg(&self.repo)
+            .arg(subcommand);
+        if matches!(subcommand.as_str(), "log" | "diff" | "show") {
+            command.arg("--no-ext-diff");
+        }
+        command
+            .args(&words[1..])
+            .env("GIT_TERMINAL_PROMPT", "0")
+            .env("GIT_OPTIONAL_LOCKS", "0");
+
+        match command.output() {
+            Ok(output) if output.status.success() => {
+                ToolResult::ok(String::from_utf8_lossy(&output.stdout).to_string())
+            }
+            Ok(output) => ToolResult::err(ToolError::Failed(format!(
+                "git {} failed ({}): {}",
+                subcommand,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))),
+            Err(e) => ToolResult::err(ToolError::from_io("Failed to run git", &e)),
         }
     }
 }
 
-/// Create a default tool registry with common tools
+/// Web page tool: downloads a URL and returns its main content as markdown
+pub struct FetchPageTool {
+    client: reqwest::Client,
+    /// Bytes of the body read before the rest is dropped
+    pub max_bytes: usize,
+}
+
+impl FetchPageTool {
+    pub fn new() -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .user_agent(concat!("rlm_agent/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .unwrap_or_default();
+        Self {
+            client,
+            max_bytes: 5 * 1024 * 1024,
+        }
+    }
+}
+
+impl Default for FetchPageTool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Classify a failed request
+fn request_error(url: &str, e: &reqwest::Error) -> ToolError {
+    let message = format!("Failed to fetch '{}': {}", url, e);
+    if e.is_timeout() {
+        ToolError::Timeout(message)
+    } else if e.is_connect() {
+        ToolError::Transient(message)
+    } else if e.is_builder() {
+        ToolError::InvalidArgs(message)
+    } else {
+        ToolError::Failed(message)
+    }
+}
+
+/// Classify an unsuccessful HTTP status
+fn status_error(url: &str, status: reqwest::StatusCode) -> ToolError {
+    let message = format!("Fetching '{}' returned {}", url, status);
+    match status.as_u16() {
+        404 | 410 => ToolError::NotFound(message),
+        401 | 403 => ToolError::PermissionDenied(message),
+        408 | 429 | 500..=599 => ToolError::Transient(message),
+        _ => ToolError::Failed(message),
+    }
+}
+
+#[async_trait]
+impl AsyncTool for FetchPageTool {
+    fn name(&self) -> &str {
+        "fetch_page"
+    }
+
+    fn description(&self) -> &str {
+        "Download a web page and return its main content as markdown"
+    }
+
+    fn usage(&self) -> &str {
+        "<tool:fetch_page>https://example.com/article</tool>"
+    }
+
+    fn category(&self) -> &str {
+        "web"
+    }
+
+    async fn execute(&self, args: &str) -> ToolResult {
+        let url = args.trim();
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return ToolResult::err(ToolError::InvalidArgs(format!(
+                "expected an http(s) URL, got '{}'",
+                url
+            )));
+        }
+
+        let mut response = match self.client.get(url).send().await {
+            Ok(response) => response,
+            Err(e) => return ToolResult::err(request_error(url, &e)),
+        };
+        if !response.status().is_success() {
+            return ToolResult::err(status_error(url, response.status()));
+        }
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+
+        let mut body = Vec::new();
+        let mut cut = false;
+        loop {
+            match response.chunk().await {
+                Ok(Some(chunk)) => {
+                    body.extend_from_slice(&chunk);
+                    if body.len() >= self.max_bytes {
+                        body.truncate(self.max_bytes);
+                        cut = true;
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => return ToolResult::err(request_error(url, &e)),
+            }
+        }
+        let text = String::from_utf8_lossy(&body);
+
+        let is_html = content_type.contains("html")
+            || (content_type.is_empty() && text.trim_start().starts_with('<'));
+        let mut output = if is_html {
+            let page = html::to_markdown(&text);
+            let title = page.title.unwrap_or_else(|| url.to_string());
+            format!("# {}\n\nSource: {}\n\n{}", title, url, page.markdown)
+        } else if content_type.starts_with("text/")
+            || content_type.contains("json")
+            || content_type.contains("xml")
+            || content_type.is_empty()
+        {
+            text.into_owned()
+        } else {
+            return ToolResult::err(ToolError::InvalidArgs(format!(
+                "'{}' is {}, not a text page",
+                url, content_type
+            )));
+        };
+        if cut {
+            output.push_str(&format!("\n[page cut at {} bytes]", self.max_bytes));
+        }
+        ToolResult::ok(output)
+    }
+}
+
+#[cfg(feature = "rlm")]
+enum PythonRequest {
+    Run(String, mpsc::Sender<ToolResult>),
+    Reset,
+}
+
+/// Python tool: runs code in a REPL that keeps its variables for the task
+///
+/// Built on the core engine's REPL ([`rlm::Rlm::repl_session`]), so it has
+/// the config's sandbox policy, workspace helpers, packages, and
+/// `exec_timeout`, and `llm_query` works. The REPL lives on a thread of its
+/// own, starts on first use, and is dropped when the next task starts.
+#[cfg(feature = "rlm")]
+pub struct PythonTool {
+    requests: mpsc::Sender<PythonRequest>,
+    timeout: Option<Duration>,
+}
+
+/// Extra time past `exec_timeout` before the agent gives up on a call,
+/// leaving the REPL's watchdog time to fire first
+#[cfg(feature = "rlm")]
+const PYTHON_TIMEOUT_GRACE: Duration = Duration::from_secs(5);
+
+#[cfg(feature = "rlm")]
+impl PythonTool {
+    pub fn new(config: rlm_core::RlmConfig) -> rlm_core::Result<Self> {
+        let timeout = config.exec_timeout.map(|t| t + PYTHON_TIMEOUT_GRACE);
+        let rlm = rlm::Rlm::new(config)?;
+        let (requests, receiver) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("python-tool".to_string())
+            .spawn(move || serve_python(rlm, receiver))?;
+        Ok(Self { requests, timeout })
+    }
+}
+
+/// Run code for the tool until it is dropped
+#[cfg(feature = "rlm")]
+fn serve_python(rlm: rlm::Rlm, requests: mpsc::Receiver<PythonRequest>) {
+    let mut session = None;
+    for request in requests {
+        let (code, reply) = match request {
+            PythonRequest::Run(code, reply) => (code, reply),
+            PythonRequest::Reset => {
+                session = None;
+                continue;
+            }
+        };
+        if session.is_none() {
+            match rlm.repl_session() {
+                Ok(started) => session = Some(started),
+                Err(e) => {
+                    let error = ToolError::Failed(format!("Failed to start Python: {}", e));
+                    let _ = reply.send(ToolResult::err(error));
+                    continue;
+                }
+            }
+        }
+        let Some(repl) = session.as_mut() else {
+            continue;
+        };
+
+        let result = match repl.execute(&code) {
+            Ok(result) if result.success => ToolResult::ok(result.stdout),
+            Ok(result) => {
+                let error = result.error.unwrap_or(result.stderr);
+                let message = match result.stdout.trim() {
+                    "" => error,
+                    stdout => format!("{}\nOutput before the error:\n{}", error, stdout),
+                };
+                ToolResult::err(ToolError::Failed(message))
+            }
+            Err(e) => {
+                // The REPL is in an unknown state; start over on the next call
+                session = None;
+                ToolResult::err(ToolError::Failed(format!(
+                    "Python REPL failed, variables are lost: {}",
+                    e
+                )))
+            }
+        };
+        let _ = reply.send(result);
+    }
+}
+
+#[cfg(feature = "rlm")]
+impl Tool for PythonTool {
+    fn name(&self) -> &str {
+        "python"
+    }
+
+    fn description(&self) -> &str {
+        "Run Python code; variables persist between calls within a task"
+    }
+
+    fn usage(&self) -> &str {
+        "<tool:python>total = sum(range(10))\nprint(total)</tool>"
+    }
+
+    fn category(&self) -> &str {
+        "code"
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    fn reset(&self) {
+        let _ = self.requests.send(PythonRequest::Reset);
+    }
+
+    fn execute(&self, args: &str) -> ToolResult {
+        let (reply, result) = mpsc::channel();
+        let gone = || ToolResult::err(ToolError::Failed("Python REPL thread stopped".to_string()));
+        if self
+            .requests
+            .send(PythonRequest::Run(args.to_string(), reply))
+            .is_err()
+        {
+            return gone();
+        }
+        result.recv().unwrap_or_else(|_| gone())
+    }
+}
+
+/// Create a default tool registry with common tools, the file tools
+/// confined to the current directory
 pub fn default_tools() -> crate::ToolRegistry {
+    default_tools_in(Workspace::default())
+}
+
+/// Create a default tool registry with the file tools confined to `workspace`
+pub fn default_tools_in(workspace: Workspace) -> crate::ToolRegistry {
     let mut registry = crate::ToolRegistry::new();
     registry.register(EchoTool);
-    registry.register(ReadFileTool);
-    registry.register(WriteFileTool);
-    registry.register(ListDirTool);
+    registry.register(ReadFileTool::new(workspace.clone()));
+    registry.register(WriteFileTool::new(workspace.clone()));
+    registry.register(EditFileTool::new(workspace.clone()));
+    registry.register(ListDirTool::new(workspace));
     registry.register(ShellTool::new());
     registry.register(CalcTool);
+    registry.register_async(FetchPageTool::new());
+    // Long pages are worth a summary rather than a cut
+    registry.set_output_limit("fetch_page", OutputLimit::summarize(20_000));
     registry
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_args() {
+        assert_eq!(
+            split_args(r#"log --grep "fix bug" -- 'a b.rs'"#).unwrap(),
+            ["log", "--grep", "fix bug", "--", "a b.rs"]
+        );
+        assert_eq!(split_args("show ''").unwrap(), ["show", ""]);
+        assert!(split_args("log \"oops").is_err());
+    }
+
+    #[cfg(feature = "rlm")]
+    #[test]
+    fn test_python_tool_keeps_state_per_task() {
+        let mock = rlm_core::MockBackend::new(["hello"]);
+        let config = rlm_core::RlmConfig::new("mock")
+            .with_backend(rlm_core::Backend::Mock(mock))
+            .with_repl_mode(rlm_core::ReplMode::Worker)
+            .with_sandbox(rlm_core::SandboxPolicy::restricted());
+        let tool = PythonTool::new(config).unwrap();
+
+        assert!(tool.execute("x = 21").success);
+        assert_eq!(
+            tool.execute("print(x * 2, llm_query('hi'))").output,
+            "42 hello\n"
+        );
+        let result = tool.execute("import subprocess");
+        assert!(result.error.unwrap().message().contains("blocked"));
+
+        tool.reset();
+        let result = tool.execute("print(x)");
+        assert!(result.error.unwrap().message().contains("NameError"));
+    }
+
+    #[test]
+    fn test_git_tool() {
+        let repo = std::env::temp_dir().join(format!("rlm_agent_git_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&repo);
+        std::fs::create_dir_all(&repo).unwrap();
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .arg("-C")
+                .arg(&repo)
+                .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {:?}", args);
+        };
+        git(&["init", "-q"]);
+        std::fs::write(repo.join("notes.txt"), "first\n").unwrap();
+        git(&["add", "notes.txt"]);
+        git(&["commit", "-q", "-m", "Add notes"]);
+        std::fs::write(repo.join("notes.txt"), "second\n").unwrap();
+
+        let tool = GitTool::new(&repo);
+        assert!(tool.execute("log --format=%s").output.contains("Add notes"));
+        assert!(tool.execute("diff").output.contains("+second"));
+        assert!(tool
+            .execute("blame HEAD notes.txt")
+            .output
+            .contains("first"));
+
+        let result = tool.execute("push origin main");
+        assert_eq!(result.error.unwrap().kind(), "permission_denied");
+        let result = tool.execute("diff --output=/tmp/x");
+        assert_eq!(result.error.unwrap().kind(), "permission_denied");
+        let result = tool.execute("show nope");
+        assert_eq!(result.error.unwrap().kind(), "failed");
+        let _ = std::fs::remove_dir_all(&repo);
+    }
+}
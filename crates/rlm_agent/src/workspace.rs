@@ -0,0 +1,215 @@
+//! Workspace jail for the filesystem tools
+//!
+//! [`ReadFileTool`](crate::tools::ReadFileTool),
+//! [`WriteFileTool`](crate::tools::WriteFileTool), and
+//! [`ListDirTool`](crate::tools::ListDirTool) resolve every path through a
+//! [`Workspace`]: relative paths start at its root, and a path that ends up
+//! outside the root once `..` and symlinks are followed is refused with
+//! [`ToolError::PermissionDenied`]. A workspace can also be read-only and
+//! cap the size of the files read or written.
+
+use crate::ToolError;
+use std::path::{Component, Path, PathBuf};
+
+/// Default cap on the size of a file read or written
+pub const DEFAULT_MAX_FILE_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Directory the filesystem tools are confined to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Workspace {
+    /// Canonicalized on every call, so it may be relative or not exist yet
+    pub root: PathBuf,
+    /// Refuse writes
+    pub read_only: bool,
+    /// Largest file read or written, in bytes (None = unlimited)
+    pub max_file_bytes: Option<u64>,
+}
+
+impl Workspace {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            read_only: false,
+            max_file_bytes: Some(DEFAULT_MAX_FILE_BYTES),
+        }
+    }
+
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    pub fn with_max_file_bytes(mut self, max_file_bytes: Option<u64>) -> Self {
+        self.max_file_bytes = max_file_bytes;
+        self
+    }
+
+    fn canonical_root(&self) -> Result<PathBuf, ToolError> {
+        self.root.canonicalize().map_err(|e| {
+            ToolError::from_io(
+                format_args!("Workspace '{}' is unavailable", self.root.display()),
+                &e,
+            )
+        })
+    }
+
+    /// `resolved` if it is inside `root`
+    fn contain(root: &Path, path: &str, resolved: PathBuf) -> Result<PathBuf, ToolError> {
+        if resolved.starts_with(root) {
+            Ok(resolved)
+        } else {
+            Err(ToolError::PermissionDenied(format!(
+                "'{}' is outside the workspace",
+                path
+            )))
+        }
+    }
+
+    /// Existing file or directory at `path`
+    pub fn resolve(&self, path: &str) -> Result<PathBuf, ToolError> {
+        let root = self.canonical_root()?;
+        let resolved = root.join(path).canonicalize().map_err(|e| {
+            // Don't tell whether something outside the workspace exists
+            match Self::contain(&root, path, lexical(&root.join(path))) {
+                Ok(_) => ToolError::from_io(format_args!("Failed to open '{}'", path), &e),
+                Err(denied) => denied,
+            }
+        })?;
+        Self::contain(&root, path, resolved)
+    }
+
+    /// Where to write `path`, which may not exist yet but whose directory must
+    pub fn resolve_for_write(&self, path: &str) -> Result<PathBuf, ToolError> {
+        if self.read_only {
+            return Err(ToolError::PermissionDenied(format!(
+                "Can't write '{}': the workspace is read-only",
+                path
+            )));
+        }
+        let root = self.canonical_root()?;
+        let target = root.join(path);
+        // Not `exists()`: that follows symlinks, and a dangling one would
+        // then be written through as a new file
+        if let Ok(meta) = std::fs::symlink_metadata(&target) {
+            if meta.file_type().is_symlink() && !target.exists() {
+                return Err(ToolError::PermissionDenied(format!(
+                    "Can't write '{}': it is a symlink to a missing file",
+                    path
+                )));
+            }
+            // Follows a symlink to wherever it really points
+            return self.resolve(path);
+        }
+        let (Some(parent), Some(name)) = (target.parent(), target.file_name()) else {
+            return Err(ToolError::InvalidArgs(format!(
+                "'{}' is not a file path",
+                path
+            )));
+        };
+        let parent = parent.canonicalize().map_err(|e| {
+            match Self::contain(&root, path, lexical(&target)) {
+                Ok(_) => ToolError::from_io(format_args!("Failed to write '{}'", path), &e),
+                Err(denied) => denied,
+            }
+        })?;
+        Self::contain(&root, path, parent.join(name))
+    }
+
+    /// Contents of the text file at `path`
+    pub fn read(&self, path: &str) -> Result<String, ToolError> {
+        let resolved = self.resolve(path)?;
+        let io_error = |e| ToolError::from_io(format_args!("Failed to read '{}'", path), &e);
+        let size = std::fs::metadata(&resolved).map_err(io_error)?.len();
+        self.check_size(path, size)?;
+        std::fs::read_to_string(&resolved).map_err(io_error)
+    }
+
+    /// Write `content` to `path`, replacing what was there
+    pub fn write(&self, path: &str, content: &str) -> Result<(), ToolError> {
+        let resolved = self.resolve_for_write(path)?;
+        self.check_size(path, content.len() as u64)?;
+        std::fs::write(resolved, content)
+            .map_err(|e| ToolError::from_io(format_args!("Failed to write '{}'", path), &e))
+    }
+
+    pub(crate) fn check_size(&self, path: &str, size: u64) -> Result<(), ToolError> {
+        match self.max_file_bytes {
+            Some(max) if size > max => Err(ToolError::InvalidArgs(format!(
+                "'{}' is {} bytes, over the workspace limit of {}",
+                path, size, max
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Default for Workspace {
+    /// The current directory
+    fn default() -> Self {
+        Self::new(".")
+    }
+}
+
+/// `path` with `.` and `..` removed without touching the filesystem
+fn lexical(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_workspace_jail() {
+        let base = std::env::temp_dir().join(format!("rlm_agent_jail_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&base);
+        let root = base.join("root");
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::write(base.join("secret.txt"), "secret").unwrap();
+        let workspace = Workspace::new(&root).with_max_file_bytes(Some(8));
+
+        workspace.write("sub/a.txt", "hello").unwrap();
+        assert_eq!(workspace.read("sub/../sub/a.txt").unwrap(), "hello");
+        let absolute = root.join("sub/a.txt");
+        assert_eq!(workspace.read(absolute.to_str().unwrap()).unwrap(), "hello");
+
+        let denied = |e: ToolError| e.kind() == "permission_denied";
+        assert!(denied(workspace.read("../secret.txt").unwrap_err()));
+        assert!(denied(workspace.read("/etc/passwd").unwrap_err()));
+        assert!(denied(workspace.read("../missing.txt").unwrap_err()));
+        assert!(denied(workspace.write("../escape.txt", "x").unwrap_err()));
+        assert_eq!(
+            workspace.read("missing.txt").unwrap_err().kind(),
+            "not_found"
+        );
+        assert_eq!(
+            workspace.write("big.txt", "too long!").unwrap_err().kind(),
+            "invalid_args"
+        );
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(base.join("secret.txt"), root.join("link")).unwrap();
+            assert!(denied(workspace.read("link").unwrap_err()));
+            assert!(denied(workspace.write("link", "x").unwrap_err()));
+            std::os::unix::fs::symlink(base.join("new.txt"), root.join("dangling")).unwrap();
+            assert!(denied(workspace.write("dangling", "x").unwrap_err()));
+            assert!(!base.join("new.txt").exists());
+        }
+
+        let read_only = workspace.clone().read_only();
+        assert!(denied(read_only.write("sub/b.txt", "x").unwrap_err()));
+        assert_eq!(read_only.read("sub/a.txt").unwrap(), "hello");
+        let _ = std::fs::remove_dir_all(&base);
+    }
+}
@@ -5,24 +5,102 @@
 //! - The system prompt tells the model to examine `context` to find what to do
 //! - The model uses the REPL to recursively process the context with sub-LLM calls
 
+mod render;
+
+use arboard::Clipboard;
 use clap::{Parser, ValueEnum};
-use rlm::{Backend, Rlm, RlmConfig};
+use render::{HighlightSink, Renderer};
+use rlm::files::write_atomic;
+use rlm::log::StderrSink;
+use rlm::{
+    Backend, CodeApproval, CompletionStatus, OpenAiBackend, Rlm, RlmCompletion, RlmConfig,
+    RlmEvent, UserConfig, UserSettings,
+};
 use rustyline::error::ReadlineError;
-use rustyline::DefaultEditor;
-use std::io::{self, Write};
-use std::path::PathBuf;
+use rustyline::{Cmd, DefaultEditor, KeyCode, KeyEvent, Modifiers};
+use serde::{Deserialize, Serialize};
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Frames of the live view's spinner
+const SPINNER: &[char] = &['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+/// Widest the live view's status line gets, so it never wraps
+const LIVE_WIDTH: usize = 78;
+
+const DEFAULT_BACKEND_URL: &str = "http://localhost:11434/v1";
+const DEFAULT_TEMPERATURE: f32 = 0.7;
+const DEFAULT_MAX_ITERATIONS: u32 = 50;
 
 /// Chat message for history tracking
+#[derive(Serialize, Deserialize)]
 struct ChatMessage {
-    role: &'static str,
+    role: String,
     content: String,
 }
 
+/// Conversation saved with --session, continued with --resume
+#[derive(Default, Serialize, Deserialize)]
+struct ChatSession {
+    /// Context file of the conversation, loaded again on resume
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    context_file: Option<PathBuf>,
+    /// Rolling summary of the turns compaction dropped from `history`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    summary: Option<String>,
+    #[serde(default)]
+    history: Vec<ChatMessage>,
+    /// Last code block run, for /copy-code
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_code: Option<String>,
+}
+
+impl ChatSession {
+    fn load(path: &Path) -> io::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    fn save(&self, path: &Path) -> io::Result<()> {
+        write_atomic(path, &serde_json::to_string_pretty(self)?)
+    }
+
+    /// Last answer, for /copy
+    fn last_answer(&self) -> Option<&str> {
+        self.history
+            .iter()
+            .rev()
+            .find(|msg| msg.role == "Assistant")
+            .map(|msg| msg.content.as_str())
+    }
+
+    /// Question of the last exchange, for /retry and /edit-last
+    fn last_question(&self) -> Option<&str> {
+        match self.history.as_slice() {
+            [.., question, answer] if question.role == "User" && answer.role == "Assistant" => {
+                Some(question.content.as_str())
+            }
+            _ => None,
+        }
+    }
+
+    /// Drop the last exchange, so a retry replaces it
+    fn pop_exchange(&mut self) {
+        if self.last_question().is_some() {
+            self.history.truncate(self.history.len() - 2);
+        }
+    }
+}
+
 /// Build the context payload for the REPL `context` variable
 ///
 /// Simple chat format - just User/Assistant turns like normal LLM chat.
 fn build_context_payload(
     file_context: Option<&str>,
+    summary: Option<&str>,
     history: &[ChatMessage],
     current_query: &str,
 ) -> String {
@@ -37,9 +115,16 @@ fn build_context_payload(
         payload.push_str("\n\n");
     }
 
+    // Turns folded away by compaction
+    if let Some(summary) = summary {
+        payload.push_str("Summary of the earlier conversation:\n");
+        payload.push_str(summary);
+        payload.push_str("\n\n");
+    }
+
     // Prior conversation in simple chat format
     for msg in history.iter().take(history.len().saturating_sub(1)) {
-        payload.push_str(msg.role);
+        payload.push_str(&msg.role);
         payload.push_str(": ");
         payload.push_str(&msg.content);
         payload.push_str("\n");
@@ -54,6 +139,371 @@ fn build_context_payload(
     payload
 }
 
+/// Context payload for the message just added to the history, compacting
+/// the history first if the payload would be longer than `--compact-at`
+fn turn_payload(
+    rlm: &Rlm,
+    args: &Args,
+    session: &mut ChatSession,
+    file_context: Option<&str>,
+    query: &str,
+) -> String {
+    let payload = || {
+        build_context_payload(
+            file_context,
+            session.summary.as_deref(),
+            &session.history,
+            query,
+        )
+    };
+    if args.compact_at == 0 || payload().len() <= args.compact_at {
+        return payload();
+    }
+    match compact_history(rlm, session, args.keep_turns as usize) {
+        Ok(0) => {}
+        Ok(folded) => eprintln!(
+            "(Summarized {} earlier messages to keep the context short)",
+            folded
+        ),
+        Err(e) => eprintln!("Couldn't summarize earlier messages: {}", e),
+    }
+    build_context_payload(
+        file_context,
+        session.summary.as_deref(),
+        &session.history,
+        query,
+    )
+}
+
+/// Fold all but the last `keep_turns` exchanges into the session's rolling
+/// summary, written by the model; returns how many messages were folded
+///
+/// The last message, the one about to be answered, always stays.
+fn compact_history(rlm: &Rlm, session: &mut ChatSession, keep_turns: usize) -> rlm::Result<usize> {
+    let keep = keep_turns * 2 + 1;
+    let fold = session.history.len().saturating_sub(keep);
+    if fold == 0 {
+        return Ok(0);
+    }
+
+    let mut prompt = String::from(
+        "Summarize the conversation below for the assistant taking part in it, so it \
+         can go on without the full transcript. Keep facts, decisions, names, numbers, \
+         and open questions; drop small talk. Reply with the summary only.\n\n",
+    );
+    if let Some(ref summary) = session.summary {
+        prompt.push_str("Summary of the conversation before this part:\n");
+        prompt.push_str(summary);
+        prompt.push_str("\n\n");
+    }
+    prompt.push_str("Conversation:\n");
+    for msg in &session.history[..fold] {
+        prompt.push_str(&format!("{}: {}\n", msg.role, msg.content));
+    }
+
+    let summary = rlm.llm_query(&prompt)?;
+    session.summary = Some(summary.trim().to_string());
+    session.history.drain(..fold);
+    Ok(fold)
+}
+
+/// Last code block executed in the REPL during a run
+fn last_code_block(result: &RlmCompletion) -> Option<&str> {
+    result
+        .iterations
+        .iter()
+        .flat_map(|it| &it.code_blocks)
+        .next_back()
+        .map(|block| block.code.as_str())
+}
+
+/// What the live view shows of the running completion
+#[derive(Default)]
+struct LiveStatus {
+    iteration: u32,
+    max_iterations: u32,
+    /// First line of the code running, if any is
+    code: Option<String>,
+    sub_queries: u32,
+}
+
+impl LiveStatus {
+    fn update(&mut self, event: &RlmEvent) {
+        match event {
+            RlmEvent::IterationStarted {
+                iteration,
+                max_iterations,
+            } => {
+                self.iteration = *iteration;
+                self.max_iterations = *max_iterations;
+                self.code = None;
+            }
+            RlmEvent::CodeStarted { code, .. } => {
+                let first = code.lines().find(|line| !line.trim().is_empty());
+                self.code = Some(first.unwrap_or_default().trim().to_string());
+            }
+            RlmEvent::CodeFinished { .. } => self.code = None,
+            RlmEvent::SubQuery { .. } => self.sub_queries += 1,
+            RlmEvent::IterationFinished { .. } => {}
+        }
+    }
+
+    /// Status line with spinner frame `frame`
+    fn line(&self, frame: char) -> String {
+        if self.iteration == 0 {
+            return format!("{} starting", frame);
+        }
+        let mut line = format!(
+            "{} iteration {}/{}",
+            frame, self.iteration, self.max_iterations
+        );
+        if self.sub_queries > 0 {
+            line.push_str(&format!(" · {} sub-queries", self.sub_queries));
+        }
+        match self.code {
+            Some(ref code) => line.push_str(&format!(" · ⚡ {}", code)),
+            None => line.push_str(" · thinking"),
+        }
+        line.chars().take(LIVE_WIDTH).collect()
+    }
+}
+
+/// Run a completion while a spinner line shows how it is going
+///
+/// `status` is kept up to date by the engine's event stream; the line is
+/// cleared again before this returns.
+fn completion_with_live_view(
+    rlm: &Rlm,
+    status: &Mutex<LiveStatus>,
+    payload: &str,
+) -> rlm::Result<RlmCompletion> {
+    *status.lock().unwrap() = LiveStatus::default();
+    let done = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            for &frame in SPINNER.iter().cycle() {
+                if done.load(Ordering::Relaxed) {
+                    break;
+                }
+                let line = status.lock().unwrap().line(frame);
+                print!("\r\x1b[2K{}", line);
+                io::stdout().flush().unwrap();
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            print!("\r\x1b[2K");
+            io::stdout().flush().unwrap();
+        });
+        let result = rlm.completion_with_context(payload, None);
+        done.store(true, Ordering::Relaxed);
+        result
+    })
+}
+
+/// `result` as pretty-printed JSON, for `--json`
+fn completion_json(result: &RlmCompletion) -> String {
+    serde_json::to_string_pretty(result).expect("completions serialize to JSON")
+}
+
+/// Append `result` to the `--output` file, one JSON object per line
+fn append_transcript(path: &Path, result: &RlmCompletion) -> io::Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(file, "{}", serde_json::to_string(result)?)
+}
+
+/// Save the turn to the session and the `--output` file, if there are any
+fn record_turn(
+    args: &Args,
+    session: &ChatSession,
+    session_path: Option<&PathBuf>,
+    result: &RlmCompletion,
+) {
+    if let Some(path) = session_path {
+        if let Err(e) = session.save(path) {
+            eprintln!("Failed to save session '{}': {}", path.display(), e);
+        }
+    }
+    if let Some(ref path) = args.output {
+        if let Err(e) = append_transcript(path, result) {
+            eprintln!("Failed to write transcript '{}': {}", path.display(), e);
+        }
+    }
+}
+
+/// Answer the `-p` question once and return the exit code
+///
+/// Only the answer goes to stdout; errors go to stderr. Exits with 0 for
+/// an answer, 1 on errors, and 2 when the iteration limit is hit first.
+fn run_once(
+    rlm: &Rlm,
+    args: &Args,
+    session: &mut ChatSession,
+    session_path: Option<&PathBuf>,
+    file_context: Option<&str>,
+    renderer: Option<&Renderer>,
+) -> i32 {
+    let question = args.prompt.as_deref().unwrap_or_default();
+    session.history.push(ChatMessage {
+        role: "User".to_string(),
+        content: question.to_string(),
+    });
+    let payload = turn_payload(rlm, args, session, file_context, question);
+    let result = match rlm.completion_with_context(&payload, None) {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return 1;
+        }
+    };
+
+    if let Some(code) = last_code_block(&result) {
+        session.last_code = Some(code.to_string());
+    }
+    session.history.push(ChatMessage {
+        role: "Assistant".to_string(),
+        content: result.response.clone(),
+    });
+    record_turn(args, session, session_path, &result);
+
+    match renderer {
+        _ if args.json => println!("{}", completion_json(&result)),
+        Some(renderer) => println!("{}", renderer.markdown(&result.response)),
+        None => println!("{}", result.response),
+    }
+    match result.status {
+        CompletionStatus::Answered => 0,
+        CompletionStatus::MaxIterations => {
+            eprintln!(
+                "No final answer after {} iterations; printed the last response",
+                result.iterations.len()
+            );
+            2
+        }
+    }
+}
+
+/// Opens and closes a multi-line message typed at the prompt
+const BLOCK_QUOTE: &str = "\"\"\"";
+
+/// Rest of a `\"\"\"` block whose first line (after the quotes) is
+/// `opening`, read up to the closing quotes
+fn read_block(rl: &mut DefaultEditor, opening: &str) -> Result<String, ReadlineError> {
+    let mut block = String::new();
+    let mut line = opening.to_string();
+    loop {
+        if let Some(body) = line.trim_end().strip_suffix(BLOCK_QUOTE) {
+            block.push_str(body);
+            return Ok(block);
+        }
+        block.push_str(&line);
+        block.push('\n');
+        line = rl.readline("... ")?;
+    }
+}
+
+/// A message written in `$VISUAL` or `$EDITOR` (vi if neither is set),
+/// starting from `initial`
+fn compose_in_editor(initial: &str) -> io::Result<String> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let path = std::env::temp_dir().join(format!("rlm_chat_{}.md", std::process::id()));
+    std::fs::write(&path, initial)?;
+    // Through the shell, so editors given with arguments ("code -w") work
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(&path)
+        .status();
+    let text = std::fs::read_to_string(&path);
+    let _ = std::fs::remove_file(&path);
+    if !status?.success() {
+        return Err(io::Error::other(format!("{} exited with an error", editor)));
+    }
+    text
+}
+
+/// Steps of a completion, for /details
+fn print_details(result: &RlmCompletion, renderer: Option<&Renderer>) {
+    for iteration in &result.iterations {
+        println!("── iteration {} ──", iteration.iteration);
+        if iteration.code_blocks.is_empty() {
+            let preview: String = iteration.response.chars().take(200).collect();
+            println!("{}", preview.trim());
+        }
+        for block in &iteration.code_blocks {
+            let lines = match renderer {
+                Some(renderer) => renderer.highlight(&block.code, "py"),
+                None => block.code.lines().map(String::from).collect(),
+            };
+            for line in lines {
+                println!("│ {}", line);
+            }
+            let Some(ref repl) = block.result else {
+                continue;
+            };
+            let output = match repl.error {
+                Some(ref error) => format!("error: {}", error),
+                None => repl.stdout.clone(),
+            };
+            for line in output.lines().take(10) {
+                println!("  → {}", line);
+            }
+        }
+    }
+    println!();
+}
+
+/// Copy `text` to the system clipboard
+///
+/// The clipboard handle is created on first use and kept for the session:
+/// on X11/Wayland the contents are served by this process and disappear
+/// when the handle is dropped.
+fn copy_to_clipboard(clipboard: &mut Option<Clipboard>, text: &str) -> Result<(), arboard::Error> {
+    if clipboard.is_none() {
+        *clipboard = Some(Clipboard::new()?);
+    }
+    clipboard.as_mut().unwrap().set_text(text)
+}
+
+/// Copy a value remembered from the last answer, reporting the outcome
+fn copy_command(clipboard: &mut Option<Clipboard>, text: Option<&str>, what: &str) {
+    match text {
+        Some(text) => match copy_to_clipboard(clipboard, text) {
+            Ok(()) => println!("Copied {} ({} chars).", what, text.chars().count()),
+            Err(e) => eprintln!("Failed to copy {}: {}", what, e),
+        },
+        None => println!("Nothing to copy yet: no {}.", what),
+    }
+    println!();
+}
+
+/// Show a code block and ask whether to run it (`--confirm`)
+///
+/// Anything typed other than "y" is passed to the model as the reason.
+fn confirm_code(code: &str) -> CodeApproval {
+    println!();
+    println!("┌─ Run this code? ─────────────────────────────────────────────");
+    for line in code.lines() {
+        println!("│ {}", line);
+    }
+    println!("└──────────────────────────────────────────────────────────────");
+    print!("[Enter] run, or say why not: ");
+    io::stdout().flush().unwrap();
+
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return CodeApproval::Deny("no confirmation available".to_string());
+    }
+    match answer.trim() {
+        "" | "y" | "yes" => CodeApproval::Approve,
+        reason => CodeApproval::Deny(reason.to_string()),
+    }
+}
+
 /// CLI Backend selection
 #[derive(Debug, Clone, Copy, ValueEnum, Default)]
 enum CliBackend {
@@ -62,13 +512,39 @@ enum CliBackend {
     Openai,
     /// Anthropic API (Claude)
     Anthropic,
+    /// Azure OpenAI; --backend-url is the resource endpoint
+    Azure,
 }
 
-impl From<CliBackend> for Backend {
-    fn from(cli: CliBackend) -> Self {
-        match cli {
+impl CliBackend {
+    /// Model used when none is given
+    fn default_model(self) -> &'static str {
+        match self {
+            CliBackend::Openai => "cogito:14b",
+            CliBackend::Anthropic => "claude-sonnet-4-20250514",
+            CliBackend::Azure => "gpt-4o",
+        }
+    }
+
+    /// Environment variable the API key is read from when none is given
+    fn key_env(self) -> &'static str {
+        match self {
+            CliBackend::Openai => "OPENAI_API_KEY",
+            CliBackend::Anthropic => "ANTHROPIC_API_KEY",
+            CliBackend::Azure => "AZURE_OPENAI_API_KEY",
+        }
+    }
+
+    /// Engine backend; Azure deployments are named after the model unless
+    /// --azure-deployment says otherwise
+    fn engine_backend(self, model: &str, args: &Args) -> Backend {
+        match self {
             CliBackend::Openai => Backend::OpenAI,
             CliBackend::Anthropic => Backend::Anthropic,
+            CliBackend::Azure => Backend::AzureOpenAI {
+                deployment: args.azure_deployment.clone().unwrap_or(model.to_string()),
+                api_version: args.azure_api_version.clone(),
+            },
         }
     }
 }
@@ -77,25 +553,48 @@ impl From<CliBackend> for Backend {
 #[command(name = "rlm_chat")]
 #[command(about = "Interactive chat CLI for RLM")]
 struct Args {
-    /// Model to use
-    #[arg(short, long, default_value = "cogito:14b")]
-    model: String,
+    /// Model to use [default: cogito:14b, claude-sonnet-4-20250514 for
+    /// anthropic, gpt-4o for azure]
+    #[arg(short, long)]
+    model: Option<String>,
 
-    /// Backend provider (openai or anthropic)
-    #[arg(short, long, value_enum, default_value = "openai")]
-    backend: CliBackend,
+    /// Backend provider [default: openai]
+    #[arg(short, long, value_enum)]
+    backend: Option<CliBackend>,
 
-    /// Backend LLM URL (for OpenAI-compatible backends)
-    #[arg(short = 'u', long, default_value = "http://localhost:11434/v1")]
-    backend_url: String,
+    /// Backend LLM URL (for OpenAI-compatible backends) [default: http://localhost:11434/v1]
+    #[arg(short = 'u', long)]
+    backend_url: Option<String>,
 
-    /// Backend API key (uses OPENAI_API_KEY or ANTHROPIC_API_KEY env vars if not set)
+    /// Backend API key (uses OPENAI_API_KEY, ANTHROPIC_API_KEY, or
+    /// AZURE_OPENAI_API_KEY if not set)
     #[arg(short = 'k', long)]
     backend_key: Option<String>,
 
-    /// Temperature for sampling
-    #[arg(short, long, default_value = "0.7")]
-    temperature: f32,
+    /// Model for `llm_query` sub-calls [default: --model]
+    #[arg(long, value_name = "MODEL")]
+    sub_model: Option<String>,
+
+    /// OpenAI-compatible URL for `llm_query` sub-calls, e.g. a local Ollama
+    /// [default: the root backend]
+    #[arg(long, value_name = "URL")]
+    sub_backend_url: Option<String>,
+
+    /// Azure OpenAI deployment [default: the model name]
+    #[arg(long, value_name = "NAME")]
+    azure_deployment: Option<String>,
+
+    /// Azure OpenAI API version
+    #[arg(long, value_name = "VERSION", default_value = "2024-10-21")]
+    azure_api_version: String,
+
+    /// Temperature for sampling [default: 0.7]
+    #[arg(short, long)]
+    temperature: Option<f32>,
+
+    /// Profile from ~/.config/rlm/config.toml to take defaults from
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
 
     /// Verbose mode (show full iterations)
     #[arg(short, long)]
@@ -105,19 +604,122 @@ struct Args {
     #[arg(short = 'e', long)]
     exec_log: bool,
 
+    /// Confirm each code block before it runs
+    #[arg(long)]
+    confirm: bool,
+
     /// Context file to load (large files supported)
     #[arg(short = 'c', long)]
     context_file: Option<PathBuf>,
+
+    /// Save the conversation to this JSON file after each turn
+    #[arg(short = 's', long, value_name = "PATH", conflicts_with = "resume")]
+    session: Option<PathBuf>,
+
+    /// Continue the conversation saved in this JSON file, saving back to it
+    #[arg(short = 'r', long, value_name = "SESSION")]
+    resume: Option<PathBuf>,
+
+    /// Print answers and code as they are, without markdown rendering or colors
+    #[arg(long)]
+    plain: bool,
+
+    /// Answer this question, print only the answer, and exit; piped stdin
+    /// is added to the context
+    #[arg(short = 'p', long, value_name = "QUESTION", conflicts_with = "confirm")]
+    prompt: Option<String>,
+
+    /// Print each turn's full completion (iterations, code, usage, timing)
+    /// as JSON instead of the answer
+    #[arg(long)]
+    json: bool,
+
+    /// Append each turn's full completion to this file, one JSON object
+    /// per line
+    #[arg(short = 'o', long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Write each message in $VISUAL or $EDITOR instead of at the prompt;
+    /// saving an empty message exits
+    #[arg(long, conflicts_with = "prompt")]
+    editor: bool,
+
+    /// Summarize older turns once the conversation sent to the model is
+    /// longer than this many characters (0 = never)
+    #[arg(long, value_name = "CHARS", default_value_t = 60_000)]
+    compact_at: usize,
+
+    /// Turns kept word for word when older ones are summarized
+    #[arg(long, value_name = "N", default_value_t = 4,
+          value_parser = clap::value_parser!(u32).range(1..))]
+    keep_turns: u32,
+}
+
+/// Settings from the user config file, exiting if it can't be read
+fn user_settings(profile: Option<&str>) -> UserSettings {
+    let settings = UserConfig::load_default().and_then(|config| config.settings(profile));
+    match settings {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Failed to load config: {}", e);
+            std::process::exit(1);
+        }
+    }
 }
 
 fn main() {
     let args = Args::parse();
 
-    // Load context file if provided
-    let file_context: Option<String> =
-        args.context_file
+    // Flags win over the config file, which wins over built-in defaults
+    let settings = user_settings(args.profile.as_deref());
+    let backend = match (args.backend, settings.backend.as_deref()) {
+        (Some(backend), _) => backend,
+        (None, Some(name)) => CliBackend::from_str(name, true).unwrap_or_else(|e| {
+            eprintln!("Invalid backend '{}' in config: {}", name, e);
+            std::process::exit(1);
+        }),
+        (None, None) => CliBackend::default(),
+    };
+    let model = args
+        .model
+        .clone()
+        .or(settings.model.clone())
+        .unwrap_or_else(|| backend.default_model().to_string());
+    let backend_url = args.backend_url.clone().or(settings.base_url.clone());
+    if matches!(backend, CliBackend::Azure) && backend_url.is_none() {
+        eprintln!("Azure OpenAI needs --backend-url set to the resource endpoint");
+        std::process::exit(1);
+    }
+    let backend_url = backend_url.unwrap_or_else(|| DEFAULT_BACKEND_URL.to_string());
+    let temperature = args
+        .temperature
+        .or(settings.temperature)
+        .unwrap_or(DEFAULT_TEMPERATURE);
+    let api_key = args.backend_key.clone().or_else(|| settings.api_key());
+
+    let mut session = match args.resume {
+        Some(ref path) => match ChatSession::load(path) {
+            Ok(session) => session,
+            Err(e) => {
+                eprintln!("Failed to resume session '{}': {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None => ChatSession::default(),
+    };
+    let session_path = args.resume.as_ref().or(args.session.as_ref());
+
+    // A resumed session keeps its context file unless another is given
+    if args.context_file.is_some() {
+        session.context_file = args.context_file.clone();
+    }
+
+    // Load context file if provided; PDF, DOCX, and HTML come out as text
+    let mut file_context: Option<String> =
+        session
+            .context_file
             .as_ref()
-            .map(|path| match std::fs::read_to_string(path) {
+            .map(|path| match rlm::ingest::read_document(path) {
                 Ok(content) => content,
                 Err(e) => {
                     eprintln!("Failed to read context file '{}': {}", path.display(), e);
@@ -125,57 +727,162 @@ fn main() {
                 }
             });
 
+    // One-shot questions take piped input as context
+    if args.prompt.is_some() && !io::stdin().is_terminal() {
+        let mut input = String::new();
+        if let Err(e) = io::stdin().read_to_string(&mut input) {
+            eprintln!("Failed to read stdin: {}", e);
+            std::process::exit(1);
+        }
+        file_context = Some(match file_context {
+            Some(file) => format!("{}\n\n{}", file, input),
+            None => input,
+        });
+    }
+
+    // Render markdown and highlight code, unless asked not to or piped
+    let renderer = (!args.plain && io::stdout().is_terminal()).then(|| Arc::new(Renderer::new()));
+
     // Configure RLM
-    let mut config = RlmConfig::new(&args.model)
-        .with_max_iterations(50)
+    let mut config = RlmConfig::new(&model)
+        .with_max_iterations(settings.max_iterations.unwrap_or(DEFAULT_MAX_ITERATIONS))
         .with_max_exec_retries(3)
-        .with_temperature(args.temperature)
+        .with_temperature(temperature)
         .with_verbose(args.verbose)
         .with_exec_log(args.exec_log)
-        .with_backend(args.backend.into());
+        .with_backend(backend.engine_backend(&model, &args));
 
     // Set base URL for OpenAI-compatible backends
-    if matches!(args.backend, CliBackend::Openai) {
-        config = config.with_base_url(&args.backend_url);
+    if matches!(backend, CliBackend::Openai | CliBackend::Azure) {
+        config = config.with_base_url(&backend_url);
+    }
+
+    if args.confirm {
+        config = config.with_approval_hook(confirm_code);
+    }
+
+    if args.prompt.is_some() {
+        // Keep stdout for the answer
+        config = config.with_log_sink(StderrSink);
+    } else if let (true, Some(renderer)) = (args.verbose, &renderer) {
+        config = config.with_log_sink(HighlightSink::new(renderer.clone()));
     }
 
     // Set API key if provided
-    if let Some(ref key) = args.backend_key {
+    if let Some(ref key) = api_key {
         config = config.with_api_key(key);
     }
 
+    // Sub-calls on a model and endpoint of their own
+    if let Some(ref sub_model) = args.sub_model {
+        config = config.with_sub_model(sub_model);
+    }
+    if let Some(ref url) = args.sub_backend_url {
+        match OpenAiBackend::from_parts(Some(url), None)
+            .and_then(|sub| sub.with_timeouts(config.timeouts))
+        {
+            Ok(sub) => config = config.with_sub_backend(Backend::Custom(Arc::new(sub))),
+            Err(e) => {
+                eprintln!("Error: sub-call backend: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Show a live view unless the run is logged or interrupted by prompts
+    let live_view = !args.verbose
+        && !args.exec_log
+        && !args.confirm
+        && args.prompt.is_none()
+        && io::stdout().is_terminal();
+    let live_status = Arc::new(Mutex::new(LiveStatus::default()));
+
     // Create RLM instance
     let rlm = match Rlm::new(config) {
         Ok(r) => r,
         Err(e) => {
             eprintln!("Failed to create RLM: {}", e);
-            match args.backend {
-                CliBackend::Openai => eprintln!("Make sure the backend is running at {}", args.backend_url),
-                CliBackend::Anthropic => eprintln!("Make sure ANTHROPIC_API_KEY is set or use -k"),
+            match backend {
+                CliBackend::Openai => {
+                    eprintln!("Make sure the backend is running at {}", backend_url)
+                }
+                CliBackend::Anthropic | CliBackend::Azure => {
+                    eprintln!("Make sure {} is set or use -k", backend.key_env())
+                }
             }
             std::process::exit(1);
         }
     };
+    let rlm = if live_view {
+        let status = live_status.clone();
+        rlm.with_event_stream(move |event| status.lock().unwrap().update(event))
+    } else {
+        rlm
+    };
+
+    if args.prompt.is_some() {
+        let code = run_once(
+            &rlm,
+            &args,
+            &mut session,
+            session_path,
+            file_context.as_deref(),
+            renderer.as_deref(),
+        );
+        std::process::exit(code);
+    }
 
     println!("╔══════════════════════════════════════════════════════════════╗");
     println!("║                        RLM Chat                              ║");
     println!("╚══════════════════════════════════════════════════════════════╝");
     println!();
-    println!("Model:   {}", args.model);
-    match args.backend {
-        CliBackend::Openai => println!("Backend: OpenAI @ {}", args.backend_url),
+    println!("Model:   {}", model);
+    match backend {
+        CliBackend::Openai => println!("Backend: OpenAI @ {}", backend_url),
         CliBackend::Anthropic => println!("Backend: Anthropic"),
+        CliBackend::Azure => println!("Backend: Azure OpenAI @ {}", backend_url),
+    }
+    if args.sub_model.is_some() || args.sub_backend_url.is_some() {
+        println!(
+            "Sub-calls: {} @ {}",
+            args.sub_model.as_deref().unwrap_or(&model),
+            args.sub_backend_url.as_deref().unwrap_or("root backend")
+        );
+    }
+    if let Some(ref profile) = args.profile {
+        println!("Profile: {}", profile);
     }
-    if let Some(ref path) = args.context_file {
+    if let Some(ref path) = session.context_file {
         let size = file_context.as_ref().map(|c| c.len()).unwrap_or(0);
         println!("Context: {} ({} bytes)", path.display(), size);
     }
+    if let Some(path) = session_path {
+        println!("Session: {}", path.display());
+    }
+    if !session.history.is_empty() {
+        println!();
+        println!(
+            "Resumed a conversation of {} messages.",
+            session.history.len()
+        );
+        if let Some(answer) = session.last_answer() {
+            let preview: String = answer.chars().take(200).collect();
+            println!("Last answer: {}", preview.replace('\n', " "));
+        }
+    }
     println!();
     println!("Type your message and press Enter. Use Ctrl+C or Ctrl+D to exit.");
+    println!("Commands: /copy (last answer), /copy-code (last executed REPL block),");
+    println!("          /details (steps of the last answer), /retry (run the last message again),");
+    println!("          /edit (write a message in $EDITOR),");
+    println!("          /edit-last (change the last message in $EDITOR and run it again)");
+    println!("Multi-line: Alt+Enter for a new line, or wrap the message in \"\"\" ... \"\"\"");
     println!();
 
-    // Chat history
-    let mut history: Vec<ChatMessage> = Vec::new();
+    let mut clipboard: Option<Clipboard> = None;
+    let mut last_result: Option<RlmCompletion> = None;
+    // Message of a turn that failed; it isn't in the history
+    let mut failed_query: Option<String> = None;
 
     // Setup readline
     let mut rl = match DefaultEditor::new() {
@@ -185,53 +892,157 @@ fn main() {
             std::process::exit(1);
         }
     };
+    rl.bind_sequence(KeyEvent(KeyCode::Enter, Modifiers::ALT), Cmd::Newline);
 
     loop {
-        let readline = rl.readline("You: ");
+        let readline = if args.editor {
+            compose_in_editor("").map_err(ReadlineError::Io)
+        } else {
+            rl.readline("You: ")
+        };
 
         match readline {
             Ok(line) => {
+                let mut echo = args.editor;
+                let line = match line.trim_start().strip_prefix(BLOCK_QUOTE) {
+                    Some(opening) => match read_block(&mut rl, opening) {
+                        Ok(block) => block,
+                        Err(_) => {
+                            println!("(message discarded)\n");
+                            continue;
+                        }
+                    },
+                    None if line.trim() == "/edit" => match compose_in_editor("") {
+                        Ok(text) => {
+                            echo = true;
+                            text
+                        }
+                        Err(e) => {
+                            eprintln!("Failed to open editor: {}\n", e);
+                            continue;
+                        }
+                    },
+                    None if matches!(line.trim(), "/retry" | "/edit-last") => {
+                        let previous = match failed_query {
+                            Some(ref query) => Some(query.clone()),
+                            None => session.last_question().map(String::from),
+                        };
+                        let message = match (line.trim(), previous) {
+                            ("/retry", None) => {
+                                println!("Nothing to retry.\n");
+                                continue;
+                            }
+                            ("/retry", Some(query)) => query,
+                            (_, previous) => {
+                                match compose_in_editor(previous.as_deref().unwrap_or("")) {
+                                    Ok(text) if text.trim().is_empty() => {
+                                        println!("(message discarded)\n");
+                                        continue;
+                                    }
+                                    Ok(text) => text,
+                                    Err(e) => {
+                                        eprintln!("Failed to open editor: {}\n", e);
+                                        continue;
+                                    }
+                                }
+                            }
+                        };
+                        // The new run replaces the last turn
+                        if failed_query.take().is_none() {
+                            session.pop_exchange();
+                        }
+                        echo = true;
+                        message
+                    }
+                    None => line,
+                };
                 let input = line.trim();
                 if input.is_empty() {
+                    if args.editor {
+                        println!("Goodbye!");
+                        break;
+                    }
                     continue;
                 }
+                if echo {
+                    println!("You: {}", input);
+                }
 
                 // Add to readline history
                 let _ = rl.add_history_entry(input);
 
+                match input {
+                    "/copy" => {
+                        copy_command(&mut clipboard, session.last_answer(), "answer");
+                        continue;
+                    }
+                    "/copy-code" => {
+                        let last_code = session.last_code.as_deref();
+                        copy_command(&mut clipboard, last_code, "code block");
+                        continue;
+                    }
+                    "/details" => {
+                        match last_result {
+                            Some(ref result) => print_details(result, renderer.as_deref()),
+                            None => println!("No answer yet.\n"),
+                        }
+                        continue;
+                    }
+                    _ => {}
+                }
+
                 // Add user message to chat history
-                history.push(ChatMessage {
-                    role: "User",
+                session.history.push(ChatMessage {
+                    role: "User".to_string(),
                     content: input.to_string(),
                 });
 
                 // Build context payload - EVERYTHING goes into context (RLM inference strategy)
-                let context_payload = build_context_payload(
+                let context_payload = turn_payload(
+                    &rlm,
+                    &args,
+                    &mut session,
                     file_context.as_deref(),
-                    &history,
                     input, // Current query
                 );
 
-                if !args.verbose {
+                if !args.verbose && !args.json && !live_view {
                     print!("Assistant: ");
                     io::stdout().flush().unwrap();
                 }
 
                 // Run completion - context_payload goes into REPL `context` variable
-                match rlm.completion_with_context(&context_payload, None) {
+                let completion = if live_view {
+                    completion_with_live_view(&rlm, &live_status, &context_payload)
+                } else {
+                    rlm.completion_with_context(&context_payload, None)
+                };
+                match completion {
                     Ok(result) => {
+                        failed_query = None;
+                        if let Some(code) = last_code_block(&result) {
+                            session.last_code = Some(code.to_string());
+                        }
+
                         // Add assistant response to history
-                        history.push(ChatMessage {
-                            role: "Assistant",
+                        session.history.push(ChatMessage {
+                            role: "Assistant".to_string(),
                             content: result.response.clone(),
                         });
+                        record_turn(&args, &session, session_path, &result);
 
-                        if args.verbose {
+                        let answer = match renderer {
+                            Some(ref renderer) => renderer.markdown(&result.response),
+                            None => result.response.clone(),
+                        };
+                        if args.json {
+                            println!("{}", completion_json(&result));
+                        } else if args.verbose {
                             println!();
                             println!(
                                 "─────────────────────────────────────────────────────────────"
                             );
-                            println!("Assistant: {}", result.response);
+                            println!("Assistant: {}", answer);
                             println!(
                                 "─────────────────────────────────────────────────────────────"
                             );
@@ -241,15 +1052,27 @@ fn main() {
                                 result.usage.total_tokens,
                                 result.execution_time
                             );
+                        } else if live_view {
+                            // Collapsed: the answer and one line on how it came about
+                            println!("Assistant: {}", answer);
+                            println!(
+                                "  ({} iterations, {} sub-queries, {:.1}s · /details to expand)",
+                                result.iterations.len(),
+                                live_status.lock().unwrap().sub_queries,
+                                result.execution_time.as_secs_f64()
+                            );
                         } else {
-                            println!("{}", result.response);
+                            println!("{}", answer);
                         }
                         println!();
+                        last_result = Some(result);
                     }
                     Err(e) => {
                         eprintln!("\nError: {}", e);
-                        // Remove the failed user message from history
-                        history.pop();
+                        // Remove the failed user message from history; /retry
+                        // picks it up from here
+                        session.history.pop();
+                        failed_query = Some(input.to_string());
                         println!();
                     }
                 }
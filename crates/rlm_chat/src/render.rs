@@ -0,0 +1,401 @@
+//! Terminal rendering of answers and code
+//!
+//! Answers are markdown: headings, lists, tables, and code come out styled
+//! with ANSI escapes instead of as raw markup. Code is highlighted with
+//! syntect, both in answers and in the verbose iteration log. `--plain`
+//! turns all of it off.
+
+use pulldown_cmark::{Alignment, CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+use rlm::LogSink;
+use std::io::Write;
+use std::sync::Arc;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::parsing::SyntaxSet;
+use syntect::util::{as_24_bit_terminal_escaped, LinesWithEndings};
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const ITALIC: &str = "\x1b[3m";
+const UNDERLINE: &str = "\x1b[4m";
+const STRIKE: &str = "\x1b[9m";
+const CYAN: &str = "\x1b[36m";
+const YELLOW: &str = "\x1b[33m";
+
+/// Syntax definitions and the color theme, loaded once per session
+pub struct Renderer {
+    syntaxes: SyntaxSet,
+    theme: Theme,
+}
+
+impl Renderer {
+    pub fn new() -> Self {
+        let mut themes = ThemeSet::load_defaults();
+        Self {
+            syntaxes: SyntaxSet::load_defaults_newlines(),
+            theme: themes
+                .themes
+                .remove("base16-ocean.dark")
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Lines of `code` colored as language `lang` (a name or extension)
+    ///
+    /// Unknown languages come back uncolored.
+    pub fn highlight(&self, code: &str, lang: &str) -> Vec<String> {
+        let Some(syntax) = self
+            .syntaxes
+            .find_syntax_by_token(lang)
+            .filter(|_| !lang.is_empty())
+        else {
+            return code.lines().map(String::from).collect();
+        };
+        let mut highlighter = HighlightLines::new(syntax, &self.theme);
+        LinesWithEndings::from(code)
+            .map(
+                |line| match highlighter.highlight_line(line, &self.syntaxes) {
+                    Ok(ranges) => {
+                        let escaped = as_24_bit_terminal_escaped(&ranges, false);
+                        format!("{}{}", escaped.trim_end_matches(['\n', '\r']), RESET)
+                    }
+                    Err(_) => line.trim_end_matches(['\n', '\r']).to_string(),
+                },
+            )
+            .collect()
+    }
+
+    /// `text` as styled terminal output
+    pub fn markdown(&self, text: &str) -> String {
+        let options =
+            Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+        let mut out = MarkdownWriter::new(self);
+        for event in Parser::new_ext(text, options) {
+            out.event(event);
+        }
+        out.finish()
+    }
+}
+
+/// Table being collected; cells are plain text, so widths line up
+struct Table {
+    alignments: Vec<Alignment>,
+    rows: Vec<Vec<String>>,
+}
+
+/// State of one markdown-to-terminal conversion
+struct MarkdownWriter<'a> {
+    renderer: &'a Renderer,
+    out: String,
+    /// Active styles, re-applied after each reset
+    styles: Vec<&'static str>,
+    /// One entry per open list: the next number, or None for bullets
+    lists: Vec<Option<u64>>,
+    quote_depth: usize,
+    /// Language and text of the code block being collected
+    code: Option<(String, String)>,
+    table: Option<Table>,
+    link: Option<String>,
+    at_line_start: bool,
+}
+
+impl<'a> MarkdownWriter<'a> {
+    fn new(renderer: &'a Renderer) -> Self {
+        Self {
+            renderer,
+            out: String::new(),
+            styles: Vec::new(),
+            lists: Vec::new(),
+            quote_depth: 0,
+            code: None,
+            table: None,
+            link: None,
+            at_line_start: true,
+        }
+    }
+
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) => self.text(&text),
+            Event::Code(code) => {
+                if let Some(ref mut table) = self.table {
+                    push_cell_text(table, &code);
+                } else {
+                    self.push_style(YELLOW);
+                    self.write(&code);
+                    self.pop_style();
+                }
+            }
+            Event::SoftBreak => self.text(" "),
+            Event::HardBreak => self.newline(),
+            Event::Rule => {
+                self.write(&format!("{}{}{}", DIM, "─".repeat(40), RESET));
+                self.block_end();
+            }
+            Event::TaskListMarker(done) => self.write(if done { "[x] " } else { "[ ] " }),
+            Event::Html(html) | Event::InlineHtml(html) => self.text(&html),
+            _ => {}
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Heading { level, .. } => {
+                self.push_style(BOLD);
+                self.push_style(CYAN);
+                if level == HeadingLevel::H1 {
+                    self.push_style(UNDERLINE);
+                }
+            }
+            Tag::BlockQuote(_) => self.quote_depth += 1,
+            Tag::CodeBlock(kind) => {
+                let lang = match kind {
+                    CodeBlockKind::Fenced(info) => {
+                        info.split_whitespace().next().unwrap_or("").to_string()
+                    }
+                    CodeBlockKind::Indented => String::new(),
+                };
+                self.code = Some((lang, String::new()));
+            }
+            Tag::List(first) => {
+                if self.lists.is_empty() && !self.at_line_start {
+                    self.newline();
+                }
+                self.lists.push(first);
+            }
+            Tag::Item => {
+                let depth = self.lists.len().saturating_sub(1);
+                let marker = match self.lists.last_mut() {
+                    Some(Some(n)) => {
+                        *n += 1;
+                        format!("{}.", *n - 1)
+                    }
+                    _ => "•".to_string(),
+                };
+                if !self.at_line_start {
+                    self.newline();
+                }
+                self.write(&format!("{}{} ", "  ".repeat(depth), marker));
+            }
+            Tag::Emphasis => self.push_style(ITALIC),
+            Tag::Strong => self.push_style(BOLD),
+            Tag::Strikethrough => self.push_style(STRIKE),
+            Tag::Link { dest_url, .. } => {
+                self.link = Some(dest_url.to_string());
+                self.push_style(UNDERLINE);
+            }
+            Tag::Table(alignments) => {
+                self.table = Some(Table {
+                    alignments,
+                    rows: Vec::new(),
+                })
+            }
+            Tag::TableHead | Tag::TableRow => {
+                if let Some(ref mut table) = self.table {
+                    table.rows.push(Vec::new());
+                }
+            }
+            Tag::TableCell => {
+                if let Some(row) = self.table.as_mut().and_then(|t| t.rows.last_mut()) {
+                    row.push(String::new());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        match tag {
+            TagEnd::Heading(level) => {
+                self.pop_style();
+                self.pop_style();
+                if level == HeadingLevel::H1 {
+                    self.pop_style();
+                }
+                self.block_end();
+            }
+            // Paragraphs in list items stay tight
+            TagEnd::Paragraph if self.lists.is_empty() => self.block_end(),
+            TagEnd::BlockQuote(_) => self.quote_depth = self.quote_depth.saturating_sub(1),
+            TagEnd::CodeBlock => {
+                let Some((lang, code)) = self.code.take() else {
+                    return;
+                };
+                if !self.at_line_start {
+                    self.newline();
+                }
+                for line in self.renderer.highlight(&code, &lang) {
+                    self.write(&format!("{}│{} ", DIM, RESET));
+                    self.write(&line);
+                    self.newline();
+                }
+                self.newline();
+            }
+            TagEnd::List(_) => {
+                self.lists.pop();
+                if !self.at_line_start {
+                    self.newline();
+                }
+                if self.lists.is_empty() {
+                    self.newline();
+                }
+            }
+            TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough => self.pop_style(),
+            TagEnd::Link => {
+                self.pop_style();
+                match (self.link.take(), self.table.as_mut()) {
+                    (Some(url), Some(table)) => push_cell_text(table, &format!(" ({})", url)),
+                    (Some(url), None) => self.write(&format!(" {}({}){}", DIM, url, RESET)),
+                    (None, _) => {}
+                }
+            }
+            TagEnd::Table => {
+                if let Some(table) = self.table.take() {
+                    self.table(&table);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        if let Some((_, ref mut code)) = self.code {
+            code.push_str(text);
+        } else if let Some(ref mut table) = self.table {
+            push_cell_text(table, text);
+        } else {
+            self.write(text);
+        }
+    }
+
+    /// Append `text`, prefixing new lines with the block quote bar
+    fn write(&mut self, text: &str) {
+        for (i, part) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.newline();
+            }
+            if part.is_empty() {
+                continue;
+            }
+            if self.at_line_start && self.quote_depth > 0 {
+                let bar = format!("{}▌{} ", DIM, RESET).repeat(self.quote_depth);
+                self.out.push_str(&bar);
+                self.out.push_str(&self.styles.concat());
+            }
+            self.out.push_str(part);
+            self.at_line_start = false;
+        }
+    }
+
+    fn newline(&mut self) {
+        self.out.push('\n');
+        self.at_line_start = true;
+    }
+
+    /// End a block, leaving an empty line after it
+    fn block_end(&mut self) {
+        if !self.at_line_start {
+            self.newline();
+        }
+        self.newline();
+    }
+
+    fn push_style(&mut self, style: &'static str) {
+        self.styles.push(style);
+        if self.table.is_none() {
+            self.out.push_str(style);
+        }
+    }
+
+    fn pop_style(&mut self) {
+        self.styles.pop();
+        if self.table.is_none() {
+            self.out.push_str(RESET);
+            self.out.push_str(&self.styles.concat());
+        }
+    }
+
+    /// Draw `table` with box lines, the header row in bold
+    fn table(&mut self, table: &Table) {
+        let columns = table.rows.iter().map(Vec::len).max().unwrap_or(0);
+        let widths: Vec<usize> = (0..columns)
+            .map(|col| {
+                table
+                    .rows
+                    .iter()
+                    .filter_map(|row| row.get(col))
+                    .map(|cell| cell.chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        for (i, row) in table.rows.iter().enumerate() {
+            let cells: Vec<String> = widths
+                .iter()
+                .enumerate()
+                .map(|(col, &width)| {
+                    let cell = row.get(col).map(String::as_str).unwrap_or("");
+                    let cell = match table.alignments.get(col) {
+                        Some(Alignment::Right) => format!("{:>width$}", cell),
+                        Some(Alignment::Center) => format!("{:^width$}", cell),
+                        _ => format!("{:<width$}", cell),
+                    };
+                    if i == 0 {
+                        format!("{}{}{}", BOLD, cell, RESET)
+                    } else {
+                        cell
+                    }
+                })
+                .collect();
+            self.write(&cells.join(" │ "));
+            self.newline();
+            if i == 0 {
+                let rule: Vec<String> = widths.iter().map(|&w| "─".repeat(w)).collect();
+                self.write(&rule.join("─┼─"));
+                self.newline();
+            }
+        }
+        self.newline();
+    }
+
+    fn finish(mut self) -> String {
+        let trimmed = self.out.trim_end().len();
+        self.out.truncate(trimmed);
+        self.out.push_str(RESET);
+        self.out
+    }
+}
+
+fn push_cell_text(table: &mut Table, text: &str) {
+    if let Some(cell) = table.rows.last_mut().and_then(|row| row.last_mut()) {
+        cell.push_str(text);
+    }
+}
+
+/// Log sink for verbose mode that highlights the code the REPL runs
+pub struct HighlightSink {
+    renderer: Arc<Renderer>,
+}
+
+impl HighlightSink {
+    pub fn new(renderer: Arc<Renderer>) -> Self {
+        Self { renderer }
+    }
+}
+
+impl LogSink for HighlightSink {
+    fn log(&self, line: &str) {
+        let mut out = std::io::stdout().lock();
+        let _ = writeln!(out, "{}", line);
+        let _ = out.flush();
+    }
+
+    fn log_code(&self, code: &str) {
+        for line in self.renderer.highlight(code, "py") {
+            self.log(&format!("│ {}", line));
+        }
+    }
+}
@@ -0,0 +1,418 @@
+//! Final-answer detection
+//!
+//! After every iteration the RLM loop asks a chain of [`AnswerDetector`]s
+//! whether the model has finished. The first detector returning an answer
+//! ends the run. The default chain recognizes `llm_output(...)`, the
+//! `FINAL_ANSWER: ` stdout prefix, `FINAL(...)` / `FINAL_VAR(...)`, and
+//! `FINAL_JSON(...)` in the response text; custom detectors can add other
+//! termination signals. The stdout prefixes and text markers come from the
+//! run's [`ParserConfig`].
+//!
+//! An [`AnswerFormat`] other than text makes the loop check the answer's
+//! shape, sending the model back to fix a malformed one.
+
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::error::{Result, RlmError};
+use crate::parsing::{extract_final_json, ParserConfig};
+use crate::types::{CodeBlock, ReplResult};
+
+/// Everything a detector can inspect for one iteration
+#[derive(Debug, Clone, Copy)]
+pub struct AnswerContext<'a> {
+    /// Raw model response for this iteration
+    pub response: &'a str,
+    /// Code blocks executed this iteration, in order
+    pub code_blocks: &'a [CodeBlock],
+    /// REPL variables after execution (`repr` strings)
+    pub locals: &'a HashMap<String, String>,
+    /// Fences and answer markers in effect
+    pub parser: &'a ParserConfig,
+}
+
+impl<'a> AnswerContext<'a> {
+    /// Execution results of this iteration's code blocks
+    pub fn results(&self) -> impl Iterator<Item = &'a ReplResult> {
+        self.code_blocks.iter().filter_map(|b| b.result.as_ref())
+    }
+}
+
+/// A termination signal for the RLM loop
+pub trait AnswerDetector: Send + Sync {
+    /// Return the final answer if this iteration signals completion
+    fn detect(&self, ctx: &AnswerContext<'_>) -> Option<String>;
+
+    /// Name shown in debug output
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+}
+
+impl fmt::Debug for dyn AnswerDetector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// `llm_output(answer)` called from REPL code
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LlmOutputDetector;
+
+impl AnswerDetector for LlmOutputDetector {
+    fn detect(&self, ctx: &AnswerContext<'_>) -> Option<String> {
+        ctx.results().find_map(|r| r.llm_output.clone())
+    }
+
+    fn name(&self) -> &str {
+        "llm_output"
+    }
+}
+
+/// A stdout line starting with a sentinel, e.g. `FINAL_ANSWER: `
+///
+/// The rest of the line is the answer.
+#[derive(Debug, Clone)]
+pub struct StdoutPrefixDetector {
+    prefix: String,
+}
+
+impl StdoutPrefixDetector {
+    pub fn new(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+        }
+    }
+}
+
+impl AnswerDetector for StdoutPrefixDetector {
+    fn detect(&self, ctx: &AnswerContext<'_>) -> Option<String> {
+        ctx.results()
+            .flat_map(|r| r.stdout.lines())
+            .find_map(|line| line.strip_prefix(self.prefix.as_str()))
+            .map(str::to_string)
+    }
+
+    fn name(&self) -> &str {
+        &self.prefix
+    }
+}
+
+/// A stdout line starting with one of the parser's sentinels
+/// (`FINAL_ANSWER: ` by default)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdoutSentinelDetector;
+
+impl AnswerDetector for StdoutSentinelDetector {
+    fn detect(&self, ctx: &AnswerContext<'_>) -> Option<String> {
+        ctx.results()
+            .find_map(|r| ctx.parser.extract_stdout_answer(&r.stdout))
+    }
+
+    fn name(&self) -> &str {
+        "stdout sentinel"
+    }
+}
+
+/// `FINAL(answer)` or `FINAL_VAR(name)` in the response text, or the
+/// parser's other answer markers (`<answer>` tags and a line-leading
+/// `ANSWER:` by default)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FinalPatternDetector;
+
+impl AnswerDetector for FinalPatternDetector {
+    fn detect(&self, ctx: &AnswerContext<'_>) -> Option<String> {
+        ctx.parser.extract_answer(ctx.response, ctx.locals)
+    }
+
+    fn name(&self) -> &str {
+        "FINAL(...)"
+    }
+}
+
+/// `FINAL_JSON({...})` or a lone ```json block in the response text
+///
+/// The answer is the JSON, compacted.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FinalJsonDetector;
+
+impl AnswerDetector for FinalJsonDetector {
+    fn detect(&self, ctx: &AnswerContext<'_>) -> Option<String> {
+        extract_final_json(ctx.response).map(|value| value.to_string())
+    }
+
+    fn name(&self) -> &str {
+        "FINAL_JSON(...)"
+    }
+}
+
+/// A stdout line that is a JSON object containing `key`
+///
+/// String values are returned as-is, anything else as JSON text.
+#[derive(Debug, Clone)]
+pub struct JsonKeyDetector {
+    key: String,
+}
+
+impl JsonKeyDetector {
+    pub fn new(key: impl Into<String>) -> Self {
+        Self { key: key.into() }
+    }
+}
+
+impl AnswerDetector for JsonKeyDetector {
+    fn detect(&self, ctx: &AnswerContext<'_>) -> Option<String> {
+        ctx.results()
+            .flat_map(|r| r.stdout.lines())
+            .find_map(|line| {
+                let value: serde_json::Value = serde_json::from_str(line.trim()).ok()?;
+                match value.get(&self.key)? {
+                    serde_json::Value::String(s) => Some(s.clone()),
+                    other => Some(other.to_string()),
+                }
+            })
+    }
+
+    fn name(&self) -> &str {
+        &self.key
+    }
+}
+
+/// The built-in detector chain, in priority order
+pub fn default_detectors() -> Vec<Arc<dyn AnswerDetector>> {
+    vec![
+        Arc::new(LlmOutputDetector),
+        Arc::new(StdoutSentinelDetector),
+        Arc::new(FinalPatternDetector),
+        Arc::new(FinalJsonDetector),
+    ]
+}
+
+/// Shape the final answer must have
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum AnswerFormat {
+    /// Anything; an answer that is JSON still fills `response_json`
+    #[default]
+    Text,
+    /// A JSON object or array
+    Json,
+    /// JSON valid against this JSON Schema
+    JsonSchema(Value),
+}
+
+/// Up to `limit` ways `value` doesn't match the JSON Schema `schema`, each
+/// prefixed with where in `value` it is; Err if the schema is invalid
+///
+/// The one validator for answers and agent tool arguments.
+pub fn schema_errors(
+    schema: &Value,
+    value: &Value,
+    limit: usize,
+) -> std::result::Result<Vec<String>, String> {
+    let validator = jsonschema::validator_for(schema).map_err(|e| e.to_string())?;
+    Ok(validator
+        .iter_errors(value)
+        .take(limit)
+        .map(|e| match e.instance_path.to_string() {
+            path if path.is_empty() => e.to_string(),
+            path => format!("{}: {}", path, e),
+        })
+        .collect())
+}
+
+impl AnswerFormat {
+    /// Fail if the JSON Schema itself is invalid
+    pub fn check_schema(&self) -> Result<()> {
+        if let AnswerFormat::JsonSchema(ref schema) = self {
+            jsonschema::validator_for(schema)
+                .map_err(|e| RlmError::Config(format!("invalid JSON schema: {}", e)))?;
+        }
+        Ok(())
+    }
+
+    /// Why an answer parsed to `json` (None if it isn't JSON) doesn't have
+    /// this format, to show the model
+    pub fn check(&self, json: Option<&Value>) -> std::result::Result<(), String> {
+        let schema = match self {
+            AnswerFormat::Text => return Ok(()),
+            AnswerFormat::Json => None,
+            AnswerFormat::JsonSchema(schema) => Some(schema),
+        };
+        let Some(json) = json else {
+            return Err("the final answer must be a JSON object or array".to_string());
+        };
+        let Some(schema) = schema else {
+            return Ok(());
+        };
+        let errors = schema_errors(schema, json, 5)?;
+        if errors.is_empty() {
+            return Ok(());
+        }
+        Err(format!(
+            "the final answer doesn't match the JSON schema:\n- {}",
+            errors.join("\n- ")
+        ))
+    }
+
+    /// What the system prompt tells the model about the answer's shape
+    pub fn instructions(&self) -> Option<String> {
+        let json = "The final answer must be JSON (an object or array). Give it as \
+                    FINAL_JSON({...}) in your response, or pass the JSON text to \
+                    llm_output() from code.";
+        match self {
+            AnswerFormat::Text => None,
+            AnswerFormat::Json => Some(json.to_string()),
+            AnswerFormat::JsonSchema(schema) => Some(format!(
+                "{} It must match this JSON Schema:\n```json\n{}\n```",
+                json,
+                serde_json::to_string_pretty(schema).unwrap_or_default()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(stdout: &str, llm_output: Option<&str>) -> CodeBlock {
+        let mut result =
+            ReplResult::success(stdout.to_string(), HashMap::new(), Default::default());
+        result.llm_output = llm_output.map(str::to_string);
+        CodeBlock {
+            code: String::new(),
+            result: Some(result),
+            retry_count: 0,
+        }
+    }
+
+    fn detect_with(
+        detectors: &[Arc<dyn AnswerDetector>],
+        response: &str,
+        blocks: &[CodeBlock],
+    ) -> Option<String> {
+        detect_with_parser(detectors, response, blocks, &ParserConfig::default())
+    }
+
+    fn detect_with_parser(
+        detectors: &[Arc<dyn AnswerDetector>],
+        response: &str,
+        blocks: &[CodeBlock],
+        parser: &ParserConfig,
+    ) -> Option<String> {
+        let locals = HashMap::new();
+        let ctx = AnswerContext {
+            response,
+            code_blocks: blocks,
+            locals: &locals,
+            parser,
+        };
+        detectors.iter().find_map(|d| d.detect(&ctx))
+    }
+
+    #[test]
+    fn test_default_chain_priority() {
+        let detectors = default_detectors();
+        let blocks = [block("FINAL_ANSWER: from stdout", Some("from llm_output"))];
+        assert_eq!(
+            detect_with(&detectors, "FINAL(from text)", &blocks).as_deref(),
+            Some("from llm_output")
+        );
+
+        let blocks = [block("FINAL_ANSWER: from stdout", None)];
+        assert_eq!(
+            detect_with(&detectors, "FINAL(from text)", &blocks).as_deref(),
+            Some("from stdout")
+        );
+
+        assert_eq!(
+            detect_with(&detectors, "FINAL(from text)", &[]).as_deref(),
+            Some("from text")
+        );
+        assert_eq!(
+            detect_with(&detectors, "FINAL_JSON({\"a\": [1, 2]})", &[]).as_deref(),
+            Some("{\"a\":[1,2]}")
+        );
+        assert_eq!(
+            detect_with(&detectors, "<answer>42</answer><done>", &[]).as_deref(),
+            Some("42")
+        );
+        assert_eq!(
+            detect_with(&detectors, "Checked.\nANSWER: 42", &[]).as_deref(),
+            Some("42")
+        );
+        assert_eq!(detect_with(&detectors, "still working", &[]), None);
+    }
+
+    #[test]
+    fn test_parser_markers() {
+        let detectors = default_detectors();
+        let parser = ParserConfig::default()
+            .with_final_marker("ANSWER")
+            .with_answer_tag("result")
+            .with_stdout_sentinel("DONE=");
+
+        let blocks = [block("DONE=7", None)];
+        assert_eq!(
+            detect_with_parser(&detectors, "", &blocks, &parser).as_deref(),
+            Some("7")
+        );
+        assert_eq!(
+            detect_with_parser(&detectors, "So: ANSWER(7)", &[], &parser).as_deref(),
+            Some("7")
+        );
+        assert_eq!(
+            detect_with_parser(&detectors, "<result>\n7\n</result>", &[], &parser).as_deref(),
+            Some("7")
+        );
+        // Not recognized without the config
+        assert_eq!(detect_with(&detectors, "<result>7</result>", &blocks), None);
+    }
+
+    #[test]
+    fn test_custom_detectors() {
+        let detectors: Vec<Arc<dyn AnswerDetector>> = vec![
+            Arc::new(StdoutPrefixDetector::new("<<DONE>>")),
+            Arc::new(JsonKeyDetector::new("answer")),
+        ];
+
+        let blocks = [block("working\n<<DONE>>42", None)];
+        assert_eq!(detect_with(&detectors, "", &blocks).as_deref(), Some("42"));
+
+        let blocks = [block("{\"answer\": \"Paris\"}", None)];
+        assert_eq!(
+            detect_with(&detectors, "", &blocks).as_deref(),
+            Some("Paris")
+        );
+
+        let blocks = [block("{\"answer\": [1, 2]}", None)];
+        assert_eq!(
+            detect_with(&detectors, "", &blocks).as_deref(),
+            Some("[1,2]")
+        );
+    }
+
+    #[test]
+    fn test_answer_format_check() {
+        let answer = serde_json::json!({"city": "Paris", "population": "2M"});
+        assert!(AnswerFormat::Text.check(None).is_ok());
+        assert!(AnswerFormat::Json.check(Some(&answer)).is_ok());
+        assert!(AnswerFormat::Json.check(None).is_err());
+
+        let schema = AnswerFormat::JsonSchema(serde_json::json!({
+            "type": "object",
+            "properties": {"population": {"type": "integer"}},
+            "required": ["city", "population"]
+        }));
+        assert!(schema.check_schema().is_ok());
+        let error = schema.check(Some(&answer)).unwrap_err();
+        assert!(error.contains("/population"), "{}", error);
+        let fixed = serde_json::json!({"city": "Paris", "population": 2_100_000});
+        assert!(schema.check(Some(&fixed)).is_ok());
+
+        let invalid = AnswerFormat::JsonSchema(serde_json::json!({"type": 5}));
+        assert!(matches!(invalid.check_schema(), Err(RlmError::Config(_))));
+    }
+}
@@ -0,0 +1,39 @@
+//! Human-in-the-loop approval of code blocks
+//!
+//! A [`CodeApprovalHook`] set with `RlmConfig::with_approval_hook` sees every
+//! code block, fix rounds included, right before it runs. It can let the
+//! block run, refuse it with a reason the model gets as the block's
+//! execution error, or replace it with edited code.
+
+use std::fmt;
+
+/// Decision on one code block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodeApproval {
+    /// Run the block as written
+    Approve,
+    /// Don't run it; the message goes back to the model as the error
+    Deny(String),
+    /// Run this code instead
+    Edit(String),
+}
+
+/// Reviews code blocks before execution
+pub trait CodeApprovalHook: Send + Sync {
+    fn review(&self, code: &str) -> CodeApproval;
+}
+
+impl fmt::Debug for dyn CodeApprovalHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CodeApprovalHook")
+    }
+}
+
+impl<F> CodeApprovalHook for F
+where
+    F: Fn(&str) -> CodeApproval + Send + Sync,
+{
+    fn review(&self, code: &str) -> CodeApproval {
+        self(code)
+    }
+}
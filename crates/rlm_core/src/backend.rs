@@ -0,0 +1,884 @@
+//! Chat backend abstraction
+//!
+//! The RLM loop only needs "send messages, get text + usage back". Built-in
+//! providers implement [`ChatBackend`], and custom providers (internal
+//! gateways, proxies with custom auth) can be plugged in through
+//! [`Backend::Custom`] without touching the orchestrator. Harnesses that
+//! hand the model tools (the agent crate) can use the providers' native
+//! tool calling through [`ChatBackend::chat_with_tools`], and retrieval
+//! tools can embed text with [`ChatBackend::embed`].
+
+use async_openai::{
+    config::{AzureConfig, Config, OpenAIConfig},
+    types::{
+        ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestMessage,
+        ChatCompletionRequestMessageContentPartImage, ChatCompletionRequestMessageContentPartText,
+        ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestUserMessageArgs,
+        ChatCompletionRequestUserMessageContent, ChatCompletionRequestUserMessageContentPart,
+        ChatCompletionTool, ChatCompletionToolArgs, ChatCompletionToolType,
+        CreateChatCompletionRequestArgs, CreateEmbeddingRequestArgs, FunctionObjectArgs, ImageUrl,
+    },
+    Client as OpenAIClient,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+use crate::error::{Result, RlmError};
+use crate::mock::MockBackend;
+use crate::types::{Backend, BackendTimeouts, Message, RlmConfig, Role, Sampling, Usage};
+
+/// Per-call sampling parameters
+#[derive(Debug, Clone)]
+pub struct ChatParams {
+    pub model: String,
+    pub temperature: f32,
+    pub max_tokens: Option<u32>,
+    /// Request provider-side caching of the stable prompt prefix
+    pub prompt_cache: bool,
+    pub sampling: Sampling,
+}
+
+impl ChatParams {
+    pub fn new(model: impl Into<String>) -> Self {
+        Self {
+            model: model.into(),
+            temperature: 0.0,
+            max_tokens: None,
+            prompt_cache: false,
+            sampling: Sampling::default(),
+        }
+    }
+
+    pub fn with_temperature(mut self, t: f32) -> Self {
+        self.temperature = t;
+        self
+    }
+
+    pub fn with_max_tokens(mut self, n: Option<u32>) -> Self {
+        self.max_tokens = n;
+        self
+    }
+
+    pub fn with_prompt_cache(mut self, v: bool) -> Self {
+        self.prompt_cache = v;
+        self
+    }
+
+    pub fn with_sampling(mut self, sampling: Sampling) -> Self {
+        self.sampling = sampling;
+        self
+    }
+}
+
+/// A tool offered to the model through native tool calling
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolDefinition {
+    pub name: String,
+    pub description: String,
+    /// JSON schema of the arguments object
+    pub parameters: Value,
+}
+
+/// A tool call the model made through native tool calling
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolUse {
+    /// Provider-assigned call id
+    pub id: String,
+    pub name: String,
+    /// The arguments object; arguments that aren't valid JSON arrive as a
+    /// string
+    pub arguments: Value,
+}
+
+/// A chat completion provider
+///
+/// Calls are blocking; implementations backed by async clients own a runtime
+/// to drive them. The same backend instance serves root calls and
+/// `llm_query` sub-calls, so it must be shareable across threads.
+pub trait ChatBackend: Send + Sync {
+    /// Send the message history and return the response text and token usage
+    fn chat(&self, messages: &[Message], params: &ChatParams) -> Result<(String, Usage)>;
+
+    /// Whether [`Self::chat_with_tools`] offers the tools natively
+    fn supports_tools(&self) -> bool {
+        false
+    }
+
+    /// Send the message history offering `tools`; returns the response
+    /// text, the tool calls the model made, and token usage
+    ///
+    /// The default ignores `tools` and never reports calls, leaving the
+    /// caller to find them in the text.
+    fn chat_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+        params: &ChatParams,
+    ) -> Result<(String, Vec<ToolUse>, Usage)> {
+        let _ = tools;
+        let (text, usage) = self.chat(messages, params)?;
+        Ok((text, Vec::new(), usage))
+    }
+
+    /// Embedding vectors for `texts` from embedding model `model`, in order
+    ///
+    /// The default fails; providers without an embeddings API keep it.
+    fn embed(&self, texts: &[String], model: &str) -> Result<Vec<Vec<f32>>> {
+        let _ = (texts, model);
+        Err(RlmError::Config(
+            "this backend doesn't support embeddings".to_string(),
+        ))
+    }
+}
+
+/// HTTP client honoring the connect/read timeouts
+fn http_client(timeouts: &BackendTimeouts) -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder();
+    if let Some(t) = timeouts.connect {
+        builder = builder.connect_timeout(t);
+    }
+    if let Some(t) = timeouts.read {
+        builder = builder.read_timeout(t);
+    }
+    builder.build().map_err(|e| RlmError::Config(e.to_string()))
+}
+
+/// Map a client-level timeout to the configured duration that tripped
+fn reqwest_timeout(e: &reqwest::Error, timeouts: &BackendTimeouts) -> Option<RlmError> {
+    if !e.is_timeout() {
+        return None;
+    }
+    let after = if e.is_connect() {
+        timeouts.connect
+    } else {
+        timeouts.read
+    };
+    Some(RlmError::BackendTimeout(after.unwrap_or_default()))
+}
+
+/// Drive a backend future to completion under the per-call watchdog
+fn block_on_with_watchdog<T>(
+    runtime: &Runtime,
+    watchdog: Option<Duration>,
+    fut: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    runtime.block_on(async {
+        match watchdog {
+            Some(limit) => tokio::time::timeout(limit, fut)
+                .await
+                .map_err(|_| RlmError::BackendTimeout(limit))?,
+            None => fut.await,
+        }
+    })
+}
+
+/// OpenAI-compatible backend (OpenAI, Ollama, vLLM, Azure OpenAI, ...)
+///
+/// Generic over the async-openai config so Azure's deployment URLs,
+/// `api-key` header, and `api-version` query parameter are handled by
+/// [`AzureConfig`].
+pub struct OpenAiBackend<C: Config = OpenAIConfig> {
+    client: OpenAIClient<C>,
+    timeouts: BackendTimeouts,
+    runtime: Runtime,
+}
+
+impl<C: Config> OpenAiBackend<C> {
+    /// Create from an explicit async-openai config
+    pub fn new(config: C) -> Result<Self> {
+        let timeouts = BackendTimeouts::default();
+        Ok(Self {
+            client: OpenAIClient::with_config(config).with_http_client(http_client(&timeouts)?),
+            timeouts,
+            runtime: Runtime::new()?,
+        })
+    }
+
+    /// Replace the connect/read timeouts and watchdog
+    pub fn with_timeouts(mut self, timeouts: BackendTimeouts) -> Result<Self> {
+        self.client = self.client.with_http_client(http_client(&timeouts)?);
+        self.timeouts = timeouts;
+        Ok(self)
+    }
+}
+
+impl OpenAiBackend<AzureConfig> {
+    /// Create an Azure OpenAI backend
+    ///
+    /// `endpoint` is the resource URL (e.g. `https://my-resource.openai.azure.com`).
+    /// Falls back to `AZURE_OPENAI_API_KEY` if no key is given.
+    pub fn azure(
+        endpoint: &str,
+        deployment: &str,
+        api_version: &str,
+        api_key: Option<&str>,
+    ) -> Result<Self> {
+        let api_key = match api_key {
+            Some(key) => key.to_string(),
+            None => std::env::var("AZURE_OPENAI_API_KEY")
+                .map_err(|_| RlmError::MissingApiKey("AZURE_OPENAI_API_KEY"))?,
+        };
+        Self::new(
+            AzureConfig::new()
+                .with_api_base(endpoint)
+                .with_deployment_id(deployment)
+                .with_api_version(api_version)
+                .with_api_key(api_key),
+        )
+    }
+}
+
+impl OpenAiBackend {
+    /// Create from an optional base URL and API key
+    ///
+    /// Without a key, `OPENAI_API_KEY` is used; local endpoints without
+    /// either get a dummy key, which Ollama accepts.
+    pub fn from_parts(base_url: Option<&str>, api_key: Option<&str>) -> Result<Self> {
+        let mut openai_config = OpenAIConfig::new();
+        if let Some(url) = base_url {
+            openai_config = openai_config.with_api_base(url);
+        }
+        if let Some(key) = api_key {
+            openai_config = openai_config.with_api_key(key);
+        } else if base_url.is_some() && std::env::var_os("OPENAI_API_KEY").is_none() {
+            // For Ollama/local models without explicit key
+            openai_config = openai_config.with_api_key("ollama");
+        }
+        Self::new(openai_config)
+    }
+}
+
+/// User message content, with images as `data:` URL parts after the text
+fn openai_user_content(message: &Message) -> ChatCompletionRequestUserMessageContent {
+    if message.images.is_empty() {
+        return ChatCompletionRequestUserMessageContent::Text(message.content.clone());
+    }
+    let text = ChatCompletionRequestUserMessageContentPart::Text(
+        ChatCompletionRequestMessageContentPartText {
+            text: message.content.clone(),
+        },
+    );
+    let images = message.images.iter().map(|image| {
+        ChatCompletionRequestUserMessageContentPart::ImageUrl(
+            ChatCompletionRequestMessageContentPartImage {
+                image_url: ImageUrl {
+                    url: image.data_url(),
+                    detail: None,
+                },
+            },
+        )
+    });
+    ChatCompletionRequestUserMessageContent::Array(std::iter::once(text).chain(images).collect())
+}
+
+/// Function tools for a chat completion request
+fn openai_tools(tools: &[ToolDefinition]) -> Result<Vec<ChatCompletionTool>> {
+    tools
+        .iter()
+        .map(|tool| {
+            let function = FunctionObjectArgs::default()
+                .name(&tool.name)
+                .description(&tool.description)
+                .parameters(tool.parameters.clone())
+                .build()?;
+            Ok(ChatCompletionToolArgs::default()
+                .r#type(ChatCompletionToolType::Function)
+                .function(function)
+                .build()?)
+        })
+        .collect()
+}
+
+impl<C: Config + Send + Sync> ChatBackend for OpenAiBackend<C> {
+    fn chat(&self, messages: &[Message], params: &ChatParams) -> Result<(String, Usage)> {
+        let (content, _, usage) = self.chat_with_tools(messages, &[], params)?;
+        Ok((content, usage))
+    }
+
+    fn supports_tools(&self) -> bool {
+        true
+    }
+
+    fn chat_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+        params: &ChatParams,
+    ) -> Result<(String, Vec<ToolUse>, Usage)> {
+        let messages: Vec<ChatCompletionRequestMessage> = messages
+            .iter()
+            .map(|m| match m.role {
+                Role::System => ChatCompletionRequestMessage::System(
+                    ChatCompletionRequestSystemMessageArgs::default()
+                        .content(m.content.clone())
+                        .build()
+                        .unwrap(),
+                ),
+                Role::User => ChatCompletionRequestMessage::User(
+                    ChatCompletionRequestUserMessageArgs::default()
+                        .content(openai_user_content(m))
+                        .build()
+                        .unwrap(),
+                ),
+                Role::Assistant => ChatCompletionRequestMessage::Assistant(
+                    ChatCompletionRequestAssistantMessageArgs::default()
+                        .content(m.content.clone())
+                        .build()
+                        .unwrap(),
+                ),
+            })
+            .collect();
+
+        let mut request_builder = CreateChatCompletionRequestArgs::default();
+        request_builder
+            .model(&params.model)
+            .messages(messages)
+            .temperature(params.temperature);
+
+        if let Some(max_tokens) = params.max_tokens {
+            request_builder.max_tokens(max_tokens);
+        }
+        let sampling = params.sampling;
+        if let Some(top_p) = sampling.top_p {
+            request_builder.top_p(top_p);
+        }
+        if let Some(penalty) = sampling.presence_penalty {
+            request_builder.presence_penalty(penalty);
+        }
+        if let Some(penalty) = sampling.frequency_penalty {
+            request_builder.frequency_penalty(penalty);
+        }
+        if let Some(seed) = sampling.seed {
+            request_builder.seed(seed);
+        }
+        if !tools.is_empty() {
+            request_builder.tools(openai_tools(tools)?);
+        }
+
+        let request = request_builder.build()?;
+
+        let response = block_on_with_watchdog(&self.runtime, self.timeouts.request, async {
+            self.client
+                .chat()
+                .create(request)
+                .await
+                .map_err(|e| match e {
+                    async_openai::error::OpenAIError::Reqwest(ref inner) => {
+                        reqwest_timeout(inner, &self.timeouts).unwrap_or(e.into())
+                    }
+                    e => e.into(),
+                })
+        })?;
+
+        let message = response.choices.first().map(|c| &c.message);
+        let content = message.and_then(|m| m.content.clone()).unwrap_or_default();
+        let calls = message
+            .and_then(|m| m.tool_calls.as_ref())
+            .into_iter()
+            .flatten()
+            .map(|call| ToolUse {
+                id: call.id.clone(),
+                name: call.function.name.clone(),
+                arguments: serde_json::from_str(&call.function.arguments)
+                    .unwrap_or_else(|_| Value::String(call.function.arguments.clone())),
+            })
+            .collect();
+
+        // OpenAI caches long prompt prefixes automatically; just report hits
+        let usage = response
+            .usage
+            .map(|u| {
+                let cached = u
+                    .prompt_tokens_details
+                    .and_then(|d| d.cached_tokens)
+                    .unwrap_or(0);
+                Usage::new(u.prompt_tokens as u64, u.completion_tokens as u64)
+                    .with_cached_input_tokens(cached as u64)
+            })
+            .unwrap_or_default();
+
+        Ok((content, calls, usage))
+    }
+
+    fn embed(&self, texts: &[String], model: &str) -> Result<Vec<Vec<f32>>> {
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+        let request = CreateEmbeddingRequestArgs::default()
+            .model(model)
+            .input(texts.to_vec())
+            .build()?;
+
+        let response = block_on_with_watchdog(&self.runtime, self.timeouts.request, async {
+            self.client
+                .embeddings()
+                .create(request)
+                .await
+                .map_err(|e| match e {
+                    async_openai::error::OpenAIError::Reqwest(ref inner) => {
+                        reqwest_timeout(inner, &self.timeouts).unwrap_or(e.into())
+                    }
+                    e => e.into(),
+                })
+        })?;
+
+        let mut data = response.data;
+        if data.len() != texts.len() {
+            return Err(RlmError::Api(format!(
+                "asked for {} embeddings, got {}",
+                texts.len(),
+                data.len()
+            )));
+        }
+        data.sort_by_key(|e| e.index);
+        Ok(data.into_iter().map(|e| e.embedding).collect())
+    }
+}
+
+/// Default Anthropic API endpoint
+const ANTHROPIC_BASE_URL: &str = "https://api.anthropic.com";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Anthropic Messages API backend
+///
+/// Talks to the Messages API directly so requests can carry
+/// `cache_control` breakpoints, which the SDK crates don't expose.
+pub struct AnthropicBackend {
+    client: reqwest::Client,
+    api_key: String,
+    base_url: String,
+    timeouts: BackendTimeouts,
+    runtime: Runtime,
+}
+
+impl AnthropicBackend {
+    /// Create with an explicit key, or from `ANTHROPIC_API_KEY` if `None`
+    pub fn new(api_key: Option<&str>) -> Result<Self> {
+        let api_key = match api_key {
+            Some(key) => key.to_string(),
+            None => std::env::var("ANTHROPIC_API_KEY")
+                .map_err(|_| RlmError::MissingApiKey("ANTHROPIC_API_KEY"))?,
+        };
+        let timeouts = BackendTimeouts::default();
+        Ok(Self {
+            client: http_client(&timeouts)?,
+            api_key,
+            base_url: ANTHROPIC_BASE_URL.to_string(),
+            timeouts,
+            runtime: Runtime::new()?,
+        })
+    }
+
+    /// Replace the connect/read timeouts and watchdog
+    pub fn with_timeouts(mut self, timeouts: BackendTimeouts) -> Result<Self> {
+        self.client = http_client(&timeouts)?;
+        self.timeouts = timeouts;
+        Ok(self)
+    }
+
+    /// Point at a different endpoint (proxies, test servers)
+    pub fn with_base_url(mut self, url: impl Into<String>) -> Self {
+        self.base_url = url.into().trim_end_matches('/').to_string();
+        self
+    }
+}
+
+/// Build a Messages API request body
+///
+/// With `params.prompt_cache`, up to three `cache_control` breakpoints are
+/// set: the system prompt, the first user turn (which carries the task and
+/// context metadata), and the latest message, so each iteration reads the
+/// previous iteration's prefix from cache. Prefixes below the provider's
+/// minimum cacheable length are simply not cached.
+fn anthropic_request_body(messages: &[Message], params: &ChatParams) -> Value {
+    let cache_control = || json!({ "type": "ephemeral" });
+    let text_block = |text: &str, cached: bool| {
+        let mut block = json!({ "type": "text", "text": text });
+        if cached {
+            block["cache_control"] = cache_control();
+        }
+        block
+    };
+
+    let turns: Vec<&Message> = messages.iter().filter(|m| m.role != Role::System).collect();
+    let last = turns.len().saturating_sub(1);
+    let turns: Vec<Value> = turns
+        .iter()
+        .enumerate()
+        .map(|(i, m)| {
+            let role = match m.role {
+                Role::Assistant => "assistant",
+                _ => "user",
+            };
+            let cached = params.prompt_cache && (i == 0 || i == last);
+            // Images go first, as Anthropic recommends; the cache breakpoint
+            // on the text block still covers them
+            let mut content: Vec<Value> = m
+                .images
+                .iter()
+                .map(|image| {
+                    json!({
+                        "type": "image",
+                        "source": {
+                            "type": "base64",
+                            "media_type": image.media_type,
+                            "data": image.base64(),
+                        },
+                    })
+                })
+                .collect();
+            content.push(text_block(&m.content, cached));
+            json!({ "role": role, "content": content })
+        })
+        .collect();
+
+    let mut body = json!({
+        "model": params.model,
+        "max_tokens": params.max_tokens.unwrap_or(4096),
+        "messages": turns,
+    });
+
+    let system: Vec<&str> = messages
+        .iter()
+        .filter(|m| m.role == Role::System)
+        .map(|m| m.content.as_str())
+        .collect();
+    if !system.is_empty() {
+        body["system"] = json!([text_block(&system.join("\n\n"), params.prompt_cache)]);
+    }
+
+    if params.temperature > 0.0 {
+        body["temperature"] = json!(params.temperature);
+    }
+    if let Some(top_p) = params.sampling.top_p {
+        body["top_p"] = json!(top_p);
+    }
+
+    body
+}
+
+/// Tool definitions in the Messages API shape
+fn anthropic_tools(tools: &[ToolDefinition]) -> Value {
+    tools
+        .iter()
+        .map(|tool| {
+            json!({
+                "name": tool.name,
+                "description": tool.description,
+                "input_schema": tool.parameters,
+            })
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct AnthropicResponse {
+    content: Vec<AnthropicContent>,
+    usage: AnthropicUsage,
+}
+
+#[derive(Deserialize)]
+struct AnthropicContent {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    text: String,
+    /// `tool_use` blocks only
+    #[serde(default)]
+    id: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    input: Value,
+}
+
+impl AnthropicResponse {
+    /// Text of the text blocks, and the tool calls
+    fn into_parts(self) -> (String, Vec<ToolUse>, Usage) {
+        let mut text = String::new();
+        let mut calls = Vec::new();
+        for block in self.content {
+            match block.kind.as_str() {
+                "text" => text.push_str(&block.text),
+                "tool_use" => calls.push(ToolUse {
+                    id: block.id,
+                    name: block.name,
+                    arguments: block.input,
+                }),
+                _ => {}
+            }
+        }
+        (text, calls, self.usage.into())
+    }
+}
+
+#[derive(Deserialize)]
+struct AnthropicUsage {
+    input_tokens: u64,
+    output_tokens: u64,
+    #[serde(default)]
+    cache_creation_input_tokens: Option<u64>,
+    #[serde(default)]
+    cache_read_input_tokens: Option<u64>,
+}
+
+impl From<AnthropicUsage> for Usage {
+    fn from(u: AnthropicUsage) -> Self {
+        // Anthropic reports cached tokens separately from input_tokens
+        let cache_read = u.cache_read_input_tokens.unwrap_or(0);
+        let input = u.input_tokens + u.cache_creation_input_tokens.unwrap_or(0) + cache_read;
+        Usage::new(input, u.output_tokens).with_cached_input_tokens(cache_read)
+    }
+}
+
+impl ChatBackend for AnthropicBackend {
+    fn chat(&self, messages: &[Message], params: &ChatParams) -> Result<(String, Usage)> {
+        let (content, _, usage) = self.chat_with_tools(messages, &[], params)?;
+        Ok((content, usage))
+    }
+
+    fn supports_tools(&self) -> bool {
+        true
+    }
+
+    fn chat_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+        params: &ChatParams,
+    ) -> Result<(String, Vec<ToolUse>, Usage)> {
+        let mut body = anthropic_request_body(messages, params);
+        if !tools.is_empty() {
+            body["tools"] = anthropic_tools(tools);
+        }
+
+        let (status, text) = block_on_with_watchdog(&self.runtime, self.timeouts.request, async {
+            let send = async {
+                let response = self
+                    .client
+                    .post(format!("{}/v1/messages", self.base_url))
+                    .header("x-api-key", &self.api_key)
+                    .header("anthropic-version", ANTHROPIC_VERSION)
+                    .json(&body)
+                    .send()
+                    .await?;
+                let status = response.status();
+                Ok::<_, reqwest::Error>((status, response.text().await?))
+            };
+            send.await.map_err(|e| {
+                reqwest_timeout(&e, &self.timeouts).unwrap_or_else(|| RlmError::Api(e.to_string()))
+            })
+        })?;
+
+        if !status.is_success() {
+            let message = serde_json::from_str::<Value>(&text)
+                .ok()
+                .and_then(|v| v["error"]["message"].as_str().map(str::to_string))
+                .unwrap_or(text);
+            return Err(RlmError::Api(format!(
+                "Anthropic API error ({}): {}",
+                status, message
+            )));
+        }
+
+        let response: AnthropicResponse = serde_json::from_str(&text)?;
+        Ok(response.into_parts())
+    }
+}
+
+impl ChatBackend for MockBackend {
+    fn chat(&self, messages: &[Message], _params: &ChatParams) -> Result<(String, Usage)> {
+        self.complete(messages)
+    }
+}
+
+/// Create the backend selected by the config
+///
+/// Uses config.backend, config.base_url, config.api_key, and config.timeouts
+/// (built-in backends only). Falls back to environment variables
+/// (OPENAI_API_KEY, ANTHROPIC_API_KEY) if no key is set.
+pub fn create_backend(config: &RlmConfig) -> Result<Arc<dyn ChatBackend>> {
+    build_backend(&config.backend, config)
+}
+
+/// Create the backend for `llm_query` sub-calls, if `config.sub_backend`
+/// names one apart from the root backend
+pub fn create_sub_backend(config: &RlmConfig) -> Result<Option<Arc<dyn ChatBackend>>> {
+    config
+        .sub_backend
+        .as_ref()
+        .map(|backend| build_backend(backend, config))
+        .transpose()
+}
+
+fn build_backend(backend: &Backend, config: &RlmConfig) -> Result<Arc<dyn ChatBackend>> {
+    match *backend {
+        Backend::OpenAI => Ok(Arc::new(
+            OpenAiBackend::from_parts(config.base_url.as_deref(), config.api_key.as_deref())?
+                .with_timeouts(config.timeouts)?,
+        )),
+        Backend::Anthropic => Ok(Arc::new(
+            AnthropicBackend::new(config.api_key.as_deref())?.with_timeouts(config.timeouts)?,
+        )),
+        Backend::AzureOpenAI {
+            ref deployment,
+            ref api_version,
+        } => {
+            let endpoint = config.base_url.as_deref().ok_or_else(|| {
+                RlmError::Config(
+                    "Azure OpenAI requires base_url set to the resource endpoint".to_string(),
+                )
+            })?;
+            Ok(Arc::new(
+                OpenAiBackend::azure(endpoint, deployment, api_version, config.api_key.as_deref())?
+                    .with_timeouts(config.timeouts)?,
+            ))
+        }
+        Backend::Mock(ref mock) => Ok(Arc::new(mock.clone())),
+        Backend::Custom(ref backend) => Ok(backend.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Image;
+
+    fn history() -> Vec<Message> {
+        vec![
+            Message::system("system prompt"),
+            Message::user("task"),
+            Message::assistant("```repl\nprint(1)\n```"),
+            Message::user("output"),
+        ]
+    }
+
+    #[test]
+    fn test_anthropic_body_cache_breakpoints() {
+        let params = ChatParams::new("claude").with_prompt_cache(true);
+        let body = anthropic_request_body(&history(), &params);
+
+        assert_eq!(body["system"][0]["cache_control"]["type"], "ephemeral");
+        let turns = body["messages"].as_array().unwrap();
+        assert_eq!(turns.len(), 3);
+        assert_eq!(turns[0]["content"][0]["cache_control"]["type"], "ephemeral");
+        assert!(turns[1]["content"][0].get("cache_control").is_none());
+        assert_eq!(turns[2]["content"][0]["cache_control"]["type"], "ephemeral");
+        assert!(body.get("temperature").is_none());
+    }
+
+    #[test]
+    fn test_anthropic_body_without_cache() {
+        let params = ChatParams::new("claude")
+            .with_temperature(0.5)
+            .with_sampling(Sampling {
+                top_p: Some(0.5),
+                seed: Some(7),
+                ..Default::default()
+            });
+        let body = anthropic_request_body(&history(), &params);
+
+        assert_eq!(body["system"][0]["text"], "system prompt");
+        assert!(body["system"][0].get("cache_control").is_none());
+        assert!(body["messages"][0]["content"][0]
+            .get("cache_control")
+            .is_none());
+        assert_eq!(body["messages"][1]["role"], "assistant");
+        assert_eq!(body["max_tokens"], 4096);
+        assert_eq!(body["temperature"], 0.5);
+        assert_eq!(body["top_p"], 0.5);
+        assert!(body.get("seed").is_none());
+    }
+
+    #[test]
+    fn test_image_blocks() {
+        let png = Image::from_bytes(b"\x89PNG\r\n\x1a\nrest".to_vec()).unwrap();
+        let messages = [Message::user("what is this?").with_images([png.clone()])];
+
+        let body = anthropic_request_body(&messages, &ChatParams::new("claude"));
+        let content = &body["messages"][0]["content"];
+        assert_eq!(content[0]["source"]["media_type"], "image/png");
+        assert_eq!(content[0]["source"]["data"], png.base64());
+        assert_eq!(content[1]["text"], "what is this?");
+
+        let ChatCompletionRequestUserMessageContent::Array(parts) =
+            openai_user_content(&messages[0])
+        else {
+            panic!("expected content parts");
+        };
+        assert!(matches!(
+            &parts[1],
+            ChatCompletionRequestUserMessageContentPart::ImageUrl(part)
+                if part.image_url.url.starts_with("data:image/png;base64,")
+        ));
+    }
+
+    #[test]
+    fn test_watchdog_times_out_hung_connection() {
+        // Accepts the TCP connection but never answers
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let timeouts = BackendTimeouts {
+            connect: None,
+            read: None,
+            request: Some(Duration::from_millis(200)),
+        };
+        let backend = AnthropicBackend::new(Some("key"))
+            .unwrap()
+            .with_base_url(url)
+            .with_timeouts(timeouts)
+            .unwrap();
+
+        let err = backend
+            .chat(&history(), &ChatParams::new("claude"))
+            .unwrap_err();
+        assert!(matches!(err, RlmError::BackendTimeout(d) if d == Duration::from_millis(200)));
+    }
+
+    #[test]
+    fn test_anthropic_tool_use() {
+        let tools = [ToolDefinition {
+            name: "read_file".to_string(),
+            description: "Read a file".to_string(),
+            parameters: json!({ "type": "object", "properties": { "path": { "type": "string" } } }),
+        }];
+        assert_eq!(anthropic_tools(&tools)[0]["input_schema"]["type"], "object");
+        assert_eq!(
+            openai_tools(&tools).unwrap()[0].function.parameters,
+            Some(tools[0].parameters.clone())
+        );
+
+        let response: AnthropicResponse = serde_json::from_value(json!({
+            "content": [
+                { "type": "text", "text": "Let me look." },
+                { "type": "tool_use", "id": "toolu_1", "name": "read_file", "input": { "path": "a.txt" } }
+            ],
+            "usage": { "input_tokens": 10, "output_tokens": 5 }
+        }))
+        .unwrap();
+        let (text, calls, _) = response.into_parts();
+        assert_eq!(text, "Let me look.");
+        assert_eq!(calls[0].id, "toolu_1");
+        assert_eq!(calls[0].arguments["path"], "a.txt");
+    }
+
+    #[test]
+    fn test_anthropic_usage_includes_cache() {
+        let usage: Usage = AnthropicUsage {
+            input_tokens: 10,
+            output_tokens: 5,
+            cache_creation_input_tokens: Some(100),
+            cache_read_input_tokens: Some(1000),
+        }
+        .into();
+
+        assert_eq!(usage.input_tokens, 1110);
+        assert_eq!(usage.cached_input_tokens, 1000);
+        assert_eq!(usage.total_tokens, 1115);
+    }
+}
@@ -0,0 +1,208 @@
+//! Response cache for `llm_query` sub-calls
+//!
+//! Models analyzing repetitive data often send the same sub-query several
+//! times. [`QueryCache`] is a small LRU keyed by (model, prompt, temperature)
+//! that can optionally be persisted to a JSON file so hits survive restarts.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::error::Result;
+
+/// Cache hit statistics for a run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct CacheKey {
+    model: String,
+    prompt: String,
+    /// `f32::to_bits` so the key can be hashed
+    temperature: u32,
+}
+
+impl CacheKey {
+    fn new(model: &str, prompt: &str, temperature: f32) -> Self {
+        Self {
+            model: model.to_string(),
+            prompt: prompt.to_string(),
+            temperature: temperature.to_bits(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    #[serde(flatten)]
+    key: CacheKey,
+    response: String,
+}
+
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<CacheKey, String>,
+    /// Least recently used first
+    order: VecDeque<CacheKey>,
+}
+
+impl Lru {
+    fn touch(&mut self, key: &CacheKey) {
+        if let Some(pos) = self.order.iter().position(|k| k == key) {
+            let key = self.order.remove(pos).unwrap();
+            self.order.push_back(key);
+        }
+    }
+
+    fn insert(&mut self, key: CacheKey, response: String, capacity: usize) {
+        if self.entries.insert(key.clone(), response).is_some() {
+            self.touch(&key);
+        } else {
+            self.order.push_back(key);
+        }
+        while self.order.len() > capacity {
+            if let Some(evicted) = self.order.pop_front() {
+                self.entries.remove(&evicted);
+            }
+        }
+    }
+}
+
+/// LRU cache of sub-query responses
+pub struct QueryCache {
+    capacity: usize,
+    path: Option<PathBuf>,
+    lru: Mutex<Lru>,
+}
+
+impl QueryCache {
+    /// In-memory cache holding at most `capacity` responses
+    pub fn in_memory(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            path: None,
+            lru: Mutex::new(Lru::default()),
+        }
+    }
+
+    /// Cache backed by a JSON file, loading existing entries if present
+    pub fn persistent(capacity: usize, path: impl Into<PathBuf>) -> Result<Self> {
+        let mut cache = Self::in_memory(capacity);
+        let path = path.into();
+        if path.exists() {
+            let entries: Vec<CacheEntry> = serde_json::from_str(&std::fs::read_to_string(&path)?)?;
+            let lru = cache.lru.get_mut().unwrap();
+            for entry in entries {
+                lru.insert(entry.key, entry.response, cache.capacity);
+            }
+        }
+        cache.path = Some(path);
+        Ok(cache)
+    }
+
+    /// Backing file, if persistent
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Look up a response, marking it as recently used
+    pub fn get(&self, model: &str, prompt: &str, temperature: f32) -> Option<String> {
+        let key = CacheKey::new(model, prompt, temperature);
+        let mut lru = self.lru.lock().unwrap();
+        let response = lru.entries.get(&key).cloned()?;
+        lru.touch(&key);
+        Some(response)
+    }
+
+    /// Store a response, evicting the least recently used entry when full
+    ///
+    /// Persistent caches are written through to disk.
+    pub fn insert(
+        &self,
+        model: &str,
+        prompt: &str,
+        temperature: f32,
+        response: &str,
+    ) -> Result<()> {
+        let key = CacheKey::new(model, prompt, temperature);
+        let mut lru = self.lru.lock().unwrap();
+        lru.insert(key, response.to_string(), self.capacity);
+
+        if let Some(ref path) = self.path {
+            let entries: Vec<CacheEntry> = lru
+                .order
+                .iter()
+                .map(|key| CacheEntry {
+                    key: key.clone(),
+                    response: lru.entries[key].clone(),
+                })
+                .collect();
+            std::fs::write(path, serde_json::to_string(&entries)?)?;
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.lru.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_includes_model_and_temperature() {
+        let cache = QueryCache::in_memory(8);
+        cache.insert("gpt-4o", "summarize", 0.0, "summary").unwrap();
+
+        assert_eq!(
+            cache.get("gpt-4o", "summarize", 0.0).as_deref(),
+            Some("summary")
+        );
+        assert_eq!(cache.get("gpt-4o", "summarize", 0.7), None);
+        assert_eq!(cache.get("gpt-4o-mini", "summarize", 0.0), None);
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let cache = QueryCache::in_memory(2);
+        cache.insert("m", "a", 0.0, "1").unwrap();
+        cache.insert("m", "b", 0.0, "2").unwrap();
+        // Touch "a" so "b" becomes the eviction candidate
+        assert!(cache.get("m", "a", 0.0).is_some());
+        cache.insert("m", "c", 0.0, "3").unwrap();
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get("m", "a", 0.0).is_some());
+        assert!(cache.get("m", "b", 0.0).is_none());
+        assert!(cache.get("m", "c", 0.0).is_some());
+    }
+
+    #[test]
+    fn test_persistent_roundtrip() {
+        let path =
+            std::env::temp_dir().join(format!("rlm-query-cache-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let cache = QueryCache::persistent(4, &path).unwrap();
+        cache.insert("m", "prompt", 0.0, "response").unwrap();
+        drop(cache);
+
+        let reloaded = QueryCache::persistent(4, &path).unwrap();
+        assert_eq!(
+            reloaded.get("m", "prompt", 0.0).as_deref(),
+            Some("response")
+        );
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
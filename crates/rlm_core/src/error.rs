@@ -0,0 +1,88 @@
+use thiserror::Error;
+
+use crate::types::PartialRun;
+
+/// RLM error types
+#[derive(Error, Debug)]
+pub enum RlmError {
+    #[error("OpenAI API error: {0}")]
+    OpenAi(#[from] async_openai::error::OpenAIError),
+
+    #[error("JSON serialization error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Python execution error: {0}")]
+    Python(String),
+
+    #[error("REPL error: {0}")]
+    Repl(String),
+
+    #[cfg(feature = "python")]
+    #[error("PyO3 error: {0}")]
+    PyO3(#[from] pyo3::PyErr),
+
+    #[error("Tokio runtime error: {0}")]
+    Runtime(#[from] std::io::Error),
+
+    #[error("Backend call timed out after {0:?}")]
+    BackendTimeout(std::time::Duration),
+
+    #[error("Telemetry error: {0}")]
+    Telemetry(String),
+
+    #[error("Max iterations reached ({0})")]
+    MaxIterationsReached(u32),
+
+    #[error("Budget exceeded: {0}")]
+    BudgetExceeded(String),
+
+    #[error("Prompt needs {tokens} tokens but the model's context window is {limit}")]
+    ContextWindowExceeded { tokens: usize, limit: usize },
+
+    /// No key was given and the named environment variable isn't set
+    #[error("No API key found. Set {0} or pass an API key.")]
+    MissingApiKey(&'static str),
+
+    #[error("Missing Python packages: {} ({interpreter})", .missing.join(", "))]
+    MissingPythonPackages {
+        missing: Vec<String>,
+        interpreter: String,
+    },
+
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+
+    #[error("Document extraction error: {0}")]
+    Document(String),
+
+    #[error("API error: {0}")]
+    Api(String),
+
+    /// A run failed after making progress; carries the iterations and usage so far
+    #[error("{error}")]
+    Incomplete {
+        error: Box<RlmError>,
+        partial: Box<PartialRun>,
+    },
+}
+
+impl RlmError {
+    /// Partial trace of the failed run, if one was recorded
+    pub fn partial(&self) -> Option<&PartialRun> {
+        match self {
+            RlmError::Incomplete { partial, .. } => Some(partial),
+            _ => None,
+        }
+    }
+
+    /// The underlying error, unwrapping any partial-trace wrapper
+    pub fn root_cause(&self) -> &RlmError {
+        match self {
+            RlmError::Incomplete { error, .. } => error.root_cause(),
+            other => other,
+        }
+    }
+}
+
+/// Result type alias for RLM operations
+pub type Result<T> = std::result::Result<T, RlmError>;
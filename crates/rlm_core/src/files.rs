@@ -0,0 +1,35 @@
+//! Saving state files
+//!
+//! Sessions, notes, and indexes are rewritten whole on every change;
+//! [`write_atomic`] makes sure a reader or a crash only ever sees the old
+//! file or the new one.
+
+use std::path::{Path, PathBuf};
+
+/// Replace the file at `path` with `contents`
+///
+/// Writes a `.tmp` sibling then renames it over `path`, so a crash never
+/// leaves half a file.
+pub fn write_atomic(path: &Path, contents: &str) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_atomic() {
+        let path =
+            std::env::temp_dir().join(format!("rlm_core_atomic_{}.json", std::process::id()));
+        write_atomic(&path, "first").unwrap();
+        write_atomic(&path, "second").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "second");
+        assert!(!path.with_extension("json.tmp").exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,538 @@
+//! HTML to markdown, for fetched pages and HTML documents
+//!
+//! Not a full HTML parser: a forgiving tokenizer plus a readability-style
+//! pass that keeps `<article>` (or `<main>`, or `<body>`), drops navigation,
+//! scripts, forms and other boilerplate, and renders what is left as
+//! markdown headings, paragraphs, lists, links, and code.
+
+/// Readable content of a page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page {
+    pub title: Option<String>,
+    pub markdown: String,
+}
+
+/// Extract the main content of `html` as markdown
+pub fn to_markdown(html: &str) -> Page {
+    let tokens = tokenize(html);
+    let title = element_range(&tokens, "title")
+        .map(|range| {
+            let text: String = tokens[range]
+                .iter()
+                .filter_map(|token| match token {
+                    Token::Text(text) => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect();
+            collapse_whitespace(&decode_entities(&text))
+        })
+        .filter(|title| !title.is_empty());
+
+    let content = ["article", "main", "body"]
+        .iter()
+        .find_map(|name| element_range(&tokens, name))
+        .unwrap_or(0..tokens.len());
+    let mut renderer = Renderer::default();
+    for token in &tokens[content] {
+        renderer.token(token);
+    }
+    Page {
+        title,
+        markdown: renderer.finish(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Text(String),
+    Start {
+        name: String,
+        attrs: Vec<(String, String)>,
+    },
+    End(String),
+}
+
+/// Elements whose content is never page text
+const SKIPPED: &[&str] = &[
+    "script", "style", "noscript", "template", "head", "nav", "header", "footer", "aside", "form",
+    "button", "select", "svg", "iframe", "canvas", "dialog",
+];
+
+/// Elements without an end tag
+const VOID: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// Elements starting a new paragraph
+const BLOCKS: &[&str] = &[
+    "p",
+    "div",
+    "section",
+    "article",
+    "main",
+    "figure",
+    "figcaption",
+    "table",
+    "ul",
+    "ol",
+    "dl",
+    "dt",
+    "dd",
+    "address",
+    "details",
+    "summary",
+];
+
+fn tokenize(html: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut rest = html;
+
+    while let Some(lt) = rest.find('<') {
+        if lt > 0 {
+            tokens.push(Token::Text(rest[..lt].to_string()));
+        }
+        rest = &rest[lt..];
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+        } else if rest.starts_with("<!") || rest.starts_with("<?") {
+            rest = rest.find('>').map_or("", |end| &rest[end + 1..]);
+        } else if let Some(end_tag) = rest.strip_prefix("</") {
+            let end = end_tag.find('>').unwrap_or(end_tag.len());
+            let name = end_tag[..end].trim().to_ascii_lowercase();
+            tokens.push(Token::End(name));
+            rest = end_tag.get(end + 1..).unwrap_or("");
+        } else if rest[1..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+            let end = tag_end(rest);
+            let inner = rest[1..end].trim_end_matches('>');
+            let self_closing = inner.ends_with('/');
+            let (name, attrs) = parse_tag(inner.trim_end_matches('/'));
+            rest = &rest[end..];
+            if self_closing {
+                tokens.push(Token::Start {
+                    name: name.clone(),
+                    attrs,
+                });
+                tokens.push(Token::End(name));
+                continue;
+            }
+
+            // Raw text: the content can't hold tags, whatever it looks like
+            if name == "script" || name == "style" {
+                let close = format!("</{}", name);
+                let at = rest.to_ascii_lowercase().find(&close).unwrap_or(rest.len());
+                rest = &rest[at..];
+            }
+            tokens.push(Token::Start { name, attrs });
+        } else {
+            tokens.push(Token::Text("<".to_string()));
+            rest = &rest[1..];
+        }
+    }
+    if !rest.is_empty() {
+        tokens.push(Token::Text(rest.to_string()));
+    }
+    tokens
+}
+
+/// Byte offset just after the `>` closing the start tag at the front of
+/// `text`, skipping `>` inside quoted attribute values
+fn tag_end(text: &str) -> usize {
+    let mut quote = None;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '>') => return i + 1,
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+/// Name and attributes of a start tag, without its angle brackets
+fn parse_tag(inner: &str) -> (String, Vec<(String, String)>) {
+    let name_end = inner
+        .find(|c: char| c.is_whitespace() || c == '/')
+        .unwrap_or(inner.len());
+    let name = inner[..name_end].to_ascii_lowercase();
+
+    let mut attrs = Vec::new();
+    let mut rest = inner[name_end..].trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+    while !rest.is_empty() {
+        let key_end = rest
+            .find(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or(rest.len());
+        let key = rest[..key_end].to_ascii_lowercase();
+        rest = rest[key_end..].trim_start();
+
+        let mut value = String::new();
+        if let Some(after) = rest.strip_prefix('=') {
+            let after = after.trim_start();
+            let (raw, remaining) = match after.chars().next() {
+                Some(q @ ('"' | '\'')) => {
+                    let body = &after[1..];
+                    let end = body.find(q).unwrap_or(body.len());
+                    (&body[..end], body.get(end + 1..).unwrap_or(""))
+                }
+                _ => {
+                    let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                    (&after[..end], &after[end..])
+                }
+            };
+            value = decode_entities(raw);
+            rest = remaining;
+        }
+        if !key.is_empty() {
+            attrs.push((key, value));
+        }
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+    }
+    (name, attrs)
+}
+
+/// Token range inside the first `name` element, start and end tags excluded
+fn element_range(tokens: &[Token], name: &str) -> Option<std::ops::Range<usize>> {
+    let start = tokens
+        .iter()
+        .position(|t| matches!(t, Token::Start { name: n, .. } if n == name))?;
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate().skip(start + 1) {
+        match token {
+            Token::Start { name: n, .. } if n == name => depth += 1,
+            Token::End(n) if n == name => {
+                if depth == 0 {
+                    return Some(start + 1..i);
+                }
+                depth -= 1;
+            }
+            _ => {}
+        }
+    }
+    Some(start + 1..tokens.len())
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest[1..]
+            .find(';')
+            .filter(|&end| end <= 10)
+            .and_then(|end| Some((entity(&rest[1..end + 1])?, end + 2)));
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn entity(name: &str) -> Option<char> {
+    if let Some(number) = name.strip_prefix('#') {
+        let code = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => number.parse().ok()?,
+        };
+        return char::from_u32(code);
+    }
+    Some(match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => ' ',
+        "ndash" => '–',
+        "mdash" => '—',
+        "hellip" => '…',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "copy" => '©',
+        _ => return None,
+    })
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Markdown writer fed one token at a time
+#[derive(Default)]
+struct Renderer {
+    out: String,
+    /// Name and depth of the skipped element we're inside
+    skipping: Option<(String, usize)>,
+    pre: usize,
+    /// Ordered lists hold their next item number
+    lists: Vec<Option<usize>>,
+    /// Where each open link's text starts, and its target
+    links: Vec<(usize, String)>,
+}
+
+impl Renderer {
+    fn token(&mut self, token: &Token) {
+        if let Some((skipped, depth)) = &mut self.skipping {
+            match token {
+                Token::Start { name, .. } if name == skipped => *depth += 1,
+                Token::End(name) if name == skipped => {
+                    if *depth == 0 {
+                        self.skipping = None;
+                    } else {
+                        *depth -= 1;
+                    }
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        match token {
+            Token::Text(text) => self.text(text),
+            Token::Start { name, attrs } => {
+                if SKIPPED.contains(&name.as_str()) {
+                    if !VOID.contains(&name.as_str()) {
+                        self.skipping = Some((name.clone(), 0));
+                    }
+                    return;
+                }
+                self.start(name, attrs);
+            }
+            Token::End(name) => self.end(name),
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        let text = decode_entities(text);
+        if self.pre > 0 {
+            self.out.push_str(&text);
+            return;
+        }
+        let starts_with_space = text.starts_with(char::is_whitespace);
+        let ends_with_space = text.ends_with(char::is_whitespace);
+        let words = collapse_whitespace(&text);
+        if words.is_empty() {
+            if starts_with_space {
+                self.space();
+            }
+            return;
+        }
+        if starts_with_space {
+            self.space();
+        }
+        self.out.push_str(&words);
+        if ends_with_space {
+            self.out.push(' ');
+        }
+    }
+
+    fn space(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with([' ', '\n']) {
+            self.out.push(' ');
+        }
+    }
+
+    /// End the current line
+    fn line_break(&mut self) {
+        self.trim_trailing_spaces();
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+    }
+
+    /// Leave a blank line before what follows
+    fn paragraph_break(&mut self) {
+        self.line_break();
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+
+    fn trim_trailing_spaces(&mut self) {
+        let trimmed = self.out.trim_end_matches(' ').len();
+        self.out.truncate(trimmed);
+    }
+
+    fn start(&mut self, name: &str, attrs: &[(String, String)]) {
+        let attr = |key: &str| {
+            attrs
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.as_str())
+        };
+        match name {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.paragraph_break();
+                let level = usize::from(name.as_bytes()[1] - b'0');
+                self.out.push_str(&"#".repeat(level));
+                self.out.push(' ');
+            }
+            "br" => self.line_break(),
+            "hr" => {
+                self.paragraph_break();
+                self.out.push_str("---");
+                self.paragraph_break();
+            }
+            "pre" => {
+                self.paragraph_break();
+                self.out.push_str("```\n");
+                self.pre += 1;
+            }
+            "code" if self.pre == 0 => self.out.push('`'),
+            "strong" | "b" => self.out.push_str("**"),
+            "em" | "i" => self.out.push('*'),
+            "blockquote" => {
+                self.paragraph_break();
+                self.out.push_str("> ");
+            }
+            "ul" | "ol" => {
+                if self.lists.is_empty() {
+                    self.paragraph_break();
+                }
+                let start = attr("start").and_then(|s| s.parse().ok()).unwrap_or(1);
+                self.lists.push((name == "ol").then_some(start));
+            }
+            "li" => {
+                self.line_break();
+                let indent = "  ".repeat(self.lists.len().saturating_sub(1));
+                self.out.push_str(&indent);
+                match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        self.out.push_str(&format!("{}. ", number));
+                        *number += 1;
+                    }
+                    _ => self.out.push_str("- "),
+                }
+            }
+            "tr" => self.line_break(),
+            "td" | "th" => self.out.push_str("| "),
+            "a" => {
+                let href = attr("href").unwrap_or("").trim().to_string();
+                self.links.push((self.out.len(), href));
+            }
+            _ if BLOCKS.contains(&name) => self.paragraph_break(),
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, name: &str) {
+        match name {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "blockquote" | "p" => self.paragraph_break(),
+            "pre" if self.pre > 0 => {
+                self.pre -= 1;
+                if !self.out.ends_with('\n') {
+                    self.out.push('\n');
+                }
+                self.out.push_str("```");
+                self.paragraph_break();
+            }
+            "code" if self.pre == 0 => self.out.push('`'),
+            "strong" | "b" => self.out.push_str("**"),
+            "em" | "i" => self.out.push('*'),
+            "ul" | "ol" => {
+                self.lists.pop();
+                if self.lists.is_empty() {
+                    self.paragraph_break();
+                }
+            }
+            "td" | "th" => self.out.push(' '),
+            "a" => {
+                let Some((at, href)) = self.links.pop() else {
+                    return;
+                };
+                let linkable =
+                    !href.is_empty() && !href.starts_with('#') && !href.starts_with("javascript:");
+                if linkable && !self.out[at..].trim().is_empty() {
+                    self.trim_trailing_spaces();
+                    self.out.insert(at, '[');
+                    self.out.push_str(&format!("]({})", href));
+                }
+            }
+            _ if BLOCKS.contains(&name) => self.paragraph_break(),
+            _ => {}
+        }
+    }
+
+    fn finish(self) -> String {
+        let mut markdown = String::new();
+        let mut blank = false;
+        for line in self.out.lines().map(str::trim_end) {
+            if line.is_empty() {
+                blank = !markdown.is_empty();
+                continue;
+            }
+            if blank {
+                markdown.push('\n');
+                blank = false;
+            }
+            markdown.push_str(line);
+            markdown.push('\n');
+        }
+        markdown.trim_end().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_markdown() {
+        let html = r#"<!DOCTYPE html>
+<html><head><title>Rust &amp; You</title>
+<script>if (a < b) { document.write("<p>no</p>"); }</script></head>
+<body>
+  <nav><a href="/">Home</a> <a href="/blog">Blog</a></nav>
+  <article>
+    <h1>Ownership</h1>
+    <p>Each value has <em>one</em> owner.
+       See <a href="https://doc.rust-lang.org/book/">the book</a>.</p>
+    <ul><li>Moves</li><li>Borrows<ol><li>shared</li><li>mutable</li></ol></li></ul>
+    <pre><code>let s = String::from("hi");
+let t = s;</code></pre>
+    <!-- <p>hidden</p> -->
+    <aside>Sponsored</aside>
+  </article>
+  <footer>&copy; 2024</footer>
+</body></html>"#;
+
+        let page = to_markdown(html);
+        assert_eq!(page.title.as_deref(), Some("Rust & You"));
+        assert_eq!(
+            page.markdown,
+            "# Ownership\n\n\
+             Each value has *one* owner. See [the book](https://doc.rust-lang.org/book/).\n\n\
+             - Moves\n\
+             - Borrows\n\
+             \x20 1. shared\n\
+             \x20 2. mutable\n\n\
+             ```\n\
+             let s = String::from(\"hi\");\n\
+             let t = s;\n\
+             ```"
+        );
+    }
+
+    #[test]
+    fn test_fallbacks() {
+        let page = to_markdown("plain <b>text</b> &#x41;&#66; &bogus; 1 < 2");
+        assert_eq!(page.title, None);
+        assert_eq!(page.markdown, "plain **text** AB &bogus; 1 < 2");
+
+        let page = to_markdown(r#"<body><p>a<br>b</p><a href='#top'>top</a></body>"#);
+        assert_eq!(page.markdown, "a\nb\n\ntop");
+    }
+}
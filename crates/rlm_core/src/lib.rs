@@ -0,0 +1,44 @@
+//! # RLM Core
+//!
+//! Provider clients, parsing, and shared types for Recursive Language Models,
+//! without the Python/REPL machinery. Used directly by harnesses that only
+//! need tool-calling against a chat backend.
+
+pub mod answer;
+pub mod approval;
+pub mod backend;
+pub mod cache;
+pub mod error;
+pub mod files;
+pub mod html;
+pub mod log;
+pub mod mock;
+pub mod parsing;
+pub mod patch;
+pub mod ratelimit;
+pub mod tokens;
+pub mod types;
+pub mod user_config;
+
+// Re-exports
+pub use answer::{schema_errors, AnswerContext, AnswerDetector, AnswerFormat};
+pub use approval::{CodeApproval, CodeApprovalHook};
+pub use backend::{
+    create_backend, create_sub_backend, AnthropicBackend, ChatBackend, ChatParams, OpenAiBackend,
+    ToolDefinition, ToolUse,
+};
+pub use cache::{CacheStats, QueryCache};
+pub use error::{Result, RlmError};
+pub use files::write_atomic;
+pub use log::LogSink;
+pub use mock::MockBackend;
+pub use parsing::{ParserConfig, StreamEvent, StreamParser};
+pub use ratelimit::{RateLimit, RateLimitedBackend};
+pub use types::{
+    AdaptiveIterations, Attachment, Backend, BackendTimeouts, BatchCompletion, ChatCompletion,
+    CodeBlock, CompletionStatus, FixContext, Image, LintAction, LintFinding, LintPolicy, Message,
+    OutputTruncation, PartialRun, PromptInput, PythonEnv, QueryCacheConfig, QueryOverrides,
+    ReplLang, ReplMode, ReplResult, RlmCompletion, RlmConfig, RlmIteration, Role, Sampling,
+    SandboxPolicy, TaskMode, Usage, Workspace,
+};
+pub use user_config::{UserConfig, UserSettings};
@@ -0,0 +1,123 @@
+//! Destination for engine logging
+//!
+//! `verbose` and `exec_log` output goes through the [`LogSink`] in
+//! [`RlmConfig`](crate::types::RlmConfig), one line at a time. The default
+//! prints to stdout; hosts embedding the library can capture, silence, or
+//! redirect it instead.
+
+use std::fmt;
+use std::io::Write;
+use std::sync::Mutex;
+
+/// Receives engine log lines (without trailing newline)
+pub trait LogSink: Send + Sync {
+    fn log(&self, line: &str);
+
+    /// Log a code block about to run, as `│ `-prefixed lines
+    ///
+    /// Sinks that can do better, like a terminal with colors, override it.
+    fn log_code(&self, code: &str) {
+        for line in code.lines() {
+            self.log(&format!("│ {}", line));
+        }
+    }
+}
+
+impl fmt::Debug for dyn LogSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("LogSink")
+    }
+}
+
+impl<F> LogSink for F
+where
+    F: Fn(&str) + Send + Sync,
+{
+    fn log(&self, line: &str) {
+        self(line)
+    }
+}
+
+/// Print to stdout, flushing after every line
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdoutSink;
+
+impl LogSink for StdoutSink {
+    fn log(&self, line: &str) {
+        let mut out = std::io::stdout().lock();
+        let _ = writeln!(out, "{}", line);
+        let _ = out.flush();
+    }
+}
+
+/// Print to stderr, keeping stdout clean for program output
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StderrSink;
+
+impl LogSink for StderrSink {
+    fn log(&self, line: &str) {
+        eprintln!("{}", line);
+    }
+}
+
+/// Discard everything
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NullSink;
+
+impl LogSink for NullSink {
+    fn log(&self, _line: &str) {}
+}
+
+/// Write lines to any [`Write`] (file, buffer, pipe)
+pub struct WriterSink<W> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send> WriterSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<W: Write + Send> LogSink for WriterSink<W> {
+    fn log(&self, line: &str) {
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = writeln!(writer, "{}", line);
+        let _ = writer.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_writer_sink() {
+        let sink = WriterSink::new(Vec::new());
+        sink.log("first");
+        sink.log("");
+        sink.log("second");
+        assert_eq!(
+            String::from_utf8(sink.into_inner()).unwrap(),
+            "first\n\nsecond\n"
+        );
+    }
+
+    #[test]
+    fn test_closure_sink() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let captured = lines.clone();
+        let sink: Arc<dyn LogSink> =
+            Arc::new(move |line: &str| captured.lock().unwrap().push(line.to_string()));
+        sink.log("hello");
+        sink.log_code("x = 1\nprint(x)");
+        assert_eq!(*lines.lock().unwrap(), ["hello", "│ x = 1", "│ print(x)"]);
+    }
+}
@@ -0,0 +1,98 @@
+//! Scripted mock backend for tests
+//!
+//! Returns a programmable sequence of responses instead of calling a real
+//! provider, so the full loop (including code execution) can be exercised
+//! without network access or API keys.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use crate::error::{Result, RlmError};
+use crate::types::{Message, Usage};
+
+/// Mock LLM backend with a shared queue of scripted responses
+///
+/// Root calls and `llm_query` sub-calls both consume from the same queue, in
+/// call order. Clones share state, so a test can keep a handle to inspect the
+/// recorded requests after the run.
+#[derive(Debug, Clone, Default)]
+pub struct MockBackend {
+    responses: Arc<Mutex<VecDeque<String>>>,
+    requests: Arc<Mutex<Vec<Vec<Message>>>>,
+}
+
+impl MockBackend {
+    /// Create a mock returning the given responses in order
+    pub fn new<I, S>(responses: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            responses: Arc::new(Mutex::new(responses.into_iter().map(Into::into).collect())),
+            requests: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Append another response to the script
+    pub fn push_response(&self, response: impl Into<String>) {
+        self.responses.lock().unwrap().push_back(response.into());
+    }
+
+    /// Number of scripted responses not yet consumed
+    pub fn remaining(&self) -> usize {
+        self.responses.lock().unwrap().len()
+    }
+
+    /// All message lists received so far, in call order
+    pub fn requests(&self) -> Vec<Vec<Message>> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Record the request and pop the next scripted response
+    ///
+    /// Usage is reported as whitespace-separated word counts so token
+    /// accounting stays deterministic.
+    pub fn complete(&self, messages: &[Message]) -> Result<(String, Usage)> {
+        self.requests.lock().unwrap().push(messages.to_vec());
+
+        let response = self.responses.lock().unwrap().pop_front().ok_or_else(|| {
+            RlmError::Api("Mock backend has no scripted responses left".to_string())
+        })?;
+
+        let input_words: usize = messages
+            .iter()
+            .map(|m| m.content.split_whitespace().count())
+            .sum();
+        let usage = Usage::new(
+            input_words as u64,
+            response.split_whitespace().count() as u64,
+        );
+
+        Ok((response, usage))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_returns_responses_in_order() {
+        let mock = MockBackend::new(["first", "second answer"]);
+        let (r1, _) = mock.complete(&[Message::user("hi")]).unwrap();
+        let (r2, usage) = mock.complete(&[Message::user("hello there")]).unwrap();
+
+        assert_eq!(r1, "first");
+        assert_eq!(r2, "second answer");
+        assert_eq!(usage, Usage::new(2, 2));
+        assert_eq!(mock.remaining(), 0);
+        assert_eq!(mock.requests().len(), 2);
+    }
+
+    #[test]
+    fn test_mock_exhausted_is_error() {
+        let mock = MockBackend::default();
+        assert!(mock.complete(&[Message::user("hi")]).is_err());
+    }
+}
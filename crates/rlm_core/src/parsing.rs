@@ -0,0 +1,1420 @@
+//! Parsing of model responses
+//!
+//! The building blocks the RLM loop uses, for harnesses that drive models
+//! the same way: code blocks ([`find_code_blocks`]), answer markers
+//! ([`find_final_answer`], [`extract_final_json`]), XML-style tags
+//! ([`find_tags`]), the prose check that rejects `FINAL(...)` used in a
+//! sentence ([`looks_like_prose`]), and [`StreamParser`] for responses
+//! arriving token by token. Matches carry byte spans into the input, so
+//! callers can cut or highlight without searching again. [`ParserConfig`]
+//! swaps in other fence languages and markers.
+
+use regex::Regex;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::LazyLock;
+
+// Pre-compiled regexes for performance
+/// Tag after the opening fence of a code block: repl or python, also py,
+/// python3, any case
+static FENCE_LANGUAGE_RE: LazyLock<Regex> =
+    LazyLock::new(|| language_regex(&ParserConfig::default().fence_languages));
+
+/// Matches exactly one of `languages`
+fn language_regex(languages: &[String]) -> Regex {
+    let tags: Vec<String> = languages.iter().map(|l| regex::escape(l)).collect();
+    Regex::new(&format!(r"^(?i:{})$", tags.join("|"))).expect("tags are escaped")
+}
+
+/// Code-fence languages and answer markers the engine recognizes
+///
+/// The defaults match the built-in prompts. Models fine-tuned on other
+/// conventions can keep them, e.g.
+/// `ParserConfig::default().with_fence_language("ipython").with_answer_tag("result")`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParserConfig {
+    /// Tags that open a code block, any case (an empty tag matches bare
+    /// fences)
+    pub fence_languages: Vec<String>,
+    /// Call-style markers: `NAME(answer)` in the response text
+    pub final_markers: Vec<String>,
+    /// Tags wrapping the answer: `<NAME>answer</NAME>` in the response text
+    pub answer_tags: Vec<String>,
+    /// Line prefixes in the response text: `NAME answer`, the answer
+    /// running up to the next code block
+    pub answer_prefixes: Vec<String>,
+    /// Stdout line prefixes; the rest of the line is the answer
+    pub stdout_sentinels: Vec<String>,
+}
+
+impl Default for ParserConfig {
+    fn default() -> Self {
+        Self {
+            fence_languages: ["repl", "python", "python3", "py"]
+                .map(String::from)
+                .to_vec(),
+            final_markers: vec!["FINAL".to_string()],
+            answer_tags: vec!["answer".to_string()],
+            answer_prefixes: vec!["ANSWER:".to_string()],
+            stdout_sentinels: vec!["FINAL_ANSWER: ".to_string()],
+        }
+    }
+}
+
+impl ParserConfig {
+    pub fn with_fence_language(mut self, tag: impl Into<String>) -> Self {
+        self.fence_languages.push(tag.into());
+        self
+    }
+
+    pub fn with_final_marker(mut self, name: impl Into<String>) -> Self {
+        self.final_markers.push(name.into());
+        self
+    }
+
+    pub fn with_answer_tag(mut self, tag: impl Into<String>) -> Self {
+        self.answer_tags.push(tag.into());
+        self
+    }
+
+    pub fn with_answer_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.answer_prefixes.push(prefix.into());
+        self
+    }
+
+    pub fn with_stdout_sentinel(mut self, prefix: impl Into<String>) -> Self {
+        self.stdout_sentinels.push(prefix.into());
+        self
+    }
+
+    fn language_re(&self) -> Cow<'_, Regex> {
+        if self.fence_languages == Self::default().fence_languages {
+            Cow::Borrowed(&*FENCE_LANGUAGE_RE)
+        } else {
+            Cow::Owned(language_regex(&self.fence_languages))
+        }
+    }
+
+    /// [`find_code_blocks`] for these fence languages
+    pub fn find_code_blocks(&self, text: &str) -> Vec<FencedBlock> {
+        fenced_code_blocks(text, &self.language_re())
+    }
+
+    /// [`extract_code_blocks`] for these fence languages
+    pub fn extract_code_blocks(&self, text: &str) -> Vec<String> {
+        self.find_code_blocks(text)
+            .into_iter()
+            .map(|block| block.code)
+            .collect()
+    }
+
+    /// [`first_code_block_end`] for these fence languages
+    pub fn first_code_block_end(&self, text: &str) -> Option<usize> {
+        self.find_code_blocks(text)
+            .first()
+            .map(|block| block.span.end)
+    }
+
+    /// First answer marked by one of the final markers, then by one of the
+    /// answer tags, then by one of the answer prefixes
+    ///
+    /// Markers get the same treatment as `FINAL(...)` in
+    /// [`find_final_answer`]. Prefixes inside code blocks don't count.
+    pub fn find_answer(&self, text: &str, locals: &HashMap<String, String>) -> Option<AnswerMatch> {
+        self.final_markers
+            .iter()
+            .find_map(|marker| find_marker_answer(text, marker, locals))
+            .or_else(|| {
+                self.answer_tags
+                    .iter()
+                    .find_map(|tag| find_tagged_answer(text, tag))
+            })
+            .or_else(|| {
+                if self.answer_prefixes.is_empty() {
+                    return None;
+                }
+                let blocks = self.find_code_blocks(text);
+                self.answer_prefixes
+                    .iter()
+                    .find_map(|prefix| find_prefixed_answer(text, prefix, &blocks))
+            })
+    }
+
+    /// The answer text of [`Self::find_answer`]
+    pub fn extract_answer(&self, text: &str, locals: &HashMap<String, String>) -> Option<String> {
+        self.find_answer(text, locals).map(|m| m.answer)
+    }
+
+    /// Answer on a stdout line starting with one of the sentinels
+    pub fn extract_stdout_answer(&self, stdout: &str) -> Option<String> {
+        stdout.lines().find_map(|line| {
+            self.stdout_sentinels
+                .iter()
+                .find_map(|prefix| line.strip_prefix(prefix.as_str()))
+                .map(str::to_string)
+        })
+    }
+}
+
+/// A code block found in a response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FencedBlock {
+    /// Tag after the opening fence, as written (`repl`, `py`, ...)
+    pub language: String,
+    /// The code, without the fences
+    pub code: String,
+    /// Byte range from the opening fence through the closing one
+    pub span: Range<usize>,
+}
+
+/// Leading run of three or more backticks or tildes on a line, after
+/// any indentation, and the rest of the line
+fn line_fence(line: &str) -> Option<(Range<usize>, &str)> {
+    let indent = line.len() - line.trim_start_matches([' ', '\t']).len();
+    let rest = &line[indent..];
+    let fence_char = rest.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+    let len = rest.len() - rest.trim_start_matches(fence_char).len();
+    (len >= 3).then(|| (indent..indent + len, &rest[len..]))
+}
+
+/// Fence opened by `line` and the info string after it
+fn opening_fence(line: &str) -> Option<(&str, &str)> {
+    let (run, info) = line_fence(line)?;
+    let fence = &line[run];
+    let info = info.trim();
+    // ```a``` on one line is inline code, not a fence
+    (!(fence.starts_with('`') && info.contains('`'))).then_some((fence, info))
+}
+
+/// End of the closing fence on `line` for a block opened by `fence`
+///
+/// The line holds only a run of the same character, at least as long as
+/// the opening one.
+fn closing_fence(line: &str, fence: &str) -> Option<usize> {
+    let (run, rest) = line_fence(line)?;
+    let closes = line[run.clone()].starts_with(&fence[..1])
+        && run.len() >= fence.len()
+        && rest.trim().is_empty();
+    closes.then_some(run.end)
+}
+
+/// Code blocks whose opening fence carries a tag matching `language_re`
+///
+/// Fences pair up line by line as in Markdown: a block closes at a line of
+/// its own fence characters, so a ``` inside a string or a ~~~ block
+/// doesn't end it, and blocks quoted inside another fence (a ````markdown
+/// example) aren't code to run. A block that never closes is not a block.
+fn fenced_code_blocks(text: &str, language_re: &Regex) -> Vec<FencedBlock> {
+    let mut blocks = Vec::new();
+    // Fence of the open block, its start, its body's start, and its
+    // language if it is a code block
+    let mut open: Option<(&str, usize, usize, Option<&str>)> = None;
+    let mut pos = 0;
+    for line in text.split_inclusive('\n') {
+        let start = pos;
+        pos += line.len();
+        let Some((fence, block_start, body, language)) = open else {
+            open = opening_fence(line).map(|(fence, info)| {
+                let language = language_re.is_match(info).then_some(info);
+                (fence, start, pos, language)
+            });
+            continue;
+        };
+        let Some(end) = closing_fence(line, fence) else {
+            continue;
+        };
+        open = None;
+        if let Some(language) = language {
+            blocks.push(FencedBlock {
+                language: language.to_string(),
+                code: text[body..start].to_string(),
+                span: block_start..start + end,
+            });
+        }
+    }
+    blocks
+}
+
+/// Find code blocks delimited by ```repl``` or ```python``` markers
+///
+/// Tolerates the variants local models emit: ```py, ```python3,
+/// ``` repl, ~~~python, and trailing spaces after the tag.
+pub fn find_code_blocks(text: &str) -> Vec<FencedBlock> {
+    fenced_code_blocks(text, &FENCE_LANGUAGE_RE)
+}
+
+/// The code of each block [`find_code_blocks`] finds
+pub fn extract_code_blocks(text: &str) -> Vec<String> {
+    find_code_blocks(text)
+        .into_iter()
+        .map(|block| block.code)
+        .collect()
+}
+
+/// Byte offset just past the closing fence of the first code block
+pub fn first_code_block_end(text: &str) -> Option<usize> {
+    find_code_blocks(text).first().map(|block| block.span.end)
+}
+
+/// An XML-style tag pair found in text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagMatch {
+    /// What follows the colon in `<name:argument>`
+    pub argument: Option<String>,
+    /// Text between the tags, untrimmed
+    pub content: String,
+    /// Byte range from the opening tag through the closing one
+    pub span: Range<usize>,
+}
+
+/// Every `<name>...</name>` or `<name:argument>...</name>` pair, in order
+///
+/// Scanning stops at an opening tag that is never closed.
+pub fn find_tags(text: &str, name: &str) -> Vec<TagMatch> {
+    let open = format!("<{}", name);
+    let close = format!("</{}>", name);
+    let mut tags = Vec::new();
+    let mut pos = 0;
+    while let Some(offset) = text[pos..].find(&open) {
+        let start = pos + offset;
+        let after = start + open.len();
+        let argument = match text[after..].chars().next() {
+            Some('>') => None,
+            Some(':') => match text[after..].find('>') {
+                Some(end) => Some(text[after + 1..after + end].to_string()),
+                None => break,
+            },
+            // `<names>` and the like
+            _ => {
+                pos = after;
+                continue;
+            }
+        };
+        let content_start = after + argument.as_ref().map_or(0, |a| a.len() + 1) + 1;
+        let Some(len) = text[content_start..].find(&close) else {
+            break;
+        };
+        pos = content_start + len + close.len();
+        tags.push(TagMatch {
+            argument,
+            content: text[content_start..content_start + len].to_string(),
+            span: start..pos,
+        });
+    }
+    tags
+}
+
+/// What a [`StreamParser`] noticed in the newest chunk of a response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamEvent {
+    /// A code block's opening fence arrived
+    CodeBlockStarted,
+    /// `llm_output(` appeared in the open code block
+    LlmOutput,
+    /// A code block closed, with its code
+    CodeBlockFinished(String),
+    /// A complete answer marker (`FINAL(...)`) outside code blocks
+    FinalAnswer(String),
+}
+
+/// Incremental parser for a response arriving token by token
+///
+/// Feed chunks to [`Self::push`] as they stream in. Events fire once each,
+/// as soon as the text seen so far settles them, so the caller can cut
+/// generation off after the first complete code block (see
+/// [`Self::first_block_end`]) instead of paying for the rest. Parses the
+/// same fences as [`extract_code_blocks`], or those of a [`ParserConfig`]
+/// (see [`Self::with_config`]). A fence line is settled by its newline;
+/// [`Self::finish`] settles a last line that has none.
+#[derive(Debug, Clone)]
+pub struct StreamParser {
+    language_re: Regex,
+    config: ParserConfig,
+    text: String,
+    /// Start of the first line not yet settled
+    line: usize,
+    /// Start of the prose being scanned for answer markers
+    prose: usize,
+    /// Open fence, where its body starts, and whether it is a code block
+    fence: Option<(String, usize, bool)>,
+    llm_output_seen: bool,
+    final_seen: bool,
+    first_block_end: Option<usize>,
+}
+
+impl Default for StreamParser {
+    fn default() -> Self {
+        Self::with_config(&ParserConfig::default())
+    }
+}
+
+impl StreamParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parser for the fences and answer markers of `config`
+    pub fn with_config(config: &ParserConfig) -> Self {
+        Self {
+            language_re: config.language_re().into_owned(),
+            config: config.clone(),
+            text: String::new(),
+            line: 0,
+            prose: 0,
+            fence: None,
+            llm_output_seen: false,
+            final_seen: false,
+            first_block_end: None,
+        }
+    }
+
+    /// Add the next chunk and return what it completed
+    pub fn push(&mut self, chunk: &str) -> Vec<StreamEvent> {
+        self.text.push_str(chunk);
+        let mut events = Vec::new();
+        while let Some(len) = self.text[self.line..].find('\n') {
+            self.settle_line(self.line + len + 1, &mut events);
+        }
+        match self.fence {
+            Some((_, body, true)) => {
+                if !self.llm_output_seen && self.text[body..].contains("llm_output(") {
+                    self.llm_output_seen = true;
+                    events.push(StreamEvent::LlmOutput);
+                }
+            }
+            Some(_) => {}
+            None => self.scan_prose(self.text.len(), &mut events),
+        }
+        events
+    }
+
+    /// Settle the last line at the end of the response
+    pub fn finish(&mut self) -> Vec<StreamEvent> {
+        let mut events = Vec::new();
+        if self.line < self.text.len() {
+            self.settle_line(self.text.len(), &mut events);
+        }
+        events
+    }
+
+    /// Pair fences on the line ending at `end`
+    fn settle_line(&mut self, end: usize, events: &mut Vec<StreamEvent>) {
+        let start = std::mem::replace(&mut self.line, end);
+        let line = &self.text[start..end];
+        match self.fence.take() {
+            None => {
+                let Some((fence, info)) = opening_fence(line) else {
+                    return;
+                };
+                let code = self.language_re.is_match(info);
+                self.fence = Some((fence.to_string(), end, code));
+                self.scan_prose(start, events);
+                if code {
+                    self.llm_output_seen = false;
+                    events.push(StreamEvent::CodeBlockStarted);
+                }
+            }
+            Some((fence, body, code)) => {
+                let Some(fence_end) = closing_fence(line, &fence) else {
+                    self.fence = Some((fence, body, code));
+                    return;
+                };
+                self.prose = end;
+                if !code {
+                    return;
+                }
+                let code = self.text[body..start].to_string();
+                if !self.llm_output_seen && code.contains("llm_output(") {
+                    events.push(StreamEvent::LlmOutput);
+                }
+                self.first_block_end.get_or_insert(start + fence_end);
+                events.push(StreamEvent::CodeBlockFinished(code));
+            }
+        }
+    }
+
+    /// Look for an answer marker in the prose before `end`
+    fn scan_prose(&mut self, end: usize, events: &mut Vec<StreamEvent>) {
+        if self.final_seen {
+            return;
+        }
+        let prose = &self.text[self.prose..end];
+        if let Some(answer) = self.config.extract_answer(prose, &HashMap::new()) {
+            self.final_seen = true;
+            events.push(StreamEvent::FinalAnswer(answer));
+        }
+    }
+
+    /// Byte offset just past the first closed code block, once it closed
+    pub fn first_block_end(&self) -> Option<usize> {
+        self.first_block_end
+    }
+
+    /// Whether a code block is open
+    pub fn in_code_block(&self) -> bool {
+        matches!(self.fence, Some((_, _, true)))
+    }
+
+    /// The response so far
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The response, without anything after the first closed code block
+    pub fn into_text(mut self) -> String {
+        if let Some(end) = self.first_block_end {
+            self.text.truncate(end);
+        }
+        self.text
+    }
+}
+
+/// A ```json block
+static JSON_FENCE_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"```[ \t]*(?i:json)[ \t]*\r?\n([\s\S]*?)```").expect("invalid regex")
+});
+
+/// Extract a structured answer from a response
+///
+/// Looks for `FINAL_JSON(<json>)` (placed like `FINAL(...)`), then, in a
+/// response without code blocks, for a ```json block. Returns `None` if
+/// neither holds valid JSON.
+pub fn extract_final_json(text: &str) -> Option<serde_json::Value> {
+    let marker = "FINAL_JSON(";
+    for (pos, _) in text.match_indices(marker) {
+        let valid_position = text[..pos]
+            .chars()
+            .last()
+            .is_none_or(|c| c.is_whitespace() || c == ':');
+        if !valid_position {
+            continue;
+        }
+        let rest = &text[pos + marker.len()..];
+        let mut values = serde_json::Deserializer::from_str(rest).into_iter();
+        if let Some(Ok(value)) = values.next() {
+            if rest[values.byte_offset()..].trim_start().starts_with(')') {
+                return Some(value);
+            }
+        }
+    }
+
+    if !extract_code_blocks(text).is_empty() {
+        return None;
+    }
+    JSON_FENCE_RE
+        .captures_iter(text)
+        .find_map(|cap| serde_json::from_str(&cap[1]).ok())
+}
+
+/// Parse a final answer that is a JSON object or array, bare or as
+/// [`extract_final_json`] finds it
+pub fn parse_json_answer(answer: &str) -> Option<serde_json::Value> {
+    serde_json::from_str::<serde_json::Value>(answer.trim())
+        .ok()
+        .filter(|v| v.is_object() || v.is_array())
+        .or_else(|| extract_final_json(answer))
+}
+
+/// How an answer was marked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnswerMarker {
+    /// `NAME(answer)`, e.g. `FINAL(42)`
+    Call(String),
+    /// `<NAME>answer</NAME>`
+    Tag(String),
+    /// `NAME answer` at the start of a line, e.g. `ANSWER: 42`
+    Prefix(String),
+}
+
+/// An answer found in a response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnswerMatch {
+    pub marker: AnswerMarker,
+    /// The answer, unquoted or resolved through locals
+    pub answer: String,
+    /// Byte range of the marker, through its closing `)` or tag, or the
+    /// end of a prefixed answer
+    pub span: Range<usize>,
+}
+
+/// Check for FINAL(answer) pattern - handles nested parentheses correctly
+pub fn extract_final_answer(text: &str) -> Option<String> {
+    extract_final_answer_raw(text, &HashMap::new())
+}
+
+/// Find the first valid `FINAL(...)` (see [`extract_final_answer_raw`])
+pub fn find_final_answer(text: &str, locals: &HashMap<String, String>) -> Option<AnswerMatch> {
+    find_marker_answer(text, "FINAL", locals)
+}
+
+/// Check for FINAL(answer) pattern with variable resolution from locals
+///
+/// This function is strict about what constitutes a valid FINAL() call:
+/// - Must be at the start of a line OR preceded by whitespace/punctuation
+/// - Content must not look like English prose (descriptive text)
+pub fn extract_final_answer_raw(text: &str, locals: &HashMap<String, String>) -> Option<String> {
+    find_final_answer(text, locals).map(|m| m.answer)
+}
+
+/// The first `<tag>...</tag>` pair that isn't blank
+fn find_tagged_answer(text: &str, tag: &str) -> Option<AnswerMatch> {
+    find_tags(text, tag)
+        .into_iter()
+        .find(|m| m.argument.is_none() && !m.content.trim().is_empty())
+        .map(|m| AnswerMatch {
+            marker: AnswerMarker::Tag(tag.to_string()),
+            answer: m.content.trim().to_string(),
+            span: m.span,
+        })
+}
+
+/// The first non-blank answer on a line starting with `prefix`, outside
+/// `blocks`, running up to the next of them
+fn find_prefixed_answer(text: &str, prefix: &str, blocks: &[FencedBlock]) -> Option<AnswerMatch> {
+    let mut pos = 0;
+    for line in text.split_inclusive('\n') {
+        let start = pos + line.len() - line.trim_start_matches([' ', '\t']).len();
+        pos += line.len();
+        if !text[start..].starts_with(prefix) || blocks.iter().any(|b| b.span.contains(&start)) {
+            continue;
+        }
+        let end = blocks
+            .iter()
+            .map(|b| b.span.start)
+            .find(|&block_start| block_start > start)
+            .unwrap_or(text.len());
+        let answer = text[start + prefix.len()..end].trim();
+        if !answer.is_empty() {
+            return Some(AnswerMatch {
+                marker: AnswerMarker::Prefix(prefix.to_string()),
+                answer: answer.to_string(),
+                span: start..end,
+            });
+        }
+    }
+    None
+}
+
+/// [`find_final_answer`] for `marker(...)`
+fn find_marker_answer(
+    text: &str,
+    marker: &str,
+    locals: &HashMap<String, String>,
+) -> Option<AnswerMatch> {
+    let start_marker = format!("{}(", marker);
+
+    // Find all occurrences and check each one
+    let mut search_start = 0;
+    while let Some(pos) = text[search_start..].find(&start_marker) {
+        let start_pos = search_start + pos;
+
+        // Check that FINAL( is at a valid position:
+        // - Start of string
+        // - After newline
+        // - After whitespace
+        // - After colon (like "Answer: FINAL()")
+        let valid_position = start_pos == 0 || {
+            let prev_char = text[..start_pos].chars().last().unwrap();
+            prev_char == '\n' || prev_char.is_whitespace() || prev_char == ':'
+        };
+
+        if !valid_position {
+            search_start = start_pos + 1;
+            continue;
+        }
+
+        let content_start = start_pos + start_marker.len();
+
+        // Count parentheses to find the matching close
+        // - Skip parens inside quoted strings (handles unbalanced parens in content)
+        // - Skip emoticon patterns like :) ;) =)
+        let mut depth = 1;
+        let mut end_pos = None;
+        let remaining = &text[content_start..];
+        let mut in_string: Option<char> = None; // Track if we're inside a string and which quote
+
+        for (i, ch) in remaining.char_indices() {
+            // Handle string tracking (skip parens inside strings)
+            if in_string.is_some() {
+                if Some(ch) == in_string {
+                    // Check for escape (look at previous char)
+                    let is_escaped = i > 0 && remaining[..i].ends_with('\\');
+                    if !is_escaped {
+                        in_string = None;
+                    }
+                }
+                continue; // Don't count parens inside strings
+            }
+
+            // Check for string start
+            if ch == '"' || ch == '\'' {
+                in_string = Some(ch);
+                continue;
+            }
+
+            match ch {
+                '(' => depth += 1,
+                ')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        // Check if this looks like an emoticon (skip it if so)
+                        let is_emoticon = i > 0 && {
+                            let prev_char = remaining[..i].chars().last().unwrap();
+                            matches!(
+                                prev_char,
+                                ':' | ';' | '=' | '8' | 'X' | 'x' | 'D' | 'P' | 'p'
+                            )
+                        };
+
+                        if is_emoticon {
+                            // This is likely an emoticon, keep going
+                            depth = 1; // Reset depth to continue looking
+                        } else {
+                            end_pos = Some(content_start + i);
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // If we didn't find a valid close, try again without emoticon skipping
+        if end_pos.is_none() {
+            depth = 1;
+            in_string = None;
+            for (i, ch) in remaining.char_indices() {
+                // Handle string tracking
+                if in_string.is_some() {
+                    if Some(ch) == in_string {
+                        let is_escaped = i > 0 && remaining[..i].ends_with('\\');
+                        if !is_escaped {
+                            in_string = None;
+                        }
+                    }
+                    continue;
+                }
+                if ch == '"' || ch == '\'' {
+                    in_string = Some(ch);
+                    continue;
+                }
+
+                match ch {
+                    '(' => depth += 1,
+                    ')' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            end_pos = Some(content_start + i);
+                            break;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        if let Some(end) = end_pos {
+            let content = text[content_start..end].trim().to_string();
+
+            // Reject if content looks like descriptive English text
+            // (e.g., "Output from executing code", "The result of the calculation")
+            if looks_like_prose(&content) {
+                search_start = end + 1;
+                continue;
+            }
+
+            // If content looks like a variable name (identifier), try to resolve it from locals
+            let resolved = is_identifier(&content)
+                .then(|| locals.get(&content).cloned())
+                .flatten();
+
+            // If content is a quoted string, unescape it
+            let answer = resolved.unwrap_or_else(|| unescape_string_literal(&content));
+
+            return Some(AnswerMatch {
+                marker: AnswerMarker::Call(marker.to_string()),
+                answer,
+                span: start_pos..end + 1,
+            });
+        }
+
+        search_start = start_pos + 1;
+    }
+
+    None
+}
+
+/// Check if text looks like descriptive English prose rather than an answer
+///
+/// The check behind rejecting `FINAL(Output from executing code)` and the
+/// like, where a model mentions the marker instead of answering.
+pub fn looks_like_prose(text: &str) -> bool {
+    let lower = text.to_lowercase();
+
+    // If text contains code-like patterns (function calls), it's probably valid
+    // even if it happens to contain some English words
+    if has_code_patterns(text) {
+        return false;
+    }
+
+    // Common prose patterns that indicate descriptive text, not actual answers
+    // These must be at the START of the content to be considered prose
+    let prose_prefixes = [
+        "output from",
+        "result of",
+        "this is the",
+        "this is a",
+        "the result",
+        "here is",
+    ];
+
+    for prefix in &prose_prefixes {
+        if lower.starts_with(prefix) {
+            return true;
+        }
+    }
+
+    // Words that strongly indicate meta-description anywhere in text
+    let strong_prose_indicators = [
+        "executing code",
+        "execution of",
+        "demonstration of",
+        "example of how",
+    ];
+
+    for indicator in &strong_prose_indicators {
+        if lower.contains(indicator) {
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Check if text contains code-like patterns (function calls, math operations)
+fn has_code_patterns(text: &str) -> bool {
+    // Look for function call patterns like foo(x) or bar(1, 2)
+    let mut chars = text.chars().peekable();
+    let mut in_identifier = false;
+
+    while let Some(c) = chars.next() {
+        if c.is_alphabetic() || c == '_' {
+            in_identifier = true;
+        } else if c == '(' && in_identifier {
+            // Found identifier followed by ( - looks like function call
+            return true;
+        } else if !c.is_alphanumeric() && c != '_' {
+            in_identifier = false;
+        }
+    }
+
+    // Also check for math/code operators
+    text.contains('+') || text.contains('*') || text.contains('/') || text.contains('[')
+}
+
+/// Check if a string is a valid Python identifier (variable name)
+fn is_identifier(s: &str) -> bool {
+    if s.is_empty() {
+        return false;
+    }
+    let mut chars = s.chars();
+    let first = chars.next().unwrap();
+    if !first.is_alphabetic() && first != '_' {
+        return false;
+    }
+    chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Strip quotes and unescape \n to actual newlines
+fn unescape_string_literal(s: &str) -> String {
+    let t = s.trim();
+    // Strip matching quotes
+    let inner =
+        if (t.starts_with('"') && t.ends_with('"')) || (t.starts_with('\'') && t.ends_with('\'')) {
+            &t[1..t.len() - 1]
+        } else {
+            return s.to_string();
+        };
+    // Simple unescape
+    inner.replace("\\n", "\n").replace("\\t", "\t")
+}
+
+/// Extract FINAL answer - handles both FINAL("literal") and FINAL(var),
+/// falling back to `<answer>...</answer>` and a line-leading `ANSWER:`
+pub fn extract_answer(text: &str, locals: &HashMap<String, String>) -> Option<String> {
+    ParserConfig::default().extract_answer(text, locals)
+}
+
+/// Extract FINAL_ANSWER from code execution stdout
+/// This is printed when FINAL() is called from within code
+pub fn extract_final_answer_from_stdout(stdout: &str) -> Option<String> {
+    for line in stdout.lines() {
+        if let Some(answer) = line.strip_prefix("FINAL_ANSWER: ") {
+            return Some(answer.to_string());
+        }
+    }
+    None
+}
+
+/// Sanitize a final answer for JSON transport
+///
+/// Decodes answers that are a Python bytes repr (`b'\\xe2\\x82\\xac'`) as
+/// UTF-8 (lossy) and strips control characters other than `\n`, `\r`, `\t`.
+/// Returns `None` when the answer is already clean.
+pub fn sanitize_final_answer(answer: &str) -> Option<String> {
+    let decoded = decode_bytes_repr(answer);
+    let source = decoded.as_deref().unwrap_or(answer);
+
+    let cleaned: String = source
+        .chars()
+        .filter(|&c| !c.is_control() || matches!(c, '\n' | '\r' | '\t'))
+        .collect();
+
+    if cleaned == answer {
+        None
+    } else {
+        Some(cleaned)
+    }
+}
+
+/// Decode a Python bytes literal (`b'...'` / `b"..."`) into a string
+fn decode_bytes_repr(text: &str) -> Option<String> {
+    let t = text.trim();
+    let inner = t
+        .strip_prefix("b'")
+        .and_then(|r| r.strip_suffix('\''))
+        .or_else(|| t.strip_prefix("b\"").and_then(|r| r.strip_suffix('"')))?;
+
+    let mut bytes = Vec::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0u8; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(b) => bytes.push(b),
+                    Err(_) => return None,
+                }
+            }
+            Some('n') => bytes.push(b'\n'),
+            Some('r') => bytes.push(b'\r'),
+            Some('t') => bytes.push(b'\t'),
+            Some('0') => bytes.push(0),
+            Some(other @ ('\\' | '\'' | '"')) => bytes.push(other as u8),
+            Some(other) => {
+                bytes.push(b'\\');
+                let mut buf = [0u8; 4];
+                bytes.extend_from_slice(other.encode_utf8(&mut buf).as_bytes());
+            }
+            None => bytes.push(b'\\'),
+        }
+    }
+
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_code_blocks_repl() {
+        let text = r#"
+Here's some code:
+
+```repl
+x = 1 + 1
+print(x)
+```
+
+And more text.
+"#;
+        let blocks = extract_code_blocks(text);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0], "x = 1 + 1\nprint(x)\n");
+    }
+
+    #[test]
+    fn test_extract_code_blocks_python() {
+        let text = r#"
+```python
+def foo():
+    return 42
+```
+"#;
+        let blocks = extract_code_blocks(text);
+        assert_eq!(blocks.len(), 1);
+        assert!(blocks[0].contains("def foo():"));
+    }
+
+    #[test]
+    fn test_extract_multiple_code_blocks() {
+        let text = r#"
+First block:
+```repl
+a = 1
+```
+
+Second block:
+```python
+b = 2
+```
+"#;
+        let blocks = extract_code_blocks(text);
+        assert_eq!(blocks.len(), 2);
+        assert!(blocks[0].contains("a = 1"));
+        assert!(blocks[1].contains("b = 2"));
+    }
+
+    #[test]
+    fn test_extract_code_blocks_none() {
+        let text = "No code blocks here, just plain text.";
+        let blocks = extract_code_blocks(text);
+        assert!(blocks.is_empty());
+    }
+
+    #[test]
+    fn test_extract_code_blocks_other_language() {
+        let text = r#"
+```javascript
+console.log("hello");
+```
+"#;
+        let blocks = extract_code_blocks(text);
+        assert!(blocks.is_empty()); // Only repl/python
+    }
+
+    #[test]
+    fn test_extract_code_blocks_fence_variants() {
+        for open in [
+            "```py",
+            "```python3",
+            "``` repl",
+            "```Python  ",
+            "~~~python",
+            "````repl",
+        ] {
+            let close = &open[..open.find(|c| c != '`' && c != '~').unwrap()];
+            let text = format!("Let's look:\n{}\nprint(1)\n  {}\ndone", open, close);
+            assert_eq!(extract_code_blocks(&text), ["print(1)\n"], "{}", open);
+            assert_eq!(
+                &text[..first_code_block_end(&text).unwrap()],
+                format!("Let's look:\n{}\nprint(1)\n  {}", open, close)
+            );
+        }
+        // A ``` line inside a ~~~ block doesn't close it
+        let text = "~~~python\nprint(\"\"\"\n```\n\"\"\")\n~~~";
+        assert_eq!(extract_code_blocks(text), ["print(\"\"\"\n```\n\"\"\")\n"]);
+
+        assert!(extract_code_blocks("```pyth\nx\n```").is_empty());
+        assert!(extract_code_blocks("```python\nnever closed").is_empty());
+    }
+
+    #[test]
+    fn test_fences_pair_up() {
+        // Closing fence at the very end, with no newline after it
+        let text = "Compute:\n```repl\nx = 1\n```";
+        assert_eq!(extract_code_blocks(text), ["x = 1\n"]);
+        assert_eq!(first_code_block_end(text), Some(text.len()));
+
+        // A ``` inside the code doesn't close the block
+        let text = "```repl\nfence = \"```\"\nprint(fence)\n```\nok";
+        assert_eq!(
+            extract_code_blocks(text),
+            ["fence = \"```\"\nprint(fence)\n"]
+        );
+
+        // Quoted examples aren't code to run
+        let text = "Format:\n````markdown\n```repl\nexample()\n```\n````\n> ```repl\n> quoted()\n> ```\n```repl\nreal()\n```";
+        let blocks = find_code_blocks(text);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].code, "real()\n");
+        assert_eq!(blocks[0].span.end, text.len());
+        assert!(extract_code_blocks("Inline ```repl x``` only").is_empty());
+    }
+
+    #[test]
+    fn test_stream_parser_events() {
+        let response =
+            "Let me look.\n```repl\nx = len(context)\nllm_output(str(x))\n```\nMore text\n```repl\ny = 1\n```";
+        let mut parser = StreamParser::new();
+        let mut events = Vec::new();
+        // Three bytes at a time splits both fences and the llm_output call
+        for chunk in response.as_bytes().chunks(3) {
+            events.extend(parser.push(std::str::from_utf8(chunk).unwrap()));
+            if parser.first_block_end().is_some() {
+                break;
+            }
+        }
+        assert_eq!(
+            events,
+            [
+                StreamEvent::CodeBlockStarted,
+                StreamEvent::LlmOutput,
+                StreamEvent::CodeBlockFinished(
+                    "x = len(context)\nllm_output(str(x))\n".to_string()
+                ),
+            ]
+        );
+        assert!(!parser.in_code_block());
+        let end = parser.first_block_end().unwrap();
+        assert_eq!(&parser.text()[..end], &response[..end]);
+        assert!(parser.into_text().ends_with("llm_output(str(x))\n```"));
+    }
+
+    #[test]
+    fn test_stream_parser_final_answer() {
+        let mut parser = StreamParser::new();
+        assert!(parser.push("Done. FINAL(4").is_empty());
+        assert_eq!(
+            parser.push("2) thanks"),
+            [StreamEvent::FinalAnswer("42".to_string())]
+        );
+        // Inside code it's just code
+        assert_eq!(
+            parser.push("\n~~~python\nFINAL(1)\n"),
+            [StreamEvent::CodeBlockStarted]
+        );
+        assert!(parser.push("FINAL(2)").is_empty());
+        assert!(parser.in_code_block());
+    }
+
+    #[test]
+    fn test_stream_parser_finish() {
+        let mut parser = StreamParser::new();
+        assert_eq!(
+            parser.push("```repl\nx = 1\n```"),
+            [StreamEvent::CodeBlockStarted]
+        );
+        // Could still become ```python, which wouldn't close the block
+        assert!(parser.in_code_block());
+        assert_eq!(
+            parser.finish(),
+            [StreamEvent::CodeBlockFinished("x = 1\n".to_string())]
+        );
+        assert_eq!(parser.first_block_end(), Some(parser.text().len()));
+    }
+
+    #[test]
+    fn test_spans_and_languages() {
+        let text = "Plan:\n```py\nx = 1\n```\nthen FINAL(x)";
+        let blocks = find_code_blocks(text);
+        assert_eq!(blocks[0].language, "py");
+        assert_eq!(&text[blocks[0].span.clone()], "```py\nx = 1\n```");
+
+        let locals = HashMap::from([("x".to_string(), "1".to_string())]);
+        let found = find_final_answer(text, &locals).unwrap();
+        assert_eq!(found.marker, AnswerMarker::Call("FINAL".to_string()));
+        assert_eq!(found.answer, "1");
+        assert_eq!(&text[found.span], "FINAL(x)");
+
+        let found = ParserConfig::default()
+            .find_answer("ok <answer> 42 </answer>", &locals)
+            .unwrap();
+        assert_eq!(found.marker, AnswerMarker::Tag("answer".to_string()));
+        assert_eq!((found.answer.as_str(), found.span), ("42", 3..24));
+    }
+
+    #[test]
+    fn test_answer_prefix() {
+        let locals = HashMap::new();
+        let text = "```repl\nprint(\"x\")\nANSWER: not this\n```\n  ANSWER: Paris,\nthe capital.\n```repl\nmore()\n```";
+        let found = ParserConfig::default().find_answer(text, &locals).unwrap();
+        assert_eq!(found.marker, AnswerMarker::Prefix("ANSWER:".to_string()));
+        assert_eq!(found.answer, "Paris,\nthe capital.");
+        assert!(text[found.span].starts_with("ANSWER: Paris"));
+
+        assert_eq!(
+            extract_answer("ANSWER:\n42", &locals),
+            Some("42".to_string())
+        );
+        assert_eq!(extract_answer("MY ANSWER: 42", &locals), None);
+        let parser = ParserConfig {
+            answer_prefixes: Vec::new(),
+            ..Default::default()
+        }
+        .with_answer_prefix("Result =");
+        assert_eq!(
+            parser.extract_answer("ANSWER: no\nResult = 7", &locals),
+            Some("7".to_string())
+        );
+    }
+
+    #[test]
+    fn test_find_tags() {
+        let text = "<tool:grep>foo</tool> <tools>no</tools> <tool>bare</tool><tool:x>open";
+        let tags = find_tags(text, "tool");
+        assert_eq!(tags.len(), 2);
+        assert_eq!(tags[0].argument.as_deref(), Some("grep"));
+        assert_eq!(tags[0].content, "foo");
+        assert_eq!(&text[tags[0].span.clone()], "<tool:grep>foo</tool>");
+        assert_eq!(tags[1].argument, None);
+        assert_eq!(tags[1].content, "bare");
+    }
+
+    #[test]
+    fn test_extract_final_json() {
+        let text = "Done.\nFINAL_JSON({\"total\": 3, \"names\": [\"a (1)\", \"b\"]}) and that's it";
+        assert_eq!(
+            extract_final_json(text),
+            Some(serde_json::json!({"total": 3, "names": ["a (1)", "b"]}))
+        );
+        assert_eq!(extract_final_json("x.FINAL_JSON([1])"), None);
+        assert_eq!(extract_final_json("FINAL_JSON({\"a\": 1"), None);
+
+        let fenced = "Here you go:\n```json\n{\"ok\": true}\n```";
+        assert_eq!(
+            extract_final_json(fenced),
+            Some(serde_json::json!({"ok": true}))
+        );
+        // Alongside code, a json block is just data
+        assert_eq!(
+            extract_final_json("```repl\nx = 1\n```\n```json\n{}\n```"),
+            None
+        );
+
+        assert_eq!(
+            parse_json_answer(" [1, 2] "),
+            Some(serde_json::json!([1, 2]))
+        );
+        assert_eq!(parse_json_answer("42"), None);
+    }
+
+    #[test]
+    fn test_extract_final_answer_simple() {
+        let text = "The answer is FINAL(42)";
+        assert_eq!(extract_final_answer(text), Some("42".to_string()));
+    }
+
+    #[test]
+    fn test_extract_final_answer_with_text() {
+        let text = "After calculation, FINAL(hello world) is the result.";
+        assert_eq!(extract_final_answer(text), Some("hello world".to_string()));
+    }
+
+    #[test]
+    fn test_extract_final_answer_multiline() {
+        let text = r#"FINAL(line 1
+line 2
+line 3)"#;
+        let answer = extract_final_answer(text).unwrap();
+        assert!(answer.contains("line 1"));
+        assert!(answer.contains("line 2"));
+        assert!(answer.contains("line 3"));
+    }
+
+    #[test]
+    fn test_extract_final_answer_none() {
+        let text = "No final answer here";
+        assert_eq!(extract_final_answer(text), None);
+    }
+
+    #[test]
+    fn test_extract_final_answer_nested_parens() {
+        let text = "FINAL(The answer is foo(x) + bar(y, z))";
+        assert_eq!(
+            extract_final_answer(text),
+            Some("The answer is foo(x) + bar(y, z)".to_string())
+        );
+    }
+
+    #[test]
+    fn test_extract_final_answer_deeply_nested() {
+        let text = "FINAL(outer(inner(deep(value))))";
+        assert_eq!(
+            extract_final_answer(text),
+            Some("outer(inner(deep(value)))".to_string())
+        );
+    }
+
+    #[test]
+    fn test_extract_final_resolves_variable() {
+        // FINAL(var) looks up variable in locals
+        let text = "The result is FINAL(result)";
+        let mut locals = HashMap::new();
+        locals.insert("result".to_string(), "computed_value".to_string());
+
+        assert_eq!(
+            extract_answer(text, &locals),
+            Some("computed_value".to_string())
+        );
+    }
+
+    #[test]
+    fn test_extract_final_variable_not_found_returns_name() {
+        // If variable not in locals, return the identifier as-is
+        let text = "FINAL(missing)";
+        let locals = HashMap::new();
+        assert_eq!(extract_answer(text, &locals), Some("missing".to_string()));
+    }
+
+    #[test]
+    fn test_extract_final_literal_string() {
+        // FINAL("literal") strips quotes
+        let text = r#"FINAL("hello world")"#;
+        let locals = HashMap::new();
+        assert_eq!(
+            extract_answer(text, &locals),
+            Some("hello world".to_string())
+        );
+    }
+
+    #[test]
+    fn test_extract_final_literal_with_newlines() {
+        // FINAL("foo\nbar") unescapes to actual newlines
+        let text = r#"FINAL("line1\nline2\nline3")"#;
+        let locals = HashMap::new();
+        assert_eq!(
+            extract_answer(text, &locals),
+            Some("line1\nline2\nline3".to_string())
+        );
+    }
+
+    #[test]
+    fn test_extract_final_rejects_prose_output() {
+        // This was a real failure case - model said "FINAL(Output from executing code)"
+        let text = "Here's the FINAL(Output from executing code) result.";
+        assert_eq!(extract_final_answer(text), None);
+    }
+
+    #[test]
+    fn test_extract_final_rejects_prose_result_of() {
+        let text = "FINAL(the result of the calculation)";
+        assert_eq!(extract_final_answer(text), None);
+    }
+
+    #[test]
+    fn test_extract_final_rejects_prose_demonstration() {
+        let text = "FINAL(This is a demonstration of the system)";
+        assert_eq!(extract_final_answer(text), None);
+    }
+
+    #[test]
+    fn test_extract_final_accepts_valid_at_line_start() {
+        let text = "FINAL(42)";
+        assert_eq!(extract_final_answer(text), Some("42".to_string()));
+    }
+
+    #[test]
+    fn test_extract_final_accepts_after_newline() {
+        let text = "Some text\nFINAL(the answer)";
+        assert_eq!(extract_final_answer(text), Some("the answer".to_string()));
+    }
+
+    #[test]
+    fn test_extract_final_accepts_after_colon() {
+        let text = "Answer: FINAL(123)";
+        assert_eq!(extract_final_answer(text), Some("123".to_string()));
+    }
+
+    #[test]
+    fn test_extract_final_accepts_numbers_list() {
+        let text = "FINAL(1, 1, 2, 3, 5, 8, 13, 21)";
+        assert_eq!(
+            extract_final_answer(text),
+            Some("1, 1, 2, 3, 5, 8, 13, 21".to_string())
+        );
+    }
+
+    #[test]
+    fn test_extract_final_skips_prose_finds_valid() {
+        // First FINAL is prose, second is valid
+        let text = "FINAL(Output from executing code)\nFINAL(42)";
+        assert_eq!(extract_final_answer(text), Some("42".to_string()));
+    }
+
+    #[test]
+    fn test_extract_final_skips_smiley_emoticon() {
+        // Smiley face :) should not close FINAL
+        let text = "FINAL(answer :) here)";
+        assert_eq!(
+            extract_final_answer(text),
+            Some("answer :) here".to_string())
+        );
+    }
+
+    #[test]
+    fn test_extract_final_skips_wink_emoticon() {
+        // Wink ;) should not close FINAL
+        let text = "FINAL(great job ;) done)";
+        assert_eq!(
+            extract_final_answer(text),
+            Some("great job ;) done".to_string())
+        );
+    }
+
+    #[test]
+    fn test_extract_final_normal_parens_still_work() {
+        // Normal parens should still close at first depth=0
+        let text = "FINAL(42) is the answer";
+        assert_eq!(extract_final_answer(text), Some("42".to_string()));
+    }
+
+    #[test]
+    fn test_extract_final_nested_with_emoticon() {
+        // Nested parens followed by emoticon
+        let text = "FINAL(foo(bar) and :) end)";
+        assert_eq!(
+            extract_final_answer(text),
+            Some("foo(bar) and :) end".to_string())
+        );
+    }
+
+    #[test]
+    fn test_extract_final_quoted_string_with_unbalanced_parens() {
+        // Quoted string content with unbalanced parens (like ASCII art)
+        let text = r#"FINAL(
+"Here's some art with unbalanced parens:
+.(.
+(..)
+Pretty cool!"
+)"#;
+        let answer = extract_final_answer(text).unwrap();
+        assert!(answer.contains("unbalanced parens"));
+        assert!(answer.contains(".(."));
+    }
+
+    #[test]
+    fn test_extract_final_multiline_quoted_content() {
+        // Real-world case: multi-line FINAL with quoted string
+        let text = r#"FINAL(
+"The answer is (1+2) = 3.
+Here's more: ((.)) art"
+)"#;
+        let answer = extract_final_answer(text).unwrap();
+        assert!(answer.contains("The answer is"));
+        assert!(answer.contains("(1+2)"));
+    }
+
+    #[test]
+    fn test_sanitize_clean_answer_untouched() {
+        assert_eq!(sanitize_final_answer("line 1\nline 2\ttabbed"), None);
+    }
+
+    #[test]
+    fn test_sanitize_strips_control_chars() {
+        assert_eq!(
+            sanitize_final_answer("ok\u{0}\u{1b}[0m done\u{7f}"),
+            Some("ok[0m done".to_string())
+        );
+    }
+
+    #[test]
+    fn test_sanitize_decodes_bytes_repr() {
+        assert_eq!(
+            sanitize_final_answer(r"b'price: \xe2\x82\xac5\n'"),
+            Some("price: €5\n".to_string())
+        );
+        // Invalid UTF-8 becomes the replacement character
+        assert_eq!(
+            sanitize_final_answer(r"b'\xff ok'"),
+            Some("\u{fffd} ok".to_string())
+        );
+    }
+}
@@ -0,0 +1,157 @@
+//! Diff-based code fixes
+//!
+//! When a code block fails, the fix round can show the model just the
+//! numbered block and its error and accept a unified diff back, instead of
+//! replaying the whole conversation and asking for the block again.
+
+use regex::Regex;
+use std::sync::LazyLock;
+
+static DIFF_BLOCK_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"```(?:diff|patch)\n([\s\S]*?)```").expect("invalid regex"));
+
+/// Extract blocks delimited by ```diff``` or ```patch``` markers
+pub fn extract_diff_blocks(text: &str) -> Vec<String> {
+    DIFF_BLOCK_RE
+        .captures_iter(text)
+        .filter_map(|cap| cap.get(1).map(|m| m.as_str().to_string()))
+        .collect()
+}
+
+/// Prefix each line with its 1-based line number
+pub fn number_lines(code: &str) -> String {
+    let width = code.lines().count().max(1).to_string().len();
+    code.lines()
+        .enumerate()
+        .map(|(i, line)| format!("{:>width$} | {}", i + 1, line, width = width))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+struct Hunk<'a> {
+    /// 0-based line the hunk claims to start at, if given
+    start: Option<usize>,
+    old: Vec<&'a str>,
+    new: Vec<&'a str>,
+}
+
+fn parse_hunks(patch: &str) -> Vec<Hunk<'_>> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    for line in patch.lines() {
+        if line.starts_with("--- ") || line.starts_with("+++ ") || line.starts_with('\\') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix("@@") {
+            // "@@ -12,3 +12,4 @@" -> 11
+            let start = header
+                .trim_start()
+                .strip_prefix('-')
+                .and_then(|r| r.split([',', ' ']).next())
+                .and_then(|n| n.parse::<usize>().ok())
+                .map(|n| n.saturating_sub(1));
+            hunks.push(Hunk {
+                start,
+                old: Vec::new(),
+                new: Vec::new(),
+            });
+            continue;
+        }
+        if hunks.is_empty() {
+            // Bare diff without a header: treat it as one hunk
+            hunks.push(Hunk {
+                start: None,
+                old: Vec::new(),
+                new: Vec::new(),
+            });
+        }
+        let hunk = hunks.last_mut().unwrap();
+        if let Some(rest) = line.strip_prefix('-') {
+            hunk.old.push(rest);
+        } else if let Some(rest) = line.strip_prefix('+') {
+            hunk.new.push(rest);
+        } else {
+            // Context line; models often drop the leading space on blank lines
+            let rest = line.strip_prefix(' ').unwrap_or(line);
+            hunk.old.push(rest);
+            hunk.new.push(rest);
+        }
+    }
+    hunks
+}
+
+/// Apply a unified diff to `original`
+///
+/// Hunks are located by their context and removed lines, preferring the
+/// position closest to the line number in the hunk header, so slightly
+/// wrong numbers from the model still apply. Returns `None` if a hunk
+/// doesn't match or the patch changes nothing.
+pub fn apply_patch(original: &str, patch: &str) -> Option<String> {
+    let mut lines: Vec<&str> = original.lines().collect();
+    let hunks = parse_hunks(patch);
+    if hunks.iter().all(|h| h.old == h.new) {
+        return None;
+    }
+
+    // Shift from earlier hunks changing the line count
+    let mut offset: isize = 0;
+    for hunk in &hunks {
+        let pos = if hunk.old.is_empty() {
+            // Pure insertion: trust the header, else append
+            hunk.start
+                .map(|s| (s as isize + offset).clamp(0, lines.len() as isize) as usize)
+                .unwrap_or(lines.len())
+        } else {
+            let hint = hunk.start.map(|s| s as isize + offset).unwrap_or(0);
+            (0..=lines.len().checked_sub(hunk.old.len())?)
+                .filter(|&i| {
+                    lines[i..i + hunk.old.len()]
+                        .iter()
+                        .zip(&hunk.old)
+                        .all(|(a, b)| a.trim_end() == b.trim_end())
+                })
+                .min_by_key(|&i| (i as isize - hint).unsigned_abs())?
+        };
+        lines.splice(pos..pos + hunk.old.len(), hunk.new.iter().copied());
+        offset += hunk.new.len() as isize - hunk.old.len() as isize;
+    }
+
+    Some(lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODE: &str = "x = 1\ny = 2\nprint(x + z)\nprint('done')";
+
+    #[test]
+    fn test_apply_patch_with_header() {
+        let patch = "--- a\n+++ b\n@@ -2,2 +2,2 @@\n y = 2\n-print(x + z)\n+print(x + y)\n";
+        assert_eq!(
+            apply_patch(CODE, patch).as_deref(),
+            Some("x = 1\ny = 2\nprint(x + y)\nprint('done')")
+        );
+    }
+
+    #[test]
+    fn test_apply_patch_wrong_line_numbers_and_multiple_hunks() {
+        let patch = "@@ -9 +9,2 @@\n x = 1\n+z = 3\n@@ -40 +41 @@\n-print('done')\n+print('ok')";
+        assert_eq!(
+            apply_patch(CODE, patch).as_deref(),
+            Some("x = 1\nz = 3\ny = 2\nprint(x + z)\nprint('ok')")
+        );
+    }
+
+    #[test]
+    fn test_apply_patch_rejects_mismatch() {
+        assert_eq!(apply_patch(CODE, "@@ -1 +1 @@\n-w = 0\n+w = 1"), None);
+        assert_eq!(apply_patch(CODE, "@@ -1 +1 @@\n x = 1"), None);
+    }
+
+    #[test]
+    fn test_extract_and_number() {
+        let text = "Fix:\n```diff\n-a\n+b\n```\n";
+        assert_eq!(extract_diff_blocks(text), vec!["-a\n+b\n".to_string()]);
+        assert_eq!(number_lines("a\nb"), "1 | a\n2 | b");
+    }
+}
@@ -0,0 +1,240 @@
+//! Client-side request and token throttling
+//!
+//! [`RateLimitedBackend`] wraps any [`ChatBackend`] and blocks before a call
+//! until it fits the requests-per-minute and tokens-per-minute budget. Root
+//! calls and `llm_query` sub-calls share one backend, so they share the
+//! budget too.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::backend::{ChatBackend, ChatParams, ToolDefinition, ToolUse};
+use crate::error::Result;
+use crate::tokens::{count_message_tokens, count_tokens};
+use crate::types::{Message, Usage};
+
+/// Requests and tokens allowed per minute (None = unlimited)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimit {
+    pub requests_per_minute: Option<u32>,
+    pub tokens_per_minute: Option<u32>,
+}
+
+impl RateLimit {
+    pub fn is_limited(&self) -> bool {
+        self.requests_per_minute.is_some() || self.tokens_per_minute.is_some()
+    }
+}
+
+struct Entry {
+    id: u64,
+    at: Instant,
+    tokens: u64,
+}
+
+#[derive(Default)]
+struct Window {
+    entries: VecDeque<Entry>,
+    next_id: u64,
+}
+
+/// Sliding-window limiter over requests and tokens
+pub struct RateLimiter {
+    limit: RateLimit,
+    window: Duration,
+    state: Mutex<Window>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self::with_window(limit, Duration::from_secs(60))
+    }
+
+    fn with_window(limit: RateLimit, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            state: Mutex::new(Window::default()),
+        }
+    }
+
+    /// Block until a call using about `tokens` tokens fits, then reserve it
+    ///
+    /// Returns a reservation id for [`Self::settle`]. A single call larger
+    /// than the token budget is let through once the window is empty.
+    pub fn acquire(&self, tokens: u64) -> u64 {
+        loop {
+            let wait = {
+                let mut state = self.state.lock().unwrap();
+                let now = Instant::now();
+                while state
+                    .entries
+                    .front()
+                    .is_some_and(|e| now.duration_since(e.at) >= self.window)
+                {
+                    state.entries.pop_front();
+                }
+
+                let requests_ok = self
+                    .limit
+                    .requests_per_minute
+                    .is_none_or(|rpm| state.entries.len() < rpm as usize);
+                let used: u64 = state.entries.iter().map(|e| e.tokens).sum();
+                let tokens_ok = self
+                    .limit
+                    .tokens_per_minute
+                    .is_none_or(|tpm| state.entries.is_empty() || used + tokens <= u64::from(tpm));
+
+                if requests_ok && tokens_ok {
+                    let id = state.next_id;
+                    state.next_id += 1;
+                    state.entries.push_back(Entry {
+                        id,
+                        at: now,
+                        tokens,
+                    });
+                    return id;
+                }
+
+                // Oldest entry leaving the window frees budget
+                let oldest = state.entries.front().map(|e| e.at).unwrap_or(now);
+                (oldest + self.window).saturating_duration_since(now)
+            };
+            std::thread::sleep(wait.max(Duration::from_millis(1)));
+        }
+    }
+
+    /// Replace a reservation's estimate with the tokens actually used
+    pub fn settle(&self, id: u64, tokens: u64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(entry) = state.entries.iter_mut().find(|e| e.id == id) {
+            entry.tokens = tokens;
+        }
+    }
+}
+
+/// Backend decorator applying a [`RateLimiter`]
+pub struct RateLimitedBackend {
+    inner: Arc<dyn ChatBackend>,
+    limiter: RateLimiter,
+}
+
+impl RateLimitedBackend {
+    pub fn new(inner: Arc<dyn ChatBackend>, limit: RateLimit) -> Self {
+        Self {
+            inner,
+            limiter: RateLimiter::new(limit),
+        }
+    }
+}
+
+impl ChatBackend for RateLimitedBackend {
+    fn chat(&self, messages: &[Message], params: &ChatParams) -> Result<(String, Usage)> {
+        // Only count when a token budget is set; tokenizing isn't free
+        let estimate = if self.limiter.limit.tokens_per_minute.is_some() {
+            count_message_tokens(&params.model, messages) as u64
+                + u64::from(params.max_tokens.unwrap_or(0))
+        } else {
+            0
+        };
+        let id = self.limiter.acquire(estimate);
+        let result = self.inner.chat(messages, params);
+        if let Ok((_, ref usage)) = result {
+            self.limiter.settle(id, usage.total_tokens);
+        }
+        result
+    }
+
+    fn supports_tools(&self) -> bool {
+        self.inner.supports_tools()
+    }
+
+    fn chat_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[ToolDefinition],
+        params: &ChatParams,
+    ) -> Result<(String, Vec<ToolUse>, Usage)> {
+        let estimate = if self.limiter.limit.tokens_per_minute.is_some() {
+            count_message_tokens(&params.model, messages) as u64
+                + u64::from(params.max_tokens.unwrap_or(0))
+        } else {
+            0
+        };
+        let id = self.limiter.acquire(estimate);
+        let result = self.inner.chat_with_tools(messages, tools, params);
+        if let Ok((_, _, ref usage)) = result {
+            self.limiter.settle(id, usage.total_tokens);
+        }
+        result
+    }
+
+    fn embed(&self, texts: &[String], model: &str) -> Result<Vec<Vec<f32>>> {
+        // Embedding input counts against the token budget like a prompt
+        let estimate = if self.limiter.limit.tokens_per_minute.is_some() {
+            texts.iter().map(|t| count_tokens(model, t) as u64).sum()
+        } else {
+            0
+        };
+        self.limiter.acquire(estimate);
+        self.inner.embed(texts, model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_millis(200);
+
+    #[test]
+    fn test_requests_per_window() {
+        let limiter = RateLimiter::with_window(
+            RateLimit {
+                requests_per_minute: Some(2),
+                tokens_per_minute: None,
+            },
+            WINDOW,
+        );
+        let start = Instant::now();
+        limiter.acquire(0);
+        limiter.acquire(0);
+        assert!(start.elapsed() < WINDOW);
+        limiter.acquire(0);
+        assert!(start.elapsed() >= WINDOW);
+    }
+
+    #[test]
+    fn test_tokens_per_window_uses_settled_usage() {
+        let limiter = RateLimiter::with_window(
+            RateLimit {
+                requests_per_minute: None,
+                tokens_per_minute: Some(100),
+            },
+            WINDOW,
+        );
+        let start = Instant::now();
+        let id = limiter.acquire(90);
+        // Call used far less than estimated, so the next one fits right away
+        limiter.settle(id, 10);
+        limiter.acquire(80);
+        assert!(start.elapsed() < WINDOW);
+        limiter.acquire(50);
+        assert!(start.elapsed() >= WINDOW);
+    }
+
+    #[test]
+    fn test_oversized_call_not_starved() {
+        let limiter = RateLimiter::with_window(
+            RateLimit {
+                requests_per_minute: None,
+                tokens_per_minute: Some(10),
+            },
+            WINDOW,
+        );
+        let start = Instant::now();
+        limiter.acquire(1000);
+        assert!(start.elapsed() < WINDOW);
+    }
+}
@@ -0,0 +1,99 @@
+//! Token counting and model context windows
+//!
+//! Counts use the OpenAI BPE tokenizers from `tiktoken-rs`. For other
+//! providers (Claude, local models) `cl100k_base` is used as an
+//! approximation, which is close enough for budgeting.
+
+use tiktoken_rs::tokenizer::{get_tokenizer, Tokenizer};
+
+use crate::types::Message;
+
+/// Per-message overhead of the chat format (role markers, separators)
+const TOKENS_PER_MESSAGE: usize = 4;
+
+/// Known context window sizes in tokens, matched by model name prefix
+///
+/// More specific prefixes must come before shorter ones.
+const CONTEXT_WINDOWS: &[(&str, usize)] = &[
+    ("gpt-4.1", 1_047_576),
+    ("gpt-4o", 128_000),
+    ("chatgpt-4o", 128_000),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4-1106", 128_000),
+    ("gpt-4-0125", 128_000),
+    ("gpt-4-32k", 32_768),
+    ("gpt-4", 8_192),
+    ("gpt-3.5-turbo", 16_385),
+    ("gpt-35-turbo", 16_385),
+    ("o1-mini", 128_000),
+    ("o1", 200_000),
+    ("o3", 200_000),
+    ("o4-mini", 200_000),
+    ("claude-", 200_000),
+    ("llama3.1", 131_072),
+    ("llama3.2", 131_072),
+    ("llama3", 8_192),
+    ("qwen2.5", 32_768),
+    ("mistral", 32_768),
+];
+
+/// Context window of a model in tokens, if known
+///
+/// Provider prefixes like `openai/` or `anthropic/` are ignored.
+pub fn context_window(model: &str) -> Option<usize> {
+    let name = model.rsplit('/').next().unwrap_or(model).to_lowercase();
+    CONTEXT_WINDOWS
+        .iter()
+        .find(|(prefix, _)| name.starts_with(prefix))
+        .map(|&(_, window)| window)
+}
+
+/// Count the tokens in `text` with the tokenizer for `model`
+pub fn count_tokens(model: &str, text: &str) -> usize {
+    let bpe = match get_tokenizer(model) {
+        Some(Tokenizer::O200kBase) => tiktoken_rs::o200k_base_singleton(),
+        _ => tiktoken_rs::cl100k_base_singleton(),
+    };
+    let bpe = bpe.lock();
+    bpe.encode_ordinary(text).len()
+}
+
+/// Count the tokens a chat history will occupy in the prompt
+pub fn count_message_tokens(model: &str, messages: &[Message]) -> usize {
+    messages
+        .iter()
+        .map(|m| TOKENS_PER_MESSAGE + count_tokens(model, &m.content))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context_window_lookup() {
+        assert_eq!(context_window("gpt-4o-mini"), Some(128_000));
+        assert_eq!(context_window("gpt-4"), Some(8_192));
+        assert_eq!(context_window("gpt-4-turbo-preview"), Some(128_000));
+        assert_eq!(context_window("claude-3-5-sonnet-20241022"), Some(200_000));
+        assert_eq!(
+            context_window("openrouter/anthropic/claude-3-haiku"),
+            Some(200_000)
+        );
+        assert_eq!(context_window("my-finetune"), None);
+    }
+
+    #[test]
+    fn test_count_tokens() {
+        assert_eq!(count_tokens("gpt-4o", ""), 0);
+        assert_eq!(count_tokens("gpt-4o", "hello world"), 2);
+        // Unknown models fall back to cl100k_base
+        assert_eq!(count_tokens("llama3", "hello world"), 2);
+    }
+
+    #[test]
+    fn test_count_message_tokens() {
+        let messages = vec![Message::system("hello world"), Message::user("hello world")];
+        assert_eq!(count_message_tokens("gpt-4o", &messages), 12);
+    }
+}
@@ -0,0 +1,1390 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::answer::{default_detectors, AnswerDetector, AnswerFormat};
+use crate::approval::CodeApprovalHook;
+use crate::backend::ChatBackend;
+use crate::cache::CacheStats;
+use crate::log::{LogSink, StdoutSink};
+use crate::mock::MockBackend;
+use crate::parsing::ParserConfig;
+use crate::ratelimit::RateLimit;
+
+/// LLM Backend provider
+#[derive(Clone, Default)]
+pub enum Backend {
+    #[default]
+    OpenAI,
+    Anthropic,
+    /// Azure OpenAI; `base_url` is the resource endpoint
+    AzureOpenAI {
+        deployment: String,
+        api_version: String,
+    },
+    /// Scripted responses for tests, no network access
+    Mock(MockBackend),
+    /// User-supplied provider (internal gateways, custom auth, ...)
+    Custom(Arc<dyn ChatBackend>),
+}
+
+impl std::fmt::Debug for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Backend::OpenAI => write!(f, "OpenAI"),
+            Backend::Anthropic => write!(f, "Anthropic"),
+            Backend::AzureOpenAI {
+                deployment,
+                api_version,
+            } => f
+                .debug_struct("AzureOpenAI")
+                .field("deployment", deployment)
+                .field("api_version", api_version)
+                .finish(),
+            Backend::Mock(mock) => f.debug_tuple("Mock").field(mock).finish(),
+            Backend::Custom(_) => write!(f, "Custom(..)"),
+        }
+    }
+}
+
+/// Token usage statistics
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Usage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub total_tokens: u64,
+    /// Portion of `input_tokens` served from the provider's prompt cache
+    #[serde(default)]
+    pub cached_input_tokens: u64,
+}
+
+impl Usage {
+    pub fn new(input: u64, output: u64) -> Self {
+        Self {
+            input_tokens: input,
+            output_tokens: output,
+            total_tokens: input + output,
+            cached_input_tokens: 0,
+        }
+    }
+
+    pub fn with_cached_input_tokens(mut self, n: u64) -> Self {
+        self.cached_input_tokens = n;
+        self
+    }
+
+    /// Accumulate usage from another instance
+    pub fn add(&mut self, other: &Usage) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.total_tokens += other.total_tokens;
+        self.cached_input_tokens += other.cached_input_tokens;
+    }
+
+    /// Usage added since `earlier`, an earlier copy of this total
+    pub fn since(&self, earlier: &Usage) -> Usage {
+        Usage {
+            input_tokens: self.input_tokens.saturating_sub(earlier.input_tokens),
+            output_tokens: self.output_tokens.saturating_sub(earlier.output_tokens),
+            total_tokens: self.total_tokens.saturating_sub(earlier.total_tokens),
+            cached_input_tokens: self
+                .cached_input_tokens
+                .saturating_sub(earlier.cached_input_tokens),
+        }
+    }
+}
+
+/// OpenAI-style message
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Message {
+    pub role: Role,
+    pub content: String,
+    /// Images sent along with the text (user messages, vision models only)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<Image>,
+}
+
+impl Message {
+    pub fn system(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::System,
+            content: content.into(),
+            images: Vec::new(),
+        }
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::User,
+            content: content.into(),
+            images: Vec::new(),
+        }
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::Assistant,
+            content: content.into(),
+            images: Vec::new(),
+        }
+    }
+
+    pub fn with_images(mut self, images: impl IntoIterator<Item = Image>) -> Self {
+        self.images.extend(images);
+        self
+    }
+}
+
+/// Image for vision-capable models
+///
+/// Serialized with the bytes base64-encoded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Image {
+    /// MIME type: `image/png`, `image/jpeg`, `image/gif`, or `image/webp`
+    pub media_type: String,
+    #[serde(with = "base64_bytes")]
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(media_type: impl Into<String>, data: impl Into<Vec<u8>>) -> Self {
+        Self {
+            media_type: media_type.into(),
+            data: data.into(),
+        }
+    }
+
+    /// Image with the media type detected from its header
+    ///
+    /// None for anything but PNG, JPEG, GIF, and WebP.
+    pub fn from_bytes(data: impl Into<Vec<u8>>) -> Option<Self> {
+        let data = data.into();
+        Self::sniff(&data).map(|media_type| Self::new(media_type, data))
+    }
+
+    /// Read an image file
+    pub fn from_file(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        Self::from_bytes(std::fs::read(path)?).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{}: not a PNG, JPEG, GIF, or WebP image", path.display()),
+            )
+        })
+    }
+
+    /// Media type of PNG, JPEG, GIF, or WebP data
+    pub fn sniff(data: &[u8]) -> Option<&'static str> {
+        if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some("image/png")
+        } else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+            Some("image/jpeg")
+        } else if data.starts_with(b"GIF8") {
+            Some("image/gif")
+        } else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+            Some("image/webp")
+        } else {
+            None
+        }
+    }
+
+    /// File extension for the media type (`png`, `jpeg`, ...)
+    pub fn extension(&self) -> &str {
+        self.media_type.strip_prefix("image/").unwrap_or("bin")
+    }
+
+    /// Base64 of the image bytes
+    pub fn base64(&self) -> String {
+        use base64::Engine;
+        base64::engine::general_purpose::STANDARD.encode(&self.data)
+    }
+
+    /// `data:` URL embedding the image
+    pub fn data_url(&self) -> String {
+        format!("data:{};base64,{}", self.media_type, self.base64())
+    }
+}
+
+mod base64_bytes {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Message role
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+/// Prompt can be a string or message list
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PromptInput {
+    Text(String),
+    Messages(Vec<Message>),
+    /// Text with images, for vision-capable models
+    Multimodal {
+        text: String,
+        images: Vec<Image>,
+    },
+}
+
+impl PromptInput {
+    /// Images in the prompt, from user messages for [`PromptInput::Messages`]
+    pub fn images(&self) -> Vec<Image> {
+        match self {
+            PromptInput::Text(_) => Vec::new(),
+            PromptInput::Messages(msgs) => msgs
+                .iter()
+                .filter(|m| m.role == Role::User)
+                .flat_map(|m| m.images.iter().cloned())
+                .collect(),
+            PromptInput::Multimodal { images, .. } => images.clone(),
+        }
+    }
+}
+
+impl From<String> for PromptInput {
+    fn from(s: String) -> Self {
+        PromptInput::Text(s)
+    }
+}
+
+impl From<&str> for PromptInput {
+    fn from(s: &str) -> Self {
+        PromptInput::Text(s.to_string())
+    }
+}
+
+impl From<Vec<Message>> for PromptInput {
+    fn from(m: Vec<Message>) -> Self {
+        PromptInput::Messages(m)
+    }
+}
+
+impl std::fmt::Display for PromptInput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PromptInput::Text(s) => write!(f, "{}", s),
+            PromptInput::Messages(msgs) => {
+                for msg in msgs {
+                    writeln!(f, "[{:?}]: {}", msg.role, msg.content)?;
+                }
+                Ok(())
+            }
+            PromptInput::Multimodal { text, images } => {
+                write!(f, "{} [{} image(s)]", text, images.len())
+            }
+        }
+    }
+}
+
+/// Result of a single LM completion call
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatCompletion {
+    pub prompt: PromptInput,
+    pub response: String,
+    pub usage: Usage,
+    #[serde(with = "humantime_serde")]
+    pub execution_time: Duration,
+}
+
+/// Result of code execution in REPL
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplResult {
+    pub stdout: String,
+    pub stderr: String,
+    pub locals: HashMap<String, String>,
+    #[serde(with = "humantime_serde")]
+    pub execution_time: Duration,
+    pub llm_calls: Vec<ChatCompletion>,
+    pub success: bool,
+    pub error: Option<String>,
+    /// Output from llm_output() call - signals iteration should stop
+    pub llm_output: Option<String>,
+}
+
+impl ReplResult {
+    /// Create a successful result
+    pub fn success(
+        stdout: String,
+        locals: HashMap<String, String>,
+        execution_time: Duration,
+    ) -> Self {
+        Self {
+            stdout,
+            stderr: String::new(),
+            locals,
+            execution_time,
+            llm_calls: Vec::new(),
+            success: true,
+            error: None,
+            llm_output: None,
+        }
+    }
+
+    /// Create a failed result
+    pub fn failure(error: String, stderr: String, execution_time: Duration) -> Self {
+        Self {
+            stdout: String::new(),
+            stderr,
+            locals: HashMap::new(),
+            execution_time,
+            llm_calls: Vec::new(),
+            success: false,
+            error: Some(error),
+            llm_output: None,
+        }
+    }
+}
+
+/// Extracted code block with its execution result
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CodeBlock {
+    pub code: String,
+    pub result: Option<ReplResult>,
+    pub retry_count: u32,
+}
+
+/// Single iteration of the RLM loop
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RlmIteration {
+    pub iteration: u32,
+    pub response: String,
+    pub code_blocks: Vec<CodeBlock>,
+    pub final_answer: Option<String>,
+    /// Original bytes of the final answer, if it had to be sanitized
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub final_answer_raw: Option<Vec<u8>>,
+    /// The final answer parsed as JSON, if it is a JSON object or array
+    /// (e.g. from `FINAL_JSON(...)`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub final_json: Option<serde_json::Value>,
+    /// Continuation requests stitched into `response` after it hit `max_tokens`
+    #[serde(default)]
+    pub continuations: u32,
+    /// Usage of the `llm_query()` sub-calls made by the iteration's code
+    #[serde(default)]
+    pub sub_call_usage: Usage,
+    #[serde(with = "humantime_serde")]
+    pub execution_time: Duration,
+}
+
+/// How a completion ended
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompletionStatus {
+    /// The model signalled a final answer
+    #[default]
+    Answered,
+    /// The iteration limit was hit; `response` is the last model response
+    MaxIterations,
+}
+
+/// Final RLM completion result
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RlmCompletion {
+    pub prompt: PromptInput,
+    pub response: String,
+    /// `response` parsed as JSON, for structured answers (see
+    /// [`RlmIteration::final_json`])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_json: Option<serde_json::Value>,
+    pub iterations: Vec<RlmIteration>,
+    pub usage: Usage,
+    #[serde(with = "humantime_serde")]
+    pub execution_time: Duration,
+    /// Sub-query cache hits and misses (all zero when caching is off)
+    #[serde(default)]
+    pub cache_stats: CacheStats,
+    #[serde(default)]
+    pub status: CompletionStatus,
+}
+
+impl RlmCompletion {
+    /// Continuation requests made across all iterations
+    pub fn continuations(&self) -> u32 {
+        self.iterations.iter().map(|i| i.continuations).sum()
+    }
+
+    /// Whether the run stopped without a final answer (see
+    /// [`RlmConfig::with_partial_results`])
+    pub fn is_partial(&self) -> bool {
+        self.status != CompletionStatus::Answered
+    }
+}
+
+/// Results of `Rlm::completion_many`, in input order
+#[derive(Debug)]
+pub struct BatchCompletion {
+    pub results: Vec<crate::Result<RlmCompletion>>,
+    /// Usage of all runs, including the partial usage of failed ones
+    pub usage: Usage,
+    /// Wall-clock time for the whole batch
+    pub execution_time: Duration,
+}
+
+impl BatchCompletion {
+    pub fn succeeded(&self) -> usize {
+        self.results.iter().filter(|r| r.is_ok()).count()
+    }
+}
+
+/// Kind of task the model is asked to perform
+///
+/// Selects the system prompt framing and how the final answer is cleaned up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskMode {
+    /// Continue the text in `context`; the answer is appended verbatim
+    #[default]
+    Completion,
+    /// Answer the question in `context` (chat / Q&A)
+    Answer,
+    /// Rewrite the input in `context` (translate, reformat, extract, ...)
+    Transform,
+}
+
+/// Trace of a run that failed before producing a final answer
+///
+/// Attached to errors via [`crate::RlmError::Incomplete`] so callers can log
+/// and bill for failed runs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartialRun {
+    pub prompt: PromptInput,
+    pub iterations: Vec<RlmIteration>,
+    pub usage: Usage,
+    #[serde(with = "humantime_serde")]
+    pub execution_time: Duration,
+}
+
+/// Sub-query response cache settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryCacheConfig {
+    /// Maximum number of cached responses
+    pub capacity: usize,
+    /// JSON file to persist the cache in (None = in-memory only)
+    pub path: Option<PathBuf>,
+}
+
+impl QueryCacheConfig {
+    pub const DEFAULT_CAPACITY: usize = 1024;
+}
+
+/// What the model sees when asked to fix failing code
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FixContext {
+    /// Only the numbered failing block and its error; the fix may be a
+    /// unified diff
+    #[default]
+    Diff,
+    /// The whole conversation so far; the fix must be a full code block
+    History,
+}
+
+/// Where REPL code runs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReplMode {
+    /// Embedded interpreter in the host process (fastest); concurrent runs
+    /// take turns on its GIL while executing Python
+    #[default]
+    InProcess,
+    /// Separate `python` worker process; a crash or hang in model code
+    /// kills only the worker, which is restarted with the context restored.
+    /// Concurrent runs execute in parallel.
+    Worker,
+}
+
+/// Language of the REPL the model writes code for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReplLang {
+    #[default]
+    Python,
+    /// Embedded QuickJS; needs the `javascript` feature of `rlm-rs`
+    JavaScript,
+    /// Embedded Lua 5.4; needs the `lua` feature of `rlm-rs`
+    Lua,
+}
+
+/// Timeouts for built-in backend HTTP clients
+///
+/// `connect` and `read` bound the TCP connect and each read; `request` is a
+/// watchdog over the whole call. `None` disables a timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackendTimeouts {
+    pub connect: Option<Duration>,
+    pub read: Option<Duration>,
+    pub request: Option<Duration>,
+}
+
+impl Default for BackendTimeouts {
+    fn default() -> Self {
+        Self {
+            connect: Some(Duration::from_secs(30)),
+            read: Some(Duration::from_secs(120)),
+            request: Some(Duration::from_secs(600)),
+        }
+    }
+}
+
+/// Sampling settings beyond temperature (None = the provider's default)
+///
+/// Backends send the ones their API has: OpenAI-compatible ones all of
+/// them, Anthropic only `top_p`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Sampling {
+    pub top_p: Option<f32>,
+    pub presence_penalty: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    /// Best-effort determinism, where the provider supports it
+    pub seed: Option<i64>,
+}
+
+/// Which part of over-long REPL output is kept in the history
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputTruncation {
+    /// Keep the first `max_output_chars` characters
+    Head,
+    /// Keep the start and the end, dropping the middle
+    #[default]
+    HeadAndTail,
+}
+
+/// Iteration limit derived from context size
+///
+/// `base + ceil(context_len / chunk_size) * per_chunk`, capped at `cap`, so
+/// small tasks stop early and large ones get room to walk every chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdaptiveIterations {
+    pub base: u32,
+    pub per_chunk: u32,
+    pub cap: u32,
+}
+
+impl AdaptiveIterations {
+    pub fn limit(&self, context_len: usize, chunk_size: usize) -> u32 {
+        let chunks = context_len.div_ceil(chunk_size.max(1));
+        let chunks = u32::try_from(chunks).unwrap_or(u32::MAX);
+        self.base
+            .saturating_add(chunks.saturating_mul(self.per_chunk))
+            .min(self.cap)
+            .max(1)
+    }
+}
+
+/// Python environment for the embedded REPL
+#[derive(Debug, Clone)]
+pub struct PythonEnv {
+    /// Virtualenv whose site-packages are made importable in the REPL
+    pub venv: Option<PathBuf>,
+    /// Fall back to `VIRTUAL_ENV` or `./.venv` when no venv is set
+    pub auto_detect: bool,
+    /// Packages that must be importable, verified at startup
+    pub required_packages: Vec<String>,
+    /// Top-level packages REPL code may import besides the standard library
+    /// (None = any)
+    pub allowed_imports: Option<Vec<String>>,
+    /// Packages the model may install with `pip_install()` (empty = no
+    /// `pip_install`); these are pip names, which can differ from the import
+    /// name (`scikit-learn` vs `sklearn`)
+    pub installable: Vec<String>,
+    /// Virtualenv `pip_install()` installs into, created on first use
+    /// (None = a per-process directory under the system temp dir)
+    pub install_venv: Option<PathBuf>,
+}
+
+impl Default for PythonEnv {
+    fn default() -> Self {
+        Self {
+            venv: None,
+            auto_detect: true,
+            required_packages: Vec::new(),
+            allowed_imports: None,
+            installable: Vec::new(),
+            install_venv: None,
+        }
+    }
+}
+
+/// Builtins and modules withheld from REPL code
+///
+/// Blocked builtins raise `PermissionError` when called; blocked modules
+/// (and their submodules) fail to import. This is a best-effort guard
+/// against accidental filesystem and network access by model code, not a
+/// security boundary - determined code can still reach them through object
+/// introspection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SandboxPolicy {
+    pub blocked_builtins: Vec<String>,
+    pub blocked_modules: Vec<String>,
+}
+
+impl SandboxPolicy {
+    /// No filesystem, process, or network access
+    pub fn restricted() -> Self {
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect();
+        Self {
+            blocked_builtins: names(&["open", "input", "breakpoint"]),
+            blocked_modules: names(&[
+                "os",
+                "posix",
+                "nt",
+                "io",
+                "codecs",
+                "pathlib",
+                "shutil",
+                "tempfile",
+                "glob",
+                "fileinput",
+                "subprocess",
+                "pty",
+                "multiprocessing",
+                "signal",
+                "socket",
+                "ssl",
+                "select",
+                "selectors",
+                "asyncio",
+                "http",
+                "urllib",
+                "ftplib",
+                "smtplib",
+                "requests",
+                "httpx",
+                "importlib",
+                "ctypes",
+            ]),
+        }
+    }
+
+    /// Remove `name` from the blocked builtins and modules
+    pub fn allow(mut self, name: &str) -> Self {
+        self.blocked_builtins.retain(|n| n != name);
+        self.blocked_modules.retain(|n| n != name);
+        self
+    }
+}
+
+impl Default for SandboxPolicy {
+    fn default() -> Self {
+        Self::restricted()
+    }
+}
+
+/// What to do with a code block the safety linter flags
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LintAction {
+    /// Run it anyway; findings are only logged
+    Allow,
+    /// Don't run it; the findings go back to the model as an execution error
+    #[default]
+    Deny,
+    /// Run it only if the host confirms (see `Rlm::with_lint_confirm`),
+    /// deny otherwise
+    Ask,
+}
+
+/// Patterns the safety linter looks for in Python code before it runs
+///
+/// The check walks the code's syntax tree, so `import os as o; o.system(..)`
+/// and `from os import system` are caught as `os.system`. A pattern matches
+/// a dotted name exactly, by `fnmatch` glob (`os.exec*`), or as a prefix
+/// (`subprocess` covers `subprocess.run`). Like [`SandboxPolicy`], this
+/// screens for accidents and obvious misuse rather than guaranteeing
+/// safety.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintPolicy {
+    pub patterns: Vec<String>,
+    /// Flag dunder access beyond the harmless ones (`__subclasses__`,
+    /// `__globals__`, `__builtins__`, ...)
+    pub dunders: bool,
+    pub action: LintAction,
+}
+
+impl LintPolicy {
+    /// Shell, process, network, and recursive-delete calls, plus dunder
+    /// tricks
+    pub fn new(action: LintAction) -> Self {
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect();
+        Self {
+            patterns: names(&[
+                "os.system",
+                "os.popen",
+                "os.exec*",
+                "os.spawn*",
+                "os.fork*",
+                "os.kill",
+                "subprocess",
+                "pty",
+                "socket",
+                "ctypes",
+                "shutil.rmtree",
+            ]),
+            dunders: true,
+            action,
+        }
+    }
+
+    /// Stop flagging `pattern`
+    pub fn allow(mut self, pattern: &str) -> Self {
+        self.patterns.retain(|p| p != pattern);
+        self
+    }
+
+    /// Also flag `pattern`
+    pub fn deny(mut self, pattern: impl Into<String>) -> Self {
+        self.patterns.push(pattern.into());
+        self
+    }
+}
+
+impl Default for LintPolicy {
+    fn default() -> Self {
+        Self::new(LintAction::default())
+    }
+}
+
+/// One flagged construct in a code block
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LintFinding {
+    /// 1-based line in the block
+    pub line: usize,
+    /// Dotted name as written after resolving imports, e.g. `os.system`
+    pub name: String,
+    /// Pattern that matched, or `dunder`
+    pub rule: String,
+}
+
+/// Directory REPL code may reach through `read_file`, `write_file`, and
+/// `list_dir`
+///
+/// Paths are resolved relative to `root`, and anything resolving outside
+/// it (`..`, absolute paths, symlinks) is refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Workspace {
+    pub root: PathBuf,
+    /// Leave out `write_file`
+    pub read_only: bool,
+}
+
+impl Workspace {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            read_only: false,
+        }
+    }
+
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+}
+
+/// Named binary data for a run, available to REPL code as
+/// `attachments["name"]` (a `bytes` object)
+///
+/// For inputs that aren't UTF-8 text: spreadsheets, SQLite files, archives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attachment {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+impl Attachment {
+    pub fn new(name: impl Into<String>, data: impl Into<Vec<u8>>) -> Self {
+        Self {
+            name: name.into(),
+            data: data.into(),
+        }
+    }
+
+    /// Read a file, named after its file name
+    pub fn from_file(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.display().to_string());
+        Ok(Self::new(name, std::fs::read(path)?))
+    }
+}
+
+/// Per-call settings for an `llm_query` sub-call
+///
+/// `LlmQueryFn` only carries a prompt string, so REPLs pass overrides by
+/// prefixing the prompt with [`QueryOverrides::encode`]; the engine strips
+/// them again with [`QueryOverrides::decode`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct QueryOverrides {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    /// Images sent with the sub-call prompt
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<Image>,
+}
+
+impl QueryOverrides {
+    /// Marker opening an encoded prompt
+    pub const PREFIX: &'static str = "\u{0}rlm-query:";
+
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Prompt carrying these overrides (the prompt itself if there are none)
+    pub fn encode(&self, prompt: &str) -> String {
+        if self.is_empty() {
+            return prompt.to_string();
+        }
+        let options = serde_json::to_string(self).unwrap_or_default();
+        format!("{}{}\u{0}{}", Self::PREFIX, options, prompt)
+    }
+
+    /// Split an encoded prompt into overrides and the prompt proper
+    ///
+    /// Fails for a malformed override header; plain prompts come back with
+    /// no overrides.
+    pub fn decode(prompt: &str) -> std::result::Result<(Self, &str), String> {
+        let Some(rest) = prompt.strip_prefix(Self::PREFIX) else {
+            return Ok((Self::default(), prompt));
+        };
+        let (options, prompt) = rest
+            .split_once('\u{0}')
+            .ok_or_else(|| "llm_query: malformed overrides".to_string())?;
+        let overrides = serde_json::from_str(options)
+            .map_err(|e| format!("llm_query: invalid overrides: {}", e))?;
+        Ok((overrides, prompt))
+    }
+}
+
+/// Configuration for RLM
+#[derive(Debug, Clone)]
+pub struct RlmConfig {
+    pub model: String,
+    pub max_iterations: u32,
+    pub max_exec_retries: u32,
+    pub temperature: f32,
+    pub max_tokens: Option<u32>,
+    /// Output budget for a root response continued past `max_tokens`
+    /// (None = no continuation)
+    pub max_answer_tokens: Option<u32>,
+    pub verbose: bool,
+    /// Show minimal execution progress (iterations, code exec, final)
+    pub exec_log: bool,
+    /// Where `verbose` and `exec_log` output goes (default: stdout)
+    pub log_sink: Arc<dyn LogSink>,
+    /// LLM backend provider
+    pub backend: Backend,
+    /// Base URL for API (optional, for custom endpoints)
+    pub base_url: Option<String>,
+    /// API key (optional, can use env vars)
+    pub api_key: Option<String>,
+    /// Python venv and required packages for the REPL
+    pub python: PythonEnv,
+    /// Model for `llm_query` sub-calls (None = `model`)
+    pub sub_model: Option<String>,
+    /// Provider for `llm_query` sub-calls (None = `backend`); built with the
+    /// same `base_url`, `api_key`, and timeouts, so use `Backend::Custom`
+    /// for a provider elsewhere
+    pub sub_backend: Option<Backend>,
+    /// Maximum number of `llm_query` sub-calls per run (None = unlimited)
+    pub max_sub_calls: Option<u32>,
+    /// Maximum number of `llm_query` sub-calls per iteration, fix rounds
+    /// included (None = unlimited)
+    pub max_sub_calls_per_iteration: Option<u32>,
+    /// Task framing for prompts and answer handling
+    pub task_mode: TaskMode,
+    /// Mark the system prompt and early history as cacheable (Anthropic)
+    pub prompt_cache: bool,
+    /// Cache identical `llm_query` sub-calls (None = disabled)
+    pub query_cache: Option<QueryCacheConfig>,
+    /// Context window in tokens (None = look up the model in the registry)
+    pub context_window: Option<usize>,
+    /// Derive the iteration limit from context size instead of `max_iterations`
+    pub adaptive_iterations: Option<AdaptiveIterations>,
+    /// Connect/read timeouts and per-call watchdog for backend requests
+    pub timeouts: BackendTimeouts,
+    /// top_p, penalties, and seed for root and sub-calls
+    pub sampling: Sampling,
+    /// Retries for a backend call that timed out
+    pub max_backend_retries: u32,
+    /// Client-side RPM/TPM throttle shared by root and sub-calls
+    pub rate_limit: RateLimit,
+    /// Termination signals checked after each iteration, in order
+    pub answer_detectors: Vec<Arc<dyn AnswerDetector>>,
+    /// Shape the final answer must have (JSON, optionally with a schema)
+    pub answer_format: AnswerFormat,
+    /// Code-fence languages and answer markers recognized in responses
+    pub parser: ParserConfig,
+    /// Keep prose after the executed code block instead of cutting the
+    /// response at its closing fence (later blocks are still dropped)
+    pub keep_trailing_prose: bool,
+    /// Restrict builtins and imports available to REPL code (None = unrestricted)
+    pub sandbox: Option<SandboxPolicy>,
+    /// Screen Python code before it runs (None = no check)
+    pub code_lint: Option<LintPolicy>,
+    /// Reviews each code block before it runs (None = run unreviewed)
+    pub approval_hook: Option<Arc<dyn CodeApprovalHook>>,
+    /// Directory exposed to REPL code through file helpers (None = no helpers)
+    pub workspace: Option<Workspace>,
+    /// Interrupt a code block running longer than this (None = never)
+    pub exec_timeout: Option<Duration>,
+    /// Run the REPL in-process or in a worker process
+    pub repl_mode: ReplMode,
+    /// Language model code is written in
+    pub repl_language: ReplLang,
+    /// Context sent with requests to fix failing code
+    pub fix_context: FixContext,
+    /// Return a partial completion instead of `MaxIterationsReached`
+    pub partial_results: bool,
+    /// Truncate REPL output fed back into history (None = unlimited)
+    pub max_output_chars: Option<usize>,
+    pub output_truncation: OutputTruncation,
+}
+
+impl Default for RlmConfig {
+    fn default() -> Self {
+        Self {
+            model: "gpt-4o".to_string(),
+            max_iterations: 20,
+            max_exec_retries: 2,
+            temperature: 0.0,
+            max_tokens: None,
+            max_answer_tokens: None,
+            verbose: false,
+            exec_log: false,
+            log_sink: Arc::new(StdoutSink),
+            backend: Backend::default(),
+            base_url: None,
+            api_key: None,
+            python: PythonEnv::default(),
+            sub_model: None,
+            sub_backend: None,
+            max_sub_calls: None,
+            max_sub_calls_per_iteration: None,
+            task_mode: TaskMode::default(),
+            prompt_cache: true,
+            query_cache: None,
+            context_window: None,
+            adaptive_iterations: None,
+            timeouts: BackendTimeouts::default(),
+            sampling: Sampling::default(),
+            max_backend_retries: 2,
+            rate_limit: RateLimit::default(),
+            answer_detectors: default_detectors(),
+            answer_format: AnswerFormat::default(),
+            parser: ParserConfig::default(),
+            keep_trailing_prose: false,
+            sandbox: None,
+            code_lint: None,
+            approval_hook: None,
+            workspace: None,
+            exec_timeout: Some(Duration::from_secs(300)),
+            repl_mode: ReplMode::default(),
+            repl_language: ReplLang::default(),
+            fix_context: FixContext::default(),
+            partial_results: false,
+            max_output_chars: None,
+            output_truncation: OutputTruncation::default(),
+        }
+    }
+}
+
+impl RlmConfig {
+    pub fn new(model: impl Into<String>) -> Self {
+        Self {
+            model: model.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_max_iterations(mut self, n: u32) -> Self {
+        self.max_iterations = n;
+        self
+    }
+
+    /// Scale the iteration limit with context size, see [`AdaptiveIterations`]
+    pub fn with_adaptive_iterations(mut self, base: u32, per_chunk: u32, cap: u32) -> Self {
+        self.adaptive_iterations = Some(AdaptiveIterations {
+            base,
+            per_chunk,
+            cap,
+        });
+        self
+    }
+
+    pub fn with_max_exec_retries(mut self, n: u32) -> Self {
+        self.max_exec_retries = n;
+        self
+    }
+
+    pub fn with_temperature(mut self, t: f32) -> Self {
+        self.temperature = t;
+        self
+    }
+
+    pub fn with_max_tokens(mut self, n: u32) -> Self {
+        self.max_tokens = Some(n);
+        self
+    }
+
+    /// Continue responses cut off by `max_tokens` up to `n` output tokens
+    ///
+    /// A root response that used its whole `max_tokens` budget before closing
+    /// a code block is extended with continuation requests, and the parts
+    /// are stitched together. Has no effect unless `max_tokens` is set.
+    pub fn with_max_answer_tokens(mut self, n: u32) -> Self {
+        self.max_answer_tokens = Some(n);
+        self
+    }
+
+    pub fn with_connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeouts.connect = timeout;
+        self
+    }
+
+    pub fn with_read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeouts.read = timeout;
+        self
+    }
+
+    /// Watchdog for a whole backend call, including retries inside the client
+    pub fn with_request_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeouts.request = timeout;
+        self
+    }
+
+    pub fn with_sampling(mut self, sampling: Sampling) -> Self {
+        self.sampling = sampling;
+        self
+    }
+
+    pub fn with_max_backend_retries(mut self, n: u32) -> Self {
+        self.max_backend_retries = n;
+        self
+    }
+
+    /// Add a termination signal after the built-in ones
+    pub fn with_answer_detector(mut self, detector: impl AnswerDetector + 'static) -> Self {
+        self.answer_detectors.push(Arc::new(detector));
+        self
+    }
+
+    /// Replace the whole detector chain (drops the built-in signals)
+    pub fn with_answer_detectors(mut self, detectors: Vec<Arc<dyn AnswerDetector>>) -> Self {
+        self.answer_detectors = detectors;
+        self
+    }
+
+    /// Require a JSON (or schema-conforming) final answer
+    ///
+    /// A detected answer of the wrong shape doesn't end the run: the model
+    /// is told what is wrong and answers again.
+    pub fn with_answer_format(mut self, format: AnswerFormat) -> Self {
+        self.answer_format = format;
+        self
+    }
+
+    /// Fence languages and answer markers for models with their own
+    /// conventions (the built-in detectors follow the markers)
+    pub fn with_parser(mut self, parser: ParserConfig) -> Self {
+        self.parser = parser;
+        self
+    }
+
+    /// Keep what the model writes after its code block, for models that
+    /// put `FINAL(...)` after the code that computes it
+    pub fn with_keep_trailing_prose(mut self, keep: bool) -> Self {
+        self.keep_trailing_prose = keep;
+        self
+    }
+
+    pub fn with_requests_per_minute(mut self, n: u32) -> Self {
+        self.rate_limit.requests_per_minute = Some(n);
+        self
+    }
+
+    pub fn with_tokens_per_minute(mut self, n: u32) -> Self {
+        self.rate_limit.tokens_per_minute = Some(n);
+        self
+    }
+
+    pub fn with_max_output_chars(mut self, n: usize) -> Self {
+        self.max_output_chars = Some(n);
+        self
+    }
+
+    pub fn with_output_truncation(mut self, mode: OutputTruncation) -> Self {
+        self.output_truncation = mode;
+        self
+    }
+
+    pub fn with_verbose(mut self, v: bool) -> Self {
+        self.verbose = v;
+        self
+    }
+
+    pub fn with_exec_log(mut self, v: bool) -> Self {
+        self.exec_log = v;
+        self
+    }
+
+    /// Send `verbose`/`exec_log` output to `sink` instead of stdout
+    pub fn with_log_sink(mut self, sink: impl LogSink + 'static) -> Self {
+        self.log_sink = Arc::new(sink);
+        self
+    }
+
+    pub fn with_backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    pub fn with_base_url(mut self, url: impl Into<String>) -> Self {
+        self.base_url = Some(url.into());
+        self
+    }
+
+    pub fn with_api_key(mut self, key: impl Into<String>) -> Self {
+        self.api_key = Some(key.into());
+        self
+    }
+
+    /// Answer `llm_query` sub-calls with `model` instead of the root model
+    pub fn with_sub_model(mut self, model: impl Into<String>) -> Self {
+        self.sub_model = Some(model.into());
+        self
+    }
+
+    /// Send `llm_query` sub-calls to `backend` instead of the root backend
+    ///
+    /// Lets a strong root model fan out to a cheap (e.g. local) one.
+    pub fn with_sub_backend(mut self, backend: Backend) -> Self {
+        self.sub_backend = Some(backend);
+        self
+    }
+
+    /// Cap `llm_query` sub-calls per iteration and per run (None = unlimited)
+    ///
+    /// A call past either limit raises an exception in model code instead
+    /// of reaching the backend, so a runaway loop fails loudly.
+    pub fn with_max_sub_calls(mut self, per_iteration: Option<u32>, total: Option<u32>) -> Self {
+        self.max_sub_calls_per_iteration = per_iteration;
+        self.max_sub_calls = total;
+        self
+    }
+
+    /// Run REPL code under a restricted-builtins policy
+    ///
+    /// `SandboxPolicy::restricted().allow("io")` keeps a single module.
+    pub fn with_sandbox(mut self, policy: SandboxPolicy) -> Self {
+        self.sandbox = Some(policy);
+        self
+    }
+
+    /// Check Python code blocks for dangerous calls before running them
+    ///
+    /// With [`LintAction::Deny`], a flagged block is not run and the model
+    /// gets the findings as its execution error, so it can rewrite it.
+    pub fn with_code_lint(mut self, policy: LintPolicy) -> Self {
+        self.code_lint = Some(policy);
+        self
+    }
+
+    /// Have `hook` approve, deny, or edit each code block before it runs
+    ///
+    /// Blocks the safety linter refuses never reach the hook; edited code
+    /// runs as given.
+    pub fn with_approval_hook(mut self, hook: impl CodeApprovalHook + 'static) -> Self {
+        self.approval_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Give REPL code `read_file`, `write_file`, and `list_dir` jailed to a
+    /// directory
+    ///
+    /// Lets a run work through a folder of documents too large for
+    /// `context`. The helpers keep working under a [`SandboxPolicy`] that
+    /// blocks `open` and `os`.
+    pub fn with_workspace(mut self, workspace: Workspace) -> Self {
+        self.workspace = Some(workspace);
+        self
+    }
+
+    /// Raise `ExecutionTimeout` in blocks running longer than `timeout`
+    ///
+    /// The error goes back to the model like any other exception.
+    pub fn with_exec_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.exec_timeout = timeout;
+        self
+    }
+
+    /// Run REPL code in a worker process instead of the host
+    ///
+    /// With an `exec_timeout`, a worker still busy a few seconds past it is
+    /// killed and replaced.
+    pub fn with_repl_mode(mut self, mode: ReplMode) -> Self {
+        self.repl_mode = mode;
+        self
+    }
+
+    /// Have the model write JavaScript or Lua instead of Python
+    ///
+    /// Python-only settings (`python`, `sandbox`, `ReplMode::Worker`) don't
+    /// apply to the other languages.
+    pub fn with_repl_language(mut self, language: ReplLang) -> Self {
+        self.repl_language = language;
+        self
+    }
+
+    pub fn with_fix_context(mut self, context: FixContext) -> Self {
+        self.fix_context = context;
+        self
+    }
+
+    /// On hitting the iteration limit, return `Ok` with the full trace, usage,
+    /// and the last model response instead of `MaxIterationsReached`
+    ///
+    /// Check [`RlmCompletion::status`] to tell such runs from answered ones.
+    pub fn with_partial_results(mut self, v: bool) -> Self {
+        self.partial_results = v;
+        self
+    }
+
+    pub fn with_task_mode(mut self, mode: TaskMode) -> Self {
+        self.task_mode = mode;
+        self
+    }
+
+    pub fn with_prompt_cache(mut self, v: bool) -> Self {
+        self.prompt_cache = v;
+        self
+    }
+
+    /// Cache up to `capacity` sub-query responses in memory
+    pub fn with_query_cache(mut self, capacity: usize) -> Self {
+        let path = self.query_cache.take().and_then(|c| c.path);
+        self.query_cache = Some(QueryCacheConfig { capacity, path });
+        self
+    }
+
+    /// Persist the sub-query cache to a JSON file
+    pub fn with_query_cache_file(mut self, path: impl Into<PathBuf>) -> Self {
+        let capacity = self
+            .query_cache
+            .take()
+            .map_or(QueryCacheConfig::DEFAULT_CAPACITY, |c| c.capacity);
+        self.query_cache = Some(QueryCacheConfig {
+            capacity,
+            path: Some(path.into()),
+        });
+        self
+    }
+
+    pub fn with_context_window(mut self, tokens: usize) -> Self {
+        self.context_window = Some(tokens);
+        self
+    }
+
+    /// Configured context window, falling back to the model registry
+    pub fn effective_context_window(&self) -> Option<usize> {
+        self.context_window
+            .or_else(|| crate::tokens::context_window(&self.model))
+    }
+
+    pub fn with_python_venv(mut self, venv: impl Into<PathBuf>) -> Self {
+        self.python.venv = Some(venv.into());
+        self
+    }
+
+    pub fn with_python_auto_detect(mut self, v: bool) -> Self {
+        self.python.auto_detect = v;
+        self
+    }
+
+    pub fn with_required_packages<I, S>(mut self, packages: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.python.required_packages = packages.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Only let REPL code import the standard library and these packages
+    ///
+    /// Other imports fail with an `ImportError` naming the allowed packages.
+    pub fn with_allowed_imports<I, S>(mut self, packages: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.python.allowed_imports = Some(packages.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Let the model install these packages with `pip_install("name")`
+    ///
+    /// Installs go into an isolated virtualenv (see
+    /// [`Self::with_install_venv`]), never the host's environment.
+    pub fn with_pip_install<I, S>(mut self, packages: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.python.installable = packages.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Virtualenv for `pip_install()`; reusing one across runs skips
+    /// reinstalling
+    pub fn with_install_venv(mut self, venv: impl Into<PathBuf>) -> Self {
+        self.python.install_venv = Some(venv.into());
+        self
+    }
+}
+
+/// humantime_serde module for Duration serialization
+mod humantime_serde {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&format!("{:?}", duration))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        // Parse "1.234s" format
+        let s = s.trim_end_matches('s');
+        let secs: f64 = s.parse().map_err(serde::de::Error::custom)?;
+        Ok(Duration::from_secs_f64(secs))
+    }
+}
@@ -0,0 +1,172 @@
+//! Per-user defaults for the command-line tools
+//!
+//! `rlm_chat` and `rlm_agent` read `~/.config/rlm/config.toml` (or
+//! `$XDG_CONFIG_HOME/rlm/config.toml`) for the settings people would
+//! otherwise pass on every run. Top-level keys are the defaults; each
+//! `[profiles.<name>]` table overrides some of them and is picked with
+//! `--profile <name>`. Flags given on the command line still win.
+//!
+//! ```toml
+//! model = "gpt-4o-mini"
+//! backend = "openai"
+//! temperature = 0.3
+//!
+//! [profiles.work]
+//! model = "claude-sonnet-4-20250514"
+//! backend = "anthropic"
+//! api_key_env = "WORK_ANTHROPIC_KEY"
+//! max_iterations = 30
+//! tool_profile = "developer"
+//! ```
+//!
+//! The API key is never stored in the file: `api_key_env` names the
+//! environment variable that holds it.
+
+use crate::error::{Result, RlmError};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Settings from the config file; unset ones fall back to the tool's flags
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct UserSettings {
+    pub model: Option<String>,
+    /// Backend name, as taken by `--backend`
+    pub backend: Option<String>,
+    /// Base URL for OpenAI-compatible backends
+    pub base_url: Option<String>,
+    /// Environment variable holding the API key
+    pub api_key_env: Option<String>,
+    pub temperature: Option<f32>,
+    pub max_iterations: Option<u32>,
+    /// Tool profile for `rlm_agent` (read-only, developer, research)
+    pub tool_profile: Option<String>,
+}
+
+impl UserSettings {
+    /// These settings, with the unset ones taken from `fallback`
+    pub fn or(self, fallback: UserSettings) -> Self {
+        Self {
+            model: self.model.or(fallback.model),
+            backend: self.backend.or(fallback.backend),
+            base_url: self.base_url.or(fallback.base_url),
+            api_key_env: self.api_key_env.or(fallback.api_key_env),
+            temperature: self.temperature.or(fallback.temperature),
+            max_iterations: self.max_iterations.or(fallback.max_iterations),
+            tool_profile: self.tool_profile.or(fallback.tool_profile),
+        }
+    }
+
+    /// The API key from the variable `api_key_env` names, if it is set
+    pub fn api_key(&self) -> Option<String> {
+        let var = self.api_key_env.as_ref()?;
+        std::env::var(var).ok().filter(|key| !key.is_empty())
+    }
+}
+
+/// Contents of the config file
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct UserConfig {
+    /// Top-level keys, used when no profile is picked or it leaves them unset
+    #[serde(flatten)]
+    pub defaults: UserSettings,
+    pub profiles: HashMap<String, UserSettings>,
+}
+
+impl UserConfig {
+    /// Where the config file is looked for
+    pub fn default_path() -> Option<PathBuf> {
+        let base = match std::env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        };
+        Some(base.join("rlm").join("config.toml"))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let invalid = |e: &dyn std::fmt::Display| {
+            RlmError::Config(format!("{}: {}", path.display(), e.to_string().trim()))
+        };
+        let text = std::fs::read_to_string(path).map_err(|e| invalid(&e))?;
+        Self::parse(&text).map_err(|e| invalid(&e))
+    }
+
+    /// The config at [`default_path`](Self::default_path), or an empty one
+    /// if there is no file
+    pub fn load_default() -> Result<Self> {
+        match Self::default_path() {
+            Some(path) if path.exists() => Self::load(path),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    fn parse(text: &str) -> std::result::Result<Self, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    /// Settings for `profile`, or the defaults without one
+    ///
+    /// Fails if the profile isn't in the file.
+    pub fn settings(&self, profile: Option<&str>) -> Result<UserSettings> {
+        let Some(name) = profile else {
+            return Ok(self.defaults.clone());
+        };
+        match self.profiles.get(name) {
+            Some(settings) => Ok(settings.clone().or(self.defaults.clone())),
+            None => Err(RlmError::Config(format!(
+                "no profile '{}' in the config file",
+                name
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profiles_override_defaults() {
+        let config = UserConfig::parse(
+            r#"
+            model = "gpt-4o-mini"
+            base_url = "http://localhost:11434/v1"
+            temperature = 0.3
+
+            [profiles.work]
+            model = "claude-sonnet-4-20250514"
+            backend = "anthropic"
+            max_iterations = 30
+            "#,
+        )
+        .unwrap();
+
+        let defaults = config.settings(None).unwrap();
+        assert_eq!(defaults.model.as_deref(), Some("gpt-4o-mini"));
+        assert_eq!(defaults.backend, None);
+
+        let work = config.settings(Some("work")).unwrap();
+        assert_eq!(work.model.as_deref(), Some("claude-sonnet-4-20250514"));
+        assert_eq!(work.backend.as_deref(), Some("anthropic"));
+        assert_eq!(work.temperature, Some(0.3));
+        assert_eq!(work.max_iterations, Some(30));
+
+        assert!(config.settings(Some("home")).is_err());
+        assert!(UserConfig::parse("temperature = \"hot\"").is_err());
+    }
+
+    #[test]
+    fn test_api_key_from_env() {
+        let settings = UserSettings {
+            api_key_env: Some("RLM_USER_CONFIG_TEST_KEY".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(settings.api_key(), None);
+        std::env::set_var("RLM_USER_CONFIG_TEST_KEY", "sk-test");
+        assert_eq!(settings.api_key().as_deref(), Some("sk-test"));
+        assert_eq!(UserSettings::default().api_key(), None);
+    }
+}
@@ -0,0 +1,104 @@
+//! Failure injection for testing clients against a misbehaving server
+//!
+//! Disabled unless one of the hidden `--chaos-*` flags is set. Each request
+//! rolls once against the configured rates and gets a [`ChaosPlan`] that the
+//! handlers act on.
+
+use std::time::Duration;
+
+use rlm_core::{ChatBackend, ChatParams, Message, RlmError, Usage};
+
+/// Injection rates, each in `0.0..=1.0`
+#[derive(Debug, Clone, Default)]
+pub struct ChaosConfig {
+    /// Fraction of requests whose backend calls fail
+    pub failure_rate: f64,
+    /// Fraction of requests delayed by `slow_delay` before responding
+    pub slow_rate: f64,
+    pub slow_delay: Duration,
+    /// Fraction of streaming requests that emit a malformed SSE event
+    pub malformed_sse_rate: f64,
+}
+
+/// What to inject into a single request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChaosPlan {
+    pub fail_backend: bool,
+    pub delay: Option<Duration>,
+    pub malformed_sse: bool,
+}
+
+impl ChaosPlan {
+    pub fn is_noop(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl ChaosConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.failure_rate > 0.0 || self.slow_rate > 0.0 || self.malformed_sse_rate > 0.0
+    }
+
+    /// Decide what to inject for one request
+    pub fn roll(&self) -> ChaosPlan {
+        if !self.is_enabled() {
+            return ChaosPlan::default();
+        }
+        let hit = |rate: f64| rate > 0.0 && rand::random::<f64>() < rate;
+        ChaosPlan {
+            fail_backend: hit(self.failure_rate),
+            delay: hit(self.slow_rate).then_some(self.slow_delay),
+            malformed_sse: hit(self.malformed_sse_rate),
+        }
+    }
+}
+
+/// Backend that fails every call, standing in for an unreachable provider
+pub struct FailingBackend;
+
+impl ChatBackend for FailingBackend {
+    fn chat(
+        &self,
+        _messages: &[Message],
+        _params: &ChatParams,
+    ) -> rlm_core::Result<(String, Usage)> {
+        Err(RlmError::Api(
+            "Injected backend failure (chaos mode)".to_string(),
+        ))
+    }
+}
+
+/// A data payload that is not valid JSON, as a truncated chunk would be
+pub const MALFORMED_SSE_DATA: &str =
+    r#"{"id":"chatcmpl-chaos","object":"chat.completion.chunk","choices":[{"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disabled_by_default() {
+        let config = ChaosConfig::default();
+        assert!(!config.is_enabled());
+        assert!(config.roll().is_noop());
+    }
+
+    #[test]
+    fn test_full_rates_always_inject() {
+        let config = ChaosConfig {
+            failure_rate: 1.0,
+            slow_rate: 1.0,
+            slow_delay: Duration::from_millis(250),
+            malformed_sse_rate: 1.0,
+        };
+        let plan = config.roll();
+        assert!(plan.fail_backend);
+        assert_eq!(plan.delay, Some(Duration::from_millis(250)));
+        assert!(plan.malformed_sse);
+    }
+
+    #[test]
+    fn test_malformed_data_is_not_json() {
+        assert!(serde_json::from_str::<serde_json::Value>(MALFORMED_SSE_DATA).is_err());
+    }
+}
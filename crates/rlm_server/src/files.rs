@@ -0,0 +1,126 @@
+//! Uploaded files for large contexts
+//!
+//! `POST /v1/files` stores a document once; chat requests then name it in
+//! `file_ids` instead of carrying its text in every body, and the server
+//! puts the text into the run's `context`. PDF, DOCX, and HTML uploads are
+//! stored as their extracted text (see [`rlm::ingest`]). Files are kept in
+//! memory until deleted or the server stops, up to a limit on their total
+//! size.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use rlm::ingest::{extract_text, DocumentKind};
+use rlm_core::RlmError;
+use uuid::Uuid;
+
+use crate::types::FileObject;
+
+struct StoredFile {
+    info: FileObject,
+    text: Arc<String>,
+}
+
+/// The uploaded files by id
+pub struct Files {
+    files: Mutex<HashMap<String, StoredFile>>,
+    /// Most bytes of text kept for all files together
+    max_bytes: usize,
+}
+
+impl Files {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            files: Mutex::new(HashMap::new()),
+            max_bytes,
+        }
+    }
+
+    /// Store an upload as text; blocks while the text is extracted. Fails
+    /// with [`RlmError::BudgetExceeded`] if the text doesn't fit in what is
+    /// left of `max_bytes`.
+    pub fn add(&self, filename: &str, purpose: &str, bytes: &[u8]) -> rlm_core::Result<FileObject> {
+        let text = extract_text(bytes, DocumentKind::detect(Path::new(filename), bytes))?;
+        let info = FileObject::new(
+            format!("file-{}", Uuid::new_v4().simple()),
+            filename.to_string(),
+            purpose.to_string(),
+            bytes.len(),
+        );
+        let mut files = self.files.lock().unwrap();
+        let stored: usize = files.values().map(|file| file.text.len()).sum();
+        if stored + text.len() > self.max_bytes {
+            return Err(RlmError::BudgetExceeded(format!(
+                "the text of {} ({} bytes) doesn't fit in the {} bytes left for files",
+                filename,
+                text.len(),
+                self.max_bytes.saturating_sub(stored)
+            )));
+        }
+        files.insert(
+            info.id.clone(),
+            StoredFile {
+                info: info.clone(),
+                text: Arc::new(text),
+            },
+        );
+        Ok(info)
+    }
+
+    pub fn info(&self, id: &str) -> Option<FileObject> {
+        let files = self.files.lock().unwrap();
+        files.get(id).map(|file| file.info.clone())
+    }
+
+    /// All files, oldest first
+    pub fn list(&self) -> Vec<FileObject> {
+        let files = self.files.lock().unwrap();
+        let mut list: Vec<FileObject> = files.values().map(|file| file.info.clone()).collect();
+        list.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+        list
+    }
+
+    /// Name and text of a file
+    pub fn text(&self, id: &str) -> Option<(String, Arc<String>)> {
+        let files = self.files.lock().unwrap();
+        files
+            .get(id)
+            .map(|file| (file.info.filename.clone(), file.text.clone()))
+    }
+
+    /// Delete a file; false if there was none
+    pub fn remove(&self, id: &str) -> bool {
+        self.files.lock().unwrap().remove(id).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_files_store_extracted_text() {
+        let files = Files::new(1024);
+        let page =
+            b"<html><head><title>Report</title></head><body><h1>Q3</h1><p>Up 4%.</p></body></html>";
+        let info = files.add("report.html", "user_data", page).unwrap();
+        assert!(info.id.starts_with("file-"));
+        assert_eq!(info.bytes, page.len());
+
+        let (name, text) = files.text(&info.id).unwrap();
+        assert_eq!(name, "report.html");
+        assert!(text.starts_with("Title: Report"), "{}", text);
+        assert!(text.contains("Up 4%."));
+
+        files.add("notes.txt", "user_data", b"plain").unwrap();
+        assert_eq!(files.list().len(), 2);
+        assert!(matches!(
+            files.add("big.txt", "user_data", &[b'x'; 1024]),
+            Err(RlmError::BudgetExceeded(_))
+        ));
+        assert!(files.remove(&info.id));
+        assert!(files.text(&info.id).is_none());
+        assert!(!files.remove(&info.id));
+    }
+}
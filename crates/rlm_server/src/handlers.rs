@@ -1,27 +1,67 @@
 //! HTTP handlers for the RLM server
 
 use axum::{
-    extract::State,
-    http::StatusCode,
+    extract::{
+        multipart::{MultipartError, MultipartRejection},
+        Extension, Multipart, Path, State,
+    },
+    http::{HeaderMap, StatusCode},
     response::{
         sse::{Event, KeepAlive, Sse},
         IntoResponse, Json, Response,
     },
 };
+use std::collections::HashMap;
 use std::convert::Infallible;
 use std::sync::Arc;
 use uuid::Uuid;
 
+use crate::chaos::{ChaosConfig, ChaosPlan, FailingBackend, MALFORMED_SSE_DATA};
+use crate::files::Files;
+use crate::logging::{RequestId, TranscriptLog};
+use crate::sessions::Sessions;
 use crate::types::{
-    ChatCompletionChunk, ChatCompletionRequest, ChatCompletionResponse, CompletionUsage,
+    ChatCompletionChunk, ChatCompletionRequest, ChatCompletionResponse, ChatMessage,
+    CompletionUsage, FunctionCall, RlmLimits, RlmOptions, RlmProgress, RlmTrace, SessionObject,
+    ToolCallMessage, ToolChoice,
+};
+use rlm::worker::WorkerPool;
+use rlm::{Rlm, RlmEvent};
+use rlm_agent::{
+    Agent, AgentConfig, AgentPrompt, AgentRun, ClientTool, ClientTurn, ToolCall, ToolRegistry,
+};
+use rlm_core::{
+    Backend, Message, PromptInput, ReplMode, RlmCompletion, RlmConfig, RlmError, Role, Sampling,
+    TaskMode,
 };
-use rlm::{Message, PromptInput, Rlm, RlmConfig, Role};
 
 /// Shared server state
 pub struct AppState {
     pub model: String,
     pub backend_url: String,
     pub backend_key: Option<String>,
+    /// Failure injection (hidden flags, off by default)
+    pub chaos: ChaosConfig,
+    /// Run REPLs in worker processes from this pool (None = in-process)
+    pub worker_pool: Option<Arc<WorkerPool>>,
+    pub sessions: Sessions,
+    pub files: Files,
+    /// Write chat completion transcripts here (None = off)
+    pub transcripts: Option<Arc<TranscriptLog>>,
+    /// Most a request's `rlm` options may ask for
+    pub limits: RlmLimits,
+}
+
+/// Roll chaos for a request and apply any injected delay
+async fn apply_chaos(state: &AppState, request_id: &str) -> ChaosPlan {
+    let plan = state.chaos.roll();
+    if !plan.is_noop() {
+        tracing::warn!("chaos: injecting {:?} into {}", plan, request_id);
+    }
+    if let Some(delay) = plan.delay {
+        tokio::time::sleep(delay).await;
+    }
+    plan
 }
 
 /// Convert OpenAI-style messages to RLM messages
@@ -37,40 +77,206 @@ fn convert_messages(messages: &[crate::types::ChatMessage]) -> Vec<Message> {
             Message {
                 role,
                 content: m.content.clone(),
+                images: Vec::new(),
             }
         })
         .collect()
 }
 
+/// Build the RLM config for a request - chat clients expect a direct
+/// answer, not a continuation
+fn request_config(state: &AppState, req: &ChatCompletionRequest) -> RlmConfig {
+    let mut config = RlmConfig::new(&state.model)
+        .with_task_mode(TaskMode::Answer)
+        .with_sampling(Sampling {
+            top_p: req.top_p,
+            presence_penalty: req.presence_penalty,
+            frequency_penalty: req.frequency_penalty,
+            seed: req.seed,
+        });
+    if let Some(temp) = req.temperature {
+        config = config.with_temperature(temp);
+    }
+    if let Some(max_tokens) = req.max_tokens {
+        config = config.with_max_tokens(max_tokens);
+    }
+    // Checked by `validate` before the run starts
+    if let Ok(format) = req.answer_format() {
+        config = config.with_answer_format(format);
+    }
+    match req.rlm {
+        Some(ref rlm) => rlm.apply(config),
+        None => config,
+    }
+}
+
+/// Prefix of the headers that set [`RlmOptions`]
+const RLM_HEADER_PREFIX: &str = "x-rlm-";
+
+/// The options set by `X-RLM-*` headers, named like the `rlm` fields
+/// (`X-RLM-Max-Iterations` sets `max_iterations`)
+///
+/// Values are read as JSON where they parse and as strings otherwise, so
+/// `5`, `true`, and `head` all work unquoted. Errors name the header.
+fn header_options(headers: &HeaderMap) -> Result<RlmOptions, (String, String)> {
+    let mut options = RlmOptions::default();
+    for (name, value) in headers {
+        let Some(field) = name.as_str().strip_prefix(RLM_HEADER_PREFIX) else {
+            continue;
+        };
+        // Header names arrive lowercased
+        let header = field
+            .split('-')
+            .fold("X-RLM".to_string(), |mut header, word| {
+                let mut chars = word.chars();
+                header.push('-');
+                header.extend(chars.next().map(|c| c.to_ascii_uppercase()));
+                header.push_str(chars.as_str());
+                header
+            });
+        let value = value
+            .to_str()
+            .map_err(|_| (header.clone(), format!("{} is not valid text", header)))?
+            .trim();
+        let value = serde_json::from_str(value)
+            .unwrap_or_else(|_| serde_json::Value::String(value.to_string()));
+        let mut object = serde_json::Map::new();
+        object.insert(field.replace('-', "_"), value);
+        let parsed: RlmOptions = serde_json::from_value(serde_json::Value::Object(object))
+            .map_err(|e| (header.clone(), format!("Invalid {} header: {}", header, e)))?;
+        options = parsed.or(options);
+    }
+    Ok(options)
+}
+
+/// The answer up to the first stop sequence
+///
+/// The model writes code and reads output before answering, so stop
+/// sequences apply to the answer rather than to each backend call.
+fn cut_at_stop(mut answer: String, stop: &[String]) -> String {
+    if let Some(end) = stop.iter().filter_map(|s| answer.find(s.as_str())).min() {
+        answer.truncate(end);
+    }
+    answer
+}
+
+/// 400 response in the OpenAI error shape
+fn invalid_request(param: &str, message: String) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(serde_json::json!({
+            "error": {
+                "message": message,
+                "type": "invalid_request_error",
+                "param": param
+            }
+        })),
+    )
+        .into_response()
+}
+
+/// 404 response in the OpenAI error shape
+fn not_found(message: String) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(serde_json::json!({
+            "error": {
+                "message": message,
+                "type": "not_found_error"
+            }
+        })),
+    )
+        .into_response()
+}
+
+/// 413 response in the OpenAI error shape
+fn payload_too_large(param: &str, message: String) -> Response {
+    (
+        StatusCode::PAYLOAD_TOO_LARGE,
+        Json(serde_json::json!({
+            "error": {
+                "message": message,
+                "type": "invalid_request_error",
+                "param": param
+            }
+        })),
+    )
+        .into_response()
+}
+
+/// 429 response in the OpenAI error shape
+fn too_many_requests(message: String) -> Response {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        Json(serde_json::json!({
+            "error": {
+                "message": message,
+                "type": "rate_limit_error"
+            }
+        })),
+    )
+        .into_response()
+}
+
 /// Handler for POST /v1/chat/completions
 pub async fn create_chat_completion(
     State(state): State<Arc<AppState>>,
-    Json(req): Json<ChatCompletionRequest>,
+    Extension(id): Extension<RequestId>,
+    headers: HeaderMap,
+    Json(mut req): Json<ChatCompletionRequest>,
 ) -> Response {
+    // Fields of the `rlm` body extension win over headers
+    match header_options(&headers) {
+        Ok(options) => req.rlm = Some(req.rlm.take().unwrap_or_default().or(options)),
+        Err((header, message)) => return invalid_request(&header, message),
+    }
+    if let Err((param, message)) = req.validate() {
+        return invalid_request(param, message);
+    }
+    let limits = req
+        .rlm
+        .as_ref()
+        .map_or(Ok(()), |rlm| rlm.check_limits(&state.limits));
+    if let Err((param, message)) = limits {
+        return invalid_request(param, message);
+    }
+    if let Some(ref id) = req.session_id {
+        if !state.sessions.contains(id) {
+            return not_found(format!("No session {}", id));
+        }
+    }
+    match file_messages(&state.files, &req.file_ids) {
+        Ok(files) => {
+            req.messages.splice(0..0, files);
+        }
+        Err(id) => return invalid_request("file_ids", format!("No file {}", id)),
+    }
     let stream = req.stream.unwrap_or(false);
 
-    if stream {
-        handle_streaming_completion(state, req).await
+    if req.uses_tools() {
+        handle_tool_completion(state, req, id, stream).await
+    } else if stream {
+        handle_streaming_completion(state, req, id).await
     } else {
-        handle_completion(state, req).await
+        handle_completion(state, req, id).await
     }
 }
 
 /// Handle non-streaming completion
-async fn handle_completion(state: Arc<AppState>, req: ChatCompletionRequest) -> Response {
-    let request_id = format!("chatcmpl-{}", Uuid::new_v4());
+async fn handle_completion(
+    state: Arc<AppState>,
+    req: ChatCompletionRequest,
+    id: RequestId,
+) -> Response {
+    let request_id = format!("chatcmpl-{}", id.0);
+    let chaos = apply_chaos(&state, &request_id).await;
 
-    // Build RLM config
-    let mut config = RlmConfig::new(&state.model);
-    if let Some(temp) = req.temperature {
-        config = config.with_temperature(temp);
-    }
-    if let Some(max_tokens) = req.max_tokens {
-        config = config.with_max_tokens(max_tokens);
-    }
+    let config = request_config(&state, &req);
+    let stop = req.stop_sequences();
+    let include_trace = req.include_trace();
 
     // Create RLM instance
-    let rlm = match create_rlm(&state, config) {
+    let rlm = match create_rlm(&state, config, &chaos) {
         Ok(r) => r,
         Err(e) => {
             return (
@@ -85,26 +291,34 @@ async fn handle_completion(state: Arc<AppState>, req: ChatCompletionRequest) ->
                 .into_response();
         }
     };
+    let rlm = rlm.with_event_stream(move |event: &RlmEvent| log_event(&id.0, event));
 
     // Convert messages to RLM format
     let messages = convert_messages(&req.messages);
     let prompt = PromptInput::Messages(messages);
 
     // Run completion in a blocking task (RLM uses synchronous code)
-    let result = tokio::task::spawn_blocking(move || rlm.completion(prompt)).await;
+    let session_id = req.session_id.clone();
+    let run_state = state.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        run_completion(&run_state, session_id.as_deref(), rlm, prompt)
+    })
+    .await;
 
     match result {
         Ok(Ok(completion)) => {
+            let trace = include_trace.then(|| RlmTrace::new(&completion));
             let response = ChatCompletionResponse::new(
                 request_id,
                 state.model.clone(),
-                completion.response,
+                cut_at_stop(completion.response, &stop),
                 CompletionUsage {
                     prompt_tokens: completion.usage.input_tokens,
                     completion_tokens: completion.usage.output_tokens,
                     total_tokens: completion.usage.total_tokens,
                 },
-            );
+            )
+            .with_trace(trace);
             (StatusCode::OK, Json(response)).into_response()
         }
         Ok(Err(e)) => (
@@ -130,22 +344,213 @@ async fn handle_completion(state: Arc<AppState>, req: ChatCompletionRequest) ->
     }
 }
 
-/// Handle streaming completion
-async fn handle_streaming_completion(state: Arc<AppState>, req: ChatCompletionRequest) -> Response {
-    let request_id = format!("chatcmpl-{}", Uuid::new_v4());
-    let model = state.model.clone();
+/// The files as user messages to go before the conversation, so their
+/// text is part of the context; Err names an unknown file
+fn file_messages(files: &Files, ids: &[String]) -> Result<Vec<ChatMessage>, String> {
+    ids.iter()
+        .map(|id| {
+            let (name, text) = files.text(id).ok_or_else(|| id.clone())?;
+            let content = format!("FILE {}:\n{}\n", name, text);
+            Ok(ChatMessage::new("user", content))
+        })
+        .collect()
+}
 
-    // Build RLM config
-    let mut config = RlmConfig::new(&state.model);
-    if let Some(temp) = req.temperature {
-        config = config.with_temperature(temp);
+/// Run a completion, in the request's session if it names one; blocks
+fn run_completion(
+    state: &AppState,
+    session_id: Option<&str>,
+    rlm: Rlm,
+    prompt: PromptInput,
+) -> rlm_core::Result<RlmCompletion> {
+    match session_id {
+        Some(id) => state.sessions.complete(id, rlm, prompt),
+        None => rlm.completion(prompt),
     }
-    if let Some(max_tokens) = req.max_tokens {
-        config = config.with_max_tokens(max_tokens);
+}
+
+/// Handler for POST /v1/sessions
+///
+/// The session's REPL is set up with the server's defaults; each completion
+/// in it still takes its own options.
+pub async fn create_session(State(state): State<Arc<AppState>>) -> Response {
+    let config = RlmConfig::new(&state.model).with_task_mode(TaskMode::Answer);
+    let rlm = match create_rlm(&state, config, &ChaosPlan::default()) {
+        Ok(rlm) => rlm,
+        Err(e) => return server_error(format!("Failed to create RLM: {}", e)),
+    };
+    let create_state = state.clone();
+    match tokio::task::spawn_blocking(move || create_state.sessions.create(rlm)).await {
+        Ok(Ok(id)) => (StatusCode::OK, Json(SessionObject::new(id))).into_response(),
+        Ok(Err(RlmError::BudgetExceeded(message))) => too_many_requests(message),
+        Ok(Err(e)) => server_error(format!("Failed to start session: {}", e)),
+        Err(e) => server_error(format!("Task join error: {}", e)),
     }
+}
+
+/// Handler for DELETE /v1/sessions/{session_id}
+pub async fn delete_session(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Response {
+    if !state.sessions.close(&id) {
+        return not_found(format!("No session {}", id));
+    }
+    Json(serde_json::json!({
+        "id": id,
+        "object": "session.deleted",
+        "deleted": true
+    }))
+    .into_response()
+}
+
+/// Handler for POST /v1/files
+///
+/// Takes `multipart/form-data` as OpenAI's endpoint does: the document in
+/// the `file` field, whose file name helps tell its kind, and an optional
+/// `purpose`. Documents are stored as their text.
+pub async fn upload_file(
+    State(state): State<Arc<AppState>>,
+    form: Result<Multipart, MultipartRejection>,
+) -> Response {
+    let mut form = match form {
+        Ok(form) => form,
+        Err(e) => return invalid_request("file", e.body_text()),
+    };
+    let form_error = |e: MultipartError| {
+        invalid_request("file", format!("Invalid form data: {}", e.body_text()))
+    };
+    let mut file = None;
+    let mut purpose = "user_data".to_string();
+    loop {
+        let field = match form.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => return form_error(e),
+        };
+        match field.name() {
+            Some("file") => {
+                let filename = field.file_name().unwrap_or("upload").to_string();
+                match field.bytes().await {
+                    Ok(bytes) => file = Some((filename, bytes)),
+                    Err(e) => return form_error(e),
+                }
+            }
+            Some("purpose") => match field.text().await {
+                Ok(text) => purpose = text,
+                Err(e) => return form_error(e),
+            },
+            _ => {}
+        }
+    }
+    let Some((filename, bytes)) = file else {
+        return invalid_request("file", "The form has no file field".to_string());
+    };
+    if bytes.is_empty() {
+        return invalid_request("file", "The file is empty".to_string());
+    }
+    let files_state = state.clone();
+    let stored =
+        tokio::task::spawn_blocking(move || files_state.files.add(&filename, &purpose, &bytes))
+            .await;
+    match stored {
+        Ok(Ok(info)) => (StatusCode::OK, Json(info)).into_response(),
+        Ok(Err(RlmError::BudgetExceeded(message))) => payload_too_large("file", message),
+        Ok(Err(e)) => invalid_request("file", format!("Can't read the file: {}", e)),
+        Err(e) => server_error(format!("Task join error: {}", e)),
+    }
+}
+
+/// Handler for GET /v1/files
+pub async fn list_files(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    Json(serde_json::json!({
+        "object": "list",
+        "data": state.files.list()
+    }))
+}
+
+/// Handler for GET /v1/files/{file_id}
+pub async fn get_file(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Response {
+    match state.files.info(&id) {
+        Some(info) => Json(info).into_response(),
+        None => not_found(format!("No file {}", id)),
+    }
+}
+
+/// Handler for DELETE /v1/files/{file_id}
+pub async fn delete_file(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Response {
+    if !state.files.remove(&id) {
+        return not_found(format!("No file {}", id));
+    }
+    Json(serde_json::json!({
+        "id": id,
+        "object": "file",
+        "deleted": true
+    }))
+    .into_response()
+}
+
+/// Progress line for an engine event
+fn progress_text(event: &RlmEvent) -> String {
+    match event {
+        RlmEvent::IterationStarted {
+            iteration,
+            max_iterations,
+        } => format!("iteration {}/{}", iteration, max_iterations),
+        RlmEvent::CodeStarted { iteration, .. } => format!("iteration {}: running code", iteration),
+        RlmEvent::CodeFinished {
+            iteration,
+            error: Some(error),
+            ..
+        } => format!(
+            "iteration {}: code failed: {}",
+            iteration,
+            error.lines().next().unwrap_or("")
+        ),
+        RlmEvent::CodeFinished { iteration, .. } => format!("iteration {}: code ran", iteration),
+        RlmEvent::SubQuery { cached: true } => "sub-query (cached)".to_string(),
+        RlmEvent::SubQuery { cached: false } => "sub-query".to_string(),
+        RlmEvent::IterationFinished {
+            iteration,
+            duration,
+        } => format!(
+            "iteration {} done in {:.1}s",
+            iteration,
+            duration.as_secs_f64()
+        ),
+    }
+}
+
+/// Log an engine event; it happens on the run's threads, outside the
+/// request's span, so it names the request itself
+fn log_event(request_id: &str, event: &RlmEvent) {
+    tracing::info!(request_id, "{}", progress_text(event));
+}
+
+/// Handle streaming completion
+///
+/// The answer is what model code passes to `llm_output`, so it only exists
+/// once the run ends and arrives as one content chunk; the root model's
+/// tokens before that are code, not answer, and are not streamed. While
+/// the run goes, chunks with an empty delta carry its progress in the
+/// `rlm_progress` extension: `{"kind": "event", "text": "iteration 2/20"}`
+/// for engine events, `{"kind": "output", ...}` for each line model code
+/// prints. Clients that only read `delta.content` see nothing of them.
+async fn handle_streaming_completion(
+    state: Arc<AppState>,
+    req: ChatCompletionRequest,
+    id: RequestId,
+) -> Response {
+    let request_id = format!("chatcmpl-{}", id.0);
+    let chaos = apply_chaos(&state, &request_id).await;
+    let model = state.model.clone();
+
+    let config = request_config(&state, &req);
+    let stop = req.stop_sequences();
+    let include_trace = req.include_trace();
 
     // Create RLM instance
-    let rlm = match create_rlm(&state, config) {
+    let rlm = match create_rlm(&state, config, &chaos) {
         Ok(r) => r,
         Err(e) => {
             return (
@@ -168,38 +573,78 @@ async fn handle_streaming_completion(state: Arc<AppState>, req: ChatCompletionRe
     // Create a channel to stream results
     let (tx, rx) = tokio::sync::mpsc::channel::<Result<Event, Infallible>>(100);
 
+    // Progress from the run's threads, while it goes. The callbacks may
+    // be kept past the run, so they hold weak senders that don't keep the
+    // stream open after [DONE]
+    let progress = {
+        let tx = tx.downgrade();
+        let (request_id, model) = (request_id.clone(), model.clone());
+        move |progress: RlmProgress| {
+            if let Some(tx) = tx.upgrade() {
+                let chunk =
+                    ChatCompletionChunk::with_progress(request_id.clone(), model.clone(), progress);
+                let _ = tx.blocking_send(Ok(
+                    Event::default().data(serde_json::to_string(&chunk).unwrap())
+                ));
+            }
+        }
+    };
+    let output_progress = progress.clone();
+    let rlm = rlm
+        .with_event_stream(move |event: &RlmEvent| {
+            log_event(&id.0, event);
+            progress(RlmProgress::new("event", progress_text(event)));
+        })
+        .with_output_stream(move |text: &str| {
+            for line in text.lines().filter(|line| !line.trim().is_empty()) {
+                output_progress(RlmProgress::new("output", line));
+            }
+        });
+
     // Spawn blocking task to run RLM
     let request_id_clone = request_id.clone();
     let model_clone = model.clone();
+    let session_id = req.session_id.clone();
     tokio::task::spawn_blocking(move || {
         // Send initial role chunk
-        let role_chunk = ChatCompletionChunk::with_role(request_id_clone.clone(), model_clone.clone());
-        let _ = tx.blocking_send(Ok(Event::default()
-            .data(serde_json::to_string(&role_chunk).unwrap())));
+        let role_chunk =
+            ChatCompletionChunk::with_role(request_id_clone.clone(), model_clone.clone());
+        let _ = tx.blocking_send(Ok(
+            Event::default().data(serde_json::to_string(&role_chunk).unwrap())
+        ));
+
+        if chaos.malformed_sse {
+            let _ = tx.blocking_send(Ok(Event::default().data(MALFORMED_SSE_DATA)));
+        }
 
         // Run completion
-        match rlm.completion(prompt) {
+        match run_completion(&state, session_id.as_deref(), rlm, prompt) {
             Ok(completion) => {
-                // Send content in chunks (split by words for more natural streaming)
-                for word in completion.response.split_inclusive(' ') {
-                    let content_chunk =
-                        ChatCompletionChunk::with_content(request_id_clone.clone(), model_clone.clone(), word.to_string());
-                    if tx
-                        .blocking_send(Ok(Event::default()
-                            .data(serde_json::to_string(&content_chunk).unwrap())))
-                        .is_err()
-                    {
-                        return;
-                    }
-                    // Small delay for more natural streaming feel
-                    std::thread::sleep(std::time::Duration::from_millis(10));
+                let trace = include_trace.then(|| RlmTrace::new(&completion));
+                let content_chunk = ChatCompletionChunk::with_content(
+                    request_id_clone.clone(),
+                    model_clone.clone(),
+                    cut_at_stop(completion.response, &stop),
+                );
+                if tx
+                    .blocking_send(Ok(
+                        Event::default().data(serde_json::to_string(&content_chunk).unwrap())
+                    ))
+                    .is_err()
+                {
+                    return;
                 }
 
-                // Send finish chunk
-                let finish_chunk =
-                    ChatCompletionChunk::finished(request_id_clone.clone(), model_clone.clone());
-                let _ = tx.blocking_send(Ok(Event::default()
-                    .data(serde_json::to_string(&finish_chunk).unwrap())));
+                // Send finish chunk, with the trace if asked for
+                let mut finish_chunk = ChatCompletionChunk::finished(
+                    request_id_clone.clone(),
+                    model_clone.clone(),
+                    "stop",
+                );
+                finish_chunk.rlm = trace;
+                let _ = tx.blocking_send(Ok(
+                    Event::default().data(serde_json::to_string(&finish_chunk).unwrap())
+                ));
 
                 // Send [DONE]
                 let _ = tx.blocking_send(Ok(Event::default().data("[DONE]")));
@@ -211,8 +656,9 @@ async fn handle_streaming_completion(state: Arc<AppState>, req: ChatCompletionRe
                     model_clone.clone(),
                     format!("Error: {}", e),
                 );
-                let _ = tx.blocking_send(Ok(Event::default()
-                    .data(serde_json::to_string(&error_chunk).unwrap())));
+                let _ = tx.blocking_send(Ok(
+                    Event::default().data(serde_json::to_string(&error_chunk).unwrap())
+                ));
                 let _ = tx.blocking_send(Ok(Event::default().data("[DONE]")));
             }
         }
@@ -226,12 +672,276 @@ async fn handle_streaming_completion(state: Arc<AppState>, req: ChatCompletionRe
         .into_response()
 }
 
-/// Create an RLM instance with the appropriate configuration
-fn create_rlm(state: &AppState, config: RlmConfig) -> rlm::Result<Rlm> {
-    match &state.backend_key {
-        Some(key) => Rlm::with_base_url_and_key(config, &state.backend_url, key),
-        None => Rlm::with_base_url(config, &state.backend_url),
+/// Split the conversation for the agent: the last user message is the
+/// task, the messages before it the earlier conversation, and the tool
+/// calls and results after it the turns the task resumes from
+fn agent_task(messages: &[ChatMessage]) -> (String, String, Vec<ClientTurn>) {
+    let names: HashMap<&str, &str> = messages
+        .iter()
+        .flat_map(|m| &m.tool_calls)
+        .map(|call| (call.id.as_str(), call.function.name.as_str()))
+        .collect();
+    let (before, after) = match messages.iter().rposition(|m| m.role == "user") {
+        Some(task) => (&messages[..task], &messages[task..]),
+        None => (messages, &messages[messages.len()..]),
+    };
+    let task = after.first().map(|m| m.content.clone()).unwrap_or_default();
+
+    let mut earlier = String::new();
+    if !before.is_empty() {
+        earlier.push_str("\nCONVERSATION SO FAR:\n");
+    }
+    for message in before {
+        let mut line = format!("{}: {}", message.role, message.content);
+        for call in &message.tool_calls {
+            line.push_str(&format!(
+                " [called {}: {}]",
+                call.function.name, call.function.arguments
+            ));
+        }
+        earlier.push_str(line.trim());
+        earlier.push('\n');
+    }
+
+    let turns = after
+        .iter()
+        .skip(1)
+        .filter_map(|message| match message.role.as_str() {
+            "assistant" => Some(ClientTurn::Assistant {
+                text: message.content.clone(),
+                calls: message
+                    .tool_calls
+                    .iter()
+                    .map(|call| ToolCall {
+                        name: call.function.name.clone(),
+                        args: call.function.arguments.clone(),
+                        arguments: serde_json::from_str(&call.function.arguments)
+                            .ok()
+                            .filter(serde_json::Value::is_object),
+                    })
+                    .collect(),
+            }),
+            "tool" => Some(ClientTurn::ToolResult {
+                name: message
+                    .tool_call_id
+                    .as_deref()
+                    .and_then(|id| names.get(id))
+                    .unwrap_or(&"tool")
+                    .to_string(),
+                output: message.content.clone(),
+            }),
+            _ => None,
+        })
+        .collect();
+    (task, earlier, turns)
+}
+
+/// Agent offering the request's tools, which the client runs
+fn create_agent(
+    state: &AppState,
+    req: &ChatCompletionRequest,
+    chaos: &ChaosPlan,
+) -> rlm_core::Result<Agent> {
+    let mut tools = ToolRegistry::new();
+    for tool in &req.tools {
+        let function = &tool.function;
+        tools.register(ClientTool::new(
+            &function.name,
+            &function.description,
+            function.parameters.clone(),
+        ));
+    }
+    let prompt = match req.tool_choice {
+        Some(ToolChoice::Mode(ref mode)) if mode == "required" => AgentPrompt::default()
+            .with_rule("Respond by calling at least one tool, not with an answer"),
+        Some(ToolChoice::Function { ref function }) => AgentPrompt::default()
+            .with_rule(format!("Respond by calling the {} tool", function.name)),
+        _ => AgentPrompt::default(),
+    };
+    let backend = if chaos.fail_backend {
+        Backend::Custom(Arc::new(FailingBackend))
+    } else {
+        Backend::OpenAI
+    };
+    let mut config = AgentConfig {
+        model: state.model.clone(),
+        backend,
+        base_url: Some(state.backend_url.clone()),
+        api_key: state.backend_key.clone(),
+        exec_log: false,
+        prompt,
+        ..Default::default()
+    };
+    if let Some(temp) = req.temperature {
+        config.temperature = temp;
+    }
+    if let Some(n) = req.rlm.as_ref().and_then(|rlm| rlm.max_iterations) {
+        config.max_iterations = n;
+    }
+    Agent::new(config, tools)
+}
+
+/// Run the agent on the request's conversation until it answers or calls
+/// tools; blocks
+fn run_agent(
+    state: &AppState,
+    req: &ChatCompletionRequest,
+    chaos: &ChaosPlan,
+) -> Result<AgentRun, String> {
+    let agent =
+        create_agent(state, req, chaos).map_err(|e| format!("Failed to create agent: {}", e))?;
+    let (task, earlier, turns) = agent_task(&req.messages);
+    agent
+        .run_resumed(&task, &earlier, &turns)
+        .map_err(|e| format!("Agent error: {}", e))
+}
+
+/// The run's pending calls as OpenAI tool calls, with fresh ids
+fn tool_call_messages(run: &AgentRun) -> Vec<ToolCallMessage> {
+    run.pending_calls
+        .iter()
+        .map(|call| ToolCallMessage {
+            id: format!("call_{}", Uuid::new_v4().simple()),
+            kind: "function".to_string(),
+            function: FunctionCall {
+                name: call.name.clone(),
+                arguments: call.args.clone(),
+            },
+        })
+        .collect()
+}
+
+/// Handle a completion offering tools
+///
+/// The agent runs until the model answers or calls tools. The client runs
+/// the calls (`finish_reason: "tool_calls"`) and sends them back with
+/// their results as `tool` messages in the next request, which resumes
+/// the task; nothing is executed on the server.
+async fn handle_tool_completion(
+    state: Arc<AppState>,
+    req: ChatCompletionRequest,
+    id: RequestId,
+    stream: bool,
+) -> Response {
+    let request_id = format!("chatcmpl-{}", id.0);
+    let chaos = apply_chaos(&state, &request_id).await;
+    let model = state.model.clone();
+    let stop = req.stop_sequences();
+
+    if !stream {
+        let result = tokio::task::spawn_blocking(move || run_agent(&state, &req, &chaos)).await;
+        let run = match result {
+            Ok(Ok(run)) => run,
+            Ok(Err(message)) => return server_error(message),
+            Err(e) => return server_error(format!("Task join error: {}", e)),
+        };
+        let usage = CompletionUsage {
+            prompt_tokens: run.usage.input_tokens,
+            completion_tokens: run.usage.output_tokens,
+            total_tokens: run.usage.total_tokens,
+        };
+        let response = if run.pending_calls.is_empty() {
+            ChatCompletionResponse::new(request_id, model, cut_at_stop(run.answer, &stop), usage)
+        } else {
+            let calls = tool_call_messages(&run);
+            ChatCompletionResponse::with_tool_calls(request_id, model, run.answer, calls, usage)
+        };
+        return (StatusCode::OK, Json(response)).into_response();
+    }
+
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<Event, Infallible>>(100);
+    tokio::task::spawn_blocking(move || {
+        let send = |chunk: ChatCompletionChunk| {
+            let data = serde_json::to_string(&chunk).unwrap();
+            let _ = tx.blocking_send(Ok(Event::default().data(data)));
+        };
+        send(ChatCompletionChunk::with_role(
+            request_id.clone(),
+            model.clone(),
+        ));
+        let chunks = match run_agent(&state, &req, &chaos) {
+            Ok(run) => run_chunks(&request_id, &model, run, &stop),
+            Err(message) => vec![ChatCompletionChunk::with_content(
+                request_id,
+                model,
+                format!("Error: {}", message),
+            )],
+        };
+        for chunk in chunks {
+            send(chunk);
+        }
+        let _ = tx.blocking_send(Ok(Event::default().data("[DONE]")));
+    });
+
+    let stream = tokio_stream::wrappers::ReceiverStream::new(rx);
+    Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// Chunks streaming the outcome of an agent run: what the model said, its
+/// tool calls if it made any, and the finish reason
+fn run_chunks(id: &str, model: &str, run: AgentRun, stop: &[String]) -> Vec<ChatCompletionChunk> {
+    let (id, model) = (id.to_string(), model.to_string());
+    if run.pending_calls.is_empty() {
+        let answer = cut_at_stop(run.answer, stop);
+        return vec![
+            ChatCompletionChunk::with_content(id.clone(), model.clone(), answer),
+            ChatCompletionChunk::finished(id, model, "stop"),
+        ];
     }
+    let calls = tool_call_messages(&run);
+    let mut chunks = Vec::new();
+    if !run.answer.is_empty() {
+        chunks.push(ChatCompletionChunk::with_content(
+            id.clone(),
+            model.clone(),
+            run.answer,
+        ));
+    }
+    chunks.push(ChatCompletionChunk::with_tool_calls(
+        id.clone(),
+        model.clone(),
+        calls,
+    ));
+    chunks.push(ChatCompletionChunk::finished(id, model, "tool_calls"));
+    chunks
+}
+
+/// 500 response in the OpenAI error shape
+fn server_error(message: String) -> Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(serde_json::json!({
+            "error": {
+                "message": message,
+                "type": "server_error"
+            }
+        })),
+    )
+        .into_response()
+}
+
+/// Create an RLM instance with the appropriate configuration
+fn create_rlm(state: &AppState, config: RlmConfig, chaos: &ChaosPlan) -> rlm_core::Result<Rlm> {
+    // Worker processes let concurrent requests run Python in parallel
+    // instead of taking turns on the in-process GIL
+    let config = match state.worker_pool {
+        Some(_) => config.with_repl_mode(ReplMode::Worker),
+        None => config,
+    };
+    let rlm = if chaos.fail_backend {
+        Rlm::with_chat_backend(config, Arc::new(FailingBackend))
+    } else {
+        match &state.backend_key {
+            Some(key) => Rlm::with_base_url_and_key(config, &state.backend_url, key),
+            None => Rlm::with_base_url(config, &state.backend_url),
+        }
+    }?;
+    Ok(match state.worker_pool {
+        Some(ref pool) => rlm.with_worker_pool(pool.clone()),
+        None => rlm,
+    })
 }
 
 /// Handler for GET /v1/models
@@ -254,3 +964,158 @@ pub async fn list_models(State(state): State<Arc<AppState>>) -> Json<serde_json:
         ]
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_progress_text() {
+        let code_failed = RlmEvent::CodeFinished {
+            iteration: 2,
+            success: false,
+            error: Some("NameError: x\n  at line 1".to_string()),
+        };
+        assert_eq!(
+            progress_text(&code_failed),
+            "iteration 2: code failed: NameError: x"
+        );
+        let finished = RlmEvent::IterationFinished {
+            iteration: 2,
+            duration: Duration::from_millis(1300),
+        };
+        assert_eq!(progress_text(&finished), "iteration 2 done in 1.3s");
+
+        let chunk = ChatCompletionChunk::with_progress(
+            "chatcmpl-1".to_string(),
+            "m".to_string(),
+            RlmProgress::new("event", progress_text(&finished)),
+        );
+        let chunk = serde_json::to_value(&chunk).unwrap();
+        assert_eq!(chunk["choices"][0]["delta"], serde_json::json!({}));
+        assert!(chunk["choices"][0].get("finish_reason").is_none());
+        assert_eq!(chunk["rlm_progress"]["kind"], "event");
+        assert_eq!(chunk["rlm_progress"]["text"], "iteration 2 done in 1.3s");
+    }
+
+    #[test]
+    fn test_rlm_options_from_headers_and_body() {
+        let mut headers = HeaderMap::new();
+        headers.insert("X-RLM-Max-Iterations", "4".parse().unwrap());
+        headers.insert("x-rlm-sub-model", "gpt-4o-mini".parse().unwrap());
+        headers.insert("X-RLM-Output-Truncation", "head".parse().unwrap());
+        headers.insert("X-RLM-Partial-Results", "true".parse().unwrap());
+        let from_headers = header_options(&headers).unwrap();
+
+        let body: RlmOptions =
+            serde_json::from_value(serde_json::json!({"max_iterations": 7})).unwrap();
+        let options = body.or(from_headers);
+        assert_eq!(options.max_iterations, Some(7));
+        assert_eq!(options.sub_model.as_deref(), Some("gpt-4o-mini"));
+
+        let config = options.apply(RlmConfig::new("gpt-4o"));
+        assert_eq!(config.max_iterations, 7);
+        assert_eq!(config.sub_model.as_deref(), Some("gpt-4o-mini"));
+        assert_eq!(config.output_truncation, rlm_core::OutputTruncation::Head);
+        assert!(config.partial_results);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("X-RLM-Max-Iterations", "many".parse().unwrap());
+        let (header, _) = header_options(&headers).unwrap_err();
+        assert_eq!(header, "X-RLM-Max-Iterations");
+        let mut headers = HeaderMap::new();
+        headers.insert("X-RLM-Colour", "blue".parse().unwrap());
+        assert!(header_options(&headers).is_err());
+    }
+
+    #[test]
+    fn test_cut_at_stop() {
+        let stop = ["END".to_string(), "\n\n".to_string()];
+        assert_eq!(cut_at_stop("a b\n\nc END".to_string(), &stop), "a b");
+        assert_eq!(cut_at_stop("a END b".to_string(), &stop), "a ");
+        assert_eq!(cut_at_stop("a b".to_string(), &[]), "a b");
+    }
+
+    #[test]
+    fn test_agent_task_from_tool_messages() {
+        let messages: Vec<ChatMessage> = serde_json::from_value(serde_json::json!([
+            {"role": "system", "content": "Be brief."},
+            {"role": "user", "content": "Weather in Paris?"},
+            {"role": "assistant", "content": null, "tool_calls": [{
+                "id": "call_1",
+                "type": "function",
+                "function": {"name": "weather", "arguments": "{\"city\":\"Paris\"}"}
+            }]},
+            {"role": "tool", "tool_call_id": "call_1", "content": "sunny"}
+        ]))
+        .unwrap();
+        let (task, earlier, turns) = agent_task(&messages);
+        assert_eq!(task, "Weather in Paris?");
+        assert_eq!(earlier, "\nCONVERSATION SO FAR:\nsystem: Be brief.\n");
+        assert_eq!(turns.len(), 2);
+        let ClientTurn::Assistant { ref calls, .. } = turns[0] else {
+            panic!("expected the assistant's calls first");
+        };
+        assert_eq!(calls[0].arguments.as_ref().unwrap()["city"], "Paris");
+        assert!(matches!(
+            turns[1],
+            ClientTurn::ToolResult { ref name, ref output } if name == "weather" && output == "sunny"
+        ));
+    }
+
+    #[test]
+    fn test_run_chunks_with_tool_calls() {
+        let run: AgentRun = serde_json::from_value(serde_json::json!({
+            "answer": "Checking.",
+            "rounds": 1,
+            "tool_calls": [],
+            "usage": rlm_core::Usage::default(),
+            "pending_calls": [{"name": "weather", "args": "{\"city\":\"Paris\"}"}]
+        }))
+        .unwrap();
+        let chunks: Vec<serde_json::Value> = run_chunks("id", "m", run, &[])
+            .iter()
+            .map(|chunk| serde_json::to_value(chunk).unwrap())
+            .collect();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0]["choices"][0]["delta"]["content"], "Checking.");
+        let call = &chunks[1]["choices"][0]["delta"]["tool_calls"][0];
+        assert_eq!(call["index"], 0);
+        assert_eq!(call["type"], "function");
+        assert!(call["id"].as_str().unwrap().starts_with("call_"));
+        assert_eq!(call["function"]["arguments"], "{\"city\":\"Paris\"}");
+        assert_eq!(chunks[2]["choices"][0]["finish_reason"], "tool_calls");
+    }
+
+    #[test]
+    fn test_trace_extension() {
+        let mock = rlm_core::MockBackend::new([
+            "```repl\nprint(llm_query('hi'))\n```",
+            "sub answer",
+            "FINAL(done)",
+        ]);
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(mock))
+            .with_repl_mode(ReplMode::Worker);
+        let completion = Rlm::new(config).unwrap().completion("q").unwrap();
+        let usage = || CompletionUsage {
+            prompt_tokens: 0,
+            completion_tokens: 0,
+            total_tokens: 0,
+        };
+
+        let response = ChatCompletionResponse::new("id".into(), "m".into(), "done".into(), usage());
+        let json = serde_json::to_value(&response).unwrap();
+        assert!(json.get("rlm").is_none());
+
+        let trace = RlmTrace::new(&completion);
+        let response = response.with_trace(Some(trace));
+        let json = serde_json::to_value(&response).unwrap();
+        let first = &json["rlm"]["iterations"][0];
+        assert_eq!(first["code_blocks"][0]["result"]["stdout"], "sub answer\n");
+        assert_eq!(first["sub_call_usage"]["total_tokens"], 3);
+        assert_eq!(json["rlm"]["sub_call_usage"]["total_tokens"], 3);
+        assert_eq!(json["rlm"]["status"], "answered");
+    }
+}
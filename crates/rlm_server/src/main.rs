@@ -1,17 +1,44 @@
 //! RLM Server - OpenAI-compatible API for RLM
 
+mod chaos;
+mod files;
 mod handlers;
+mod logging;
+mod sessions;
 mod types;
 
-use axum::{routing::{get, post}, Router};
+use axum::{
+    extract::DefaultBodyLimit,
+    http::HeaderName,
+    middleware,
+    routing::{delete, get, post},
+    Router,
+};
 use clap::Parser;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
+use tower_http::compression::predicate::{NotForContentType, Predicate, SizeAbove};
+use tower_http::compression::CompressionLayer;
 use tower_http::cors::{Any, CorsLayer};
+use tower_http::decompression::RequestDecompressionLayer;
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use handlers::{create_chat_completion, list_models, AppState};
+use chaos::ChaosConfig;
+use files::Files;
+use handlers::{
+    create_chat_completion, create_session, delete_file, delete_session, get_file, list_files,
+    list_models, upload_file, AppState,
+};
+use logging::{
+    assign_request_id, record_transcript, request_span, TranscriptLog, REQUEST_ID_HEADER,
+};
+use rlm::worker::WorkerPool;
+use rlm_core::PythonEnv;
+use sessions::Sessions;
+use types::RlmLimits;
 
 /// RLM Server - OpenAI-compatible API for Recursive Language Models
 #[derive(Parser, Debug)]
@@ -33,6 +60,109 @@ struct Args {
     /// Backend API key (optional, uses OPENAI_API_KEY env var if not provided)
     #[arg(short = 'k', long)]
     backend_key: Option<String>,
+
+    /// Compress responses (JSON and SSE) larger than this many bytes when the
+    /// client sends Accept-Encoding: gzip or deflate
+    #[arg(long, default_value = "1024")]
+    compress_min_bytes: u16,
+
+    /// Run each request's REPL in a separate Python process, keeping this
+    /// many started ahead of time (0 = in-process REPLs, which execute
+    /// Python one request at a time)
+    #[arg(long, default_value = "0")]
+    repl_workers: usize,
+
+    /// Maximum request body size in megabytes, after decompression
+    #[arg(long, default_value = "64")]
+    max_body_mb: usize,
+
+    /// Close sessions unused for this many minutes
+    #[arg(long, default_value = "30")]
+    session_idle_mins: u64,
+
+    /// Most sessions open at once; more are refused with 429
+    #[arg(long, default_value = "100")]
+    max_sessions: usize,
+
+    /// Most megabytes of text kept for uploaded files; uploads past it are
+    /// refused with 413
+    #[arg(long, default_value = "512")]
+    max_files_mb: usize,
+
+    /// Highest `max_iterations` a request may set
+    #[arg(long, default_value = "100")]
+    max_request_iterations: u32,
+
+    /// Highest `max_sub_calls` (and per-iteration limit) a request may set
+    #[arg(long, default_value = "1000")]
+    max_request_sub_calls: u32,
+
+    /// Highest `context_window` a request may set, in tokens
+    #[arg(long, default_value = "2000000")]
+    max_request_context_window: usize,
+
+    /// Write each chat completion's request and response, secrets
+    /// redacted, to a JSON file named by its request id in this directory
+    #[arg(long)]
+    transcript_dir: Option<PathBuf>,
+
+    /// Chaos testing: fraction of requests whose backend calls fail
+    #[arg(long, default_value = "0", hide = true)]
+    chaos_failure_rate: f64,
+
+    /// Chaos testing: fraction of requests delayed by --chaos-slow-ms
+    #[arg(long, default_value = "0", hide = true)]
+    chaos_slow_rate: f64,
+
+    /// Chaos testing: delay for slowed requests, in milliseconds
+    #[arg(long, default_value = "5000", hide = true)]
+    chaos_slow_ms: u64,
+
+    /// Chaos testing: fraction of streaming requests that emit malformed SSE
+    #[arg(long, default_value = "0", hide = true)]
+    chaos_malformed_sse_rate: f64,
+}
+
+/// Build the API router with its middleware stack
+///
+/// Request bodies may be gzip/deflate encoded (Content-Encoding); responses
+/// are compressed when the client advertises support. SSE is included -
+/// the encoder flushes whenever the event stream pauses, so chunks still
+/// arrive as they are produced.
+fn router(state: Arc<AppState>, compress_min_bytes: u16, max_body_bytes: usize) -> Router {
+    // CORS configuration for browser clients
+    let cors = CorsLayer::new()
+        .allow_origin(Any)
+        .allow_methods(Any)
+        .allow_headers(Any)
+        .expose_headers([HeaderName::from_static(REQUEST_ID_HEADER)]);
+
+    let compression = CompressionLayer::new().compress_when(
+        SizeAbove::new(compress_min_bytes)
+            .and(NotForContentType::GRPC)
+            .and(NotForContentType::IMAGES),
+    );
+
+    Router::new()
+        .route(
+            "/v1/chat/completions",
+            post(create_chat_completion).layer(middleware::from_fn_with_state(
+                state.transcripts.clone(),
+                record_transcript,
+            )),
+        )
+        .route("/v1/models", get(list_models))
+        .route("/v1/sessions", post(create_session))
+        .route("/v1/sessions/{session_id}", delete(delete_session))
+        .route("/v1/files", post(upload_file).get(list_files))
+        .route("/v1/files/{file_id}", get(get_file).delete(delete_file))
+        .layer(DefaultBodyLimit::max(max_body_bytes))
+        .layer(RequestDecompressionLayer::new())
+        .layer(compression)
+        .layer(TraceLayer::new_for_http().make_span_with(request_span))
+        .layer(cors)
+        .layer(middleware::from_fn(assign_request_id))
+        .with_state(state)
 }
 
 #[tokio::main]
@@ -45,33 +175,387 @@ async fn main() {
     let args = Args::parse();
 
     // Resolve API key from args or environment
-    let backend_key = args.backend_key.or_else(|| std::env::var("OPENAI_API_KEY").ok());
+    let backend_key = args
+        .backend_key
+        .or_else(|| std::env::var("OPENAI_API_KEY").ok());
+
+    let chaos = ChaosConfig {
+        failure_rate: args.chaos_failure_rate.clamp(0.0, 1.0),
+        slow_rate: args.chaos_slow_rate.clamp(0.0, 1.0),
+        slow_delay: Duration::from_millis(args.chaos_slow_ms),
+        malformed_sse_rate: args.chaos_malformed_sse_rate.clamp(0.0, 1.0),
+    };
+    if chaos.is_enabled() {
+        tracing::warn!("Chaos mode enabled: {:?}", chaos);
+    }
+
+    let transcripts = args
+        .transcript_dir
+        .as_ref()
+        .map(|dir| match TranscriptLog::new(dir) {
+            Ok(log) => Arc::new(log),
+            Err(e) => panic!("Can't use transcript directory {}: {}", dir.display(), e),
+        });
 
     let state = Arc::new(AppState {
         model: args.model.clone(),
         backend_url: args.backend_url.clone(),
         backend_key,
+        chaos,
+        worker_pool: (args.repl_workers > 0)
+            .then(|| WorkerPool::for_env(&PythonEnv::default(), args.repl_workers)),
+        sessions: Sessions::new(
+            Duration::from_secs(args.session_idle_mins * 60),
+            args.max_sessions,
+        ),
+        files: Files::new(args.max_files_mb * 1024 * 1024),
+        transcripts,
+        limits: RlmLimits {
+            max_iterations: args.max_request_iterations,
+            max_sub_calls: args.max_request_sub_calls,
+            context_window: args.max_request_context_window,
+        },
     });
 
-    // CORS configuration for browser clients
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(Any);
+    let sweep_state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(sessions::SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            sweep_state.sessions.close_idle();
+        }
+    });
 
-    // Build router
-    let app = Router::new()
-        .route("/v1/chat/completions", post(create_chat_completion))
-        .route("/v1/models", get(list_models))
-        .layer(TraceLayer::new_for_http())
-        .layer(cors)
-        .with_state(state);
+    let app = router(
+        state,
+        args.compress_min_bytes,
+        args.max_body_mb * 1024 * 1024,
+    );
 
     let addr = SocketAddr::from(([0, 0, 0, 0], args.port));
     tracing::info!("RLM Server starting on {}", addr);
     tracing::info!("Model: {}", args.model);
     tracing::info!("Backend URL: {}", args.backend_url);
+    if args.repl_workers > 0 {
+        tracing::info!("REPL workers: {} (pooled processes)", args.repl_workers);
+    }
 
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
     axum::serve(listener, app).await.unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{header, Request, StatusCode};
+    use flate2::read::GzDecoder;
+    use flate2::write::GzEncoder;
+    use http_body_util::BodyExt;
+    use std::io::{Read, Write};
+    use tower::ServiceExt;
+
+    fn test_state() -> AppState {
+        AppState {
+            model: "test-model".to_string(),
+            backend_url: "http://127.0.0.1:9".to_string(),
+            backend_key: None,
+            chaos: ChaosConfig::default(),
+            worker_pool: None,
+            sessions: Sessions::new(Duration::from_secs(60), 4),
+            files: Files::new(1024 * 1024),
+            transcripts: None,
+            limits: RlmLimits::default(),
+        }
+    }
+
+    fn test_router(compress_min_bytes: u16) -> Router {
+        router(Arc::new(test_state()), compress_min_bytes, 1024 * 1024)
+    }
+
+    #[tokio::test]
+    async fn test_gzip_response_when_accepted() {
+        let req = Request::get("/v1/models")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let res = test_router(0).oneshot(req).await.unwrap();
+        assert_eq!(res.headers()[header::CONTENT_ENCODING], "gzip");
+
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let mut json = String::new();
+        GzDecoder::new(&body[..]).read_to_string(&mut json).unwrap();
+        assert!(json.contains("test-model"));
+
+        // Small responses are left alone
+        let req = Request::get("/v1/models")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let res = test_router(u16::MAX).oneshot(req).await.unwrap();
+        assert!(res.headers().get(header::CONTENT_ENCODING).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_gzip_request_body_decoded() {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"{}").unwrap();
+        let req = Request::post("/v1/chat/completions")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::CONTENT_ENCODING, "gzip")
+            .body(Body::from(encoder.finish().unwrap()))
+            .unwrap();
+        let res = test_router(1024).oneshot(req).await.unwrap();
+        // Valid JSON missing required fields, not a syntax error on gzip bytes
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_parameters_rejected() {
+        for (param, value) in [
+            ("n", serde_json::json!(2)),
+            ("top_p", serde_json::json!(1.5)),
+            ("stop", serde_json::json!(["a", "b", "c", "d", "e"])),
+            ("frequency_penalty", serde_json::json!(-3)),
+            ("response_format", serde_json::json!({"type": "xml"})),
+            (
+                "response_format",
+                serde_json::json!({"type": "json_schema"}),
+            ),
+            (
+                "response_format",
+                serde_json::json!({"type": "json_schema", "json_schema": {"schema": {"type": 5}}}),
+            ),
+            (
+                "tools",
+                serde_json::json!([{"type": "retrieval", "function": {"name": "x"}}]),
+            ),
+            (
+                "tool_choice",
+                serde_json::json!({"type": "function", "function": {"name": "missing"}}),
+            ),
+            (
+                "messages",
+                serde_json::json!([{"role": "tool", "tool_call_id": "call_1", "content": "x"}]),
+            ),
+        ] {
+            let mut body = serde_json::json!({
+                "messages": [{"role": "user", "content": "hi"}]
+            });
+            body[param] = value;
+            let req = Request::post("/v1/chat/completions")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            let res = test_router(1024).oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+            let body = res.into_body().collect().await.unwrap().to_bytes();
+            let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(error["error"]["param"], param);
+        }
+
+        // A stop sequence could cut a JSON answer short
+        let body = serde_json::json!({
+            "messages": [{"role": "user", "content": "hi"}],
+            "response_format": {"type": "json_object"},
+            "stop": "}"
+        });
+        let req = Request::post("/v1/chat/completions")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let res = test_router(1024).oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error["error"]["param"], "stop");
+
+        // The tool-calling agent would silently drop these
+        for (field, value, param) in [
+            ("seed", serde_json::json!(7), "seed"),
+            ("max_tokens", serde_json::json!(100), "max_tokens"),
+            (
+                "rlm",
+                serde_json::json!({"sub_model": "gpt-4o-mini"}),
+                "rlm.sub_model",
+            ),
+        ] {
+            let mut body = serde_json::json!({
+                "messages": [{"role": "user", "content": "hi"}],
+                "tools": [{"type": "function", "function": {"name": "lookup"}}]
+            });
+            body[field] = value;
+            let req = Request::post("/v1/chat/completions")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            let res = test_router(1024).oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+            let body = res.into_body().collect().await.unwrap().to_bytes();
+            let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(error["error"]["param"], param);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unknown_session_not_found() {
+        let body = serde_json::json!({
+            "messages": [{"role": "user", "content": "hi"}],
+            "session_id": "sess_missing"
+        });
+        let req = Request::post("/v1/chat/completions")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let res = test_router(1024).oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let req = Request::delete("/v1/sessions/sess_missing")
+            .body(Body::empty())
+            .unwrap();
+        let res = test_router(1024).oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_file_upload_and_delete() {
+        let app = test_router(1024);
+        let form = "--XyZ\r\n\
+            Content-Disposition: form-data; name=\"purpose\"\r\n\r\n\
+            user_data\r\n\
+            --XyZ\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"notes.txt\"\r\n\
+            Content-Type: text/plain\r\n\r\n\
+            line one\nline two\r\n\
+            --XyZ--\r\n";
+        let req = Request::post("/v1/files")
+            .header(header::CONTENT_TYPE, "multipart/form-data; boundary=XyZ")
+            .body(Body::from(form))
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let file: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(file["object"], "file");
+        assert_eq!(file["bytes"], 17);
+        assert_eq!(file["purpose"], "user_data");
+        let id = file["id"].as_str().unwrap().to_string();
+
+        let req = Request::get("/v1/files").body(Body::empty()).unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let list: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(list["data"][0]["id"], id.as_str());
+
+        let body = serde_json::json!({
+            "messages": [{"role": "user", "content": "hi"}],
+            "file_ids": [id, "file-missing"]
+        });
+        let req = Request::post("/v1/chat/completions")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error["error"]["param"], "file_ids");
+        assert!(error["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("file-missing"));
+
+        let uri = format!("/v1/files/{}", id);
+        let req = Request::delete(uri.as_str()).body(Body::empty()).unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let req = Request::get(uri.as_str()).body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_request_id_and_transcript() {
+        let dir = std::env::temp_dir().join(format!("rlm_transcripts_{}", std::process::id()));
+        let mut state = test_state();
+        state.transcripts = Some(Arc::new(TranscriptLog::new(&dir).unwrap()));
+        let app = router(Arc::new(state), 1024, 1024 * 1024);
+
+        let body = serde_json::json!({
+            "messages": [{"role": "user", "content": "key sk-abcdefghijklmnopqrst"}],
+            "n": 2
+        });
+        let req = Request::post("/v1/chat/completions")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::AUTHORIZATION, "Bearer client-secret")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let id = res.headers()[REQUEST_ID_HEADER]
+            .to_str()
+            .unwrap()
+            .to_string();
+        // The transcript is written once the response body is done
+        res.into_body().collect().await.unwrap();
+
+        let path = dir.join(format!("{}.json", id));
+        let transcript: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(transcript["request_id"], id.as_str());
+        assert_eq!(transcript["status"], 400);
+        assert_eq!(
+            transcript["request"]["headers"]["authorization"],
+            "[REDACTED]"
+        );
+        assert_eq!(
+            transcript["request"]["body"]["messages"][0]["content"],
+            "key [REDACTED]"
+        );
+        assert_eq!(transcript["response"]["body"]["error"]["param"], "n");
+    }
+
+    #[tokio::test]
+    async fn test_invalid_rlm_options_rejected() {
+        let body = serde_json::json!({
+            "messages": [{"role": "user", "content": "hi"}]
+        })
+        .to_string();
+        let requests = [
+            (
+                Request::post("/v1/chat/completions")
+                    .header("X-RLM-Max-Iterations", "0")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body.clone()))
+                    .unwrap(),
+                "rlm.max_iterations",
+            ),
+            (
+                Request::post("/v1/chat/completions")
+                    .header("X-RLM-Max-Sub-Calls", "1000000")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body.clone()))
+                    .unwrap(),
+                "rlm.max_sub_calls",
+            ),
+            (
+                Request::post("/v1/chat/completions")
+                    .header("X-RLM-Context-Window", "large")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body))
+                    .unwrap(),
+                "X-RLM-Context-Window",
+            ),
+        ];
+        for (req, param) in requests {
+            let res = test_router(1024).oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+            let body = res.into_body().collect().await.unwrap().to_bytes();
+            let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(error["error"]["param"], param);
+        }
+    }
+}
@@ -0,0 +1,228 @@
+//! Sessions: a REPL and conversation kept alive between completions
+//!
+//! `POST /v1/sessions` starts one; completions naming its `session_id` then
+//! only send the new messages and run in the same REPL, so they can build
+//! on what earlier turns computed. Each session lives on its own thread,
+//! which owns the engine's [`RlmSession`] and runs the turns sent to it one
+//! at a time. Sessions unused for the idle timeout are closed by a sweep
+//! the server runs every [`SWEEP_INTERVAL`], and at most `max_sessions`
+//! are open at once.
+
+use std::collections::HashMap;
+use std::sync::{mpsc, Mutex};
+use std::time::{Duration, Instant};
+
+use rlm::{Rlm, RlmSession};
+use rlm_core::{PromptInput, RlmCompletion, RlmError};
+use uuid::Uuid;
+
+/// How often the server closes idle sessions
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A turn for a session's thread
+struct Turn {
+    /// Run with this request's config, in the session's REPL
+    rlm: Rlm,
+    prompt: PromptInput,
+    reply: mpsc::Sender<rlm_core::Result<RlmCompletion>>,
+}
+
+struct Session {
+    turns: mpsc::Sender<Turn>,
+    last_used: Instant,
+    /// Turns sent and not answered yet
+    running: usize,
+}
+
+/// The open sessions by id
+pub struct Sessions {
+    sessions: Mutex<HashMap<String, Session>>,
+    idle_timeout: Duration,
+    max_sessions: usize,
+}
+
+impl Sessions {
+    pub fn new(idle_timeout: Duration, max_sessions: usize) -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            idle_timeout,
+            max_sessions,
+        }
+    }
+
+    /// Start a session with a REPL set up by `rlm`; blocks until the REPL
+    /// is ready. Fails with [`RlmError::BudgetExceeded`] if `max_sessions`
+    /// are open.
+    pub fn create(&self, rlm: Rlm) -> rlm_core::Result<String> {
+        self.close_idle();
+        self.check_room(&self.sessions.lock().unwrap())?;
+
+        let (ready_tx, ready) = mpsc::channel();
+        let (turns, receiver) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("rlm-session".to_string())
+            .spawn(move || match rlm.session() {
+                Ok(session) => {
+                    let _ = ready_tx.send(Ok(()));
+                    serve_session(session, receiver);
+                }
+                Err(e) => {
+                    let _ = ready_tx.send(Err(e));
+                }
+            })?;
+        ready
+            .recv()
+            .map_err(|_| RlmError::Repl("session thread exited while starting".to_string()))??;
+
+        let id = format!("sess_{}", Uuid::new_v4().simple());
+        let mut sessions = self.sessions.lock().unwrap();
+        // Others may have started while this REPL was set up
+        self.check_room(&sessions)?;
+        sessions.insert(
+            id.clone(),
+            Session {
+                turns,
+                last_used: Instant::now(),
+                running: 0,
+            },
+        );
+        Ok(id)
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.sessions.lock().unwrap().contains_key(id)
+    }
+
+    /// Close a session, ending its REPL; false if there was none
+    pub fn close(&self, id: &str) -> bool {
+        self.sessions.lock().unwrap().remove(id).is_some()
+    }
+
+    /// Run a turn of a session; blocks until it is done, after the turns
+    /// sent before it
+    pub fn complete(
+        &self,
+        id: &str,
+        rlm: Rlm,
+        prompt: PromptInput,
+    ) -> rlm_core::Result<RlmCompletion> {
+        let closed = || RlmError::Repl(format!("session {} is closed", id));
+        let turns = {
+            let mut sessions = self.sessions.lock().unwrap();
+            let session = sessions.get_mut(id).ok_or_else(closed)?;
+            session.running += 1;
+            session.turns.clone()
+        };
+        let (reply, result) = mpsc::channel();
+        let completion = match turns.send(Turn { rlm, prompt, reply }) {
+            Ok(()) => result.recv().map_err(|_| closed()).and_then(|r| r),
+            Err(_) => Err(closed()),
+        };
+        // Idle time counts from the end of the turn
+        if let Some(session) = self.sessions.lock().unwrap().get_mut(id) {
+            session.running -= 1;
+            session.last_used = Instant::now();
+        }
+        completion
+    }
+
+    fn check_room(&self, sessions: &HashMap<String, Session>) -> rlm_core::Result<()> {
+        if sessions.len() >= self.max_sessions {
+            return Err(RlmError::BudgetExceeded(format!(
+                "{} sessions are open, the most this server allows",
+                self.max_sessions
+            )));
+        }
+        Ok(())
+    }
+
+    /// Drop sessions with no turn running that were last used longer than
+    /// the idle timeout ago
+    pub fn close_idle(&self) {
+        let idle_timeout = self.idle_timeout;
+        self.sessions.lock().unwrap().retain(|id, session| {
+            let keep = session.running > 0 || session.last_used.elapsed() < idle_timeout;
+            if !keep {
+                tracing::info!("closing idle session {}", id);
+            }
+            keep
+        });
+    }
+}
+
+/// Run turns in the session until it is closed
+fn serve_session(mut session: RlmSession, turns: mpsc::Receiver<Turn>) {
+    for turn in turns {
+        let result = turn.rlm.completion_in_session(&mut session, turn.prompt);
+        let _ = turn.reply.send(result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rlm_core::{Backend, MockBackend, ReplMode, RlmConfig};
+
+    fn mock_rlm(responses: &[&str]) -> Rlm {
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(MockBackend::new(responses.to_vec())))
+            .with_repl_mode(ReplMode::Worker);
+        Rlm::new(config).unwrap()
+    }
+
+    #[test]
+    fn test_session_turns_share_repl() {
+        let sessions = Sessions::new(Duration::from_secs(60), 4);
+        let id = sessions.create(mock_rlm(&[])).unwrap();
+        assert!(sessions.contains(&id));
+
+        let first = mock_rlm(&["```repl\ntotal = 40\n```", "FINAL(ok)"]);
+        let result = sessions.complete(&id, first, "keep 40".into()).unwrap();
+        assert_eq!(result.response, "ok");
+        let second = mock_rlm(&["```repl\nllm_output(str(total + 2))\n```"]);
+        let result = sessions.complete(&id, second, "add 2".into()).unwrap();
+        assert_eq!(result.response, "42");
+
+        assert!(sessions.close(&id));
+        assert!(!sessions.close(&id));
+        assert!(sessions
+            .complete(&id, mock_rlm(&[]), "again".into())
+            .is_err());
+    }
+
+    #[test]
+    fn test_idle_sessions_closed() {
+        let sessions = Sessions::new(Duration::ZERO, 4);
+        let first = sessions.create(mock_rlm(&[])).unwrap();
+        let second = sessions.create(mock_rlm(&[])).unwrap();
+        assert!(!sessions.contains(&first));
+        assert!(sessions.contains(&second));
+        sessions.close_idle();
+        assert!(!sessions.contains(&second));
+
+        // Not while a turn runs, however long it takes
+        let third = sessions.create(mock_rlm(&[])).unwrap();
+        let slow = mock_rlm(&["```repl\nimport time\ntime.sleep(0.5)\nllm_output('done')\n```"]);
+        std::thread::scope(|scope| {
+            let turn = scope.spawn(|| sessions.complete(&third, slow, "wait".into()));
+            std::thread::sleep(Duration::from_millis(200));
+            sessions.close_idle();
+            assert!(sessions.contains(&third));
+            assert_eq!(turn.join().unwrap().unwrap().response, "done");
+        });
+        sessions.close_idle();
+        assert!(!sessions.contains(&third));
+    }
+
+    #[test]
+    fn test_max_sessions() {
+        let sessions = Sessions::new(Duration::from_secs(60), 1);
+        let first = sessions.create(mock_rlm(&[])).unwrap();
+        assert!(matches!(
+            sessions.create(mock_rlm(&[])),
+            Err(RlmError::BudgetExceeded(_))
+        ));
+        sessions.close(&first);
+        sessions.create(mock_rlm(&[])).unwrap();
+    }
+}
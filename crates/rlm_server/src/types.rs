@@ -1,15 +1,96 @@
 //! OpenAI-compatible request/response types for the RLM server
 
-use serde::{Deserialize, Serialize};
+use rlm_core::{
+    AnswerFormat, CacheStats, CompletionStatus, OutputTruncation, RlmCompletion, RlmConfig,
+    RlmIteration, Usage,
+};
+use serde::{Deserialize, Deserializer, Serialize};
 
 /// A chat message in OpenAI format
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatMessage {
     pub role: String,
+    /// Null in assistant messages that only call tools
+    #[serde(default, deserialize_with = "null_as_empty")]
     pub content: String,
+    /// Tool calls of an assistant message
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCallMessage>,
+    /// The call a `tool` message answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+impl ChatMessage {
+    pub fn new(role: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            role: role.into(),
+            content: content.into(),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        }
+    }
+}
+
+fn null_as_empty<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(Option::<String>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// A function call made by the model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCallMessage {
+    pub id: String,
+    #[serde(rename = "type", default = "function_type")]
+    pub kind: String,
+    pub function: FunctionCall,
+}
+
+fn function_type() -> String {
+    "function".to_string()
+}
+
+/// Name and JSON-encoded arguments of a function call
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    pub arguments: String,
+}
+
+/// A tool offered in the request
+#[derive(Debug, Clone, Deserialize)]
+pub struct ToolSpec {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: FunctionSpec,
+}
+
+/// A function the client runs when the model calls it
+#[derive(Debug, Clone, Deserialize)]
+pub struct FunctionSpec {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// JSON schema of the arguments (None = no arguments)
+    #[serde(default)]
+    pub parameters: Option<serde_json::Value>,
+}
+
+/// `tool_choice` as sent: `none`, `auto`, `required`, or a named function
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ToolChoice {
+    Mode(String),
+    Function { function: ToolChoiceFunction },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ToolChoiceFunction {
+    pub name: String,
 }
 
 /// Request body for chat completions
+///
+/// OpenAI fields not listed here, such as `user`, are accepted and ignored.
 #[derive(Debug, Clone, Deserialize)]
 pub struct ChatCompletionRequest {
     /// The model to use (ignored - RLM uses its configured backend)
@@ -30,6 +111,445 @@ pub struct ChatCompletionRequest {
     /// Whether to stream the response
     #[serde(default)]
     pub stream: Option<bool>,
+
+    /// Nucleus sampling mass (0-1)
+    #[serde(default)]
+    pub top_p: Option<f32>,
+
+    /// Cut the answer before the first of these (at most 4)
+    #[serde(default)]
+    pub stop: Option<StopSequences>,
+
+    /// Penalty for tokens already present (-2 to 2)
+    #[serde(default)]
+    pub presence_penalty: Option<f32>,
+
+    /// Penalty by how often tokens already occurred (-2 to 2)
+    #[serde(default)]
+    pub frequency_penalty: Option<f32>,
+
+    /// Seed for best-effort deterministic sampling
+    #[serde(default)]
+    pub seed: Option<i64>,
+
+    /// Number of choices; RLM produces one answer per run, so only 1
+    #[serde(default)]
+    pub n: Option<u32>,
+
+    /// `text`, `json_object`, or `json_schema` with a schema the answer
+    /// is validated against
+    #[serde(default)]
+    pub response_format: Option<ResponseFormat>,
+
+    /// Functions the model may call; the client runs them and sends the
+    /// results back as `tool` messages
+    #[serde(default)]
+    pub tools: Vec<ToolSpec>,
+
+    /// Whether and which tool the model has to call
+    #[serde(default)]
+    pub tool_choice: Option<ToolChoice>,
+
+    /// RLM-specific options (vendor extension)
+    #[serde(default)]
+    pub rlm: Option<RlmOptions>,
+
+    /// Continue a session started with `POST /v1/sessions`; `messages`
+    /// then only holds what is new (vendor extension)
+    #[serde(default)]
+    pub session_id: Option<String>,
+
+    /// Files uploaded with `POST /v1/files` whose text goes into the
+    /// context ahead of the messages (vendor extension)
+    #[serde(default)]
+    pub file_ids: Vec<String>,
+}
+
+/// The `rlm` request extension, also settable with `X-RLM-*` headers
+/// (`X-RLM-Max-Iterations: 5`); unset fields keep the server's defaults
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RlmOptions {
+    /// Attach the run's iteration trace to the response
+    pub include_trace: Option<bool>,
+    pub max_iterations: Option<u32>,
+    pub max_exec_retries: Option<u32>,
+    /// Model for `llm_query` sub-calls
+    pub sub_model: Option<String>,
+    pub max_sub_calls: Option<u32>,
+    pub max_sub_calls_per_iteration: Option<u32>,
+    /// Characters of REPL output kept in the history
+    pub max_output_chars: Option<usize>,
+    pub output_truncation: Option<OutputTruncation>,
+    /// Token budget of the model's context window
+    pub context_window: Option<usize>,
+    /// Answer with what was found when the run stops early
+    pub partial_results: Option<bool>,
+}
+
+impl RlmOptions {
+    /// These options with unset fields taken from `fallback`
+    pub fn or(self, fallback: RlmOptions) -> Self {
+        Self {
+            include_trace: self.include_trace.or(fallback.include_trace),
+            max_iterations: self.max_iterations.or(fallback.max_iterations),
+            max_exec_retries: self.max_exec_retries.or(fallback.max_exec_retries),
+            sub_model: self.sub_model.or(fallback.sub_model),
+            max_sub_calls: self.max_sub_calls.or(fallback.max_sub_calls),
+            max_sub_calls_per_iteration: self
+                .max_sub_calls_per_iteration
+                .or(fallback.max_sub_calls_per_iteration),
+            max_output_chars: self.max_output_chars.or(fallback.max_output_chars),
+            output_truncation: self.output_truncation.or(fallback.output_truncation),
+            context_window: self.context_window.or(fallback.context_window),
+            partial_results: self.partial_results.or(fallback.partial_results),
+        }
+    }
+
+    /// The options on top of the server's config
+    pub fn apply(&self, mut config: RlmConfig) -> RlmConfig {
+        if let Some(n) = self.max_iterations {
+            config = config.with_max_iterations(n);
+        }
+        if let Some(n) = self.max_exec_retries {
+            config = config.with_max_exec_retries(n);
+        }
+        if let Some(ref model) = self.sub_model {
+            config = config.with_sub_model(model);
+        }
+        if self.max_sub_calls.is_some() || self.max_sub_calls_per_iteration.is_some() {
+            let per_iteration = self
+                .max_sub_calls_per_iteration
+                .or(config.max_sub_calls_per_iteration);
+            let total = self.max_sub_calls.or(config.max_sub_calls);
+            config = config.with_max_sub_calls(per_iteration, total);
+        }
+        if let Some(n) = self.max_output_chars {
+            config = config.with_max_output_chars(n);
+        }
+        if let Some(mode) = self.output_truncation {
+            config = config.with_output_truncation(mode);
+        }
+        if let Some(tokens) = self.context_window {
+            config = config.with_context_window(tokens);
+        }
+        if let Some(v) = self.partial_results {
+            config = config.with_partial_results(v);
+        }
+        config
+    }
+
+    /// Reject values above the server's limits
+    pub fn check_limits(&self, limits: &RlmLimits) -> Result<(), (&'static str, String)> {
+        let over = |param, name, limit: usize, value: Option<usize>| match value {
+            Some(value) if value > limit => Err((
+                param,
+                format!("{} must be at most {} on this server", name, limit),
+            )),
+            _ => Ok(()),
+        };
+        over(
+            "rlm.max_iterations",
+            "max_iterations",
+            limits.max_iterations as usize,
+            self.max_iterations.map(|n| n as usize),
+        )?;
+        over(
+            "rlm.max_sub_calls",
+            "max_sub_calls",
+            limits.max_sub_calls as usize,
+            self.max_sub_calls.map(|n| n as usize),
+        )?;
+        over(
+            "rlm.max_sub_calls_per_iteration",
+            "max_sub_calls_per_iteration",
+            limits.max_sub_calls as usize,
+            self.max_sub_calls_per_iteration.map(|n| n as usize),
+        )?;
+        over(
+            "rlm.context_window",
+            "context_window",
+            limits.context_window,
+            self.context_window,
+        )
+    }
+
+    fn validate(&self) -> Result<(), (&'static str, String)> {
+        if self.max_iterations == Some(0) {
+            return Err((
+                "rlm.max_iterations",
+                "max_iterations must be at least 1".to_string(),
+            ));
+        }
+        if self.sub_model.as_deref().is_some_and(str::is_empty) {
+            return Err(("rlm.sub_model", "sub_model must not be empty".to_string()));
+        }
+        if self.max_output_chars == Some(0) {
+            return Err((
+                "rlm.max_output_chars",
+                "max_output_chars must be at least 1".to_string(),
+            ));
+        }
+        if self.context_window == Some(0) {
+            return Err((
+                "rlm.context_window",
+                "context_window must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Upper bounds on the `rlm` options a request may set, so one request
+/// can't run for hours or fan out thousands of sub-calls
+#[derive(Debug, Clone, Copy)]
+pub struct RlmLimits {
+    pub max_iterations: u32,
+    /// Also bounds `max_sub_calls_per_iteration`
+    pub max_sub_calls: u32,
+    pub context_window: usize,
+}
+
+impl Default for RlmLimits {
+    fn default() -> Self {
+        Self {
+            max_iterations: 100,
+            max_sub_calls: 1000,
+            context_window: 2_000_000,
+        }
+    }
+}
+
+/// `response_format` as sent
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResponseFormat {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub json_schema: Option<JsonSchemaFormat>,
+}
+
+/// The `json_schema` member of a `json_schema` response format
+///
+/// `name` and `strict` are accepted and ignored: answers are always
+/// validated against the schema.
+#[derive(Debug, Clone, Deserialize)]
+pub struct JsonSchemaFormat {
+    #[serde(default)]
+    pub schema: Option<serde_json::Value>,
+}
+
+/// `stop` as sent: a single sequence or a list
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum StopSequences {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl ChatCompletionRequest {
+    /// Stop sequences, empty if none were sent
+    pub fn stop_sequences(&self) -> Vec<String> {
+        match self.stop {
+            Some(StopSequences::One(ref stop)) => vec![stop.clone()],
+            Some(StopSequences::Many(ref stops)) => stops.clone(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Whether the response should carry the run's trace
+    pub fn include_trace(&self) -> bool {
+        self.rlm
+            .as_ref()
+            .and_then(|rlm| rlm.include_trace)
+            .unwrap_or(false)
+    }
+
+    /// Whether the request goes to the tool-calling agent: it offers tools
+    /// and doesn't turn them off with `tool_choice: "none"`
+    pub fn uses_tools(&self) -> bool {
+        !self.tools.is_empty()
+            && !matches!(self.tool_choice, Some(ToolChoice::Mode(ref mode)) if mode == "none")
+    }
+
+    /// Answer format requested by `response_format`, text if none
+    pub fn answer_format(&self) -> Result<AnswerFormat, (&'static str, String)> {
+        let Some(ref format) = self.response_format else {
+            return Ok(AnswerFormat::Text);
+        };
+        let answer_format = match format.kind.as_str() {
+            "text" => AnswerFormat::Text,
+            "json_object" => AnswerFormat::Json,
+            "json_schema" => match format.json_schema.as_ref().and_then(|s| s.schema.clone()) {
+                Some(schema) => AnswerFormat::JsonSchema(schema),
+                None => {
+                    return Err((
+                        "response_format",
+                        "json_schema requires json_schema.schema".to_string(),
+                    ))
+                }
+            },
+            other => {
+                return Err((
+                    "response_format",
+                    format!(
+                        "unsupported response_format type '{}' (expected text, json_object, or json_schema)",
+                        other
+                    ),
+                ))
+            }
+        };
+        answer_format
+            .check_schema()
+            .map_err(|e| ("response_format", e.to_string()))?;
+        Ok(answer_format)
+    }
+
+    /// Check parameters the server can't honor as given; the error names
+    /// the parameter and why
+    pub fn validate(&self) -> Result<(), (&'static str, String)> {
+        if let Some(n) = self.n.filter(|n| *n != 1) {
+            return Err((
+                "n",
+                format!("n = {} is not supported: RLM returns a single choice", n),
+            ));
+        }
+        if let Some(top_p) = self.top_p.filter(|p| !(0.0..=1.0).contains(p)) {
+            return Err((
+                "top_p",
+                format!("top_p must be between 0 and 1, got {}", top_p),
+            ));
+        }
+        let penalties = [
+            ("presence_penalty", self.presence_penalty),
+            ("frequency_penalty", self.frequency_penalty),
+        ];
+        for (param, value) in penalties {
+            if let Some(value) = value.filter(|v| !(-2.0..=2.0).contains(v)) {
+                return Err((
+                    param,
+                    format!("{} must be between -2 and 2, got {}", param, value),
+                ));
+            }
+        }
+        let stop = self.stop_sequences();
+        if stop.len() > 4 {
+            return Err(("stop", "stop takes at most 4 sequences".to_string()));
+        }
+        if stop.iter().any(String::is_empty) {
+            return Err(("stop", "stop sequences must not be empty".to_string()));
+        }
+        if let Some(ref rlm) = self.rlm {
+            rlm.validate()?;
+        }
+        if self.session_id.is_some() && self.uses_tools() {
+            return Err((
+                "session_id",
+                "sessions can't be combined with tools".to_string(),
+            ));
+        }
+        if self.uses_tools() {
+            self.validate_agent_params()?;
+        }
+        // Cutting a JSON answer at a stop sequence would break the JSON
+        if !matches!(self.answer_format()?, AnswerFormat::Text) && !stop.is_empty() {
+            return Err((
+                "stop",
+                "stop can't be combined with a JSON response_format".to_string(),
+            ));
+        }
+        self.validate_tools()
+    }
+
+    /// Reject what the tool-calling agent can't honor: of the sampling and
+    /// `rlm` options it takes `temperature` and `max_iterations` only
+    fn validate_agent_params(&self) -> Result<(), (&'static str, String)> {
+        let rlm = self.rlm.clone().unwrap_or_default();
+        let set = [
+            ("max_tokens", self.max_tokens.is_some()),
+            ("top_p", self.top_p.is_some()),
+            ("presence_penalty", self.presence_penalty.is_some()),
+            ("frequency_penalty", self.frequency_penalty.is_some()),
+            ("seed", self.seed.is_some()),
+            (
+                "response_format",
+                !matches!(self.answer_format()?, AnswerFormat::Text),
+            ),
+            ("rlm.include_trace", self.include_trace()),
+            ("rlm.max_exec_retries", rlm.max_exec_retries.is_some()),
+            ("rlm.sub_model", rlm.sub_model.is_some()),
+            ("rlm.max_sub_calls", rlm.max_sub_calls.is_some()),
+            (
+                "rlm.max_sub_calls_per_iteration",
+                rlm.max_sub_calls_per_iteration.is_some(),
+            ),
+            ("rlm.max_output_chars", rlm.max_output_chars.is_some()),
+            ("rlm.output_truncation", rlm.output_truncation.is_some()),
+            ("rlm.context_window", rlm.context_window.is_some()),
+            ("rlm.partial_results", rlm.partial_results.is_some()),
+        ];
+        match set.into_iter().find(|(_, set)| *set) {
+            Some((param, _)) => Err((param, format!("{} can't be combined with tools", param))),
+            None => Ok(()),
+        }
+    }
+
+    fn validate_tools(&self) -> Result<(), (&'static str, String)> {
+        for tool in &self.tools {
+            if tool.kind != "function" {
+                return Err((
+                    "tools",
+                    format!("unsupported tool type '{}' (expected function)", tool.kind),
+                ));
+            }
+            if tool.function.name.is_empty() {
+                return Err(("tools", "tool functions need a name".to_string()));
+            }
+        }
+        let offered = |name: &str| self.tools.iter().any(|t| t.function.name == name);
+        match self.tool_choice {
+            Some(ToolChoice::Mode(ref mode))
+                if !["none", "auto", "required"].contains(&mode.as_str()) =>
+            {
+                return Err((
+                    "tool_choice",
+                    format!(
+                        "unsupported tool_choice '{}' (expected none, auto, required, or a function)",
+                        mode
+                    ),
+                ));
+            }
+            Some(ToolChoice::Function { ref function }) if !offered(&function.name) => {
+                return Err((
+                    "tool_choice",
+                    format!(
+                        "tool_choice names '{}', which is not in tools",
+                        function.name
+                    ),
+                ));
+            }
+            _ => {}
+        }
+        // Tool results have to answer a call made earlier
+        let mut calls = std::collections::HashSet::new();
+        for message in &self.messages {
+            calls.extend(message.tool_calls.iter().map(|call| call.id.as_str()));
+            if message.role != "tool" {
+                continue;
+            }
+            match message.tool_call_id {
+                Some(ref id) if calls.contains(id.as_str()) => {}
+                _ => {
+                    return Err((
+                        "messages",
+                        "tool messages need the tool_call_id of an earlier assistant tool call"
+                            .to_string(),
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 /// A choice in the completion response
@@ -57,6 +577,39 @@ pub struct ChatCompletionResponse {
     pub model: String,
     pub choices: Vec<ChatCompletionChoice>,
     pub usage: CompletionUsage,
+    /// The run's trace, when the request asked for it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rlm: Option<RlmTrace>,
+}
+
+/// The run behind a response, sent as the `rlm` vendor extension for
+/// requests with `"rlm": {"include_trace": true}`
+#[derive(Debug, Clone, Serialize)]
+pub struct RlmTrace {
+    /// Each iteration: the model's response, its code blocks with their
+    /// REPL output, and the usage of its sub-calls
+    pub iterations: Vec<RlmIteration>,
+    pub status: CompletionStatus,
+    pub cache_stats: CacheStats,
+    /// Usage of all sub-calls, included in the response's `usage`
+    pub sub_call_usage: Usage,
+    pub execution_ms: u64,
+}
+
+impl RlmTrace {
+    pub fn new(completion: &RlmCompletion) -> Self {
+        let mut sub_call_usage = Usage::default();
+        for iteration in &completion.iterations {
+            sub_call_usage.add(&iteration.sub_call_usage);
+        }
+        Self {
+            iterations: completion.iterations.clone(),
+            status: completion.status,
+            cache_stats: completion.cache_stats,
+            sub_call_usage,
+            execution_ms: completion.execution_time.as_millis() as u64,
+        }
+    }
 }
 
 /// A delta message for streaming responses
@@ -66,6 +619,16 @@ pub struct ChatMessageDelta {
     pub role: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCallDelta>>,
+}
+
+/// A tool call in a streaming chunk, sent whole
+#[derive(Debug, Clone, Serialize)]
+pub struct ToolCallDelta {
+    pub index: u32,
+    #[serde(flatten)]
+    pub call: ToolCallMessage,
 }
 
 /// A choice in a streaming chunk
@@ -85,16 +648,35 @@ pub struct ChatCompletionChunk {
     pub created: u64,
     pub model: String,
     pub choices: Vec<ChatCompletionChunkChoice>,
+    /// The run's trace, on the last chunk when the request asked for it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rlm: Option<RlmTrace>,
+    /// What the run is doing, on chunks with an empty delta sent while it
+    /// goes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rlm_progress: Option<RlmProgress>,
+}
+
+/// Progress of a streamed run, sent as the `rlm_progress` vendor extension
+#[derive(Debug, Clone, Serialize)]
+pub struct RlmProgress {
+    /// `event` for engine events, `output` for a line model code printed
+    pub kind: String,
+    pub text: String,
+}
+
+impl RlmProgress {
+    pub fn new(kind: &str, text: impl Into<String>) -> Self {
+        Self {
+            kind: kind.to_string(),
+            text: text.into(),
+        }
+    }
 }
 
 impl ChatCompletionResponse {
     /// Create a new completion response
-    pub fn new(
-        id: String,
-        model: String,
-        content: String,
-        usage: CompletionUsage,
-    ) -> Self {
+    pub fn new(id: String, model: String, content: String, usage: CompletionUsage) -> Self {
         Self {
             id,
             object: "chat.completion".to_string(),
@@ -105,15 +687,33 @@ impl ChatCompletionResponse {
             model,
             choices: vec![ChatCompletionChoice {
                 index: 0,
-                message: ChatMessage {
-                    role: "assistant".to_string(),
-                    content,
-                },
+                message: ChatMessage::new("assistant", content),
                 finish_reason: "stop".to_string(),
             }],
             usage,
+            rlm: None,
         }
     }
+
+    /// Attach the run's trace
+    pub fn with_trace(mut self, trace: Option<RlmTrace>) -> Self {
+        self.rlm = trace;
+        self
+    }
+
+    /// A response calling tools, with what the model said along with them
+    pub fn with_tool_calls(
+        id: String,
+        model: String,
+        content: String,
+        tool_calls: Vec<ToolCallMessage>,
+        usage: CompletionUsage,
+    ) -> Self {
+        let mut response = Self::new(id, model, content, usage);
+        response.choices[0].message.tool_calls = tool_calls;
+        response.choices[0].finish_reason = "tool_calls".to_string();
+        response
+    }
 }
 
 impl ChatCompletionChunk {
@@ -132,9 +732,12 @@ impl ChatCompletionChunk {
                 delta: ChatMessageDelta {
                     role: Some("assistant".to_string()),
                     content: None,
+                    tool_calls: None,
                 },
                 finish_reason: None,
             }],
+            rlm: None,
+            rlm_progress: None,
         }
     }
 
@@ -153,14 +756,36 @@ impl ChatCompletionChunk {
                 delta: ChatMessageDelta {
                     role: None,
                     content: Some(content),
+                    tool_calls: None,
                 },
                 finish_reason: None,
             }],
+            rlm: None,
+            rlm_progress: None,
         }
     }
 
+    /// Create a chunk carrying the response's tool calls
+    pub fn with_tool_calls(id: String, model: String, calls: Vec<ToolCallMessage>) -> Self {
+        let mut chunk = Self::finished(id, model, "tool_calls");
+        let calls = (0..)
+            .zip(calls)
+            .map(|(index, call)| ToolCallDelta { index, call });
+        chunk.choices[0].delta.tool_calls = Some(calls.collect());
+        chunk.choices[0].finish_reason = None;
+        chunk
+    }
+
+    /// Create a chunk with an empty delta carrying run progress
+    pub fn with_progress(id: String, model: String, progress: RlmProgress) -> Self {
+        let mut chunk = Self::finished(id, model, "");
+        chunk.choices[0].finish_reason = None;
+        chunk.rlm_progress = Some(progress);
+        chunk
+    }
+
     /// Create a final chunk with finish_reason
-    pub fn finished(id: String, model: String) -> Self {
+    pub fn finished(id: String, model: String, reason: &str) -> Self {
         Self {
             id,
             object: "chat.completion.chunk".to_string(),
@@ -174,9 +799,61 @@ impl ChatCompletionChunk {
                 delta: ChatMessageDelta {
                     role: None,
                     content: None,
+                    tool_calls: None,
                 },
-                finish_reason: Some("stop".to_string()),
+                finish_reason: Some(reason.to_string()),
             }],
+            rlm: None,
+            rlm_progress: None,
+        }
+    }
+}
+
+/// A session as returned by `POST /v1/sessions`
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionObject {
+    pub id: String,
+    pub object: String,
+    pub created: u64,
+}
+
+impl SessionObject {
+    pub fn new(id: String) -> Self {
+        Self {
+            id,
+            object: "session".to_string(),
+            created: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        }
+    }
+}
+
+/// An uploaded file in the OpenAI shape
+#[derive(Debug, Clone, Serialize)]
+pub struct FileObject {
+    pub id: String,
+    pub object: String,
+    /// Size of the upload, before text extraction
+    pub bytes: usize,
+    pub created_at: u64,
+    pub filename: String,
+    pub purpose: String,
+}
+
+impl FileObject {
+    pub fn new(id: String, filename: String, purpose: String, bytes: usize) -> Self {
+        Self {
+            id,
+            object: "file".to_string(),
+            bytes,
+            created_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            filename,
+            purpose,
         }
     }
 }
@@ -0,0 +1,271 @@
+//! Text of documents used as context
+//!
+//! Most long documents aren't plain text. [`read_document`] detects PDF,
+//! DOCX, and HTML files and extracts their text, with markers where pages
+//! and sections start so the model can cite and chunk by them:
+//!
+//! - PDF: a `--- Page N ---` line before each page (feature `pdf`)
+//! - DOCX: headings as markdown `#` lines, table rows as `a | b`, and a
+//!   `--- Page break ---` line for explicit breaks (feature `docx`)
+//! - HTML: the main content as markdown, headings included
+//!
+//! Everything else is read as UTF-8 text.
+
+use std::path::Path;
+
+use crate::error::{Result, RlmError};
+use crate::html;
+
+/// Format of a document, as far as extraction is concerned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocumentKind {
+    Text,
+    Html,
+    Pdf,
+    Docx,
+}
+
+impl DocumentKind {
+    /// Kind of the document at `path` with contents `bytes`
+    ///
+    /// Leading bytes decide where they can; the extension is the fallback.
+    pub fn detect(path: &Path, bytes: &[u8]) -> Self {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_ascii_lowercase)
+            .unwrap_or_default();
+        if bytes.starts_with(b"%PDF-") {
+            return DocumentKind::Pdf;
+        }
+        if bytes.starts_with(b"PK\x03\x04") && (extension == "docx" || contains(bytes, b"word/")) {
+            return DocumentKind::Docx;
+        }
+        if matches!(extension.as_str(), "html" | "htm" | "xhtml") || looks_like_html(bytes) {
+            return DocumentKind::Html;
+        }
+        DocumentKind::Text
+    }
+}
+
+/// Text of the document at `path`, extracted according to its kind
+pub fn read_document(path: impl AsRef<Path>) -> Result<String> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path).map_err(|e| RlmError::Document(e.to_string()))?;
+    extract_text(&bytes, DocumentKind::detect(path, &bytes))
+}
+
+/// Text of a document of kind `kind`
+pub fn extract_text(bytes: &[u8], kind: DocumentKind) -> Result<String> {
+    match kind {
+        DocumentKind::Text => Ok(String::from_utf8_lossy(bytes).into_owned()),
+        DocumentKind::Html => {
+            let page = html::to_markdown(&String::from_utf8_lossy(bytes));
+            Ok(match page.title {
+                Some(title) => format!("Title: {}\n\n{}", title, page.markdown),
+                None => page.markdown,
+            })
+        }
+        DocumentKind::Pdf => pdf_text(bytes),
+        DocumentKind::Docx => docx_text(bytes),
+    }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+fn looks_like_html(bytes: &[u8]) -> bool {
+    let head = String::from_utf8_lossy(&bytes[..bytes.len().min(512)]).to_ascii_lowercase();
+    let head = head.trim_start_matches('\u{feff}').trim_start();
+    head.starts_with("<!doctype html") || head.starts_with("<html")
+}
+
+#[cfg(feature = "pdf")]
+fn pdf_text(bytes: &[u8]) -> Result<String> {
+    let pages = pdf_extract::extract_text_from_mem_by_pages(bytes)
+        .map_err(|e| RlmError::Document(format!("PDF: {}", e)))?;
+    Ok(pages
+        .iter()
+        .enumerate()
+        .map(|(i, page)| format!("--- Page {} ---\n{}\n", i + 1, page.trim()))
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+#[cfg(not(feature = "pdf"))]
+fn pdf_text(_bytes: &[u8]) -> Result<String> {
+    Err(RlmError::Document(
+        "PDF support is not built in (enable the `pdf` feature)".to_string(),
+    ))
+}
+
+#[cfg(feature = "docx")]
+fn docx_text(bytes: &[u8]) -> Result<String> {
+    use std::io::Read;
+
+    let invalid = |e: &dyn std::fmt::Display| RlmError::Document(format!("DOCX: {}", e));
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes)).map_err(|e| invalid(&e))?;
+    let mut xml = String::new();
+    archive
+        .by_name("word/document.xml")
+        .map_err(|e| invalid(&e))?
+        .read_to_string(&mut xml)
+        .map_err(|e| invalid(&e))?;
+    docx_body_text(&xml).map_err(|e| invalid(&e))
+}
+
+#[cfg(not(feature = "docx"))]
+fn docx_text(_bytes: &[u8]) -> Result<String> {
+    Err(RlmError::Document(
+        "DOCX support is not built in (enable the `docx` feature)".to_string(),
+    ))
+}
+
+/// Text of `word/document.xml`, one line per paragraph or table row
+#[cfg(feature = "docx")]
+fn docx_body_text(xml: &str) -> std::result::Result<String, quick_xml::Error> {
+    use quick_xml::events::{BytesStart, Event};
+    use quick_xml::Reader;
+
+    fn attribute(element: &BytesStart, name: &[u8]) -> Option<String> {
+        element
+            .attributes()
+            .flatten()
+            .find(|attr| attr.key.local_name().as_ref() == name)
+            .and_then(|attr| attr.unescape_value().ok())
+            .map(|value| value.into_owned())
+    }
+
+    let mut reader = Reader::from_str(xml);
+    let mut out = String::new();
+    let mut paragraph = String::new();
+    // Markdown prefix from the paragraph style or numbering
+    let mut prefix = String::new();
+    let mut in_text = false;
+    // Cells of the table row being read
+    let mut row: Option<Vec<String>> = None;
+    loop {
+        match reader.read_event()? {
+            Event::Start(element) => match element.local_name().as_ref() {
+                b"t" => in_text = true,
+                b"numPr" if prefix.is_empty() => prefix = "- ".to_string(),
+                b"tr" => row = Some(Vec::new()),
+                b"tc" => row.get_or_insert_with(Vec::new).push(String::new()),
+                _ => {}
+            },
+            Event::Empty(element) => match element.local_name().as_ref() {
+                b"pStyle" => {
+                    let style = attribute(&element, b"val").unwrap_or_default();
+                    let level = match style.strip_prefix("Heading") {
+                        Some(level) => level.parse::<usize>().ok(),
+                        None => (style == "Title").then_some(1),
+                    };
+                    if let Some(level) = level {
+                        prefix = format!("{} ", "#".repeat(level.clamp(1, 6)));
+                    }
+                }
+                b"tab" => paragraph.push('\t'),
+                b"br" if attribute(&element, b"type").as_deref() == Some("page") => {
+                    out.push_str("--- Page break ---\n");
+                }
+                b"br" | b"cr" => paragraph.push('\n'),
+                _ => {}
+            },
+            Event::Text(text) if in_text => paragraph.push_str(&text.unescape()?),
+            Event::End(element) => match element.local_name().as_ref() {
+                b"t" => in_text = false,
+                b"p" => {
+                    let text = std::mem::take(&mut paragraph);
+                    let text = text.trim();
+                    match row.as_mut().and_then(|cells| cells.last_mut()) {
+                        Some(cell) if !text.is_empty() => {
+                            if !cell.is_empty() {
+                                cell.push(' ');
+                            }
+                            cell.push_str(text);
+                        }
+                        Some(_) => {}
+                        None if text.is_empty() => {}
+                        None => {
+                            out.push_str(&prefix);
+                            out.push_str(text);
+                            out.push('\n');
+                        }
+                    }
+                    prefix.clear();
+                }
+                b"tr" => {
+                    if let Some(cells) = row.take() {
+                        out.push_str(&cells.join(" | "));
+                        out.push('\n');
+                    }
+                }
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_and_extract() {
+        let path = Path::new("report.bin");
+        assert_eq!(
+            DocumentKind::detect(path, b"%PDF-1.7\n..."),
+            DocumentKind::Pdf
+        );
+        assert_eq!(
+            DocumentKind::detect(Path::new("report.docx"), b"PK\x03\x04..."),
+            DocumentKind::Docx
+        );
+        assert_eq!(
+            DocumentKind::detect(path, b"PK\x03\x04...word/document.xml"),
+            DocumentKind::Docx
+        );
+        assert_eq!(
+            DocumentKind::detect(path, b"\xef\xbb\xbf  <!DOCTYPE html><html>"),
+            DocumentKind::Html
+        );
+        assert_eq!(
+            DocumentKind::detect(Path::new("page.HTM"), b"hi"),
+            DocumentKind::Html
+        );
+        assert_eq!(
+            DocumentKind::detect(path, b"plain words"),
+            DocumentKind::Text
+        );
+
+        let html = b"<html><head><title>Report</title></head>\
+            <body><h1>Results</h1><p>All good.</p></body></html>";
+        let text = extract_text(html, DocumentKind::Html).unwrap();
+        assert!(text.starts_with("Title: Report\n\n"));
+        assert!(text.contains("# Results"));
+        assert!(text.contains("All good."));
+    }
+
+    #[cfg(feature = "docx")]
+    #[test]
+    fn test_docx_body_text() {
+        let xml = r#"<w:document xmlns:w="w"><w:body>
+            <w:p><w:pPr><w:pStyle w:val="Heading2"/></w:pPr><w:r><w:t>Scope</w:t></w:r></w:p>
+            <w:p><w:r><w:t xml:space="preserve">Fish &amp; </w:t></w:r><w:r><w:t>chips</w:t></w:r></w:p>
+            <w:p><w:pPr><w:numPr><w:ilvl w:val="0"/></w:numPr></w:pPr><w:r><w:t>item</w:t></w:r></w:p>
+            <w:p><w:r><w:br w:type="page"/></w:r></w:p>
+            <w:tbl><w:tr><w:tc><w:p><w:r><w:t>a</w:t></w:r></w:p></w:tc>
+            <w:tc><w:p><w:r><w:t>b</w:t></w:r></w:p></w:tc></w:tr></w:tbl>
+        </w:body></w:document>"#;
+        assert_eq!(
+            docx_body_text(xml).unwrap(),
+            "## Scope\nFish & chips\n- item\n--- Page break ---\na | b\n"
+        );
+    }
+}
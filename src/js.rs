@@ -0,0 +1,266 @@
+//! JavaScript REPL (`javascript` feature)
+//!
+//! [`JsRepl`] runs model code in an embedded QuickJS engine, for models
+//! tuned on JavaScript and hosts without a Python runtime. It provides the
+//! same bindings as the Python REPL: `context`, `print` (and `console.log`),
+//! `llm_query` (with an optional `{model, temperature, max_tokens}` object),
+//! and `llm_output`.
+
+use rquickjs::context::EvalOptions;
+use rquickjs::{Coerced, Context, Ctx, Exception, Function, Runtime, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::env::{LlmQueryFn, ReplEnvironment};
+use crate::error::{Result, RlmError};
+use crate::types::{QueryOverrides, ReplResult};
+
+/// Defines `print`, `console`, and the locals snapshot on top of the native
+/// `__rlm_write`
+const PRELUDE: &str = r#"
+(() => {
+  const show = (v) => {
+    if (typeof v === "string") return v;
+    if (v !== null && typeof v === "object") {
+      try { return JSON.stringify(v); } catch (e) { return String(v); }
+    }
+    return String(v);
+  };
+  globalThis.print = (...args) => __rlm_write(args.map(show).join(" ") + "\n");
+  globalThis.console = { log: print, info: print, warn: print, error: print, debug: print };
+  const query = llm_query;
+  globalThis.llm_query = (prompt, options) => query(prompt, JSON.stringify(options ?? {}));
+  const builtins = new Set(Object.getOwnPropertyNames(globalThis));
+  globalThis.__rlm_locals = () => {
+    const out = {};
+    for (const name of Object.getOwnPropertyNames(globalThis)) {
+      const value = globalThis[name];
+      if (builtins.has(name) || name.startsWith("_") || typeof value === "function") continue;
+      out[name] = show(value);
+    }
+    return out;
+  };
+})();
+"#;
+
+#[derive(Default)]
+struct Captured {
+    stdout: String,
+    llm_output: Option<String>,
+}
+
+/// JavaScript REPL backed by QuickJS
+pub struct JsRepl {
+    context: Context,
+    // Kept alive for the context; holds the interrupt handler
+    _runtime: Runtime,
+    captured: Rc<RefCell<Captured>>,
+    /// Interrupt running code once this passes
+    deadline: Arc<Mutex<Option<Instant>>>,
+    exec_timeout: Option<Duration>,
+}
+
+fn js_error(e: rquickjs::Error) -> RlmError {
+    RlmError::Repl(format!("JavaScript engine error: {}", e))
+}
+
+/// Describe the pending exception after a failed eval
+fn exception_message(ctx: &Ctx<'_>, error: rquickjs::Error) -> String {
+    if !matches!(error, rquickjs::Error::Exception) {
+        return error.to_string();
+    }
+    let thrown = ctx.catch();
+    match thrown.as_exception() {
+        Some(exception) => exception.to_string(),
+        None => thrown
+            .get::<Coerced<String>>()
+            .map(|s| s.0)
+            .unwrap_or_else(|_| "uncaught exception".to_string()),
+    }
+}
+
+impl JsRepl {
+    /// Create a REPL whose `llm_query` calls `query_fn`
+    ///
+    /// A block running longer than `exec_timeout` is interrupted.
+    pub fn new(query_fn: LlmQueryFn, exec_timeout: Option<Duration>) -> Result<Self> {
+        let runtime = Runtime::new().map_err(js_error)?;
+        let context = Context::full(&runtime).map_err(js_error)?;
+
+        let deadline: Arc<Mutex<Option<Instant>>> = Arc::new(Mutex::new(None));
+        let check = deadline.clone();
+        runtime.set_interrupt_handler(Some(Box::new(move || {
+            check.lock().unwrap().is_some_and(|d| Instant::now() >= d)
+        })));
+
+        let captured = Rc::new(RefCell::new(Captured::default()));
+        context
+            .with(|ctx| -> rquickjs::Result<()> {
+                let globals = ctx.globals();
+
+                let out = captured.clone();
+                globals.set(
+                    "__rlm_write",
+                    Function::new(ctx.clone(), move |text: Coerced<String>| {
+                        out.borrow_mut().stdout.push_str(&text.0);
+                    })?,
+                )?;
+
+                let answer = captured.clone();
+                globals.set(
+                    "llm_output",
+                    Function::new(ctx.clone(), move |value: Coerced<String>| {
+                        answer.borrow_mut().llm_output = Some(value.0);
+                    })?,
+                )?;
+
+                globals.set(
+                    "llm_query",
+                    Function::new(
+                        ctx.clone(),
+                        move |ctx: Ctx<'_>,
+                              prompt: Coerced<String>,
+                              options: String|
+                              -> rquickjs::Result<String> {
+                            serde_json::from_str::<QueryOverrides>(&options)
+                                .map_err(|e| format!("llm_query: invalid options: {}", e))
+                                .and_then(|overrides| query_fn(&overrides.encode(&prompt.0)))
+                                .map_err(|e| Exception::throw_message(&ctx, &e))
+                        },
+                    )?,
+                )?;
+
+                ctx.eval::<(), _>(PRELUDE)
+            })
+            .map_err(js_error)?;
+
+        Ok(Self {
+            context,
+            _runtime: runtime,
+            captured,
+            deadline,
+            exec_timeout,
+        })
+    }
+}
+
+impl ReplEnvironment for JsRepl {
+    fn execute(&mut self, code: &str) -> Result<ReplResult> {
+        *self.captured.borrow_mut() = Captured::default();
+        let start = Instant::now();
+        *self.deadline.lock().unwrap() = self.exec_timeout.map(|t| start + t);
+
+        // Sloppy mode, so plain `x = 1` keeps `x` for later blocks
+        let mut options = EvalOptions::default();
+        options.strict = false;
+        options.backtrace_barrier = true;
+        let error = self.context.with(|ctx| {
+            ctx.eval_with_options::<Value, _>(code, options)
+                .err()
+                .map(|e| exception_message(&ctx, e))
+        });
+
+        *self.deadline.lock().unwrap() = None;
+        let execution_time = start.elapsed();
+        let captured = std::mem::take(&mut *self.captured.borrow_mut());
+
+        let mut result = match error {
+            None => ReplResult::success(String::new(), HashMap::new(), execution_time),
+            Some(error) => {
+                let error = match self.exec_timeout {
+                    Some(timeout) if execution_time >= timeout => format!(
+                        "ExecutionTimeout: code block exceeded {:?} and was interrupted",
+                        timeout
+                    ),
+                    _ => error,
+                };
+                ReplResult::failure(error, String::new(), execution_time)
+            }
+        };
+        result.stdout = captured.stdout;
+        result.llm_output = captured.llm_output;
+        Ok(result)
+    }
+
+    fn add_context(&mut self, name: &str, value: &str) -> Result<()> {
+        self.context
+            .with(|ctx| ctx.globals().set(name, value))
+            .map_err(js_error)
+    }
+
+    fn get_locals(&self) -> HashMap<String, String> {
+        self.context.with(|ctx| {
+            ctx.globals()
+                .get::<_, Function>("__rlm_locals")
+                .and_then(|locals| locals.call::<_, HashMap<String, String>>(()))
+                .unwrap_or_default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repl(exec_timeout: Option<Duration>) -> JsRepl {
+        let query_fn: LlmQueryFn = Arc::new(|prompt: &str| {
+            if prompt == "fail" {
+                Err("backend down".to_string())
+            } else {
+                Ok(format!("echo: {}", prompt))
+            }
+        });
+        JsRepl::new(query_fn, exec_timeout).unwrap()
+    }
+
+    #[test]
+    fn test_js_bindings() {
+        let mut repl = repl(None);
+        repl.add_context("context", "some data").unwrap();
+
+        let result = repl
+            .execute("CONTEXT_LEN = 9\nanswer = llm_query('hi')\nconsole.log(context, [1, 2])")
+            .unwrap();
+        assert!(result.success, "{:?}", result.error);
+        assert_eq!(result.stdout, "some data [1,2]\n");
+
+        let result = repl
+            .execute("print(answer, CONTEXT_LEN); llm_output(answer)")
+            .unwrap();
+        assert_eq!(result.stdout, "echo: hi 9\n");
+        assert_eq!(result.llm_output.as_deref(), Some("echo: hi"));
+
+        let result = repl
+            .execute("print(llm_query('hi', {model: 'small'}))")
+            .unwrap();
+        assert!(result.stdout.contains("{\"model\":\"small\"}\u{0}hi"));
+
+        let locals = repl.get_locals();
+        assert_eq!(locals.get("answer").map(String::as_str), Some("echo: hi"));
+        assert_eq!(locals.get("CONTEXT_LEN").map(String::as_str), Some("9"));
+        assert!(!locals.contains_key("print"));
+    }
+
+    #[test]
+    fn test_js_errors() {
+        let mut repl = repl(None);
+        let result = repl.execute("print('before'); null.foo").unwrap();
+        assert!(!result.success);
+        assert_eq!(result.stdout, "before\n");
+        assert!(result.error.unwrap().contains("cannot read property"));
+
+        let result = repl.execute("llm_query('fail')").unwrap();
+        assert!(result.error.unwrap().contains("backend down"));
+    }
+
+    #[test]
+    fn test_js_exec_timeout() {
+        let mut repl = repl(Some(Duration::from_millis(200)));
+        let result = repl.execute("while (true) {}").unwrap();
+        assert!(result.error.unwrap().starts_with("ExecutionTimeout"));
+        assert!(repl.execute("print(1)").unwrap().success);
+    }
+}
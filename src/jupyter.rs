@@ -0,0 +1,753 @@
+//! Jupyter kernel REPL (`jupyter` feature)
+//!
+//! [`JupyterRepl`] runs model code in a running Jupyter kernel, talking the
+//! kernel messaging protocol over ZeroMQ, so the REPL can live in a remote,
+//! resource-managed kernel while the loop stays in Rust. Connect with the
+//! kernel's connection file and plug it in with [`Rlm::with_repl_factory`]:
+//!
+//! ```no_run
+//! # fn main() -> rlm::Result<()> {
+//! use rlm::jupyter::{ConnectionInfo, JupyterOptions, JupyterRepl};
+//! use rlm::{Rlm, RlmConfig};
+//!
+//! let info = ConnectionInfo::from_file("kernel-1234.json")?;
+//! let rlm = Rlm::new(RlmConfig::new("gpt-4o"))?
+//!     .with_repl_factory(JupyterRepl::factory(info, JupyterOptions::default()));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Model code reaches `llm_query` and `llm_output` through the kernel's stdin
+//! channel (`input()` requests tagged for the engine), which every kernel
+//! implements. The default bindings are for IPython kernels; other kernels
+//! need [`JupyterOptions::bindings`] and [`JupyterOptions::assign`] in their
+//! own language.
+//!
+//! [`Rlm::with_repl_factory`]: crate::Rlm::with_repl_factory
+
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::env::{LlmQueryFn, ReplEnvironment};
+use crate::error::{Result, RlmError};
+use crate::rlm::ReplFactory;
+use crate::types::{QueryOverrides, ReplResult};
+
+/// Frame separating ZeroMQ routing identities from the message
+const DELIMITER: &[u8] = b"<IDS|MSG>";
+
+/// Prefix of `input()` prompts addressed to the engine
+const CALL_PREFIX: &str = "\u{0}rlm:";
+
+/// Engine bindings for IPython kernels
+pub const IPYTHON_BINDINGS: &str = r#"
+import json as _rlm_json
+
+
+def _rlm_call(op, **args):
+    args["op"] = op
+    return _rlm_json.loads(input("\x00rlm:" + _rlm_json.dumps(args)))
+
+
+def llm_query(prompt, model=None, temperature=None, max_tokens=None):
+    options = {"model": model, "temperature": temperature, "max_tokens": max_tokens}
+    options = {k: v for k, v in options.items() if v is not None}
+    reply = _rlm_call("llm_query", prompt=str(prompt), **options)
+    if reply.get("ok"):
+        return reply["value"]
+    raise RuntimeError(reply.get("error") or "llm_query failed")
+
+
+def llm_output(answer):
+    _rlm_call("llm_output", value=str(answer))
+
+
+def _rlm_locals():
+    import types
+    out = {}
+    for name, value in list(globals().items()):
+        if name.startswith("_") or name in ("In", "Out") or callable(value) \
+                or isinstance(value, types.ModuleType):
+            continue
+        try:
+            out[name] = value if isinstance(value, str) else repr(value)
+        except Exception:
+            pass
+    _rlm_call("locals", locals=out)
+"#;
+
+/// Kernel connection file contents (`jupyter --runtime-dir`/kernel-*.json)
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConnectionInfo {
+    #[serde(default = "default_transport")]
+    pub transport: String,
+    pub ip: String,
+    pub shell_port: u16,
+    pub iopub_port: u16,
+    pub stdin_port: u16,
+    pub control_port: u16,
+    #[serde(default)]
+    pub key: String,
+    #[serde(default = "default_signature_scheme")]
+    pub signature_scheme: String,
+}
+
+fn default_transport() -> String {
+    "tcp".to_string()
+}
+
+fn default_signature_scheme() -> String {
+    "hmac-sha256".to_string()
+}
+
+impl ConnectionInfo {
+    /// Read a connection file written by the kernel launcher
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            RlmError::Config(format!(
+                "Cannot read connection file {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    fn endpoint(&self, port: u16) -> String {
+        match self.transport.as_str() {
+            "ipc" => format!("ipc://{}-{}", self.ip, port),
+            transport => format!("{}://{}:{}", transport, self.ip, port),
+        }
+    }
+}
+
+/// Settings for [`JupyterRepl`]
+#[derive(Debug, Clone)]
+pub struct JupyterOptions {
+    /// Code defining `llm_query`, `llm_output`, and `_rlm_locals()` in the
+    /// kernel's language
+    pub bindings: String,
+    /// Statement assigning a context; `{name}` is replaced with the variable
+    /// name, and `_rlm_call("context", ...)` must fetch its value
+    pub assign: String,
+    /// Run before the bindings when connecting (e.g. to clear state left by
+    /// an earlier run in a shared kernel)
+    pub reset: Option<String>,
+    /// Interrupt a block running longer than this (None = never)
+    pub exec_timeout: Option<Duration>,
+}
+
+impl Default for JupyterOptions {
+    fn default() -> Self {
+        Self {
+            bindings: IPYTHON_BINDINGS.to_string(),
+            assign: r#"{name} = _rlm_call("context", name="{name}")["value"]"#.to_string(),
+            reset: Some("%reset -f".to_string()),
+            exec_timeout: Some(Duration::from_secs(300)),
+        }
+    }
+}
+
+/// How long the kernel gets to go idle after an interrupt
+const INTERRUPT_GRACE: Duration = Duration::from_secs(10);
+
+/// Attempts at reaching the kernel while the iopub subscription settles
+const HANDSHAKE_ATTEMPTS: u32 = 20;
+
+/// Decoded kernel message
+struct Message {
+    header: Value,
+    parent_header: Value,
+    content: Value,
+}
+
+impl Message {
+    fn msg_type(&self) -> &str {
+        self.header["msg_type"].as_str().unwrap_or_default()
+    }
+
+    fn parent_id(&self) -> &str {
+        self.parent_header["msg_id"].as_str().unwrap_or_default()
+    }
+}
+
+/// Output gathered while one request runs
+#[derive(Default)]
+struct Collected {
+    stdout: String,
+    stderr: String,
+    error: Option<String>,
+    llm_output: Option<String>,
+    locals: Option<HashMap<String, String>>,
+    timed_out: bool,
+}
+
+/// REPL backed by a Jupyter kernel
+pub struct JupyterRepl {
+    _context: zmq::Context,
+    shell: zmq::Socket,
+    iopub: zmq::Socket,
+    stdin: zmq::Socket,
+    control: zmq::Socket,
+    key: Vec<u8>,
+    session: String,
+    counter: Cell<u64>,
+    query_fn: LlmQueryFn,
+    options: JupyterOptions,
+    contexts: HashMap<String, String>,
+}
+
+fn zmq_error(e: zmq::Error) -> RlmError {
+    RlmError::Repl(format!("Jupyter connection error: {}", e))
+}
+
+/// Drop terminal colour codes from kernel tracebacks
+fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' && chars.peek() == Some(&'[') {
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Text for a rich output bundle, preferring plain text
+fn render_data(data: &Value) -> String {
+    for mime in ["text/plain", "text/markdown", "text/html"] {
+        if let Some(text) = data[mime].as_str() {
+            return text.to_string();
+        }
+    }
+    let kinds: Vec<&str> = data
+        .as_object()
+        .map(|d| d.keys().map(String::as_str).collect())
+        .unwrap_or_default();
+    format!("[{} output]", kinds.join(", "))
+}
+
+impl JupyterRepl {
+    /// Connect to a running kernel and install the engine bindings
+    pub fn connect(
+        info: &ConnectionInfo,
+        query_fn: LlmQueryFn,
+        options: JupyterOptions,
+    ) -> Result<Self> {
+        if !info.key.is_empty() && info.signature_scheme != "hmac-sha256" {
+            return Err(RlmError::Config(format!(
+                "Unsupported kernel signature scheme: {}",
+                info.signature_scheme
+            )));
+        }
+
+        let context = zmq::Context::new();
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let session = format!("rlm-{}-{:x}", std::process::id(), nanos);
+
+        let socket = |kind: zmq::SocketType, port: u16| -> Result<zmq::Socket> {
+            let socket = context.socket(kind).map_err(zmq_error)?;
+            socket.set_linger(0).map_err(zmq_error)?;
+            if kind == zmq::DEALER {
+                socket.set_identity(session.as_bytes()).map_err(zmq_error)?;
+            }
+            socket.connect(&info.endpoint(port)).map_err(zmq_error)?;
+            Ok(socket)
+        };
+        let shell = socket(zmq::DEALER, info.shell_port)?;
+        let stdin = socket(zmq::DEALER, info.stdin_port)?;
+        let control = socket(zmq::DEALER, info.control_port)?;
+        let iopub = socket(zmq::SUB, info.iopub_port)?;
+        iopub.set_subscribe(b"").map_err(zmq_error)?;
+
+        let repl = Self {
+            _context: context,
+            shell,
+            iopub,
+            stdin,
+            control,
+            key: info.key.as_bytes().to_vec(),
+            session,
+            counter: Cell::new(0),
+            query_fn,
+            options,
+            contexts: HashMap::new(),
+        };
+        repl.handshake()?;
+
+        let mut setup = repl.options.reset.clone().into_iter().collect::<Vec<_>>();
+        setup.push(repl.options.bindings.clone());
+        for code in setup {
+            let collected = repl.run(&code, false)?;
+            if let Some(error) = collected.error {
+                return Err(RlmError::Repl(format!("Kernel setup failed: {}", error)));
+            }
+        }
+        Ok(repl)
+    }
+
+    /// [`ReplFactory`] connecting a fresh client for every run
+    pub fn factory(info: ConnectionInfo, options: JupyterOptions) -> impl ReplFactory {
+        move |query_fn: LlmQueryFn| -> Result<Box<dyn ReplEnvironment>> {
+            Ok(Box::new(Self::connect(&info, query_fn, options.clone())?))
+        }
+    }
+
+    fn sign(&self, parts: &[&[u8]]) -> String {
+        if self.key.is_empty() {
+            return String::new();
+        }
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes any key");
+        for part in parts {
+            mac.update(part);
+        }
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    /// Send a message, returning its id
+    fn send(
+        &self,
+        socket: &zmq::Socket,
+        msg_type: &str,
+        parent_header: &Value,
+        content: Value,
+    ) -> Result<String> {
+        let n = self.counter.get() + 1;
+        self.counter.set(n);
+        let msg_id = format!("{}-{}", self.session, n);
+        let header = json!({
+            "msg_id": msg_id,
+            "session": self.session,
+            "username": "rlm",
+            "date": humantime::format_rfc3339_micros(SystemTime::now()).to_string(),
+            "msg_type": msg_type,
+            "version": "5.3",
+        });
+        let parts = [
+            serde_json::to_vec(&header)?,
+            serde_json::to_vec(parent_header)?,
+            b"{}".to_vec(),
+            serde_json::to_vec(&content)?,
+        ];
+        let signature = self.sign(&[&parts[0], &parts[1], &parts[2], &parts[3]]);
+        let mut frames = vec![DELIMITER.to_vec(), signature.into_bytes()];
+        frames.extend(parts);
+        socket.send_multipart(frames, 0).map_err(zmq_error)?;
+        Ok(msg_id)
+    }
+
+    fn recv(socket: &zmq::Socket) -> Result<Option<Message>> {
+        let frames = socket.recv_multipart(0).map_err(zmq_error)?;
+        let Some(start) = frames.iter().position(|f| f == DELIMITER) else {
+            return Ok(None);
+        };
+        let part = |i: usize| -> Value {
+            frames
+                .get(start + i)
+                .and_then(|f| serde_json::from_slice(f).ok())
+                .unwrap_or(Value::Null)
+        };
+        Ok(Some(Message {
+            header: part(2),
+            parent_header: part(3),
+            content: part(5),
+        }))
+    }
+
+    /// Wait until iopub messages arrive, so none of the first run are lost
+    fn handshake(&self) -> Result<()> {
+        for _ in 0..HANDSHAKE_ATTEMPTS {
+            let id = self.send(&self.shell, "kernel_info_request", &json!({}), json!({}))?;
+            let deadline = Instant::now() + Duration::from_millis(500);
+            while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+                if self
+                    .iopub
+                    .poll(zmq::POLLIN, left.as_millis() as i64)
+                    .map_err(zmq_error)?
+                    == 0
+                {
+                    break;
+                }
+                if Self::recv(&self.iopub)?.is_some_and(|m| m.parent_id() == id) {
+                    // Drain the matching reply from the shell channel
+                    while self.shell.poll(zmq::POLLIN, 1000).map_err(zmq_error)? > 0 {
+                        let _ = Self::recv(&self.shell)?;
+                    }
+                    return Ok(());
+                }
+            }
+        }
+        Err(RlmError::Repl("Jupyter kernel did not respond".to_string()))
+    }
+
+    /// Answer an `input()` request from the kernel
+    fn answer_input(&self, request: &Message, collected: &mut Collected) -> Result<()> {
+        let prompt = request.content["prompt"].as_str().unwrap_or_default();
+        let value = match prompt.strip_prefix(CALL_PREFIX) {
+            Some(call) => {
+                let call: Value = serde_json::from_str(call)?;
+                let reply = match call["op"].as_str().unwrap_or_default() {
+                    "llm_query" => {
+                        let prompt = call["prompt"].as_str().unwrap_or_default();
+                        match serde_json::from_value::<QueryOverrides>(call.clone())
+                            .map_err(|e| format!("llm_query: invalid options: {}", e))
+                            .and_then(|overrides| (self.query_fn)(&overrides.encode(prompt)))
+                        {
+                            Ok(value) => json!({"ok": true, "value": value}),
+                            Err(error) => json!({"ok": false, "error": error}),
+                        }
+                    }
+                    "llm_output" => {
+                        collected.llm_output = call["value"].as_str().map(str::to_string);
+                        json!({"ok": true})
+                    }
+                    "locals" => {
+                        collected.locals = serde_json::from_value(call["locals"].clone()).ok();
+                        json!({"ok": true})
+                    }
+                    "context" => {
+                        let name = call["name"].as_str().unwrap_or_default();
+                        json!({"ok": true, "value": self.contexts.get(name)})
+                    }
+                    op => json!({"ok": false, "error": format!("unknown call: {}", op)}),
+                };
+                reply.to_string()
+            }
+            // Model code asked for user input; there is nobody to type it
+            None => String::new(),
+        };
+        self.send(
+            &self.stdin,
+            "input_reply",
+            &request.header,
+            json!({ "value": value }),
+        )?;
+        Ok(())
+    }
+
+    /// Execute code and gather its output until the kernel is idle again
+    fn run(&self, code: &str, silent: bool) -> Result<Collected> {
+        let id = self.send(
+            &self.shell,
+            "execute_request",
+            &json!({}),
+            json!({
+                "code": code,
+                "silent": silent,
+                "store_history": !silent,
+                "user_expressions": {},
+                "allow_stdin": true,
+                "stop_on_error": true,
+            }),
+        )?;
+
+        let mut collected = Collected::default();
+        let mut replied = false;
+        let mut idle = false;
+        let mut deadline = self.options.exec_timeout.map(|t| Instant::now() + t);
+        while !(replied && idle) {
+            let now = Instant::now();
+            if deadline.is_some_and(|d| now >= d) {
+                if collected.timed_out {
+                    return Err(RlmError::Repl(
+                        "Jupyter kernel did not respond to an interrupt".to_string(),
+                    ));
+                }
+                self.send(&self.control, "interrupt_request", &json!({}), json!({}))?;
+                collected.timed_out = true;
+                deadline = Some(now + INTERRUPT_GRACE);
+            }
+            let wait = deadline
+                .map(|d| d.saturating_duration_since(now).min(Duration::from_secs(1)))
+                .unwrap_or(Duration::from_secs(1));
+
+            let mut items = [
+                self.iopub.as_poll_item(zmq::POLLIN),
+                self.stdin.as_poll_item(zmq::POLLIN),
+                self.shell.as_poll_item(zmq::POLLIN),
+            ];
+            zmq::poll(&mut items, wait.as_millis() as i64).map_err(zmq_error)?;
+            let [iopub, stdin, shell] = items.map(|i| i.is_readable());
+
+            if iopub {
+                if let Some(msg) = Self::recv(&self.iopub)?.filter(|m| m.parent_id() == id) {
+                    let content = &msg.content;
+                    match msg.msg_type() {
+                        "stream" => {
+                            let text = content["text"].as_str().unwrap_or_default();
+                            match content["name"].as_str() {
+                                Some("stderr") => collected.stderr.push_str(text),
+                                _ => collected.stdout.push_str(text),
+                            }
+                        }
+                        "execute_result" | "display_data" => {
+                            collected.stdout.push_str(&render_data(&content["data"]));
+                            collected.stdout.push('\n');
+                        }
+                        "error" => {
+                            let traceback: Vec<&str> = content["traceback"]
+                                .as_array()
+                                .map(|t| t.iter().filter_map(Value::as_str).collect())
+                                .unwrap_or_default();
+                            collected.error = Some(if traceback.is_empty() {
+                                format!(
+                                    "{}: {}",
+                                    content["ename"].as_str().unwrap_or("Error"),
+                                    content["evalue"].as_str().unwrap_or_default()
+                                )
+                            } else {
+                                strip_ansi(&traceback.join("\n"))
+                            });
+                        }
+                        "status" => idle |= content["execution_state"] == "idle",
+                        _ => {}
+                    }
+                }
+            }
+            if stdin {
+                if let Some(msg) = Self::recv(&self.stdin)?.filter(|m| m.parent_id() == id) {
+                    if msg.msg_type() == "input_request" {
+                        self.answer_input(&msg, &mut collected)?;
+                    }
+                }
+            }
+            if shell {
+                if let Some(msg) = Self::recv(&self.shell)?.filter(|m| m.parent_id() == id) {
+                    if msg.msg_type() == "execute_reply" {
+                        replied = true;
+                        if msg.content["status"] == "error" && collected.error.is_none() {
+                            collected.error = Some(format!(
+                                "{}: {}",
+                                msg.content["ename"].as_str().unwrap_or("Error"),
+                                msg.content["evalue"].as_str().unwrap_or_default()
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(collected)
+    }
+}
+
+impl ReplEnvironment for JupyterRepl {
+    fn execute(&mut self, code: &str) -> Result<ReplResult> {
+        let start = Instant::now();
+        let collected = self.run(code, false)?;
+        let execution_time = start.elapsed();
+
+        let error = if collected.timed_out {
+            Some(format!(
+                "ExecutionTimeout: code block exceeded {:?} and was interrupted",
+                self.options.exec_timeout.unwrap_or_default()
+            ))
+        } else {
+            collected.error
+        };
+        let mut result = match error {
+            None => ReplResult::success(String::new(), HashMap::new(), execution_time),
+            Some(error) => ReplResult::failure(error, String::new(), execution_time),
+        };
+        result.stdout = collected.stdout;
+        result.stderr = collected.stderr;
+        result.llm_output = collected.llm_output;
+        Ok(result)
+    }
+
+    fn add_context(&mut self, name: &str, value: &str) -> Result<()> {
+        self.contexts.insert(name.to_string(), value.to_string());
+        let code = self.options.assign.replace("{name}", name);
+        match self.run(&code, true)?.error {
+            Some(error) => Err(RlmError::Repl(format!(
+                "Setting `{}` failed: {}",
+                name, error
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    fn get_locals(&self) -> HashMap<String, String> {
+        self.run("_rlm_locals()", true)
+            .ok()
+            .and_then(|c| c.locals)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    /// Minimal kernel speaking just enough of the protocol: `say <text>`
+    /// prints, `ask <text>` calls llm_query and prints the answer, `answer
+    /// <text>` calls llm_output, `fail` raises, `spin` never finishes
+    fn fake_kernel() -> ConnectionInfo {
+        let context = zmq::Context::new();
+        let bind = |kind| {
+            let socket = context.socket(kind).unwrap();
+            socket.bind("tcp://127.0.0.1:*").unwrap();
+            let endpoint = socket.get_last_endpoint().unwrap().unwrap();
+            let port = endpoint.rsplit(':').next().unwrap().parse().unwrap();
+            (socket, port)
+        };
+        let (shell, shell_port) = bind(zmq::ROUTER);
+        let (iopub, iopub_port) = bind(zmq::PUB);
+        let (stdin, stdin_port) = bind(zmq::ROUTER);
+        let (control, control_port) = bind(zmq::ROUTER);
+
+        thread::spawn(move || {
+            let _context = context;
+            let frames = |msg_type: &str, parent: &Value, content: Value| {
+                vec![
+                    DELIMITER.to_vec(),
+                    Vec::new(),
+                    json!({"msg_id": "k", "msg_type": msg_type})
+                        .to_string()
+                        .into_bytes(),
+                    parent.to_string().into_bytes(),
+                    b"{}".to_vec(),
+                    content.to_string().into_bytes(),
+                ]
+            };
+            let publish = |msg_type: &str, parent: &Value, content: Value| {
+                iopub
+                    .send_multipart(frames(msg_type, parent, content), 0)
+                    .unwrap();
+            };
+            let reply = |socket: &zmq::Socket, ids: &[Vec<u8>], msg_type, parent: &Value| {
+                let mut out = ids.to_vec();
+                out.extend(frames(msg_type, parent, json!({"status": "ok"})));
+                socket.send_multipart(out, 0).unwrap();
+            };
+            let split = |frames: Vec<Vec<u8>>| {
+                let at = frames.iter().position(|f| f == DELIMITER).unwrap();
+                let header: Value = serde_json::from_slice(&frames[at + 2]).unwrap();
+                let content: Value = serde_json::from_slice(&frames[at + 5]).unwrap();
+                (frames[..at].to_vec(), header, content)
+            };
+            let ask = |ids: &[Vec<u8>], parent: &Value, call: Value| -> Value {
+                let mut out = ids.to_vec();
+                let prompt = format!("{}{}", CALL_PREFIX, call);
+                out.extend(frames("input_request", parent, json!({"prompt": prompt})));
+                stdin.send_multipart(out, 0).unwrap();
+                let (_, _, content) = split(stdin.recv_multipart(0).unwrap());
+                serde_json::from_str(content["value"].as_str().unwrap()).unwrap()
+            };
+
+            loop {
+                let Ok(raw) = shell.recv_multipart(0) else {
+                    return;
+                };
+                let (ids, header, content) = split(raw);
+                publish("status", &header, json!({"execution_state": "busy"}));
+                if header["msg_type"] == "execute_request" {
+                    let code = content["code"].as_str().unwrap();
+                    for line in code.lines() {
+                        if let Some(text) = line.strip_prefix("say ") {
+                            publish("stream", &header, json!({"name": "stdout", "text": text}));
+                        } else if let Some(text) = line.strip_prefix("ask ") {
+                            let answer =
+                                ask(&ids, &header, json!({"op": "llm_query", "prompt": text}));
+                            let text = answer["value"].as_str().unwrap().to_string();
+                            publish("stream", &header, json!({"name": "stdout", "text": text}));
+                        } else if let Some(text) = line.strip_prefix("answer ") {
+                            ask(&ids, &header, json!({"op": "llm_output", "value": text}));
+                        } else if line == "fail" {
+                            let traceback = ["\u{1b}[0;31mValueError\u{1b}[0m: bad"];
+                            publish("error", &header, json!({"traceback": traceback}));
+                        } else if line == "spin" {
+                            control.recv_multipart(0).unwrap();
+                        } else if line.starts_with("context = ") {
+                            ask(&ids, &header, json!({"op": "context", "name": "context"}));
+                        }
+                    }
+                }
+                publish("status", &header, json!({"execution_state": "idle"}));
+                reply(&shell, &ids, "execute_reply", &header);
+            }
+        });
+
+        ConnectionInfo {
+            transport: "tcp".to_string(),
+            ip: "127.0.0.1".to_string(),
+            shell_port,
+            iopub_port,
+            stdin_port,
+            control_port,
+            key: "secret".to_string(),
+            signature_scheme: default_signature_scheme(),
+        }
+    }
+
+    fn options(exec_timeout: Option<Duration>) -> JupyterOptions {
+        JupyterOptions {
+            reset: None,
+            exec_timeout,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_jupyter_execute() {
+        let query_fn: LlmQueryFn = Arc::new(|prompt: &str| Ok(format!("echo: {}", prompt)));
+        let mut repl = JupyterRepl::connect(&fake_kernel(), query_fn, options(None)).unwrap();
+        repl.add_context("context", "data").unwrap();
+
+        let result = repl.execute("say hi\nask what\nanswer done").unwrap();
+        assert!(result.success, "{:?}", result.error);
+        assert_eq!(result.stdout, "hiecho: what");
+        assert_eq!(result.llm_output.as_deref(), Some("done"));
+
+        let result = repl.execute("fail").unwrap();
+        assert_eq!(result.error.as_deref(), Some("ValueError: bad"));
+    }
+
+    #[test]
+    fn test_jupyter_interrupt_on_timeout() {
+        let query_fn: LlmQueryFn = Arc::new(|_: &str| Ok(String::new()));
+        let mut repl = JupyterRepl::connect(
+            &fake_kernel(),
+            query_fn,
+            options(Some(Duration::from_millis(200))),
+        )
+        .unwrap();
+        let result = repl.execute("spin").unwrap();
+        assert!(result.error.unwrap().starts_with("ExecutionTimeout"));
+        assert!(repl.execute("say ok").unwrap().success);
+    }
+
+    #[test]
+    fn test_strip_ansi_and_render() {
+        assert_eq!(strip_ansi("\u{1b}[1;32mok\u{1b}[0m done"), "ok done");
+        assert_eq!(
+            render_data(&json!({"text/plain": "42", "text/html": "<b>42</b>"})),
+            "42"
+        );
+        assert_eq!(
+            render_data(&json!({"image/png": "..."})),
+            "[image/png output]"
+        );
+    }
+}
@@ -2,20 +2,73 @@
 //!
 //! An inference engine enabling LLMs to recursively decompose tasks
 //! via REPL-based code execution.
+//!
+//! Provider clients, parsing, and shared types live in `rlm-core` and are
+//! re-exported here unchanged.
+//!
+//! ## Concurrency
+//!
+//! Every run gets its own REPL, so concurrent [`Rlm::completion`] calls
+//! (from threads, [`Rlm::completion_many`], or a server's blocking tasks)
+//! never see each other's variables. How far they actually run in parallel
+//! depends on [`ReplMode`]:
+//!
+//! - `InProcess` REPLs share the one embedded interpreter and its GIL.
+//!   Waiting on the root model happens outside the interpreter, so runs
+//!   overlap there, but Python code from concurrent runs executes one
+//!   block at a time. PyO3 does not support sub-interpreters, so this
+//!   can't be lifted in-process.
+//! - `Worker` REPLs are separate `python` processes with no shared GIL:
+//!   concurrent runs execute fully in parallel. A shared
+//!   [`WorkerPool`](worker::WorkerPool) (`Rlm::with_worker_pool`) keeps
+//!   processes started ahead of time so runs don't wait for interpreter
+//!   startup.
+//!
+//! The JavaScript and Lua REPLs are per-run engines without a global lock.
 
-pub mod error;
-pub mod parsing;
-pub mod types;
+pub use rlm_core::{
+    answer, approval, backend, cache, error, files, html, log, mock, parsing, patch, ratelimit,
+    tokens, types, user_config,
+};
 
 pub mod env;
+pub mod ingest;
+#[cfg(feature = "javascript")]
+pub mod js;
+#[cfg(feature = "jupyter")]
+pub mod jupyter;
+#[cfg(feature = "lua")]
+pub mod lua;
 
+mod lint;
 mod prompts;
+mod python;
 mod rlm;
+pub mod snapshot;
+#[cfg(feature = "telemetry")]
+pub mod telemetry;
+pub mod worker;
 
 // Re-exports
+pub use answer::{AnswerContext, AnswerDetector, AnswerFormat};
+pub use approval::{CodeApproval, CodeApprovalHook};
+pub use backend::{
+    AnthropicBackend, ChatBackend, ChatParams, OpenAiBackend, ToolDefinition, ToolUse,
+};
+pub use cache::{CacheStats, QueryCache};
 pub use error::{Result, RlmError};
-pub use rlm::Rlm;
+pub use log::LogSink;
+pub use mock::MockBackend;
+pub use parsing::{ParserConfig, StreamEvent, StreamParser};
+pub use ratelimit::{RateLimit, RateLimitedBackend};
+pub use rlm::{
+    EventFn, LintConfirmFn, OutputFn, ReplFactory, ReplSession, Rlm, RlmEvent, RlmSession,
+};
 pub use types::{
-    Backend, ChatCompletion, CodeBlock, Message, PromptInput, ReplResult, RlmCompletion, RlmConfig,
-    RlmIteration, Role, Usage,
+    AdaptiveIterations, Attachment, Backend, BackendTimeouts, BatchCompletion, ChatCompletion,
+    CodeBlock, CompletionStatus, FixContext, Image, LintAction, LintFinding, LintPolicy, Message,
+    OutputTruncation, PartialRun, PromptInput, PythonEnv, QueryCacheConfig, QueryOverrides,
+    ReplLang, ReplMode, ReplResult, RlmCompletion, RlmConfig, RlmIteration, Role, Sampling,
+    SandboxPolicy, TaskMode, Usage, Workspace,
 };
+pub use user_config::{UserConfig, UserSettings};
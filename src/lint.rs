@@ -0,0 +1,160 @@
+//! Safety linter for Python code blocks
+//!
+//! Runs before a block is executed: the code is parsed with Python's `ast`
+//! on the host interpreter, in a namespace of its own, so model code can
+//! neither see nor patch the checker. See [`LintPolicy`] for what is
+//! flagged.
+
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::ffi::CString;
+
+use crate::error::{Result, RlmError};
+use crate::types::{LintFinding, LintPolicy};
+
+/// Python defining `lint(code, patterns, dunders)`, returning
+/// `(line, name, rule)` tuples
+const LINT_CODE: &str = r#"
+import ast, fnmatch, re
+
+SAFE_DUNDERS = {
+    "__name__", "__qualname__", "__doc__", "__module__", "__class__",
+    "__init__", "__file__", "__version__", "__len__", "__str__", "__repr__",
+}
+DUNDER = re.compile(r"__\w+__")
+
+def lint(code, patterns, dunders):
+    try:
+        tree = ast.parse(code)
+    except SyntaxError:
+        # Reported when the block runs
+        return []
+
+    aliases, findings, seen = {}, [], set()
+
+    def flag(node, name, rule=None):
+        if rule is None:
+            rule = next(
+                (p for p in patterns
+                 if name == p or name.startswith(p + ".") or fnmatch.fnmatchcase(name, p)),
+                None,
+            )
+        # One finding per pattern and line, but every distinct dunder
+        key = (node.lineno, name if rule == "dunder" else rule)
+        if rule and key not in seen:
+            seen.add(key)
+            findings.append((node.lineno, name, rule))
+
+    def dotted(node):
+        parts = []
+        while isinstance(node, ast.Attribute):
+            parts.append(node.attr)
+            node = node.value
+        if not isinstance(node, ast.Name):
+            return None
+        parts.append(aliases.get(node.id, node.id))
+        return ".".join(reversed(parts))
+
+    def unsafe_dunder(name):
+        return dunders and DUNDER.fullmatch(name) and name not in SAFE_DUNDERS
+
+    # Imports first, so uses anywhere resolve through their aliases
+    for node in ast.walk(tree):
+        if isinstance(node, ast.Import):
+            for alias in node.names:
+                top = alias.name.split(".")[0]
+                aliases[alias.asname or top] = alias.name if alias.asname else top
+                flag(node, alias.name)
+        elif isinstance(node, ast.ImportFrom) and node.module and not node.level:
+            for alias in node.names:
+                full = node.module + "." + alias.name
+                aliases[alias.asname or alias.name] = full
+                flag(node, full)
+
+    for node in ast.walk(tree):
+        if isinstance(node, (ast.Attribute, ast.Name)):
+            name = dotted(node)
+            if name:
+                flag(node, name)
+            attr = node.attr if isinstance(node, ast.Attribute) else node.id
+            if unsafe_dunder(attr):
+                flag(node, attr, "dunder")
+        elif isinstance(node, ast.Constant) and isinstance(node.value, str):
+            # getattr(obj, "__globals__") and friends
+            if unsafe_dunder(node.value):
+                flag(node, node.value, "dunder")
+
+    return sorted(findings)
+"#;
+
+/// Check `code` against `policy`
+///
+/// Code that doesn't parse yields no findings; the `SyntaxError` surfaces
+/// when it runs.
+pub(crate) fn lint_python(code: &str, policy: &LintPolicy) -> Result<Vec<LintFinding>> {
+    let source =
+        CString::new(LINT_CODE).map_err(|e| RlmError::Repl(format!("Linter source: {}", e)))?;
+    Python::attach(|py| -> Result<Vec<LintFinding>> {
+        let ns = PyDict::new(py);
+        py.run(&source, Some(&ns), None)?;
+        let lint = ns
+            .get_item("lint")?
+            .ok_or_else(|| RlmError::Repl("Linter failed to load".to_string()))?;
+        let findings: Vec<(usize, String, String)> = lint
+            .call1((code, policy.patterns.clone(), policy.dunders))?
+            .extract()?;
+        Ok(findings
+            .into_iter()
+            .map(|(line, name, rule)| LintFinding { line, name, rule })
+            .collect())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::LintAction;
+
+    fn flagged(code: &str) -> Vec<(usize, String)> {
+        lint_python(code, &LintPolicy::new(LintAction::Deny))
+            .unwrap()
+            .into_iter()
+            .map(|f| (f.line, f.name))
+            .collect()
+    }
+
+    #[test]
+    fn test_lint_resolves_imports() {
+        let code =
+            "import os as o\nfrom shutil import rmtree as rm\no.system('ls')\nrm('/tmp/x')\n";
+        assert_eq!(
+            flagged(code),
+            [
+                (2, "shutil.rmtree".to_string()),
+                (3, "os.system".to_string()),
+                (4, "shutil.rmtree".to_string())
+            ]
+        );
+        assert_eq!(
+            flagged("import subprocess\nsubprocess.run(['ls'])")[1].1,
+            "subprocess.run"
+        );
+        assert!(flagged("import os\nprint(os.path.join('a', 'b'), __name__)").is_empty());
+        assert!(flagged("def broken(:").is_empty());
+    }
+
+    #[test]
+    fn test_lint_dunders() {
+        let code = "x = ().__class__.__bases__[0].__subclasses__()\ny = getattr(f, '__globals__')";
+        let findings = lint_python(code, &LintPolicy::default()).unwrap();
+        let names: Vec<_> = findings.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["__bases__", "__subclasses__", "__globals__"]);
+        assert!(findings.iter().all(|f| f.rule == "dunder"));
+
+        let policy = LintPolicy {
+            dunders: false,
+            ..Default::default()
+        };
+        assert!(lint_python(code, &policy).unwrap().is_empty());
+    }
+}
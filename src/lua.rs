@@ -0,0 +1,265 @@
+//! Lua REPL (`lua` feature)
+//!
+//! [`LuaRepl`] runs model code in an embedded Lua 5.4 interpreter, built
+//! from source, for hosts that can't ship a Python runtime. It provides the
+//! same bindings as the Python REPL: `context`, `print`, `llm_query` (with an
+//! optional `{model = ..., temperature = ..., max_tokens = ...}` table), and
+//! `llm_output`.
+
+use mlua::{Function, HookTriggers, Lua, Table, Value};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::env::{LlmQueryFn, ReplEnvironment};
+use crate::error::{Result, RlmError};
+use crate::types::{QueryOverrides, ReplResult};
+
+/// Defines `print`, `io.write`, and the locals snapshot on top of the
+/// native `__rlm_write`
+const PRELUDE: &str = r##"
+local write = __rlm_write
+__rlm_write = nil
+
+local function show(v, depth)
+  if type(v) ~= "table" then return tostring(v) end
+  depth = depth or 0
+  if depth > 2 then return "{...}" end
+  local parts, n = {}, #v
+  for i = 1, n do parts[#parts + 1] = show(v[i], depth + 1) end
+  for k, item in pairs(v) do
+    if not (math.type(k) == "integer" and k >= 1 and k <= n) then
+      parts[#parts + 1] = tostring(k) .. " = " .. show(item, depth + 1)
+    end
+  end
+  return "{" .. table.concat(parts, ", ") .. "}"
+end
+
+function print(...)
+  local parts = {}
+  for i = 1, select("#", ...) do parts[i] = show((select(i, ...))) end
+  write(table.concat(parts, "\t") .. "\n")
+end
+
+io.write = function(...)
+  for i = 1, select("#", ...) do write(tostring((select(i, ...)))) end
+end
+
+local builtins = {}
+for name in pairs(_G) do builtins[name] = true end
+
+function __rlm_locals()
+  local out = {}
+  for name, value in pairs(_G) do
+    if type(name) == "string" and not builtins[name] and name:sub(1, 1) ~= "_"
+        and type(value) ~= "function" then
+      out[name] = type(value) == "string" and value or show(value)
+    end
+  end
+  return out
+end
+"##;
+
+/// VM instructions between timeout checks
+const HOOK_INTERVAL: u32 = 10_000;
+
+#[derive(Default)]
+struct Captured {
+    stdout: String,
+    llm_output: Option<String>,
+}
+
+/// Lua REPL backed by an embedded Lua 5.4
+pub struct LuaRepl {
+    lua: Lua,
+    captured: Rc<RefCell<Captured>>,
+    /// Abort running code once this passes
+    deadline: Rc<Cell<Option<Instant>>>,
+    exec_timeout: Option<Duration>,
+}
+
+fn lua_error(e: mlua::Error) -> RlmError {
+    RlmError::Repl(format!("Lua error: {}", e))
+}
+
+impl LuaRepl {
+    /// Create a REPL whose `llm_query` calls `query_fn`
+    ///
+    /// A block running longer than `exec_timeout` is aborted.
+    pub fn new(query_fn: LlmQueryFn, exec_timeout: Option<Duration>) -> Result<Self> {
+        let lua = Lua::new();
+        let captured = Rc::new(RefCell::new(Captured::default()));
+        let deadline: Rc<Cell<Option<Instant>>> = Rc::new(Cell::new(None));
+
+        {
+            let globals = lua.globals();
+            let out = captured.clone();
+            let write = lua
+                .create_function(move |_, text: String| {
+                    out.borrow_mut().stdout.push_str(&text);
+                    Ok(())
+                })
+                .map_err(lua_error)?;
+            let answer = captured.clone();
+            let llm_output = lua
+                .create_function(move |_, value: Value| {
+                    let text = match value {
+                        Value::String(s) => s.to_str()?.to_string(),
+                        other => other.to_string()?,
+                    };
+                    answer.borrow_mut().llm_output = Some(text);
+                    Ok(())
+                })
+                .map_err(lua_error)?;
+            let llm_query = lua
+                .create_function(move |_, (prompt, options): (String, Option<Table>)| {
+                    let overrides = match options {
+                        Some(options) => QueryOverrides {
+                            model: options.get("model")?,
+                            temperature: options.get("temperature")?,
+                            max_tokens: options.get("max_tokens")?,
+                            ..Default::default()
+                        },
+                        None => QueryOverrides::default(),
+                    };
+                    query_fn(&overrides.encode(&prompt)).map_err(mlua::Error::RuntimeError)
+                })
+                .map_err(lua_error)?;
+            globals.set("__rlm_write", write).map_err(lua_error)?;
+            globals.set("llm_output", llm_output).map_err(lua_error)?;
+            globals.set("llm_query", llm_query).map_err(lua_error)?;
+        }
+        lua.load(PRELUDE)
+            .set_name("prelude")
+            .exec()
+            .map_err(lua_error)?;
+
+        let check = deadline.clone();
+        lua.set_hook(
+            HookTriggers::new().every_nth_instruction(HOOK_INTERVAL),
+            move |_, _| match check.get() {
+                Some(d) if Instant::now() >= d => {
+                    Err(mlua::Error::RuntimeError("ExecutionTimeout".to_string()))
+                }
+                _ => Ok(()),
+            },
+        );
+
+        Ok(Self {
+            lua,
+            captured,
+            deadline,
+            exec_timeout,
+        })
+    }
+}
+
+impl ReplEnvironment for LuaRepl {
+    fn execute(&mut self, code: &str) -> Result<ReplResult> {
+        *self.captured.borrow_mut() = Captured::default();
+        let start = Instant::now();
+        self.deadline.set(self.exec_timeout.map(|t| start + t));
+
+        let error = self.lua.load(code).set_name("repl").exec().err();
+
+        self.deadline.set(None);
+        let execution_time = start.elapsed();
+        let captured = std::mem::take(&mut *self.captured.borrow_mut());
+
+        let mut result = match error {
+            None => ReplResult::success(String::new(), HashMap::new(), execution_time),
+            Some(error) => {
+                let error = match self.exec_timeout {
+                    Some(timeout) if execution_time >= timeout => format!(
+                        "ExecutionTimeout: code block exceeded {:?} and was aborted",
+                        timeout
+                    ),
+                    _ => error.to_string(),
+                };
+                ReplResult::failure(error, String::new(), execution_time)
+            }
+        };
+        result.stdout = captured.stdout;
+        result.llm_output = captured.llm_output;
+        Ok(result)
+    }
+
+    fn add_context(&mut self, name: &str, value: &str) -> Result<()> {
+        self.lua.globals().set(name, value).map_err(lua_error)
+    }
+
+    fn get_locals(&self) -> HashMap<String, String> {
+        self.lua
+            .globals()
+            .get::<_, Function>("__rlm_locals")
+            .and_then(|locals| locals.call::<_, Table>(()))
+            .and_then(|table| table.pairs::<String, String>().collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn repl(exec_timeout: Option<Duration>) -> LuaRepl {
+        let query_fn: LlmQueryFn = Arc::new(|prompt: &str| {
+            if prompt == "fail" {
+                Err("backend down".to_string())
+            } else {
+                Ok(format!("echo: {}", prompt))
+            }
+        });
+        LuaRepl::new(query_fn, exec_timeout).unwrap()
+    }
+
+    #[test]
+    fn test_lua_bindings() {
+        let mut repl = repl(None);
+        repl.add_context("context", "some data").unwrap();
+
+        let result = repl
+            .execute("CONTEXT_LEN = 9\nanswer = llm_query('hi')\nprint(context, {1, 2})")
+            .unwrap();
+        assert!(result.success, "{:?}", result.error);
+        assert_eq!(result.stdout, "some data\t{1, 2}\n");
+
+        let result = repl
+            .execute("io.write(answer, ' ', CONTEXT_LEN, '\\n'); llm_output(answer)")
+            .unwrap();
+        assert_eq!(result.stdout, "echo: hi 9\n");
+        assert_eq!(result.llm_output.as_deref(), Some("echo: hi"));
+
+        let result = repl
+            .execute("print(llm_query('hi', {model = 'small'}))")
+            .unwrap();
+        assert!(result.stdout.contains("{\"model\":\"small\"}\u{0}hi"));
+
+        let locals = repl.get_locals();
+        assert_eq!(locals.get("answer").map(String::as_str), Some("echo: hi"));
+        assert_eq!(locals.get("CONTEXT_LEN").map(String::as_str), Some("9"));
+        assert!(!locals.contains_key("print"));
+    }
+
+    #[test]
+    fn test_lua_errors() {
+        let mut repl = repl(None);
+        let result = repl.execute("print('before'); error('boom')").unwrap();
+        assert!(!result.success);
+        assert_eq!(result.stdout, "before\n");
+        assert!(result.error.unwrap().contains("boom"));
+
+        let result = repl.execute("llm_query('fail')").unwrap();
+        assert!(result.error.unwrap().contains("backend down"));
+    }
+
+    #[test]
+    fn test_lua_exec_timeout() {
+        let mut repl = repl(Some(Duration::from_millis(200)));
+        let result = repl.execute("while true do end").unwrap();
+        assert!(result.error.unwrap().starts_with("ExecutionTimeout"));
+        assert!(repl.execute("print(1)").unwrap().success);
+    }
+}
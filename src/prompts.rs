@@ -1,7 +1,327 @@
+use crate::patch::number_lines;
+use crate::types::{Attachment, LintFinding, PythonEnv, ReplLang, TaskMode, Workspace};
+
+/// Suggested chunk size for slicing `context`, exposed as `CHUNK_SUGGESTED_SIZE`
+///
+/// Large contexts get the 3500 char segments the strategy hint asks for,
+/// smaller ones can be processed in a single chunk.
+pub fn suggested_chunk_size(context_len: usize) -> usize {
+    if context_len > 6000 {
+        3500
+    } else {
+        context_len.max(1)
+    }
+}
+
+/// Task framing (opening line, task hint) for each task mode
+fn task_framing(task_mode: TaskMode) -> (&'static str, &'static str) {
+    match task_mode {
+        TaskMode::Completion => (
+            "You are an LLM performing TEXT GENERATION. Your output will be appended to context.",
+            "Examine the END of `context` to find your task. Your output appends to it.",
+        ),
+        TaskMode::Answer => (
+            "You are an LLM ANSWERING A QUESTION. Your output is a standalone answer shown to the user.",
+            "The question is usually at the END of `context`; everything before it is supporting material.\n\
+            Answer it directly - do not continue or repeat the context.",
+        ),
+        TaskMode::Transform => (
+            "You are an LLM performing a TRANSFORMATION. Your output is the transformed input, nothing else.",
+            "Find the instructions in `context` (usually at the END) and apply them to the data.\n\
+            Output only the transformed result - no commentary, no surrounding code fences.",
+        ),
+    }
+}
+
+/// Language-specific parts of the system prompt
+struct LangPrompt {
+    name: &'static str,
+    len_expr: &'static str,
+    /// `llm_query` call with per-call overrides
+    query_options: &'static str,
+    extra_rule: &'static str,
+    explore: &'static str,
+    examples: &'static str,
+    mistakes: &'static str,
+}
+
+const PYTHON_PROMPT: LangPrompt = LangPrompt {
+    name: "Python",
+    len_expr: "len(context)",
+    query_options: "llm_query(prompt, model=\"...\", temperature=0.2, max_tokens=500)",
+    extra_rule: "",
+    explore: r#"```repl
+print("=== START ===")
+print(context[:500])
+print("=== END ===")
+print(context[-500:])
+```"#,
+    examples: r#"EXAMPLE A - Simple Task:
+```repl
+task = context[-300:]  # Find the task
+print(task)
+```
+→ Output shows: "User: What is 2+2?\nAssistant:"
+```repl
+llm_output("4")
+```
+
+EXAMPLE B - Analysis with Sub-LLM:
+```repl
+document = context[:4000]
+analysis = llm_query(f"Analyze this text and list key points:\n\n{document}")
+print(analysis)
+```
+→ Output shows analysis
+```repl
+llm_output(analysis)
+```
+
+EXAMPLE C - Large Context Chunking:
+```repl
+# Split into chunks, leaving space for task at end
+chunks = [context[i:i+CHUNK_SUGGESTED_SIZE] for i in range(0, CONTEXT_LEN-500, CHUNK_SUGGESTED_SIZE)]
+print(f"{len(chunks)} chunks to process")
+summaries = []
+```
+```repl
+s1 = llm_query(f"Summarize:\n{chunks[0]}")
+summaries.append(s1)
+print(f"Chunk 1: {s1[:200]}...")
+```
+```repl
+# Continue with remaining chunks...
+final = llm_query(f"Combine summaries:\n" + "\n---\n".join(summaries))
+llm_output(final)
+```"#,
+    mistakes: r#"BAD:  llm_query("summarize the context")      → Sub-LLM can't see context!
+GOOD: llm_query(f"summarize: {context}")    → Pass the data explicitly
+
+BAD:  answer = llm_query(...)                 → Forgot to print
+GOOD: answer = llm_query(...); print(answer)  → See what you got
+
+BAD:  Multiple code blocks in one response    → Only first executes
+GOOD: One code block, wait for output         → Iterate properly"#,
+};
+
+const JAVASCRIPT_PROMPT: LangPrompt = LangPrompt {
+    name: "JavaScript",
+    len_expr: "context.length",
+    query_options: "llm_query(prompt, {model: \"...\", temperature: 0.2, max_tokens: 500})",
+    extra_rule: "\n6. Assign without `let`/`const` (`x = ...`) to keep values for later blocks",
+    explore: r#"```repl
+print("=== START ===")
+print(context.slice(0, 500))
+print("=== END ===")
+print(context.slice(-500))
+```"#,
+    examples: r#"EXAMPLE A - Simple Task:
+```repl
+task = context.slice(-300)  // Find the task
+print(task)
+```
+→ Output shows: "User: What is 2+2?\nAssistant:"
+```repl
+llm_output("4")
+```
+
+EXAMPLE B - Analysis with Sub-LLM:
+```repl
+doc = context.slice(0, 4000)
+analysis = llm_query(`Analyze this text and list key points:\n\n${doc}`)
+print(analysis)
+```
+→ Output shows analysis
+```repl
+llm_output(analysis)
+```
+
+EXAMPLE C - Large Context Chunking:
+```repl
+// Split into chunks, leaving space for task at end
+chunks = []
+for (let i = 0; i < CONTEXT_LEN - 500; i += CHUNK_SUGGESTED_SIZE) chunks.push(context.slice(i, i + CHUNK_SUGGESTED_SIZE))
+print(`${chunks.length} chunks to process`)
+summaries = []
+```
+```repl
+s1 = llm_query(`Summarize:\n${chunks[0]}`)
+summaries.push(s1)
+print(`Chunk 1: ${s1.slice(0, 200)}...`)
+```
+```repl
+// Continue with remaining chunks...
+final = llm_query("Combine summaries:\n" + summaries.join("\n---\n"))
+llm_output(final)
+```"#,
+    mistakes: r#"BAD:  llm_query("summarize the context")      → Sub-LLM can't see context!
+GOOD: llm_query(`summarize: ${context}`)      → Pass the data explicitly
+
+BAD:  answer = llm_query(...)                 → Forgot to print
+GOOD: answer = llm_query(...); print(answer)  → See what you got
+
+BAD:  const x = ... / let x = ...             → Only plain `x = ...` is kept for later blocks
+GOOD: x = ...                                 → Available in the next block
+
+BAD:  Multiple code blocks in one response    → Only first executes
+GOOD: One code block, wait for output         → Iterate properly"#,
+};
+
+const LUA_PROMPT: LangPrompt = LangPrompt {
+    name: "Lua",
+    len_expr: "#context",
+    query_options: "llm_query(prompt, {model = \"...\", temperature = 0.2, max_tokens = 500})",
+    extra_rule: "\n6. Don't use `local` for values you need in later blocks - only globals persist",
+    explore: r#"```repl
+print("=== START ===")
+print(context:sub(1, 500))
+print("=== END ===")
+print(context:sub(-500))
+```"#,
+    examples: r#"EXAMPLE A - Simple Task:
+```repl
+task = context:sub(-300)  -- Find the task
+print(task)
+```
+→ Output shows: "User: What is 2+2?\nAssistant:"
+```repl
+llm_output("4")
+```
+
+EXAMPLE B - Analysis with Sub-LLM:
+```repl
+doc = context:sub(1, 4000)
+analysis = llm_query("Analyze this text and list key points:\n\n" .. doc)
+print(analysis)
+```
+→ Output shows analysis
+```repl
+llm_output(analysis)
+```
+
+EXAMPLE C - Large Context Chunking:
+```repl
+-- Split into chunks, leaving space for task at end
+chunks = {}
+for i = 1, CONTEXT_LEN - 500, CHUNK_SUGGESTED_SIZE do
+  chunks[#chunks + 1] = context:sub(i, i + CHUNK_SUGGESTED_SIZE - 1)
+end
+print(#chunks .. " chunks to process")
+summaries = {}
+```
+```repl
+s1 = llm_query("Summarize:\n" .. chunks[1])
+table.insert(summaries, s1)
+print("Chunk 1: " .. s1:sub(1, 200) .. "...")
+```
+```repl
+-- Continue with remaining chunks...
+final = llm_query("Combine summaries:\n" .. table.concat(summaries, "\n---\n"))
+llm_output(final)
+```"#,
+    mistakes: r#"BAD:  llm_query("summarize the context")      → Sub-LLM can't see context!
+GOOD: llm_query("summarize: " .. context)     → Pass the data explicitly
+
+BAD:  answer = llm_query(...)                 → Forgot to print
+GOOD: answer = llm_query(...); print(answer)  → See what you got
+
+BAD:  local x = ...                           → Gone in the next block
+GOOD: x = ...                                 → Available in the next block
+
+BAD:  chunks[0]                               → Lua tables start at 1
+GOOD: chunks[1]                               → First element
+
+BAD:  Multiple code blocks in one response    → Only first executes
+GOOD: One code block, wait for output         → Iterate properly"#,
+};
+
+/// AVAILABLE FUNCTIONS lines for the Python text-processing helpers
+pub const TEXT_HELPER_FUNCTIONS: &str = "
+  chunk_text(text, size=CHUNK_SUGGESTED_SIZE, overlap=0) → list
+                            → Split at paragraph/line/word boundaries
+  count_tokens(text) → int  → Estimated token count (~4 chars per token)
+  grep(pattern, text, context=0) → list  → Regex-matching lines as \"lineno: line\"
+  parse_json(text)          → Parse JSON from a sub-LLM reply (fences/prose ok)";
+
+/// AVAILABLE FUNCTIONS lines describing the Python package policy
+///
+/// Empty when imports are unrestricted and `pip_install` is off.
+pub fn package_functions(env: &PythonEnv) -> String {
+    let mut out = String::new();
+    if !env.installable.is_empty() {
+        out.push_str(&format!(
+            "\n  pip_install(name)         → Install a package first: {}",
+            env.installable.join(", ")
+        ));
+    }
+    if let Some(ref allowed) = env.allowed_imports {
+        let extra = if allowed.is_empty() {
+            String::new()
+        } else {
+            format!(" plus {}", allowed.join(", "))
+        };
+        out.push_str(&format!(
+            "\n\nImportable: the standard library{} - other imports raise ImportError.",
+            extra
+        ));
+    }
+    out
+}
+
+/// AVAILABLE FUNCTIONS lines listing the run's attachments
+pub fn attachment_functions(attachments: &[Attachment]) -> String {
+    let mut out = String::from("\n\nAttachments (bytes, not part of `context`):");
+    for attachment in attachments {
+        out.push_str(&format!(
+            "\n  attachments[{:?}] → {} bytes",
+            attachment.name,
+            attachment.data.len()
+        ));
+    }
+    out
+}
+
+/// AVAILABLE FUNCTIONS line for sending images with a sub-call
+pub const IMAGE_QUERY_FUNCTION: &str = "\n  llm_query(prompt, images=[data, ...]) → str  → Send PNG/JPEG/GIF/WebP bytes (an image attachment, a crop of one) with the prompt";
+
+/// AVAILABLE FUNCTIONS lines for the workspace file helpers
+pub fn workspace_functions(workspace: &Workspace) -> String {
+    let mut out = String::from(
+        "\n  list_dir(path=\".\") → list  → Files in the workspace (directories end in /)\
+         \n  read_file(path) → str     → Read a workspace file (paths relative to the workspace)",
+    );
+    if !workspace.read_only {
+        out.push_str("\n  write_file(path, text)    → Save intermediate results to the workspace");
+    }
+    out
+}
+
 /// Build the system prompt for RLM
 ///
 /// Dynamic strategy based on context size with clear structured sections.
-pub fn build_system_prompt(context_len: usize) -> String {
+/// The opening framing depends on the task mode, the code examples on the
+/// REPL language. Token counts let the model judge how much of `context`
+/// fits into its own window and sub-calls. `extra_functions` is appended to
+/// the function list (see [`package_functions`], [`workspace_functions`]).
+pub fn build_system_prompt(
+    context_len: usize,
+    context_tokens: usize,
+    context_window: Option<usize>,
+    task_mode: TaskMode,
+    language: ReplLang,
+    extra_functions: &str,
+) -> String {
+    let (role_line, task_hint) = task_framing(task_mode);
+    let lang = match language {
+        ReplLang::Python => &PYTHON_PROMPT,
+        ReplLang::JavaScript => &JAVASCRIPT_PROMPT,
+        ReplLang::Lua => &LUA_PROMPT,
+    };
+    let window_line = match context_window {
+        Some(window) => format!("Model context window: {window} tokens"),
+        None => "Model context window: unknown".to_string(),
+    };
+
     // Dynamic strategy based on context size
     let strategy_hint = if context_len > 6000 {
         "Your context is LARGE - use chunking strategy. Process in 3000-4000 char segments."
@@ -12,19 +332,26 @@ pub fn build_system_prompt(context_len: usize) -> String {
     };
 
     format!(
-        r#"You are an LLM performing TEXT GENERATION. Your output will be appended to context.
+        r#"{role_line}
 
-You have a Python REPL to interactively explore, analyze, and build your response.
+You have a {language} REPL to interactively explore, analyze, and build your response.
 The task/prompt is in `context`. You iterate until you call llm_output(your_response).
 
 ═══════════════════════════════════════════════════════════════════════════════
                               CONTEXT INFO
 ═══════════════════════════════════════════════════════════════════════════════
 
-Context size: {context_len} characters (stored in `context` variable)
+Context size: {context_len} characters, ~{context_tokens} tokens (stored in `context` variable)
+{window_line}
 Strategy: {strategy_hint}
 
-Examine the END of `context` to find your task. Your output appends to it.
+Predefined variables (already set, no need to recompute):
+  CONTEXT_LEN               → {len_expr}
+  CONTEXT_SHA256            → SHA-256 hex digest of context
+  CHUNK_SUGGESTED_SIZE      → {chunk_size} (recommended slice size for context)
+  REMAINING_ITERATIONS      → iterations left, updated before every step
+
+{task_hint}
 
 ═══════════════════════════════════════════════════════════════════════════════
                            AVAILABLE FUNCTIONS
@@ -32,7 +359,10 @@ Examine the END of `context` to find your task. Your output appends to it.
 
   print(value)              → Display output, continue reasoning
   llm_query(prompt) → str   → Query sub-LLM (CANNOT see your context!)
-  llm_output(answer)        → Submit final answer (TERMINATES iteration)
+    {query_options}
+                            → Same, overriding the sub-LLM settings (e.g. a cheap
+                              model for bulk chunks, a strong one for synthesis)
+  llm_output(answer)        → Submit final answer (TERMINATES iteration){extra_functions}
 
 CRITICAL: llm_query() runs in isolated context. You MUST include all
 necessary information in the prompt string. It cannot see `context`.
@@ -45,19 +375,14 @@ necessary information in the prompt string. It cannot see `context`.
 2. Code executes immediately - you see output next iteration
 3. ALWAYS print() values you need to inspect
 4. Store llm_query() results in variables: `result = llm_query(...)`
-5. Call llm_output(answer) ONLY when task is COMPLETE
+5. Call llm_output(answer) ONLY when task is COMPLETE{extra_rule}
 
 ═══════════════════════════════════════════════════════════════════════════════
                                STRATEGY
 ═══════════════════════════════════════════════════════════════════════════════
 
 STEP 1 - EXPLORE: Always start by examining context
-```repl
-print("=== START ===")
-print(context[:500])
-print("=== END ===")
-print(context[-500:])
-```
+{explore}
 
 STEP 2 - PLAN: Identify what's being asked (usually at the end of context)
 
@@ -69,74 +394,125 @@ STEP 4 - FINISH: Call llm_output(your_answer) when done
                                EXAMPLES
 ═══════════════════════════════════════════════════════════════════════════════
 
-EXAMPLE A - Simple Task:
-```repl
-task = context[-300:]  # Find the task
-print(task)
-```
-→ Output shows: "User: What is 2+2?\nAssistant:"
-```repl
-llm_output("4")
-```
-
-EXAMPLE B - Analysis with Sub-LLM:
-```repl
-document = context[:4000]
-analysis = llm_query(f"Analyze this text and list key points:\n\n{{document}}")
-print(analysis)
-```
-→ Output shows analysis
-```repl
-llm_output(analysis)
-```
-
-EXAMPLE C - Large Context Chunking:
-```repl
-# Split into chunks, leaving space for task at end
-chunks = [context[i:i+3500] for i in range(0, len(context)-500, 3500)]
-print(f"{{len(chunks)}} chunks to process")
-summaries = []
-```
-```repl
-s1 = llm_query(f"Summarize:\n{{chunks[0]}}")
-summaries.append(s1)
-print(f"Chunk 1: {{s1[:200]}}...")
-```
-```repl
-# Continue with remaining chunks...
-final = llm_query(f"Combine summaries:\n" + "\n---\n".join(summaries))
-llm_output(final)
-```
+{examples}
 
 ═══════════════════════════════════════════════════════════════════════════════
                             COMMON MISTAKES
 ═══════════════════════════════════════════════════════════════════════════════
 
-BAD:  llm_query("summarize the context")      → Sub-LLM can't see context!
-GOOD: llm_query(f"summarize: {{context}}")    → Pass the data explicitly
-
-BAD:  answer = llm_query(...)                 → Forgot to print
-GOOD: answer = llm_query(...); print(answer)  → See what you got
-
-BAD:  Multiple code blocks in one response    → Only first executes
-GOOD: One code block, wait for output         → Iterate properly
+{mistakes}
 
 ═══════════════════════════════════════════════════════════════════════════════
 
 Your task is in `context`. Start by exploring it. Execute code now:"#,
+        role_line = role_line,
+        language = lang.name,
+        len_expr = lang.len_expr,
+        query_options = lang.query_options,
+        extra_rule = lang.extra_rule,
+        extra_functions = extra_functions,
+        explore = lang.explore,
+        examples = lang.examples,
+        mistakes = lang.mistakes,
+        task_hint = task_hint,
         context_len = context_len,
-        strategy_hint = strategy_hint
+        strategy_hint = strategy_hint,
+        chunk_size = suggested_chunk_size(context_len)
     )
 }
 
 /// Build the initial user prompt for the first iteration
 pub fn build_initial_user_prompt() -> String {
-    "Begin by examining the `context` variable to understand your task. Write a ```repl code block:".to_string()
+    "Begin by examining the `context` variable to understand your task. Write a ```repl code block:"
+        .to_string()
+}
+
+/// System prompt addition for a later turn of a session
+pub const SESSION_TURN_NOTE: &str = "This request continues a session: the variables your code set in earlier turns are still defined in the REPL, and `context` starts with the earlier requests and your answers to them, followed by the new request.";
+
+/// Error raised by `llm_query` once the sub-call budget is used up
+pub fn sub_call_budget_exhausted_message(max_sub_calls: u32) -> String {
+    format!(
+        "[BUDGET EXHAUSTED] llm_query() limit of {} calls reached for this run. \
+        No further sub-LLM calls will be made. Aggregate the results you already have \
+        and call llm_output() with your best answer.",
+        max_sub_calls
+    )
+}
+
+/// Error raised by `llm_query` past the per-iteration limit
+pub fn sub_call_iteration_limit_message(max_per_iteration: u32) -> String {
+    format!(
+        "[SUB-CALL LIMIT] llm_query() limit of {} calls per iteration reached. \
+        Print what you have so far; you can make more calls in the next code block. \
+        Use larger chunks to need fewer calls.",
+        max_per_iteration
+    )
+}
+
+/// Execution error for a block the approval hook denied
+pub fn code_denied_message(reason: &str) -> String {
+    format!(
+        "[DENIED] The user did not allow this code to run: {}\n\
+        Take this into account and try a different approach.",
+        reason
+    )
+}
+
+/// Note ahead of the output of a block the approval hook edited
+pub fn code_edited_message(code: &str) -> String {
+    format!(
+        "[EDITED] The user changed your code before running it. \
+        This ran instead:\n```repl\n{}\n```",
+        code
+    )
+}
+
+/// Execution error for a block the safety linter refused to run
+pub fn lint_denial_message(findings: &[LintFinding]) -> String {
+    let mut out = String::from(
+        "[BLOCKED] This code was not run: the safety check flagged calls \
+        that are not allowed here.",
+    );
+    for finding in findings {
+        let why = if finding.rule == "dunder" {
+            "dunder access".to_string()
+        } else {
+            format!("matches `{}`", finding.rule)
+        };
+        out.push_str(&format!(
+            "\n  line {}: {} ({})",
+            finding.line, finding.name, why
+        ));
+    }
+    out.push_str("\nRewrite the code without them.");
+    out
 }
 
 /// Build the continuation prompt for subsequent iterations
-pub fn build_continue_prompt(iteration: u32, max_iterations: u32) -> String {
-    let urgency = if iteration >= max_iterations - 3 {
+///
+/// Switches to wrap-up mode once the sub-call budget is exhausted.
+/// Prompt for a focused fix round: the failing block, numbered, and its error
+pub fn build_fix_prompt(code: &str, error: &str) -> String {
+    format!(
+        "This REPL code failed:\n```\n{}\n```\n\n\
+        Error:\n```error\n{}\n```\n\n\
+        Variables from earlier blocks (and any lines that ran before the error) are still set.\n\
+        Reply with a unified diff against the code above in a ```diff``` block, \
+        or the full corrected code in a ```repl``` block.",
+        number_lines(code),
+        error
+    )
+}
+
+pub fn build_continue_prompt(
+    iteration: u32,
+    max_iterations: u32,
+    sub_calls_exhausted: bool,
+) -> String {
+    let urgency = if sub_calls_exhausted {
+        "WRAP UP: llm_query() budget is exhausted. Aggregate what you have and call llm_output() now."
+    } else if iteration >= max_iterations.saturating_sub(3) {
         "URGENT: Running low on iterations! Finish soon or call llm_output() with partial result."
     } else if iteration >= max_iterations / 2 {
         "You're halfway through iterations. Make progress toward completion."
@@ -153,3 +529,35 @@ pub fn build_continue_prompt(iteration: u32, max_iterations: u32) -> String {
         urgency
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `{name}` placeholders in `text`
+    fn placeholders(text: &str) -> Vec<&str> {
+        text.match_indices('{')
+            .filter_map(|(start, _)| {
+                let end = start + text[start..].find('}')?;
+                let name = &text[start + 1..end];
+                let is_name =
+                    !name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c == '_');
+                is_name.then_some(&text[start..=end])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_system_prompt_has_no_placeholders() {
+        for mode in [TaskMode::Completion, TaskMode::Answer, TaskMode::Transform] {
+            let prompt = build_system_prompt(100, 25, Some(8000), mode, ReplLang::Python, "");
+            assert!(prompt.contains(task_framing(mode).1));
+            // The code examples have f-strings of their own
+            let text = prompt
+                .replace(PYTHON_PROMPT.examples, "")
+                .replace(PYTHON_PROMPT.mistakes, "");
+            assert_eq!(placeholders(&text), Vec::<&str>::new(), "{:?}", mode);
+        }
+        assert_eq!(placeholders("a {task_hint} b {x.y}"), ["{task_hint}"]);
+    }
+}
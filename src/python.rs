@@ -0,0 +1,369 @@
+//! Python interpreter/venv discovery for the embedded REPL
+//!
+//! PyO3 embeds the interpreter it was built against (see `PYO3_PYTHON`), so
+//! the interpreter itself is fixed at build time. What can be chosen at
+//! runtime is the virtualenv whose packages the REPL sees.
+
+use pyo3::prelude::*;
+use pyo3::types::{PyCFunction, PyDict};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Once;
+use std::time::Duration;
+
+use crate::env::ReplEnvironment;
+use crate::error::{Result, RlmError};
+use crate::rlm::OutputFn;
+use crate::types::{PythonEnv, ReplResult};
+
+/// Resolve the venv to use: explicit config first, then auto-detection
+///
+/// Auto-detection checks `VIRTUAL_ENV` and then a `.venv` directory in the
+/// current working directory.
+pub(crate) fn resolve_venv(env: &PythonEnv) -> Option<PathBuf> {
+    if let Some(ref venv) = env.venv {
+        return Some(venv.clone());
+    }
+    if !env.auto_detect {
+        return None;
+    }
+    std::env::var_os("VIRTUAL_ENV")
+        .map(PathBuf::from)
+        .or_else(|| Some(PathBuf::from(".venv")))
+        .filter(|p| p.is_dir())
+}
+
+/// Site-packages directory of a venv for the given interpreter version
+fn site_packages_dir(venv: &Path, major: u8, minor: u8) -> PathBuf {
+    if cfg!(windows) {
+        venv.join("Lib").join("site-packages")
+    } else {
+        venv.join("lib")
+            .join(format!("python{}.{}", major, minor))
+            .join("site-packages")
+    }
+}
+
+/// Python executable inside a venv
+fn venv_executable(venv: &Path) -> PathBuf {
+    if cfg!(windows) {
+        venv.join("Scripts").join("python.exe")
+    } else {
+        venv.join("bin").join("python")
+    }
+}
+
+/// Interpreter for an out-of-process REPL worker
+///
+/// Unlike the embedded interpreter this one can follow the venv: its own
+/// `python` if it has one, else `python3` from `PATH`.
+pub(crate) fn worker_interpreter(env: &PythonEnv) -> PathBuf {
+    resolve_venv(env)
+        .map(|venv| venv_executable(&venv))
+        .filter(|exe| exe.exists())
+        .unwrap_or_else(|| PathBuf::from(if cfg!(windows) { "python" } else { "python3" }))
+}
+
+/// Hand a Rust output callback to the embedded interpreter
+///
+/// REPL setup code can only pass source text, so the callback is parked on
+/// `builtins` under a unique name; the returned expression takes it back off
+/// (leaving nothing behind for other REPLs to find).
+pub(crate) fn stream_hook(output: OutputFn) -> Result<String> {
+    static NEXT_HOOK: AtomicU64 = AtomicU64::new(0);
+    let name = format!("_rlm_stream_{}", NEXT_HOOK.fetch_add(1, Ordering::Relaxed));
+
+    Python::attach(|py| -> Result<()> {
+        let emit =
+            PyCFunction::new_closure(py, None, None, move |args, _kwargs| -> PyResult<()> {
+                let text: String = args.get_item(0)?.extract()?;
+                output(&text);
+                Ok(())
+            })?;
+        py.import("builtins")?.setattr(name.as_str(), emit)?;
+        Ok(())
+    })?;
+    Ok(format!("vars(__import__(\"builtins\")).pop(\"{}\")", name))
+}
+
+/// Python watchdog behind `RlmConfig::exec_timeout`
+///
+/// A timer thread raises `ExecutionTimeout` asynchronously in the thread
+/// running the block, then an uncatchable-by-`except Exception`
+/// `ExecutionTimeoutForced` every second if the code swallowed it. This
+/// stops pure-Python loops; a call blocked inside C code is interrupted once
+/// it returns to the interpreter.
+///
+/// The watchdog stays out of the REPL namespace, so model code can't disarm
+/// it or get at `ctypes` through it: [`WatchedRepl`] holds it for the
+/// embedded interpreter and the worker script keeps its own. The exception
+/// classes have no Python methods, leaving nothing to follow back from a
+/// caught timeout either.
+pub(crate) const WATCHDOG_CODE: &str = r#"
+def _rlm_make_watchdog():
+    import ctypes
+    import threading
+
+    class ExecutionTimeout(TimeoutError):
+        """Raised in a code block that ran past the execution timeout"""
+
+        __module__ = "builtins"
+
+    class ExecutionTimeoutForced(BaseException):
+        """Raised if the code swallowed ExecutionTimeout and kept running"""
+
+        __module__ = "builtins"
+
+    def raise_in(thread_id, exc):
+        ctypes.pythonapi.PyThreadState_SetAsyncExc(
+            ctypes.c_ulong(thread_id), None if exc is None else ctypes.py_object(exc)
+        )
+
+    class Watchdog:
+        """Raises ExecutionTimeout in the REPL thread when a block runs too long"""
+
+        def __init__(self):
+            self.lock = threading.Lock()
+            self.timer = None
+            self.armed = False
+            self.fired = False
+            self.thread_id = 0
+
+        def arm(self, seconds):
+            self.thread_id = threading.get_ident()
+            self.armed = True
+            self.fired = False
+            self.timer = threading.Timer(seconds, self._fire)
+            self.timer.daemon = True
+            self.timer.start()
+
+        def _fire(self):
+            with self.lock:
+                if self.armed:
+                    exc = ExecutionTimeoutForced if self.fired else ExecutionTimeout
+                    self.fired = True
+                    raise_in(self.thread_id, exc)
+                    # Fire again in case the code swallowed the exception
+                    self.timer = threading.Timer(1.0, self._fire)
+                    self.timer.daemon = True
+                    self.timer.start()
+
+        def disarm(self):
+            with self.lock:
+                self.armed = False
+                if self.timer is not None:
+                    self.timer.cancel()
+                # Drop a timeout that fired just as the block finished
+                raise_in(self.thread_id, None)
+
+    return Watchdog()
+"#;
+
+/// A [`WATCHDOG_CODE`] watchdog, made outside any REPL namespace
+fn make_watchdog(py: Python<'_>) -> Result<Bound<'_, PyAny>> {
+    let source = CString::new(WATCHDOG_CODE)
+        .map_err(|e| RlmError::Repl(format!("Watchdog source: {}", e)))?;
+    let scope = PyDict::new(py);
+    py.run(&source, Some(&scope), None)?;
+    let make = scope
+        .get_item("_rlm_make_watchdog")?
+        .ok_or_else(|| RlmError::Repl("Watchdog failed to load".to_string()))?;
+    Ok(make.call0()?)
+}
+
+/// Embedded REPL whose blocks are interrupted after `timeout`
+///
+/// Arms the watchdog around each block from Rust; model code never sees it.
+pub(crate) struct WatchedRepl {
+    repl: Box<dyn ReplEnvironment>,
+    watchdog: Py<PyAny>,
+    timeout: Duration,
+}
+
+impl WatchedRepl {
+    pub(crate) fn new(repl: Box<dyn ReplEnvironment>, timeout: Duration) -> Result<Self> {
+        let watchdog = Python::attach(|py| make_watchdog(py).map(Bound::unbind))?;
+        Ok(Self {
+            repl,
+            watchdog,
+            timeout,
+        })
+    }
+}
+
+impl ReplEnvironment for WatchedRepl {
+    fn execute(&mut self, code: &str) -> Result<ReplResult> {
+        let seconds = self.timeout.as_secs_f64();
+        Python::attach(|py| self.watchdog.call_method1(py, "arm", (seconds,)))?;
+        let result = self.repl.execute(code);
+        Python::attach(|py| self.watchdog.call_method0(py, "disarm"))?;
+        result
+    }
+
+    fn add_context(&mut self, name: &str, value: &str) -> Result<()> {
+        self.repl.add_context(name, value)
+    }
+
+    fn get_locals(&self) -> HashMap<String, String> {
+        self.repl.get_locals()
+    }
+}
+
+/// Activate the configured venv and verify required packages are importable
+///
+/// Called once at startup so missing packages surface as a readable error
+/// instead of an `ImportError` from inside model code mid-run. Only an
+/// explicitly configured venv that doesn't fit the interpreter is an error;
+/// an auto-detected one may belong to another project and is skipped.
+pub(crate) fn prepare_interpreter(env: &PythonEnv) -> Result<()> {
+    static SET_EXECUTABLE: Once = Once::new();
+    let mut venv = resolve_venv(env);
+
+    Python::attach(|py| -> Result<()> {
+        let sys = py.import("sys")?;
+        let version_info = sys.getattr("version_info")?;
+        let major: u8 = version_info.getattr("major")?.extract()?;
+        let minor: u8 = version_info.getattr("minor")?.extract()?;
+
+        if let Some(ref dir) = venv {
+            let site_packages = site_packages_dir(dir, major, minor);
+            if !site_packages.is_dir() {
+                let problem = format!(
+                    "Python venv '{}' has no site-packages for Python {}.{} (expected {})",
+                    dir.display(),
+                    major,
+                    minor,
+                    site_packages.display()
+                );
+                if env.venv.is_some() {
+                    return Err(RlmError::Config(problem));
+                }
+                tracing::warn!("{}; not using it", problem);
+                venv = None;
+            } else {
+                py.import("site")?
+                    .call_method1("addsitedir", (site_packages.to_string_lossy().to_string(),))?;
+            }
+        }
+
+        // Subprocesses spawned from model code should use the venv too;
+        // `sys.executable` is process-wide, so it is set for the first one
+        if let Some(ref explicit) = env.venv {
+            let executable = venv_executable(explicit);
+            if executable.exists() {
+                let mut set = Ok(());
+                SET_EXECUTABLE.call_once(|| {
+                    set = sys.setattr("executable", executable.to_string_lossy().to_string());
+                });
+                set?;
+            }
+        }
+
+        let util = py.import("importlib.util")?;
+        let missing: Vec<String> = env
+            .required_packages
+            .iter()
+            .filter(|pkg| {
+                // find_spec raises for dotted names whose parent is missing
+                util.call_method1("find_spec", (pkg.as_str(),))
+                    .map(|spec| spec.is_none())
+                    .unwrap_or(true)
+            })
+            .cloned()
+            .collect();
+
+        if !missing.is_empty() {
+            let interpreter = format!(
+                "Python {}.{}{}",
+                major,
+                minor,
+                venv.as_ref()
+                    .map(|v| format!(", venv {}", v.display()))
+                    .unwrap_or_default()
+            );
+            return Err(RlmError::MissingPythonPackages {
+                missing,
+                interpreter,
+            });
+        }
+
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_venv_explicit_wins() {
+        let env = PythonEnv {
+            venv: Some(PathBuf::from("/opt/venvs/rlm")),
+            ..Default::default()
+        };
+        assert_eq!(resolve_venv(&env), Some(PathBuf::from("/opt/venvs/rlm")));
+    }
+
+    #[test]
+    fn test_resolve_venv_disabled() {
+        let env = PythonEnv {
+            auto_detect: false,
+            ..Default::default()
+        };
+        assert_eq!(resolve_venv(&env), None);
+    }
+
+    #[test]
+    fn test_explicit_venv_must_fit() {
+        let dir = std::env::temp_dir().join(format!("rlm-venv-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let env = PythonEnv {
+            venv: Some(dir.clone()),
+            ..Default::default()
+        };
+        let result = prepare_interpreter(&env);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(result, Err(RlmError::Config(_))));
+    }
+
+    #[test]
+    fn test_stream_hook() {
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(String::new()));
+        let sink = seen.clone();
+        let expr = stream_hook(std::sync::Arc::new(move |text: &str| {
+            sink.lock().unwrap().push_str(text)
+        }))
+        .unwrap();
+
+        Python::attach(|py| {
+            let code = std::ffi::CString::new(format!("({})('hi')", expr)).unwrap();
+            py.eval(&code, None, None).unwrap();
+            // Taken off builtins by the first lookup
+            assert!(py.eval(&code, None, None).is_err());
+        });
+        assert_eq!(*seen.lock().unwrap(), "hi");
+    }
+
+    #[test]
+    fn test_watchdog_interrupts_embedded_code() {
+        Python::attach(|py| {
+            let watchdog = make_watchdog(py).unwrap();
+            watchdog.call_method1("arm", (0.2,)).unwrap();
+            let error = py.run(c"while True:\n    pass", None, None).unwrap_err();
+            watchdog.call_method0("disarm").unwrap();
+            assert_eq!(error.get_type(py).name().unwrap(), "ExecutionTimeout");
+            assert!(error.is_instance_of::<pyo3::exceptions::PyTimeoutError>(py));
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_site_packages_dir() {
+        assert_eq!(
+            site_packages_dir(Path::new("/venv"), 3, 11),
+            PathBuf::from("/venv/lib/python3.11/site-packages")
+        );
+    }
+}
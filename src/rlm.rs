@@ -1,51 +1,157 @@
-use anthropic_sdk::{Anthropic, ContentBlock, MessageCreateBuilder};
-use async_openai::{
-    config::OpenAIConfig,
-    types::{
-        ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestMessage,
-        ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestUserMessageArgs,
-        CreateChatCompletionRequestArgs,
-    },
-    Client as OpenAIClient,
-};
-use std::io::{self, Write};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Instant;
-use tokio::runtime::Runtime;
+use std::time::{Duration, Instant};
 
+use crate::answer::{AnswerContext, AnswerFormat};
+use crate::approval::CodeApproval;
+use crate::backend::{create_backend, create_sub_backend, ChatBackend, ChatParams};
+use crate::cache::{CacheStats, QueryCache};
 use crate::env::{execute_with_error_handling, LlmQueryFn, PyO3Repl, ReplEnvironment};
 use crate::error::{Result, RlmError};
-use crate::parsing::{extract_answer, extract_code_blocks, extract_final_answer_from_stdout};
-use crate::prompts::{build_continue_prompt, build_initial_user_prompt, build_system_prompt};
+#[cfg(feature = "javascript")]
+use crate::js::JsRepl;
+use crate::lint::lint_python;
+#[cfg(feature = "lua")]
+use crate::lua::LuaRepl;
+use crate::parsing::{parse_json_answer, sanitize_final_answer, ParserConfig};
+use crate::patch::{apply_patch, extract_diff_blocks};
+use crate::prompts::{
+    attachment_functions, build_continue_prompt, build_fix_prompt, build_initial_user_prompt,
+    build_system_prompt, code_denied_message, code_edited_message, lint_denial_message,
+    package_functions, sub_call_budget_exhausted_message, sub_call_iteration_limit_message,
+    suggested_chunk_size, workspace_functions, IMAGE_QUERY_FUNCTION, SESSION_TURN_NOTE,
+    TEXT_HELPER_FUNCTIONS,
+};
+use crate::python::{prepare_interpreter, stream_hook, worker_interpreter, WatchedRepl};
+use crate::ratelimit::RateLimitedBackend;
+#[cfg(feature = "telemetry")]
+use crate::telemetry::{RunRecord, Telemetry};
+use crate::tokens::{count_message_tokens, count_tokens};
 use crate::types::{
-    Backend, CodeBlock, Message, PromptInput, ReplResult, RlmCompletion, RlmConfig, RlmIteration,
-    Role, Usage,
+    Attachment, BatchCompletion, CodeBlock, CompletionStatus, FixContext, Image, LintAction,
+    LintFinding, Message, OutputTruncation, PartialRun, PromptInput, PythonEnv, QueryOverrides,
+    ReplLang, ReplMode, ReplResult, RlmCompletion, RlmConfig, RlmIteration, Role, SandboxPolicy,
+    TaskMode, Usage, Workspace,
 };
+use crate::worker::{WorkerOptions, WorkerPool, WorkerRepl};
 
-/// LLM client abstraction
-enum LlmClient {
-    OpenAI(OpenAIClient<OpenAIConfig>),
-    Anthropic(Anthropic),
+/// Format one line and send it to the configured log sink
+macro_rules! log_line {
+    ($rlm:expr, $($arg:tt)*) => {
+        $rlm.config.log_sink.log(&format!($($arg)*))
+    };
 }
 
-/// Truncate response after first ```repl``` or ```python``` block ends
-/// Discards everything after the closing ``` to force step-by-step evaluation
-fn truncate_after_first_repl_block(text: &str) -> String {
-    // Find start of first repl/python block
-    let block_start = text.find("```repl\n").or_else(|| text.find("```python\n"));
+/// Call the backend, retrying calls that hit a timeout
+///
+/// A hung connection is dropped by the backend's watchdog; the retry opens a
+/// fresh one. Other errors are returned immediately.
+fn chat_with_retry(
+    backend: &dyn ChatBackend,
+    messages: &[Message],
+    params: &ChatParams,
+    max_retries: u32,
+) -> Result<(String, Usage)> {
+    let mut attempt = 0;
+    loop {
+        match backend.chat(messages, params) {
+            Err(RlmError::BackendTimeout(_)) if attempt < max_retries => attempt += 1,
+            result => return result,
+        }
+    }
+}
 
-    let Some(start) = block_start else {
-        return text.to_string(); // No block, return as-is
+/// Sub-call parameters with the overrides model code passed applied
+fn query_params(base: &ChatParams, overrides: &QueryOverrides) -> ChatParams {
+    let mut params = base.clone();
+    if let Some(ref model) = overrides.model {
+        params.model = model.clone();
+    }
+    if let Some(temperature) = overrides.temperature {
+        params.temperature = temperature;
+    }
+    if overrides.max_tokens.is_some() {
+        params.max_tokens = overrides.max_tokens;
+    }
+    params
+}
+
+/// Run one code block in a REPL whose watchdog stops blocks after
+/// `timeout` (None = no watchdog), telling the model why a block was stopped
+fn execute_watched(
+    repl: &mut dyn ReplEnvironment,
+    code: &str,
+    timeout: Option<Duration>,
+) -> Result<ReplResult> {
+    let Some(timeout) = timeout else {
+        return execute_with_error_handling(repl, code);
+    };
+    let started = Instant::now();
+    let mut result = match execute_with_error_handling(repl, code) {
+        // ExecutionTimeoutForced is a BaseException and may escape the
+        // REPL's error handling; report it to the model all the same
+        Err(e) if is_exec_timeout(&e.to_string()) => {
+            ReplResult::failure(e.to_string(), String::new(), started.elapsed())
+        }
+        other => other?,
     };
+    if let Some(error) = result.error.as_mut().filter(|e| is_exec_timeout(e)) {
+        error.push_str(&format!(
+            "\nThe code block exceeded the {}s execution timeout; split the work into \
+             smaller steps.",
+            timeout.as_secs_f64()
+        ));
+    }
+    Ok(result)
+}
 
-    // Find the closing ``` after the block start
-    let after_marker = start + 8; // skip past "```repl\n" or "```python"
-    if let Some(end_offset) = text[after_marker..].find("\n```") {
-        let end = after_marker + end_offset + 4; // include the closing ```
-        text[..end].to_string()
-    } else {
-        text.to_string() // No closing, return as-is
+/// Whether a Python error is one the watchdog raised
+fn is_exec_timeout(error: &str) -> bool {
+    error.lines().any(|line| {
+        let name = line.split(':').next().unwrap_or_default().trim();
+        let name = name.rsplit('.').next().unwrap_or(name);
+        name == "ExecutionTimeout" || name == "ExecutionTimeoutForced"
+    })
+}
+
+/// Cap REPL output at `max` characters, marking how much was dropped
+fn truncate_output(text: &str, max: usize, mode: OutputTruncation) -> String {
+    let total = text.chars().count();
+    if total <= max {
+        return text.to_string();
     }
+    let dropped = total - max;
+    match mode {
+        OutputTruncation::Head => {
+            let head: String = text.chars().take(max).collect();
+            format!("{}\n[truncated {} chars]", head, dropped)
+        }
+        OutputTruncation::HeadAndTail => {
+            let head_len = max / 2;
+            let head: String = text.chars().take(head_len).collect();
+            let tail: String = text.chars().skip(total - (max - head_len)).collect();
+            format!("{}\n[truncated {} chars]\n{}", head, dropped, tail)
+        }
+    }
+}
+
+/// Truncate response after first ```repl``` or ```python``` block ends
+/// Discards everything after the closing ``` to force step-by-step evaluation;
+/// with `keep_prose`, only the code blocks after the first one go
+fn truncate_after_first_repl_block(text: &str, parser: &ParserConfig, keep_prose: bool) -> String {
+    let blocks = parser.find_code_blocks(text);
+    let end = match (blocks.first(), blocks.get(1)) {
+        // No complete block, return as-is
+        (None, _) => return text.to_string(),
+        (Some(first), _) if !keep_prose => first.span.end,
+        (Some(_), Some(second)) => second.span.start,
+        (Some(_), None) => text.len(),
+    };
+    text[..end].trim_end().to_string()
 }
 
 /// Format code execution result for history - simple REPL-style output
@@ -70,11 +176,618 @@ fn format_execution_result(result: &ReplResult) -> String {
     }
 }
 
+/// Follow-up sent when a root response ran into `max_tokens`
+const CONTINUE_TRUNCATED_PROMPT: &str = "Your previous message was cut off by the output \
+    token limit. Continue exactly where it stopped, without repeating anything or adding \
+    any preamble.";
+
+/// Cargo feature a REPL language needs, if it isn't compiled in
+fn missing_feature(language: ReplLang) -> Option<&'static str> {
+    match language {
+        ReplLang::Python => None,
+        ReplLang::JavaScript => (!cfg!(feature = "javascript")).then_some("javascript"),
+        ReplLang::Lua => (!cfg!(feature = "lua")).then_some("lua"),
+    }
+}
+
+/// Give a fresh REPL `context` and run the setup code in it
+fn init_repl<R: ReplEnvironment + 'static>(
+    mut repl: R,
+    context_payload: &str,
+    setup: &[String],
+) -> Result<Box<dyn ReplEnvironment>> {
+    // Add context variable to REPL - this is the DATA to analyze, not instructions
+    repl.add_context("context", context_payload)?;
+    for code in setup {
+        execute_with_error_handling(&mut repl, code)?;
+    }
+    Ok(Box::new(repl))
+}
+
+/// Extra time a worker gets past `exec_timeout` before it is killed
+const WORKER_KILL_GRACE: Duration = Duration::from_secs(5);
+
+/// Python installing a [`SandboxPolicy`] in the REPL namespace
+///
+/// REPL code gets its own `__builtins__` with blocked builtins replaced and
+/// a guarded `__import__`; the interpreter-wide `builtins` module is left
+/// alone, so library code imported by the model still works.
+const SANDBOX_CODE: &str = r#"
+def _rlm_install_sandbox(ns, blocked_builtins, blocked_modules):
+    import builtins
+
+    # Build on the REPL's current builtins so an import allowlist stays in force
+    current = ns.get("__builtins__", builtins)
+    safe = dict(current if isinstance(current, dict) else vars(current))
+    real_import = safe["__import__"]
+
+    def is_blocked(name):
+        parts = name.split(".")
+        return any(".".join(parts[:i]) in blocked_modules for i in range(1, len(parts) + 1))
+
+    def guarded_import(name, globals=None, locals=None, fromlist=(), level=0):
+        if level == 0 and is_blocked(name):
+            raise ImportError(f"import of '{name}' is blocked by the sandbox policy")
+        return real_import(name, globals, locals, fromlist, level)
+
+    def blocked(name):
+        def call(*args, **kwargs):
+            raise PermissionError(f"{name}() is disabled by the sandbox policy")
+        return call
+
+    for name in blocked_builtins:
+        if name in safe:
+            safe[name] = blocked(name)
+    safe["__import__"] = guarded_import
+    # Drop blocked modules imported before the sandbox went up, the REPL's
+    # own _rlm_ helpers' included
+    for name, value in list(ns.items()):
+        if type(value) is type(builtins) and is_blocked(value.__name__):
+            del ns[name]
+    ns["__builtins__"] = safe
+"#;
+
+fn sandbox_code(policy: &SandboxPolicy) -> String {
+    let list = |names: &[String]| serde_json::to_string(names).unwrap_or_else(|_| "[]".into());
+    format!(
+        "{}\n_rlm_install_sandbox(globals(), set({}), set({}))\ndel _rlm_install_sandbox\n",
+        SANDBOX_CODE,
+        list(&policy.blocked_builtins),
+        list(&policy.blocked_modules)
+    )
+}
+
+/// Python installing the import allowlist and `pip_install` in the REPL
+///
+/// Like the sandbox, only imports written in REPL code are checked; packages
+/// import their own dependencies as usual. Installs go into a separate venv
+/// created with an interpreter of the REPL's own Python version, whose
+/// site-packages is then added to `sys.path`.
+const PACKAGES_CODE: &str = r#"
+def _rlm_install_packages(ns, allowed, installable, venv):
+    import builtins, importlib, os, re, shutil, site, subprocess, sys
+
+    stdlib = set(getattr(sys, "stdlib_module_names", ())) | set(sys.builtin_module_names)
+    version = f"{sys.version_info[0]}.{sys.version_info[1]}"
+
+    def key(spec):
+        name = re.match(r"[A-Za-z0-9._-]*", spec.strip()).group(0)
+        return re.sub(r"[-_.]+", "-", name).lower()
+
+    specs = {key(spec): spec for spec in installable}
+    hint = f"; pip_install() can add: {', '.join(sorted(specs))}" if specs else ""
+
+    current = ns.get("__builtins__", builtins)
+    safe = dict(current if isinstance(current, dict) else vars(current))
+    real_import = safe["__import__"]
+
+    def guarded_import(name, globals=None, locals=None, fromlist=(), level=0):
+        top = name.split(".")[0]
+        if level == 0 and allowed is not None and top not in stdlib and top not in allowed:
+            permitted = ", ".join(sorted(allowed)) or "none"
+            raise ImportError(
+                f"package '{top}' is not available in this REPL "
+                f"(standard library plus: {permitted}){hint}"
+            )
+        try:
+            return real_import(name, globals, locals, fromlist, level)
+        except ModuleNotFoundError as e:
+            if level == 0 and e.name == top and specs:
+                raise ModuleNotFoundError(f"No module named '{top}'{hint}", name=top) from None
+            raise
+
+    if os.name == "nt":
+        python = os.path.join(venv, "Scripts", "python.exe")
+        site_packages = os.path.join(venv, "Lib", "site-packages")
+    else:
+        python = os.path.join(venv, "bin", "python")
+        site_packages = os.path.join(venv, "lib", "python" + version, "site-packages")
+
+    def add_venv():
+        if os.path.isdir(site_packages) and site_packages not in sys.path:
+            site.addsitedir(site_packages)
+        importlib.invalidate_caches()
+
+    def create_venv():
+        # sys.executable may be the host binary when the interpreter is embedded
+        candidates = [
+            os.path.join(sys.base_prefix, "bin", "python" + version),
+            os.path.join(sys.base_prefix, "python.exe"),
+            shutil.which("python" + version),
+            sys.executable,
+        ]
+        base = next((c for c in candidates if c and os.path.isfile(c)), None)
+        if base is None:
+            raise RuntimeError(f"pip_install: no Python {version} interpreter found to create {venv}")
+        done = subprocess.run([base, "-m", "venv", venv], capture_output=True, text=True)
+        if done.returncode != 0:
+            raise RuntimeError(f"pip_install: creating {venv} failed:\n{done.stderr.strip()[-2000:]}")
+
+    def pip_install(*packages):
+        """Install allowlisted packages into the REPL's private virtualenv"""
+        unknown = [p for p in packages if key(p) not in specs]
+        if unknown:
+            raise PermissionError(
+                f"pip_install: {', '.join(unknown)} not allowed; "
+                f"installable: {', '.join(sorted(specs)) or 'none'}"
+            )
+        if not os.path.exists(python):
+            create_venv()
+        args = [python, "-m", "pip", "install", "--quiet", "--disable-pip-version-check"]
+        done = subprocess.run(args + [specs[key(p)] for p in packages], capture_output=True, text=True)
+        if done.returncode != 0:
+            raise RuntimeError(f"pip_install failed:\n{done.stderr.strip()[-2000:]}")
+        add_venv()
+        print("Installed: " + ", ".join(packages))
+
+    add_venv()
+    safe["__import__"] = guarded_import
+    ns["__builtins__"] = safe
+    if specs:
+        ns["pip_install"] = pip_install
+"#;
+
+/// Import allowlist and `pip_install` setup, if either is configured
+fn packages_code(env: &PythonEnv) -> Option<String> {
+    if env.allowed_imports.is_none() && env.installable.is_empty() {
+        return None;
+    }
+    let allowed = match env.allowed_imports {
+        Some(ref names) => format!("set({})", serde_json::to_string(names).ok()?),
+        None => "None".to_string(),
+    };
+    let venv = env
+        .install_venv
+        .clone()
+        .unwrap_or_else(|| std::env::temp_dir().join(format!("rlm-pip-{}", std::process::id())));
+    Some(format!(
+        "{}\n_rlm_install_packages(globals(), {}, {}, {})\ndel _rlm_install_packages\n",
+        PACKAGES_CODE,
+        allowed,
+        serde_json::to_string(&env.installable).ok()?,
+        serde_json::to_string(&venv.to_string_lossy()).ok()?
+    ))
+}
+
+/// Python defining the workspace file helpers
+///
+/// `open` and `os` are bound when the helpers are defined, so they keep
+/// working under a sandbox that blocks both for model code.
+const WORKSPACE_CODE: &str = r#"
+def _rlm_install_workspace(ns, root, read_only):
+    import io, os
+
+    root = os.path.realpath(root)
+
+    def resolve(path):
+        full = os.path.realpath(os.path.join(root, os.fspath(path)))
+        if full != root and not full.startswith(root + os.sep):
+            raise PermissionError(f"{path!r} is outside the workspace")
+        return full
+
+    def read_file(path, encoding="utf-8"):
+        """Read a text file from the workspace"""
+        with io.open(resolve(path), encoding=encoding, errors="replace") as f:
+            return f.read()
+
+    def write_file(path, text, encoding="utf-8"):
+        """Write a text file in the workspace, creating parent directories"""
+        full = resolve(path)
+        os.makedirs(os.path.dirname(full), exist_ok=True)
+        with io.open(full, "w", encoding=encoding) as f:
+            return f.write(str(text))
+
+    def list_dir(path="."):
+        """Entries of a workspace directory; subdirectories end in '/'"""
+        full = resolve(path)
+        return sorted(
+            name + "/" if os.path.isdir(os.path.join(full, name)) else name
+            for name in os.listdir(full)
+        )
+
+    ns["read_file"] = read_file
+    ns["list_dir"] = list_dir
+    if not read_only:
+        ns["write_file"] = write_file
+"#;
+
+fn workspace_code(workspace: &Workspace) -> String {
+    format!(
+        "{}\n_rlm_install_workspace(globals(), {}, {})\ndel _rlm_install_workspace\n",
+        WORKSPACE_CODE,
+        serde_json::to_string(&workspace.root.to_string_lossy()).unwrap_or_default(),
+        if workspace.read_only { "True" } else { "False" }
+    )
+}
+
+/// Python wrapping `llm_query` to take per-call overrides as keywords
+const QUERY_OVERRIDES_CODE: &str = r#"
+def _rlm_install_query_overrides(ns):
+    import base64, json
+
+    base = ns["llm_query"]
+
+    def media_type(data):
+        if data.startswith(b"\x89PNG\r\n\x1a\n"):
+            return "image/png"
+        if data.startswith(b"\xff\xd8\xff"):
+            return "image/jpeg"
+        if data.startswith(b"GIF8"):
+            return "image/gif"
+        if data[:4] == b"RIFF" and data[8:12] == b"WEBP":
+            return "image/webp"
+        raise ValueError("llm_query: images must be PNG, JPEG, GIF, or WebP bytes")
+
+    def llm_query(prompt, model=None, temperature=None, max_tokens=None, images=None):
+        options = {}
+        if model is not None:
+            options["model"] = str(model)
+        if temperature is not None:
+            options["temperature"] = float(temperature)
+        if max_tokens is not None:
+            options["max_tokens"] = int(max_tokens)
+        if images:
+            options["images"] = [
+                {"media_type": media_type(bytes(data)),
+                 "data": base64.b64encode(bytes(data)).decode()}
+                for data in images
+            ]
+        if options:
+            prompt = "\x00rlm-query:" + json.dumps(options) + "\x00" + str(prompt)
+        return base(prompt)
+
+    ns["llm_query"] = llm_query
+
+_rlm_install_query_overrides(globals())
+del _rlm_install_query_overrides
+"#;
+
+/// Python defining the text-processing helpers
+///
+/// `re` and `json` are bound when the helpers are defined, so they keep
+/// working under an import allowlist or sandbox.
+const TEXT_HELPERS_CODE: &str = r#"
+def _rlm_install_text_helpers(ns):
+    import json, re
+
+    def chunk_text(text, size=None, overlap=0):
+        """Split text into chunks of at most `size` characters, preferring
+        to break at a paragraph, line, or word boundary"""
+        text = str(text)
+        size = int(size or ns.get("CHUNK_SUGGESTED_SIZE") or 4000)
+        overlap = max(0, min(int(overlap), size - 1))
+        chunks, start = [], 0
+        while start < len(text):
+            end = min(start + size, len(text))
+            if end < len(text):
+                window = text[start:end]
+                for sep in ("\n\n", "\n", " "):
+                    cut = window.rfind(sep)
+                    if cut > size // 2:
+                        end = start + cut + len(sep)
+                        break
+            chunks.append(text[start:end])
+            if end >= len(text):
+                break
+            start = max(end - overlap, start + 1)
+        return chunks
+
+    def count_tokens(text):
+        """Estimate the token count of text (about 4 characters per token)"""
+        return (len(str(text)) + 3) // 4
+
+    def grep(pattern, text, context=0, ignore_case=False):
+        """Lines of text matching the regex, as 'lineno: line' strings;
+        `context` adds that many surrounding lines"""
+        regex = re.compile(pattern, re.IGNORECASE if ignore_case else 0)
+        lines = str(text).splitlines()
+        keep = set()
+        for i, line in enumerate(lines):
+            if regex.search(line):
+                keep.update(range(max(0, i - context), min(len(lines), i + context + 1)))
+        return [f"{i + 1}: {lines[i]}" for i in sorted(keep)]
+
+    def parse_json(text):
+        """Parse JSON from text, ignoring code fences and surrounding prose"""
+        text = str(text).strip()
+        fenced = re.search(r"```(?:json)?\s*\n(.*?)```", text, re.DOTALL)
+        if fenced:
+            text = fenced.group(1).strip()
+        try:
+            return json.loads(text)
+        except ValueError:
+            pass
+        decoder = json.JSONDecoder()
+        for match in re.finditer(r"[\[{]", text):
+            try:
+                return decoder.raw_decode(text, match.start())[0]
+            except ValueError:
+                continue
+        raise ValueError(f"no JSON found in: {text[:200]!r}")
+
+    ns["chunk_text"] = chunk_text
+    ns["count_tokens"] = count_tokens
+    ns["grep"] = grep
+    ns["parse_json"] = parse_json
+
+_rlm_install_text_helpers(globals())
+del _rlm_install_text_helpers
+"#;
+
+/// Python replacing `print` in the REPL namespace with one that also passes
+/// each printed chunk to `emit`
+const STREAM_CODE: &str = r#"
+def _rlm_install_stream(ns, emit):
+    import builtins, io, sys
+
+    real_print = builtins.print
+
+    def print(*args, sep=" ", end="\n", file=None, flush=False):
+        text = io.StringIO()
+        real_print(*args, sep=sep, end=end, file=text)
+        real_print(text.getvalue(), end="", file=file, flush=flush)
+        if file is None or file is sys.stdout:
+            emit(text.getvalue())
+
+    ns["print"] = print
+"#;
+
+fn stream_code(emit: &str) -> String {
+    format!(
+        "{}\n_rlm_install_stream(globals(), {})\ndel _rlm_install_stream\n",
+        STREAM_CODE, emit
+    )
+}
+
+/// Python defining `attachments`, decoded from base64
+const ATTACHMENTS_CODE: &str = r#"
+def _rlm_install_attachments(ns, encoded):
+    import base64
+
+    ns["attachments"] = {name: base64.b64decode(data) for name, data in encoded.items()}
+"#;
+
+/// Setup code carrying the attachments themselves, so a restarted worker
+/// gets them back too
+fn attachments_code(attachments: &[Attachment]) -> String {
+    let encoded: BTreeMap<&str, String> = attachments
+        .iter()
+        .map(|a| (a.name.as_str(), BASE64.encode(&a.data)))
+        .collect();
+    format!(
+        "{}\n_rlm_install_attachments(globals(), {})\ndel _rlm_install_attachments\n",
+        ATTACHMENTS_CODE,
+        serde_json::to_string(&encoded).unwrap_or_default()
+    )
+}
+
+/// Python snippet defining the context metadata constants in the REPL
+///
+/// These are referenced by the system prompt so model code doesn't have to
+/// recompute `len(context)` or guess a chunk size.
+fn context_metadata_code(context: &str) -> String {
+    let digest: String = Sha256::digest(context.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let len = context.chars().count();
+    format!(
+        "CONTEXT_LEN = {}\nCONTEXT_SHA256 = \"{}\"\nCHUNK_SUGGESTED_SIZE = {}\n",
+        len,
+        digest,
+        suggested_chunk_size(len)
+    )
+}
+
+/// What a prompt puts into `context`: its text, or the user messages
+fn prompt_payload(prompt: &PromptInput) -> String {
+    match prompt {
+        PromptInput::Text(s) | PromptInput::Multimodal { text: s, .. } => s.clone(),
+        PromptInput::Messages(msgs) => msgs
+            .iter()
+            .filter(|m| m.role == Role::User)
+            .map(|m| m.content.as_str())
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+/// Clean up the final answer according to the task mode
+///
+/// Completion answers are appended to the context, so they are kept verbatim.
+/// Answers drop surrounding whitespace and a leading role/answer label;
+/// transforms additionally unwrap a single enclosing code fence.
+fn finalize_answer(answer: String, task_mode: TaskMode) -> String {
+    match task_mode {
+        TaskMode::Completion => answer,
+        TaskMode::Answer => {
+            let trimmed = answer.trim();
+            ["Assistant:", "Answer:"]
+                .iter()
+                .find_map(|label| trimmed.strip_prefix(label))
+                .unwrap_or(trimmed)
+                .trim()
+                .to_string()
+        }
+        TaskMode::Transform => {
+            let trimmed = answer.trim();
+            if trimmed.starts_with("```") && trimmed.ends_with("```") && trimmed.len() > 6 {
+                let inner = &trimmed[3..trimmed.len() - 3];
+                // Drop the language tag line, if any
+                match inner.split_once('\n') {
+                    Some((_, body)) => body.trim_end().to_string(),
+                    None => inner.trim().to_string(),
+                }
+            } else {
+                trimmed.to_string()
+            }
+        }
+    }
+}
+
+/// Creates the REPL for each run
+///
+/// Implement this to run model code somewhere else (remote executor, Docker
+/// runner, custom DSL) while reusing the orchestration loop. `llm_query`
+/// calls from model code must go through `query_fn` so they share the
+/// run's budget, cache, and usage tracking. The engine then sets `context`
+/// and the `CONTEXT_*` constants via plain assignments; timeouts and
+/// sandboxing are up to the environment.
+pub trait ReplFactory: Send + Sync {
+    fn create(&self, query_fn: LlmQueryFn) -> Result<Box<dyn ReplEnvironment>>;
+}
+
+impl<F> ReplFactory for F
+where
+    F: Fn(LlmQueryFn) -> Result<Box<dyn ReplEnvironment>> + Send + Sync,
+{
+    fn create(&self, query_fn: LlmQueryFn) -> Result<Box<dyn ReplEnvironment>> {
+        self(query_fn)
+    }
+}
+
+/// A REPL outside the iteration loop, for hosts running code themselves
+///
+/// Made by [`Rlm::repl_session`] with the sandbox, workspace helpers,
+/// packages, and `exec_timeout` of a run; `context` is empty and
+/// `llm_query` calls the `Rlm`'s backend without the run's quotas or cache.
+/// The safety linter and approval hook are left to the host. Variables
+/// persist from one [`ReplSession::execute`] to the next.
+pub struct ReplSession {
+    repl: Box<dyn ReplEnvironment>,
+    timeout: Option<Duration>,
+}
+
+impl ReplSession {
+    /// Run code, interrupting it after the configured `exec_timeout`
+    pub fn execute(&mut self, code: &str) -> Result<ReplResult> {
+        execute_watched(self.repl.as_mut(), code, self.timeout)
+    }
+}
+
+/// A REPL and conversation kept across completions
+///
+/// Made by [`Rlm::session`]; [`Rlm::completion_in_session`] runs each turn
+/// in the same REPL, so variables model code set in earlier turns are
+/// still defined, and `context` holds the earlier requests and answers
+/// followed by the new request. `llm_query` calls and output go to the
+/// turn that is running and count against its quotas.
+pub struct RlmSession {
+    repl: Box<dyn ReplEnvironment>,
+    /// `llm_query` of the current turn
+    query_fn: Arc<Mutex<Option<LlmQueryFn>>>,
+    /// Live output of the current turn
+    output: Arc<Mutex<Option<OutputFn>>>,
+    turns: Vec<(String, String)>,
+}
+
+impl RlmSession {
+    /// Completed turns as (request, answer)
+    pub fn turns(&self) -> &[(String, String)] {
+        &self.turns
+    }
+
+    /// `context` for a turn: the earlier turns, then the new request
+    fn context(&self, request: &str) -> String {
+        let mut context = String::new();
+        for (earlier, answer) in &self.turns {
+            context.push_str(&format!(
+                "EARLIER REQUEST:\n{}\n\nYOUR ANSWER:\n{}\n\n",
+                earlier, answer
+            ));
+        }
+        if !context.is_empty() {
+            context.push_str("NEW REQUEST:\n");
+        }
+        context.push_str(request);
+        context
+    }
+}
+
+/// Receives REPL output while a code block is still running
+pub type OutputFn = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Progress of a run as it happens (see [`Rlm::with_event_stream`])
+///
+/// Iterations count from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RlmEvent {
+    /// The model is asked for the next step
+    IterationStarted {
+        iteration: u32,
+        max_iterations: u32,
+    },
+    /// The iteration's code block is about to run
+    CodeStarted {
+        iteration: u32,
+        code: String,
+    },
+    /// The code block ran, retries included
+    CodeFinished {
+        iteration: u32,
+        success: bool,
+        error: Option<String>,
+    },
+    /// Model code called `llm_query`; a `cached` one never reached the backend
+    SubQuery {
+        cached: bool,
+    },
+    IterationFinished {
+        iteration: u32,
+        duration: Duration,
+    },
+}
+
+/// Receives the [`RlmEvent`]s of a run
+pub type EventFn = Arc<dyn Fn(&RlmEvent) + Send + Sync>;
+
+/// Decides whether a block flagged under [`LintAction::Ask`] may run
+pub type LintConfirmFn = Arc<dyn Fn(&str, &[LintFinding]) -> bool + Send + Sync>;
+
 /// Main RLM orchestrator
+///
+/// `Rlm` is `Send + Sync`: completions may run concurrently from several
+/// threads, each with its own REPL (see the crate docs on concurrency).
 pub struct Rlm {
     config: RlmConfig,
-    client: LlmClient,
-    runtime: Runtime,
+    backend: Arc<dyn ChatBackend>,
+    /// Answers `llm_query` sub-calls; the root backend unless
+    /// `config.sub_backend` is set
+    sub_backend: Arc<dyn ChatBackend>,
+    /// Shared across runs so repeated completions reuse sub-query responses
+    query_cache: Option<Arc<QueryCache>>,
+    /// User-supplied REPL replacing the built-in ones
+    repl_factory: Option<Arc<dyn ReplFactory>>,
+    /// Live REPL output
+    output_stream: Option<OutputFn>,
+    /// Live run progress
+    event_stream: Option<EventFn>,
+    /// Approves blocks the safety linter asks about
+    lint_confirm: Option<LintConfirmFn>,
+    /// Started worker processes for `ReplMode::Worker`
+    worker_pool: Option<Arc<WorkerPool>>,
+    #[cfg(feature = "telemetry")]
+    telemetry: Option<Arc<Telemetry>>,
 }
 
 impl Rlm {
@@ -82,43 +795,127 @@ impl Rlm {
     ///
     /// Uses config.backend, config.base_url, and config.api_key to configure the client.
     /// Falls back to environment variables (OPENAI_API_KEY, ANTHROPIC_API_KEY) if no key provided.
+    /// Activates the configured Python venv and fails early if required packages are missing.
     pub fn new(config: RlmConfig) -> Result<Self> {
-        let runtime = Runtime::new()?;
-        let client = Self::create_client(&config)?;
+        let backend = create_backend(&config)?;
+        Self::with_chat_backend(config, backend)
+    }
+
+    /// Create with a custom chat backend, ignoring config.backend
+    pub fn with_chat_backend(config: RlmConfig, backend: Arc<dyn ChatBackend>) -> Result<Self> {
+        if let Some(feature) = missing_feature(config.repl_language) {
+            return Err(RlmError::Config(format!(
+                "ReplLang::{:?} requires the `{}` feature",
+                config.repl_language, feature
+            )));
+        }
+        if config.repl_language == ReplLang::Python {
+            prepare_interpreter(&config.python)?;
+        } else if config.repl_mode == ReplMode::Worker {
+            return Err(RlmError::Config(
+                "ReplMode::Worker is only available for Python".to_string(),
+            ));
+        }
+        config.answer_format.check_schema()?;
+        let backend: Arc<dyn ChatBackend> = if config.rate_limit.is_limited() {
+            Arc::new(RateLimitedBackend::new(backend, config.rate_limit))
+        } else {
+            backend
+        };
+        // A separate sub-call provider gets its own throttle
+        let sub_backend: Arc<dyn ChatBackend> = match create_sub_backend(&config)? {
+            Some(sub) if config.rate_limit.is_limited() => {
+                Arc::new(RateLimitedBackend::new(sub, config.rate_limit))
+            }
+            Some(sub) => sub,
+            None => backend.clone(),
+        };
+        let query_cache = match config.query_cache {
+            Some(ref cache) => Some(Arc::new(match cache.path {
+                Some(ref path) => QueryCache::persistent(cache.capacity, path)?,
+                None => QueryCache::in_memory(cache.capacity),
+            })),
+            None => None,
+        };
         Ok(Self {
             config,
-            client,
-            runtime,
+            backend,
+            sub_backend,
+            query_cache,
+            repl_factory: None,
+            output_stream: None,
+            event_stream: None,
+            lint_confirm: None,
+            worker_pool: None,
+            #[cfg(feature = "telemetry")]
+            telemetry: None,
         })
     }
 
-    /// Create the appropriate LLM client based on config
-    fn create_client(config: &RlmConfig) -> Result<LlmClient> {
-        match config.backend {
-            Backend::OpenAI => {
-                let mut openai_config = OpenAIConfig::new();
-                if let Some(ref url) = config.base_url {
-                    openai_config = openai_config.with_api_base(url);
-                }
-                if let Some(ref key) = config.api_key {
-                    openai_config = openai_config.with_api_key(key);
-                } else if config.base_url.is_some() {
-                    // For Ollama/local models without explicit key
-                    openai_config = openai_config.with_api_key("ollama");
-                }
-                Ok(LlmClient::OpenAI(OpenAIClient::with_config(openai_config)))
-            }
-            Backend::Anthropic => {
-                let anthropic = if let Some(ref key) = config.api_key {
-                    Anthropic::new(key).map_err(|e| RlmError::Config(e.to_string()))?
-                } else {
-                    Anthropic::from_env().map_err(|e| RlmError::Config(e.to_string()))?
-                };
-                Ok(LlmClient::Anthropic(anthropic))
-            }
+    /// Run model code in a custom [`ReplEnvironment`]
+    ///
+    /// The system prompt still follows `config.repl_language`, so pick the
+    /// language closest to what the environment executes.
+    pub fn with_repl_factory(mut self, factory: impl ReplFactory + 'static) -> Self {
+        self.repl_factory = Some(Arc::new(factory));
+        self
+    }
+
+    /// Receive what model code prints while a block is still running
+    ///
+    /// Useful to show progress of long loops ("chunk 3/40"). Covers
+    /// `print()` in the built-in Python REPLs; the complete output still
+    /// arrives in the block's [`ReplResult`] afterwards.
+    pub fn with_output_stream(mut self, output: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        self.output_stream = Some(Arc::new(output));
+        self
+    }
+
+    /// Receive [`RlmEvent`]s as a run goes: iterations, code blocks, and
+    /// sub-queries
+    ///
+    /// Lets a UI show a live view instead of waiting for the completion.
+    /// Sub-query events come from the REPL's thread.
+    pub fn with_event_stream(mut self, events: impl Fn(&RlmEvent) + Send + Sync + 'static) -> Self {
+        self.event_stream = Some(Arc::new(events));
+        self
+    }
+
+    /// Send `event` to the event stream, if there is one
+    fn emit(&self, event: RlmEvent) {
+        if let Some(ref events) = self.event_stream {
+            events(&event);
         }
     }
 
+    /// Decide on code blocks flagged under [`LintAction::Ask`]
+    ///
+    /// Called with the code and the findings; returning `false` (or having
+    /// no callback) denies the block like [`LintAction::Deny`].
+    pub fn with_lint_confirm(
+        mut self,
+        confirm: impl Fn(&str, &[LintFinding]) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.lint_confirm = Some(Arc::new(confirm));
+        self
+    }
+
+    /// Take `ReplMode::Worker` processes from a pool of started ones
+    ///
+    /// The pool is ignored if it runs a different interpreter than
+    /// `config.python` selects.
+    pub fn with_worker_pool(mut self, pool: Arc<WorkerPool>) -> Self {
+        self.worker_pool = Some(pool);
+        self
+    }
+
+    /// Record run statistics into a telemetry store
+    #[cfg(feature = "telemetry")]
+    pub fn with_telemetry(mut self, telemetry: Arc<Telemetry>) -> Self {
+        self.telemetry = Some(telemetry);
+        self
+    }
+
     /// Create with explicit API key (legacy, prefer using config.with_api_key())
     pub fn with_api_key(config: RlmConfig, api_key: &str) -> Result<Self> {
         let config = RlmConfig {
@@ -147,23 +944,140 @@ impl Rlm {
         Self::new(config)
     }
 
+    /// Run independent completions concurrently, returning results in input order
+    ///
+    /// Up to `concurrency` runs execute at once on worker threads. They share
+    /// this instance's backend client, rate limiter, and sub-query cache;
+    /// each run gets its own REPL.
+    pub fn completion_many<I, P>(&self, inputs: I, concurrency: usize) -> BatchCompletion
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PromptInput>,
+    {
+        let start = Instant::now();
+        let inputs: Vec<PromptInput> = inputs.into_iter().map(Into::into).collect();
+        let slots: Vec<Mutex<Option<Result<RlmCompletion>>>> =
+            inputs.iter().map(|_| Mutex::new(None)).collect();
+        let next = AtomicUsize::new(0);
+
+        std::thread::scope(|scope| {
+            for _ in 0..concurrency.clamp(1, inputs.len().max(1)) {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(input) = inputs.get(i) else {
+                        break;
+                    };
+                    let result = self.completion(input.clone());
+                    *slots[i].lock().unwrap() = Some(result);
+                });
+            }
+        });
+
+        let results: Vec<Result<RlmCompletion>> = slots
+            .into_iter()
+            .map(|slot| {
+                slot.into_inner()
+                    .unwrap()
+                    .expect("every input is processed")
+            })
+            .collect();
+
+        let mut usage = Usage::default();
+        for result in &results {
+            match result {
+                Ok(completion) => usage.add(&completion.usage),
+                Err(e) => {
+                    if let Some(partial) = e.partial() {
+                        usage.add(&partial.usage);
+                    }
+                }
+            }
+        }
+
+        BatchCompletion {
+            results,
+            usage,
+            execution_time: start.elapsed(),
+        }
+    }
+
     /// Run a completion with the given prompt
     ///
     /// The entire prompt (data + question) goes into the REPL `context` variable.
     /// The system prompt tells the model to examine `context` to find what to do.
+    ///
+    /// Images in the prompt ([`PromptInput::Multimodal`], or on user
+    /// messages) are shown to the root model with its first message. With
+    /// the Python REPL they are also attachments (`attachments["image_1.png"]`,
+    /// ...), which `llm_query(prompt, images=[...])` can pass on to sub-calls.
     pub fn completion(&self, prompt: impl Into<PromptInput>) -> Result<RlmCompletion> {
+        self.completion_with_attachments(prompt, &[])
+    }
+
+    /// Run a completion with binary attachments
+    ///
+    /// Like [`Self::completion`], with each attachment available to REPL
+    /// code as `attachments["name"]` (`bytes`) and listed in the system
+    /// prompt. Needs the Python REPL.
+    pub fn completion_with_attachments(
+        &self,
+        prompt: impl Into<PromptInput>,
+        attachments: &[Attachment],
+    ) -> Result<RlmCompletion> {
         let prompt = prompt.into();
-        let context_payload = match &prompt {
-            PromptInput::Text(s) => s.clone(),
-            PromptInput::Messages(msgs) => msgs
-                .iter()
-                .filter(|m| m.role == Role::User)
-                .map(|m| m.content.as_str())
-                .collect::<Vec<_>>()
-                .join("\n"),
-        };
+        let images = prompt.images();
+        let context_payload = prompt_payload(&prompt);
         // Root prompt is optional - can be used to remind the model of the original question
-        self.completion_with_context(&context_payload, None)
+        self.run(&context_payload, None, attachments, &images, None)
+    }
+
+    /// Start a session whose turns share one REPL (see [`RlmSession`])
+    ///
+    /// The REPL is set up like a run's, with an empty `context`. Turns may
+    /// be run by other `Rlm`s as long as their REPL settings match.
+    pub fn session(&self) -> Result<RlmSession> {
+        let query_slot: Arc<Mutex<Option<LlmQueryFn>>> = Arc::default();
+        let output_slot: Arc<Mutex<Option<OutputFn>>> = Arc::default();
+        let current = query_slot.clone();
+        let query_fn: LlmQueryFn = Arc::new(move |prompt: &str| {
+            // Not held during the call, sub-calls may run concurrently
+            let query_fn = current.lock().unwrap().clone();
+            match query_fn {
+                Some(query_fn) => query_fn(prompt),
+                None => Err("llm_query is only available while a turn runs".to_string()),
+            }
+        });
+        let current = output_slot.clone();
+        let output: OutputFn = Arc::new(move |text: &str| {
+            let output = current.lock().unwrap().clone();
+            if let Some(output) = output {
+                output(text);
+            }
+        });
+
+        Ok(RlmSession {
+            repl: self.create_repl(query_fn, "", &[], Some(output))?,
+            query_fn: query_slot,
+            output: output_slot,
+            turns: Vec::new(),
+        })
+    }
+
+    /// Run the next turn of a session
+    ///
+    /// Like [`Self::completion`], in the session's REPL. The turn is only
+    /// recorded in the session when it produces an answer.
+    pub fn completion_in_session(
+        &self,
+        session: &mut RlmSession,
+        prompt: impl Into<PromptInput>,
+    ) -> Result<RlmCompletion> {
+        let prompt = prompt.into();
+        let request = prompt_payload(&prompt);
+        let context_payload = session.context(&request);
+        let completion = self.run(&context_payload, None, &[], &prompt.images(), Some(session))?;
+        session.turns.push((request, completion.response.clone()));
+        Ok(completion)
     }
 
     /// Run a completion with context payload and optional root prompt reminder
@@ -174,168 +1088,290 @@ impl Rlm {
     ///
     /// The model uses the REPL to examine `context` and recursively process it.
     pub fn completion_with_context(
+        &self,
+        context_payload: &str,
+        root_prompt: Option<&str>,
+    ) -> Result<RlmCompletion> {
+        self.run(context_payload, root_prompt, &[], &[], None)
+    }
+
+    /// Run the loop and record telemetry
+    fn run(
+        &self,
+        context_payload: &str,
+        root_prompt: Option<&str>,
+        attachments: &[Attachment],
+        images: &[Image],
+        session: Option<&mut RlmSession>,
+    ) -> Result<RlmCompletion> {
+        let result = self.run_loop(context_payload, root_prompt, attachments, images, session);
+
+        #[cfg(feature = "telemetry")]
+        if let Some(ref telemetry) = self.telemetry {
+            if let Some(record) = RunRecord::from_result(&self.config, &result) {
+                // Telemetry must never fail a run
+                let _ = telemetry.record(&record);
+            }
+        }
+
+        result
+    }
+
+    /// The REPL iteration loop behind [`Self::completion_with_context`]
+    fn run_loop(
         &self,
         context_payload: &str,
         _root_prompt: Option<&str>,
+        attachments: &[Attachment],
+        images: &[Image],
+        session: Option<&mut RlmSession>,
     ) -> Result<RlmCompletion> {
-        let prompt = PromptInput::Text(context_payload.to_string());
+        let prompt = if images.is_empty() {
+            PromptInput::Text(context_payload.to_string())
+        } else {
+            PromptInput::Multimodal {
+                text: context_payload.to_string(),
+                images: images.to_vec(),
+            }
+        };
         let start = Instant::now();
 
+        if !attachments.is_empty() && self.config.repl_language != ReplLang::Python {
+            return Err(RlmError::Config(
+                "Attachments are only available in the Python REPL".to_string(),
+            ));
+        }
+
+        // Python code gets the image bytes too, to crop and pass to sub-calls
+        let mut attachments = attachments.to_vec();
+        if self.config.repl_language == ReplLang::Python {
+            attachments.extend(images.iter().enumerate().map(|(i, image)| {
+                Attachment::new(
+                    format!("image_{}.{}", i + 1, image.extension()),
+                    image.data.clone(),
+                )
+            }));
+        }
+
         // Build initial messages - system prompt includes context metadata
-        let system_prompt = build_system_prompt(context_payload.len());
+        let mut extra_functions = match self.config.repl_language {
+            ReplLang::Python if self.repl_factory.is_none() => {
+                let mut lines = TEXT_HELPER_FUNCTIONS.to_string();
+                lines.push_str(&package_functions(&self.config.python));
+                if let Some(ref workspace) = self.config.workspace {
+                    lines.push_str(&workspace_functions(workspace));
+                }
+                lines
+            }
+            _ => String::new(),
+        };
+        if !attachments.is_empty() {
+            extra_functions.push_str(&attachment_functions(&attachments));
+        }
+        if !images.is_empty() && self.config.repl_language == ReplLang::Python {
+            extra_functions.push_str(IMAGE_QUERY_FUNCTION);
+        }
+        let mut system_prompt = build_system_prompt(
+            context_payload.len(),
+            count_tokens(&self.config.model, context_payload),
+            self.config.effective_context_window(),
+            self.config.task_mode,
+            self.config.repl_language,
+            &extra_functions,
+        );
+        if let Some(format) = self.config.answer_format.instructions() {
+            system_prompt.push_str("\n\n");
+            system_prompt.push_str(&format);
+        }
+        let continues_session = session.as_ref().is_some_and(|s| !s.turns.is_empty());
+        if continues_session {
+            system_prompt.push_str("\n\n");
+            system_prompt.push_str(SESSION_TURN_NOTE);
+        }
 
         // Initial user message - tells model to start examining context
         let initial_user_msg = build_initial_user_prompt();
 
         let mut history: Vec<Message> = vec![
             Message::system(system_prompt),
-            Message::user(&initial_user_msg),
+            Message::user(&initial_user_msg).with_images(images.iter().cloned()),
         ];
 
         let mut iterations: Vec<RlmIteration> = Vec::new();
         let mut total_usage = Usage::default();
 
-        // Create REPL with callback that uses our backend config
-        let backend_for_callback = self.config.backend.clone();
-        let model_for_callback = self.config.model.clone();
-        let temp_for_callback = self.config.temperature;
-        let api_key_for_callback = self.config.api_key.clone();
-        let base_url_for_callback = self.config.base_url.clone();
+        // Sub-calls get a fresh single-message history each
+        let backend_for_callback = self.sub_backend.clone();
+        let params_for_callback = self.sub_call_params();
 
         // We need to track usage from sub-calls
         let sub_call_usage = Arc::new(Mutex::new(Usage::default()));
         let sub_call_usage_for_callback = sub_call_usage.clone();
 
+        // Count sub-calls against the optional per-run and per-iteration quotas
+        let max_sub_calls = self.config.max_sub_calls;
+        let sub_call_count = Arc::new(AtomicU32::new(0));
+        let sub_call_count_for_callback = sub_call_count.clone();
+        let max_per_iteration = self.config.max_sub_calls_per_iteration;
+        let iteration_sub_calls = Arc::new(AtomicU32::new(0));
+        let iteration_sub_calls_for_callback = iteration_sub_calls.clone();
+
+        let max_backend_retries = self.config.max_backend_retries;
+
+        // Identical sub-queries are answered from the cache without a backend call
+        let query_cache = self.query_cache.clone();
+        let cache_stats = Arc::new(Mutex::new(CacheStats::default()));
+        let cache_stats_for_callback = cache_stats.clone();
+        let events_for_callback = self.event_stream.clone();
+        let emit_sub_query = move |cached: bool| {
+            if let Some(ref events) = events_for_callback {
+                events(&RlmEvent::SubQuery { cached });
+            }
+        };
+
         let query_fn: LlmQueryFn = Arc::new(move |prompt: &str| {
-            // Create a new runtime for the callback (we're in a different thread context)
-            let rt = match Runtime::new() {
-                Ok(rt) => rt,
-                Err(e) => return Err(format!("Runtime error: {}", e)),
-            };
+            let (overrides, prompt) = QueryOverrides::decode(prompt)?;
+            let params = query_params(&params_for_callback, &overrides);
+            let model = &params.model;
+            let temperature = params.temperature;
+            // Cache keys don't cover the output cap or images
+            let query_cache = query_cache
+                .as_ref()
+                .filter(|_| overrides.max_tokens.is_none() && overrides.images.is_empty());
+            if let Some(cache) = query_cache {
+                if let Some(cached) = cache.get(model, prompt, temperature) {
+                    cache_stats_for_callback.lock().unwrap().hits += 1;
+                    emit_sub_query(true);
+                    return Ok(cached);
+                }
+                cache_stats_for_callback.lock().unwrap().misses += 1;
+            }
 
-            rt.block_on(async {
-                match backend_for_callback {
-                    Backend::OpenAI => {
-                        // Create OpenAI client for sub-call
-                        let mut openai_config = OpenAIConfig::new();
-                        if let Some(ref url) = base_url_for_callback {
-                            openai_config = openai_config.with_api_base(url);
-                        }
-                        if let Some(ref key) = api_key_for_callback {
-                            openai_config = openai_config.with_api_key(key);
-                        } else if base_url_for_callback.is_some() {
-                            openai_config = openai_config.with_api_key("ollama");
-                        }
-                        let client = OpenAIClient::with_config(openai_config);
-
-                        let messages = vec![ChatCompletionRequestMessage::User(
-                            ChatCompletionRequestUserMessageArgs::default()
-                                .content(prompt)
-                                .build()
-                                .map_err(|e| e.to_string())?,
-                        )];
-
-                        let request = CreateChatCompletionRequestArgs::default()
-                            .model(&model_for_callback)
-                            .messages(messages)
-                            .temperature(temp_for_callback)
-                            .build()
-                            .map_err(|e| e.to_string())?;
-
-                        let response = client
-                            .chat()
-                            .create(request)
-                            .await
-                            .map_err(|e| e.to_string())?;
-
-                        // Track usage
-                        if let Some(usage) = &response.usage {
-                            let mut guard = sub_call_usage_for_callback.lock().unwrap();
-                            guard.input_tokens += usage.prompt_tokens as u64;
-                            guard.output_tokens += usage.completion_tokens as u64;
-                            guard.total_tokens += usage.total_tokens as u64;
-                        }
+            // Errors surface as exceptions in model code
+            let made = sub_call_count_for_callback.fetch_add(1, Ordering::SeqCst);
+            if let Some(max) = max_sub_calls.filter(|max| made >= *max) {
+                return Err(sub_call_budget_exhausted_message(max));
+            }
+            if let Some(max) = max_per_iteration {
+                if iteration_sub_calls_for_callback.fetch_add(1, Ordering::SeqCst) >= max {
+                    // Not made, so it doesn't count against the run
+                    sub_call_count_for_callback.fetch_sub(1, Ordering::SeqCst);
+                    return Err(sub_call_iteration_limit_message(max));
+                }
+            }
+            emit_sub_query(false);
+
+            let (content, usage) = chat_with_retry(
+                backend_for_callback.as_ref(),
+                &[Message::user(prompt).with_images(overrides.images)],
+                &params,
+                max_backend_retries,
+            )
+            .map_err(|e| e.to_string())?;
+
+            // Track usage
+            sub_call_usage_for_callback.lock().unwrap().add(&usage);
+
+            if let Some(cache) = query_cache {
+                // A failed write only costs a future cache miss
+                let _ = cache.insert(model, prompt, temperature, &content);
+            }
 
-                        let content = response
-                            .choices
-                            .first()
-                            .and_then(|c| c.message.content.clone())
-                            .unwrap_or_default();
+            Ok(content)
+        });
 
-                        Ok(content)
+        // Live output for the caller and, in verbose mode, the log
+        let streamed = Arc::new(AtomicBool::new(false));
+        let output: Option<OutputFn> = if self.output_stream.is_some() || self.config.verbose {
+            let user = self.output_stream.clone();
+            let sink = self.config.verbose.then(|| self.config.log_sink.clone());
+            let streamed = streamed.clone();
+            Some(Arc::new(move |text: &str| {
+                if let Some(ref sink) = sink {
+                    if !streamed.swap(true, Ordering::SeqCst) {
+                        sink.log("📤 Output:");
                     }
-                    Backend::Anthropic => {
-                        // Create Anthropic client for sub-call
-                        let client = if let Some(ref key) = api_key_for_callback {
-                            Anthropic::new(key).map_err(|e| e.to_string())?
-                        } else {
-                            Anthropic::from_env().map_err(|e| e.to_string())?
-                        };
-
-                        let params = MessageCreateBuilder::new(&model_for_callback, 4096)
-                            .user(prompt)
-                            .build();
-
-                        let response = client
-                            .messages()
-                            .create(params)
-                            .await
-                            .map_err(|e| e.to_string())?;
-
-                        // Track usage
-                        {
-                            let mut guard = sub_call_usage_for_callback.lock().unwrap();
-                            guard.input_tokens += response.usage.input_tokens as u64;
-                            guard.output_tokens += response.usage.output_tokens as u64;
-                            guard.total_tokens +=
-                                (response.usage.input_tokens + response.usage.output_tokens) as u64;
-                        }
-
-                        // Extract text from content blocks
-                        let content = response
-                            .content
-                            .iter()
-                            .filter_map(|block| {
-                                if let ContentBlock::Text { text } = block {
-                                    Some(text.as_str())
-                                } else {
-                                    None
-                                }
-                            })
-                            .collect::<Vec<_>>()
-                            .join("");
-
-                        Ok(content)
+                    for line in text.lines() {
+                        sink.log(&format!("   {}", line));
                     }
                 }
-            })
-        });
-
-        let mut repl = PyO3Repl::new(query_fn)?;
+                if let Some(ref user) = user {
+                    user(text);
+                }
+            }))
+        } else {
+            None
+        };
+        let mut fresh = None;
+        let repl: &mut dyn ReplEnvironment = match session {
+            Some(session) => {
+                *session.query_fn.lock().unwrap() = Some(query_fn);
+                *session.output.lock().unwrap() = output;
+                self.load_context(session.repl.as_mut(), context_payload, &attachments)?;
+                session.repl.as_mut()
+            }
+            None => fresh
+                .insert(self.create_repl(query_fn, context_payload, &attachments, output)?)
+                .as_mut(),
+        };
 
-        // Add context variable to REPL - this is the DATA to analyze, not instructions
-        repl.add_context("context", context_payload)?;
+        // Attach the trace gathered so far to failures inside the loop
+        let incomplete = |error: RlmError, iterations: &[RlmIteration], usage: &Usage| {
+            let mut usage = usage.clone();
+            usage.add(&sub_call_usage.lock().unwrap());
+            RlmError::Incomplete {
+                error: Box::new(error),
+                partial: Box::new(PartialRun {
+                    prompt: prompt.clone(),
+                    iterations: iterations.to_vec(),
+                    usage,
+                    execution_time: start.elapsed(),
+                }),
+            }
+        };
 
         // Main iteration loop
-        for iteration_num in 0..self.config.max_iterations {
+        // Fixed limit, or one scaled to how many chunks the context spans
+        let max_iterations = match self.config.adaptive_iterations {
+            Some(adaptive) => adaptive.limit(
+                context_payload.len(),
+                suggested_chunk_size(context_payload.len()),
+            ),
+            None => self.config.max_iterations,
+        };
+
+        for iteration_num in 0..max_iterations {
             let iter_start = Instant::now();
+            iteration_sub_calls.store(0, Ordering::SeqCst);
+            let sub_usage_before = sub_call_usage.lock().unwrap().clone();
+
+            // Keep the iteration budget visible to model code
+            execute_with_error_handling(
+                &mut *repl,
+                &format!("REMAINING_ITERATIONS = {}", max_iterations - iteration_num),
+            )
+            .map_err(|e| incomplete(e, &iterations, &total_usage))?;
+            self.emit(RlmEvent::IterationStarted {
+                iteration: iteration_num + 1,
+                max_iterations,
+            });
 
             // Minimal progress log
             if self.config.exec_log && !self.config.verbose {
-                println!("── iter {} ──", iteration_num + 1);
-                let _ = io::stdout().flush();
+                log_line!(self, "── iter {} ──", iteration_num + 1);
             }
 
             if self.config.verbose {
-                println!("┌─────────────────────────────────────────────────────────────┐");
-                println!(
+                self.log("┌─────────────────────────────────────────────────────────────┐");
+                log_line!(
+                    self,
                     "│ ITERATION {:3}                                               │",
                     iteration_num + 1
                 );
-                println!("└─────────────────────────────────────────────────────────────┘");
-                println!();
-                println!("📥 LLM Query (message history):");
-                println!("─────────────────────────────────────────────────────────────");
+                self.log("└─────────────────────────────────────────────────────────────┘");
+                self.log("");
+                self.log("📥 LLM Query (message history):");
+                self.log("─────────────────────────────────────────────────────────────");
                 for (i, msg) in history.iter().enumerate() {
                     let role_str = match msg.role {
                         Role::System => "SYSTEM",
@@ -351,44 +1387,57 @@ impl Rlm {
                     } else {
                         msg.content.clone()
                     };
-                    println!("[{}] {}: {}", i, role_str, content_preview);
-                    println!();
+                    log_line!(self, "[{}] {}: {}", i, role_str, content_preview);
+                    self.log("");
                 }
-                println!("─────────────────────────────────────────────────────────────");
-                println!();
-                println!(
+                self.log("─────────────────────────────────────────────────────────────");
+                self.log("");
+                log_line!(
+                    self,
                     "📦 REPL context variable ({} chars):",
                     context_payload.len()
                 );
                 if context_payload.len() > 300 {
-                    println!("{}...[truncated]", &context_payload[..300]);
+                    log_line!(self, "{}...[truncated]", &context_payload[..300]);
                 } else if context_payload.is_empty() {
-                    println!("(empty)");
+                    self.log("(empty)");
                 } else {
-                    println!("{}", context_payload);
+                    log_line!(self, "{}", context_payload);
                 }
-                println!("─────────────────────────────────────────────────────────────");
-                let _ = io::stdout().flush();
+                self.log("─────────────────────────────────────────────────────────────");
             }
 
             // Call LLM
-            let (raw_response, usage) = self.call_llm(&history)?;
+            let (raw_response, usage) = self
+                .call_llm(&history)
+                .map_err(|e| incomplete(e, &iterations, &total_usage))?;
             total_usage.add(&usage);
 
+            // Stitch a response cut off by max_tokens back together
+            let (raw_response, continuations) = self
+                .continue_truncated(&history, raw_response, &usage, &mut total_usage)
+                .map_err(|e| incomplete(e, &iterations, &total_usage))?;
+            if continuations > 0 && (self.config.verbose || self.config.exec_log) {
+                log_line!(self, "   ↪ response continued {} time(s)", continuations);
+            }
+
             // Truncate after first ```repl``` block ends - discard everything after
-            let response_text = truncate_after_first_repl_block(&raw_response);
+            let response_text = truncate_after_first_repl_block(
+                &raw_response,
+                &self.config.parser,
+                self.config.keep_trailing_prose,
+            );
 
             if self.config.verbose {
-                println!();
-                println!("📤 LLM Response:");
-                println!("─────────────────────────────────────────────────────────────");
+                self.log("");
+                self.log("📤 LLM Response:");
+                self.log("─────────────────────────────────────────────────────────────");
                 if response_text.len() > 2000 {
-                    println!("{}...[truncated]", &response_text[..2000]);
+                    log_line!(self, "{}...[truncated]", &response_text[..2000]);
                 } else {
-                    println!("{}", response_text);
+                    log_line!(self, "{}", response_text);
                 }
-                println!("─────────────────────────────────────────────────────────────");
-                let _ = io::stdout().flush();
+                self.log("─────────────────────────────────────────────────────────────");
             }
 
             // Add assistant response to history
@@ -396,15 +1445,13 @@ impl Rlm {
 
             // Extract code blocks - only execute the FIRST one, throw away extras
             // This forces step-by-step evaluation
-            let code_blocks = extract_code_blocks(&response_text);
+            let code_blocks = self.config.parser.extract_code_blocks(&response_text);
             let mut executed_blocks: Vec<CodeBlock> = Vec::new();
 
             if self.config.verbose && code_blocks.is_empty() {
-                println!("📝 No code blocks in this iteration");
-                let _ = io::stdout().flush();
+                self.log("📝 No code blocks in this iteration");
             } else if self.config.exec_log && !self.config.verbose && code_blocks.is_empty() {
-                println!("   (no code)");
-                let _ = io::stdout().flush();
+                self.log("   (no code)");
             }
 
             // Only execute first code block (step-by-step)
@@ -413,37 +1460,48 @@ impl Rlm {
                     // Show first line of code as preview
                     let preview: String =
                         code.lines().next().unwrap_or("").chars().take(50).collect();
-                    println!(
+                    log_line!(
+                        self,
                         "   ⚡ {}{}",
                         preview,
                         if code.len() > 50 { "..." } else { "" }
                     );
-                    let _ = io::stdout().flush();
                 }
                 if self.config.verbose {
                     if code_blocks.len() > 1 {
-                        println!(
+                        log_line!(
+                            self,
                             "📝 Executing Code Block 1 of {} (others discarded):",
                             code_blocks.len()
                         );
                     } else {
-                        println!("📝 Executing Code Block:");
+                        self.log("📝 Executing Code Block:");
                     }
-                    println!("┌─────────────────────────────────────────────────────────────┐");
-                    for line in code.lines() {
-                        println!("│ {}", line);
-                    }
-                    println!("└─────────────────────────────────────────────────────────────┘");
-                    let _ = io::stdout().flush();
+                    self.log("┌─────────────────────────────────────────────────────────────┐");
+                    self.config.log_sink.log_code(code);
+                    self.log("└─────────────────────────────────────────────────────────────┘");
                 }
 
-                let block_result =
-                    self.execute_with_retry(&mut repl, code, &mut history, &mut total_usage)?;
+                streamed.store(false, Ordering::SeqCst);
+                self.emit(RlmEvent::CodeStarted {
+                    iteration: iteration_num + 1,
+                    code: code.clone(),
+                });
+                let block_result = self
+                    .execute_with_retry(&mut *repl, code, &mut history, &mut total_usage)
+                    .map_err(|e| incomplete(e, &iterations, &total_usage))?;
+                if let Some(ref res) = block_result.result {
+                    self.emit(RlmEvent::CodeFinished {
+                        iteration: iteration_num + 1,
+                        success: res.success,
+                        error: res.error.clone(),
+                    });
+                }
 
                 if self.config.exec_log && !self.config.verbose {
                     if let Some(ref res) = block_result.result {
                         if res.success {
-                            print!("   → ✓");
+                            let mut line = "   → ✓".to_string();
                             if !res.stdout.is_empty() {
                                 // Show first line of output
                                 let out_preview: String = res
@@ -454,86 +1512,124 @@ impl Rlm {
                                     .chars()
                                     .take(60)
                                     .collect();
-                                print!(" {}", out_preview);
+                                line.push_str(&format!(" {}", out_preview));
                                 if res.stdout.lines().count() > 1 {
-                                    print!(" (+{} lines)", res.stdout.lines().count() - 1);
+                                    line.push_str(&format!(
+                                        " (+{} lines)",
+                                        res.stdout.lines().count() - 1
+                                    ));
                                 }
                             }
-                            println!();
+                            self.config.log_sink.log(&line);
                         } else {
-                            println!("   → ✗ {}", res.error.as_deref().unwrap_or("error"));
+                            log_line!(self, "   → ✗ {}", res.error.as_deref().unwrap_or("error"));
                         }
                     }
-                    let _ = io::stdout().flush();
                 }
                 if self.config.verbose {
                     if let Some(ref res) = block_result.result {
                         if res.success {
-                            println!(
+                            log_line!(
+                                self,
                                 "✅ Execution SUCCESS (retries: {})",
                                 block_result.retry_count
                             );
-                            if !res.stdout.is_empty() {
-                                println!("📤 Output:");
+                            // Already shown live if it was printed
+                            if !res.stdout.is_empty() && !streamed.load(Ordering::SeqCst) {
+                                self.log("📤 Output:");
                                 for line in res.stdout.lines() {
-                                    println!("   {}", line);
+                                    log_line!(self, "   {}", line);
                                 }
                             }
                         } else {
-                            println!(
+                            log_line!(
+                                self,
                                 "❌ Execution FAILED (retries: {})",
                                 block_result.retry_count
                             );
                             if let Some(ref err) = res.error {
-                                println!("   Error: {}", err);
+                                log_line!(self, "   Error: {}", err);
                             }
                         }
                     }
-                    let _ = io::stdout().flush();
                 }
 
                 executed_blocks.push(block_result);
             }
 
-            // Check for final answer - llm_output() invocation is the primary signal
-            // Also check FINAL patterns for backwards compatibility
+            // Check for final answer - the first detector that fires wins
+            // (by default: llm_output(), FINAL_ANSWER: stdout, FINAL patterns)
             let locals = repl.get_locals();
-
-            // Primary: Check if llm_output was called in code execution
-            let llm_output_answer = executed_blocks
-                .iter()
-                .filter_map(|b| b.result.as_ref())
-                .find_map(|r| r.llm_output.clone());
-
-            // Fallback: Check stdout from code execution (FINAL_ANSWER: prefix)
-            let final_from_code = executed_blocks
+            let answer_ctx = AnswerContext {
+                response: &response_text,
+                code_blocks: &executed_blocks,
+                locals: &locals,
+                parser: &self.config.parser,
+            };
+            let final_answer = self
+                .config
+                .answer_detectors
                 .iter()
-                .filter_map(|b| b.result.as_ref())
-                .find_map(|r| extract_final_answer_from_stdout(&r.stdout));
+                .find_map(|d| d.detect(&answer_ctx));
+
+            // Keep control characters and Python bytes reprs out of the answer,
+            // recording the original in the trace
+            let (final_answer, final_answer_raw) = match final_answer {
+                Some(answer) => match sanitize_final_answer(&answer) {
+                    Some(clean) => (Some(clean), Some(answer.into_bytes())),
+                    None => (Some(answer), None),
+                },
+                None => (None, None),
+            };
 
-            // Fallback: Check response text for FINAL patterns
-            let final_answer = llm_output_answer
-                .or(final_from_code)
-                .or_else(|| extract_answer(&response_text, &locals));
+            let final_json = final_answer.as_deref().and_then(parse_json_answer);
+
+            // An answer of the wrong shape doesn't count; the model is told
+            // why and tries again
+            let format_error = final_answer
+                .as_ref()
+                .and_then(|_| self.config.answer_format.check(final_json.as_ref()).err());
+            let (final_answer, final_json) = match format_error {
+                Some(ref error) => {
+                    if self.config.verbose {
+                        log_line!(self, "⚠️  Final answer rejected: {}", error);
+                    }
+                    (None, None)
+                }
+                None => (final_answer, final_json),
+            };
 
             if self.config.exec_log && !self.config.verbose && final_answer.is_some() {
-                println!("   🎯 FINAL");
-                let _ = io::stdout().flush();
+                self.log("   🎯 FINAL");
             }
             if self.config.verbose {
-                println!("⏱️  Iteration time: {:?}", iter_start.elapsed());
+                log_line!(self, "⏱️  Iteration time: {:?}", iter_start.elapsed());
                 if final_answer.is_some() {
-                    println!("🎯 FINAL answer detected!");
+                    self.log("🎯 FINAL answer detected!");
+                }
+                if let Some(ref raw) = final_answer_raw {
+                    log_line!(
+                        self,
+                        "⚠️  Final answer sanitized ({} bytes original)",
+                        raw.len()
+                    );
                 }
-                println!();
-                let _ = io::stdout().flush();
+                self.log("");
             }
 
+            self.emit(RlmEvent::IterationFinished {
+                iteration: iteration_num + 1,
+                duration: iter_start.elapsed(),
+            });
             iterations.push(RlmIteration {
                 iteration: iteration_num,
                 response: response_text.clone(),
                 code_blocks: executed_blocks,
                 final_answer: final_answer.clone(),
+                final_answer_raw,
+                final_json: final_json.clone(),
+                continuations,
+                sub_call_usage: sub_call_usage.lock().unwrap().since(&sub_usage_before),
                 execution_time: iter_start.elapsed(),
             });
 
@@ -543,157 +1639,385 @@ impl Rlm {
                 let sub_usage = sub_call_usage.lock().unwrap();
                 total_usage.add(&sub_usage);
 
+                // JSON answers come back as bare JSON, without fences or
+                // surrounding prose
+                let response = match (&self.config.answer_format, &final_json) {
+                    (AnswerFormat::Text, _) | (_, None) => {
+                        finalize_answer(answer, self.config.task_mode)
+                    }
+                    (_, Some(json)) => json.to_string(),
+                };
                 return Ok(RlmCompletion {
                     prompt,
-                    response: answer,
+                    response,
+                    response_json: final_json,
                     iterations,
                     usage: total_usage,
                     execution_time: start.elapsed(),
+                    cache_stats: *cache_stats.lock().unwrap(),
+                    status: CompletionStatus::Answered,
                 });
             }
 
             // Note: execution results already added to history in execute_with_retry
 
             // Add continue prompt to keep model on track
-            let continue_msg = build_continue_prompt(iteration_num, self.config.max_iterations);
+            let sub_calls_exhausted = self
+                .config
+                .max_sub_calls
+                .is_some_and(|max| sub_call_count.load(Ordering::SeqCst) >= max);
+            let mut continue_msg =
+                build_continue_prompt(iteration_num, max_iterations, sub_calls_exhausted);
+            if let Some(error) = format_error {
+                continue_msg = format!(
+                    "Your final answer was rejected: {}\nFix it and answer again.\n\n{}",
+                    error, continue_msg
+                );
+            }
             history.push(Message::user(&continue_msg));
         }
 
-        Err(RlmError::MaxIterationsReached(self.config.max_iterations))
-    }
-
-    /// Call the LLM with the current history
-    fn call_llm(&self, history: &[Message]) -> Result<(String, Usage)> {
-        match &self.client {
-            LlmClient::OpenAI(client) => self.call_openai(client, history),
-            LlmClient::Anthropic(client) => self.call_anthropic(client, history),
+        if self.config.partial_results {
+            total_usage.add(&sub_call_usage.lock().unwrap());
+            return Ok(RlmCompletion {
+                prompt,
+                response: iterations
+                    .last()
+                    .map(|it| it.response.clone())
+                    .unwrap_or_default(),
+                response_json: None,
+                iterations,
+                usage: total_usage,
+                execution_time: start.elapsed(),
+                cache_stats: *cache_stats.lock().unwrap(),
+                status: CompletionStatus::MaxIterations,
+            });
         }
+
+        Err(incomplete(
+            RlmError::MaxIterationsReached(max_iterations),
+            &iterations,
+            &total_usage,
+        ))
     }
 
-    /// Call OpenAI-compatible API
-    fn call_openai(
+    /// Continue a root response that was cut off by `max_tokens`
+    ///
+    /// A response is treated as cut off when it used the whole `max_tokens`
+    /// budget without closing a code block. The model is asked to pick up
+    /// where it stopped until the response is complete or
+    /// `max_answer_tokens` output tokens are spent. Returns the stitched
+    /// response and the number of continuation requests.
+    fn continue_truncated(
         &self,
-        client: &OpenAIClient<OpenAIConfig>,
         history: &[Message],
-    ) -> Result<(String, Usage)> {
-        let messages: Vec<ChatCompletionRequestMessage> = history
-            .iter()
-            .map(|m| match m.role {
-                Role::System => ChatCompletionRequestMessage::System(
-                    ChatCompletionRequestSystemMessageArgs::default()
-                        .content(m.content.clone())
-                        .build()
-                        .unwrap(),
-                ),
-                Role::User => ChatCompletionRequestMessage::User(
-                    ChatCompletionRequestUserMessageArgs::default()
-                        .content(m.content.clone())
-                        .build()
-                        .unwrap(),
-                ),
-                Role::Assistant => ChatCompletionRequestMessage::Assistant(
-                    ChatCompletionRequestAssistantMessageArgs::default()
-                        .content(m.content.clone())
-                        .build()
-                        .unwrap(),
-                ),
-            })
-            .collect();
-
-        let mut request_builder = CreateChatCompletionRequestArgs::default();
-        request_builder
-            .model(&self.config.model)
-            .messages(messages)
-            .temperature(self.config.temperature);
-
-        if let Some(max_tokens) = self.config.max_tokens {
-            request_builder.max_tokens(max_tokens);
+        mut response: String,
+        usage: &Usage,
+        total_usage: &mut Usage,
+    ) -> Result<(String, u32)> {
+        let (Some(per_call), Some(budget)) =
+            (self.config.max_tokens, self.config.max_answer_tokens)
+        else {
+            return Ok((response, 0));
+        };
+        let (per_call, budget) = (u64::from(per_call), u64::from(budget));
+
+        let mut continuations = 0;
+        let mut last_output = usage.output_tokens;
+        let mut spent = usage.output_tokens;
+        while last_output >= per_call
+            && spent < budget
+            && self.config.parser.extract_code_blocks(&response).is_empty()
+        {
+            let mut messages = history.to_vec();
+            messages.push(Message::assistant(&response));
+            messages.push(Message::user(CONTINUE_TRUNCATED_PROMPT));
+
+            let (part, part_usage) = self.call_llm(&messages)?;
+            total_usage.add(&part_usage);
+            response.push_str(&part);
+            continuations += 1;
+            last_output = part_usage.output_tokens;
+            spent += part_usage.output_tokens;
         }
 
-        let request = request_builder.build()?;
-
-        let response = self
-            .runtime
-            .block_on(async { client.chat().create(request).await })?;
+        Ok((response, continuations))
+    }
 
-        let content = response
-            .choices
-            .first()
-            .and_then(|c| c.message.content.clone())
-            .unwrap_or_default();
+    fn log(&self, line: &str) {
+        self.config.log_sink.log(line);
+    }
 
-        let usage = response
-            .usage
-            .map(|u| Usage::new(u.prompt_tokens as u64, u.completion_tokens as u64))
-            .unwrap_or_default();
+    /// Call the LLM with the current history
+    ///
+    /// Fails without sending if the history plus the output budget doesn't
+    /// fit the model's context window, and warns when it gets close.
+    fn call_llm(&self, history: &[Message]) -> Result<(String, Usage)> {
+        if let Some(limit) = self.config.effective_context_window() {
+            let tokens = count_message_tokens(&self.config.model, history);
+            let reserved = self.config.max_tokens.unwrap_or(0) as usize;
+            if tokens + reserved > limit {
+                return Err(RlmError::ContextWindowExceeded {
+                    tokens: tokens + reserved,
+                    limit,
+                });
+            }
+            if (self.config.verbose || self.config.exec_log) && tokens * 10 > limit * 9 {
+                log_line!(
+                    self,
+                    "⚠️  History is {} tokens, {}% of the {} token context window",
+                    tokens,
+                    tokens * 100 / limit,
+                    limit
+                );
+            }
+        }
 
-        Ok((content, usage))
+        let params = ChatParams::new(&self.config.model)
+            .with_temperature(self.config.temperature)
+            .with_max_tokens(self.config.max_tokens)
+            .with_prompt_cache(self.config.prompt_cache)
+            .with_sampling(self.config.sampling);
+        chat_with_retry(
+            self.backend.as_ref(),
+            history,
+            &params,
+            self.config.max_backend_retries,
+        )
     }
 
-    /// Call Anthropic API
-    fn call_anthropic(&self, client: &Anthropic, history: &[Message]) -> Result<(String, Usage)> {
-        // Extract system message
-        let system_content = history
-            .iter()
-            .find(|m| m.role == Role::System)
-            .map(|m| m.content.clone());
-
-        // Build request using builder pattern
-        let max_tokens = self.config.max_tokens.unwrap_or(4096);
-        let mut builder = MessageCreateBuilder::new(&self.config.model, max_tokens);
+    /// Set up the REPL for one run with `context` and the helpers defined
+    fn create_repl(
+        &self,
+        query_fn: LlmQueryFn,
+        context_payload: &str,
+        attachments: &[Attachment],
+        output: Option<OutputFn>,
+    ) -> Result<Box<dyn ReplEnvironment>> {
+        let mut setup = vec![context_metadata_code(context_payload)];
+        if !attachments.is_empty() {
+            setup.push(attachments_code(attachments));
+        }
 
-        // Add system prompt if present
-        if let Some(system) = system_content {
-            builder = builder.system(system);
+        if let Some(ref factory) = self.repl_factory {
+            let mut repl = factory.create(query_fn)?;
+            repl.add_context("context", context_payload)?;
+            for code in &setup {
+                execute_with_error_handling(repl.as_mut(), code)?;
+            }
+            return Ok(repl);
         }
 
-        // Add temperature if set
-        if self.config.temperature > 0.0 {
-            builder = builder.temperature(self.config.temperature);
+        // The metadata assignments are valid JavaScript and Lua as well
+        match self.config.repl_language {
+            ReplLang::Python => {}
+            ReplLang::JavaScript => {
+                #[cfg(feature = "javascript")]
+                return init_repl(
+                    JsRepl::new(query_fn, self.config.exec_timeout)?,
+                    context_payload,
+                    &setup,
+                );
+                #[cfg(not(feature = "javascript"))]
+                unreachable!("rejected in Rlm::with_chat_backend");
+            }
+            ReplLang::Lua => {
+                #[cfg(feature = "lua")]
+                return init_repl(
+                    LuaRepl::new(query_fn, self.config.exec_timeout)?,
+                    context_payload,
+                    &setup,
+                );
+                #[cfg(not(feature = "lua"))]
+                unreachable!("rejected in Rlm::with_chat_backend");
+            }
         }
 
-        // Add messages (skip system messages)
-        for msg in history.iter().filter(|m| m.role != Role::System) {
-            builder = match msg.role {
-                Role::User => builder.user(msg.content.clone()),
-                Role::Assistant => builder.assistant(msg.content.clone()),
-                Role::System => builder, // shouldn't happen due to filter
+        setup.push(QUERY_OVERRIDES_CODE.to_string());
+        setup.push(TEXT_HELPERS_CODE.to_string());
+        if let Some(ref workspace) = self.config.workspace {
+            setup.push(workspace_code(workspace));
+        }
+        if let Some(code) = packages_code(&self.config.python) {
+            setup.push(code);
+        }
+        // The worker forwards `_rlm_emit` over its pipe
+        if let Some(ref output) = output {
+            let emit = match self.config.repl_mode {
+                ReplMode::InProcess => stream_hook(output.clone())?,
+                ReplMode::Worker => "_rlm_emit".to_string(),
             };
+            setup.push(stream_code(&emit));
+        }
+        // Last, so the setup above can still import what it needs
+        if let Some(ref policy) = self.config.sandbox {
+            setup.push(sandbox_code(policy));
         }
 
-        let params = builder.build();
+        match self.config.repl_mode {
+            ReplMode::InProcess => {
+                let repl = init_repl(PyO3Repl::new(query_fn)?, context_payload, &setup)?;
+                match self.config.exec_timeout {
+                    Some(timeout) => Ok(Box::new(WatchedRepl::new(repl, timeout)?)),
+                    None => Ok(repl),
+                }
+            }
+            ReplMode::Worker => {
+                let python = worker_interpreter(&self.config.python);
+                let pool = self
+                    .worker_pool
+                    .clone()
+                    .filter(|pool| pool.python() == python);
+                let mut repl = WorkerRepl::new(
+                    query_fn,
+                    WorkerOptions {
+                        python,
+                        setup,
+                        exec_timeout: self.config.exec_timeout,
+                        // Leave the watchdog time to fire first
+                        kill_after: self.config.exec_timeout.map(|t| t + WORKER_KILL_GRACE),
+                        pool,
+                    },
+                )?;
+                if let Some(output) = output {
+                    repl = repl.with_output(output);
+                }
+                repl.add_context("context", context_payload)?;
+                Ok(Box::new(repl))
+            }
+        }
+    }
 
-        let response = self
-            .runtime
-            .block_on(async { client.messages().create(params).await })
-            .map_err(|e| RlmError::Api(e.to_string()))?;
+    /// Give a session's REPL the context of a new turn
+    fn load_context(
+        &self,
+        repl: &mut dyn ReplEnvironment,
+        context_payload: &str,
+        attachments: &[Attachment],
+    ) -> Result<()> {
+        repl.add_context("context", context_payload)?;
+        execute_with_error_handling(repl, &context_metadata_code(context_payload))?;
+        if !attachments.is_empty() {
+            execute_with_error_handling(repl, &attachments_code(attachments))?;
+        }
+        Ok(())
+    }
 
-        // Extract text from content blocks
-        let content = response
-            .content
-            .iter()
-            .filter_map(|block| {
-                if let ContentBlock::Text { text } = block {
-                    Some(text.as_str())
-                } else {
-                    None
-                }
-            })
-            .collect::<Vec<_>>()
-            .join("");
+    /// Run one code block, interrupting it after `exec_timeout`
+    fn execute_block(&self, repl: &mut dyn ReplEnvironment, code: &str) -> Result<ReplResult> {
+        execute_watched(repl, code, self.watchdog_timeout())
+    }
 
-        let usage = Usage::new(
-            response.usage.input_tokens as u64,
-            response.usage.output_tokens as u64,
-        );
+    /// Timeout enforced by the Python watchdog
+    ///
+    /// Only the built-in Python REPLs have the watchdog; the JavaScript and
+    /// Lua REPLs enforce the timeout themselves, custom ones are on their own.
+    fn watchdog_timeout(&self) -> Option<Duration> {
+        self.config.exec_timeout.filter(|_| {
+            self.repl_factory.is_none() && self.config.repl_language == ReplLang::Python
+        })
+    }
+
+    /// Open a [`ReplSession`] set up like a run's REPL
+    pub fn repl_session(&self) -> Result<ReplSession> {
+        let backend = self.sub_backend.clone();
+        let base = self.sub_call_params();
+        let max_backend_retries = self.config.max_backend_retries;
+        let query_fn: LlmQueryFn = Arc::new(move |prompt: &str| {
+            let (overrides, prompt) = QueryOverrides::decode(prompt)?;
+            let params = query_params(&base, &overrides);
+            let (content, _) = chat_with_retry(
+                backend.as_ref(),
+                &[Message::user(prompt).with_images(overrides.images)],
+                &params,
+                max_backend_retries,
+            )
+            .map_err(|e| e.to_string())?;
+            Ok(content)
+        });
+
+        Ok(ReplSession {
+            repl: self.create_repl(query_fn, "", &[], None)?,
+            timeout: self.watchdog_timeout(),
+        })
+    }
 
-        Ok((content, usage))
+    /// Send `prompt` to the model as model code's `llm_query` would, outside
+    /// any run
+    ///
+    /// Not counted against the sub-call limits and not cached.
+    pub fn llm_query(&self, prompt: &str) -> Result<String> {
+        let (content, _) = chat_with_retry(
+            self.sub_backend.as_ref(),
+            &[Message::user(prompt)],
+            &self.sub_call_params(),
+            self.config.max_backend_retries,
+        )?;
+        Ok(content)
+    }
+
+    /// Parameters for `llm_query` sub-calls before model code's overrides
+    fn sub_call_params(&self) -> ChatParams {
+        let model = self.config.sub_model.as_ref().unwrap_or(&self.config.model);
+        ChatParams::new(model)
+            .with_temperature(self.config.temperature)
+            .with_sampling(self.config.sampling)
+    }
+
+    /// Run the safety linter over a Python block
+    ///
+    /// Returns the error to show the model if the block must not run.
+    fn screen_block(&self, code: &str) -> Result<Option<String>> {
+        let policy = match self.config.code_lint {
+            Some(ref policy) if self.config.repl_language == ReplLang::Python => policy,
+            _ => return Ok(None),
+        };
+        let findings = lint_python(code, policy)?;
+        if findings.is_empty() {
+            return Ok(None);
+        }
+        let approved = match policy.action {
+            LintAction::Allow => true,
+            LintAction::Deny => false,
+            LintAction::Ask => self
+                .lint_confirm
+                .as_ref()
+                .is_some_and(|confirm| confirm(code, &findings)),
+        };
+        if self.config.verbose || self.config.exec_log {
+            for finding in &findings {
+                log_line!(
+                    self,
+                    "   ⚠️  Lint: line {}: {} ({})",
+                    finding.line,
+                    finding.name,
+                    if approved { "allowed" } else { "blocked" }
+                );
+            }
+        }
+        Ok((!approved).then(|| lint_denial_message(&findings)))
+    }
+
+    /// Ask the approval hook about a block; approved without one
+    fn review_block(&self, code: &str) -> CodeApproval {
+        let approval = match self.config.approval_hook {
+            Some(ref hook) => hook.review(code),
+            None => return CodeApproval::Approve,
+        };
+        if self.config.verbose || self.config.exec_log {
+            match approval {
+                CodeApproval::Approve => {}
+                CodeApproval::Deny(ref reason) => log_line!(self, "   ✋ Denied: {}", reason),
+                CodeApproval::Edit(_) => self.log("   ✏️  Edited before running"),
+            }
+        }
+        approval
     }
 
     /// Execute code with automatic retry on failure
     fn execute_with_retry(
         &self,
-        repl: &mut PyO3Repl,
+        repl: &mut dyn ReplEnvironment,
         code: &str,
         history: &mut Vec<Message>,
         total_usage: &mut Usage,
@@ -702,24 +2026,46 @@ impl Rlm {
         let mut current_code = code.to_string();
 
         loop {
-            let result = execute_with_error_handling(repl, &current_code)?;
+            let refused = |error| ReplResult::failure(error, String::new(), Duration::ZERO);
+            let mut edited = false;
+            let result = match self.screen_block(&current_code)? {
+                Some(denial) => refused(denial),
+                None => match self.review_block(&current_code) {
+                    CodeApproval::Approve => self.execute_block(repl, &current_code)?,
+                    CodeApproval::Deny(reason) => refused(code_denied_message(&reason)),
+                    CodeApproval::Edit(code) => {
+                        edited = true;
+                        current_code = code;
+                        self.execute_block(repl, &current_code)?
+                    }
+                },
+            };
+
+            // Keep huge prints from flooding the history
+            let cap = |text: &str| match self.config.max_output_chars {
+                Some(max) => truncate_output(text, max, self.config.output_truncation),
+                None => text.to_string(),
+            };
 
             // Add execution result to history wrapped in ```result block
             let output = if result.success {
                 if result.stdout.is_empty() {
                     "```result\n(no output)\n```".to_string()
                 } else {
-                    format!("```result\n{}\n```", result.stdout.trim())
+                    format!("```result\n{}\n```", cap(result.stdout.trim()))
                 }
             } else {
                 format!(
                     "```error\n{}\n```",
-                    result
-                        .error
-                        .as_ref()
-                        .unwrap_or(&"Unknown error".to_string())
+                    cap(result.error.as_deref().unwrap_or("Unknown error"))
                 )
             };
+            // The model has to know its block isn't what produced the output
+            let output = if edited {
+                format!("{}\n{}", code_edited_message(&current_code), output)
+            } else {
+                output
+            };
             history.push(Message::user(&output));
 
             // If success or max retries reached, return
@@ -734,21 +2080,55 @@ impl Rlm {
             // Ask LLM to fix the error
             retry_count += 1;
 
-            let fix_prompt = "Please fix the code and try again. Provide the corrected code in a ```repl``` block.";
-            history.push(Message::user(fix_prompt));
-
-            // Call LLM for fix
-            let (fix_response, usage) = self.call_llm(history)?;
-            total_usage.add(&usage);
-
-            history.push(Message::assistant(&fix_response));
+            let fixed = match self.config.fix_context {
+                FixContext::History => {
+                    let fix_prompt = "Please fix the code and try again. Provide the corrected code in a ```repl``` block.";
+                    history.push(Message::user(fix_prompt));
+
+                    // Call LLM for fix
+                    let (fix_response, usage) = self.call_llm(history)?;
+                    total_usage.add(&usage);
+
+                    history.push(Message::assistant(&fix_response));
+                    self.config
+                        .parser
+                        .extract_code_blocks(&fix_response)
+                        .into_iter()
+                        .next()
+                }
+                FixContext::Diff => {
+                    // System prompt plus just the failing block keeps the fix
+                    // round small no matter how long the run has been
+                    let error = cap(result.error.as_deref().unwrap_or("Unknown error"));
+                    let messages = [
+                        history[0].clone(),
+                        Message::user(build_fix_prompt(&current_code, &error)),
+                    ];
+                    let (fix_response, usage) = self.call_llm(&messages)?;
+                    total_usage.add(&usage);
+
+                    let fixed = extract_diff_blocks(&fix_response)
+                        .iter()
+                        .find_map(|patch| apply_patch(&current_code, patch))
+                        .or_else(|| {
+                            self.config
+                                .parser
+                                .extract_code_blocks(&fix_response)
+                                .into_iter()
+                                .next()
+                        });
+                    // Record the code that actually runs next, not the diff
+                    if let Some(ref code) = fixed {
+                        history.push(Message::assistant(format!("```repl\n{}\n```", code)));
+                    }
+                    fixed
+                }
+            };
 
-            // Extract fixed code
-            let fixed_blocks = extract_code_blocks(&fix_response);
-            if let Some(fixed) = fixed_blocks.first() {
-                current_code = fixed.clone();
+            if let Some(fixed) = fixed {
+                current_code = fixed;
             } else {
-                // No code block in fix response, return with error
+                // No usable fix in the response, return with error
                 return Ok(CodeBlock {
                     code: current_code,
                     result: Some(result),
@@ -762,6 +2142,10 @@ impl Rlm {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::answer::AnswerDetector;
+    use crate::mock::MockBackend;
+    use crate::types::{AdaptiveIterations, Backend, LintPolicy, QueryCacheConfig};
+    use std::collections::HashMap;
 
     #[test]
     fn test_rlm_config_default() {
@@ -772,6 +2156,49 @@ mod tests {
         assert_eq!(config.temperature, 0.0);
     }
 
+    #[test]
+    fn test_exec_timeout_config() {
+        let config = RlmConfig::default();
+        assert_eq!(config.exec_timeout, Some(Duration::from_secs(300)));
+        assert_eq!(config.with_exec_timeout(None).exec_timeout, None);
+    }
+
+    #[test]
+    fn test_python_exec_watchdog() {
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(MockBackend::new(Vec::<&str>::new())))
+            .with_repl_mode(ReplMode::Worker)
+            .with_exec_timeout(Some(Duration::from_millis(300)));
+        let rlm = Rlm::new(config).unwrap();
+        let mut session = rlm.repl_session().unwrap();
+        session.execute("x = 1").unwrap();
+
+        let started = Instant::now();
+        let result = session.execute("while True:\n    pass").unwrap();
+        assert!(!result.success);
+        let error = result.error.unwrap();
+        assert!(error.contains("ExecutionTimeout"), "{}", error);
+        assert!(error.contains("the 0.3s execution timeout"), "{}", error);
+        // Stopped by the watchdog, not by killing the worker
+        assert!(started.elapsed() < WORKER_KILL_GRACE);
+
+        let swallowed =
+            "while True:\n    try:\n        while True:\n            pass\n    except Exception:\n        pass";
+        let result = session.execute(swallowed).unwrap();
+        assert!(!result.success);
+        let error = result.error.unwrap();
+        assert!(error.contains("ExecutionTimeoutForced"), "{}", error);
+        assert!(started.elapsed() < WORKER_KILL_GRACE * 2);
+
+        let result = session.execute("print(x)").unwrap();
+        assert_eq!(result.stdout, "1\n");
+
+        assert!(is_exec_timeout(
+            "Traceback:\n  File \"<repl>\"\nExecutionTimeoutForced: code block exceeded"
+        ));
+        assert!(!is_exec_timeout("ValueError: ExecutionTimeout"));
+    }
+
     #[test]
     fn test_rlm_config_builder() {
         let config = RlmConfig::new("gpt-4o-mini")
@@ -786,4 +2213,1043 @@ mod tests {
         assert_eq!(config.temperature, 0.5);
         assert!(config.verbose);
     }
+
+    #[test]
+    fn test_query_cache_config() {
+        let config = RlmConfig::new("gpt-4o").with_query_cache_file("/tmp/cache.json");
+        let cache = config.query_cache.unwrap();
+        assert_eq!(cache.capacity, QueryCacheConfig::DEFAULT_CAPACITY);
+
+        let config = RlmConfig::new("gpt-4o")
+            .with_query_cache_file("/tmp/cache.json")
+            .with_query_cache(16);
+        let cache = config.query_cache.unwrap();
+        assert_eq!(cache.capacity, 16);
+        assert_eq!(
+            cache.path.as_deref(),
+            Some(std::path::Path::new("/tmp/cache.json"))
+        );
+    }
+
+    #[test]
+    fn test_mock_backend_final_answer() {
+        let mock = MockBackend::new(["Done.\nFINAL(42)"]);
+        let config = RlmConfig::new("mock").with_backend(Backend::Mock(mock.clone()));
+        let rlm = Rlm::new(config).unwrap();
+
+        let result = rlm.completion("What is 6 * 7?").unwrap();
+        assert_eq!(result.response, "42");
+        assert_eq!(result.iterations.len(), 1);
+        assert_eq!(mock.requests().len(), 1);
+    }
+
+    #[test]
+    fn test_structured_final_json() {
+        let mock = MockBackend::new(["Done.\nFINAL_JSON({\"answer\": 42, \"unit\": null})"]);
+        let config = RlmConfig::new("mock").with_backend(Backend::Mock(mock));
+        let rlm = Rlm::new(config).unwrap();
+
+        let result = rlm.completion("q").unwrap();
+        assert_eq!(result.response, "{\"answer\":42,\"unit\":null}");
+        let json = result.response_json.expect("parsed answer");
+        assert_eq!(json["answer"], 42);
+        assert_eq!(result.iterations[0].final_json.as_ref(), Some(&json));
+    }
+
+    #[test]
+    fn test_answer_format_retries_invalid_answer() {
+        let mock = MockBackend::new([
+            "FINAL(Paris)",
+            "FINAL_JSON({\"city\": \"Paris\"})",
+            "Fixed.\nFINAL_JSON({\"city\": \"Paris\", \"population\": 2100000})",
+        ]);
+        let schema = serde_json::json!({
+            "type": "object",
+            "required": ["city", "population"]
+        });
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(mock.clone()))
+            .with_answer_format(AnswerFormat::JsonSchema(schema));
+        let rlm = Rlm::new(config).unwrap();
+
+        let result = rlm.completion("q").unwrap();
+        assert_eq!(
+            result.response,
+            "{\"city\":\"Paris\",\"population\":2100000}"
+        );
+        assert_eq!(result.iterations.len(), 3);
+        assert!(result.iterations[0].final_answer.is_none());
+        let requests = mock.requests();
+        assert!(requests[0][0].content.contains("JSON Schema"));
+        let retry = &requests[2].last().unwrap().content;
+        assert!(retry.contains("rejected"), "{}", retry);
+        assert!(retry.contains("population"), "{}", retry);
+
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(MockBackend::new(["FINAL(x)"])))
+            .with_answer_format(AnswerFormat::JsonSchema(serde_json::json!({"type": 5})));
+        assert!(matches!(Rlm::new(config), Err(RlmError::Config(_))));
+    }
+
+    #[test]
+    fn test_custom_parser_config() {
+        let mock = MockBackend::new([
+            "```ipython\nprint(6 * 7)\n```\n```repl\nprint('ignored')\n```",
+            "<result>42</result>",
+        ]);
+        let parser = ParserConfig {
+            fence_languages: vec!["ipython".to_string()],
+            ..Default::default()
+        }
+        .with_answer_tag("result");
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(mock.clone()))
+            .with_parser(parser)
+            .with_repl_mode(ReplMode::Worker);
+        let rlm = Rlm::new(config).unwrap();
+
+        let result = rlm.completion("q").unwrap();
+        assert_eq!(result.response, "42");
+        let blocks = &result.iterations[0].code_blocks;
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].result.as_ref().unwrap().stdout, "42\n");
+    }
+
+    #[test]
+    fn test_truncate_after_first_block() {
+        let parser = ParserConfig::default();
+        let text = "Plan.\n```repl\nx = 6 * 7\n```\nThen FINAL(x)\n```repl\nmore()\n```";
+        assert_eq!(
+            truncate_after_first_repl_block(text, &parser, false),
+            "Plan.\n```repl\nx = 6 * 7\n```"
+        );
+        assert_eq!(
+            truncate_after_first_repl_block(text, &parser, true),
+            "Plan.\n```repl\nx = 6 * 7\n```\nThen FINAL(x)"
+        );
+        assert_eq!(
+            truncate_after_first_repl_block("no code", &parser, false),
+            "no code"
+        );
+    }
+
+    #[test]
+    fn test_keep_trailing_prose() {
+        let mock = MockBackend::new(["```repl\nanswer = str(6 * 7)\n```\nFINAL(answer)"]);
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(mock))
+            .with_keep_trailing_prose(true)
+            .with_repl_mode(ReplMode::Worker);
+        let rlm = Rlm::new(config).unwrap();
+
+        let result = rlm.completion("q").unwrap();
+        assert_eq!(result.response, "42");
+        assert_eq!(result.iterations.len(), 1);
+    }
+
+    #[test]
+    fn test_mock_backend_max_iterations() {
+        let mock = MockBackend::new(["thinking...", "still thinking..."]);
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(mock))
+            .with_max_iterations(2);
+        let rlm = Rlm::new(config).unwrap();
+
+        let err = rlm.completion("q").unwrap_err();
+        assert!(matches!(
+            err.root_cause(),
+            RlmError::MaxIterationsReached(2)
+        ));
+
+        let partial = err.partial().expect("partial trace attached");
+        assert_eq!(partial.iterations.len(), 2);
+        assert_eq!(partial.iterations[1].response, "still thinking...");
+        assert!(partial.usage.total_tokens > 0);
+    }
+
+    #[test]
+    fn test_partial_results_on_max_iterations() {
+        let mock = MockBackend::new(["thinking...", "best guess: 42"]);
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(mock))
+            .with_max_iterations(2)
+            .with_partial_results(true);
+        let rlm = Rlm::new(config).unwrap();
+
+        let result = rlm.completion("q").unwrap();
+        assert!(result.is_partial());
+        assert_eq!(result.status, CompletionStatus::MaxIterations);
+        assert_eq!(result.response, "best guess: 42");
+        assert_eq!(result.iterations.len(), 2);
+        assert!(result.usage.total_tokens > 0);
+    }
+
+    #[cfg(feature = "javascript")]
+    #[test]
+    fn test_javascript_repl_run() {
+        let mock = MockBackend::new([
+            "```repl\nwords = context.split(' ').length\nprint(words)\n```",
+            "```repl\nllm_output(`${words} words`)\n```",
+        ]);
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(mock.clone()))
+            .with_repl_language(ReplLang::JavaScript);
+        let rlm = Rlm::new(config).unwrap();
+
+        let result = rlm.completion("one two three").unwrap();
+        assert_eq!(result.response, "3 words");
+        assert!(mock.requests()[0][0].content.contains("JavaScript REPL"));
+    }
+
+    #[cfg(feature = "lua")]
+    #[test]
+    fn test_lua_repl_run() {
+        let mock = MockBackend::new([
+            "```repl\nwords = select(2, context:gsub('%S+', ''))\nprint(words)\n```",
+            "```repl\nllm_output(words .. ' words')\n```",
+        ]);
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(mock.clone()))
+            .with_repl_language(ReplLang::Lua);
+        let rlm = Rlm::new(config).unwrap();
+
+        let result = rlm.completion("one two three").unwrap();
+        assert_eq!(result.response, "3 words");
+        assert!(mock.requests()[0][0].content.contains("Lua REPL"));
+    }
+
+    #[cfg(not(feature = "lua"))]
+    #[test]
+    fn test_repl_language_requires_feature() {
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(MockBackend::new(["FINAL(x)"])))
+            .with_repl_language(ReplLang::Lua);
+        assert!(matches!(Rlm::new(config), Err(RlmError::Config(_))));
+    }
+
+    /// Custom environment that understands `let name = value` and `answer name`
+    #[derive(Default)]
+    struct TinyRepl {
+        vars: HashMap<String, String>,
+    }
+
+    impl ReplEnvironment for TinyRepl {
+        fn execute(&mut self, code: &str) -> Result<ReplResult> {
+            let mut result = ReplResult::success(String::new(), HashMap::new(), Default::default());
+            for line in code.lines() {
+                if let Some((name, value)) =
+                    line.strip_prefix("let ").and_then(|l| l.split_once(" = "))
+                {
+                    self.vars.insert(name.to_string(), value.to_string());
+                } else if let Some(name) = line.strip_prefix("answer ") {
+                    result.llm_output = self.vars.get(name).cloned();
+                }
+            }
+            Ok(result)
+        }
+
+        fn add_context(&mut self, name: &str, value: &str) -> Result<()> {
+            self.vars.insert(name.to_string(), value.to_string());
+            Ok(())
+        }
+
+        fn get_locals(&self) -> HashMap<String, String> {
+            self.vars.clone()
+        }
+    }
+
+    #[test]
+    fn test_repl_session_keeps_state() {
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(MockBackend::new(["sub answer"])))
+            .with_repl_mode(ReplMode::Worker)
+            .with_sandbox(SandboxPolicy::restricted());
+        let rlm = Rlm::new(config).unwrap();
+        let mut session = rlm.repl_session().unwrap();
+
+        session.execute("x = 20").unwrap();
+        let result = session.execute("print(x + 1, llm_query('hi'))").unwrap();
+        assert_eq!(result.stdout.trim(), "21 sub answer");
+        let result = session.execute("import os").unwrap();
+        assert!(!result.success);
+        assert!(result
+            .error
+            .unwrap()
+            .contains("blocked by the sandbox policy"));
+    }
+
+    #[test]
+    fn test_session_keeps_repl_and_turns() {
+        let mock = MockBackend::new([
+            "```repl\nx = 41\n```",
+            "FINAL(set)",
+            "```repl\nllm_output(f'{x + 1} {llm_query(\"hi\")} {\"EARLIER REQUEST\" in context}')\n```",
+            "sub",
+        ]);
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(mock.clone()))
+            .with_repl_mode(ReplMode::Worker);
+        let rlm = Rlm::new(config).unwrap();
+        let mut session = rlm.session().unwrap();
+
+        let first = rlm.completion_in_session(&mut session, "set x").unwrap();
+        assert_eq!(first.response, "set");
+        let second = rlm.completion_in_session(&mut session, "add one").unwrap();
+        assert_eq!(second.response, "42 sub True");
+        assert_eq!(second.iterations[0].sub_call_usage.total_tokens, 2);
+        assert_eq!(session.turns().len(), 2);
+        assert_eq!(session.turns()[1].0, "add one");
+
+        let requests = mock.requests();
+        assert!(!requests[0][0].content.contains(SESSION_TURN_NOTE));
+        assert!(requests[2][0].content.contains(SESSION_TURN_NOTE));
+    }
+
+    #[test]
+    fn test_custom_repl_factory() {
+        let mock = MockBackend::new(["```repl\nlet greeting = hi\nanswer context\n```"]);
+        let config = RlmConfig::new("mock").with_backend(Backend::Mock(mock));
+        let rlm = Rlm::new(config)
+            .unwrap()
+            .with_repl_factory(|_query_fn: LlmQueryFn| {
+                Ok(Box::new(TinyRepl::default()) as Box<dyn ReplEnvironment>)
+            });
+
+        let result = rlm.completion("the question").unwrap();
+        assert_eq!(result.response, "the question");
+    }
+
+    #[test]
+    fn test_truncated_answer_continued() {
+        let mock = MockBackend::new(["FINAL(1, 2, 3,", " 4, 5)"]);
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(mock.clone()))
+            .with_max_tokens(3)
+            .with_max_answer_tokens(100);
+        let rlm = Rlm::new(config).unwrap();
+
+        let result = rlm.completion("q").unwrap();
+        assert_eq!(result.response, "1, 2, 3, 4, 5");
+        assert_eq!(result.iterations[0].continuations, 1);
+        assert_eq!(result.continuations(), 1);
+
+        let continuation = &mock.requests()[1];
+        assert_eq!(
+            continuation[continuation.len() - 2].content,
+            "FINAL(1, 2, 3,"
+        );
+        assert_eq!(
+            continuation.last().unwrap().content,
+            CONTINUE_TRUNCATED_PROMPT
+        );
+    }
+
+    #[test]
+    fn test_continuation_respects_budget() {
+        let mock = MockBackend::new(["a b c", "d e f", "FINAL(42)"]);
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(mock))
+            .with_max_tokens(3)
+            .with_max_answer_tokens(6);
+        let rlm = Rlm::new(config).unwrap();
+
+        // Budget of 6 allows one continuation; the next root call answers
+        let result = rlm.completion("q").unwrap();
+        assert_eq!(result.iterations[0].response, "a b cd e f");
+        assert_eq!(result.iterations[0].continuations, 1);
+        assert_eq!(result.response, "42");
+    }
+
+    #[test]
+    fn test_adaptive_iteration_limit() {
+        let adaptive = AdaptiveIterations {
+            base: 4,
+            per_chunk: 2,
+            cap: 30,
+        };
+        assert_eq!(adaptive.limit(100, suggested_chunk_size(100)), 6);
+        assert_eq!(adaptive.limit(7000, suggested_chunk_size(7000)), 8);
+        assert_eq!(adaptive.limit(70_000, suggested_chunk_size(70_000)), 30);
+
+        // Adaptive limit replaces max_iterations for the run
+        let mock = MockBackend::new(["one", "two", "three"]);
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(mock))
+            .with_max_iterations(20)
+            .with_adaptive_iterations(1, 1, 10);
+        let rlm = Rlm::new(config).unwrap();
+
+        let err = rlm.completion("q").unwrap_err();
+        assert!(matches!(
+            err.root_cause(),
+            RlmError::MaxIterationsReached(2)
+        ));
+    }
+
+    #[cfg(feature = "telemetry")]
+    #[test]
+    fn test_runs_recorded_in_telemetry() {
+        let telemetry = Arc::new(Telemetry::in_memory().unwrap());
+        let mock = MockBackend::new(["FINAL(1)", "thinking", "thinking"]);
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(mock))
+            .with_max_iterations(2);
+        let rlm = Rlm::new(config).unwrap().with_telemetry(telemetry.clone());
+
+        rlm.completion("q").unwrap();
+        rlm.completion("q").unwrap_err();
+
+        let summary = telemetry.summary().unwrap();
+        assert_eq!(summary.len(), 1);
+        assert_eq!(summary[0].runs, 2);
+        assert_eq!(summary[0].success_rate, 0.5);
+        assert_eq!(summary[0].avg_iterations_to_answer, Some(1.0));
+    }
+
+    #[test]
+    fn test_backend_timeout_retried() {
+        struct Flaky(std::sync::atomic::AtomicU32);
+        impl ChatBackend for Flaky {
+            fn chat(&self, _messages: &[Message], _params: &ChatParams) -> Result<(String, Usage)> {
+                if self.0.fetch_add(1, Ordering::SeqCst) < 2 {
+                    Err(RlmError::BackendTimeout(std::time::Duration::from_secs(1)))
+                } else {
+                    Ok(("FINAL(ok)".to_string(), Usage::new(1, 1)))
+                }
+            }
+        }
+
+        let config = RlmConfig::new("mock").with_max_backend_retries(2);
+        let rlm = Rlm::with_chat_backend(config, Arc::new(Flaky(AtomicU32::new(0)))).unwrap();
+        assert_eq!(rlm.completion("q").unwrap().response, "ok");
+
+        let config = RlmConfig::new("mock").with_max_backend_retries(1);
+        let rlm = Rlm::with_chat_backend(config, Arc::new(Flaky(AtomicU32::new(0)))).unwrap();
+        let err = rlm.completion("q").unwrap_err();
+        assert!(matches!(err.root_cause(), RlmError::BackendTimeout(_)));
+    }
+
+    #[test]
+    fn test_custom_answer_detector() {
+        struct Sentinel;
+        impl AnswerDetector for Sentinel {
+            fn detect(&self, ctx: &AnswerContext<'_>) -> Option<String> {
+                ctx.response.strip_prefix("DONE:").map(str::to_string)
+            }
+        }
+
+        let mock = MockBackend::new(["DONE:42"]);
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(mock))
+            .with_answer_detector(Sentinel);
+        let rlm = Rlm::new(config).unwrap();
+        assert_eq!(rlm.completion("q").unwrap().response, "42");
+
+        // Without the built-ins, FINAL(...) no longer terminates the run
+        let mock = MockBackend::new(["FINAL(1)"]);
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(mock))
+            .with_max_iterations(1)
+            .with_answer_detectors(vec![Arc::new(Sentinel)]);
+        let rlm = Rlm::new(config).unwrap();
+        let err = rlm.completion("q").unwrap_err();
+        assert!(matches!(
+            err.root_cause(),
+            RlmError::MaxIterationsReached(1)
+        ));
+    }
+
+    #[test]
+    fn test_exec_log_goes_to_sink() {
+        let lines = Arc::new(Mutex::new(Vec::<String>::new()));
+        let captured = lines.clone();
+        let mock = MockBackend::new(["FINAL(42)"]);
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(mock))
+            .with_exec_log(true)
+            .with_log_sink(move |line: &str| captured.lock().unwrap().push(line.to_string()));
+        let rlm = Rlm::new(config).unwrap();
+        rlm.completion("q").unwrap();
+
+        let lines = lines.lock().unwrap();
+        assert_eq!(lines.first().map(String::as_str), Some("── iter 1 ──"));
+        assert!(lines.iter().any(|l| l.contains("FINAL")));
+    }
+
+    #[test]
+    fn test_completion_many_keeps_input_order() {
+        // One worker consumes the script in order
+        let mock = MockBackend::new(["FINAL(0)", "FINAL(1)", "FINAL(2)"]);
+        let config = RlmConfig::new("mock").with_backend(Backend::Mock(mock));
+        let rlm = Rlm::new(config).unwrap();
+
+        let batch = rlm.completion_many(["a", "b", "c"], 1);
+        let answers: Vec<_> = batch
+            .results
+            .iter()
+            .map(|r| r.as_ref().unwrap().response.as_str())
+            .collect();
+        assert_eq!(answers, ["0", "1", "2"]);
+    }
+
+    #[test]
+    fn test_completion_many_aggregates_usage() {
+        struct Fixed;
+        impl ChatBackend for Fixed {
+            fn chat(&self, _messages: &[Message], _params: &ChatParams) -> Result<(String, Usage)> {
+                Ok(("FINAL(ok)".to_string(), Usage::new(2, 1)))
+            }
+        }
+
+        let rlm = Rlm::with_chat_backend(RlmConfig::new("m"), Arc::new(Fixed)).unwrap();
+        let batch = rlm.completion_many(vec!["q"; 5], 3);
+        assert_eq!(batch.succeeded(), 5);
+        assert_eq!(batch.usage, Usage::new(10, 5));
+    }
+
+    #[test]
+    fn test_api_failure_keeps_partial_trace() {
+        // Script runs out after the first iteration, so the second call fails
+        let mock = MockBackend::new(["exploring"]);
+        let config = RlmConfig::new("mock").with_backend(Backend::Mock(mock));
+        let rlm = Rlm::new(config).unwrap();
+
+        let err = rlm.completion("q").unwrap_err();
+        assert!(matches!(err.root_cause(), RlmError::Api(_)));
+        assert_eq!(err.partial().unwrap().iterations.len(), 1);
+    }
+
+    #[test]
+    fn test_context_window_exceeded_before_send() {
+        let mock = MockBackend::new(["FINAL(42)"]);
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(mock.clone()))
+            .with_context_window(100);
+        let rlm = Rlm::new(config).unwrap();
+
+        let err = rlm.completion("q").unwrap_err();
+        assert!(matches!(
+            err.root_cause(),
+            RlmError::ContextWindowExceeded { limit: 100, .. }
+        ));
+        assert!(mock.requests().is_empty());
+    }
+
+    #[test]
+    fn test_custom_chat_backend() {
+        struct Fixed;
+        impl ChatBackend for Fixed {
+            fn chat(&self, _messages: &[Message], params: &ChatParams) -> Result<(String, Usage)> {
+                Ok((format!("FINAL({})", params.model), Usage::new(1, 1)))
+            }
+        }
+
+        let config = RlmConfig::new("gateway-model").with_backend(Backend::Custom(Arc::new(Fixed)));
+        let rlm = Rlm::new(config).unwrap();
+
+        let result = rlm.completion("hi").unwrap();
+        assert_eq!(result.response, "gateway-model");
+        assert_eq!(result.usage, Usage::new(1, 1));
+    }
+
+    #[test]
+    fn test_llm_query_overrides() {
+        struct Echo(Mutex<Vec<&'static str>>);
+        impl ChatBackend for Echo {
+            fn chat(&self, messages: &[Message], params: &ChatParams) -> Result<(String, Usage)> {
+                let text = match messages {
+                    [_] => format!(
+                        "{} {} {:?}",
+                        params.model, params.temperature, params.max_tokens
+                    ),
+                    _ => self.0.lock().unwrap().remove(0).to_string(),
+                };
+                Ok((text, Usage::new(1, 1)))
+            }
+        }
+
+        let root = Echo(Mutex::new(vec![
+            "```repl\na = llm_query('x', model='cheap', temperature=0.5, max_tokens=20)\nllm_output(a + ' | ' + llm_query('y'))\n```",
+        ]));
+        let config = RlmConfig::new("strong")
+            .with_temperature(0.1)
+            .with_backend(Backend::Custom(Arc::new(root)))
+            .with_repl_mode(ReplMode::Worker);
+        let rlm = Rlm::new(config).unwrap();
+
+        let result = rlm.completion("q").unwrap();
+        assert_eq!(result.response, "cheap 0.5 Some(20) | strong 0.1 None");
+        assert_eq!(rlm.llm_query("z").unwrap(), "strong 0.1 None");
+    }
+
+    #[test]
+    fn test_sub_model_and_backend() {
+        struct Named(&'static str);
+        impl ChatBackend for Named {
+            fn chat(&self, messages: &[Message], params: &ChatParams) -> Result<(String, Usage)> {
+                let text = match messages {
+                    [_] => format!("{} {}", self.0, params.model),
+                    _ => "```repl\nllm_output(llm_query('x') + ' | ' + llm_query('y', model='other'))\n```".to_string(),
+                };
+                Ok((text, Usage::new(1, 1)))
+            }
+        }
+
+        let config = RlmConfig::new("strong")
+            .with_backend(Backend::Custom(Arc::new(Named("cloud"))))
+            .with_sub_backend(Backend::Custom(Arc::new(Named("local"))))
+            .with_sub_model("cheap")
+            .with_repl_mode(ReplMode::Worker);
+        let rlm = Rlm::new(config).unwrap();
+
+        let result = rlm.completion("q").unwrap();
+        assert_eq!(result.response, "local cheap | local other");
+        assert_eq!(result.iterations[0].sub_call_usage, Usage::new(2, 2));
+        assert_eq!(rlm.llm_query("z").unwrap(), "local cheap");
+    }
+
+    #[test]
+    fn test_sub_call_limits_raise() {
+        struct Scripted(Mutex<Vec<&'static str>>);
+        impl ChatBackend for Scripted {
+            fn chat(&self, messages: &[Message], _params: &ChatParams) -> Result<(String, Usage)> {
+                let text = match messages {
+                    [_] => "sub".to_string(),
+                    _ => self.0.lock().unwrap().remove(0).to_string(),
+                };
+                Ok((text, Usage::new(1, 1)))
+            }
+        }
+
+        let root = Scripted(Mutex::new(vec![
+            "```repl\nn = 0\ntry:\n    for _ in range(5):\n        llm_query('x')\n        n += 1\nexcept RuntimeError as e:\n    first = str(e)\n```",
+            "```repl\nllm_query('y')\ntry:\n    llm_query('z')\nexcept RuntimeError as e:\n    second = str(e)\nllm_output(f'{n} | {first} | {second}')\n```",
+        ]));
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Custom(Arc::new(root)))
+            .with_max_sub_calls(Some(2), Some(3))
+            .with_repl_mode(ReplMode::Worker);
+        let rlm = Rlm::new(config).unwrap();
+
+        let result = rlm.completion("q").unwrap();
+        let parts: Vec<&str> = result.response.split(" | ").collect();
+        assert_eq!(parts[0], "2");
+        assert!(parts[1].starts_with("[SUB-CALL LIMIT]"), "{}", parts[1]);
+        assert!(parts[2].starts_with("[BUDGET EXHAUSTED]"), "{}", parts[2]);
+    }
+
+    #[test]
+    fn test_query_overrides_round_trip() {
+        let overrides = QueryOverrides {
+            model: Some("cheap".to_string()),
+            max_tokens: Some(20),
+            ..Default::default()
+        };
+        let encoded = overrides.encode("hi");
+        assert_eq!(QueryOverrides::decode(&encoded).unwrap(), (overrides, "hi"));
+        assert_eq!(QueryOverrides::default().encode("hi"), "hi");
+        assert!(QueryOverrides::decode("\u{0}rlm-query:{").is_err());
+    }
+
+    #[test]
+    fn test_missing_required_packages() {
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(MockBackend::default()))
+            .with_required_packages(["json", "surely_not_installed_pkg"]);
+
+        match Rlm::new(config) {
+            Err(RlmError::MissingPythonPackages { missing, .. }) => {
+                assert_eq!(missing, vec!["surely_not_installed_pkg".to_string()]);
+            }
+            other => panic!("expected MissingPythonPackages, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn test_final_answer_sanitized_with_raw_in_trace() {
+        let mock = MockBackend::new(["FINAL(bad\u{0}answer)"]);
+        let config = RlmConfig::new("mock").with_backend(Backend::Mock(mock));
+        let rlm = Rlm::new(config).unwrap();
+
+        let result = rlm.completion("q").unwrap();
+        assert_eq!(result.response, "badanswer");
+        assert_eq!(
+            result.iterations[0].final_answer_raw.as_deref(),
+            Some("bad\u{0}answer".as_bytes())
+        );
+    }
+
+    #[test]
+    fn test_finalize_answer_modes() {
+        let raw = "  Answer: Paris \n".to_string();
+        assert_eq!(finalize_answer(raw.clone(), TaskMode::Completion), raw);
+        assert_eq!(finalize_answer(raw, TaskMode::Answer), "Paris");
+
+        let fenced = "```json\n{\"a\": 1}\n```".to_string();
+        assert_eq!(finalize_answer(fenced, TaskMode::Transform), "{\"a\": 1}");
+    }
+
+    #[test]
+    fn test_truncate_output() {
+        let text = "abcdefghij";
+        assert_eq!(truncate_output(text, 20, OutputTruncation::Head), text);
+        assert_eq!(
+            truncate_output(text, 4, OutputTruncation::Head),
+            "abcd\n[truncated 6 chars]"
+        );
+        assert_eq!(
+            truncate_output(text, 4, OutputTruncation::HeadAndTail),
+            "ab\n[truncated 6 chars]\nij"
+        );
+        // Counts characters, not bytes
+        assert_eq!(
+            truncate_output("äöüß", 2, OutputTruncation::Head),
+            "äö\n[truncated 2 chars]"
+        );
+    }
+
+    #[test]
+    fn test_sandbox_code_lists() {
+        let code = sandbox_code(&SandboxPolicy::restricted().allow("io").allow("open"));
+        assert!(code.contains("set([\"input\",\"breakpoint\"])"));
+        assert!(code.contains("\"socket\""));
+        assert!(!code.contains("\"io\""));
+    }
+
+    #[test]
+    fn test_sandbox_blocks_escapes() {
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(MockBackend::new(Vec::<&str>::new())))
+            .with_repl_mode(ReplMode::Worker)
+            .with_sandbox(SandboxPolicy::restricted());
+        // The watchdog is set up too, before the sandbox
+        assert!(config.exec_timeout.is_some());
+        let rlm = Rlm::new(config).unwrap();
+        let mut session = rlm.repl_session().unwrap();
+
+        for code in [
+            "open('/etc/hostname').read()",
+            "import os",
+            "import ctypes",
+            "_rlm_watchdog.disarm()",
+        ] {
+            let result = session.execute(code).unwrap();
+            assert!(!result.success, "`{}` ran in the sandbox", code);
+        }
+        let result = session
+            .execute("print([n for n, v in globals().items() if type(v).__name__ == 'module'])")
+            .unwrap();
+        assert_eq!(result.stdout.trim(), "[]");
+
+        // Nor can the helpers the setup code leaves behind lead to one
+        let walk = r#"
+found, seen = set(), {}
+todo = list(globals().values()) + list(__builtins__.values())
+while todo:
+    obj = todo.pop()
+    if id(obj) in seen:
+        continue
+    seen[id(obj)] = obj
+    if type(obj).__name__ == "module":
+        if obj.__name__ in ("ctypes", "os", "threading"):
+            found.add(obj.__name__)
+        continue
+    if isinstance(obj, dict):
+        todo.extend(obj.values())
+    elif isinstance(obj, (list, tuple, set, frozenset)):
+        todo.extend(obj)
+    for name in ("__globals__", "__func__", "__self__", "__wrapped__"):
+        if hasattr(obj, name):
+            todo.append(getattr(obj, name))
+    closure = getattr(obj, "__closure__", None)
+    if isinstance(closure, tuple):
+        todo.extend(cell.cell_contents for cell in closure)
+    if hasattr(obj, "__dict__"):
+        todo.append(dict(vars(obj)))
+    todo.append(type(obj))
+print(sorted(found))
+"#;
+        let result = session.execute(walk).unwrap();
+        assert!(result.success, "{:?}", result.error);
+        assert_eq!(result.stdout.trim(), "[]");
+    }
+
+    #[test]
+    fn test_code_lint_denies_and_asks() {
+        let blocked = "```repl\nimport os\nos.system('echo hi')\nllm_output('ran')\n```";
+        let mock = MockBackend::new([blocked, "```repl\nllm_output('rewritten')\n```"]);
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(mock.clone()))
+            .with_code_lint(LintPolicy::new(LintAction::Deny))
+            .with_repl_mode(ReplMode::Worker);
+        let rlm = Rlm::new(config).unwrap();
+
+        assert_eq!(rlm.completion("q").unwrap().response, "rewritten");
+        let requests = mock.requests();
+        let error = &requests[1].last().unwrap().content;
+        assert!(error.contains("[BLOCKED]"), "{}", error);
+        assert!(error.contains("line 2: os.system (matches `os.system`)"));
+
+        let mock = MockBackend::new([blocked]);
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(mock))
+            .with_code_lint(LintPolicy::new(LintAction::Ask))
+            .with_repl_mode(ReplMode::Worker);
+        let rlm = Rlm::new(config)
+            .unwrap()
+            .with_lint_confirm(|code, findings| code.contains("echo") && findings.len() == 1);
+        assert_eq!(rlm.completion("q").unwrap().response, "ran");
+    }
+
+    #[test]
+    fn test_approval_hook_denies_and_edits() {
+        let mock = MockBackend::new([
+            "```repl\nllm_output('first')\n```",
+            "```repl\nprint(1)\n```",
+            "```repl\nllm_output('done')\n```",
+        ]);
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(mock.clone()))
+            .with_approval_hook(|code: &str| {
+                if code.contains("first") {
+                    CodeApproval::Deny("not that".to_string())
+                } else if code.contains("print(1)") {
+                    CodeApproval::Edit("print(2)".to_string())
+                } else {
+                    CodeApproval::Approve
+                }
+            })
+            .with_repl_mode(ReplMode::Worker);
+        let rlm = Rlm::new(config).unwrap();
+
+        let result = rlm.completion("q").unwrap();
+        assert_eq!(result.response, "done");
+        assert_eq!(result.iterations[0].code_blocks[0].code, "print(2)");
+        let requests = mock.requests();
+        assert!(requests[1].iter().any(|m| m
+            .content
+            .contains("[DENIED] The user did not allow this code to run: not that")));
+        assert!(requests[2].iter().any(|m| m.content.contains("[EDITED]")
+            && m.content
+                .ends_with("```repl\nprint(2)\n```\n```result\n2\n```")));
+    }
+
+    #[test]
+    fn test_binary_attachments() {
+        let mock = MockBackend::new([
+            "```repl\nblob = attachments['blob.bin']\nllm_output(f\"{len(blob)} {blob[:2]!r} {blob[2:].decode()}\")\n```",
+        ]);
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(mock.clone()))
+            .with_repl_mode(ReplMode::Worker);
+        let rlm = Rlm::new(config).unwrap();
+
+        let attachments = [Attachment::new("blob.bin", b"\xff\x00abc".to_vec())];
+        let result = rlm.completion_with_attachments("q", &attachments).unwrap();
+        assert_eq!(result.response, "5 b'\\xff\\x00' abc");
+        assert!(mock.requests()[0][0]
+            .content
+            .contains("attachments[\"blob.bin\"] → 5 bytes"));
+    }
+
+    #[test]
+    fn test_images_reach_root_and_sub_calls() {
+        let mock = MockBackend::new([
+            "```repl\nimg = attachments['image_1.png']\nllm_output(llm_query('what is this?', images=[img]))\n```",
+            "a cat",
+        ]);
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(mock.clone()))
+            .with_repl_mode(ReplMode::Worker);
+        let rlm = Rlm::new(config).unwrap();
+
+        let png = Image::from_bytes(b"\x89PNG\r\n\x1a\npixels".to_vec()).unwrap();
+        let prompt = PromptInput::Multimodal {
+            text: "Describe the picture".to_string(),
+            images: vec![png.clone()],
+        };
+        let result = rlm.completion(prompt).unwrap();
+        assert_eq!(result.response, "a cat");
+
+        let requests = mock.requests();
+        assert!(requests[0][0].content.contains("images=[data, ...]"));
+        assert_eq!(requests[0][1].images, requests[1][0].images);
+        assert_eq!(requests[1][0].content, "what is this?");
+        assert_eq!(requests[1][0].images, [png]);
+    }
+
+    #[test]
+    fn test_import_allowlist() {
+        let mock = MockBackend::new([
+            "```repl\nimport json\ntry:\n    import numpy\nexcept ImportError as e:\n    print(e)\ntry:\n    import os\nexcept ImportError as e:\n    print(e)\n```",
+            "```repl\nllm_output(json.dumps([1]))\n```",
+        ]);
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(mock.clone()))
+            .with_allowed_imports(["pandas"])
+            .with_sandbox(SandboxPolicy::restricted())
+            .with_repl_mode(ReplMode::Worker);
+        let rlm = Rlm::new(config).unwrap();
+
+        let result = rlm.completion("q").unwrap();
+        assert_eq!(result.response, "[1]");
+        let requests = mock.requests();
+        assert!(requests[0][0]
+            .content
+            .contains("the standard library plus pandas"));
+        let output: String = requests[1].iter().map(|m| m.content.as_str()).collect();
+        assert!(
+            output.contains("package 'numpy' is not available"),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains("blocked by the sandbox policy"),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_output_streamed_while_running() {
+        let mock = MockBackend::new([
+            "```repl\nfor i in range(3):\n    print(f'chunk {i + 1}/3')\nllm_output('done')\n```",
+        ]);
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(mock))
+            .with_repl_mode(ReplMode::Worker);
+        let chunks = Arc::new(Mutex::new(Vec::new()));
+        let sink = chunks.clone();
+        let rlm = Rlm::new(config)
+            .unwrap()
+            .with_output_stream(move |text: &str| sink.lock().unwrap().push(text.to_string()));
+
+        assert_eq!(rlm.completion("q").unwrap().response, "done");
+        assert_eq!(
+            *chunks.lock().unwrap(),
+            ["chunk 1/3\n", "chunk 2/3\n", "chunk 3/3\n"]
+        );
+    }
+
+    #[test]
+    fn test_event_stream() {
+        let mock = MockBackend::new([
+            "```repl\nx = llm_query('hi')\n```",
+            "sub answer",
+            "```repl\nllm_output(x)\n```",
+        ]);
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(mock))
+            .with_max_iterations(5)
+            .with_repl_mode(ReplMode::Worker);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let rlm = Rlm::new(config)
+            .unwrap()
+            .with_event_stream(move |event: &RlmEvent| sink.lock().unwrap().push(event.clone()));
+
+        assert_eq!(rlm.completion("q").unwrap().response, "sub answer");
+        let events = events.lock().unwrap();
+        let steps: Vec<String> = events
+            .iter()
+            .map(|event| match event {
+                RlmEvent::IterationStarted {
+                    iteration,
+                    max_iterations,
+                } => format!("start {}/{}", iteration, max_iterations),
+                RlmEvent::CodeStarted { iteration, code } => {
+                    format!("code {} {}", iteration, code.trim())
+                }
+                RlmEvent::CodeFinished {
+                    iteration, success, ..
+                } => format!("ran {} {}", iteration, success),
+                RlmEvent::SubQuery { cached } => format!("query {}", cached),
+                RlmEvent::IterationFinished { iteration, .. } => format!("end {}", iteration),
+            })
+            .collect();
+        assert_eq!(
+            steps,
+            [
+                "start 1/5",
+                "code 1 x = llm_query('hi')",
+                "query false",
+                "ran 1 true",
+                "end 1",
+                "start 2/5",
+                "code 2 llm_output(x)",
+                "ran 2 true",
+                "end 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_workspace_helpers() {
+        let root = std::env::temp_dir().join(format!("rlm-workspace-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::write(root.join("a.txt"), "alpha").unwrap();
+
+        let mock = MockBackend::new([
+            "```repl\ntry:\n    read_file('../../etc/passwd')\nexcept PermissionError as e:\n    denied = 'outside' in str(e)\nwrite_file('docs/out.txt', read_file('a.txt').upper())\nllm_output(f'{list_dir()} {denied}')\n```",
+        ]);
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(mock.clone()))
+            .with_workspace(Workspace::new(&root))
+            .with_sandbox(SandboxPolicy::restricted())
+            .with_repl_mode(ReplMode::Worker);
+        let rlm = Rlm::new(config).unwrap();
+
+        let result = rlm.completion("q");
+        let written = std::fs::read_to_string(root.join("docs/out.txt"));
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(result.unwrap().response, "['a.txt', 'docs/'] True");
+        assert_eq!(written.unwrap(), "ALPHA");
+        assert!(mock.requests()[0][0]
+            .content
+            .contains("write_file(path, text)"));
+    }
+
+    #[test]
+    fn test_text_helpers() {
+        let mock = MockBackend::new([
+            "```repl\nchunks = chunk_text('aaa bbb ccc', 8)\nhits = grep('Q', context, ignore_case=True)\ndata = parse_json('Result: {\"n\": 2} done')\nllm_output(f\"{chunks} {hits} {data['n']} {count_tokens('abcdefgh')}\")\n```",
+        ]);
+        let config = RlmConfig::new("mock")
+            .with_backend(Backend::Mock(mock.clone()))
+            .with_sandbox(SandboxPolicy::restricted())
+            .with_repl_mode(ReplMode::Worker);
+        let rlm = Rlm::new(config).unwrap();
+
+        let result = rlm.completion("q").unwrap();
+        assert_eq!(result.response, "['aaa bbb ', 'ccc'] ['1: q'] 2 2");
+        assert!(mock.requests()[0][0].content.contains("chunk_text(text"));
+    }
+
+    #[test]
+    fn test_packages_code() {
+        assert!(packages_code(&PythonEnv::default()).is_none());
+
+        let env = PythonEnv {
+            installable: vec!["scikit-learn==1.5".to_string()],
+            install_venv: Some("/tmp/rlm-venv".into()),
+            ..Default::default()
+        };
+        let code = packages_code(&env).unwrap();
+        assert!(code.contains("(globals(), None, [\"scikit-learn==1.5\"], \"/tmp/rlm-venv\")"));
+    }
+
+    #[test]
+    fn test_context_metadata_code() {
+        let code = context_metadata_code("abc");
+        assert!(code.contains("CONTEXT_LEN = 3\n"));
+        assert!(code.contains(
+            "CONTEXT_SHA256 = \"ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad\""
+        ));
+        assert!(code.contains("CHUNK_SUGGESTED_SIZE = 3\n"));
+
+        // Both count characters, not bytes
+        let code = context_metadata_code(&"ä".repeat(4000));
+        assert!(code.contains("CONTEXT_LEN = 4000\n"));
+        assert!(code.contains("CHUNK_SUGGESTED_SIZE = 4000\n"));
+    }
 }
@@ -0,0 +1,180 @@
+//! Snapshot and restore of Python REPL state
+//!
+//! A [`ReplSnapshot`] holds the pickled REPL variables, so a session can be
+//! checkpointed, moved to another process, or branched to try two
+//! continuations from the same intermediate state. Values that can't be
+//! pickled (open files, generators, most functions defined in REPL code) are
+//! left out and listed in [`ReplSnapshot::skipped`]; imported modules are
+//! recorded by name and imported again on restore.
+//!
+//! Restoring unpickles the data inside the REPL, which can run arbitrary
+//! code: only restore snapshots you took yourself.
+
+use serde::{Deserialize, Serialize};
+
+use crate::env::{execute_with_error_handling, PyO3Repl, ReplEnvironment};
+use crate::error::{Result, RlmError};
+use crate::worker::WorkerRepl;
+
+/// Engine bindings, which the target REPL defines itself
+const BINDINGS: &[&str] = &[
+    "llm_query",
+    "llm_output",
+    "pip_install",
+    "print",
+    "read_file",
+    "write_file",
+    "list_dir",
+    "chunk_text",
+    "count_tokens",
+    "grep",
+    "parse_json",
+    "In",
+    "Out",
+    "exit",
+    "quit",
+];
+
+/// Prints the pickled namespace as one JSON line
+const SNAPSHOT_CODE: &str = r#"
+def _rlm_snapshot(ns, bindings):
+    import base64, json, pickle, types
+
+    values, modules, skipped = {}, {}, []
+    for name, value in list(ns.items()):
+        if name.startswith("_") or name in bindings:
+            continue
+        if isinstance(value, types.ModuleType):
+            modules[name] = value.__name__
+            continue
+        try:
+            values[name] = pickle.dumps(value)
+        except Exception:
+            skipped.append(name)
+    data = base64.b64encode(pickle.dumps({"values": values, "modules": modules}))
+    print(json.dumps({"data": data.decode(), "skipped": sorted(skipped)}))
+"#;
+
+/// Loads a snapshot into the namespace
+const RESTORE_CODE: &str = r#"
+def _rlm_restore(ns, data):
+    import base64, pickle, sys
+
+    state = pickle.loads(base64.b64decode(data))
+    for name, module in state["modules"].items():
+        try:
+            # Resolves to the REPL's own __import__, so a sandbox still applies
+            __import__(module)
+            ns[name] = sys.modules[module]
+        except ImportError:
+            pass
+    for name, value in state["values"].items():
+        ns[name] = pickle.loads(value)
+"#;
+
+/// Pickled REPL variables (see the [module docs](self))
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplSnapshot {
+    /// Base64 pickle of the variables and module aliases
+    pub data: String,
+    /// Variables that couldn't be pickled
+    pub skipped: Vec<String>,
+}
+
+fn snapshot<R: ReplEnvironment + ?Sized>(repl: &mut R) -> Result<ReplSnapshot> {
+    let code = format!(
+        "{}\n_rlm_snapshot(globals(), {})\ndel _rlm_snapshot\n",
+        SNAPSHOT_CODE,
+        serde_json::to_string(BINDINGS)?
+    );
+    let result = execute_with_error_handling(repl, &code)?;
+    if let Some(error) = result.error {
+        return Err(RlmError::Repl(format!("Snapshot failed: {}", error)));
+    }
+    let line = result.stdout.lines().last().unwrap_or_default();
+    serde_json::from_str(line)
+        .map_err(|e| RlmError::Repl(format!("Snapshot produced no data: {}", e)))
+}
+
+fn restore<R: ReplEnvironment + ?Sized>(repl: &mut R, snapshot: &ReplSnapshot) -> Result<()> {
+    let code = format!(
+        "{}\n_rlm_restore(globals(), {})\ndel _rlm_restore\n",
+        RESTORE_CODE,
+        serde_json::to_string(&snapshot.data)?
+    );
+    match execute_with_error_handling(repl, &code)?.error {
+        Some(error) => Err(RlmError::Repl(format!("Restore failed: {}", error))),
+        None => Ok(()),
+    }
+}
+
+impl PyO3Repl {
+    /// Pickle the REPL variables
+    pub fn snapshot(&mut self) -> Result<ReplSnapshot> {
+        snapshot(self)
+    }
+
+    /// Load a snapshot, overwriting variables of the same name
+    pub fn restore(&mut self, snapshot: &ReplSnapshot) -> Result<()> {
+        restore(self, snapshot)
+    }
+}
+
+impl WorkerRepl {
+    /// Pickle the worker's REPL variables
+    pub fn snapshot(&mut self) -> Result<ReplSnapshot> {
+        snapshot(self)
+    }
+
+    /// Load a snapshot, overwriting variables of the same name
+    ///
+    /// Snapshots move freely between worker and in-process REPLs of the
+    /// same Python version.
+    pub fn restore(&mut self, snapshot: &ReplSnapshot) -> Result<()> {
+        restore(self, snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::LlmQueryFn;
+    use crate::worker::WorkerOptions;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    fn worker() -> WorkerRepl {
+        let query_fn: LlmQueryFn = Arc::new(|prompt: &str| Ok(prompt.to_string()));
+        WorkerRepl::new(
+            query_fn,
+            WorkerOptions {
+                python: PathBuf::from(if cfg!(windows) { "python" } else { "python3" }),
+                setup: Vec::new(),
+                exec_timeout: None,
+                kill_after: None,
+                pool: None,
+            },
+        )
+        .expect("python3 available")
+    }
+
+    #[test]
+    fn test_snapshot_branches_state() {
+        let mut repl = worker();
+        let setup = "import json as j\nrows = [{'a': 1}]\ngen = (i for i in rows)\n";
+        assert!(repl.execute(setup).unwrap().success);
+        let snapshot = repl.snapshot().unwrap();
+        assert_eq!(snapshot.skipped, ["gen"]);
+
+        // Diverge the original, then branch from the checkpoint
+        repl.execute("rows.append({'a': 2})").unwrap();
+        let mut branch = worker();
+        branch.restore(&snapshot).unwrap();
+        let result = branch.execute("print(j.dumps(rows))").unwrap();
+        assert_eq!(result.stdout, "[{\"a\": 1}]\n");
+        assert_eq!(
+            repl.get_locals().get("rows").map(String::as_str),
+            Some("[{'a': 1}, {'a': 2}]")
+        );
+    }
+}
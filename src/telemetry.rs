@@ -0,0 +1,293 @@
+//! Local run telemetry (opt-in, `telemetry` feature)
+//!
+//! Records one row of anonymized statistics per run into a SQLite file and
+//! aggregates them per model. No prompts, answers, or code are stored - only
+//! counts, token usage, and timing.
+
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::{Result, RlmError};
+use crate::types::{CompletionStatus, RlmCompletion, RlmConfig, RlmIteration, TaskMode, Usage};
+
+/// How a run ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunOutcome {
+    Answered,
+    MaxIterations,
+    Error,
+}
+
+impl RunOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RunOutcome::Answered => "answered",
+            RunOutcome::MaxIterations => "max_iterations",
+            RunOutcome::Error => "error",
+        }
+    }
+}
+
+/// Statistics for a single run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub model: String,
+    pub task_mode: TaskMode,
+    pub outcome: RunOutcome,
+    pub iterations: u32,
+    pub code_blocks: u32,
+    /// Executions that needed an error-fix retry
+    pub retries: u32,
+    /// Iterations that produced neither code nor a final answer
+    pub stalled_iterations: u32,
+    pub usage: Usage,
+    pub duration_ms: u64,
+}
+
+impl RunRecord {
+    fn from_iterations(
+        config: &RlmConfig,
+        outcome: RunOutcome,
+        iterations: &[RlmIteration],
+        usage: &Usage,
+        duration_ms: u64,
+    ) -> Self {
+        let count = |n: usize| u32::try_from(n).unwrap_or(u32::MAX);
+        Self {
+            model: config.model.clone(),
+            task_mode: config.task_mode,
+            outcome,
+            iterations: count(iterations.len()),
+            code_blocks: count(iterations.iter().map(|i| i.code_blocks.len()).sum()),
+            retries: iterations
+                .iter()
+                .flat_map(|i| &i.code_blocks)
+                .map(|b| b.retry_count)
+                .sum(),
+            stalled_iterations: count(
+                iterations
+                    .iter()
+                    .filter(|i| i.code_blocks.is_empty() && i.final_answer.is_none())
+                    .count(),
+            ),
+            usage: usage.clone(),
+            duration_ms,
+        }
+    }
+
+    /// Build a record from a run result
+    ///
+    /// Returns `None` for failures before the loop started (no trace), which
+    /// say nothing about the model.
+    pub fn from_result(config: &RlmConfig, result: &Result<RlmCompletion>) -> Option<Self> {
+        match result {
+            Ok(c) => Some(Self::from_iterations(
+                config,
+                match c.status {
+                    CompletionStatus::Answered => RunOutcome::Answered,
+                    CompletionStatus::MaxIterations => RunOutcome::MaxIterations,
+                },
+                &c.iterations,
+                &c.usage,
+                c.execution_time.as_millis() as u64,
+            )),
+            Err(e) => {
+                let partial = e.partial()?;
+                let outcome = match e.root_cause() {
+                    RlmError::MaxIterationsReached(_) => RunOutcome::MaxIterations,
+                    _ => RunOutcome::Error,
+                };
+                Some(Self::from_iterations(
+                    config,
+                    outcome,
+                    &partial.iterations,
+                    &partial.usage,
+                    partial.execution_time.as_millis() as u64,
+                ))
+            }
+        }
+    }
+}
+
+/// Aggregated statistics for one model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelSummary {
+    pub model: String,
+    pub runs: u64,
+    /// Fraction of runs that produced a final answer
+    pub success_rate: f64,
+    /// Mean iterations of successful runs
+    pub avg_iterations_to_answer: Option<f64>,
+    /// Retries per executed code block
+    pub retry_rate: f64,
+    /// Stalled iterations per iteration
+    pub stall_rate: f64,
+    pub avg_total_tokens: f64,
+    pub avg_duration_ms: f64,
+}
+
+/// SQLite-backed telemetry store
+pub struct Telemetry {
+    conn: Mutex<Connection>,
+}
+
+impl Telemetry {
+    /// Open (or create) a telemetry database file
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::init(Connection::open(path).map_err(sql_err)?)
+    }
+
+    /// Telemetry kept in memory for the lifetime of the process
+    pub fn in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory().map_err(sql_err)?)
+    }
+
+    fn init(conn: Connection) -> Result<Self> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS runs (
+                id INTEGER PRIMARY KEY,
+                recorded_at INTEGER NOT NULL,
+                model TEXT NOT NULL,
+                task_mode TEXT NOT NULL,
+                outcome TEXT NOT NULL,
+                iterations INTEGER NOT NULL,
+                code_blocks INTEGER NOT NULL,
+                retries INTEGER NOT NULL,
+                stalled_iterations INTEGER NOT NULL,
+                input_tokens INTEGER NOT NULL,
+                output_tokens INTEGER NOT NULL,
+                duration_ms INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS runs_model ON runs (model);",
+        )
+        .map_err(sql_err)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Store one run
+    pub fn record(&self, record: &RunRecord) -> Result<()> {
+        let recorded_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let task_mode = serde_json::to_value(record.task_mode)?;
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT INTO runs (recorded_at, model, task_mode, outcome, iterations,
+                    code_blocks, retries, stalled_iterations, input_tokens, output_tokens,
+                    duration_ms)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                params![
+                    recorded_at as i64,
+                    record.model,
+                    task_mode.as_str().unwrap_or_default(),
+                    record.outcome.as_str(),
+                    record.iterations,
+                    record.code_blocks,
+                    record.retries,
+                    record.stalled_iterations,
+                    record.usage.input_tokens as i64,
+                    record.usage.output_tokens as i64,
+                    record.duration_ms as i64,
+                ],
+            )
+            .map_err(sql_err)?;
+        Ok(())
+    }
+
+    /// Per-model aggregates over all recorded runs
+    pub fn summary(&self) -> Result<Vec<ModelSummary>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare(
+                "SELECT model,
+                    COUNT(*),
+                    AVG(outcome = 'answered'),
+                    AVG(CASE WHEN outcome = 'answered' THEN iterations END),
+                    CAST(SUM(retries) AS REAL) / MAX(SUM(code_blocks), 1),
+                    CAST(SUM(stalled_iterations) AS REAL) / MAX(SUM(iterations), 1),
+                    AVG(input_tokens + output_tokens),
+                    AVG(duration_ms)
+                 FROM runs GROUP BY model ORDER BY model",
+            )
+            .map_err(sql_err)?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok(ModelSummary {
+                    model: row.get(0)?,
+                    runs: row.get::<_, i64>(1)? as u64,
+                    success_rate: row.get(2)?,
+                    avg_iterations_to_answer: row.get(3)?,
+                    retry_rate: row.get(4)?,
+                    stall_rate: row.get(5)?,
+                    avg_total_tokens: row.get(6)?,
+                    avg_duration_ms: row.get(7)?,
+                })
+            })
+            .map_err(sql_err)?;
+        rows.collect::<rusqlite::Result<Vec<_>>>().map_err(sql_err)
+    }
+}
+
+fn sql_err(e: rusqlite::Error) -> RlmError {
+    RlmError::Telemetry(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(model: &str, outcome: RunOutcome, iterations: u32, retries: u32) -> RunRecord {
+        RunRecord {
+            model: model.to_string(),
+            task_mode: TaskMode::Answer,
+            outcome,
+            iterations,
+            code_blocks: iterations,
+            retries,
+            stalled_iterations: 0,
+            usage: Usage::new(100, 20),
+            duration_ms: 1000,
+        }
+    }
+
+    #[test]
+    fn test_summary_per_model() {
+        let telemetry = Telemetry::in_memory().unwrap();
+        telemetry
+            .record(&record("a", RunOutcome::Answered, 2, 0))
+            .unwrap();
+        telemetry
+            .record(&record("a", RunOutcome::Answered, 4, 1))
+            .unwrap();
+        telemetry
+            .record(&record("a", RunOutcome::MaxIterations, 10, 1))
+            .unwrap();
+        telemetry
+            .record(&record("b", RunOutcome::Error, 1, 0))
+            .unwrap();
+
+        let summary = telemetry.summary().unwrap();
+        assert_eq!(summary.len(), 2);
+
+        let a = &summary[0];
+        assert_eq!(a.model, "a");
+        assert_eq!(a.runs, 3);
+        assert!((a.success_rate - 2.0 / 3.0).abs() < 1e-9);
+        assert_eq!(a.avg_iterations_to_answer, Some(3.0));
+        assert!((a.retry_rate - 2.0 / 16.0).abs() < 1e-9);
+        assert_eq!(a.avg_total_tokens, 120.0);
+
+        let b = &summary[1];
+        assert_eq!(b.success_rate, 0.0);
+        assert_eq!(b.avg_iterations_to_answer, None);
+    }
+}
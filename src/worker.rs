@@ -0,0 +1,760 @@
+//! Out-of-process Python REPL
+//!
+//! [`WorkerRepl`] runs model code in a separate `python` process speaking
+//! JSON lines over stdin/stdout. A segfault in a native extension or a block
+//! that ignores the in-REPL timeout kills only the worker: it is replaced by
+//! a fresh one with the contexts and setup code restored, and the failure
+//! goes back to the model like any other error.
+//!
+//! Workers don't share a GIL with the host or with each other, so
+//! concurrent runs in worker mode execute truly in parallel. A
+//! [`WorkerPool`] keeps processes started ahead of time, so a run doesn't
+//! wait for `python` to boot.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::env::{LlmQueryFn, ReplEnvironment};
+use crate::error::{Result, RlmError};
+use crate::python::{worker_interpreter, WATCHDOG_CODE};
+use crate::rlm::OutputFn;
+use crate::types::{PythonEnv, ReplResult};
+
+/// Python side of the protocol, run with `python -c` after [`WATCHDOG_CODE`]
+const WORKER_SCRIPT: &str = r#"
+import contextlib, io, json, os, time, traceback, types
+
+_watchdog = _rlm_make_watchdog()
+del _rlm_make_watchdog
+
+# Keep private copies of the pipes: stray writes to fd 1 (native code,
+# subprocesses) go to stderr instead, and `exit()` in model code closing
+# sys.stdin doesn't cut us off
+_proto = os.fdopen(os.dup(1), "w", encoding="utf-8")
+_requests = os.fdopen(os.dup(0), "r", encoding="utf-8")
+os.dup2(2, 1)
+
+_output = [None]
+
+# What model code gets is defined in a scope of its own, so its __globals__
+# don't lead back to this script's modules and watchdog
+_exposed = {"json": json, "proto": _proto, "requests": _requests, "output": _output}
+exec(
+    """
+def send(msg):
+    proto.write(json.dumps(msg) + "\\n")
+    proto.flush()
+
+
+def recv():
+    line = requests.readline()
+    if not line:
+        raise SystemExit(0)
+    return json.loads(line)
+
+
+def llm_query(prompt):
+    send({"type": "llm_query", "prompt": str(prompt)})
+    reply = recv()
+    if reply.get("ok"):
+        return reply["value"]
+    raise RuntimeError(reply.get("error") or "llm_query failed")
+
+
+def llm_output(answer):
+    output[0] = str(answer)
+
+
+def _rlm_emit(text):
+    send({"type": "output", "text": text})
+""",
+    _exposed,
+)
+_send, _recv = _exposed["send"], _exposed["recv"]
+
+ns = {
+    "__name__": "__main__",
+    "llm_query": _exposed["llm_query"],
+    "llm_output": _exposed["llm_output"],
+    "_rlm_emit": _exposed["_rlm_emit"],
+}
+
+
+def _locals():
+    out = {}
+    for name, value in list(ns.items()):
+        if name.startswith("_") or callable(value) or isinstance(value, types.ModuleType):
+            continue
+        try:
+            out[name] = value if isinstance(value, str) else repr(value)
+        except Exception:
+            pass
+    return out
+
+
+def _exec(code, timeout=None):
+    _output[0] = None
+    stdout, stderr = io.StringIO(), io.StringIO()
+    error = None
+    start = time.monotonic()
+    try:
+        if timeout:
+            _watchdog.arm(timeout)
+        try:
+            with contextlib.redirect_stdout(stdout), contextlib.redirect_stderr(stderr):
+                exec(compile(code, "<repl>", "exec"), ns)
+        finally:
+            if timeout:
+                _watchdog.disarm()
+    except BaseException as e:
+        # Drop this frame so the traceback starts at the model's code
+        error = "".join(traceback.format_exception(type(e), e, e.__traceback__.tb_next))
+    return {
+        "type": "result",
+        "stdout": stdout.getvalue(),
+        "stderr": stderr.getvalue(),
+        "error": error,
+        "llm_output": _output[0],
+        "execution_time": time.monotonic() - start,
+    }
+
+
+while True:
+    msg = _recv()
+    op = msg.get("op")
+    if op == "exec":
+        _send(_exec(msg["code"], msg.get("timeout")))
+    elif op == "set":
+        ns[msg["name"]] = msg["value"]
+        _send({"type": "ok"})
+    elif op == "locals":
+        _send({"type": "locals", "locals": _locals()})
+    else:
+        _send({"type": "error", "error": "unknown op: %r" % op})
+"#;
+
+/// Bytes of worker stderr kept for crash reports
+const STDERR_TAIL_BYTES: usize = 4096;
+
+/// How long protocol round-trips other than `exec` may take
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Request<'a> {
+    Exec {
+        code: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        timeout: Option<f64>,
+    },
+    Set {
+        name: &'a str,
+        value: &'a str,
+    },
+    Locals,
+}
+
+#[derive(Serialize)]
+struct QueryReply {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Reply {
+    Result {
+        stdout: String,
+        stderr: String,
+        error: Option<String>,
+        llm_output: Option<String>,
+        execution_time: f64,
+    },
+    LlmQuery {
+        prompt: String,
+    },
+    Output {
+        text: String,
+    },
+    Ok,
+    Locals {
+        locals: HashMap<String, String>,
+    },
+    Error {
+        error: String,
+    },
+}
+
+/// Settings for [`WorkerRepl`]
+#[derive(Debug, Clone)]
+pub struct WorkerOptions {
+    /// Interpreter to run the worker with
+    pub python: PathBuf,
+    /// Code run in every new worker, after the contexts are set
+    pub setup: Vec<String>,
+    /// Raise `ExecutionTimeout` in a block running longer than this, from a
+    /// watchdog inside the worker (None = never)
+    pub exec_timeout: Option<Duration>,
+    /// Kill a worker whose block runs longer than this (None = never)
+    pub kill_after: Option<Duration>,
+    /// Take processes from this pool instead of starting them (its
+    /// interpreter is used in place of `python`)
+    pub pool: Option<Arc<WorkerPool>>,
+}
+
+/// Why a request got no reply
+enum Lost {
+    Timeout,
+    Exited,
+}
+
+struct WorkerProcess {
+    child: Child,
+    stdin: ChildStdin,
+    replies: Receiver<std::result::Result<Reply, String>>,
+    stderr_tail: Arc<Mutex<String>>,
+}
+
+impl WorkerProcess {
+    fn spawn(python: &Path) -> Result<Self> {
+        let mut child = Command::new(python)
+            .args(["-u", "-c"])
+            .arg([WATCHDOG_CODE, WORKER_SCRIPT].concat())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                RlmError::Python(format!(
+                    "Failed to start REPL worker '{}': {}",
+                    python.display(),
+                    e
+                ))
+            })?;
+
+        let stdin = child.stdin.take().expect("piped stdin");
+        let stdout = child.stdout.take().expect("piped stdout");
+        let mut stderr = child.stderr.take().expect("piped stderr");
+
+        let (tx, replies) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                let reply = serde_json::from_str(&line)
+                    .map_err(|e| format!("Invalid message from REPL worker: {}", e));
+                if tx.send(reply).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let stderr_tail = Arc::new(Mutex::new(String::new()));
+        let tail = stderr_tail.clone();
+        thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            while let Ok(n) = stderr.read(&mut buf) {
+                if n == 0 {
+                    break;
+                }
+                let mut tail = tail.lock().unwrap();
+                tail.push_str(&String::from_utf8_lossy(&buf[..n]));
+                if tail.len() > STDERR_TAIL_BYTES {
+                    let mut cut = tail.len() - STDERR_TAIL_BYTES;
+                    while !tail.is_char_boundary(cut) {
+                        cut += 1;
+                    }
+                    tail.drain(..cut);
+                }
+            }
+        });
+
+        Ok(Self {
+            child,
+            stdin,
+            replies,
+            stderr_tail,
+        })
+    }
+
+    fn send<T: Serialize>(&mut self, message: &T) -> bool {
+        let Ok(mut line) = serde_json::to_string(message) else {
+            return false;
+        };
+        line.push('\n');
+        self.stdin.write_all(line.as_bytes()).is_ok() && self.stdin.flush().is_ok()
+    }
+
+    fn recv(&self, deadline: Option<Instant>) -> std::result::Result<Reply, Lost> {
+        let reply = match deadline {
+            Some(deadline) => self
+                .replies
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                .map_err(|e| match e {
+                    RecvTimeoutError::Timeout => Lost::Timeout,
+                    RecvTimeoutError::Disconnected => Lost::Exited,
+                }),
+            None => self.replies.recv().map_err(|_| Lost::Exited),
+        };
+        match reply {
+            Ok(Ok(reply)) => Ok(reply),
+            Ok(Err(message)) => Ok(Reply::Error { error: message }),
+            Err(lost) => Err(lost),
+        }
+    }
+
+    /// Kill the process and describe how it ended
+    fn kill(mut self) -> String {
+        let _ = self.child.kill();
+        let status = self
+            .child
+            .wait()
+            .map(|s| s.to_string())
+            .unwrap_or_else(|e| e.to_string());
+        let tail = self.stderr_tail.lock().unwrap().trim().to_string();
+        if tail.is_empty() {
+            status
+        } else {
+            format!("{}\n{}", status, tail)
+        }
+    }
+}
+
+impl Drop for WorkerProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Worker processes started ahead of time
+///
+/// Each [`WorkerRepl`] takes a fresh process and never gives it back, so no
+/// REPL state leaks between runs; a background thread starts replacements
+/// to keep `size` processes waiting. Share one pool between [`Rlm`]
+/// instances (`Rlm::with_worker_pool`) to serve concurrent requests without
+/// paying interpreter startup on each.
+///
+/// [`Rlm`]: crate::Rlm
+pub struct WorkerPool {
+    python: PathBuf,
+    size: usize,
+    idle: Mutex<Vec<WorkerProcess>>,
+    refilling: AtomicBool,
+}
+
+impl WorkerPool {
+    /// Create a pool of `size` processes running `python`, started in the
+    /// background
+    pub fn new(python: impl Into<PathBuf>, size: usize) -> Arc<Self> {
+        let pool = Arc::new(Self {
+            python: python.into(),
+            size,
+            idle: Mutex::new(Vec::new()),
+            refilling: AtomicBool::new(false),
+        });
+        pool.refill();
+        pool
+    }
+
+    /// Create a pool running the interpreter `env` selects for workers (the
+    /// venv's `python` if there is one)
+    pub fn for_env(env: &PythonEnv, size: usize) -> Arc<Self> {
+        Self::new(worker_interpreter(env), size)
+    }
+
+    /// Interpreter the pool's processes run
+    pub fn python(&self) -> &Path {
+        &self.python
+    }
+
+    /// Processes currently waiting to be taken
+    pub fn idle(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+
+    /// A started process, or a new one if none is waiting
+    fn take(self: &Arc<Self>) -> Result<WorkerProcess> {
+        let waiting = {
+            let mut idle = self.idle.lock().unwrap();
+            // Skip processes that died while waiting
+            loop {
+                match idle.pop() {
+                    Some(mut process) => {
+                        if matches!(process.child.try_wait(), Ok(None)) {
+                            break Some(process);
+                        }
+                    }
+                    None => break None,
+                }
+            }
+        };
+        self.refill();
+        match waiting {
+            Some(process) => Ok(process),
+            None => WorkerProcess::spawn(&self.python),
+        }
+    }
+
+    /// Start processes in the background until `size` are waiting
+    fn refill(self: &Arc<Self>) {
+        if self.refilling.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let pool: Weak<Self> = Arc::downgrade(self);
+        thread::spawn(move || {
+            while let Some(pool) = pool.upgrade() {
+                if pool.idle() < pool.size {
+                    match WorkerProcess::spawn(&pool.python) {
+                        Ok(process) => {
+                            pool.idle.lock().unwrap().push(process);
+                            continue;
+                        }
+                        // Tried again on the next take
+                        Err(_) => {
+                            pool.refilling.store(false, Ordering::SeqCst);
+                            break;
+                        }
+                    }
+                }
+                pool.refilling.store(false, Ordering::SeqCst);
+                // Pick up a take that came in between the check and the store
+                if pool.idle() >= pool.size || pool.refilling.swap(true, Ordering::SeqCst) {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+impl fmt::Debug for WorkerPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WorkerPool")
+            .field("python", &self.python)
+            .field("size", &self.size)
+            .field("idle", &self.idle())
+            .finish()
+    }
+}
+
+/// Python REPL running in a child process
+pub struct WorkerRepl {
+    options: WorkerOptions,
+    query_fn: LlmQueryFn,
+    /// Replayed into a replacement worker
+    contexts: Vec<(String, String)>,
+    process: Option<WorkerProcess>,
+    output: Option<OutputFn>,
+}
+
+impl WorkerRepl {
+    /// Start a worker and run the setup code in it
+    pub fn new(query_fn: LlmQueryFn, options: WorkerOptions) -> Result<Self> {
+        let mut repl = Self {
+            options,
+            query_fn,
+            contexts: Vec::new(),
+            process: None,
+            output: None,
+        };
+        repl.restart()?;
+        Ok(repl)
+    }
+
+    /// Pass output sent with `_rlm_emit(text)` to `output` as it arrives
+    pub fn with_output(mut self, output: OutputFn) -> Self {
+        self.output = Some(output);
+        self
+    }
+
+    /// Replace the worker with a fresh one holding the contexts and setup
+    fn restart(&mut self) -> Result<()> {
+        self.process = Some(match self.options.pool {
+            Some(ref pool) => pool.take()?,
+            None => WorkerProcess::spawn(&self.options.python)?,
+        });
+        for (name, value) in self.contexts.clone() {
+            self.set(&name, &value)?;
+        }
+        for code in self.options.setup.clone() {
+            let result = self.run(&code, false)?;
+            if let Some(error) = result.error {
+                return Err(RlmError::Python(format!(
+                    "REPL worker setup failed: {}",
+                    error
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn process(&mut self) -> Result<&mut WorkerProcess> {
+        if self.process.is_none() {
+            self.restart()?;
+        }
+        Ok(self.process.as_mut().expect("worker started"))
+    }
+
+    fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        let process = self.process()?;
+        if !process.send(&Request::Set { name, value }) {
+            return Err(self.lost("set a context", Lost::Exited));
+        }
+        match process.recv(Some(Instant::now() + CONTROL_TIMEOUT)) {
+            Ok(Reply::Ok) => Ok(()),
+            Ok(Reply::Error { error }) => Err(RlmError::Python(error)),
+            Ok(_) => Err(RlmError::Python("Unexpected reply from REPL worker".into())),
+            Err(lost) => Err(self.lost("set a context", lost)),
+        }
+    }
+
+    /// Execute code, answering `llm_query` calls until the result arrives;
+    /// `watched` applies `exec_timeout` and `kill_after`
+    ///
+    /// A worker that dies or hangs is torn down and reported as `Err`;
+    /// [`ReplEnvironment::execute`] turns that into a failed result.
+    fn run(&mut self, code: &str, watched: bool) -> Result<ReplResult> {
+        let started = Instant::now();
+        let (timeout, kill_after) = match watched {
+            true => (self.options.exec_timeout, self.options.kill_after),
+            false => (None, None),
+        };
+        let deadline = kill_after.map(|t| started + t);
+        let query_fn = self.query_fn.clone();
+        let output = self.output.clone();
+        let process = self.process()?;
+        let request = Request::Exec {
+            code,
+            timeout: timeout.map(|t| t.as_secs_f64()),
+        };
+        if !process.send(&request) {
+            return Err(self.lost("run code", Lost::Exited));
+        }
+        loop {
+            match process.recv(deadline) {
+                Ok(Reply::LlmQuery { prompt }) => {
+                    let reply = match query_fn(&prompt) {
+                        Ok(value) => QueryReply {
+                            ok: true,
+                            value: Some(value),
+                            error: None,
+                        },
+                        Err(error) => QueryReply {
+                            ok: false,
+                            value: None,
+                            error: Some(error),
+                        },
+                    };
+                    if !process.send(&reply) {
+                        return Err(self.lost("run code", Lost::Exited));
+                    }
+                }
+                Ok(Reply::Output { text }) => {
+                    if let Some(ref output) = output {
+                        output(&text);
+                    }
+                }
+                Ok(Reply::Result {
+                    stdout,
+                    stderr,
+                    error,
+                    llm_output,
+                    execution_time,
+                }) => {
+                    let execution_time = Duration::from_secs_f64(execution_time.max(0.0));
+                    let mut result = match error {
+                        None => ReplResult::success(String::new(), HashMap::new(), execution_time),
+                        Some(error) => ReplResult::failure(error, String::new(), execution_time),
+                    };
+                    result.stdout = stdout;
+                    result.stderr = stderr;
+                    result.llm_output = llm_output;
+                    return Ok(result);
+                }
+                Ok(Reply::Error { error }) => return Err(RlmError::Python(error)),
+                Ok(_) => return Err(RlmError::Python("Unexpected reply from REPL worker".into())),
+                Err(lost) => return Err(self.lost("run code", lost)),
+            }
+        }
+    }
+
+    /// Tear down a worker that stopped answering
+    fn lost(&mut self, action: &str, lost: Lost) -> RlmError {
+        let report = self
+            .process
+            .take()
+            .map(WorkerProcess::kill)
+            .unwrap_or_default();
+        let reason = match lost {
+            Lost::Timeout => format!(
+                "was killed after {:?}",
+                self.options.kill_after.unwrap_or_default()
+            ),
+            Lost::Exited => "exited".to_string(),
+        };
+        RlmError::Python(format!(
+            "REPL worker {} while trying to {} ({})",
+            reason, action, report
+        ))
+    }
+}
+
+impl ReplEnvironment for WorkerRepl {
+    fn execute(&mut self, code: &str) -> Result<ReplResult> {
+        let started = Instant::now();
+        match self.run(code, true) {
+            Ok(result) => Ok(result),
+            // The worker died or hung: start over so the next block has a
+            // working REPL, and tell the model what it lost
+            Err(e) if self.process.is_none() => {
+                self.restart()?;
+                Ok(ReplResult::failure(
+                    format!(
+                        "{}\nThe REPL was restarted: variables defined by earlier blocks \
+                         are gone, only `context` and the built-in helpers remain.",
+                        e
+                    ),
+                    String::new(),
+                    started.elapsed(),
+                ))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn add_context(&mut self, name: &str, value: &str) -> Result<()> {
+        self.set(name, value)?;
+        self.contexts.retain(|(n, _)| n != name);
+        self.contexts.push((name.to_string(), value.to_string()));
+        Ok(())
+    }
+
+    fn get_locals(&self) -> HashMap<String, String> {
+        // Needs a round-trip; a worker that can't answer has no locals
+        let Some(process) = self.process.as_ref() else {
+            return HashMap::new();
+        };
+        let mut stdin = &process.stdin;
+        let line = serde_json::to_string(&Request::Locals).unwrap_or_default() + "\n";
+        if stdin
+            .write_all(line.as_bytes())
+            .and_then(|_| stdin.flush())
+            .is_err()
+        {
+            return HashMap::new();
+        }
+        match process.recv(Some(Instant::now() + CONTROL_TIMEOUT)) {
+            Ok(Reply::Locals { locals }) => locals,
+            _ => HashMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn python() -> PathBuf {
+        PathBuf::from(if cfg!(windows) { "python" } else { "python3" })
+    }
+
+    fn worker(setup: &[&str], kill_after: Option<Duration>) -> WorkerRepl {
+        let query_fn: LlmQueryFn = Arc::new(|prompt: &str| Ok(format!("echo: {}", prompt)));
+        WorkerRepl::new(
+            query_fn,
+            WorkerOptions {
+                python: python(),
+                setup: setup.iter().map(|s| s.to_string()).collect(),
+                exec_timeout: None,
+                kill_after,
+                pool: None,
+            },
+        )
+        .expect("python3 available")
+    }
+
+    #[test]
+    fn test_pool_hands_out_fresh_processes() {
+        let pool = WorkerPool::new(python(), 2);
+        let started = Instant::now();
+        while pool.idle() < 2 && started.elapsed() < Duration::from_secs(10) {
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(pool.idle(), 2);
+
+        let query_fn: LlmQueryFn = Arc::new(|prompt: &str| Ok(prompt.to_string()));
+        let options = WorkerOptions {
+            python: PathBuf::from("unused"),
+            setup: vec!["LIMIT = 3".to_string()],
+            exec_timeout: None,
+            kill_after: None,
+            pool: Some(pool.clone()),
+        };
+        let mut first = WorkerRepl::new(query_fn.clone(), options.clone()).unwrap();
+        let mut second = WorkerRepl::new(query_fn, options).unwrap();
+        first.execute("x = 1").unwrap();
+        let result = second.execute("print(LIMIT, 'x' in globals())").unwrap();
+        assert_eq!(result.stdout, "3 False\n");
+    }
+
+    #[test]
+    fn test_worker_exec_and_llm_query() {
+        let mut repl = worker(&["LIMIT = 3"], None);
+        repl.add_context("context", "some data").unwrap();
+
+        let result = repl
+            .execute("print(context, LIMIT)\nanswer = llm_query('hi')\nllm_output(answer)")
+            .unwrap();
+        assert!(result.success, "{:?}", result.error);
+        assert_eq!(result.stdout, "some data 3\n");
+        assert_eq!(result.llm_output.as_deref(), Some("echo: hi"));
+        assert_eq!(
+            repl.get_locals().get("answer").map(String::as_str),
+            Some("echo: hi")
+        );
+
+        let result = repl.execute("1 / 0").unwrap();
+        assert!(!result.success);
+        assert!(result.error.unwrap().contains("ZeroDivisionError"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_worker_crash_restarts_with_context() {
+        let mut repl = worker(&["LIMIT = 3"], None);
+        repl.add_context("context", "some data").unwrap();
+        repl.execute("x = 1").unwrap();
+
+        let result = repl.execute("import os\nos.kill(os.getpid(), 11)").unwrap();
+        assert!(!result.success);
+        assert!(result.error.unwrap().contains("restarted"));
+
+        let result = repl
+            .execute("print(context, LIMIT, 'x' in globals())")
+            .unwrap();
+        assert_eq!(result.stdout, "some data 3 False\n");
+    }
+
+    #[test]
+    fn test_worker_killed_on_timeout() {
+        let mut repl = worker(&[], Some(Duration::from_millis(500)));
+        let started = Instant::now();
+        let result = repl.execute("while True:\n    pass").unwrap();
+        assert!(!result.success);
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let result = repl.execute("print('alive')").unwrap();
+        assert_eq!(result.stdout, "alive\n");
+    }
+}